@@ -45,7 +45,11 @@ fn highest_version_dir(base: &Path) -> Option<String> {
 }
 
 fn main() {
-    prost_build::compile_protos(&["./src/heapdump.proto"], &["./src"]).unwrap();
+    prost_build::compile_protos(
+        &["./src/heapdump.proto", "./src/perfetto_trace.proto"],
+        &["./src"],
+    )
+    .unwrap();
 
     let root = env::var("CARGO_MANIFEST_DIR").unwrap();
     if cfg!(feature = "m5") {
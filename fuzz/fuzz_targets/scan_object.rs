@@ -0,0 +1,46 @@
+//! Scans objects of the bidirectional model whose header has been
+//! overwritten with arbitrary bytes. Whatever the header says, scanning must
+//! not panic, and a status the model does not know about must be scanned
+//! through the TIB, exactly like the fallback variant of the model does.
+#![no_main]
+
+use hwgc_soft::{Address, BidirectionalObjectModel, HeapDump, ObjectModel, ObjectReference};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+const STATUS_BYTE_OFFSET: usize = 1;
+const LAST_VALID_STATUS: u8 = 2;
+
+/// Addresses of the objects of a small heap restored once per process: an
+/// object array (status 2) pointing to objects without references (status 0).
+fn objects() -> &'static [ObjectReference] {
+    static OBJECTS: OnceLock<Vec<ObjectReference>> = OnceLock::new();
+    OBJECTS.get_or_init(|| {
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64").unwrap();
+        heapdump.map_spaces().unwrap();
+        let mut object_model = BidirectionalObjectModel::<true>::new();
+        object_model.restore_objects(&heapdump);
+        object_model.objects_iter().collect()
+    })
+}
+
+fn scan<O: ObjectModel>(o: ObjectReference) -> Vec<(Address, u64)> {
+    let mut slots = vec![];
+    O::scan_object(o, |start, count| slots.push((start, count)));
+    slots
+}
+
+fuzz_target!(|input: (u8, [u8; 8])| {
+    let (index, header) = input;
+    let objects = objects();
+    let o = objects[index as usize % objects.len()];
+    let header_slot = o.to_address();
+    let original = unsafe { header_slot.load::<u64>() };
+    unsafe { header_slot.store(u64::from_le_bytes(header)) };
+    let scanned = scan::<BidirectionalObjectModel<true>>(o);
+    if header[STATUS_BYTE_OFFSET] > LAST_VALID_STATUS {
+        assert!(BidirectionalObjectModel::<true>::tib_lookup_required(o));
+        assert_eq!(scanned, scan::<BidirectionalObjectModel<false>>(o));
+    }
+    unsafe { header_slot.store(original) };
+});
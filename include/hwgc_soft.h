@@ -0,0 +1,39 @@
+#ifndef HWGC_SOFT_H
+#define HWGC_SOFT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct HwgcSession HwgcSession;
+
+/* Apart from marked_objects and time_ns, only collected by builds with the
+ * detailed_stats feature. */
+typedef struct HwgcTraceStats {
+    uint64_t marked_objects;
+    uint64_t slots;
+    uint64_t non_empty_slots;
+    uint64_t sends;
+    uint64_t packets;
+    uint64_t time_ns;
+} HwgcTraceStats;
+
+/* Object model and tracing loop are named as on the command line, e.g.
+ * "OpenJDK" and "EdgeSlot". Returns NULL on error. */
+HwgcSession *hwgc_session_new(const char *heapdump_path, const char *object_model,
+                              const char *tracing_loop, size_t threads);
+/* Returns 0 on success. stats may be NULL. After a failed closure, the
+ * session can only be freed. */
+int hwgc_trace(HwgcSession *session, HwgcTraceStats *stats);
+void hwgc_session_free(HwgcSession *session);
+/* Error of the last failed call on this thread, or NULL. */
+const char *hwgc_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // HWGC_SOFT_H
@@ -0,0 +1,149 @@
+//! How many objects alignment encoding spares a TIB lookup (`ae-coverage`),
+//! with the patterns in use and with the best patterns for the heapdumps.
+//! An object matches a pattern only if its reference fields are exactly the
+//! fields of the pattern, so the best table of `k` patterns is simply the
+//! `k` most common sets of reference fields.
+
+use crate::object_model::{
+    ae_field_mask, ae_patterns, AePatternTable, FALLBACK_PATTERN, FIELD_CODES, PATTERN_FIELDS,
+};
+use crate::*;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct Coverage {
+    objects: usize,
+    /// Always encoded, with a code of their own
+    objarrays: usize,
+    /// Other objects by the mask of their reference fields, `None` for those
+    /// with references past the fields a pattern can cover
+    masks: HashMap<Option<u8>, usize>,
+}
+
+impl Coverage {
+    fn add(&mut self, heapdump: &HeapDump) {
+        for o in &heapdump.objects {
+            self.objects += 1;
+            if o.objarray_length.is_some() {
+                self.objarrays += 1;
+            } else {
+                *self.masks.entry(ae_field_mask(o)).or_default() += 1;
+            }
+        }
+    }
+
+    /// Objects that still need a TIB lookup with `table`.
+    fn fallbacks(&self, table: &AePatternTable) -> usize {
+        self.masks
+            .iter()
+            .filter(|(&mask, _)| table.code_of(mask) == FALLBACK_PATTERN)
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    /// The table of the `k` most common masks, ties going to the smaller
+    /// mask so that the table does not depend on hashing.
+    fn best(&self, k: usize) -> AePatternTable {
+        let mut masks: Vec<(u8, usize)> = self
+            .masks
+            .iter()
+            .filter_map(|(&mask, &count)| mask.map(|m| (m, count)))
+            .collect();
+        masks.sort_by_key(|&(mask, count)| (std::cmp::Reverse(count), mask));
+        let mut table = [None; FIELD_CODES];
+        for (code, &(mask, _)) in masks.iter().take(k).enumerate() {
+            table[code] = Some(mask);
+        }
+        AePatternTable::new(table).unwrap()
+    }
+
+    fn print(&self, name: &str, best: &AePatternTable, k: usize) {
+        let percent = |n: usize| n as f64 * 100.0 / self.objects.max(1) as f64;
+        println!(
+            "{}: {} objects, {} object arrays",
+            name, self.objects, self.objarrays
+        );
+        for (label, table) in [("Chosen", ae_patterns()), ("Best", best)] {
+            let fallbacks = self.fallbacks(table);
+            println!(
+                "  {:<6} patterns: {:>7.2}% encoded, {:>7.2}% fallback",
+                label,
+                percent(self.objects - fallbacks),
+                percent(fallbacks)
+            );
+        }
+        println!("  Best {} patterns:", k);
+        for (code, mask) in best.masks().iter().enumerate() {
+            let Some(mask) = mask else {
+                continue;
+            };
+            let fields: Vec<u64> = (0..PATTERN_FIELDS)
+                .filter(|f| mask & (1 << f) != 0)
+                .collect();
+            println!(
+                "    {} {:<22} {:>7.2}%",
+                code,
+                format!("{:?}", fields),
+                percent(self.masks[&Some(*mask)])
+            );
+        }
+    }
+}
+
+pub fn ae_coverage(args: &Args) -> Result<()> {
+    let Some(Commands::AeCoverage(coverage_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    let k = coverage_args.patterns;
+    if !(1..=FIELD_CODES).contains(&k) {
+        bail!(
+            "Alignment encoding has codes for 1 to {} patterns",
+            FIELD_CODES
+        );
+    }
+    let mut total = Coverage::default();
+    for path in &args.paths {
+        let heapdump = HeapDump::from_path(path)?;
+        let mut coverage = Coverage::default();
+        coverage.add(&heapdump);
+        coverage.print(path, &coverage.best(k), k);
+        total.add(&heapdump);
+    }
+    let best = total.best(k);
+    if args.paths.len() > 1 {
+        total.print("All heapdumps", &best, k);
+    }
+    if let Some(output_path) = &coverage_args.output_path {
+        std::fs::write(output_path, best.to_toml())?;
+        info!("Wrote the best {} patterns to {}", k, output_path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_best_patterns() {
+        let mut coverage = Coverage {
+            objects: 100,
+            objarrays: 10,
+            ..Default::default()
+        };
+        // Fields 0 and 2, then 0, then 1, then too many fields to encode
+        coverage.masks.insert(Some(0b101), 40);
+        coverage.masks.insert(Some(0b1), 25);
+        coverage.masks.insert(Some(0b10), 15);
+        coverage.masks.insert(None, 10);
+        let best = coverage.best(2);
+        assert_eq!(best.code_of(Some(0b101)), 0);
+        assert_eq!(best.code_of(Some(0b1)), 1);
+        assert_eq!(coverage.fallbacks(&best), 25);
+        // OpenJDK-MMTk's patterns have field 0 alone, but neither fields 0
+        // and 2 nor field 1 alone
+        assert_eq!(coverage.fallbacks(&AePatternTable::default()), 65);
+        assert_eq!(coverage.fallbacks(&coverage.best(FIELD_CODES)), 10);
+    }
+}
@@ -0,0 +1,234 @@
+//! Whether the objects and slots of a heapdump line up as the tracing
+//! loops and the simulator assume (`alignment`). Mark bytes and side mark
+//! bits are per word, the simulated caches charge one line for the header
+//! of an object and one for a slot, and the scan engines split arrays at
+//! slot boundaries. A header straddling two lines costs an extra miss on
+//! every mark, and a slot straddling two lines, or not aligned to its
+//! size, should never happen, but synthetic and foreign heapdumps may get
+//! it wrong.
+
+use crate::constants::BYTES_IN_WORD;
+use crate::*;
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Audit {
+    objects: u64,
+    /// Headers running into the next line
+    straddling_headers: u64,
+    /// Objects not aligned to a word
+    misaligned_objects: u64,
+    slots: u64,
+    straddling_slots: u64,
+    /// Slots not aligned to their size
+    misaligned_slots: u64,
+    /// Objects by the log2 of the alignment of their start, up to that of
+    /// a line
+    alignments: Vec<u64>,
+}
+
+/// An object or slot that breaks an assumption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Flag {
+    kind: &'static str,
+    address: u64,
+    /// The object of a slot, or the object itself
+    object: u64,
+}
+
+struct Layout {
+    log_line_size: u32,
+    header_size: u64,
+    slot_size: u64,
+}
+
+impl Layout {
+    fn straddles(&self, address: u64, size: u64) -> bool {
+        address >> self.log_line_size != (address + size - 1) >> self.log_line_size
+    }
+
+    fn audit(&self, objects: &[HeapObject]) -> (Audit, Vec<Flag>) {
+        let mut audit = Audit {
+            alignments: vec![0; self.log_line_size as usize + 1],
+            ..Default::default()
+        };
+        let mut flags = vec![];
+        let mut flag = |kind, address, object| {
+            flags.push(Flag {
+                kind,
+                address,
+                object,
+            })
+        };
+        for o in objects {
+            audit.objects += 1;
+            let log_alignment = o.start.trailing_zeros().min(self.log_line_size);
+            audit.alignments[log_alignment as usize] += 1;
+            if o.start % BYTES_IN_WORD as u64 != 0 {
+                audit.misaligned_objects += 1;
+                flag("misaligned_object", o.start, o.start);
+            }
+            if self.straddles(o.start, self.header_size.min(o.size.max(1))) {
+                audit.straddling_headers += 1;
+                flag("straddling_header", o.start, o.start);
+            }
+            for e in &o.edges {
+                audit.slots += 1;
+                if e.slot % self.slot_size != 0 {
+                    audit.misaligned_slots += 1;
+                    flag("misaligned_slot", e.slot, o.start);
+                }
+                if self.straddles(e.slot, self.slot_size) {
+                    audit.straddling_slots += 1;
+                    flag("straddling_slot", e.slot, o.start);
+                }
+            }
+        }
+        (audit, flags)
+    }
+}
+
+fn print(path: &str, audit: &Audit, args: &AlignmentArgs) {
+    let percent = |n: u64, of: u64| n as f64 * 100.0 / of.max(1) as f64;
+    println!(
+        "{}: {} objects and {} slots in lines of {} bytes",
+        path, audit.objects, audit.slots, args.line_size
+    );
+    println!(
+        "  Headers of {} bytes straddling lines: {} ({:.2}%), objects not word aligned: {}",
+        args.header_size,
+        audit.straddling_headers,
+        percent(audit.straddling_headers, audit.objects),
+        audit.misaligned_objects
+    );
+    println!(
+        "  Slots of {} bytes straddling lines: {}, not aligned to their size: {}",
+        args.slot_size, audit.straddling_slots, audit.misaligned_slots
+    );
+    if audit.straddling_slots + audit.misaligned_slots + audit.misaligned_objects > 0 {
+        warn!(
+            "{} breaks the alignment the tracing loops and the simulator assume",
+            path
+        );
+    }
+    let last = audit.alignments.len() - 1;
+    println!(
+        "  Start alignment: {}",
+        audit
+            .alignments
+            .iter()
+            .enumerate()
+            .filter(|(_, &n)| n > 0)
+            .map(|(k, &n)| format!(
+                "{}{}: {} ({:.2}%)",
+                1u64 << k,
+                if k == last { "+" } else { "" },
+                n,
+                percent(n, audit.objects)
+            ))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+pub fn alignment(args: &Args) -> Result<()> {
+    let Some(Commands::Alignment(alignment_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    if !alignment_args.line_size.is_power_of_two() || alignment_args.line_size < 8 {
+        bail!("--line-size has to be a power of two of at least 8 bytes");
+    }
+    if alignment_args.header_size == 0 || alignment_args.slot_size == 0 {
+        bail!("--header-size and --slot-size have to be at least a byte");
+    }
+    let layout = Layout {
+        log_line_size: alignment_args.line_size.trailing_zeros(),
+        header_size: alignment_args.header_size,
+        slot_size: alignment_args.slot_size,
+    };
+    let mut csv = match &alignment_args.output_path {
+        Some(output_path) => {
+            let mut file = BufWriter::new(File::create(output_path)?);
+            writeln!(file, "heapdump,kind,address,object")?;
+            Some(file)
+        }
+        None => None,
+    };
+    for path in &args.paths {
+        let heapdump = HeapDump::from_path(path)?;
+        let (audit, flags) = layout.audit(&heapdump.objects);
+        print(path, &audit, alignment_args);
+        if let Some(csv) = &mut csv {
+            for f in &flags {
+                writeln!(csv, "{},{},{},{}", path, f.kind, f.address, f.object)?;
+            }
+        }
+    }
+    if let Some(mut csv) = csv {
+        csv.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::NormalEdge;
+
+    #[test]
+    fn test_alignment_audit() {
+        let object = |start, size, slots: &[u64]| HeapObject {
+            start,
+            size,
+            edges: slots
+                .iter()
+                .map(|&slot| NormalEdge { slot, objref: 0 })
+                .collect(),
+            ..Default::default()
+        };
+        let objects = [
+            object(0x1000, 32, &[0x1010, 0x1018]),
+            // The header runs into the next line
+            object(0x1038, 24, &[0x1048]),
+            // A slot across two lines, and one not aligned at all
+            object(0x1080, 128, &[0x10bc, 0x10c4]),
+            object(0x1004, 16, &[]),
+        ];
+        let layout = Layout {
+            log_line_size: 6,
+            header_size: 16,
+            slot_size: 8,
+        };
+        let (audit, flags) = layout.audit(&objects);
+        assert_eq!(
+            audit,
+            Audit {
+                objects: 4,
+                straddling_headers: 1,
+                misaligned_objects: 1,
+                slots: 5,
+                straddling_slots: 1,
+                misaligned_slots: 2,
+                alignments: vec![0, 0, 1, 1, 0, 0, 2],
+            }
+        );
+        assert_eq!(
+            flags[..2],
+            [
+                Flag {
+                    kind: "straddling_header",
+                    address: 0x1038,
+                    object: 0x1038
+                },
+                Flag {
+                    kind: "misaligned_slot",
+                    address: 0x10bc,
+                    object: 0x1080
+                }
+            ]
+        );
+        assert_eq!(flags.len(), 5);
+    }
+}
@@ -4,13 +4,99 @@ use anyhow::Result;
 use polars::functions::concat_df_diagonal;
 use polars::prelude::*;
 use std::{
-    collections::{HashMap, VecDeque},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
     fs::File,
     iter,
 };
 
 type Depth = u64;
 
+/// Scan cost of reading an object's header, in the same unit as one slot.
+const HEADER_SCAN_COST: u64 = 1;
+/// Scan cost of looking up an object's TIB, in the same unit as one slot.
+const TIB_SCAN_COST: u64 = 1;
+
+/// Cost of scanning `o`, which has `slots` slots, including its TIB lookup.
+pub(crate) fn sized_scan_cost<O: ObjectModel>(o: ObjectReference, slots: u64) -> u64 {
+    let tib = if O::tib_lookup_required(o) {
+        TIB_SCAN_COST
+    } else {
+        0
+    };
+    HEADER_SCAN_COST + tib + slots
+}
+
+/// Lower bound on tracing time with unbounded parallelism.
+///
+/// Each object costs one header read plus one unit per slot, and its children
+/// can only be discovered once it has been scanned. The critical path is
+/// therefore the latest finish time over all objects when every object starts
+/// as soon as its earliest-finishing parent is done.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CriticalPath {
+    /// Cost along the critical path
+    pub(crate) length: u64,
+    /// Cost of scanning the whole heap serially
+    pub(crate) total_work: u64,
+}
+
+impl CriticalPath {
+    /// Best possible speedup over a single processor.
+    pub(crate) fn speedup_bound(&self) -> f64 {
+        self.total_work as f64 / self.length as f64
+    }
+}
+
+/// Computes the critical path of the restored heap. This does not touch mark
+/// bits, so it can run right before a trace or simulation of the same heap.
+pub(crate) fn critical_path<O: ObjectModel>(object_model: &O) -> CriticalPath {
+    weighted_critical_path(object_model, |_, slots| HEADER_SCAN_COST + slots)
+}
+
+/// The critical path where scanning an object with some slots costs
+/// `scan_cost(object, slots)`.
+pub(crate) fn weighted_critical_path<O: ObjectModel>(
+    object_model: &O,
+    scan_cost: impl Fn(ObjectReference, u64) -> u64,
+) -> CriticalPath {
+    // Dijkstra over start times, since costs are non-negative
+    let mut start_times: HashMap<ObjectReference, u64> = HashMap::new();
+    let mut queue: BinaryHeap<Reverse<(u64, ObjectReference)>> = BinaryHeap::new();
+    let roots = object_model.roots().iter();
+    for root in roots.filter_map(|&r| ObjectReference::from_raw(r)) {
+        if start_times.insert(root, 0).is_none() {
+            queue.push(Reverse((0, root)));
+        }
+    }
+    let mut length = 0;
+    let mut total_work = 0;
+    while let Some(Reverse((start, o))) = queue.pop() {
+        if start_times[&o] < start {
+            // Stale entry, o was reached earlier through another parent
+            continue;
+        }
+        let mut children = vec![];
+        let mut slots = 0;
+        for e in O::slots_range(o) {
+            slots += 1;
+            children.extend(unsafe { e.load_object() });
+        }
+        let cost = scan_cost(o, slots);
+        let finish = start + cost;
+        total_work += cost;
+        length = length.max(finish);
+        for child in children {
+            let best = start_times.entry(child).or_insert(u64::MAX);
+            if finish < *best {
+                *best = finish;
+                queue.push(Reverse((finish, child)));
+            }
+        }
+    }
+    CriticalPath { length, total_work }
+}
+
 pub fn object_depth<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
     let object_depth_args = if let Some(Commands::Depth(a)) = args.command {
         a
@@ -19,34 +105,40 @@ pub fn object_depth<O: ObjectModel>(mut object_model: O, args: Args) -> Result<(
     };
     let mut dfs = vec![];
     for (i, path) in args.paths.iter().enumerate() {
-        let heapdump = HeapDump::from_path(path)?;
+        let mut heapdump = HeapDump::from_path(path)?;
+        heapdump.select_roots(&args.roots)?;
+        if let Some(seed) = args.aslr_seed {
+            heapdump.randomize_layout(seed)?;
+        }
         object_model.reset();
         heapdump.map_spaces()?;
         object_model.restore_objects(&heapdump);
+        let cp = critical_path(&object_model);
+        println!(
+            "{}: critical path {} of {} total scan work, speedup bound {:.1}",
+            path,
+            cp.length,
+            cp.total_work,
+            cp.speedup_bound()
+        );
         let mut depth_hist: HashMap<Depth, u64> = HashMap::new();
-        let mut mark_queue: VecDeque<(u64, Depth)> = VecDeque::new();
+        let mut mark_queue: VecDeque<(ObjectReference, Depth)> = VecDeque::new();
         for root in object_model.roots() {
-            let o = *root;
-            mark_queue.push_back((o, 0));
-            debug_assert_ne!(o, 0);
+            mark_queue.push_back((ObjectReference::from_raw_unchecked(*root), 0));
         }
         while let Some((o, depth)) = mark_queue.pop_front() {
             if unsafe { trace_object(o, 1) } {
                 *depth_hist.entry(depth).or_default() += 1;
-                O::scan_object(o, |edge, repeat| {
-                    for i in 0..repeat {
-                        let e = edge.wrapping_add(i as usize);
-                        let child = unsafe { *e };
-                        if child != 0 {
-                            mark_queue.push_back((child, depth + 1));
-                        }
+                for e in O::slots_range(o) {
+                    if let Some(child) = unsafe { e.load_object() } {
+                        mark_queue.push_back((child, depth + 1));
                     }
-                });
+                }
             }
         }
         debug_assert_eq!(
             depth_hist.values().sum::<u64>() as usize,
-            object_model.objects().len()
+            object_model.num_objects()
         );
         let (depth_vec, count_vec): (Vec<Depth>, Vec<u64>) = depth_hist.into_iter().unzip();
         let mut df = df! {
@@ -65,3 +157,70 @@ pub fn object_depth<O: ObjectModel>(mut object_model: O, args: Args) -> Result<(
     writer.finish(&mut df)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::generated_src::{NormalEdge, Space};
+    use crate::trace::conformance::HEAP;
+
+    const BASE: u64 = 0x20000000000;
+
+    /// The `i`th object of the test heap, of `klass`, pointing to the objects
+    /// numbered `children`.
+    fn object(i: u64, klass: u64, children: &[u64]) -> HeapObject {
+        let start = BASE + 0x40 * i;
+        HeapObject {
+            start,
+            klass,
+            size: 16 + 8 * children.len() as u64,
+            objarray_length: None,
+            instance_mirror_start: None,
+            instance_mirror_count: None,
+            edges: children
+                .iter()
+                .enumerate()
+                .map(|(j, &child)| NormalEdge {
+                    slot: start + 16 + 8 * j as u64,
+                    objref: BASE + 0x40 * child,
+                })
+                .collect(),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn test_critical_path() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        // a -> {b, c}, b -> {d, e}, c -> e and e -> f. Scanning e starts
+        // once c, its earlier parent, is done, so the critical path is
+        // a (3), c (2), e (2) and f (1), not through b
+        let heapdump = HeapDump {
+            objects: vec![
+                object(0, 0x7e57_0001, &[1, 2]),
+                object(1, 0x7e57_0001, &[3, 4]),
+                object(2, 0x7e57_0002, &[4]),
+                object(3, 0x7e57_0003, &[]),
+                object(4, 0x7e57_0002, &[5]),
+                object(5, 0x7e57_0003, &[]),
+            ],
+            roots: vec![RootEdge { objref: BASE }],
+            root_sets: vec![],
+            spaces: vec![Space {
+                name: "immix".to_string(),
+                start: BASE,
+                end: BASE + 0x40 * 6,
+            }],
+            ..Default::default()
+        };
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        let cp = critical_path(&object_model);
+        heapdump.unmap_spaces().unwrap();
+        assert_eq!(cp.length, 8);
+        assert_eq!(cp.total_work, 12);
+        assert_eq!(cp.speedup_bound(), 1.5);
+    }
+}
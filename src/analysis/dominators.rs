@@ -0,0 +1,336 @@
+//! Which objects own the heap (`dominators`): the dominator tree of the
+//! heapdump graph, like Eclipse MAT's, with the bytes, objects and slots
+//! each object retains, i.e., what tracing would no longer visit if the
+//! object were unreachable.
+//!
+//! Immediate dominators come from the iterative algorithm of Cooper, Harvey
+//! and Kennedy over a virtual node whose successors are the roots, which is
+//! simpler than Lengauer-Tarjan and converges in a few passes on heaps.
+
+use crate::*;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// The virtual node that every root hangs off
+const SUPER_ROOT: usize = 0;
+const UNDEFINED: usize = usize::MAX;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Retained {
+    bytes: u64,
+    objects: u64,
+    slots: u64,
+}
+
+/// The dominator tree of the objects reachable from the roots. Node `i + 1`
+/// is object `i` of the heapdump.
+struct DominatorTree<'a> {
+    heapdump: &'a HeapDump,
+    /// Immediate dominator of each node, `UNDEFINED` if unreachable
+    idom: Vec<usize>,
+    retained: Vec<Retained>,
+    /// Children of each node in the dominator tree, most retained first
+    children: Vec<Vec<usize>>,
+}
+
+impl<'a> DominatorTree<'a> {
+    fn new(heapdump: &'a HeapDump) -> Self {
+        let n = heapdump.objects.len() + 1;
+        let index: HashMap<u64, usize> = heapdump
+            .objects
+            .iter()
+            .enumerate()
+            .map(|(i, o)| (o.start, i + 1))
+            .collect();
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; n];
+        successors[SUPER_ROOT] = heapdump
+            .all_roots()
+            .filter_map(|r| index.get(&r.objref).copied())
+            .collect();
+        for (i, o) in heapdump.objects.iter().enumerate() {
+            successors[i + 1] = o
+                .edges
+                .iter()
+                .filter_map(|e| index.get(&e.objref).copied())
+                .collect();
+        }
+
+        // Postorder numbers from an iterative depth-first search
+        let mut postorder = vec![UNDEFINED; n];
+        let mut order = vec![];
+        let mut visited = vec![false; n];
+        let mut stack = vec![(SUPER_ROOT, 0)];
+        visited[SUPER_ROOT] = true;
+        while let Some((node, next)) = stack.last_mut() {
+            if let Some(&succ) = successors[*node].get(*next) {
+                *next += 1;
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                postorder[*node] = order.len();
+                order.push(*node);
+                stack.pop();
+            }
+        }
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; n];
+        for &node in &order {
+            for &succ in &successors[node] {
+                predecessors[succ].push(node);
+            }
+        }
+
+        let mut idom = vec![UNDEFINED; n];
+        idom[SUPER_ROOT] = SUPER_ROOT;
+        let intersect = |idom: &[usize], mut a: usize, mut b: usize| {
+            while a != b {
+                while postorder[a] < postorder[b] {
+                    a = idom[a];
+                }
+                while postorder[b] < postorder[a] {
+                    b = idom[b];
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            // Reverse postorder, skipping the super root that comes last
+            for &node in order.iter().rev().skip(1) {
+                let mut new_idom = UNDEFINED;
+                for &pred in &predecessors[node] {
+                    if idom[pred] == UNDEFINED {
+                        continue;
+                    }
+                    new_idom = if new_idom == UNDEFINED {
+                        pred
+                    } else {
+                        intersect(&idom, pred, new_idom)
+                    };
+                }
+                if idom[node] != new_idom {
+                    idom[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        // A dominator finishes after everything it dominates, so postorder
+        // adds each subtree up before its root is added to its own dominator
+        let mut retained = vec![Retained::default(); n];
+        let mut children: Vec<Vec<usize>> = vec![vec![]; n];
+        for &node in &order {
+            if node == SUPER_ROOT {
+                continue;
+            }
+            let o = &heapdump.objects[node - 1];
+            let r = &mut retained[node];
+            r.bytes += o.size;
+            r.objects += 1;
+            r.slots += o.edges.len() as u64;
+            let r = *r;
+            let parent = &mut retained[idom[node]];
+            parent.bytes += r.bytes;
+            parent.objects += r.objects;
+            parent.slots += r.slots;
+            children[idom[node]].push(node);
+        }
+        for c in &mut children {
+            // Ties broken by address so that the tree is stable across runs
+            c.sort_by_key(|&node| {
+                (
+                    std::cmp::Reverse(retained[node].bytes),
+                    heapdump.objects[node - 1].start,
+                )
+            });
+        }
+        DominatorTree {
+            heapdump,
+            idom,
+            retained,
+            children,
+        }
+    }
+
+    fn total(&self) -> Retained {
+        self.retained[SUPER_ROOT]
+    }
+
+    fn print(&self, node: usize, depth: usize, args: &DominatorsArgs) {
+        let total = self.total().bytes.max(1) as f64;
+        let children = &self.children[node];
+        let shown = children
+            .iter()
+            .take(args.top)
+            .take_while(|&&c| self.retained[c].bytes as f64 * 100.0 / total >= args.min_percent)
+            .count();
+        for &child in &children[..shown] {
+            let o = &self.heapdump.objects[child - 1];
+            let r = self.retained[child];
+            println!(
+                "{:indent$}0x{:x} klass 0x{:x}: {} bytes ({:.2}%), {} objects, {} slots",
+                "",
+                o.start,
+                o.klass,
+                r.bytes,
+                r.bytes as f64 * 100.0 / total,
+                r.objects,
+                r.slots,
+                indent = 2 * depth + 2
+            );
+            if depth + 1 < args.depth {
+                self.print(child, depth + 1, args);
+            }
+        }
+        if shown < children.len() {
+            let rest: u64 = children[shown..]
+                .iter()
+                .map(|&c| self.retained[c].bytes)
+                .sum();
+            println!(
+                "{:indent$}... {} more retaining {} bytes ({:.2}%)",
+                "",
+                children.len() - shown,
+                rest,
+                rest as f64 * 100.0 / total,
+                indent = 2 * depth + 2
+            );
+        }
+    }
+
+    fn write_csv(&self, path: &str, output: &mut impl Write) -> Result<()> {
+        let mut nodes: Vec<usize> = (1..self.idom.len())
+            .filter(|&node| self.idom[node] != UNDEFINED)
+            .collect();
+        nodes.sort_by_key(|&node| {
+            (
+                std::cmp::Reverse(self.retained[node].bytes),
+                self.heapdump.objects[node - 1].start,
+            )
+        });
+        for node in nodes {
+            let o = &self.heapdump.objects[node - 1];
+            let r = self.retained[node];
+            let dominator = match self.idom[node] {
+                SUPER_ROOT => 0,
+                d => self.heapdump.objects[d - 1].start,
+            };
+            writeln!(
+                output,
+                "{},{},{},{},{},{},{},{}",
+                path, o.start, o.klass, o.size, r.bytes, r.objects, r.slots, dominator
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn dominators(args: &Args) -> Result<()> {
+    let Some(Commands::Dominators(dominators_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    let mut csv = match &dominators_args.output_path {
+        Some(output_path) => {
+            let mut file = BufWriter::new(File::create(output_path)?);
+            writeln!(
+                file,
+                "heapdump,address,klass,size,retained_bytes,retained_objects,retained_slots,dominator"
+            )?;
+            Some(file)
+        }
+        None => None,
+    };
+    for path in &args.paths {
+        let mut heapdump = HeapDump::from_path(path)?;
+        heapdump.select_roots(&args.roots)?;
+        let tree = DominatorTree::new(&heapdump);
+        let total = tree.total();
+        println!(
+            "{}: {} live bytes, {} objects, {} slots",
+            path, total.bytes, total.objects, total.slots
+        );
+        tree.print(SUPER_ROOT, 0, dominators_args);
+        if let Some(csv) = &mut csv {
+            tree.write_csv(path, csv)?;
+        }
+    }
+    if let Some(mut csv) = csv {
+        csv.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::NormalEdge;
+
+    fn object(start: u64, size: u64, children: &[u64]) -> HeapObject {
+        HeapObject {
+            start,
+            klass: 0x7e57_0002,
+            size,
+            objarray_length: None,
+            instance_mirror_start: None,
+            instance_mirror_count: None,
+            edges: children
+                .iter()
+                .enumerate()
+                .map(|(i, &objref)| NormalEdge {
+                    slot: start + 16 + 8 * i as u64,
+                    objref,
+                })
+                .collect(),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn test_dominator_tree() {
+        // a -> b -> d, a -> c -> d and d -> e -> d, with a second root at c
+        // and an unreachable f
+        let heapdump = HeapDump {
+            objects: vec![
+                object(0x1000, 32, &[0x1100, 0x1200]),
+                object(0x1100, 24, &[0x1300]),
+                object(0x1200, 24, &[0x1300]),
+                object(0x1300, 40, &[0x1400]),
+                object(0x1400, 24, &[0x1300]),
+                object(0x1500, 16, &[]),
+            ],
+            roots: vec![RootEdge { objref: 0x1000 }, RootEdge { objref: 0x1200 }],
+            root_sets: vec![],
+            spaces: vec![],
+            ..Default::default()
+        };
+        let tree = DominatorTree::new(&heapdump);
+        assert_eq!(
+            tree.idom[1..],
+            [SUPER_ROOT, 1, SUPER_ROOT, SUPER_ROOT, 4, UNDEFINED]
+        );
+        assert_eq!(
+            tree.retained[4],
+            Retained {
+                bytes: 64,
+                objects: 2,
+                slots: 2
+            }
+        );
+        assert_eq!(tree.retained[1].bytes, 56);
+        assert_eq!(tree.total().bytes, 144);
+        assert_eq!(tree.children[SUPER_ROOT], vec![4, 1, 3]);
+
+        let mut csv = vec![];
+        tree.write_csv("test", &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 5);
+        assert_eq!(
+            csv.lines().next().unwrap(),
+            format!("test,{},{},40,64,2,2,0", 0x1300, 0x7e57_0002)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Which objects share cache lines (`line-conflicts`), for evaluating mark
+//! byte false sharing between parallel markers and hardware that marks at
+//! the granularity of lines.
+//!
+//! Objects sharing a line conflict. Groups are the connected components of
+//! the conflicts, so that handing each group to one marker never shares a
+//! line between markers; as objects do not overlap, they are the runs of
+//! objects each ending in the line the next one starts in. Colors are such
+//! that objects of one color never share a line, so that they can all be
+//! marked in parallel. The conflicts form an interval graph, whose greedy
+//! coloring in address order is optimal, with as many colors as the most
+//! objects in one line.
+
+use crate::*;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// An object with the lines it touches, its group, color and conflicts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    start: u64,
+    size: u64,
+    first_line: u64,
+    last_line: u64,
+    group: u64,
+    color: u64,
+    degree: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct LineConflicts {
+    /// Lines touched by any object, and by more than one
+    lines: u64,
+    shared_lines: u64,
+    /// Objects sharing a line with another object
+    conflicted_objects: u64,
+    /// Pairs of objects sharing a line, and the most any object is in
+    conflicts: u64,
+    max_degree: u64,
+    groups: u64,
+    /// The most objects and bytes of one group
+    largest_group_objects: u64,
+    largest_group_bytes: u64,
+    /// Objects of each color
+    color_sizes: Vec<u64>,
+}
+
+/// The spans of `objects`, given as `(start, size)`, in address order.
+fn line_spans(objects: &[(u64, u64)], log_line_size: u32, header_only: bool) -> Vec<Span> {
+    let mut spans: Vec<Span> = objects
+        .iter()
+        .map(|&(start, size)| {
+            // The mark byte is in the header word
+            let end = if header_only { start + 8 } else { start + size };
+            Span {
+                start,
+                size,
+                first_line: start >> log_line_size,
+                last_line: (end.max(start + 1) - 1) >> log_line_size,
+                group: 0,
+                color: 0,
+                degree: 0,
+            }
+        })
+        .collect();
+    spans.sort_unstable_by_key(|s| s.start);
+    spans
+}
+
+/// Assigns the groups, colors and degrees of `spans`, and adds them up.
+fn color(spans: &mut [Span]) -> LineConflicts {
+    let mut stats = LineConflicts::default();
+    // Only the first and last lines of an object can be shared, and in
+    // address order they never go backwards, so the objects sharing a line
+    // are consecutive in this list
+    let mut boundaries: Vec<(u64, usize)> = vec![];
+    for (i, s) in spans.iter().enumerate() {
+        stats.lines += s.last_line - s.first_line + 1;
+        boundaries.push((s.first_line, i));
+        if s.last_line != s.first_line {
+            boundaries.push((s.last_line, i));
+        }
+    }
+    for run in boundaries.chunk_by(|a, b| a.0 == b.0) {
+        let k = run.len() as u64;
+        if k == 1 {
+            continue;
+        }
+        stats.lines -= k - 1;
+        stats.shared_lines += 1;
+        stats.conflicts += k * (k - 1) / 2;
+        for &(_, i) in run {
+            spans[i].degree += k - 1;
+        }
+    }
+
+    // Objects before the current one still in its first line, with their
+    // colors
+    let mut active: Vec<(u64, u64)> = vec![];
+    let mut group_objects = 0;
+    let mut group_bytes = 0;
+    for i in 0..spans.len() {
+        let first_line = spans[i].first_line;
+        if i == 0 || spans[i - 1].last_line != first_line {
+            stats.groups += 1;
+            group_objects = 0;
+            group_bytes = 0;
+        }
+        group_objects += 1;
+        group_bytes += spans[i].size;
+        stats.largest_group_objects = stats.largest_group_objects.max(group_objects);
+        stats.largest_group_bytes = stats.largest_group_bytes.max(group_bytes);
+        active.retain(|&(last_line, _)| last_line == first_line);
+        let color = (0..)
+            .find(|c| active.iter().all(|&(_, used)| used != *c))
+            .unwrap();
+        active.push((spans[i].last_line, color));
+        let span = &mut spans[i];
+        span.group = stats.groups - 1;
+        span.color = color;
+        if span.degree > 0 {
+            stats.conflicted_objects += 1;
+        }
+        stats.max_degree = stats.max_degree.max(span.degree);
+        if stats.color_sizes.len() <= color as usize {
+            stats.color_sizes.resize(color as usize + 1, 0);
+        }
+        stats.color_sizes[color as usize] += 1;
+    }
+    stats
+}
+
+fn print(path: &str, spans: &[Span], stats: &LineConflicts, args: &LineConflictsArgs) {
+    let objects = spans.len().max(1) as f64;
+    println!(
+        "{}: {} objects in {} lines of {} bytes{}",
+        path,
+        spans.len(),
+        stats.lines,
+        args.line_size,
+        if args.header_only {
+            ", headers only"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "  Shared lines: {} ({:.2}%), conflicted objects: {} ({:.2}%), conflicts: {}, mean degree {:.2}, max {}",
+        stats.shared_lines,
+        stats.shared_lines as f64 * 100.0 / stats.lines.max(1) as f64,
+        stats.conflicted_objects,
+        stats.conflicted_objects as f64 * 100.0 / objects,
+        stats.conflicts,
+        stats.conflicts as f64 * 2.0 / objects,
+        stats.max_degree
+    );
+    println!(
+        "  Groups: {}, {:.2} objects each, at most {} objects and {} bytes",
+        stats.groups,
+        spans.len() as f64 / stats.groups.max(1) as f64,
+        stats.largest_group_objects,
+        stats.largest_group_bytes
+    );
+    println!(
+        "  Colors: {}, with {} objects",
+        stats.color_sizes.len(),
+        stats
+            .color_sizes
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+fn write_csv(path: &str, spans: &[Span], output: &mut impl Write) -> Result<()> {
+    for s in spans {
+        writeln!(
+            output,
+            "{},{},{},{},{},{},{},{}",
+            path, s.start, s.size, s.first_line, s.last_line, s.group, s.color, s.degree
+        )?;
+    }
+    Ok(())
+}
+
+pub fn line_conflicts(args: &Args) -> Result<()> {
+    let Some(Commands::LineConflicts(conflicts_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    if !conflicts_args.line_size.is_power_of_two() || conflicts_args.line_size < 8 {
+        anyhow::bail!("--line-size has to be a power of two of at least 8 bytes");
+    }
+    let log_line_size = conflicts_args.line_size.trailing_zeros();
+    let mut csv = match &conflicts_args.output_path {
+        Some(output_path) => {
+            let mut file = BufWriter::new(File::create(output_path)?);
+            writeln!(
+                file,
+                "heapdump,address,size,first_line,last_line,group,color,degree"
+            )?;
+            Some(file)
+        }
+        None => None,
+    };
+    for path in &args.paths {
+        let heapdump = HeapDump::from_path(path)?;
+        let objects: Vec<(u64, u64)> = heapdump.objects.iter().map(|o| (o.start, o.size)).collect();
+        let mut spans = line_spans(&objects, log_line_size, conflicts_args.header_only);
+        let stats = color(&mut spans);
+        print(path, &spans, &stats, conflicts_args);
+        if let Some(csv) = &mut csv {
+            write_csv(path, &spans, csv)?;
+        }
+    }
+    if let Some(mut csv) = csv {
+        csv.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_conflicts() {
+        // Three objects in line 0, the last running into line 1 where a
+        // fourth starts, a large object alone in lines 2 to 4, and two
+        // objects sharing line 5
+        let objects = [
+            (0x20, 16),
+            (0x00, 16),
+            (0x10, 16),
+            (0x30, 32),
+            (0x50, 16),
+            (0x80, 192),
+            (0x140, 16),
+            (0x150, 16),
+        ];
+        let mut spans = line_spans(&objects, 6, false);
+        let stats = color(&mut spans);
+        assert_eq!(
+            spans.iter().map(|s| s.degree).collect::<Vec<_>>(),
+            [3, 3, 3, 4, 1, 0, 1, 1]
+        );
+        assert_eq!(
+            spans.iter().map(|s| s.color).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            spans.iter().map(|s| s.group).collect::<Vec<_>>(),
+            [0, 0, 0, 0, 0, 1, 2, 2]
+        );
+        assert_eq!(
+            stats,
+            LineConflicts {
+                lines: 6,
+                shared_lines: 3,
+                conflicted_objects: 7,
+                conflicts: 8,
+                max_degree: 4,
+                groups: 3,
+                largest_group_objects: 5,
+                largest_group_bytes: 192,
+                color_sizes: vec![4, 2, 1, 1],
+            }
+        );
+
+        // With only the headers, the fourth object no longer reaches line 1
+        let mut spans = line_spans(&objects, 6, true);
+        let stats = color(&mut spans);
+        assert_eq!(stats.groups, 4);
+        assert_eq!(stats.conflicts, 7);
+    }
+}
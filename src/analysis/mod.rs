@@ -1,3 +1,5 @@
+use crate::results::RunResults;
+use crate::util::{imbalance, owner_hash};
 use crate::*;
 use anyhow::Result;
 use std::alloc;
@@ -8,7 +10,12 @@ mod work;
 use work::*;
 mod stats;
 use stats::*;
+pub(crate) mod ae_coverage;
+pub(crate) mod alignment;
 pub(crate) mod depth;
+pub(crate) mod dominators;
+pub(crate) mod line_conflicts;
+pub(crate) mod why_reachable;
 
 struct Analysis {
     owner_shift: usize,
@@ -23,6 +30,8 @@ struct Analysis {
     /// How far to go to get to the next stride of the same thread
     next_stride_delta: usize,
     eager_load: bool,
+    /// Seed of `--owner-hash`
+    owner_hash: Option<u64>,
 }
 
 impl Analysis {
@@ -38,14 +47,35 @@ impl Analysis {
             stride_length: 1 << args.owner_shift,
             next_stride_delta: 1 << (args.owner_shift + args.log_num_threads),
             eager_load: args.eager_load,
+            owner_hash: args.owner_hash,
         }
     }
 
     fn get_owner_thread(&self, o: u64) -> usize {
+        self.get_linear_owner_thread(o) ^ self.owner_hash_of(o)
+    }
+
+    /// The owner of `o` without `--owner-hash`, going round the threads in
+    /// address order.
+    fn get_linear_owner_thread(&self, o: u64) -> usize {
         let mask = ((self.num_threads - 1) << self.owner_shift) as u64;
         ((o & mask) >> self.owner_shift) as usize
     }
 
+    /// What `--owner-hash` XORs into the owners of the strides of the
+    /// superblock of `o`, the `num_threads` strides from an aligned address,
+    /// so that each thread still owns one of them.
+    fn owner_hash_of(&self, o: u64) -> usize {
+        match self.owner_hash {
+            Some(seed) => owner_hash(
+                o >> (self.owner_shift + self.log_num_threads),
+                seed,
+                self.log_num_threads as u32,
+            ) as usize,
+            None => 0,
+        }
+    }
+
     fn reset(&mut self) {
         self.work_queue.clear();
     }
@@ -85,7 +115,6 @@ impl Analysis {
                 self.create_root_edges_work(i, root_pages_raw as *mut u64, num_roots as u64);
             }
         }
-        let object_sizes = o.object_sizes();
         // If group-slots optimization is not enable, then the work queue
         // depth should be equal to the number of roots
         if !self.rle {
@@ -94,10 +123,10 @@ impl Analysis {
             debug_assert_eq!(self.work_queue.len(), self.num_threads);
         }
         while let Some(tagged_work) = self.work_queue.pop_front() {
-            self.do_work(tagged_work, object_sizes);
+            self.do_work(tagged_work, o);
         }
         debug_assert!(self.work_queue.is_empty());
-        // for n in o.objects() {
+        // for n in o.objects_iter() {
         //     let header = Header::load(*n);
         //     if header.get_mark_byte() != 1 {
         //         error!("0x{:x} not marked by transitive closure", n);
@@ -107,6 +136,22 @@ impl Analysis {
     }
 }
 
+/// Runs the analysis on one heapdump under the bidirectional object model,
+/// and returns the tabulated statistics.
+#[cfg(feature = "python")]
+pub(crate) fn analyze_heapdump(
+    heapdump: &HeapDump,
+    analysis_args: AnalysisArgs,
+) -> Result<Vec<(String, u64)>> {
+    let mut object_model = BidirectionalObjectModel::<true>::new();
+    let mut analysis = Analysis::from_args(analysis_args);
+    heapdump.map_spaces()?;
+    object_model.restore_objects(heapdump);
+    analysis.run(&object_model);
+    heapdump.unmap_spaces()?;
+    Ok(analysis.stats.columns())
+}
+
 pub fn reified_analysis<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
     let analysis_args = if let Some(Commands::Analyze(a)) = args.command {
         a
@@ -119,6 +164,7 @@ pub fn reified_analysis<O: ObjectModel>(mut object_model: O, args: Args) -> Resu
         "The distributed GC work analysis assumes bidirectional for now"
     );
     let mut analysis = Analysis::from_args(analysis_args);
+    let mut results = RunResults::new(&args.paths, args.results.clone())?;
     for path in &args.paths {
         let p: &Path = path.as_ref();
         // Fake a DaCapo iteration for easier parsing
@@ -129,7 +175,11 @@ pub fn reified_analysis<O: ObjectModel>(mut object_model: O, args: Args) -> Resu
         let start = std::time::Instant::now();
         // reset object model internal states
         object_model.reset();
-        let heapdump = HeapDump::from_path(path)?;
+        let mut heapdump = HeapDump::from_path(path)?;
+        heapdump.select_roots(&args.roots)?;
+        if let Some(seed) = args.aslr_seed {
+            heapdump.randomize_layout(seed)?;
+        }
         // mmap
         heapdump.map_spaces()?;
         // write objects to the heap
@@ -141,9 +191,15 @@ pub fn reified_analysis<O: ObjectModel>(mut object_model: O, args: Args) -> Resu
             p.file_name().unwrap(),
             duration.as_millis()
         );
-        analysis.stats.print();
+        println!(
+            "Owned objects: imbalance {:.3} ({:.3} with the linear mapping), per worker {:?}",
+            imbalance(&analysis.stats.owned_objects),
+            imbalance(&analysis.stats.linear_owned_objects),
+            analysis.stats.owned_objects
+        );
+        results.add_table(analysis.stats.table(path));
         analysis.reset();
         heapdump.unmap_spaces()?;
     }
-    Ok(())
+    results.write()
 }
@@ -1,4 +1,6 @@
 use super::Work;
+use crate::results::StatsTable;
+use crate::util::imbalance;
 use std::{collections::HashMap, mem::Discriminant};
 
 /// Statistics about communication in a distributed near-memory GC
@@ -55,6 +57,10 @@ pub(super) struct AnalysisStats {
     pub(super) work_dist: HashMap<usize, u64>,
     /// Total objects marked
     pub(super) marked_objects: u64,
+    /// Objects marked owned by each worker, and by each worker without
+    /// `--owner-hash`
+    pub(super) owned_objects: Vec<u64>,
+    pub(super) linear_owned_objects: Vec<u64>,
     pub(super) los_objects: u64,
     pub(super) los_objarrays: u64,
     /// Total number of inter-worker messages sent
@@ -82,11 +88,14 @@ impl AnalysisStats {
     pub(super) fn new(num_threads: usize) -> Self {
         Self {
             num_threads,
+            owned_objects: vec![0; num_threads],
+            linear_owned_objects: vec![0; num_threads],
             ..Default::default()
         }
     }
 
-    pub(super) fn print(&self) {
+    /// The tabulated statistics, as column name and value.
+    pub(super) fn columns(&self) -> Vec<(String, u64)> {
         let mut dist: Vec<(usize, u64)> = self
             .work_dist
             .iter()
@@ -115,82 +124,72 @@ impl AnalysisStats {
                 "Edges",
             ),
         ];
-        println!("============================ Tabulate Statistics ============================");
-        print!(
-            "obj\tobj.los\tobj.los.objarray\t\
-            size\tsize.los\tsize.los.objarray\t\
-            slots\tslots.vis.empty\tslots.vis.child.vis\tslots.vis.child.invis\t\
-            slots.invis.empty\tslots.invis.child.vis\tslots.invis.child.invis\t\
-            slots.root.empty\tslots.root.non_empty\t\
-            slots.objarray\tslots.objarray.empty\t\
-            work"
-        );
-        for (x, _) in &dist {
-            print!("\twork.{}", x);
-        }
-        for (_, ds) in discriminants {
-            for i in 0..self.num_threads {
-                print!("\tinternal_msg.{}.{}", i, ds);
-            }
-        }
-        for (_, ds) in discriminants {
-            for i in 0..self.num_threads {
-                print!("\texternal_msg.{}.{}", i, ds);
-            }
+        let mut columns: Vec<(String, u64)> = [
+            ("obj", self.marked_objects),
+            ("obj.los", self.los_objects),
+            ("obj.los.objarray", self.los_objarrays),
+            ("size", self.total_object_size),
+            ("size.los", self.los_object_size),
+            ("size.los.objarray", self.los_objarray_size),
+            ("slots", self.slots),
+            ("slots.vis.empty", self.visible_empty_slots),
+            (
+                "slots.vis.child.vis",
+                self.visible_non_empty_slots_visible_child,
+            ),
+            (
+                "slots.vis.child.invis",
+                self.visible_non_empty_slots_invisible_child,
+            ),
+            ("slots.invis.empty", self.invisible_empty_slots),
+            (
+                "slots.invis.child.vis",
+                self.invisible_non_empty_slots_visible_child,
+            ),
+            (
+                "slots.invis.child.invis",
+                self.invisible_non_empty_slots_invisible_child,
+            ),
+            ("slots.root.empty", self.empty_root_slots),
+            ("slots.root.non_empty", self.non_empty_root_slots),
+            ("slots.objarray", self.objarray_slots),
+            ("slots.objarray.empty", self.objarray_empty_slots),
+            ("work", self.total_work),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        for (x, work_cnt) in &dist {
+            columns.push((format!("work.{}", x), *work_cnt));
         }
-        println!();
-        print!(
-            "{}\t{}\t{}\t\
-            {}\t{}\t{}\t\
-            {}\t{}\t{}\t{}\t\
-            {}\t{}\t{}\t\
-            {}\t{}\t\
-            {}\t{}\t\
-            {}",
-            self.marked_objects,
-            self.los_objects,
-            self.los_objarrays,
-            self.total_object_size,
-            self.los_object_size,
-            self.los_objarray_size,
-            self.slots,
-            self.visible_empty_slots,
-            self.visible_non_empty_slots_visible_child,
-            self.visible_non_empty_slots_invisible_child,
-            self.invisible_empty_slots,
-            self.invisible_non_empty_slots_visible_child,
-            self.invisible_non_empty_slots_invisible_child,
-            self.empty_root_slots,
-            self.non_empty_root_slots,
-            self.objarray_slots,
-            self.objarray_empty_slots,
-            self.total_work
-        );
-        for (_, work_cnt) in &dist {
-            print!("\t{}", work_cnt);
+        for (x, objects) in self.owned_objects.iter().enumerate() {
+            columns.push((format!("obj.owner.{}", x), *objects));
         }
-        for (dis, _) in discriminants {
-            for i in 0..self.num_threads {
-                let count = self
-                    .internal_messages
-                    .get(&(i, dis))
-                    .copied()
-                    .unwrap_or_default();
-                print!("\t{}", count);
+        for (kind, messages) in [
+            ("internal_msg", &self.internal_messages),
+            ("external_msg", &self.external_messages),
+        ] {
+            for (dis, ds) in discriminants {
+                for i in 0..self.num_threads {
+                    let count = messages.get(&(i, dis)).copied().unwrap_or_default();
+                    columns.push((format!("{}.{}.{}", kind, i, ds), count as u64));
+                }
             }
         }
-        for (dis, _) in discriminants {
-            for i in 0..self.num_threads {
-                let count = self
-                    .external_messages
-                    .get(&(i, dis))
-                    .copied()
-                    .unwrap_or_default();
-                print!("\t{}", count);
-            }
+        columns
+    }
+
+    /// The tabulated statistics, after checking that they add up.
+    pub(super) fn table(&self, heapdump: &str) -> StatsTable {
+        let mut table = StatsTable::new("analyze", Some(heapdump));
+        for (name, value) in self.columns() {
+            table.push(name, value);
         }
-        println!();
-        println!("-------------------------- End Tabulate Statistics --------------------------");
+        table.push("obj.imbalance", imbalance(&self.owned_objects));
+        table.push(
+            "obj.linear_imbalance",
+            imbalance(&self.linear_owned_objects),
+        );
         debug_assert_eq!(
             self.slots,
             self.visible_empty_slots
@@ -207,5 +206,6 @@ impl AnalysisStats {
         //     self.msg_process_edge + self.msg_process_edges + self.msg_process_node
         // );
         debug_assert_eq!(self.total_work, self.work_dist.values().sum::<u64>());
+        table
     }
 }
@@ -0,0 +1,306 @@
+//! Why an object is reachable (`why-reachable`): the shortest paths from the
+//! roots to it in the heapdump graph, with the slot of each edge, for
+//! finding out why one model marks an object that another does not.
+
+use crate::heapdump::DEFAULT_ROOT_SET;
+use crate::util::parse_address;
+use crate::*;
+use anyhow::{bail, Result};
+use std::collections::{HashMap, VecDeque};
+use std::io::BufRead;
+
+/// How an object is first reached on a shortest path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parent {
+    /// A root of the root set with this index in `root_set_names`
+    Root(usize),
+    /// Edge `edge` of object `object`, both indices into the heapdump
+    Edge { object: usize, edge: usize },
+}
+
+/// Shortest paths from the roots to every object, found by a breadth-first
+/// search from all roots at once.
+struct Reachability<'a> {
+    heapdump: &'a HeapDump,
+    /// Object starts in address order, with their index in the heapdump
+    starts: Vec<(u64, usize)>,
+    root_set_names: Vec<&'a str>,
+    /// Edges from the roots, `None` for unreachable objects
+    distance: Vec<Option<u32>>,
+    /// The first `max_paths` parents of each object one step closer to the
+    /// roots, which is all the path enumeration needs
+    parents: Vec<Vec<Parent>>,
+}
+
+impl<'a> Reachability<'a> {
+    fn new(heapdump: &'a HeapDump, max_paths: usize) -> Self {
+        let mut starts: Vec<(u64, usize)> = heapdump
+            .objects
+            .iter()
+            .enumerate()
+            .map(|(i, o)| (o.start, i))
+            .collect();
+        starts.sort_unstable();
+        let index: HashMap<u64, usize> = starts.iter().copied().collect();
+        let root_sets = std::iter::once((DEFAULT_ROOT_SET, &heapdump.roots)).chain(
+            heapdump
+                .root_sets
+                .iter()
+                .map(|s| (s.name.as_str(), &s.roots)),
+        );
+        let mut reachability = Reachability {
+            heapdump,
+            starts,
+            root_set_names: vec![],
+            distance: vec![None; heapdump.objects.len()],
+            parents: vec![vec![]; heapdump.objects.len()],
+        };
+        let mut queue = VecDeque::new();
+        for (set, (name, roots)) in root_sets.enumerate() {
+            reachability.root_set_names.push(name);
+            for root in roots {
+                let Some(&o) = index.get(&root.objref) else {
+                    continue;
+                };
+                if reachability.visit(o, 0, Parent::Root(set), max_paths) {
+                    queue.push_back(o);
+                }
+            }
+        }
+        while let Some(o) = queue.pop_front() {
+            let distance = reachability.distance[o].unwrap() + 1;
+            for (edge, e) in heapdump.objects[o].edges.iter().enumerate() {
+                let Some(&child) = index.get(&e.objref) else {
+                    continue;
+                };
+                let parent = Parent::Edge { object: o, edge };
+                if reachability.visit(child, distance, parent, max_paths) {
+                    queue.push_back(child);
+                }
+            }
+        }
+        reachability
+    }
+
+    /// Records `parent` if it puts `o` at `distance`, and returns whether `o`
+    /// was reached for the first time.
+    fn visit(&mut self, o: usize, distance: u32, parent: Parent, max_paths: usize) -> bool {
+        match self.distance[o] {
+            None => {
+                self.distance[o] = Some(distance);
+                self.parents[o].push(parent);
+                true
+            }
+            Some(d) => {
+                if d == distance && self.parents[o].len() < max_paths {
+                    self.parents[o].push(parent);
+                }
+                false
+            }
+        }
+    }
+
+    /// The object containing `addr`, with the offset of `addr` in it.
+    fn containing(&self, addr: u64) -> Option<(usize, u64)> {
+        let i = self.starts.partition_point(|&(start, _)| start <= addr);
+        let &(start, o) = self.starts.get(i.checked_sub(1)?)?;
+        (addr < start + self.heapdump.objects[o].size).then_some((o, addr - start))
+    }
+
+    /// Up to `k` shortest paths from the roots to `o`, each from the root
+    /// down to `o`.
+    fn paths(&self, o: usize, k: usize) -> Vec<Vec<Parent>> {
+        let mut paths = vec![];
+        let mut suffix = vec![];
+        self.collect_paths(o, k, &mut suffix, &mut paths);
+        paths
+    }
+
+    fn collect_paths(
+        &self,
+        o: usize,
+        k: usize,
+        suffix: &mut Vec<Parent>,
+        paths: &mut Vec<Vec<Parent>>,
+    ) {
+        for &parent in &self.parents[o] {
+            if paths.len() == k {
+                return;
+            }
+            suffix.push(parent);
+            match parent {
+                Parent::Root(_) => paths.push(suffix.iter().rev().copied().collect()),
+                Parent::Edge { object, .. } => self.collect_paths(object, k, suffix, paths),
+            }
+            suffix.pop();
+        }
+    }
+
+    fn describe(&self, o: usize) -> String {
+        let object = &self.heapdump.objects[o];
+        let array = match object.objarray_length {
+            Some(length) => format!(", array of {}", length),
+            None => String::new(),
+        };
+        format!(
+            "0x{:x} (klass 0x{:x}, {} bytes{})",
+            object.start, object.klass, object.size, array
+        )
+    }
+
+    /// Prints why `addr`, or the object containing it, is reachable.
+    fn explain(&self, addr: u64, k: usize) {
+        let Some((o, offset)) = self.containing(addr) else {
+            println!("0x{:x} is not in any object of the heapdump", addr);
+            return;
+        };
+        if offset != 0 {
+            println!("0x{:x} is at +0x{:x} in {}", addr, offset, self.describe(o));
+        }
+        let Some(distance) = self.distance[o] else {
+            println!("{} is not reachable from the roots", self.describe(o));
+            return;
+        };
+        let paths = self.paths(o, k);
+        println!(
+            "{} is {} edges from the roots, {} shortest path{}:",
+            self.describe(o),
+            distance,
+            paths.len(),
+            if paths.len() == 1 { "" } else { "s" }
+        );
+        for (i, path) in paths.iter().enumerate() {
+            println!("  Path {}:", i + 1);
+            for step in path {
+                match *step {
+                    Parent::Root(set) => {
+                        println!("    root of set {:?}", self.root_set_names[set])
+                    }
+                    Parent::Edge { object, edge } => {
+                        let source = &self.heapdump.objects[object];
+                        println!(
+                            "    {}\n      slot +0x{:x} ->",
+                            self.describe(object),
+                            source.edges[edge].slot - source.start
+                        );
+                    }
+                }
+            }
+            println!("    {}", self.describe(o));
+        }
+    }
+}
+
+pub fn why_reachable(args: &Args) -> Result<()> {
+    let Some(Commands::WhyReachable(query_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    if query_args.paths == 0 {
+        bail!("Asking for no paths");
+    }
+    let addresses = query_args
+        .addresses
+        .iter()
+        .map(|a| parse_address(a))
+        .collect::<Result<Vec<u64>>>()?;
+    for path in &args.paths {
+        let mut heapdump = HeapDump::from_path(path)?;
+        heapdump.select_roots(&args.roots)?;
+        let reachability = Reachability::new(&heapdump, query_args.paths);
+        println!("{}:", path);
+        for &addr in &addresses {
+            reachability.explain(addr, query_args.paths);
+        }
+        if !addresses.is_empty() {
+            continue;
+        }
+        // Without addresses, answer queries from stdin against the graph
+        // built once, as building it dominates for large heapdumps
+        for line in std::io::stdin().lock().lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_address(&line) {
+                Ok(addr) => reachability.explain(addr, query_args.paths),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::{NormalEdge, RootSet};
+
+    fn object(start: u64, edges: &[(u64, u64)]) -> HeapObject {
+        HeapObject {
+            start,
+            klass: 0x7e57_0001,
+            size: 32,
+            objarray_length: None,
+            instance_mirror_start: None,
+            instance_mirror_count: None,
+            edges: edges
+                .iter()
+                .map(|&(offset, objref)| NormalEdge {
+                    slot: start + offset,
+                    objref,
+                })
+                .collect(),
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn test_why_reachable() {
+        // a -> c and b -> c -> d, with a longer a -> e -> d and an
+        // unreachable f
+        let heapdump = HeapDump {
+            objects: vec![
+                object(0x1000, &[(16, 0x1040), (24, 0x1080)]),
+                object(0x1020, &[(16, 0x1040)]),
+                object(0x1040, &[(24, 0x1060)]),
+                object(0x1060, &[]),
+                object(0x1080, &[(16, 0x10a0)]),
+                object(0x10a0, &[(16, 0x1060)]),
+                object(0x10c0, &[]),
+            ],
+            roots: vec![RootEdge { objref: 0x1000 }],
+            root_sets: vec![RootSet {
+                name: "stack".to_string(),
+                roots: vec![RootEdge { objref: 0x1020 }],
+            }],
+            spaces: vec![],
+            ..Default::default()
+        };
+        let reachability = Reachability::new(&heapdump, 4);
+        assert_eq!(reachability.distance[3], Some(2));
+        assert_eq!(reachability.distance[5], Some(2));
+        assert_eq!(reachability.distance[6], None);
+        assert_eq!(reachability.containing(0x1068), Some((3, 8)));
+        assert_eq!(reachability.containing(0x10e0), None);
+        let paths = reachability.paths(3, 4);
+        assert_eq!(
+            paths,
+            vec![
+                vec![
+                    Parent::Root(0),
+                    Parent::Edge { object: 0, edge: 0 },
+                    Parent::Edge { object: 2, edge: 0 },
+                ],
+                vec![
+                    Parent::Root(1),
+                    Parent::Edge { object: 1, edge: 0 },
+                    Parent::Edge { object: 2, edge: 0 },
+                ],
+            ]
+        );
+        assert_eq!(reachability.paths(3, 1).len(), 1);
+        assert_eq!(parse_address("0x1068").unwrap(), 0x1068);
+        assert_eq!(parse_address("4200").unwrap(), 4200);
+        assert!(parse_address("0xg").is_err());
+    }
+}
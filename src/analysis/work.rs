@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use object_model::BidirectionalTib;
 
 use crate::BidirectionalObjectModel;
@@ -147,11 +145,20 @@ impl super::Analysis {
     fn get_pointers_in_stride(&self) -> usize {
         1usize << (self.owner_shift - self.log_pointer_size)
     }
+
+    /// The start of the stride `worker` owns in the superblock after that
+    /// of `p`, at the same place in it unless owners are hashed.
+    fn get_next_stride_start(&self, p: *mut u64, worker: usize) -> *mut u64 {
+        let next = p as usize + self.next_stride_delta;
+        let superblock = next & !(self.next_stride_delta - 1);
+        let stride = worker ^ self.owner_hash_of(next as u64);
+        (superblock | (stride << self.owner_shift)) as *mut u64
+    }
 }
 
 // Do work
 impl super::Analysis {
-    pub(super) fn do_work(&mut self, work: TaggedWork, object_sizes: &HashMap<u64, u64>) {
+    pub(super) fn do_work<O: ObjectModel>(&mut self, work: TaggedWork, object_model: &O) {
         // use usize::MAX to represent the environment so that the worker
         // knows that the work comes from an external message
         let creator = match work.creator {
@@ -164,7 +171,7 @@ impl super::Analysis {
         let inner_work = work.work;
         match inner_work {
             Work::MarkObject(o) => {
-                self.do_mark_object(o, object_sizes);
+                self.do_mark_object(o, object_model);
             }
             Work::LoadTIB(o) => self.do_load_tib(o),
             Work::ScanObject { tib_ptr, o } => self.do_scan_object(tib_ptr, o),
@@ -177,6 +184,7 @@ impl super::Analysis {
         if let Space::Los = HeapDump::get_space_type(o) {
             self.stats.los_object_size += object_size;
             self.stats.los_objects += 1;
+            let o = ObjectReference::from_raw_unchecked(o);
             let is_objarray = unsafe { BidirectionalObjectModel::<true>::is_objarray(o) };
             if is_objarray {
                 self.stats.los_objarrays += 1;
@@ -186,14 +194,14 @@ impl super::Analysis {
     }
 
     fn do_objarray_slot_stats(&mut self, o: u64) {
+        let o = ObjectReference::from_raw_unchecked(o);
         let is_objarray = unsafe { BidirectionalObjectModel::<true>::is_objarray(o) };
         if is_objarray {
             BidirectionalObjectModel::<true>::scan_object(o, |e, repeat| {
                 for i in 0..repeat {
-                    let edge = e.wrapping_add(i as usize);
                     self.stats.objarray_slots += 1;
-                    let child = unsafe { *edge };
-                    if child == 0 {
+                    let child = unsafe { e.shift::<u64>(i as usize).load_object() };
+                    if child.is_none() {
                         self.stats.objarray_empty_slots += 1;
                     }
                 }
@@ -201,9 +209,9 @@ impl super::Analysis {
         }
     }
 
-    fn do_mark_object(&mut self, o: u64, object_sizes: &HashMap<u64, u64>) {
-        debug_assert_ne!(o, 0);
-        let mut header = Header::load(o);
+    fn do_mark_object<O: ObjectModel>(&mut self, o: u64, object_model: &O) {
+        let obj = ObjectReference::from_raw_unchecked(o);
+        let mut header = Header::load(obj);
         let mark_byte = header.get_mark_byte();
         if mark_byte == 1 {
             return;
@@ -212,12 +220,15 @@ impl super::Analysis {
         let status_byte = header.get_byte(BidirectionalTib::STATUS_BYTE_OFFSET);
         let num_refs = header.get_byte(BidirectionalTib::NUMREFS_BYTE_OFFSET);
         self.stats.marked_objects += 1;
-        let object_size = object_sizes.get(&o).unwrap();
+        let object_size = object_model.object_size(obj).unwrap();
         self.stats.total_object_size += object_size;
         // mark the object
         header.set_mark_byte(1);
-        header.store(o);
+        header.store(obj);
         let object_owner = self.get_owner_thread(o);
+        let linear_owner = self.get_linear_owner_thread(o);
+        self.stats.owned_objects[object_owner] += 1;
+        self.stats.linear_owned_objects[linear_owner] += 1;
         match status_byte {
             0 => {}
             1 => {
@@ -242,7 +253,7 @@ impl super::Analysis {
         };
         // We might not be able to access the entire object, but we can cheat
         // for the purpose of collecting stats
-        self.do_los_object_stats(o, *object_size);
+        self.do_los_object_stats(o, object_size);
         self.do_objarray_slot_stats(o);
     }
 
@@ -322,8 +333,9 @@ impl super::Analysis {
         // Suppose we start with 01000 and end with 11000 (count = 3)
         // We clear lower bits, so we have 0
         let stride_start = (start as usize) >> (self.owner_shift + self.log_num_threads);
-        // We set the thread id, so 00;
-        let stride_start = (stride_start << self.log_num_threads) | worker;
+        // We set the thread id, so 00, permuted by the owner hash if any;
+        let stride_start =
+            (stride_start << self.log_num_threads) | (worker ^ self.owner_hash_of(start as u64));
         // Then we get the start of the first stride, so 00000
         let mut stride_start = (stride_start << self.owner_shift) as *mut u64;
         let pointers_in_stride = self.get_pointers_in_stride();
@@ -347,7 +359,7 @@ impl super::Analysis {
                 edge = edge.wrapping_add(1);
             }
             // Go to the next stride of the same thread
-            stride_start = (stride_start as usize + self.next_stride_delta) as *mut u64;
+            stride_start = self.get_next_stride_start(stride_start, worker);
             stride_end = stride_start.wrapping_add(pointers_in_stride);
         }
     }
@@ -402,18 +414,20 @@ impl super::Analysis {
             let leftover_strides = leftover.div_ceil(ptr_in_stide);
             // dbg!(leftover_strides);
             debug_assert!(leftover_strides >= 1);
-            for i in edge_owner + 1
-                ..std::cmp::min(
-                    edge_owner + self.num_threads,
-                    edge_owner + leftover_strides as usize + 1,
-                )
-            {
-                // if repeat > 16 {
-                //     dbg!(i % self.num_threads);
-                // }
-                let worker = i % self.num_threads;
-                // println!("{}->{} {:?}*{}", object_owner, edge_owner, edge, repeat);
-                self.create_edges_work(sender, worker, start, count);
+            // The owners of the following strides, each once. Without an
+            // owner hash, they are the threads after the edge owner in turn
+            let mut workers = vec![edge_owner];
+            let mut stride = self.get_stride_start(start);
+            for _ in 0..leftover_strides {
+                if workers.len() == self.num_threads {
+                    break;
+                }
+                stride = stride.wrapping_add(ptr_in_stide as usize);
+                let worker = self.get_owner_thread(stride as u64);
+                if !workers.contains(&worker) {
+                    workers.push(worker);
+                    self.create_edges_work(sender, worker, start, count);
+                }
             }
         }
     }
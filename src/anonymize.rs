@@ -0,0 +1,240 @@
+use crate::heapdump::generated_src::{NormalEdge, RootSet, Space};
+use crate::*;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+/// The object models and the simulator tell spaces apart by these address
+/// bits, so rebasing keeps them.
+const SPACE_TYPE_SHIFT: u32 = 41;
+const SPACE_TYPE_MASK: u64 = 0x7 << SPACE_TYPE_SHIFT;
+/// Spaces are moved by multiples of this, so that page offsets for every
+/// supported page size, and the address bits that decide which rank owns an
+/// object, stay the same.
+const SPACE_ALIGNMENT: u64 = 1 << 30;
+
+fn fnv1a(words: impl IntoIterator<Item = u64>) -> u64 {
+    util::fnv1a(words.into_iter().flat_map(u64::to_le_bytes))
+}
+
+/// An opaque klass id derived from the layout of an object of the klass.
+///
+/// Klasses with the same layout get the same id, also across dumps, which is
+/// what the TIB caches of the object models, shared by all the dumps of a
+/// run, rely on.
+fn shape_id(object: &HeapObject) -> u64 {
+    const OBJARRAY: u64 = 1;
+    const INSTANCE_MIRROR: u64 = 2;
+    const ORDINARY: u64 = 3;
+    let hash = if object.objarray_length.is_some() {
+        fnv1a([OBJARRAY])
+    } else if object.instance_mirror_start.is_some() {
+        // Every mirror has its own layout, which the object models do not
+        // cache by klass
+        fnv1a([INSTANCE_MIRROR])
+    } else {
+        let offsets = object.edges.iter().map(|e| e.slot - object.start);
+        fnv1a([ORDINARY, object.size].into_iter().chain(offsets))
+    };
+    // Word aligned and below the canonical 47-bit user address limit, like a
+    // real klass pointer
+    (hash & ((1 << 47) - 1) & !0x7).max(0x8)
+}
+
+/// Maps every address of the original dump to the rebased one.
+struct Rebase {
+    /// Original start, original end and new start of each space
+    spaces: Vec<(u64, u64, u64)>,
+}
+
+impl Rebase {
+    fn new(spaces: &[Space]) -> Self {
+        let mut sorted: Vec<&Space> = spaces.iter().collect();
+        sorted.sort_by_key(|s| s.start);
+        let mut next_start: HashMap<u64, u64> = HashMap::new();
+        let spaces = sorted
+            .into_iter()
+            .map(|s| {
+                let space_type = s.start & SPACE_TYPE_MASK;
+                let cursor = next_start.entry(space_type).or_insert(space_type);
+                let new_start = *cursor + s.start % SPACE_ALIGNMENT;
+                *cursor = (new_start + (s.end - s.start)).next_multiple_of(SPACE_ALIGNMENT);
+                (s.start, s.end, new_start)
+            })
+            .collect();
+        Rebase { spaces }
+    }
+
+    fn address(&self, addr: u64) -> Result<u64> {
+        if addr == 0 {
+            return Ok(0);
+        }
+        self.spaces
+            .iter()
+            .find(|&&(start, end, _)| (start..end).contains(&addr))
+            .map(|&(start, _, new_start)| addr - start + new_start)
+            .ok_or_else(|| anyhow!("Address 0x{:x} is outside every space", addr))
+    }
+}
+
+/// A copy of `heapdump` with generic space and root set names, spaces rebased to the
+/// start of the address range of their type, and klass ids that only tell
+/// object layouts apart.
+fn anonymized(heapdump: &HeapDump) -> Result<HeapDump> {
+    let rebase = Rebase::new(&heapdump.spaces);
+    let mut klasses: HashMap<u64, u64> = HashMap::new();
+    let objects = heapdump
+        .objects
+        .iter()
+        .map(|o| {
+            let klass = *klasses.entry(o.klass).or_insert_with(|| shape_id(o));
+            let edges = o
+                .edges
+                .iter()
+                .map(|e| {
+                    Ok(NormalEdge {
+                        slot: rebase.address(e.slot)?,
+                        objref: rebase.address(e.objref)?,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            Ok(HeapObject {
+                start: rebase.address(o.start)?,
+                klass,
+                size: o.size,
+                objarray_length: o.objarray_length,
+                instance_mirror_start: o
+                    .instance_mirror_start
+                    .map(|a| rebase.address(a))
+                    .transpose()?,
+                instance_mirror_count: o.instance_mirror_count,
+                edges,
+                pinned: o.pinned,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let rebase_roots = |roots: &[RootEdge]| {
+        roots
+            .iter()
+            .map(|r| {
+                Ok(RootEdge {
+                    objref: rebase.address(r.objref)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    let roots = rebase_roots(&heapdump.roots)?;
+    // Set names may name threads of the workload
+    let root_sets = heapdump
+        .root_sets
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            Ok(RootSet {
+                name: format!("set{}", i),
+                roots: rebase_roots(&s.roots)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let spaces = rebase
+        .spaces
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, end, new_start))| Space {
+            name: format!("space{}", i),
+            start: new_start,
+            end: new_start + (end - start),
+        })
+        .collect();
+    Ok(HeapDump {
+        objects,
+        roots,
+        spaces,
+        root_sets,
+        ..Default::default()
+    })
+}
+
+pub fn anonymize(args: &Args) -> Result<()> {
+    let Some(Commands::Anonymize(anonymize_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    assert_eq!(
+        args.paths.len(),
+        1,
+        "Can only anonymize one heap dump at a time"
+    );
+    let heapdump = HeapDump::from_path(&args.paths[0])?;
+    let anonymized = anonymized(&heapdump)?;
+    anonymized.to_binpb_zst(&anonymize_args.output_path)?;
+    info!(
+        "Wrote {} objects with {} distinct klass ids to {}",
+        anonymized.objects.len(),
+        anonymized
+            .objects
+            .iter()
+            .map(|o| o.klass)
+            .collect::<std::collections::HashSet<_>>()
+            .len(),
+        anonymize_args.output_path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize_preserves_graph() {
+        let mut heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        // A second space of the same type, far away
+        let far = 0x20000000000 + 0x1_2345_6000;
+        heapdump.spaces.push(Space {
+            name: "secret".into(),
+            start: far,
+            end: far + 0x1000,
+        });
+        heapdump.objects.push(HeapObject {
+            start: far + 0x10,
+            klass: 0xdead_beef,
+            size: 24,
+            objarray_length: None,
+            instance_mirror_start: None,
+            instance_mirror_count: None,
+            edges: vec![NormalEdge {
+                slot: far + 0x20,
+                objref: heapdump.objects[0].start,
+            }],
+            pinned: true,
+        });
+        heapdump.roots.push(RootEdge { objref: far + 0x10 });
+        let anonymized = anonymized(&heapdump).unwrap();
+
+        assert!(anonymized
+            .spaces
+            .iter()
+            .all(|s| s.name.starts_with("space")));
+        assert_eq!(
+            anonymized.spaces[1].start,
+            0x20000000000 + SPACE_ALIGNMENT + far % SPACE_ALIGNMENT
+        );
+        let rebase = Rebase::new(&heapdump.spaces);
+        for (o, a) in heapdump.objects.iter().zip(&anonymized.objects) {
+            assert_eq!(rebase.address(o.start).unwrap(), a.start);
+            assert_eq!(o.start % SPACE_ALIGNMENT, a.start % SPACE_ALIGNMENT);
+            assert_eq!(o.edges.len(), a.edges.len());
+            for (e, f) in o.edges.iter().zip(&a.edges) {
+                assert_eq!(e.slot - o.start, f.slot - a.start);
+                assert_eq!(rebase.address(e.objref).unwrap(), f.objref);
+            }
+        }
+        // Same klass, same id, and the objarray keeps apart from the leaves
+        let klass_of = |i: usize| anonymized.objects[i].klass;
+        assert_eq!(klass_of(1), klass_of(2));
+        assert_ne!(klass_of(0), klass_of(1));
+        assert_ne!(klass_of(65), 0xdead_beef);
+        assert!(anonymized.objects[65].pinned);
+        // Ids do not depend on the dump
+        assert_eq!(anonymized, self::anonymized(&anonymized).unwrap());
+    }
+}
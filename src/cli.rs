@@ -1,4 +1,6 @@
-use crate::simulate::PageSize;
+use crate::simulate::{
+    CacheGeometry, PageSize, ProcessorTimingModel, RefreshTiming, TlbGeometry, TopologyFile,
+};
 use crate::*;
 use clap::{Parser, Subcommand, ValueEnum};
 #[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
@@ -19,24 +21,191 @@ pub struct Args {
     #[arg(short, long, value_enum)]
     pub object_model: ObjectModelChoice,
 
+    /// Only trace from the roots of these root sets of each heapdump ("default" for the ungrouped roots).
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub roots: Vec<String>,
+
+    /// Also write the version, command line, heapdump checksums and every
+    /// tabulated statistic of the run to this JSON file ("-" for stdout).
+    #[arg(long, global = true)]
+    pub results: Option<String>,
+
+    /// Report the progress of restoring, sanity tracing and simulating on
+    /// stderr every this many seconds.
+    #[arg(long, global = true)]
+    pub progress: Option<u64>,
+
+    /// Move the spaces of each heapdump by random page-aligned offsets drawn from this seed when restoring it.
+    #[arg(long, global = true)]
+    pub aslr_seed: Option<u64>,
+
+    /// Keep the objects of each heapdump in a side bitmap and size table
+    /// instead of an address list and a size map, to save memory on large
+    /// heaps.
+    #[arg(long, global = true)]
+    pub object_map: bool,
+
+    /// Load the field patterns alignment encoding gives codes to (OpenJDKAE) from this TOML file.
+    #[arg(long, global = true)]
+    pub ae_patterns: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
-#[derive(Parser, Debug, Clone, Copy)]
+#[derive(Parser, Debug, Clone)]
 pub struct TraceArgs {
     #[arg(short, long, value_enum)]
     pub(crate) tracing_loop: TracingLoopChoice,
     #[arg(short, long, default_value_t = 5)]
     pub(crate) iterations: usize,
-    #[arg(long, default_value_t = 16)]
+    #[arg(long, default_value_t = 16, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
     pub(crate) shape_cache_size: usize,
+    /// Ways of each set of the shape cache (0 makes it fully associative).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) shape_cache_ways: usize,
+    /// How a set-associative shape cache picks the set of a klass.
+    #[arg(long, value_enum, default_value_t = ShapeCacheIndexChoice::LowBits)]
+    pub(crate) shape_cache_index: ShapeCacheIndexChoice,
+    /// Entries of a fully associative victim cache behind the shape cache, holding the shapes it evicts (0 for none).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) shape_victim_cache: usize,
+    /// Entries of a second-level shape cache, looked up on a miss in the shape cache and its victim cache (0 for none).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) shape_l2_cache: usize,
+    /// Write the shapes in the shape cache after the last heapdump, with how often each was looked up, to this JSON file.
+    #[arg(long)]
+    pub(crate) shape_cache_export: Option<String>,
+    /// Warm up the shape cache with the shapes of a file written by `--shape-cache-export`.
+    #[arg(long)]
+    pub(crate) shape_cache_import: Option<String>,
     /// Number of worker threads to use, if the tracing loop supports parallelism.
     #[arg(long, default_value_t = num_cpus::get())]
     pub(crate) threads: usize,
     /// Work Packet buffer capacity.
     #[arg(long, default_value_t = 4096)]
     pub(crate) wp_capacity: usize,
+    /// Report every iteration rather than only the last one of each heapdump.
+    #[arg(long, default_value_t = false)]
+    pub(crate) all_iterations: bool,
+    /// Write per-worker deque length, steal and packet counters of the
+    /// parallel tracing loops to this Perfetto trace (.json.gz).
+    #[arg(long)]
+    pub(crate) trace_events: Option<String>,
+    /// Interval between two samples of the worker counters, in microseconds.
+    #[arg(long, default_value_t = 100)]
+    pub(crate) sample_interval_us: u64,
+    /// Order in which the workers of WPEdgeSlot and WPEdgeSlotDual run their
+    /// own packets.
+    #[arg(long, value_enum, default_value_t = QueueDisciplineChoice::Lifo)]
+    pub(crate) queue_discipline: QueueDisciplineChoice,
+    /// Packets a worker keeps in its local queue under the Hierarchical
+    /// discipline, before pushing new ones to the global queue.
+    #[arg(long, default_value_t = 16)]
+    pub(crate) local_queue_limit: usize,
+    /// Packets the queues of WPEdgeSlot and WPEdgeSlotDual may hold before
+    /// workers run new packets themselves instead of queueing them (0 for
+    /// no limit).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) packet_budget: usize,
+    /// Microseconds a packet of WPEdgeSlot and WPEdgeSlotDual may wait at
+    /// the bottom of a local queue before it is promoted to the global queue
+    /// for any worker to take (0 for never).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) packet_aging: u64,
+    /// Objects DistributedNodeObjref sends to another thread in one message,
+    /// like `--coalesce-loads` of the NMPGC simulation (1 sends each on its
+    /// own).
+    #[arg(long, default_value_t = 1)]
+    pub(crate) send_batch: usize,
+    /// Order in which the packets of WPEdgeSlot and WPEdgeSlotDual load
+    /// their slots.
+    #[arg(long, value_enum, default_value_t = SlotOrderChoice::Unsorted)]
+    pub(crate) slot_order: SlotOrderChoice,
+    /// Entries of a direct-mapped filter of recently seen referents each
+    /// worker checks before a mark attempt, skipping the slots of objects
+    /// in it (EdgeSlot, WPEdgeSlot and WPEdgeSlotDual).
+    #[arg(long)]
+    pub(crate) referent_filter: Option<usize>,
+    /// Time every packet of the work-packet tracing loops with the TSC, and
+    /// report log-scaled latency histograms of each kind of packet.
+    #[arg(long, default_value_t = false)]
+    pub(crate) packet_latency: bool,
+    /// Time one in this many object scans of every thread with the TSC, and
+    /// report the klasses taking the most scan time and marking the most
+    /// objects.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) klass_profile: Option<u64>,
+    /// Klasses to report with `--klass-profile`.
+    #[arg(long, default_value_t = 10, requires = "klass_profile")]
+    pub(crate) klass_profile_top: usize,
+    /// Write which thread marked each object in the last iteration to this
+    /// Parquet file, with its owner under DistributedNodeObjref (parallel
+    /// tracing loops only).
+    #[arg(long)]
+    pub(crate) marker_map: Option<String>,
+    /// Between iterations, rewrite this fraction of the non-empty slots of
+    /// live objects to point to other live objects, and report the work of
+    /// retracing from the slots written.
+    #[arg(long)]
+    pub(crate) mutate: Option<f64>,
+    /// Seed of the slots and targets `--mutate` picks.
+    #[arg(long, default_value_t = 0, requires = "mutate")]
+    pub(crate) mutate_seed: u64,
+    /// Slide the live objects of each space after the last iteration, and
+    /// report the bytes moved and slots rewritten (Bidirectional only).
+    #[arg(long, default_value_t = false)]
+    pub(crate) compact: bool,
+    /// Pin every object of these spaces, in addition to the objects pinned
+    /// in the heapdump, so that compaction leaves them in place.
+    #[arg(long, value_delimiter = ',', requires = "compact")]
+    pub(crate) pinned_spaces: Vec<String>,
+    /// Replay the lines each object moved by `--compact` is read from and
+    /// written to through the DDR4 model, and report the read and write
+    /// traffic of each space and the bandwidth the copy needs.
+    #[arg(long, default_value_t = false, requires = "compact")]
+    pub(crate) compact_traffic: bool,
+    /// Report the bytes of heap each closure touches and the bandwidth it
+    /// achieves, against the peak memory bandwidth of the host.
+    #[arg(long, default_value_t = false)]
+    pub(crate) roofline: bool,
+    /// Peak memory bandwidth in GB/s for `--roofline`, measured with a
+    /// streaming read over all cores if not given.
+    #[arg(long, requires = "roofline")]
+    pub(crate) peak_bandwidth: Option<f64>,
+    /// Report the DRAM row hit rate, rows and bank balance of the cache
+    /// lines the closure touches, through the address mapping of the
+    /// simulator.
+    #[arg(long, default_value_t = false)]
+    pub(crate) row_buffer: bool,
+    /// Count these events with perf_event around every iteration, such as
+    /// cycles,instructions,LLC-misses,dTLB-misses.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) perf_counters: Vec<String>,
+    /// Run the sanity trace of the heapdump in release builds too, and
+    /// report its reachable objects, back edges, depth and roots.
+    #[arg(long, default_value_t = false)]
+    pub(crate) sanity: bool,
+    /// How to check the heap after the closures, exiting with an error if
+    /// the check fails.
+    #[arg(long, value_enum, default_value_t = VerifyChoice::Fast)]
+    pub(crate) verify: VerifyChoice,
+    #[command(flatten)]
+    pub(crate) root_stacks: RootStackArgs,
+}
+
+/// Synthesized thread stacks the roots are scanned from, see `root_scan`
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct RootStackArgs {
+    /// Deal the roots out to this many thread stacks and charge for scanning their frames.
+    #[arg(long)]
+    pub(crate) root_stacks: Option<usize>,
+    /// Roots in each frame of the root stacks.
+    #[arg(long, default_value_t = 4, requires = "root_stacks")]
+    pub(crate) frame_roots: usize,
+    /// Words in each frame of the root stacks.
+    #[arg(long, default_value_t = 32, requires = "root_stacks")]
+    pub(crate) frame_words: usize,
 }
 
 #[derive(Parser, Debug, Clone, Copy)]
@@ -49,6 +218,9 @@ pub struct AnalysisArgs {
     pub(crate) rle: bool,
     #[arg(short, long, default_value_t = false)]
     pub(crate) eager_load: bool,
+    /// Assign the strides to workers by XORing a hash of the address above them, seeded with this, into the owner, instead of going round the workers in address order.
+    #[arg(long)]
+    pub(crate) owner_hash: Option<u64>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -63,6 +235,9 @@ pub struct PaperAnalysisArgs {
     pub(crate) analysis_name: PaperAnalysisChoice,
     #[arg(short, long)]
     pub(crate) output_path: String,
+    /// Also write every distinct shape and size of ShapeDemographic, most common first, with the fraction of objects and slots the shapes up to it cover, to this CSV.
+    #[arg(long)]
+    pub(crate) shape_inventory: Option<String>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
@@ -71,6 +246,9 @@ pub enum PaperAnalysisChoice {
     ShapeDemographic,
     EdgeChunks,
     Degrees,
+    /// Reuse distances of the cache lines an EdgeSlot closure touches, and
+    /// the miss rate of every power-of-two cache size
+    ReuseDistance,
 }
 
 /// Simulation args
@@ -82,14 +260,237 @@ pub struct SimulationArgs {
     pub(crate) architecture: SimulationArchitectureChoice,
     #[arg(short, long)]
     pub(crate) trace_path: Option<String>,
+    /// Write the busy and idle periods and queue depth samples of the processors to this compact binary log as the simulation goes, instead of keeping them in memory for `-t`. `convert-trace` turns it into a Perfetto trace.
+    #[arg(long, conflicts_with = "trace_path")]
+    pub(crate) event_log: Option<String>,
     #[arg(long, default_value_t = false)]
     pub(crate) use_dramsim3: bool,
     #[arg(long, default_value = "configs/DDR4_8Gb_x8_3200.ini")]
     pub(crate) dramsim3_config: String,
+    /// Refresh the ranks of the naive DRAM model, stalling the accesses that arrive during a refresh (DRAMsim3 refreshes as its config says).
+    #[arg(long, conflicts_with = "use_dramsim3")]
+    pub(crate) refresh: bool,
+    /// DRAM cycles between two refreshes of a rank (tREFI).
+    #[arg(long, default_value_t = RefreshTiming::default().interval, requires = "refresh")]
+    pub(crate) refresh_interval: usize,
+    /// DRAM cycles a refresh takes (tRFC).
+    #[arg(long, default_value_t = RefreshTiming::default().latency, requires = "refresh")]
+    pub(crate) refresh_latency: usize,
+    /// Share of the DRAM bandwidth co-running mutators take from the closure, from 0 up to 1, served in a window at the end of every `--mutator-slice` during which the closure cannot access the rank.
+    #[arg(long, conflicts_with = "use_dramsim3")]
+    pub(crate) mutator_share: Option<f64>,
+    /// DRAM cycles of a time slice shared by the closure and the mutators.
+    #[arg(long, default_value_t = 1600, requires = "mutator_share")]
+    pub(crate) mutator_slice: usize,
+    /// When the controller of the naive DRAM model closes the open row of a bank.
+    #[arg(long, value_enum, default_value_t = PagePolicyChoice::Open, conflicts_with = "use_dramsim3")]
+    pub(crate) page_policy: PagePolicyChoice,
+    /// Idle DRAM cycles after which `--page-policy Timeout` closes a row.
+    #[arg(long, default_value_t = 200)]
+    pub(crate) page_timeout: usize,
+    /// Which bits of a physical address select its channel, DIMM, rank, bank, row and column, and so which processor owns it.
+    #[arg(long, value_enum, default_value_t = AddressMappingChoice::Baseline, conflicts_with = "use_dramsim3")]
+    pub(crate) address_mapping: AddressMappingChoice,
+    /// XOR a hash of the upper bits of a physical address, seeded with this, into its channel, DIMM and rank, as controllers hash them, so that owners no longer follow the address order.
+    #[arg(long, conflicts_with = "use_dramsim3")]
+    pub(crate) owner_hash: Option<u64>,
     #[arg(long, value_enum, default_value_t = TopologyChoice::Line)]
     pub(crate) topology: TopologyChoice,
+    /// Costs of the four links of `--topology Ring`, in ring order from DIMM 0 (0-2, 2-1, 1-3 and 3-0). Messages take the lighter way around, then the one with fewer hops. All 1 by default.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) link_weights: Vec<u64>,
+    /// TOML file with the DIMMs and the links between them, each with its own latency and width if given, instead of a built-in `--topology`; see `configs/topology_chord.toml`. Messages take the route a one-word message arrives first on, then the one with fewer hops.
+    #[arg(long, value_parser = TopologyFile::from_file, conflicts_with_all = ["topology", "link_weights"])]
+    pub(crate) topology_file: Option<Box<TopologyFile>>,
     #[arg(long, value_enum, default_value_t = PageSize::TwoMB)]
     pub(crate) page_size: PageSize,
+    /// TOML file overriding the NMPGC processor timing model, see `ProcessorTimingModel`.
+    #[arg(long, value_parser = ProcessorTimingModel::from_file)]
+    pub(crate) timing_model: Option<Box<ProcessorTimingModel>>,
+    /// Also report the scan-cost-weighted critical path of the heap as a speedup bound.
+    #[arg(long, default_value_t = false)]
+    pub(crate) critical_path: bool,
+    /// Expand NMPGC work counts into estimated RISC-V instruction counts and report CPI.
+    #[arg(long, default_value_t = false)]
+    pub(crate) isa_accounting: bool,
+    /// Number of MSHRs per NMPGC processor for overlapping Load misses (0 means a blocking cache).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) mshrs: usize,
+    /// Wire and router delay, in ticks, of each NMPGC network hop on top of serialization.
+    #[arg(long, default_value_t = 0)]
+    pub(crate) link_latency: usize,
+    /// Bytes an NMPGC network link moves per tick (the flit size).
+    #[arg(long, default_value_t = 2, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) link_width: usize,
+    /// Messages each directed NMPGC network link can buffer (0 means unbounded); senders stall on a full link.
+    #[arg(long, default_value_t = 0)]
+    pub(crate) link_buffer: usize,
+    /// Sample each NMPGC processor's work queue and inbox depth every this many ticks into the trace (0 disables).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) queue_sample_interval: usize,
+    /// Detect NMPGC termination with probe waves and broadcast it, instead of using global knowledge.
+    #[arg(long, default_value_t = false)]
+    pub(crate) distributed_termination: bool,
+    /// Maximum number of messages an NMPGC processor handles per inbox read.
+    #[arg(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) inbox_batch: usize,
+    /// Inbox length at which an NMPGC processor reads its inbox ahead of its queued work (0 reads it only when out of work).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) inbox_priority: usize,
+    /// Maximum number of remote Load targets an NMPGC processor packs into one message (1 disables coalescing).
+    #[arg(long, default_value_t = 1)]
+    pub(crate) coalesce_loads: usize,
+    /// How many slots the NMPGC prefetch engine may run ahead of the scan (0 disables it).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) prefetch_depth: usize,
+    /// Number of MSHRs available to the NMPGC prefetch engine.
+    #[arg(long, default_value_t = 4)]
+    pub(crate) prefetch_mshrs: usize,
+    /// Geometry of each NMPGC processor cache as `<sets>x<ways>x<line size>`, or `none`.
+    #[arg(long, default_value_t = CacheGeometry::DEFAULT)]
+    pub(crate) cache: CacheGeometry,
+    /// Geometry of each NMPGC processor TLB as `<entries>x<ways>`, or `none` for physically addressed processors (defaults to a TLB sized for `--page-size`).
+    #[arg(long)]
+    pub(crate) tlb: Option<TlbGeometry>,
+    /// Ticks an NMPGC TLB miss takes, instead of those of a page table walk for `--page-size`.
+    #[arg(long)]
+    pub(crate) tlb_miss_penalty: Option<usize>,
+    /// Geometry of a last-level cache shared by the NMPGC processors of each DIMM, as `<sets>x<ways>x<line size>`.
+    #[arg(long)]
+    pub(crate) llc: Option<CacheGeometry>,
+    /// Let the NMPGC processors of a DIMM mark and load each other's memory through the coherent last-level cache instead of sending messages.
+    #[arg(long, default_value_t = false, requires = "llc")]
+    pub(crate) dimm_sharing: bool,
+    /// Number of lines in the NMPGC mark buffer that combines mark-byte writes before they go to DRAM (0 writes through).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) mark_buffer: usize,
+    /// Also mark every line of this many bytes an object covers in a side table of one byte per line, as Immix does (256 for Immix lines).
+    #[arg(long, value_parser = crate::util::parse_power_of_two)]
+    pub(crate) line_marks: Option<u64>,
+    /// Also mark every block of this many bytes an object covers in a side table of one byte per block (32768 for Immix blocks).
+    #[arg(long, value_parser = crate::util::parse_power_of_two)]
+    pub(crate) block_marks: Option<u64>,
+    /// Make NMPGC processors mark objects in a private bitmap each instead of their headers, scanning an object once per processor reaching it, and OR the bitmaps together at the end.
+    #[arg(long, default_value_t = false, conflicts_with = "tenant")]
+    pub(crate) private_marks: bool,
+    /// Make NMPGC processors read the TIB, and its oop maps, of every object whose header or TIB pointer does not say where its slots are before scanning it.
+    #[arg(long, default_value_t = false)]
+    pub(crate) tib_fetch: bool,
+    /// Geometry of a metadata cache of each NMPGC processor for `--tib-fetch`, as `<sets>x<ways>x<line size>`; TIBs go through the data cache if not given.
+    #[arg(long, requires = "tib_fetch")]
+    pub(crate) tib_cache: Option<CacheGeometry>,
+    /// Put an NMPGC processor to sleep after this many idle ticks in a row, waking it up when work or a message arrives.
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) sleep_after: Option<usize>,
+    /// Ticks a sleeping NMPGC processor takes to wake up before it can do anything.
+    #[arg(long, default_value_t = 100, requires = "sleep_after")]
+    pub(crate) wake_latency: usize,
+    /// Power of an idle NMPGC processor, as a fraction of its active power, for the energy estimate of `--sleep-after`.
+    #[arg(long, default_value_t = 0.5, requires = "sleep_after")]
+    pub(crate) idle_power: f64,
+    /// Power of a sleeping NMPGC processor, as a fraction of its active power.
+    #[arg(long, default_value_t = 0.05, requires = "sleep_after")]
+    pub(crate) sleep_power: f64,
+    /// Stream the slots of object arrays with at least this many slots through a DMA-like scan engine next to each NMPGC rank, instead of scanning them slot by slot.
+    #[arg(long)]
+    pub(crate) scan_engine: Option<usize>,
+    /// Bytes of slots a scan engine reads per tick; 16 is the peak of a DDR4-3200 channel at 1.6 GHz.
+    #[arg(long, default_value_t = 16, requires = "scan_engine", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) scan_engine_bandwidth: usize,
+    /// Entries of the work queue of each NMPGC processor, each taken by a mark, a load or a message carrying one; work that finds them all taken overflows under `--overflow-policy`.
+    #[arg(long, conflicts_with = "tenant")]
+    pub(crate) mark_queue: Option<usize>,
+    /// What an NMPGC processor does with work that finds its `--mark-queue` full.
+    #[arg(long, value_enum, default_value_t = OverflowPolicyChoice::Spill, requires = "mark_queue")]
+    pub(crate) overflow_policy: OverflowPolicyChoice,
+    #[command(flatten)]
+    pub(crate) root_stacks: RootStackArgs,
+    /// Save the state of the NMPGC simulation to this file every `--checkpoint-interval` ticks and when stopped with ctrl-C.
+    #[arg(long, conflicts_with = "use_dramsim3")]
+    pub(crate) checkpoint: Option<String>,
+    /// Ticks between two checkpoints (0 only saves one when stopped with ctrl-C).
+    #[arg(long, default_value_t = 100_000_000)]
+    pub(crate) checkpoint_interval: usize,
+    /// Continue the NMPGC simulation saved in this checkpoint, with the same heapdump and arguments.
+    #[arg(long, conflicts_with = "use_dramsim3")]
+    pub(crate) resume: Option<String>,
+    /// Tick the processors of each NMPGC DIMM in a thread of their own, putting the messages between DIMMs through the network every this many ticks (0 ticks every processor in the main thread; 1 gives the same results as 0).
+    #[arg(long, default_value_t = 0)]
+    pub(crate) shard_quantum: usize,
+    /// Only simulate NMPGC in detail for windows of this many ticks, fast-forwarding `--fast-forward` steps between them, and extrapolate the total ticks.
+    #[arg(long, requires = "fast_forward", conflicts_with_all = ["shard_quantum", "checkpoint", "resume"])]
+    pub(crate) sample_window: Option<usize>,
+    /// Simulate each heapdump with this many layouts, from `--aslr-seed` on, and report the variance of the ticks between them.
+    #[arg(long, default_value_t = 1, conflicts_with_all = ["checkpoint", "resume"])]
+    pub(crate) aslr_runs: usize,
+    /// What scanning an object costs the IdealTraceUtilization processors.
+    #[arg(long, value_enum, default_value_t = IdealCostChoice::Unit)]
+    pub(crate) ideal_cost: IdealCostChoice,
+    /// Steps between two windows of `--sample-window` in which every processor does one item of work without taking time.
+    #[arg(long, requires = "sample_window")]
+    pub(crate) fast_forward: Option<usize>,
+    /// Skip checking that each simulation marked the same objects in each space as an EdgeSlot closure on the host.
+    #[arg(long)]
+    pub(crate) no_cross_check: bool,
+    /// Write which processor marked each object, and which one owns it, to
+    /// this Parquet file (NMPGC only).
+    #[arg(long)]
+    pub(crate) marker_map: Option<String>,
+    /// Stop a simulation with an error once it reaches this many ticks.
+    #[arg(long)]
+    pub(crate) max_ticks: Option<usize>,
+    /// Stop a simulation with an error once it goes this many ticks without marking an object while it has work or messages left, 0 to never.
+    #[arg(long, default_value_t = 10_000_000)]
+    pub(crate) stall_ticks: usize,
+    /// Trace this heapdump concurrently with each of the others on the same NMPGC processors and network, and report how much each tenant slows the other down.
+    #[arg(long, conflicts_with_all = ["sample_window", "checkpoint", "resume", "aslr_runs"])]
+    pub(crate) tenant: Option<String>,
+    /// Which tenant's work an NMPGC processor picks next with `--tenant`.
+    #[arg(long, value_enum, default_value_t = TenantArbitrationChoice::Shared, requires = "tenant")]
+    pub(crate) tenant_arbitration: TenantArbitrationChoice,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum QueueDisciplineChoice {
+    /// Newest packet first, depth-first tracing
+    Lifo,
+    /// Oldest packet first, breadth-first tracing
+    Fifo,
+    /// A bounded local LIFO queue that overflows to the global FIFO queue
+    Hierarchical,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum VerifyChoice {
+    /// No checks
+    Off,
+    /// Every object marked by the last closure, in a parallel pass
+    Fast,
+    /// Also that no slot of any object, nor the length of any object
+    /// array, changed during tracing
+    Full,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum SlotOrderChoice {
+    /// The order the slots were found in
+    Unsorted,
+    /// Sorted by address
+    Sorted,
+    /// Bucketed by 4 KiB page, keeping the order within a page
+    Page,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum ShapeCacheIndexChoice {
+    /// The low bits of the klass pointer above its word alignment
+    LowBits,
+    /// A multiplicative hash of the klass pointer
+    Hashed,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
@@ -99,6 +500,70 @@ pub enum SimulationArchitectureChoice {
     NMPGC,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum TenantArbitrationChoice {
+    /// One queue for both tenants, in the order the work arrives
+    Shared,
+    /// A queue per tenant, taking from each in turn
+    RoundRobin,
+    /// A queue per tenant, serving the `--tenant` heapdump only when the
+    /// other one has no work
+    Priority,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug, serde::Serialize, serde::Deserialize)]
+#[clap(rename_all = "verbatim")]
+pub enum OverflowPolicyChoice {
+    /// Write the work to memory, and read it back once the queue drains
+    Spill,
+    /// Forget the work, and rescan for it once the processor runs out
+    Drop,
+    /// Stall the scan of an object until the queue has room
+    Backpressure,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum IdealCostChoice {
+    /// A tick per object
+    Unit,
+    /// A tick for the header, one for the TIB if it has to be looked up,
+    /// and one per slot
+    Size,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum PagePolicyChoice {
+    /// Keep rows open until another row of the bank is needed
+    Open,
+    /// Close the row after every access
+    Closed,
+    /// Close rows left idle for `--page-timeout` cycles
+    Timeout,
+}
+
+/// Layouts of the fields of a physical address, from the block offset up.
+/// The order of the variants is that of the layouts in `simulate::memory`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum AddressMappingChoice {
+    /// Column, channel, bank, DIMM, rank, row: owners change every 8 KiB
+    Baseline,
+    /// Two column bits, channel, the other column bits, bank, DIMM, rank,
+    /// row: channels interleaved every 256 bytes, the default granularity
+    /// of Intel and AMD server controllers
+    Channel256,
+    /// Channel, column, bank, rank, DIMM, row: channels interleaved every
+    /// cache line, as controllers set up for bandwidth over row locality do
+    ChannelLine,
+    /// Column, channel, DIMM, rank, bank, row: the ranks of a channel
+    /// interleaved below the banks, which spreads a row's worth of
+    /// consecutive addresses over all ranks before reusing a bank
+    RankBank,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
 #[clap(rename_all = "verbatim")]
 pub enum TopologyChoice {
@@ -107,14 +572,230 @@ pub enum TopologyChoice {
     FullyConnected,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum ClosureSourceChoice {
+    /// NMPGC with 8 processors, timing root scanning with `--root-stacks`
+    Simulated,
+    /// A tracing loop timed on the host, with the root stacks scanned on
+    /// the host
+    Host,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum ReclaimChoice {
+    /// A pass over every object timed on the host, adding up the dead bytes
+    Sweep,
+    /// Sliding compaction timed on the host, with the Bidirectional object
+    /// models only
+    Compact,
+    /// Leave the dead objects where they are
+    None,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    Trace(TraceArgs),
+    Trace(Box<TraceArgs>),
     Analyze(AnalysisArgs),
     Depth(DepthArgs),
     PaperAnalyze(PaperAnalysisArgs),
-    Simulate(SimulationArgs),
+    Simulate(Box<SimulationArgs>),
     Export(ExportArgs),
+    /// Print object, space, size and outdegree statistics of the heapdumps without restoring them
+    Stats,
+    /// Write a copy of a heapdump with generic space and root set names, rebased spaces and opaque klass ids
+    Anonymize(AnonymizeArgs),
+    /// Check that the live objects of a heapdump fit below 4 GiB and move them there, optionally with 4-byte slots
+    Rebase32(Rebase32Args),
+    /// Trace the heapdumps with EdgeSlot and other tracing loops, and report where the loops mark or scan differently
+    Selftest(SelftestArgs),
+    /// Write the TIBs built for the object model as a flat binary table with a JSON manifest, for hardware tracers
+    ExportTibs(ExportTibsArgs),
+    /// Report the objects alignment encoding covers with the patterns in use and with the best patterns for the heapdumps
+    AeCoverage(AeCoverageArgs),
+    /// Print the shortest paths from the roots to objects, with the slot of each edge, without restoring the heapdumps
+    WhyReachable(WhyReachableArgs),
+    /// Print the dominator tree of the heapdumps with the bytes, objects and slots each object retains, without restoring them
+    Dominators(DominatorsArgs),
+    /// Group and color the objects of the heapdumps by the cache lines they share, for mark false sharing and line-granularity marking experiments, without restoring them
+    LineConflicts(LineConflictsArgs),
+    /// Check that object headers and slots do not straddle cache lines, and count the objects by the alignment of their start, without restoring the heapdumps
+    Alignment(AlignmentArgs),
+    /// Time the scan of one object of each shape of the heapdumps, such as those of `[synthetic]microbench`, with the object model
+    Microbench(MicrobenchArgs),
+    /// Estimate the GC pause of each heapdump from its root scanning, closure, reference processing and sweeping or compaction, with a breakdown of the phases
+    Estimate(EstimateArgs),
+    /// Convert the event logs of `simulate --event-log` to a Perfetto trace, optionally only a window of time or some of the processors
+    ConvertTrace(ConvertTraceArgs),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Debug)]
+#[clap(rename_all = "verbatim")]
+pub enum TraceFormatChoice {
+    /// Gzipped JSON trace events, as written by `simulate -t`
+    Json,
+    /// Perfetto protobuf trace packets, smaller and faster to load
+    Proto,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ConvertTraceArgs {
+    /// Write the trace to this file.
+    #[arg(short, long)]
+    pub(crate) output_path: String,
+    #[arg(long, value_enum, default_value_t = TraceFormatChoice::Json)]
+    pub(crate) format: TraceFormatChoice,
+    /// Only keep the events from this many microseconds into each simulation, clipping the spans.
+    #[arg(long)]
+    pub(crate) from_us: Option<f64>,
+    /// Only keep the events up to this many microseconds into each simulation, clipping the spans.
+    #[arg(long)]
+    pub(crate) until_us: Option<f64>,
+    /// Only keep the events of these processors, all by default.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) threads: Vec<u32>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct EstimateArgs {
+    /// Where the times of root scanning and the closure come from.
+    #[arg(long, value_enum, default_value_t = ClosureSourceChoice::Simulated)]
+    pub(crate) closure: ClosureSourceChoice,
+    /// Tracing loop of a closure on the host.
+    #[arg(short, long, value_enum, default_value_t = TracingLoopChoice::ParEdgeSlot)]
+    pub(crate) tracing_loop: TracingLoopChoice,
+    /// Number of worker threads of a closure on the host, if the tracing loop supports parallelism.
+    #[arg(long, default_value_t = num_cpus::get())]
+    pub(crate) threads: usize,
+    #[command(flatten)]
+    pub(crate) root_stacks: RootStackArgs,
+    /// Microseconds of reference processing to add to each pause. Heapdumps do not record which objects are references, so it is taken as given rather than modeled.
+    #[arg(long, default_value_t = 0.0)]
+    pub(crate) reference_us: f64,
+    /// How the dead objects are reclaimed after the closure.
+    #[arg(long, value_enum, default_value_t = ReclaimChoice::Sweep)]
+    pub(crate) reclaim: ReclaimChoice,
+    /// Write the time of every phase of every heapdump to this CSV.
+    #[arg(long)]
+    pub(crate) csv: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct MicrobenchArgs {
+    /// Scans of each object to time.
+    #[arg(long, default_value_t = 10000)]
+    pub(crate) repetitions: usize,
+    /// Write the time of every shape to this CSV.
+    #[arg(short, long)]
+    pub(crate) output_path: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct AlignmentArgs {
+    /// Bytes of a cache line, a power of two.
+    #[arg(long, default_value_t = 64)]
+    pub(crate) line_size: u64,
+    /// Bytes of an object header, the mark word and klass pointer of OpenJDK by default.
+    #[arg(long, default_value_t = 16)]
+    pub(crate) header_size: u64,
+    /// Bytes of a slot, 4 for heapdumps from `rebase32 --compress-slots`.
+    #[arg(long, default_value_t = 8)]
+    pub(crate) slot_size: u64,
+    /// Write every straddling or misaligned header and slot, with its object, to this CSV.
+    #[arg(short, long)]
+    pub(crate) output_path: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct LineConflictsArgs {
+    /// Bytes of a cache line, a power of two.
+    #[arg(long, default_value_t = 64)]
+    pub(crate) line_size: u64,
+    /// Only count the header word of each object, which holds its mark byte, instead of the whole object.
+    #[arg(long, default_value_t = false)]
+    pub(crate) header_only: bool,
+    /// Write every object with its lines, group, color and number of conflicts to this CSV.
+    #[arg(short, long)]
+    pub(crate) output_path: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct DominatorsArgs {
+    /// Levels of the dominator tree to print.
+    #[arg(long, default_value_t = 4)]
+    pub(crate) depth: usize,
+    /// Children to print for each object, most retained first.
+    #[arg(long, default_value_t = 10)]
+    pub(crate) top: usize,
+    /// Leave out objects retaining less than this percentage of the live bytes.
+    #[arg(long, default_value_t = 1.0)]
+    pub(crate) min_percent: f64,
+    /// Write every live object with its retained size and immediate dominator to this CSV.
+    #[arg(short, long)]
+    pub(crate) output_path: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct WhyReachableArgs {
+    /// Addresses of objects or inside them, in hex with 0x or in decimal. Read one per line from stdin if none are given.
+    pub(crate) addresses: Vec<String>,
+    /// Print up to this many of the shortest paths to each object.
+    #[arg(short = 'k', long, default_value_t = 1)]
+    pub(crate) paths: usize,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct AeCoverageArgs {
+    /// Number of field patterns of the best table, up to the 6 codes alignment encoding has for them.
+    #[arg(short = 'k', long, default_value_t = 6)]
+    pub(crate) patterns: usize,
+    /// Write the best patterns to this TOML file, for `--ae-patterns`.
+    #[arg(short, long)]
+    pub(crate) output_path: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ExportTibsArgs {
+    /// Where to write the table, the manifest goes to the same path with .json appended
+    #[arg(short, long)]
+    pub(crate) output_path: String,
+    /// Target address of the TIB arena of the hardware, in hex with 0x or in decimal, for the relocations in the manifest; the TIBs keep their host addresses if not given.
+    #[arg(long, value_parser = crate::util::parse_address)]
+    pub(crate) tib_base: Option<u64>,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct SelftestArgs {
+    /// Tracing loops to check against EdgeSlot, all the others if none are given.
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    pub(crate) tracing_loops: Vec<TracingLoopChoice>,
+    /// Number of worker threads of the parallel tracing loops.
+    #[arg(long, default_value_t = num_cpus::get())]
+    pub(crate) threads: usize,
+    /// Work Packet buffer capacity.
+    #[arg(long, default_value_t = 4096)]
+    pub(crate) wp_capacity: usize,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct AnonymizeArgs {
+    /// Where to write the anonymized heapdump (.binpb.zst)
+    #[arg(short, long)]
+    pub(crate) output_path: String,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Rebase32Args {
+    /// Where to write the rebased heapdump (.binpb.zst), only checking whether it fits if not given
+    #[arg(short, long)]
+    pub(crate) output_path: Option<String>,
+    /// Address of the first space, leaving the pages below unmapped.
+    #[arg(long, default_value_t = 0x10000)]
+    pub(crate) base: u64,
+    /// Shrink every slot to 4 bytes and pack the objects of each space.
+    #[arg(long, default_value_t = false)]
+    pub(crate) compress_slots: bool,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -0,0 +1,269 @@
+//! Estimated pause of a stop-the-world collection of each heapdump
+//! (`estimate`), composed of the phases the other commands model one at a
+//! time: scanning the roots, the closure, reference processing and
+//! reclaiming the dead objects. The phases run one after the other, so the
+//! pause is their sum. Each phase says where its time comes from, since a
+//! pause adding simulated and host times is only as good as its weakest
+//! phase:
+//!
+//! - `simulated`: ticks of NMPGC with 8 processors at its clock frequency.
+//!   The closure is the ticks after the last root stack was scanned.
+//! - `host`: timed on this machine.
+//! - `given`: taken from the command line.
+//! - `none`: the phase costs nothing as configured, such as root scanning
+//!   without `--root-stacks`, where the roots are a flat array.
+
+use crate::object_model::Header;
+use crate::results::{RunResults, StatsTable};
+use crate::root_scan::RootStacks;
+use crate::simulate::simulate_closure;
+use crate::util::ticks_to_us;
+use crate::*;
+use anyhow::{bail, Result};
+use clap::Parser;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant};
+
+/// Characters of the bar of a phase taking the whole pause
+const BAR_WIDTH: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Phase {
+    name: &'static str,
+    source: &'static str,
+    us: f64,
+}
+
+#[derive(Debug, Default)]
+struct PauseEstimate {
+    phases: Vec<Phase>,
+}
+
+impl PauseEstimate {
+    fn push(&mut self, name: &'static str, source: &'static str, us: f64) {
+        self.phases.push(Phase { name, source, us });
+    }
+
+    fn pause_us(&self) -> f64 {
+        self.phases.iter().map(|p| p.us).sum()
+    }
+
+    /// Prints the pause with a bar per phase, scaled to the pause.
+    fn print(&self, path: &str) {
+        let pause = self.pause_us();
+        println!("Estimated pause of {}: {:.3} us", path, pause);
+        for phase in &self.phases {
+            let fraction = if pause > 0.0 { phase.us / pause } else { 0.0 };
+            let bar = "#".repeat((fraction * BAR_WIDTH as f64).round() as usize);
+            println!(
+                "  {:<22} {:>12.3} us {:>6.1}%  {:<width$}  ({})",
+                phase.name,
+                phase.us,
+                fraction * 100.0,
+                bar,
+                phase.source,
+                width = BAR_WIDTH
+            );
+        }
+    }
+
+    fn write_csv(&self, path: &str, output: &mut impl Write) -> Result<()> {
+        for phase in &self.phases {
+            writeln!(
+                output,
+                "{},{},{},{}",
+                path, phase.name, phase.source, phase.us
+            )?;
+        }
+        writeln!(output, "{},pause,,{}", path, self.pause_us())?;
+        Ok(())
+    }
+
+    fn table(&self, path: &str) -> StatsTable {
+        let mut table = StatsTable::new("estimate", Some(path));
+        for phase in &self.phases {
+            table.push(format!("{}_us", phase.name), phase.us);
+        }
+        table.push("pause_us", self.pause_us());
+        table
+    }
+}
+
+/// Walks every object as a sweep building free lists would, reading its
+/// mark byte and adding up the bytes of those not marked with `mark_sense`.
+/// Returns the time and the dead bytes.
+fn sweep<O: ObjectModel>(mark_sense: u8, object_model: &O) -> (Duration, u64) {
+    let start = Instant::now();
+    let mut dead_bytes = 0;
+    for o in object_model.objects_iter() {
+        if Header::load(o).get_mark_byte() != mark_sense {
+            dead_bytes += object_model.object_size(o).unwrap_or(0);
+        }
+    }
+    (start.elapsed(), std::hint::black_box(dead_bytes))
+}
+
+fn us(elapsed: Duration) -> f64 {
+    elapsed.as_nanos() as f64 / 1000.0
+}
+
+/// Times root scanning and the closure of the restored heap as
+/// `--closure` says, returning the mark sense of the closure.
+fn estimate_closure<O: ObjectModel>(
+    estimate_args: &EstimateArgs,
+    path: &str,
+    object_model: &mut O,
+    estimate: &mut PauseEstimate,
+) -> Result<u8> {
+    let stacks = &estimate_args.root_stacks;
+    match estimate_args.closure {
+        ClosureSourceChoice::Simulated => {
+            let mut simulation_args =
+                SimulationArgs::parse_from(["simulate", "-a", "NMPGC", "-p", "8"]);
+            simulation_args.root_stacks = *stacks;
+            let closure = simulate_closure(path, &simulation_args, &*object_model)?;
+            let root_scan_us = ticks_to_us(closure.root_scan_ticks as u64, closure.frequency_ghz);
+            let closure_us = ticks_to_us(
+                (closure.ticks - closure.root_scan_ticks) as u64,
+                closure.frequency_ghz,
+            );
+            match stacks.root_stacks {
+                Some(_) => estimate.push("root_scan", "simulated", root_scan_us),
+                None => estimate.push("root_scan", "none", 0.0),
+            }
+            estimate.push("closure", "simulated", closure_us);
+            Ok(closure.mark_sense)
+        }
+        ClosureSourceChoice::Host => {
+            match stacks.root_stacks {
+                Some(threads) => {
+                    let stacks = RootStacks::synthesize(
+                        object_model.roots(),
+                        threads,
+                        stacks.frame_roots,
+                        stacks.frame_words,
+                    )?;
+                    let (elapsed, _) = stacks.scan_on_host();
+                    estimate.push("root_scan", "host", us(elapsed));
+                }
+                None => estimate.push("root_scan", "none", 0.0),
+            }
+            let session = TraceSession::new()
+                .with_tracing_loop(estimate_args.tracing_loop)
+                .with_threads(estimate_args.threads);
+            let Some((closure, mark_sense)) = session.trace_restored(object_model)? else {
+                bail!("No closure of {} was traced", path);
+            };
+            estimate.push("closure", "host", us(closure.time));
+            Ok(mark_sense)
+        }
+    }
+}
+
+pub fn estimate<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
+    let Some(Commands::Estimate(estimate_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    if estimate_args.reclaim == ReclaimChoice::Compact
+        && !matches!(
+            args.object_model,
+            ObjectModelChoice::Bidirectional | ObjectModelChoice::BidirectionalFallback
+        )
+    {
+        bail!("Only the Bidirectional object models support compaction");
+    }
+    if estimate_args.reference_us < 0.0 {
+        bail!("--reference-us cannot be negative");
+    }
+    let stacks = &estimate_args.root_stacks;
+    if let Some(threads) = stacks.root_stacks {
+        RootStacks::check(threads, stacks.frame_roots, stacks.frame_words)?;
+    }
+    let mut csv = match &estimate_args.csv {
+        Some(csv_path) => {
+            let mut file = BufWriter::new(File::create(csv_path)?);
+            writeln!(file, "heapdump,phase,source,us")?;
+            Some(file)
+        }
+        None => None,
+    };
+    let mut results = RunResults::new(&args.paths, args.results.clone())?;
+    for path in &args.paths {
+        object_model.reset();
+        let mut heapdump = HeapDump::from_path(path)?;
+        heapdump.select_roots(&args.roots)?;
+        if let Some(seed) = args.aslr_seed {
+            heapdump.randomize_layout(seed)?;
+        }
+        heapdump.map_spaces()?;
+        object_model.restore_objects(&heapdump);
+        let mut estimate = PauseEstimate::default();
+        let mark_sense = estimate_closure(estimate_args, path, &mut object_model, &mut estimate)?;
+        estimate.push("reference_processing", "given", estimate_args.reference_us);
+        match estimate_args.reclaim {
+            ReclaimChoice::Sweep => {
+                let (elapsed, _) = sweep(mark_sense, &object_model);
+                estimate.push("reclaim", "host", us(elapsed));
+            }
+            ReclaimChoice::Compact => {
+                let start = Instant::now();
+                if object_model.compact(mark_sense, false).is_none() {
+                    bail!("The object model does not support compaction");
+                }
+                estimate.push("reclaim", "host", us(start.elapsed()));
+            }
+            ReclaimChoice::None => estimate.push("reclaim", "none", 0.0),
+        }
+        estimate.print(path);
+        if let Some(csv) = &mut csv {
+            estimate.write_csv(path, csv)?;
+        }
+        results.add_table(estimate.table(path));
+        heapdump.unmap_spaces()?;
+    }
+    if let Some(mut csv) = csv {
+        csv.flush()?;
+    }
+    results.write()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+    use crate::trace::verify::host_closure;
+
+    #[test]
+    fn test_estimate() {
+        let mut estimate = PauseEstimate::default();
+        estimate.push("root_scan", "none", 0.0);
+        estimate.push("closure", "simulated", 30.0);
+        estimate.push("reclaim", "host", 10.0);
+        assert_eq!(estimate.pause_us(), 40.0);
+        let mut csv = vec![];
+        estimate.write_csv("h", &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "h,root_scan,none,0\nh,closure,simulated,30\nh,reclaim,host,10\nh,pause,,40\n"
+        );
+        let table = estimate.table("h");
+        assert_eq!(table.columns.last().unwrap().0, "pause_us");
+
+        // Only the objects the closure did not mark are dead
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        host_closure(1, &object_model);
+        assert_eq!(sweep(1, &object_model).1, 0);
+        let total: u64 = object_model
+            .objects_iter()
+            .map(|o| object_model.object_size(o).unwrap())
+            .sum();
+        assert_eq!(sweep(2, &object_model).1, total);
+        heapdump.unmap_spaces().unwrap();
+    }
+}
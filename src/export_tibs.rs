@@ -0,0 +1,294 @@
+//! Writing the TIBs an object model built from the heapdumps out as a flat
+//! table (`export-tibs`), so that the FPGA tracer scans objects with exactly
+//! the metadata the software model uses. Everything is little-endian:
+//!
+//! | Offset | Bytes | Content |
+//! |---|---|---|
+//! | 0 | 24 | Header: magic `HWGCTIBS`, version (u32), entries (u32), blocks (u32), zero (u32) |
+//! | 24 | 24 per entry | Entries, by klass: klass (u64), TIB type (u8), pattern (u8), zero (u16), first block (u32), blocks (u32), zero (u32) |
+//! | 24 + 24 * entries | 8 per block | Blocks: byte offset of the first slot from the start of the object (u32), slots (u32) |
+//!
+//! TIB types are 0 for ordinary objects, 1 for object arrays and 2 for
+//! instance mirrors. The pattern is the alignment encoding of the TIB, 7
+//! (fallback) when scanning needs the blocks. A JSON manifest next to the
+//! table records the layout and where the TIBs came from.
+//!
+//! The objects of a memory image point to their TIBs at host addresses,
+//! which the manifest relocates into a TIB arena of the target: each TIB, by
+//! klass, with its host and target address. With `--tib-base` the TIBs are
+//! packed from that address in host address order, each at the same offset
+//! from a multiple of `TIB_PATTERN_ALIGN` as on the host, so that they keep
+//! their alignment encoding patterns. Otherwise the target addresses are the
+//! host ones.
+
+use crate::object_model::{TibRecord, TIB_PATTERN_ALIGN};
+use crate::*;
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+const MAGIC: &[u8; 8] = b"HWGCTIBS";
+const VERSION: u32 = 1;
+const HEADER_BYTES: usize = 24;
+const ENTRY_BYTES: usize = 24;
+const BLOCK_BYTES: usize = 8;
+
+#[derive(Serialize)]
+struct Manifest {
+    format: &'static str,
+    version: u32,
+    object_model: String,
+    heapdumps: Vec<String>,
+    byte_order: &'static str,
+    header_bytes: usize,
+    entry_bytes: usize,
+    block_bytes: usize,
+    entries: usize,
+    blocks: usize,
+    entries_offset: usize,
+    blocks_offset: usize,
+    tib_types: [&'static str; 3],
+    tib_arena: TibArena,
+    /// By klass, like the entries of the table
+    relocations: Vec<Relocation>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct TibArena {
+    base: u64,
+    bytes: u64,
+    /// Alignment of `base`
+    alignment: u64,
+    /// Bytes each TIB takes in the arena
+    tib_bytes: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Relocation {
+    klass: u64,
+    host: u64,
+    target: u64,
+}
+
+/// Places the TIBs of `records` in an arena from `base`, or where they are
+/// on the host if not given, as described in the module documentation.
+fn relocate(
+    records: &[TibRecord],
+    base: Option<u64>,
+    tib_bytes: u64,
+) -> Result<(TibArena, Vec<Relocation>)> {
+    let mut hosts: Vec<(u64, u64)> = records.iter().map(|r| (r.tib, r.klass)).collect();
+    hosts.sort_unstable();
+    let mut targets = Vec::with_capacity(hosts.len());
+    let arena_base = match base {
+        Some(base) => {
+            if !base.is_multiple_of(TIB_PATTERN_ALIGN) {
+                bail!(
+                    "The TIB arena has to start at a multiple of {} bytes, not at 0x{:x}",
+                    TIB_PATTERN_ALIGN,
+                    base
+                );
+            }
+            let mut cursor = base;
+            for &(host, klass) in &hosts {
+                let target = cursor + host.wrapping_sub(cursor) % TIB_PATTERN_ALIGN;
+                targets.push((klass, host, target));
+                cursor = target + tib_bytes;
+            }
+            base
+        }
+        None => {
+            targets.extend(hosts.iter().map(|&(host, klass)| (klass, host, host)));
+            hosts
+                .first()
+                .map_or(0, |&(host, _)| host - host % TIB_PATTERN_ALIGN)
+        }
+    };
+    let end = targets
+        .last()
+        .map_or(arena_base, |&(_, _, t)| t + tib_bytes);
+    let mut relocations: Vec<Relocation> = targets
+        .into_iter()
+        .map(|(klass, host, target)| Relocation {
+            klass,
+            host,
+            target,
+        })
+        .collect();
+    relocations.sort_by_key(|r| r.klass);
+    let arena = TibArena {
+        base: arena_base,
+        bytes: end - arena_base,
+        alignment: TIB_PATTERN_ALIGN,
+        tib_bytes,
+    };
+    Ok((arena, relocations))
+}
+
+/// Lays `records` out as described in the module documentation, sorting
+/// them by klass.
+fn encode(records: &mut [TibRecord]) -> Result<Vec<u8>> {
+    records.sort_by_key(|r| r.klass);
+    let blocks: usize = records.iter().map(|r| r.blocks.len()).sum();
+    let mut bytes =
+        Vec::with_capacity(HEADER_BYTES + records.len() * ENTRY_BYTES + blocks * BLOCK_BYTES);
+    bytes.extend_from_slice(MAGIC);
+    for v in [VERSION, records.len() as u32, blocks as u32, 0] {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    let mut first_block = 0u32;
+    for r in records.iter() {
+        bytes.extend_from_slice(&r.klass.to_le_bytes());
+        bytes.extend_from_slice(&[r.ttype, r.pattern, 0, 0]);
+        for v in [first_block, r.blocks.len() as u32, 0] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        first_block += r.blocks.len() as u32;
+    }
+    for r in records.iter() {
+        for &(offset, count) in &r.blocks {
+            let (Ok(offset), Ok(count)) = (u32::try_from(offset), u32::try_from(count)) else {
+                bail!(
+                    "Klass 0x{:x} has a block of {} slots at offset {}, which does not fit in 32 bits",
+                    r.klass,
+                    count,
+                    offset
+                );
+            };
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+pub fn export_tibs<O: ObjectModel>(_object_model: O, args: Args) -> Result<()> {
+    let Some(Commands::ExportTibs(export_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    let mut records = O::tib_records();
+    let bytes = encode(&mut records)?;
+    let blocks = records.iter().map(|r| r.blocks.len()).sum();
+    let tib_bytes = std::mem::size_of::<O::Tib>() as u64;
+    let (tib_arena, relocations) = relocate(&records, export_args.tib_base, tib_bytes)?;
+    let manifest = Manifest {
+        format: "hwgc-tibs",
+        version: VERSION,
+        object_model: format!("{:?}", args.object_model),
+        heapdumps: args.paths.clone(),
+        byte_order: "little",
+        header_bytes: HEADER_BYTES,
+        entry_bytes: ENTRY_BYTES,
+        block_bytes: BLOCK_BYTES,
+        entries: records.len(),
+        blocks,
+        entries_offset: HEADER_BYTES,
+        blocks_offset: HEADER_BYTES + records.len() * ENTRY_BYTES,
+        tib_types: ["Ordinary", "ObjArray", "InstanceMirror"],
+        tib_arena,
+        relocations,
+    };
+    std::fs::write(&export_args.output_path, &bytes)?;
+    let manifest_path = format!("{}.json", export_args.output_path);
+    serde_json::to_writer_pretty(std::fs::File::create(&manifest_path)?, &manifest)?;
+    info!(
+        "Wrote {} TIBs with {} blocks to {}, described by {}",
+        records.len(),
+        blocks,
+        export_args.output_path,
+        manifest_path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::generated_src::NormalEdge;
+
+    #[test]
+    fn test_export_tibs() {
+        // A klass no other test uses, with references in the first two
+        // fields, which alignment encoding captures
+        let klass = 0x7e57_0000;
+        let start = 0x1000_0000;
+        let heapdump = HeapDump {
+            objects: vec![HeapObject {
+                start,
+                klass,
+                size: 32,
+                objarray_length: None,
+                instance_mirror_start: None,
+                instance_mirror_count: None,
+                edges: vec![
+                    NormalEdge {
+                        slot: start + 16,
+                        objref: 0,
+                    },
+                    NormalEdge {
+                        slot: start + 24,
+                        objref: 0,
+                    },
+                ],
+                pinned: false,
+            }],
+            roots: vec![],
+            spaces: vec![],
+            root_sets: vec![],
+            ..Default::default()
+        };
+        OpenJDKObjectModel::<true>::new().restore_tibs(&heapdump);
+        let mut records: Vec<TibRecord> = OpenJDKObjectModel::<true>::tib_records()
+            .into_iter()
+            .filter(|r| r.klass == klass)
+            .collect();
+        assert_eq!(
+            records,
+            vec![TibRecord {
+                klass,
+                tib: records[0].tib,
+                ttype: 0,
+                pattern: 5,
+                blocks: vec![(16, 2)],
+            }]
+        );
+        let bytes = encode(&mut records).unwrap();
+        assert_eq!(bytes.len(), HEADER_BYTES + ENTRY_BYTES + BLOCK_BYTES);
+        assert_eq!(&bytes[..8], MAGIC);
+        assert_eq!(bytes[12..16], 1u32.to_le_bytes());
+        assert_eq!(bytes[24..32], klass.to_le_bytes());
+        assert_eq!(bytes[32..34], [0, 5]);
+        assert_eq!(bytes[40..44], 1u32.to_le_bytes());
+        assert_eq!(bytes[48..], [16, 0, 0, 0, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_relocate() {
+        let record = |klass, tib| TibRecord {
+            klass,
+            tib,
+            ttype: 0,
+            pattern: 0,
+            blocks: vec![],
+        };
+        let records = [record(2, 0x7000_0010), record(1, 0x7000_0008)];
+        let (arena, relocations) = relocate(&records, Some(0x1000), 40).unwrap();
+        assert_eq!(
+            arena,
+            TibArena {
+                base: 0x1000,
+                bytes: 0x78,
+                alignment: TIB_PATTERN_ALIGN,
+                tib_bytes: 40
+            }
+        );
+        // Packed in host order, at the same offsets from the pattern
+        // alignment as on the host, which moves the second TIB past the
+        // first
+        let targets: Vec<(u64, u64)> = relocations.iter().map(|r| (r.klass, r.target)).collect();
+        assert_eq!(targets, [(1, 0x1008), (2, 0x1050)]);
+        assert!(relocate(&records, Some(0x1008), 40).is_err());
+        let (arena, relocations) = relocate(&records, None, 40).unwrap();
+        assert_eq!((arena.base, arena.bytes), (0x7000_0000, 0x38));
+        assert!(relocations.iter().all(|r| r.host == r.target));
+    }
+}
@@ -0,0 +1,115 @@
+//! Exit codes of the binary, so that the scripts driving experiments can
+//! tell the failures of the infrastructure (a heapdump that cannot be read,
+//! a heap that cannot be mapped) from those of the models (a verification
+//! failure, a diverged simulation) without parsing the output. Errors are
+//! classified by a [`Failure`] attached to them as context where they
+//! happen; anything else exits with 1, as `anyhow` does, and clap exits with
+//! 2 on bad arguments.
+
+use crate::simulate::SimulationDiverged;
+use anyhow::Result;
+use std::fmt;
+use std::process::ExitCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// A heapdump could not be read or decoded, or is of a newer version
+    BadHeapdump,
+    /// The spaces of a heapdump could not be mapped at their addresses, or
+    /// unmapped
+    Mmap,
+    /// A closure or simulation did not mark what the host does
+    Verification,
+    /// A simulation hit `--max-ticks` or `--stall-ticks`
+    Divergence,
+    /// Stopped with ctrl-C, after printing the partial statistics
+    Interrupted,
+    Other,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Other => 1,
+            Failure::BadHeapdump => 3,
+            Failure::Mmap => 4,
+            Failure::Verification => 5,
+            Failure::Divergence => 6,
+            // As a shell reports a process killed by SIGINT
+            Failure::Interrupted => 130,
+        }
+    }
+
+    /// The failure attached to `error`, or to any error it was caused by.
+    pub fn of(error: &anyhow::Error) -> Failure {
+        if let Some(&failure) = error.downcast_ref::<Failure>() {
+            failure
+        } else if error.downcast_ref::<SimulationDiverged>().is_some() {
+            Failure::Divergence
+        } else {
+            Failure::Other
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self {
+            Failure::BadHeapdump => "Bad heapdump",
+            Failure::Mmap => "Cannot map the heap",
+            Failure::Verification => "Verification failed",
+            Failure::Divergence => "Simulation diverged",
+            Failure::Interrupted => "Interrupted",
+            Failure::Other => "Failed",
+        };
+        write!(f, "{} (exit code {})", what, self.exit_code())
+    }
+}
+
+/// Prints the error of a run, if any, and returns the exit code of its
+/// outcome. A run stopped with ctrl-C exits with the code of
+/// [`Failure::Interrupted`] even though it printed its statistics.
+pub fn exit_code(result: Result<()>) -> ExitCode {
+    let failure = match result {
+        Ok(()) if crate::stop::requested() => Failure::Interrupted,
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            Failure::of(&error)
+        }
+    };
+    ExitCode::from(failure.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HeapDump;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_failure_of() {
+        let error = HeapDump::from_path("/nonexistent/heapdump.binpb.zst").unwrap_err();
+        assert_eq!(Failure::of(&error), Failure::BadHeapdump);
+        let error = HeapDump::from_path("[synthetic]nothing").unwrap_err();
+        assert_eq!(Failure::of(&error), Failure::BadHeapdump);
+        // Context added further up keeps the failure
+        let error = Err::<(), _>(anyhow!("unmarked"))
+            .context(Failure::Verification)
+            .context("tracing fop")
+            .unwrap_err();
+        assert_eq!(Failure::of(&error), Failure::Verification);
+        assert_eq!(Failure::of(&anyhow!("other")), Failure::Other);
+        let codes: Vec<u8> = [
+            Failure::Other,
+            Failure::BadHeapdump,
+            Failure::Mmap,
+            Failure::Verification,
+            Failure::Divergence,
+            Failure::Interrupted,
+        ]
+        .map(Failure::exit_code)
+        .to_vec();
+        assert_eq!(codes, [1, 3, 4, 5, 6, 130]);
+    }
+}
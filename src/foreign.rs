@@ -0,0 +1,208 @@
+//! Heapdumps of VMs on other targets, as recorded by `pointer_size` and
+//! `byte_order` in `heapdump.proto`, are converted when loaded, so that the
+//! rest of the toolchain only ever sees heapdumps of this host.
+//!
+//! A big-endian heapdump holds its addresses, and klass pointers, as raw
+//! words of the target's memory read on a little-endian host, so each is
+//! byte-swapped within the pointer size.
+//!
+//! A heapdump of a VM with 4-byte pointers has every offset into its spaces
+//! doubled. That turns the 32-bit layout of objects, with a 4-byte mark word
+//! and klass pointer, array lengths at 8 and elements from 12, into the
+//! 64-bit one the object models restore, with the length at 16 and elements
+//! from 24, and keeps every other field at the same word it was in. As the
+//! spaces of such a VM are below 4 GiB, they carry no space type bits, so
+//! each space is also moved to the region of the type its name gives.
+
+use crate::heapdump::generated_src::ByteOrder;
+use crate::*;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+const LIMIT_32: u64 = 1 << 32;
+const PAGE_SIZE: u64 = 4096;
+/// Bytes of the region of each space type, see `HeapDump::get_space_type`
+const LOG_TYPE_REGION: u32 = 41;
+
+/// The space type a space of a 32-bit heapdump goes to, by its name, as
+/// the type bits of `HeapDump::get_space_type`.
+fn space_type(name: &str) -> u64 {
+    let name = name.to_ascii_lowercase();
+    if name.contains("immortal") {
+        2
+    } else if name.contains("los") || name.contains("large") {
+        3
+    } else if name.contains("nonmoving") || name.contains("non_moving") {
+        4
+    } else {
+        1
+    }
+}
+
+impl HeapDump {
+    /// Converts a heapdump of another target into one of this host, if it
+    /// is not already.
+    pub(crate) fn convert_to_native(&mut self) -> Result<()> {
+        let pointer_size = match self.pointer_size {
+            0 | 8 => 8,
+            4 => 4,
+            n => bail!("Unsupported pointer size of {} bytes", n),
+        };
+        if self.byte_order() == ByteOrder::BigEndian {
+            let swap = |a: u64| -> Result<u64> {
+                if pointer_size == 4 {
+                    if a >= LIMIT_32 {
+                        bail!("Address 0x{:x} does not fit in 4 bytes", a);
+                    }
+                    Ok((a as u32).swap_bytes() as u64)
+                } else {
+                    Ok(a.swap_bytes())
+                }
+            };
+            for space in &mut self.spaces {
+                space.start = swap(space.start)?;
+                space.end = swap(space.end)?;
+            }
+            for object in &mut self.objects {
+                object.klass = swap(object.klass)?;
+            }
+            self.map_addresses(swap)?;
+        }
+        if pointer_size == 4 {
+            self.widen()?;
+        }
+        self.pointer_size = 0;
+        self.set_byte_order(ByteOrder::LittleEndian);
+        Ok(())
+    }
+
+    /// Replaces every address of the objects and roots by `f` of it,
+    /// leaving null references null.
+    fn map_addresses(&mut self, f: impl Fn(u64) -> Result<u64>) -> Result<()> {
+        let f = |a: u64| if a == 0 { Ok(0) } else { f(a) };
+        for object in &mut self.objects {
+            object.start = f(object.start)?;
+            if let Some(mirror) = object.instance_mirror_start {
+                object.instance_mirror_start = Some(f(mirror)?);
+            }
+            for edge in &mut object.edges {
+                edge.slot = f(edge.slot)?;
+                edge.objref = f(edge.objref)?;
+            }
+        }
+        let root_sets = self.root_sets.iter_mut().flat_map(|s| &mut s.roots);
+        for root in self.roots.iter_mut().chain(root_sets) {
+            root.objref = f(root.objref)?;
+        }
+        Ok(())
+    }
+
+    /// Doubles the offsets into the spaces of a heapdump with 4-byte
+    /// pointers, and moves each space to the region of its type.
+    fn widen(&mut self) -> Result<()> {
+        self.spaces.sort_by_key(|s| s.start);
+        // Next free address of the region of each type
+        let mut cursors: HashMap<u64, u64> = HashMap::new();
+        // Original start, original end and new start of each space
+        let mut moves: Vec<(u64, u64, u64)> = vec![];
+        for space in &mut self.spaces {
+            if space.end > LIMIT_32 {
+                bail!("Space {} ends above 4 GiB with 4-byte pointers", space.name);
+            }
+            let space_type = space_type(&space.name);
+            let cursor = cursors
+                .entry(space_type)
+                .or_insert(space_type << LOG_TYPE_REGION);
+            let new_start = *cursor;
+            let new_end = new_start + (space.end - space.start) * 2;
+            *cursor = new_end.next_multiple_of(PAGE_SIZE);
+            moves.push((space.start, space.end, new_start));
+            space.start = new_start;
+            space.end = new_end;
+        }
+        let widen = |a: u64| -> Result<u64> {
+            match moves
+                .iter()
+                .find(|&&(start, end, _)| (start..end).contains(&a))
+            {
+                Some(&(start, _, new_start)) => Ok(new_start + (a - start) * 2),
+                None => bail!("Address 0x{:x} is outside every space", a),
+            }
+        };
+        self.map_addresses(widen)?;
+        for object in &mut self.objects {
+            object.size *= 2;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::generated_src::{NormalEdge, Space};
+
+    #[test]
+    fn test_to_native() {
+        // A 32-bit big-endian heap with an array of two elements at 0x1000
+        // pointing to an object and null, in one space
+        let be = |a: u32| a.swap_bytes() as u64;
+        let mut heapdump = HeapDump {
+            objects: vec![
+                HeapObject {
+                    start: be(0x1000),
+                    klass: be(0x8000),
+                    size: 20,
+                    objarray_length: Some(2),
+                    edges: vec![
+                        NormalEdge {
+                            slot: be(0x100c),
+                            objref: be(0x1018),
+                        },
+                        NormalEdge {
+                            slot: be(0x1010),
+                            objref: 0,
+                        },
+                    ],
+                    ..Default::default()
+                },
+                HeapObject {
+                    start: be(0x1018),
+                    klass: be(0x8010),
+                    size: 8,
+                    ..Default::default()
+                },
+            ],
+            roots: vec![RootEdge { objref: be(0x1000) }],
+            spaces: vec![Space {
+                name: "immix".into(),
+                start: be(0x1000),
+                end: be(0x2000),
+            }],
+            pointer_size: 4,
+            byte_order: ByteOrder::BigEndian as i32,
+            ..Default::default()
+        };
+        heapdump.convert_to_native().unwrap();
+        let base = 0x200_0000_0000;
+        assert_eq!(
+            (heapdump.spaces[0].start, heapdump.spaces[0].end),
+            (base, base + 0x2000)
+        );
+        let array = &heapdump.objects[0];
+        assert_eq!((array.start, array.klass, array.size), (base, 0x8000, 40));
+        assert_eq!(array.edges[0].slot, base + 24);
+        assert_eq!(array.edges[0].objref, base + 0x30);
+        assert_eq!((array.edges[1].slot, array.edges[1].objref), (base + 32, 0));
+        assert_eq!(heapdump.objects[1].size, 16);
+        assert_eq!(heapdump.roots[0].objref, base);
+        assert_eq!(
+            (heapdump.pointer_size, heapdump.byte_order()),
+            (0, ByteOrder::LittleEndian)
+        );
+        // Native heapdumps are left alone
+        let before = heapdump.clone();
+        heapdump.convert_to_native().unwrap();
+        assert_eq!(heapdump, before);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::heapdump::DEFAULT_ROOT_SET;
+use crate::*;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Power-of-two histogram, where bucket `i` counts the values in
+/// `[2^(i-1), 2^i)` and bucket 0 counts zeros.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Histogram {
+    pub(crate) buckets: Vec<u64>,
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+    }
+
+    pub(crate) fn add(&mut self, other: &Histogram) {
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// An upper bound on the `q` quantile, the largest value of the bucket
+    /// holding it.
+    pub(crate) fn quantile(&self, q: f64) -> u64 {
+        let rank = (q * self.count() as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return if i == 0 { 0 } else { (1u64 << i) - 1 };
+            }
+        }
+        0
+    }
+
+    pub(crate) fn print(&self, title: &str, unit: &str) {
+        let total: u64 = self.buckets.iter().sum();
+        println!("  {}:", title);
+        for (i, &count) in self.buckets.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let range = match i {
+                0 => "0".to_string(),
+                1 => "1".to_string(),
+                _ => format!("{}-{}", 1u64 << (i - 1), (1u64 << i) - 1),
+            };
+            println!(
+                "    {:>21} {:<5} {:>12} {:>7.2}%",
+                range,
+                unit,
+                count,
+                count as f64 * 100.0 / total as f64
+            );
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SpaceStats {
+    pub(crate) objects: u64,
+    pub(crate) bytes: u64,
+    pub(crate) live_objects: u64,
+    pub(crate) live_bytes: u64,
+}
+
+/// Indices of the objects reachable from the roots.
+fn reachable(heapdump: &HeapDump) -> HashSet<usize> {
+    let index: HashMap<u64, usize> = heapdump
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(i, o)| (o.start, i))
+        .collect();
+    let mut live = HashSet::new();
+    let mut stack: Vec<u64> = heapdump.all_roots().map(|r| r.objref).collect();
+    while let Some(o) = stack.pop() {
+        // Dumps may contain roots into spaces that were not dumped
+        let Some(&i) = index.get(&o) else {
+            continue;
+        };
+        if live.insert(i) {
+            stack.extend(
+                heapdump.objects[i]
+                    .edges
+                    .iter()
+                    .map(|e| e.objref)
+                    .filter(|&r| r != 0),
+            );
+        }
+    }
+    live
+}
+
+/// What `stats` reports about a heapdump.
+pub(crate) struct HeapStats {
+    pub(crate) objects: u64,
+    pub(crate) live_objects: u64,
+    pub(crate) roots: u64,
+    pub(crate) klasses: u64,
+    pub(crate) slots: u64,
+    pub(crate) objarrays: u64,
+    pub(crate) pinned: u64,
+    /// Name and number of roots of each root set, empty if the heapdump
+    /// does not group its roots
+    pub(crate) root_sets: Vec<(String, u64)>,
+    /// By space in address order, with "(other)" for the objects outside
+    /// every space
+    pub(crate) spaces: Vec<(String, SpaceStats)>,
+    pub(crate) sizes: Histogram,
+    pub(crate) outdegrees: Histogram,
+}
+
+impl HeapStats {
+    pub(crate) fn new(heapdump: &HeapDump) -> Self {
+        let live = reachable(heapdump);
+        let mut spaces: Vec<(&str, u64, u64)> = heapdump
+            .spaces
+            .iter()
+            .map(|s| (s.name.as_str(), s.start, s.end))
+            .collect();
+        spaces.sort_by_key(|s| s.1);
+        // One extra entry for objects outside every space
+        let mut space_stats: Vec<SpaceStats> =
+            (0..=spaces.len()).map(|_| Default::default()).collect();
+        let mut sizes = Histogram::default();
+        let mut outdegrees = Histogram::default();
+        let mut klasses = HashSet::new();
+        let mut objarrays = 0;
+        let mut pinned = 0;
+        let mut slots = 0;
+        for (i, o) in heapdump.objects.iter().enumerate() {
+            let space = spaces
+                .iter()
+                .position(|&(_, start, end)| (start..end).contains(&o.start))
+                .unwrap_or(spaces.len());
+            let s = &mut space_stats[space];
+            s.objects += 1;
+            s.bytes += o.size;
+            if live.contains(&i) {
+                s.live_objects += 1;
+                s.live_bytes += o.size;
+            }
+            sizes.record(o.size);
+            outdegrees.record(o.edges.iter().filter(|e| e.objref != 0).count() as u64);
+            klasses.insert(o.klass);
+            if o.objarray_length.is_some() {
+                objarrays += 1;
+            }
+            if o.pinned {
+                pinned += 1;
+            }
+            slots += o.edges.len() as u64;
+        }
+        let root_sets = if heapdump.root_sets.is_empty() {
+            vec![]
+        } else {
+            std::iter::once((DEFAULT_ROOT_SET.to_string(), heapdump.roots.len() as u64))
+                .chain(
+                    heapdump
+                        .root_sets
+                        .iter()
+                        .map(|s| (s.name.clone(), s.roots.len() as u64)),
+                )
+                .collect()
+        };
+        let names = spaces
+            .iter()
+            .map(|s| s.0.to_string())
+            .chain(std::iter::once("(other)".to_string()));
+        HeapStats {
+            objects: heapdump.objects.len() as u64,
+            live_objects: live.len() as u64,
+            roots: heapdump.all_roots().count() as u64,
+            klasses: klasses.len() as u64,
+            slots,
+            objarrays,
+            pinned,
+            root_sets,
+            spaces: names.zip(space_stats).collect(),
+            sizes,
+            outdegrees,
+        }
+    }
+
+    fn print(&self, path: &str) {
+        println!("{}:", path);
+        println!(
+            "  Objects: {}, live: {}, roots: {}, klasses: {}, slots: {}",
+            self.objects, self.live_objects, self.roots, self.klasses, self.slots
+        );
+        if !self.root_sets.is_empty() {
+            let sets: Vec<String> = self
+                .root_sets
+                .iter()
+                .map(|(name, roots)| format!("{} ({})", name, roots))
+                .collect();
+            println!("  Root sets: {}", sets.join(", "));
+        }
+        println!(
+            "  Object arrays: {} ({:.2}% of objects)",
+            self.objarrays,
+            if self.objects > 0 {
+                self.objarrays as f64 * 100.0 / self.objects as f64
+            } else {
+                0.0
+            }
+        );
+        if self.pinned > 0 {
+            println!("  Pinned objects: {}", self.pinned);
+        }
+        println!(
+            "  {:<12} {:>12} {:>14} {:>12} {:>14}",
+            "Space", "Objects", "Bytes", "Live objs", "Live bytes"
+        );
+        for (name, s) in &self.spaces {
+            if name == "(other)" && s.objects == 0 {
+                continue;
+            }
+            println!(
+                "  {:<12} {:>12} {:>14} {:>12} {:>14}",
+                name, s.objects, s.bytes, s.live_objects, s.live_bytes
+            );
+        }
+        self.sizes.print("Object sizes", "B");
+        self.outdegrees.print("Non-null outgoing edges", "edges");
+    }
+}
+
+/// Characterizes heapdumps without restoring them.
+pub fn heap_stats(args: &Args) -> Result<()> {
+    for path in &args.paths {
+        let heapdump = HeapDump::from_path(path)?;
+        HeapStats::new(&heapdump).print(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut h = Histogram::default();
+        for v in [0, 1, 2, 3, 4, 7, 8] {
+            h.record(v);
+        }
+        assert_eq!(h.buckets, vec![1, 1, 2, 2, 1]);
+        assert_eq!(h.quantile(0.5), 3);
+        assert_eq!(h.quantile(1.0), 15);
+        let mut sum = Histogram::default();
+        sum.record(1000);
+        sum.add(&h);
+        assert_eq!(sum.count(), 8);
+        assert_eq!(sum.buckets[10], 1);
+        assert_eq!(sum.quantile(0.1), 0);
+    }
+}
@@ -1,18 +1,51 @@
-mod generated_src {
+pub(crate) mod generated_src {
     include!(concat!(env!("OUT_DIR"), "/heapdump.generated_src.rs"));
 }
-use anyhow::Result;
+use crate::failure::Failure;
+use anyhow::{bail, Context, Result};
 use prost::Message;
 use rand::seq::SliceRandom;
-use rand::{rngs::SmallRng, SeedableRng};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::Path;
 
 pub use generated_src::*;
 
 use super::util::{dzmmap_noreplace, munmap};
 
+/// Name of the set of roots that are not in any named root set
+pub const DEFAULT_ROOT_SET: &str = "default";
+
+/// The address bits that tell the types of spaces apart
+const SPACE_TYPE_MASK: u64 = 0xe0000000000;
+const SPACE_TYPE_SHIFT: u64 = 41;
+/// `randomize_layout` moves spaces by multiples of the smallest page size,
+/// so that objects keep their alignment, by up to 1 GiB each.
+const LAYOUT_PAGE_SIZE: u64 = 4096;
+const LAYOUT_MAX_PAGES: u64 = 1 << 18;
+
+/// Moves `space` up by `shift`, recording the move as `(original start,
+/// original end, shift)`.
+fn move_space(
+    space: &mut generated_src::Space,
+    shift: u64,
+    moves: &mut Vec<(u64, u64, u64)>,
+) -> Result<()> {
+    if (space.end + shift - 1) & SPACE_TYPE_MASK != space.start & SPACE_TYPE_MASK {
+        bail!(
+            "Cannot move space {} at 0x{:x} by 0x{:x} without changing its type",
+            space.name,
+            space.start,
+            shift
+        );
+    }
+    moves.push((space.start, space.end, shift));
+    space.start += shift;
+    space.end += shift;
+    Ok(())
+}
+
 pub enum Space {
     Immix,
     Immortal,
@@ -22,21 +55,162 @@ pub enum Space {
 
 impl HeapDump {
     fn from_binpb_zst(p: impl AsRef<Path>) -> Result<HeapDump> {
-        let file = File::open(p)?;
+        let file = File::open(&p)?;
         let mut reader = zstd::Decoder::new(file)?;
         let mut buf = vec![];
         reader.read_to_end(&mut buf)?;
-        Ok(HeapDump::decode(buf.as_slice())?)
+        let mut heapdump = HeapDump::decode(buf.as_slice())?;
+        heapdump.check_schema_version(&p.as_ref().display().to_string())?;
+        heapdump.convert_to_native()?;
+        Ok(heapdump)
+    }
+
+    pub fn to_binpb_zst(&self, p: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(p)?;
+        let mut writer = zstd::Encoder::new(file, 0)?;
+        writer.write_all(&self.encode_to_vec())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Every root, ungrouped or in a root set.
+    pub fn all_roots(&self) -> impl Iterator<Item = &RootEdge> {
+        self.roots
+            .iter()
+            .chain(self.root_sets.iter().flat_map(|s| &s.roots))
+    }
+
+    /// Keeps only the roots of the named root sets, where the ungrouped
+    /// roots form the set `DEFAULT_ROOT_SET`. Keeps every root if `names` is
+    /// empty.
+    pub fn select_roots(&mut self, names: &[String]) -> Result<()> {
+        if names.is_empty() {
+            return Ok(());
+        }
+        let mut roots = vec![];
+        for name in names {
+            if name == DEFAULT_ROOT_SET {
+                roots.extend_from_slice(&self.roots);
+                continue;
+            }
+            let Some(set) = self.root_sets.iter().find(|s| &s.name == name) else {
+                let available: Vec<&str> = std::iter::once(DEFAULT_ROOT_SET)
+                    .chain(self.root_sets.iter().map(|s| s.name.as_str()))
+                    .collect();
+                return Err(anyhow::anyhow!(
+                    "No root set named {:?}, the heapdump has {}",
+                    name,
+                    available.join(", ")
+                ));
+            };
+            roots.extend_from_slice(&set.roots);
+        }
+        self.roots = roots;
+        self.root_sets.clear();
+        Ok(())
+    }
+
+    /// Pins every object of the named spaces, in addition to the objects
+    /// pinned in the heapdump itself.
+    pub fn pin_spaces(&mut self, names: &[String]) -> Result<()> {
+        let mut ranges = vec![];
+        for name in names {
+            let Some(space) = self.spaces.iter().find(|s| &s.name == name) else {
+                let available: Vec<&str> = self.spaces.iter().map(|s| s.name.as_str()).collect();
+                return Err(anyhow::anyhow!(
+                    "No space named {:?}, the heapdump has {}",
+                    name,
+                    available.join(", ")
+                ));
+            };
+            ranges.push(space.start..space.end);
+        }
+        for object in &mut self.objects {
+            if ranges.iter().any(|r| r.contains(&object.start)) {
+                object.pinned = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves every space up by a random number of pages drawn from `seed`,
+    /// rewriting every address, so that results can be told apart from
+    /// artifacts of where the spaces happen to start, such as objects that
+    /// all map to the same cache sets or owners.
+    pub fn randomize_layout(&mut self, seed: u64) -> Result<()> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut order: Vec<usize> = (0..self.spaces.len()).collect();
+        order.sort_by_key(|&i| self.spaces[i].start);
+        // Original start, original end and shift of each space. The shift
+        // only grows with the address, so spaces cannot come to overlap.
+        let mut moves: Vec<(u64, u64, u64)> = vec![];
+        let mut shift = 0;
+        for i in order {
+            shift += rng.random_range(0..LAYOUT_MAX_PAGES) * LAYOUT_PAGE_SIZE;
+            move_space(&mut self.spaces[i], shift, &mut moves)?;
+        }
+        self.relocate(&moves)
+    }
+
+    /// Moves every space up by `offset`, which keeps the types of the
+    /// spaces, the owners of addresses and the cache sets they map to as
+    /// long as `offset` is a multiple of the interleaving.
+    pub fn offset_layout(&mut self, offset: u64) -> Result<()> {
+        let mut moves: Vec<(u64, u64, u64)> = vec![];
+        for space in &mut self.spaces {
+            move_space(space, offset, &mut moves)?;
+        }
+        self.relocate(&moves)
+    }
+
+    /// Rewrites every address of the objects and roots for the spaces moved
+    /// as `(original start, original end, shift)`.
+    fn relocate(&mut self, moves: &[(u64, u64, u64)]) -> Result<()> {
+        let relocate = |addr: u64| -> Result<u64> {
+            if addr == 0 {
+                return Ok(0);
+            }
+            match moves
+                .iter()
+                .find(|&&(start, end, _)| (start..end).contains(&addr))
+            {
+                Some(&(_, _, shift)) => Ok(addr + shift),
+                None => bail!("Address 0x{:x} is outside every space", addr),
+            }
+        };
+        for object in &mut self.objects {
+            object.start = relocate(object.start)?;
+            if let Some(mirror) = object.instance_mirror_start {
+                object.instance_mirror_start = Some(relocate(mirror)?);
+            }
+            for edge in &mut object.edges {
+                edge.slot = relocate(edge.slot)?;
+                edge.objref = relocate(edge.objref)?;
+            }
+        }
+        let root_sets = self.root_sets.iter_mut().flat_map(|s| &mut s.roots);
+        for root in self.roots.iter_mut().chain(root_sets) {
+            root.objref = relocate(root.objref)?;
+        }
+        Ok(())
     }
 
     pub fn from_path(path: &str) -> Result<HeapDump> {
-        let hd = if path.starts_with("[synthetic]") {
+        Self::read_path(path)
+            .with_context(|| format!("Cannot read heapdump {}", path))
+            .context(Failure::BadHeapdump)
+    }
+
+    fn read_path(path: &str) -> Result<HeapDump> {
+        let mut hd = if path.starts_with("[synthetic]") {
             match path.strip_prefix("[synthetic]") {
                 Some(name) => {
                     if name.starts_with("linked_list") {
                         LinkedListHeapDump::new(name).to_heapdump()
                     } else if name.starts_with("objarray") {
                         LeafObjectArrayHeapDump::new(name).to_heapdump()
+                    } else if name == "microbench" {
+                        microbench_heapdump()
                     } else {
                         return Err(anyhow::anyhow!("Invalid synthetic heapdump name: {}", path));
                     }
@@ -48,12 +222,17 @@ impl HeapDump {
         } else {
             HeapDump::from_binpb_zst(path)?
         };
+        crate::provenance::record(path, &hd);
+        // Whatever version it was read in, it is now in this one
+        hd.schema_version = crate::provenance::SCHEMA_VERSION;
         Ok(hd)
     }
     pub fn map_spaces(&self) -> Result<()> {
         for s in &self.spaces {
             debug!("Mapping {} at 0x{:x}", s.name, s.start);
-            dzmmap_noreplace(s.start, (s.end - s.start) as usize)?;
+            dzmmap_noreplace(s.start, (s.end - s.start) as usize)
+                .with_context(|| format!("Cannot map {} at 0x{:x}", s.name, s.start))
+                .context(Failure::Mmap)?;
         }
         Ok(())
     }
@@ -61,15 +240,15 @@ impl HeapDump {
     pub fn unmap_spaces(&self) -> Result<()> {
         for s in &self.spaces {
             debug!("Unmapping {} at 0x{:x}", s.name, s.start);
-            munmap(s.start, (s.end - s.start) as usize)?;
+            munmap(s.start, (s.end - s.start) as usize)
+                .with_context(|| format!("Cannot unmap {} at 0x{:x}", s.name, s.start))
+                .context(Failure::Mmap)?;
         }
         Ok(())
     }
 
     pub fn get_space_type(o: u64) -> Space {
-        let space_mask: u64 = 0xe0000000000;
-        let space_shift: u64 = 41;
-        match (o & space_mask) >> space_shift {
+        match (o & SPACE_TYPE_MASK) >> SPACE_TYPE_SHIFT {
             1 => Space::Immix,
             2 => Space::Immortal,
             3 => Space::Los,
@@ -132,6 +311,7 @@ impl LinkedListHeapDump {
                     instance_mirror_start: None,
                     instance_mirror_count: None,
                     edges,
+                    pinned: false,
                 }
             })
             .collect()
@@ -152,6 +332,7 @@ impl LinkedListHeapDump {
                     instance_mirror_start: None,
                     instance_mirror_count: None,
                     edges: vec![],
+                    pinned: false,
                 }
             })
             .collect();
@@ -189,6 +370,8 @@ impl LinkedListHeapDump {
             objects,
             roots,
             spaces,
+            root_sets: vec![],
+            ..Default::default()
         }
     }
 }
@@ -255,6 +438,7 @@ impl LeafObjectArrayHeapDump {
             instance_mirror_start: None,
             instance_mirror_count: None,
             edges: array_content,
+            pinned: false,
         }];
 
         (0..self.num_objs).for_each(|i| {
@@ -267,6 +451,7 @@ impl LeafObjectArrayHeapDump {
                 instance_mirror_start: None,
                 instance_mirror_count: None,
                 edges: vec![], // Leaf object with no outgoing pointers
+                pinned: false,
             });
         });
 
@@ -274,6 +459,137 @@ impl LeafObjectArrayHeapDump {
             objects,
             roots,
             spaces,
+            root_sets: vec![],
+            ..Default::default()
+        }
+    }
+}
+
+/// Lengths of the object arrays of `[synthetic]microbench`
+const MICROBENCH_ARRAY_LENGTHS: [u64; 5] = [0, 1, 8, 64, 512];
+
+// RUST_LOG=info cargo run --release -- [synthetic]microbench -o OpenJDKAE microbench
+/// One object of each shape the object models scan differently, for
+/// `microbench`: a leaf, an instance with the fields of each alignment
+/// encoding pattern in use, an instance with a field past the patterns,
+/// which falls back to its TIB, and object arrays of a few lengths. Every
+/// slot points to the leaf, and every object is a root.
+fn microbench_heapdump() -> HeapDump {
+    const START: u64 = 0x20000000000;
+    let fields = crate::object_model::PATTERN_FIELDS;
+    let leaf_size = 2 * 8; // header, klass
+    let mut shapes: Vec<(u64, Option<u64>, Vec<u64>)> = vec![(leaf_size, None, vec![])];
+    for mask in crate::object_model::ae_patterns().masks().iter().flatten() {
+        let fields: Vec<u64> = (0..fields).filter(|f| mask & (1 << f) != 0).collect();
+        shapes.push(((2 + 7) * 8, None, fields));
+    }
+    // The first field and the one after the last a pattern covers
+    shapes.push(((2 + fields + 1) * 8, None, vec![0, fields]));
+    for length in MICROBENCH_ARRAY_LENGTHS {
+        // header, klass, array length, and the references
+        shapes.push(((3 + length) * 8, Some(length), (1..=length).collect()));
+    }
+    let mut objects = vec![];
+    let mut cursor = START;
+    for (i, (size, objarray_length, fields)) in shapes.into_iter().enumerate() {
+        let first_slot = cursor + 16;
+        objects.push(generated_src::HeapObject {
+            start: cursor,
+            // One klass for every object array, as java.lang.Object[] is.
+            // TIBs are cached by klass for the whole process, so the klasses
+            // stay clear of those of the other synthetic heapdumps.
+            klass: if objarray_length.is_some() {
+                0x6d62_0000
+            } else {
+                0x6d62_0001 + i as u64
+            },
+            size,
+            objarray_length,
+            instance_mirror_start: None,
+            instance_mirror_count: None,
+            edges: fields
+                .into_iter()
+                .map(|f| generated_src::NormalEdge {
+                    slot: first_slot + f * 8,
+                    objref: START,
+                })
+                .collect(),
+            pinned: false,
+        });
+        cursor = (cursor + size).next_multiple_of(16);
+    }
+    let roots = objects
+        .iter()
+        .map(|o| generated_src::RootEdge { objref: o.start })
+        .collect();
+    HeapDump {
+        objects,
+        roots,
+        spaces: vec![generated_src::Space {
+            name: "immix".to_string(),
+            start: START,
+            end: cursor,
+        }],
+        root_sets: vec![],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_roots() {
+        let root = |objref| RootEdge { objref };
+        let set = |name: &str, roots| RootSet {
+            name: name.to_string(),
+            roots,
+        };
+        let mut heapdump = HeapDump {
+            objects: vec![],
+            roots: vec![root(8)],
+            spaces: vec![],
+            root_sets: vec![set("main", vec![root(16)]), set("worker", vec![root(24)])],
+            ..Default::default()
+        };
+        assert_eq!(heapdump.all_roots().count(), 3);
+        assert!(heapdump.clone().select_roots(&["gc".into()]).is_err());
+        heapdump.select_roots(&[]).unwrap();
+        assert_eq!(heapdump.root_sets.len(), 2);
+        heapdump
+            .select_roots(&["worker".into(), DEFAULT_ROOT_SET.into()])
+            .unwrap();
+        assert_eq!(heapdump.roots, vec![root(24), root(8)]);
+        assert!(heapdump.root_sets.is_empty());
+    }
+
+    #[test]
+    fn test_pin_spaces() {
+        let mut heapdump = HeapDump::from_path("[synthetic]objarray_8").unwrap();
+        assert!(heapdump.objects.iter().all(|o| !o.pinned));
+        assert!(heapdump.pin_spaces(&["los".into()]).is_err());
+        heapdump.pin_spaces(&["immix".into()]).unwrap();
+        assert!(heapdump.objects.iter().all(|o| o.pinned));
+    }
+
+    #[test]
+    fn test_randomize_layout() {
+        let original = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        let mut heapdump = original.clone();
+        heapdump.randomize_layout(7).unwrap();
+        let shift = heapdump.spaces[0].start - original.spaces[0].start;
+        assert_eq!(shift % LAYOUT_PAGE_SIZE, 0);
+        assert_eq!(heapdump.roots[0].objref, original.roots[0].objref + shift);
+        for (o, r) in original.objects.iter().zip(&heapdump.objects) {
+            assert_eq!(r.start, o.start + shift);
+            for (e, f) in o.edges.iter().zip(&r.edges) {
+                assert_eq!((f.slot, f.objref), (e.slot + shift, e.objref + shift));
+            }
         }
+        // The same seed gives the same layout
+        let mut again = original.clone();
+        again.randomize_layout(7).unwrap();
+        assert_eq!(again, heapdump);
     }
 }
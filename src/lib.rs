@@ -5,18 +5,34 @@ extern crate lazy_static;
 extern crate log;
 
 mod analysis;
+mod anonymize;
 mod cli;
 #[allow(dead_code)]
 mod constants;
+mod estimate;
 mod export;
+mod export_tibs;
+mod failure;
+mod foreign;
+mod heap_stats;
 mod heapdump;
 #[cfg(feature = "m5")]
 pub mod m5;
+mod memory_usage;
+mod microbench;
 mod object_model;
 mod paper_analysis;
 mod probes;
+mod progress;
+mod provenance;
+#[cfg(feature = "python")]
+mod python;
+mod rebase32;
+mod results;
+mod root_scan;
 pub(crate) mod shim;
 mod simulate;
+mod stop;
 mod trace;
 mod util;
 
@@ -24,13 +40,38 @@ pub mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
+pub use crate::analysis::ae_coverage::ae_coverage;
+pub use crate::analysis::alignment::alignment;
 pub use crate::analysis::depth::object_depth;
+pub use crate::analysis::dominators::dominators;
+pub use crate::analysis::line_conflicts::line_conflicts;
 pub use crate::analysis::reified_analysis;
+pub use crate::analysis::why_reachable::why_reachable;
+pub use crate::anonymize::anonymize;
 pub use crate::cli::*;
+pub use crate::estimate::estimate;
 pub use crate::export::export;
+pub use crate::export_tibs::export_tibs;
+pub use crate::failure::{exit_code, Failure};
+pub use crate::heap_stats::heap_stats;
 pub use crate::heapdump::{HeapDump, HeapObject, LinkedListHeapDump, RootEdge};
-pub use crate::object_model::{BidirectionalObjectModel, ObjectModel, OpenJDKObjectModel};
+pub use crate::memory_usage::CountingAllocator;
+pub use crate::microbench::microbench;
+pub use crate::object_model::{
+    set_ae_patterns, tib_allocation_stats, AePatternTable, BidirectionalObjectModel,
+    CompactionStats, ObjectModel, ObjectMove, OpenJDKObjectModel,
+};
 pub use crate::paper_analysis::reified_paper_analysis;
-pub use crate::simulate::reified_simulation;
+pub use crate::progress::enable_progress;
+pub use crate::provenance::{report_provenance, SCHEMA_VERSION};
+pub use crate::rebase32::rebase32;
+pub use crate::results::git_version;
+pub use crate::simulate::{convert_trace, reified_simulation};
+pub use crate::stop::install_stop_handler;
 pub use crate::trace::reified_trace;
-pub use crate::trace::TracingLoopChoice;
+pub use crate::trace::selftest;
+pub use crate::trace::{
+    bench_iter, bench_prepare, bench_release, Bench, HeapdumpTrace, TimedTracingStats,
+    TraceSession, TracingLoopChoice, TracingStats,
+};
+pub use crate::util::typed_obj::{Address, ObjectReference};
@@ -4,12 +4,44 @@ use anyhow::Result;
 
 use clap::Parser;
 use hwgc_soft::*;
-use std::time::Instant;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "count_allocations")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 fn reified_main<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
     if let Some(Commands::PaperAnalyze(_)) = args.command {
         return reified_paper_analysis(object_model, args);
     }
+    if let Some(Commands::Stats) = args.command {
+        return heap_stats(&args);
+    }
+    if let Some(Commands::Anonymize(_)) = args.command {
+        return anonymize(&args);
+    }
+    if let Some(Commands::Rebase32(_)) = args.command {
+        return rebase32(&args);
+    }
+    if let Some(Commands::AeCoverage(_)) = args.command {
+        return ae_coverage(&args);
+    }
+    if let Some(Commands::WhyReachable(_)) = args.command {
+        return why_reachable(&args);
+    }
+    if let Some(Commands::Dominators(_)) = args.command {
+        return dominators(&args);
+    }
+    if let Some(Commands::LineConflicts(_)) = args.command {
+        return line_conflicts(&args);
+    }
+    if let Some(Commands::Alignment(_)) = args.command {
+        return alignment(&args);
+    }
+    if let Some(Commands::ConvertTrace(_)) = args.command {
+        return convert_trace(&args);
+    }
 
     for path in &args.paths {
         let start = Instant::now();
@@ -23,6 +55,12 @@ fn reified_main<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
             elapsed.as_millis()
         );
     }
+    report_provenance(&args.paths);
+    let tib_memory = tib_allocation_stats();
+    info!(
+        "{} TIBs take {} bytes, plus {} bytes of alignment padding",
+        tib_memory.allocations, tib_memory.bytes_allocated, tib_memory.padding_bytes
+    );
 
     if let Some(ref cmd) = args.command {
         match cmd {
@@ -31,6 +69,10 @@ fn reified_main<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
             Commands::Depth(_) => object_depth(object_model, args),
             Commands::Simulate(_) => reified_simulation(object_model, args),
             Commands::Export(_) => export(object_model, args),
+            Commands::Selftest(_) => selftest(object_model, args),
+            Commands::ExportTibs(_) => export_tibs(object_model, args),
+            Commands::Estimate(_) => estimate(object_model, args),
+            Commands::Microbench(_) => microbench(object_model, args),
             _ => unreachable!(),
         }
     } else {
@@ -38,34 +80,41 @@ fn reified_main<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
     }
 }
 
-fn get_git_info() -> String {
-    match (built_info::GIT_COMMIT_HASH, built_info::GIT_DIRTY) {
-        (Some(hash), Some(dirty)) => format!(
-            "{}{}",
-            hash.split_at(7).0,
-            if dirty { "-dirty" } else { "" }
-        ),
-        (Some(hash), None) => format!("{}{}", hash.split_at(7).0, "-?"),
-        _ => "unknown-git-version".to_string(),
-    }
+pub fn main() -> ExitCode {
+    exit_code(run())
 }
 
-pub fn main() -> Result<()> {
+fn run() -> Result<()> {
     env_logger::init();
     println!(
         "hwgc_soft {} (DRAMsim3 {})",
-        get_git_info(),
+        git_version(),
         env!("DRAMSIM3_GIT_HASH")
     );
     let args = Args::parse();
+    install_stop_handler()?;
+    if let Some(path) = &args.ae_patterns {
+        set_ae_patterns(AePatternTable::from_file(path)?)?;
+    }
+    if let Some(secs) = args.progress {
+        enable_progress(Duration::from_secs(secs));
+    }
     match args.object_model {
-        ObjectModelChoice::OpenJDK => reified_main(OpenJDKObjectModel::<false>::new(), args),
-        ObjectModelChoice::OpenJDKAE => reified_main(OpenJDKObjectModel::<true>::new(), args),
-        ObjectModelChoice::Bidirectional => {
-            reified_main(BidirectionalObjectModel::<true>::new(), args)
-        }
-        ObjectModelChoice::BidirectionalFallback => {
-            reified_main(BidirectionalObjectModel::<false>::new(), args)
-        }
+        ObjectModelChoice::OpenJDK => reified_main(
+            OpenJDKObjectModel::<false>::new().with_object_map(args.object_map),
+            args,
+        ),
+        ObjectModelChoice::OpenJDKAE => reified_main(
+            OpenJDKObjectModel::<true>::new().with_object_map(args.object_map),
+            args,
+        ),
+        ObjectModelChoice::Bidirectional => reified_main(
+            BidirectionalObjectModel::<true>::new().with_object_map(args.object_map),
+            args,
+        ),
+        ObjectModelChoice::BidirectionalFallback => reified_main(
+            BidirectionalObjectModel::<false>::new().with_object_map(args.object_map),
+            args,
+        ),
     }
 }
@@ -0,0 +1,132 @@
+//! Memory footprint of the tooling itself, which decides which heapdumps
+//! can be processed on a shared server. With `--features count_allocations`
+//! the binary counts its allocations with [`CountingAllocator`], which is
+//! off by default since its counters are shared by all the threads of a
+//! trace. The peak resident set size comes from `/proc/self/status`, which
+//! is the only place that also sees the mapped spaces of the heapdumps.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Whether the binary installs [`CountingAllocator`], without which the
+/// allocations stay at zero and are not reported.
+pub(crate) const COUNTING: bool = cfg!(feature = "count_allocations");
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting the allocations made through it. Only
+/// the binary installs it as the `#[global_allocator]`, and only with
+/// `--features count_allocations`, so that the Python and C libraries keep
+/// the allocator of the program they are linked into; elsewhere the
+/// counters stay at zero.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // Counted as a new allocation of the growth, which is what it costs
+        // when the block cannot grow in place
+        count(new_size.saturating_sub(layout.size()));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count(bytes: usize) {
+    // Relaxed, since the counters are only read between phases, after the
+    // threads allocating in a phase have been joined or have gone idle
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Allocations made so far, and the memory used by the process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct MemoryUsage {
+    pub(crate) allocations: u64,
+    pub(crate) allocated_bytes: u64,
+    /// Peak resident set size in bytes (VmHWM), if the platform reports it
+    pub(crate) peak_rss: Option<u64>,
+}
+
+impl MemoryUsage {
+    pub(crate) fn now() -> Self {
+        MemoryUsage {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+            peak_rss: peak_rss(),
+        }
+    }
+
+    /// The allocations made since `earlier`, with the peak RSS of `self`.
+    pub(crate) fn since(&self, earlier: &MemoryUsage) -> MemoryUsage {
+        MemoryUsage {
+            allocations: self.allocations - earlier.allocations,
+            allocated_bytes: self.allocated_bytes - earlier.allocated_bytes,
+            peak_rss: self.peak_rss,
+        }
+    }
+}
+
+/// The peak RSS in bytes, from the VmHWM line of `/proc/self/status`.
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_status_kb(&status, "VmHWM:").map(|kb| kb * 1024)
+}
+
+fn parse_status_kb(status: &str, key: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with(key))?;
+    line[key.len()..]
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Resets the peak RSS to the current RSS, so that the next reading is the
+/// peak of what runs in between. Does nothing where `/proc/self/clear_refs`
+/// cannot be written, leaving the peak since the process started.
+pub(crate) fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_usage() {
+        let status =
+            "Name:\thwgc_soft\nVmPeak:\t  123456 kB\nVmHWM:\t    2048 kB\nVmRSS:\t    1024 kB\n";
+        assert_eq!(parse_status_kb(status, "VmHWM:"), Some(2048));
+        assert_eq!(parse_status_kb(status, "VmSwap:"), None);
+
+        let before = MemoryUsage::now();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = CountingAllocator.alloc(layout);
+            let ptr = CountingAllocator.realloc(ptr, layout, 256);
+            CountingAllocator.dealloc(ptr, Layout::from_size_align(256, 8).unwrap());
+        }
+        // The tests do not install the allocator, so nothing else counts
+        let usage = MemoryUsage::now().since(&before);
+        assert_eq!(usage.allocations, 2);
+        assert_eq!(usage.allocated_bytes, 256);
+        if cfg!(target_os = "linux") {
+            assert!(usage.peak_rss.unwrap() > 0);
+        }
+    }
+}
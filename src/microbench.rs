@@ -0,0 +1,197 @@
+//! How long the object model takes to scan one object of each shape
+//! (`microbench`), on this machine, to calibrate the latencies the
+//! simulator charges for decoding an object. Each shape is scanned over and
+//! over on its own, so its header, TIB and slots stay in the L1 cache, and
+//! the time is that of the decoding alone. `[synthetic]microbench` has one
+//! object of each alignment encoding pattern, a fallback and a few object
+//! arrays.
+
+use crate::object_model::FALLBACK_PATTERN;
+use crate::results::{RunResults, StatsTable};
+use crate::*;
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+/// An object standing for every object with the same TIB and slots, and
+/// what scanning it took.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Shape {
+    object: ObjectReference,
+    slots: u64,
+    objarray: bool,
+    /// Alignment encoding pattern of the TIB, the fallback for the models
+    /// without alignment encoding and for instance mirrors
+    pattern: u8,
+    tib_lookup: bool,
+    ns_per_scan: f64,
+}
+
+/// The first object of each shape, in the order of the model.
+fn shapes<O: ObjectModel>(object_model: &O) -> Vec<Shape> {
+    let patterns: HashMap<u64, u8> = O::tib_records()
+        .into_iter()
+        .map(|r| (r.tib, r.pattern))
+        .collect();
+    let mut seen = HashSet::new();
+    let mut shapes = vec![];
+    for o in object_model.objects_iter() {
+        let tib = O::get_tib(o) as u64;
+        let slots = O::num_edges(o);
+        if !seen.insert((tib, slots)) {
+            continue;
+        }
+        shapes.push(Shape {
+            object: o,
+            slots,
+            objarray: unsafe { O::is_objarray(o) },
+            pattern: patterns.get(&tib).copied().unwrap_or(FALLBACK_PATTERN),
+            tib_lookup: O::tib_lookup_required(o),
+            ns_per_scan: 0.0,
+        });
+    }
+    shapes
+}
+
+/// Scans `o` `repetitions` times, after one scan to warm the caches, and
+/// returns the nanoseconds of a scan.
+fn time_scans<O: ObjectModel>(o: ObjectReference, repetitions: usize) -> f64 {
+    let mut sum = 0u64;
+    let mut scan = || {
+        O::scan_object(black_box(o), |first, count| {
+            sum = sum.wrapping_add(first.as_u64() ^ count)
+        })
+    };
+    scan();
+    let start = Instant::now();
+    for _ in 0..repetitions {
+        scan();
+    }
+    let elapsed = start.elapsed();
+    black_box(sum);
+    elapsed.as_nanos() as f64 / repetitions as f64
+}
+
+/// The time of each shape in the order printed, and their mean.
+fn table(path: &str, shapes: &[Shape]) -> StatsTable {
+    let mut table = StatsTable::new("microbench", Some(path));
+    for (i, s) in shapes.iter().enumerate() {
+        table.push(format!("ns_per_scan.{}", i), s.ns_per_scan);
+    }
+    let sum: f64 = shapes.iter().map(|s| s.ns_per_scan).sum();
+    table.push("ns_per_scan.mean", sum / shapes.len().max(1) as f64);
+    table
+}
+
+pub fn microbench<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
+    let Some(Commands::Microbench(microbench_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    if microbench_args.repetitions == 0 {
+        bail!("--repetitions has to be at least 1");
+    }
+    let mut csv = match &microbench_args.output_path {
+        Some(output_path) => {
+            let mut file = BufWriter::new(File::create(output_path)?);
+            writeln!(
+                file,
+                "heapdump,object_model,object,slots,objarray,pattern,tib_lookup,ns_per_scan"
+            )?;
+            Some(file)
+        }
+        None => None,
+    };
+    let mut results = RunResults::new(&args.paths, args.results.clone())?;
+    for path in &args.paths {
+        object_model.reset();
+        let heapdump = HeapDump::from_path(path)?;
+        heapdump.map_spaces()?;
+        object_model.restore_objects(&heapdump);
+        let mut shapes = shapes(&object_model);
+        for s in &mut shapes {
+            s.ns_per_scan = time_scans::<O>(s.object, microbench_args.repetitions);
+        }
+        println!(
+            "{}: {} shapes with {:?}, {} scans each",
+            path,
+            shapes.len(),
+            args.object_model,
+            microbench_args.repetitions
+        );
+        println!(
+            "  {:>18} {:>8} {:>8} {:>8} {:>10} {:>12}",
+            "object", "slots", "objarray", "pattern", "tib lookup", "ns/scan"
+        );
+        for s in &shapes {
+            println!(
+                "  {:>18} {:>8} {:>8} {:>8} {:>10} {:>12.2}",
+                format!("0x{:x}", s.object.to_raw()),
+                s.slots,
+                s.objarray,
+                s.pattern,
+                s.tib_lookup,
+                s.ns_per_scan
+            );
+            if let Some(csv) = &mut csv {
+                writeln!(
+                    csv,
+                    "{},{:?},{},{},{},{},{},{}",
+                    path,
+                    args.object_model,
+                    s.object.to_raw(),
+                    s.slots,
+                    s.objarray,
+                    s.pattern,
+                    s.tib_lookup,
+                    s.ns_per_scan
+                )?;
+            }
+        }
+        results.add_table(table(path, &shapes));
+        heapdump.unmap_spaces()?;
+    }
+    if let Some(mut csv) = csv {
+        csv.flush()?;
+    }
+    results.write()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+
+    #[test]
+    fn test_microbench_shapes() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]microbench").unwrap();
+        let mut object_model = OpenJDKObjectModel::<true>::new();
+        heapdump.map_spaces().unwrap();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        let shapes = shapes(&object_model);
+        // Every object of the heapdump has a shape of its own
+        assert_eq!(shapes.len(), heapdump.objects.len());
+        let slots: Vec<u64> = shapes.iter().map(|s| s.slots).collect();
+        let edges: Vec<u64> = heapdump
+            .objects
+            .iter()
+            .map(|o| o.edges.len() as u64)
+            .collect();
+        assert_eq!(slots, edges);
+        // Only the instance with a field past the patterns reads its TIB
+        let lookups: Vec<bool> = shapes.iter().map(|s| s.tib_lookup).collect();
+        let fallback = heapdump
+            .objects
+            .iter()
+            .rposition(|o| o.objarray_length.is_none())
+            .unwrap();
+        assert!(lookups[fallback]);
+        assert_eq!(lookups.iter().filter(|&&l| l).count(), 1);
+        assert!(time_scans::<OpenJDKObjectModel<true>>(shapes[0].object, 10) >= 0.0);
+        heapdump.unmap_spaces().unwrap();
+    }
+}
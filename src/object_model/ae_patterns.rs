@@ -0,0 +1,235 @@
+//! The patterns of reference fields that alignment encoding (`OpenJDKAE`)
+//! gives a code to. The TIB of a klass whose fields match one of them is
+//! allocated at an address whose bits 3 to 5 hold the code, so that scanning
+//! its objects does not read the TIB. Codes 6 (object arrays) and 7
+//! (fallback, read the TIB) are fixed. Codes 0 to 5 are OpenJDK-MMTk's
+//! choice by default, and can be loaded from a TOML file with
+//! `--ae-patterns`, which lists the reference fields of each code, counting
+//! from the first field after the header and the klass pointer:
+//!
+//! ```toml
+//! [codes]
+//! 0 = []
+//! 1 = [0]
+//! 2 = [1, 2, 3]
+//! ```
+
+use super::FALLBACK_PATTERN;
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Code of object arrays, whose slots follow the length
+pub(crate) const REF_ARRAY_CODE: u8 = 6;
+/// Codes available for field patterns, 0 to 5
+pub(crate) const FIELD_CODES: usize = REF_ARRAY_CODE as usize;
+/// Fields a pattern can cover, one bit each in its mask
+pub(crate) const PATTERN_FIELDS: u64 = 7;
+/// Words before the first field, the header and the klass pointer
+const FIELDS_START: usize = 2;
+
+static AE_PATTERNS: OnceCell<AePatternTable> = OnceCell::new();
+
+/// The patterns alignment encoding uses, OpenJDK-MMTk's unless
+/// [`set_ae_patterns`] was called before.
+pub(crate) fn ae_patterns() -> &'static AePatternTable {
+    AE_PATTERNS.get_or_init(AePatternTable::default)
+}
+
+/// Makes alignment encoding use `table`, which has to happen before any TIB
+/// is allocated, as TIBs are never moved.
+pub fn set_ae_patterns(table: AePatternTable) -> Result<()> {
+    AE_PATTERNS
+        .set(table)
+        .map_err(|_| anyhow!("The alignment encoding patterns are already in use"))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AePatternFile {
+    codes: BTreeMap<String, Vec<u64>>,
+}
+
+/// Field patterns by code, as bitmasks of the first [`PATTERN_FIELDS`]
+/// fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AePatternTable {
+    masks: [Option<u8>; FIELD_CODES],
+    /// Runs of reference slots of each code, as word offset from the start
+    /// of the object and number of slots, as scanning wants them
+    runs: [Vec<(usize, u64)>; FIELD_CODES],
+}
+
+impl Default for AePatternTable {
+    fn default() -> Self {
+        // NoRef, Ref0, Ref1_2_3, Ref4_5_6, Ref2 and Ref0_1
+        Self::new([
+            Some(0b0000000),
+            Some(0b0000001),
+            Some(0b0001110),
+            Some(0b1110000),
+            Some(0b0000100),
+            Some(0b0000011),
+        ])
+        .unwrap()
+    }
+}
+
+impl AePatternTable {
+    pub(crate) fn new(masks: [Option<u8>; FIELD_CODES]) -> Result<Self> {
+        for (code, mask) in masks.iter().enumerate() {
+            let Some(mask) = *mask else {
+                continue;
+            };
+            if mask >> PATTERN_FIELDS != 0 {
+                bail!(
+                    "Code {} covers fields past the first {}",
+                    code,
+                    PATTERN_FIELDS
+                );
+            }
+            if masks[..code].contains(&Some(mask)) {
+                bail!("Code {} repeats the fields of an earlier code", code);
+            }
+        }
+        let runs = masks.map(|mask| {
+            let mut runs: Vec<(usize, u64)> = vec![];
+            let mask = mask.unwrap_or(0);
+            for field in 0..PATTERN_FIELDS as usize {
+                if mask & (1 << field) == 0 {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some((start, count)) if *start + *count as usize == FIELDS_START + field => {
+                        *count += 1
+                    }
+                    _ => runs.push((FIELDS_START + field, 1)),
+                }
+            }
+            runs
+        });
+        Ok(AePatternTable { masks, runs })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read alignment encoding patterns {}: {}", path, e))?;
+        let file: AePatternFile = toml::from_str(&content).map_err(|e| {
+            anyhow!(
+                "Failed to parse alignment encoding patterns {}: {}",
+                path,
+                e
+            )
+        })?;
+        let mut masks = [None; FIELD_CODES];
+        for (code, fields) in file.codes {
+            let index = code
+                .parse::<usize>()
+                .ok()
+                .filter(|&c| c < FIELD_CODES)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Pattern codes go from 0 to {}, not {}",
+                        FIELD_CODES - 1,
+                        code
+                    )
+                })?;
+            let mut mask = 0u8;
+            for field in fields {
+                if field >= PATTERN_FIELDS {
+                    bail!(
+                        "Code {} covers field {}, past the first {}",
+                        code,
+                        field,
+                        PATTERN_FIELDS
+                    );
+                }
+                mask |= 1 << field;
+            }
+            masks[index] = Some(mask);
+        }
+        Self::new(masks)
+    }
+
+    /// The table as a file `from_file` reads.
+    pub(crate) fn to_toml(&self) -> String {
+        let mut toml = String::from("[codes]\n");
+        for (code, mask) in self.masks.iter().enumerate() {
+            if let Some(mask) = mask {
+                let fields: Vec<String> = (0..PATTERN_FIELDS)
+                    .filter(|f| mask & (1 << f) != 0)
+                    .map(|f| f.to_string())
+                    .collect();
+                toml.push_str(&format!("{} = [{}]\n", code, fields.join(", ")));
+            }
+        }
+        toml
+    }
+
+    pub(crate) fn masks(&self) -> &[Option<u8>; FIELD_CODES] {
+        &self.masks
+    }
+
+    /// The code of the fields in `mask`, or the fallback if no pattern
+    /// matches them exactly.
+    pub(crate) fn code_of(&self, mask: Option<u8>) -> u8 {
+        mask.and_then(|mask| self.masks.iter().position(|&m| m == Some(mask)))
+            .map_or(FALLBACK_PATTERN, |code| code as u8)
+    }
+
+    /// The runs of slots of objects whose TIB has a field pattern `code`.
+    pub(crate) fn runs(&self, code: u8) -> &[(usize, u64)] {
+        &self.runs[code as usize]
+    }
+}
+
+/// The mask of the reference fields in `runs` of byte offset and slots, or
+/// `None` if some are past the first [`PATTERN_FIELDS`] fields.
+pub(crate) fn field_mask(runs: impl Iterator<Item = (u64, u64)>) -> Option<u8> {
+    let mut mask = 0u8;
+    for (offset, count) in runs {
+        let first_field = (offset >> 3) - FIELDS_START as u64;
+        if first_field + count > PATTERN_FIELDS {
+            return None;
+        }
+        for field in first_field..first_field + count {
+            mask |= 1 << field;
+        }
+    }
+    Some(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ae_pattern_table() {
+        let table = AePatternTable::default();
+        assert_eq!(table.code_of(Some(0b1110)), 2);
+        assert_eq!(table.runs(2), &[(3, 3)]);
+        assert_eq!(table.code_of(Some(0b101)), FALLBACK_PATTERN);
+        assert_eq!(table.code_of(None), FALLBACK_PATTERN);
+        assert_eq!(field_mask([(24, 3)].into_iter()), Some(0b1110));
+        assert_eq!(field_mask([(16, 1), (64, 2)].into_iter()), None);
+
+        let path = std::env::temp_dir().join("test_ae_pattern_table.toml");
+        std::fs::write(&path, "[codes]\n0 = [0, 2]\n3 = [5, 6]\n").unwrap();
+        let table = AePatternTable::from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(table.code_of(Some(0b101)), 0);
+        assert_eq!(table.runs(0), &[(2, 1), (4, 1)]);
+        assert_eq!(table.runs(3), &[(7, 2)]);
+        assert_eq!(table.code_of(Some(0)), FALLBACK_PATTERN);
+        std::fs::write(&path, table.to_toml()).unwrap();
+        assert_eq!(
+            AePatternTable::from_file(path.to_str().unwrap()).unwrap(),
+            table
+        );
+        std::fs::write(&path, "[codes]\n6 = [0]\n").unwrap();
+        assert!(AePatternTable::from_file(path.to_str().unwrap()).is_err());
+        std::fs::write(&path, "[codes]\n0 = [1]\n1 = [1]\n").unwrap();
+        assert!(AePatternTable::from_file(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
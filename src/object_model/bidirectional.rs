@@ -1,28 +1,59 @@
-use std::alloc::{self, Layout};
-use std::collections::HashMap;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::ptr;
-use std::sync::Mutex;
+use std::sync::Arc;
 
+use crate::progress::Progress;
+use crate::util::bump::BumpAllocator;
+use crate::util::typed_obj::{Address, ObjectReference};
 use crate::{HeapDump, HeapObject, ObjectModel};
 
-use super::{HasTibType, Header, TibType};
+use super::object_map::ObjectTable;
+use super::tib_cache::{first_of_each_klass, TibCache, TIB_ARENA};
+use super::{
+    CompactionStats, HasTibType, Header, ObjectMove, TibRecord, TibType, FALLBACK_PATTERN,
+};
 
 pub struct BidirectionalObjectModel<const HEADER: bool> {
     forwarding: HashMap<u64, u64>,
-    objects: Vec<u64>,
-    roots: Vec<u64>,
-    object_sizes: HashMap<u64, u64>,
+    objects: ObjectTable,
+    roots: Arc<[u64]>,
+    /// Address ranges of the spaces of the heapdump, objects never move
+    /// across them when compacting
+    spaces: Vec<Range<u64>>,
+    /// Objects that compaction must leave in place
+    pinned: HashSet<u64>,
 }
 
 impl<const HEADER: bool> BidirectionalObjectModel<HEADER> {
     pub fn new() -> Self {
         BidirectionalObjectModel {
             forwarding: HashMap::new(),
-            objects: vec![],
-            roots: vec![],
-            object_sizes: HashMap::new(),
+            objects: ObjectTable::new(false),
+            roots: Arc::new([]),
+            spaces: vec![],
+            pinned: HashSet::new(),
         }
     }
+
+    /// Keeps the objects in side metadata rather than a list and a map,
+    /// see `--object-map`.
+    pub fn with_object_map(mut self, object_map: bool) -> Self {
+        self.objects = ObjectTable::new(object_map);
+        self
+    }
+
+    /// Bytes occupied by `o` in the bidirectional layout, from the header to
+    /// the last reference slot. Whatever followed the slots in the original
+    /// object (non-reference fields, or the padding of arrays) is not kept.
+    fn extent(o: ObjectReference) -> u64 {
+        let mut end = o.to_address() + 16;
+        Self::scan_object(o, |slots, count| {
+            end = slots + count * 8;
+        });
+        end - o.to_address()
+    }
 }
 
 impl<const HEADER: bool> Default for BidirectionalObjectModel<HEADER> {
@@ -32,15 +63,25 @@ impl<const HEADER: bool> Default for BidirectionalObjectModel<HEADER> {
 }
 
 fn alloc_tib(tib: impl FnOnce() -> Tib) -> &'static Tib {
-    unsafe {
-        let storage = alloc::alloc(Layout::new::<Tib>()) as *mut Tib;
-        ptr::write(storage, tib());
-        storage.as_ref().unwrap()
-    }
+    TIB_ARENA
+        .lock()
+        .unwrap()
+        .alloc(tib(), std::mem::align_of::<Tib>() as u64, 0)
 }
 
 lazy_static! {
-    static ref TIBS: Mutex<HashMap<u64, &'static Tib>> = Mutex::new(HashMap::new());
+    static ref TIBS: TibCache<Tib> = TibCache::new();
+}
+
+/// Creates the TIBs of the klasses of `objects` that do not have one yet.
+fn restore_klass_tibs<'a>(objects: impl Iterator<Item = &'a HeapObject>) {
+    for object in first_of_each_klass(objects).into_values() {
+        if object.objarray_length.is_some() {
+            Tib::objarray(object.klass);
+        } else {
+            Tib::non_objarray(object.klass, object);
+        }
+    }
 }
 
 #[repr(C)]
@@ -70,9 +111,7 @@ impl Tib {
     pub(crate) const NUMREFS_BYTE_OFFSET: u8 = 2;
 
     fn insert_with_cache(klass: u64, tib: impl FnOnce() -> Tib) -> &'static Tib {
-        let mut tibs = TIBS.lock().unwrap();
-        tibs.entry(klass).or_insert_with(|| alloc_tib(tib));
-        tibs.get(&klass).unwrap()
+        TIBS.get_or_insert_with(klass, || alloc_tib(tib))
     }
 
     fn objarray(klass: u64) -> &'static Tib {
@@ -96,62 +135,63 @@ impl Tib {
         }
     }
 
-    unsafe fn scan_object_fallback<F>(o: u64, mut callback: F)
-    where
-        F: FnMut(*mut u64, u64),
-    {
+    unsafe fn slots_fallback(o: ObjectReference) -> Option<(Address, u64)> {
         let tib_ptr = BidirectionalObjectModel::<false>::get_tib(o);
         if tib_ptr.is_null() {
-            panic!("Object 0x{:x} has a null tib pointer", { o });
+            panic!("Object {} has a null tib pointer", o);
         }
         let tib: &Tib = &*tib_ptr;
+        let start = o.to_address();
         match tib.ttype {
             TibType::ObjArray => {
-                let objarray_length = *((o as *mut u64).wrapping_add(2) as *const u64);
-                callback((o as *mut u64).wrapping_add(3), objarray_length);
-            }
-            TibType::Ordinary => {
-                callback((o as *mut u64).wrapping_add(2), tib.num_refs);
+                let objarray_length = start.shift::<u64>(2).load::<u64>();
+                Some((start.shift::<u64>(3), objarray_length))
             }
+            TibType::Ordinary => Some((start.shift::<u64>(2), tib.num_refs)),
             TibType::InstanceMirror => {
                 unreachable!("Instance mirror shouldn't be necessary for bidirectional")
             }
         }
     }
 
-    unsafe fn scan_object_header<F>(o: u64, mut callback: F)
-    where
-        F: FnMut(*mut u64, u64),
-    {
+    unsafe fn slots_header(o: ObjectReference) -> Option<(Address, u64)> {
         let header = Header::load(o);
         let status_byte = header.get_byte(Self::STATUS_BYTE_OFFSET);
+        let start = o.to_address();
         match status_byte {
-            0 => {
-                // no ref
-            }
+            // no ref
+            0 => None,
             1 => {
                 let num_refs = header.get_byte(Self::NUMREFS_BYTE_OFFSET);
-                callback((o as *mut u64).wrapping_add(2), num_refs as u64);
+                Some((start.shift::<u64>(2), num_refs as u64))
             }
             2 => {
-                let objarray_length = *((o as *mut u64).wrapping_add(2) as *const u64);
-                callback((o as *mut u64).wrapping_add(3), objarray_length);
-            }
-            u8::MAX => Self::scan_object_fallback(o, callback),
-            _ => {
-                unreachable!()
+                let objarray_length = start.shift::<u64>(2).load::<u64>();
+                Some((start.shift::<u64>(3), objarray_length))
             }
+            // Only `u8::MAX` is ever encoded, but a corrupted status must not
+            // make the scan read arbitrary memory, so fall back to the TIB,
+            // which is always correct.
+            _ => Self::slots_fallback(o),
         }
     }
 
-    unsafe fn scan_object<const HEADER: bool, F>(o: u64, callback: F)
-    where
-        F: FnMut(*mut u64, u64),
-    {
+    /// The slots of `o`, which the bidirectional layout keeps together, as
+    /// the first slot and their number.
+    unsafe fn slots<const HEADER: bool>(o: ObjectReference) -> Option<(Address, u64)> {
         if HEADER {
-            Self::scan_object_header(o, callback);
+            Self::slots_header(o)
         } else {
-            Self::scan_object_fallback(o, callback);
+            Self::slots_fallback(o)
+        }
+    }
+
+    unsafe fn scan_object<const HEADER: bool, F>(o: ObjectReference, mut callback: F)
+    where
+        F: FnMut(Address, u64),
+    {
+        if let Some((first, count)) = Self::slots::<HEADER>(o) {
+            callback(first, count);
         }
     }
 
@@ -185,25 +225,25 @@ impl<const HEADER: bool> ObjectModel for BidirectionalObjectModel<HEADER> {
     fn reset(&mut self) {
         self.objects.clear();
         self.forwarding.clear();
-        self.roots.clear();
-        self.object_sizes.clear();
+        self.roots = Arc::new([]);
+        self.spaces.clear();
+        self.pinned.clear();
     }
 
     fn restore_tibs(&mut self, heapdump: &HeapDump) -> usize {
-        let before_size = TIBS.lock().unwrap().len();
-        for object in &heapdump.objects {
-            let is_objarray = object.objarray_length.is_some();
-            if is_objarray {
-                let _tib = Tib::objarray(object.klass);
-            } else if object.instance_mirror_start.is_none() {
-                let _tib = Tib::non_objarray(object.klass, object);
-            };
-        }
-        let after_size = TIBS.lock().unwrap().len();
+        let before_size = TIBS.len();
+        restore_klass_tibs(
+            heapdump
+                .objects
+                .iter()
+                .filter(|o| o.instance_mirror_start.is_none()),
+        );
+        let after_size = TIBS.len();
         after_size - before_size
     }
 
     fn restore_objects(&mut self, heapdump: &HeapDump) {
+        self.spaces = heapdump.spaces.iter().map(|s| s.start..s.end).collect();
         // First pass: calculate forwarding table
         for object in &heapdump.objects {
             let start = object.start;
@@ -227,19 +267,49 @@ impl<const HEADER: bool> ObjectModel for BidirectionalObjectModel<HEADER> {
             self.forwarding.insert(start, new_start);
             // println!("Forwarding 0x{:x} -> 0x{:x}", start, new_start);
         }
-        for o in self.forwarding.values() {
-            self.objects.push(*o);
-        }
+        self.roots = heapdump
+            .all_roots()
+            .map(|r| *self.forwarding.get(&r.objref).unwrap())
+            .collect();
 
-        for r in &heapdump.roots {
-            self.roots.push(*self.forwarding.get(&r.objref).unwrap());
-        }
-
-        // Second pass: deserilize object and update edges
+        let mut objects = Vec::with_capacity(heapdump.objects.len());
         for object in &heapdump.objects {
+            let new_start = *self.forwarding.get(&object.start).unwrap();
+            objects.push((new_start, object.size));
+            if object.pinned {
+                self.pinned.insert(new_start);
+            }
+        }
+        self.objects.set(objects);
+
+        // Second pass: deserilize object and update edges. Objects never
+        // overlap, so they can be written out in parallel.
+        let forwarding = &self.forwarding;
+        let progress = Progress::new(
+            "Restoring objects",
+            "objects",
+            Some(heapdump.objects.len() as u64),
+        );
+        // Every TIB is created before the write-out, so that the threads only
+        // look them up. Mirrors get a TIB of their own, in heapdump order.
+        restore_klass_tibs(
+            heapdump
+                .objects
+                .iter()
+                .filter(|o| o.instance_mirror_start.is_none()),
+        );
+        let mirror_tibs: HashMap<u64, &'static Tib> = heapdump
+            .objects
+            .iter()
+            .filter(|o| o.instance_mirror_start.is_some())
+            .map(|o| (o.start, Tib::non_objarray(o.klass, o)))
+            .collect();
+        heapdump.objects.par_iter().for_each(|object| {
             let is_objarray = object.objarray_length.is_some();
             let tib = if is_objarray {
                 Tib::objarray(object.klass)
+            } else if let Some(&tib) = mirror_tibs.get(&object.start) {
+                tib
             } else {
                 Tib::non_objarray(object.klass, object)
             };
@@ -249,84 +319,321 @@ impl<const HEADER: bool> ObjectModel for BidirectionalObjectModel<HEADER> {
             let header = tib.encode_header();
             // We need to leak this, so the underlying memory won't be collected
             let tib_ptr = tib as *const Tib;
-            let new_start = *self.forwarding.get(&object.start).unwrap();
+            let new_start =
+                ObjectReference::from_raw_unchecked(*forwarding.get(&object.start).unwrap());
+            let start = new_start.to_address();
+            if HEADER {
+                header.store(new_start);
+            }
             unsafe {
-                if HEADER {
-                    header.store(new_start);
-                }
-                std::ptr::write::<u64>((new_start + 8) as *mut u64, tib_ptr as u64);
+                start.shift::<u64>(1).store(tib_ptr as u64);
             }
             // Write out array length for obj array
             if let Some(l) = object.objarray_length {
                 unsafe {
-                    std::ptr::write::<u64>((new_start + 16) as *mut u64, l);
+                    start.shift::<u64>(2).store(l);
                 }
             }
             // Write out each non-zero ref field
-            let mut ref_cursor: u64 = if is_objarray {
-                new_start + 24
+            let mut ref_cursor = if is_objarray {
+                start.shift::<u64>(3)
             } else {
-                new_start + 16
+                start.shift::<u64>(2)
             };
             for e in &object.edges {
                 unsafe {
                     let new_referent = if e.objref == 0 {
                         0
                     } else {
-                        *self.forwarding.get(&e.objref).unwrap()
+                        *forwarding.get(&e.objref).unwrap()
                     };
-                    std::ptr::write::<u64>(ref_cursor as *mut u64, new_referent);
-                    ref_cursor += 8;
+                    ref_cursor.store(new_referent);
+                    ref_cursor = ref_cursor.shift::<u64>(1);
                 }
             }
-            debug_assert_eq!(ref_cursor, object.start + object.size);
-            self.object_sizes.insert(new_start, object.size);
-        }
+            debug_assert_eq!(ref_cursor.as_u64(), object.start + object.size);
+            progress.add(1);
+        });
+        progress.finish();
     }
 
-    fn scan_object<F>(o: u64, callback: F)
+    fn scan_object<F>(o: ObjectReference, callback: F)
     where
-        F: FnMut(*mut u64, u64),
+        F: FnMut(Address, u64),
     {
         unsafe { Tib::scan_object::<HEADER, _>(o, callback) }
     }
 
+    fn num_edges(o: ObjectReference) -> u64 {
+        unsafe { Tib::slots::<HEADER>(o) }.map_or(0, |(_, count)| count)
+    }
+
+    fn slots_range(o: ObjectReference) -> impl Iterator<Item = Address> {
+        let (first, count) = unsafe { Tib::slots::<HEADER>(o) }.unwrap_or((Address::ZERO, 0));
+        (0..count as usize).map(move |i| first.shift::<u64>(i))
+    }
+
     fn roots(&self) -> &[u64] {
         &self.roots
     }
 
-    fn objects(&self) -> &[u64] {
-        &self.objects
+    fn shared_roots(&self) -> Arc<[u64]> {
+        self.roots.clone()
+    }
+
+    fn objects_iter(&self) -> Box<dyn Iterator<Item = ObjectReference> + '_> {
+        self.objects.iter()
+    }
+
+    fn object_size(&self, o: ObjectReference) -> Option<u64> {
+        self.objects.size(o)
     }
 
-    fn object_sizes(&self) -> &HashMap<u64, u64> {
-        &self.object_sizes
+    fn num_objects(&self) -> usize {
+        self.objects.len()
     }
 
-    unsafe fn is_objarray(o: u64) -> bool {
+    unsafe fn is_objarray(o: ObjectReference) -> bool {
         let tib_ptr = Self::get_tib(o);
         if tib_ptr.is_null() {
-            panic!("Object 0x{:x} has a null tib pointer", { o });
+            panic!("Object {} has a null tib pointer", o);
         }
         let tib: &Tib = &*tib_ptr;
         matches!(tib.ttype, TibType::ObjArray)
     }
 
-    fn get_tib(o: u64) -> *const Self::Tib {
-        unsafe { *((o as *mut u64).wrapping_add(1) as *const *const Tib) }
+    fn get_tib(o: ObjectReference) -> *const Self::Tib {
+        unsafe { o.to_address().shift::<u64>(1).load::<*const Tib>() }
     }
 
-    fn tib_lookup_required(o: u64) -> bool {
+    fn tib_lookup_required(o: ObjectReference) -> bool {
         if HEADER {
             let header = Header::load(o);
             let status_byte = header.get_byte(Tib::STATUS_BYTE_OFFSET);
             // Too many refs, so the number of refs cannot be encoded in the
-            // header
-            status_byte == u8::MAX
+            // header. Other unknown statuses are scanned through the TIB too.
+            status_byte > StatusByte::ObjArray as u8
         } else {
             // If the number of refs is not encoded in the header
             // A tib lookup is always required
             true
         }
     }
+
+    fn tib_records() -> Vec<TibRecord> {
+        TIBS.entries()
+            .into_iter()
+            .map(|(klass, tib)| TibRecord {
+                klass,
+                tib: tib as *const Tib as u64,
+                ttype: tib.ttype as u8,
+                pattern: FALLBACK_PATTERN,
+                // The references follow the header and the TIB pointer
+                blocks: match tib.ttype {
+                    TibType::Ordinary if tib.num_refs > 0 => vec![(16, tib.num_refs)],
+                    _ => vec![],
+                },
+            })
+            .collect()
+    }
+
+    fn compact(&mut self, mark_sense: u8, record_moves: bool) -> Option<CompactionStats> {
+        // A Lisp-2 style sliding compaction: compute the new address of every
+        // live object, forward all slots while the objects are still in
+        // place, and then move them in address order, which never overwrites
+        // an object that has yet to move. Pinned objects stay where they
+        // are, and the objects after them slide towards their end instead.
+        let mut stats = CompactionStats {
+            moves: record_moves.then(Vec::new),
+            ..Default::default()
+        };
+        let mut live: Vec<ObjectReference> = self
+            .objects
+            .iter()
+            .filter(|&o| Header::load(o).get_mark_byte() == mark_sense)
+            .collect();
+        live.sort_unstable();
+        stats.live_objects = live.len() as u64;
+        let mut forwarding: HashMap<ObjectReference, ObjectReference> =
+            HashMap::with_capacity(live.len());
+        let mut extents = Vec::with_capacity(live.len());
+        let mut i = 0;
+        for space in &self.spaces {
+            // Sliding never moves an object up, so there is always room,
+            // even for objects that end past their space
+            let mut to_space = BumpAllocator::new(space.start, u64::MAX);
+            let mut used_end = space.start;
+            while i < live.len() && space.contains(&live[i].to_raw()) {
+                let o = live[i];
+                let extent = Self::extent(o);
+                if self.pinned.contains(&o.to_raw()) {
+                    // The cursor has not passed `o`, for the same reason
+                    stats.pinned_objects += 1;
+                    stats.pinned_bytes += extent;
+                    to_space.skip_to(o.to_raw());
+                }
+                let new = to_space.alloc(extent, 1).unwrap();
+                forwarding.insert(o, ObjectReference::from_raw_unchecked(new));
+                extents.push(extent);
+                used_end = o.to_raw() + extent;
+                i += 1;
+            }
+            stats.bytes_fragmented += to_space.stats.skipped_bytes;
+            stats.bytes_reclaimed += used_end - to_space.cursor();
+        }
+        assert_eq!(i, live.len(), "Live objects outside of any space");
+
+        let forward = |referent: u64, stats: &mut CompactionStats| -> u64 {
+            let Some(referent) = ObjectReference::from_raw(referent) else {
+                return 0;
+            };
+            let new = *forwarding
+                .get(&referent)
+                .unwrap_or_else(|| panic!("{} is reachable but not marked", referent));
+            if new != referent {
+                stats.slots_updated += 1;
+            }
+            new.to_raw()
+        };
+        for &o in &live {
+            Self::scan_object(o, |slots, count| {
+                for j in 0..count as usize {
+                    let slot = slots.shift::<u64>(j);
+                    unsafe { slot.store(forward(slot.load::<u64>(), &mut stats)) };
+                }
+                stats.slots_scanned += count;
+            });
+        }
+        self.roots = self.roots.iter().map(|&r| forward(r, &mut stats)).collect();
+
+        for (&o, &extent) in live.iter().zip(&extents) {
+            let new = forwarding[&o];
+            if new != o {
+                unsafe {
+                    ptr::copy(
+                        o.to_address().to_ptr::<u8>(),
+                        new.to_address().to_mut_ptr::<u8>(),
+                        extent as usize,
+                    )
+                };
+                stats.objects_moved += 1;
+                stats.bytes_moved += extent;
+                if let Some(moves) = &mut stats.moves {
+                    moves.push(ObjectMove {
+                        from: o.to_raw(),
+                        to: new.to_raw(),
+                        bytes: extent,
+                    });
+                }
+            }
+        }
+
+        for new in self.forwarding.values_mut() {
+            *new = forwarding
+                .get(&ObjectReference::from_raw_unchecked(*new))
+                .map_or(0, |o| o.to_raw());
+        }
+        self.forwarding.retain(|_, new| *new != 0);
+        self.objects.set(
+            live.iter()
+                .zip(&extents)
+                .map(|(o, &extent)| (forwarding[o].to_raw(), extent))
+                .collect(),
+        );
+        self.pinned
+            .retain(|&o| forwarding.contains_key(&ObjectReference::from_raw_unchecked(o)));
+        Some(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::generated_src::{NormalEdge, RootEdge, Space};
+
+    /// Away from the heaps of the other tests, so that nothing they leave
+    /// behind is mistaken for an object
+    const BASE: u64 = 0x20000000000 + (1 << 32);
+
+    #[test]
+    fn test_compact_around_pinned() {
+        let _guard = crate::trace::conformance::HEAP
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        // Five objects with one slot each: a live one, a dead one, a pinned
+        // live one, a dead one and a live one, each pointing to the next
+        // live one
+        let object = |i: u64, next: Option<u64>, pinned| HeapObject {
+            start: BASE + i * 24,
+            klass: 0xdead_0000,
+            size: 24,
+            objarray_length: None,
+            instance_mirror_start: None,
+            instance_mirror_count: None,
+            edges: vec![NormalEdge {
+                slot: BASE + i * 24 + 16,
+                objref: next.map_or(0, |j| BASE + j * 24),
+            }],
+            pinned,
+        };
+        let heapdump = HeapDump {
+            objects: vec![
+                object(0, Some(2), false),
+                object(1, None, false),
+                object(2, Some(4), true),
+                object(3, None, false),
+                object(4, None, false),
+            ],
+            roots: vec![RootEdge { objref: BASE }],
+            spaces: vec![Space {
+                name: "immix".into(),
+                start: BASE,
+                end: BASE + 4096,
+            }],
+            root_sets: vec![],
+            ..Default::default()
+        };
+        heapdump.map_spaces().unwrap();
+        // Unmap even if a check fails, so that later tests can map the heap
+        struct Unmap<'a>(&'a HeapDump);
+        impl Drop for Unmap<'_> {
+            fn drop(&mut self) {
+                self.0.unmap_spaces().unwrap();
+            }
+        }
+        let _unmap = Unmap(&heapdump);
+        let mut object_model = BidirectionalObjectModel::<true>::new();
+        object_model.restore_objects(&heapdump);
+        type Model = BidirectionalObjectModel<true>;
+        let object = |i: u64| ObjectReference::from_raw_unchecked(BASE + i * 24);
+        assert_eq!(Model::num_edges(object(0)), 1);
+        assert_eq!(
+            Model::slots_range(object(0)).collect::<Vec<_>>(),
+            vec![Address::from_raw(BASE + 16)]
+        );
+        for i in [0, 2, 4] {
+            let mut header = Header::load(object(i));
+            header.set_mark_byte(1);
+            header.store(object(i));
+        }
+        let stats = object_model.compact(1, true).unwrap();
+        let slot = |o: u64| unsafe { *((o + 16) as *const u64) };
+        let pinned = BASE + 48;
+        assert_eq!(slot(BASE), pinned);
+        assert_eq!(slot(pinned), BASE + 72);
+        assert_eq!(stats.objects_moved, 1);
+        assert_eq!(
+            stats.moves.unwrap(),
+            [ObjectMove {
+                from: BASE + 96,
+                to: BASE + 72,
+                bytes: 24
+            }]
+        );
+        assert_eq!(stats.slots_updated, 1);
+        assert_eq!(stats.pinned_objects, 1);
+        assert_eq!(stats.pinned_bytes, 24);
+        assert_eq!(stats.bytes_fragmented, 24);
+        assert_eq!(stats.bytes_reclaimed, 24);
+    }
 }
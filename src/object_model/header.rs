@@ -1,3 +1,4 @@
+use crate::util::typed_obj::ObjectReference;
 use std::sync::atomic::{AtomicU8, Ordering};
 
 #[repr(transparent)]
@@ -8,12 +9,12 @@ impl Header {
         Header(0)
     }
 
-    pub fn load(o: u64) -> Self {
-        unsafe { Header(*(o as *mut u64)) }
+    pub fn load(o: ObjectReference) -> Self {
+        unsafe { Header(o.to_address().load::<u64>()) }
     }
 
-    pub fn store(self, o: u64) {
-        unsafe { *(o as *mut u64) = self.0 };
+    pub fn store(self, o: ObjectReference) {
+        unsafe { o.to_address().store(self.0) };
     }
 
     pub fn get_mark_byte(&self) -> u8 {
@@ -24,12 +25,12 @@ impl Header {
         self.set_byte(val, 0);
     }
 
-    pub fn attempt_mark_byte(o: u64, new_byte: u8) -> bool {
+    pub fn attempt_mark_byte(o: ObjectReference, new_byte: u8) -> bool {
         let old_byte = Header::load(o).get_mark_byte();
         if old_byte == new_byte {
             return false;
         }
-        let work = unsafe { &*(o as *const u64 as *const AtomicU8) };
+        let work = unsafe { &*o.to_address().to_ptr::<AtomicU8>() };
         work.compare_exchange(old_byte, new_byte, Ordering::SeqCst, Ordering::SeqCst)
             .is_ok()
     }
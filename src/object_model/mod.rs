@@ -1,5 +1,7 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 
+use crate::util::bump::AllocationStats;
+use crate::util::typed_obj::{Address, ObjectReference};
 use crate::HeapDump;
 
 #[repr(u8)]
@@ -14,27 +16,129 @@ pub trait HasTibType {
     fn get_tib_type(&self) -> TibType;
 }
 
+/// Work done by sliding the live objects of each space towards its start,
+/// see `ObjectModel::compact`.
+#[derive(Debug, Default, Clone)]
+pub struct CompactionStats {
+    pub live_objects: u64,
+    /// Live objects whose address changed
+    pub objects_moved: u64,
+    pub bytes_moved: u64,
+    /// Slots of live objects, each read once to forward its referent
+    pub slots_scanned: u64,
+    /// Non-null slots and roots rewritten to point to a new address
+    pub slots_updated: u64,
+    /// Bytes between the start of each space and its last live byte that
+    /// are no longer in use
+    pub bytes_reclaimed: u64,
+    /// Live objects left in place because they are pinned
+    pub pinned_objects: u64,
+    pub pinned_bytes: u64,
+    /// Free bytes below pinned objects, which sliding cannot reclaim
+    pub bytes_fragmented: u64,
+    /// The objects moved, in the order they were copied, if asked for
+    pub moves: Option<Vec<ObjectMove>>,
+}
+
+/// An object copied from `from` to `to` by `ObjectModel::compact`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectMove {
+    pub from: u64,
+    pub to: u64,
+    pub bytes: u64,
+}
+
+/// Memory taken by the TIBs of every object model so far, and by the
+/// padding that aligned them.
+pub fn tib_allocation_stats() -> AllocationStats {
+    tib_cache::TIB_ARENA.lock().unwrap().stats()
+}
+
+/// Alignment encoding pattern of the TIBs that scanning has to read, which
+/// is all of them for the models without alignment encoding.
+pub(crate) const FALLBACK_PATTERN: u8 = 7;
+
+/// Alignment encoding reads the pattern of a TIB from the bits of its
+/// address below this, so TIBs moved by a multiple of it keep their patterns.
+pub(crate) const TIB_PATTERN_ALIGN: u64 = openjdk::PATTERN_ALIGN;
+
+/// What scanning needs to know about a klass, as `export-tibs` writes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TibRecord {
+    pub(crate) klass: u64,
+    /// Address of the TIB, which the objects of the klass point to
+    pub(crate) tib: u64,
+    pub(crate) ttype: u8,
+    /// Alignment encoding pattern of the address of the TIB
+    pub(crate) pattern: u8,
+    /// Runs of reference fields, as byte offset from the start of the object
+    /// and number of slots. Empty for object arrays.
+    pub(crate) blocks: Vec<(u64, u64)>,
+}
+
 pub trait ObjectModel: Send + 'static {
     type Tib: HasTibType;
     fn restore_tibs(&mut self, heapdump: &HeapDump) -> usize;
     fn restore_objects(&mut self, heapdump: &HeapDump);
-    fn scan_object<F>(o: u64, callback: F)
+    /// Calls back with each run of slots of `o`, as the first slot and the
+    /// number of slots.
+    fn scan_object<F>(o: ObjectReference, callback: F)
     where
-        F: FnMut(*mut u64, u64);
+        F: FnMut(Address, u64);
+    /// Number of slots of `o`, for the statistics that need no more than
+    /// that.
+    fn num_edges(o: ObjectReference) -> u64 {
+        let mut slots = 0;
+        Self::scan_object(o, |_, count| slots += count);
+        slots
+    }
+    /// The slots of `o`, in the order `scan_object` calls back with them.
+    fn slots_range(o: ObjectReference) -> impl Iterator<Item = Address> {
+        let mut runs = vec![];
+        Self::scan_object(o, |first, count| runs.push((first, count)));
+        runs.into_iter()
+            .flat_map(|(first, count)| (0..count as usize).map(move |i| first.shift::<u64>(i)))
+    }
+    /// The root slots, each holding the object a root refers to, or 0.
+    /// Tracers point into it rather than copy it, so it stays a slice of
+    /// bare words.
     fn roots(&self) -> &[u64];
-    fn objects(&self) -> &[u64];
+    /// The same root slots, shared with tracers that hand them to other
+    /// threads. The model replaces its roots rather than writing to them,
+    /// so a tracer holding on to them never sees them change.
+    fn shared_roots(&self) -> Arc<[u64]>;
     fn reset(&mut self);
-    fn object_sizes(&self) -> &HashMap<u64, u64>;
+    /// Every object, in the order restored, or in address order with
+    /// `--object-map`, which keeps no list of them.
+    fn objects_iter(&self) -> Box<dyn Iterator<Item = ObjectReference> + '_>;
+    fn object_size(&self, o: ObjectReference) -> Option<u64>;
+    fn num_objects(&self) -> usize;
     #[allow(clippy::missing_safety_doc)]
-    unsafe fn is_objarray(o: u64) -> bool;
-    fn get_tib(o: u64) -> *const Self::Tib;
-    fn tib_lookup_required(o: u64) -> bool;
+    unsafe fn is_objarray(o: ObjectReference) -> bool;
+    fn get_tib(o: ObjectReference) -> *const Self::Tib;
+    fn tib_lookup_required(o: ObjectReference) -> bool;
+    /// The TIBs cached by klass so far. Instance mirrors get a TIB of their
+    /// own, which is not cached, so they are left out.
+    fn tib_records() -> Vec<TibRecord>;
+    /// Slides the objects marked with `mark_sense` within their space,
+    /// except for pinned ones, and drops everything else from the model.
+    /// Returns `None` if the model cannot relocate objects. The moves are
+    /// only recorded with `record_moves`, as there is one per moved object.
+    fn compact(&mut self, _mark_sense: u8, _record_moves: bool) -> Option<CompactionStats> {
+        None
+    }
 }
 
+mod ae_patterns;
 mod bidirectional;
 mod header;
+mod object_map;
 mod openjdk;
+mod tib_cache;
+pub(crate) use ae_patterns::{ae_patterns, FIELD_CODES, PATTERN_FIELDS};
+pub use ae_patterns::{set_ae_patterns, AePatternTable};
 pub use bidirectional::BidirectionalObjectModel;
 pub use bidirectional::Tib as BidirectionalTib;
 pub use header::Header;
+pub(crate) use openjdk::ae_field_mask;
 pub use openjdk::OpenJDKObjectModel;
@@ -0,0 +1,221 @@
+//! Where the objects of a model are and how big they are. By default that is
+//! a list of addresses and a map of sizes, which cost tens of bytes per
+//! object on top of the heapdump. With `--object-map`, it is side metadata
+//! instead: a bitmap with a bit per word of heap set at the start of every
+//! object, and the sizes of the objects in address order, found by counting
+//! the bits before an object.
+
+use crate::util::typed_obj::ObjectReference;
+use std::collections::{BTreeMap, HashMap};
+
+/// Bytes of heap covered by one bitmap, so that the bitmaps of spaces far
+/// apart need not cover the gap between them
+const LOG_REGION_BYTES: u32 = 22;
+/// Bytes of heap per bit
+const LOG_GRANULE_BYTES: u32 = 3;
+const REGION_WORDS: usize = 1 << (LOG_REGION_BYTES - LOG_GRANULE_BYTES - 6);
+/// Bitmap words per entry of the table of objects before each block
+const LOG_BLOCK_WORDS: u32 = 3;
+
+#[derive(Debug, Clone)]
+struct Region {
+    bits: Vec<u64>,
+    /// Objects of the region before each block of bitmap words
+    blocks: Vec<u32>,
+    /// Sizes of the objects of the region, in address order
+    sizes: Vec<u64>,
+}
+
+impl Region {
+    fn new() -> Self {
+        Region {
+            bits: vec![0; REGION_WORDS],
+            blocks: vec![0; REGION_WORDS >> LOG_BLOCK_WORDS],
+            sizes: vec![],
+        }
+    }
+
+    /// Index of the object starting at granule `i` among the objects of the
+    /// region, if one starts there.
+    fn index(&self, i: usize) -> Option<usize> {
+        let (word, bit) = (i / 64, i % 64);
+        if self.bits[word] & (1 << bit) == 0 {
+            return None;
+        }
+        let block = word >> LOG_BLOCK_WORDS;
+        let before: u32 = self.bits[block << LOG_BLOCK_WORDS..word]
+            .iter()
+            .map(|w| w.count_ones())
+            .sum();
+        let below = (self.bits[word] & ((1 << bit) - 1)).count_ones();
+        Some((self.blocks[block] + before + below) as usize)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ObjectMap {
+    regions: BTreeMap<u64, Region>,
+    len: usize,
+}
+
+impl ObjectMap {
+    /// Builds the map of objects given as `(start, size)`, in any order.
+    pub(crate) fn new(mut objects: Vec<(u64, u64)>) -> Self {
+        objects.sort_unstable();
+        let mut regions: BTreeMap<u64, Region> = BTreeMap::new();
+        for &(start, size) in &objects {
+            assert_eq!(start % (1 << LOG_GRANULE_BYTES), 0, "Unaligned object");
+            let region = regions
+                .entry(start >> LOG_REGION_BYTES)
+                .or_insert_with(Region::new);
+            let i = ((start & ((1 << LOG_REGION_BYTES) - 1)) >> LOG_GRANULE_BYTES) as usize;
+            region.bits[i / 64] |= 1 << (i % 64);
+            region.sizes.push(size);
+        }
+        for region in regions.values_mut() {
+            let mut count = 0;
+            for (block, words) in region.bits.chunks(1 << LOG_BLOCK_WORDS).enumerate() {
+                region.blocks[block] = count;
+                count += words.iter().map(|w| w.count_ones()).sum::<u32>();
+            }
+        }
+        ObjectMap {
+            regions,
+            len: objects.len(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn size(&self, o: u64) -> Option<u64> {
+        let region = self.regions.get(&(o >> LOG_REGION_BYTES))?;
+        if !o.is_multiple_of(1 << LOG_GRANULE_BYTES) {
+            return None;
+        }
+        let i = ((o & ((1 << LOG_REGION_BYTES) - 1)) >> LOG_GRANULE_BYTES) as usize;
+        region.index(i).map(|index| region.sizes[index])
+    }
+
+    /// The objects in address order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.regions.iter().flat_map(|(&r, region)| {
+            let base = r << LOG_REGION_BYTES;
+            region.bits.iter().enumerate().flat_map(move |(w, &word)| {
+                let mut bits = word;
+                std::iter::from_fn(move || {
+                    if bits == 0 {
+                        return None;
+                    }
+                    let bit = bits.trailing_zeros() as u64;
+                    bits &= bits - 1;
+                    Some(base + ((w as u64 * 64 + bit) << LOG_GRANULE_BYTES))
+                })
+            })
+        })
+    }
+
+    /// Bytes the map takes.
+    pub(crate) fn metadata_bytes(&self) -> usize {
+        self.regions
+            .values()
+            .map(|r| r.bits.len() * 8 + r.blocks.len() * 4 + r.sizes.len() * 8)
+            .sum()
+    }
+}
+
+/// The objects of a model, in either representation.
+#[derive(Debug, Default)]
+pub(crate) struct ObjectTable {
+    objects: Vec<ObjectReference>,
+    sizes: HashMap<ObjectReference, u64>,
+    /// Set with `--object-map`, in place of the other two
+    map: Option<ObjectMap>,
+    use_map: bool,
+}
+
+impl ObjectTable {
+    pub(crate) fn new(use_map: bool) -> Self {
+        ObjectTable {
+            use_map,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.objects.clear();
+        self.sizes.clear();
+        self.map = None;
+    }
+
+    /// Replaces the objects with `objects`, as `(start, size)`.
+    pub(crate) fn set(&mut self, objects: Vec<(u64, u64)>) {
+        self.clear();
+        if self.use_map {
+            let map = ObjectMap::new(objects);
+            info!(
+                "Object map of {} objects takes {} bytes",
+                map.len(),
+                map.metadata_bytes()
+            );
+            self.map = Some(map);
+        } else {
+            let objects = objects
+                .into_iter()
+                .map(|(o, size)| (ObjectReference::from_raw_unchecked(o), size));
+            self.objects = objects.clone().map(|(o, _)| o).collect();
+            self.sizes = objects.collect();
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = ObjectReference> + '_> {
+        match &self.map {
+            Some(map) => Box::new(map.iter().map(ObjectReference::from_raw_unchecked)),
+            None => Box::new(self.objects.iter().copied()),
+        }
+    }
+
+    pub(crate) fn size(&self, o: ObjectReference) -> Option<u64> {
+        match &self.map {
+            Some(map) => map.size(o.to_raw()),
+            None => self.sizes.get(&o).copied(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match &self.map {
+            Some(map) => map.len(),
+            None => self.objects.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_map() {
+        // Across words, blocks and regions, out of order
+        let objects = vec![
+            (0x1000, 24),
+            (0x10_0000_0000, 16),
+            (0x1018, 40),
+            (0x1000 + 64 * 8 * 9, 32),
+            (0x4f_fff8, 8),
+        ];
+        let map = ObjectMap::new(objects.clone());
+        assert_eq!(map.len(), 5);
+        let mut sorted: Vec<u64> = objects.iter().map(|&(o, _)| o).collect();
+        sorted.sort_unstable();
+        assert_eq!(map.iter().collect::<Vec<_>>(), sorted);
+        for &(o, size) in &objects {
+            assert_eq!(map.size(o), Some(size));
+        }
+        assert_eq!(map.size(0x1008), None);
+        assert_eq!(map.size(0x1001), None);
+        assert_eq!(map.size(0x20_0000_0000), None);
+        assert_eq!(map.metadata_bytes(), 3 * (64 * 1024 + 4 * 1024) + 5 * 8);
+    }
+}
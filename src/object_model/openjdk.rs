@@ -1,16 +1,22 @@
 use crate::constants::*;
+use crate::progress::Progress;
+use crate::util::typed_obj::{Address, ObjectReference};
 use crate::{HeapDump, HeapObject, ObjectModel};
-use fixedbitset::FixedBitSet;
-use std::alloc::{self, Layout};
+use rayon::prelude::*;
 use std::collections::HashMap;
-use std::mem::size_of;
-use std::ptr;
+#[cfg(feature = "object_maps")]
 use std::sync::Mutex;
+#[cfg(not(feature = "object_maps"))]
+use std::sync::RwLock;
+use std::sync::Arc;
 
-use super::{HasTibType, TibType};
+use super::ae_patterns::{ae_patterns, field_mask, REF_ARRAY_CODE};
+use super::object_map::ObjectTable;
+use super::tib_cache::{first_of_each_klass, TibCache, TIB_ARENA};
+use super::{HasTibType, TibRecord, TibType, FALLBACK_PATTERN};
 
 lazy_static! {
-    static ref TIBS: Mutex<HashMap<u64, &'static Tib>> = Mutex::new(HashMap::new());
+    static ref TIBS: TibCache<Tib> = TibCache::new();
 }
 
 #[repr(C)]
@@ -27,37 +33,31 @@ impl HasTibType for Tib {
     }
 }
 
-#[repr(u8)]
-#[derive(Copy, Debug, Clone)]
+/// What the alignment of a TIB says about the objects of its klass
+#[derive(Copy, Debug, Clone, PartialEq)]
 enum AlignmentEncodingPattern {
-    Fallback = 7,
-    RefArray = 6,
-    NoRef = 0,
-    Ref0 = 1,
-    Ref1_2_3 = 2,
-    Ref4_5_6 = 3,
-    Ref2 = 4,
-    Ref0_1 = 5,
+    Fallback,
+    RefArray,
+    /// A pattern of reference fields of the `AePatternTable`, by code
+    Fields(u8),
 }
 
 impl From<AlignmentEncodingPattern> for u8 {
     fn from(value: AlignmentEncodingPattern) -> Self {
-        value as u8
+        match value {
+            AlignmentEncodingPattern::Fallback => FALLBACK_PATTERN,
+            AlignmentEncodingPattern::RefArray => REF_ARRAY_CODE,
+            AlignmentEncodingPattern::Fields(code) => code,
+        }
     }
 }
 
 impl From<u8> for AlignmentEncodingPattern {
     fn from(value: u8) -> Self {
         match value {
-            0 => Self::NoRef,
-            1 => Self::Ref0,
-            2 => Self::Ref1_2_3,
-            3 => Self::Ref4_5_6,
-            4 => Self::Ref2,
-            5 => Self::Ref0_1,
-            6 => Self::RefArray,
-            7 => Self::Fallback,
-            _ => unreachable!(),
+            FALLBACK_PATTERN => Self::Fallback,
+            REF_ARRAY_CODE => Self::RefArray,
+            code => Self::Fields(code),
         }
     }
 }
@@ -68,7 +68,6 @@ impl AlignmentEncoding {
     const FIELD_WIDTH: u32 = 3;
     const MAX_ALIGN_WORDS: u32 = 1 << Self::FIELD_WIDTH;
     const FIELD_SHIFT: u32 = LOG_BYTES_IN_WORD as u32;
-    const ALIGNMENT_INCREMENT: u32 = 1 << Self::FIELD_SHIFT;
     const KLASS_MASK: u32 = (Self::MAX_ALIGN_WORDS - 1) << Self::FIELD_SHIFT;
     const VERBOSE: bool = false;
 
@@ -81,53 +80,40 @@ impl AlignmentEncoding {
         debug_assert_eq!(inverse, align_code as u8);
         ret
     }
+}
 
-    fn _get_padded_size(size: usize, align_code: Option<u8>) -> usize {
-        let padding: usize = if align_code.is_some() {
-            (Self::MAX_ALIGN_WORDS << Self::FIELD_SHIFT) as usize
-        } else {
-            0
-        };
-        size + padding
-    }
+pub(super) const PATTERN_ALIGN: u64 =
+    AlignmentEncoding::MAX_ALIGN_WORDS as u64 * BYTES_IN_WORD as u64;
 
-    fn get_padded_word_size(word_size: usize, align_code: Option<u8>) -> usize {
-        let padding: usize = if align_code.is_some() {
-            (Self::MAX_ALIGN_WORDS) as usize
-        } else {
-            0
-        };
-        word_size + padding
+fn alloc_tib(tib: impl FnOnce() -> Tib, align_code: Option<u8>) -> &'static Tib {
+    // The code goes in the bits of the address just above the word offset
+    let (align, offset) = match align_code {
+        Some(a) => (PATTERN_ALIGN, (a as u64) << AlignmentEncoding::FIELD_SHIFT),
+        None => (BYTES_IN_WORD as u64, 0),
+    };
+    let tib = TIB_ARENA.lock().unwrap().alloc(tib(), align, offset);
+    let region = tib as *const Tib as usize;
+    if AlignmentEncoding::VERBOSE {
+        eprintln!(
+            "Tib: region = 0x{:x}, tib code = {}, requested = {:?}",
+            region,
+            u8::from(AlignmentEncoding::get_tib_code_for_region(region)),
+            align_code
+        );
     }
+    debug_assert!(align_code
+        .is_none_or(|a| u8::from(AlignmentEncoding::get_tib_code_for_region(region)) == a));
+    tib
 }
 
-fn alloc_tib(tib: impl FnOnce() -> Tib, align_code: Option<u8>) -> &'static Tib {
-    unsafe {
-        let word_size = (size_of::<Tib>() + (BYTES_IN_WORD - 1)) & (!(BYTES_IN_WORD - 1));
-        let padded_word_size = AlignmentEncoding::get_padded_word_size(word_size, align_code);
-        let layout =
-            Layout::from_size_align(padded_word_size * BYTES_IN_WORD, BYTES_IN_WORD).unwrap();
-        let storage = alloc::alloc(layout) as *mut Tib;
-        let mut region = storage as usize;
-        let limit = region + padded_word_size * BYTES_IN_WORD;
-        if let Some(a) = align_code {
-            while AlignmentEncoding::get_tib_code_for_region(region) as u8 != a {
-                region += AlignmentEncoding::ALIGNMENT_INCREMENT as usize;
-                debug_assert!(region <= limit);
-            }
-        }
-        if AlignmentEncoding::VERBOSE {
-            eprintln!(
-                "Tib: region = 0x{:x}, tib code = {}, requested = {:?}",
-                region,
-                AlignmentEncoding::get_tib_code_for_region(region) as u8,
-                align_code
-            );
+/// Creates the TIBs of the klasses of `objects` that do not have one yet.
+fn restore_klass_tibs<'a, const AE: bool>(objects: impl Iterator<Item = &'a HeapObject>) {
+    for object in first_of_each_klass(objects).into_values() {
+        if object.objarray_length.is_some() {
+            Tib::objarray::<AE>(object.klass);
+        } else {
+            Tib::non_objarray::<AE>(object.klass, object);
         }
-        debug_assert!(layout.size() >= size_of::<Tib>());
-        let storage = region as *mut Tib;
-        ptr::write(storage, tib());
-        storage.as_ref().unwrap()
     }
 }
 
@@ -137,10 +123,7 @@ impl Tib {
         tib: impl FnOnce() -> Tib,
         encoded_value: Option<u8>,
     ) -> &'static Tib {
-        let mut tibs = TIBS.lock().unwrap();
-        tibs.entry(klass)
-            .or_insert_with(|| alloc_tib(tib, encoded_value));
-        tibs.get(&klass).unwrap()
+        TIBS.get_or_insert_with(klass, || alloc_tib(tib, encoded_value))
     }
 
     fn objarray<const AE: bool>(klass: u64) -> &'static Tib {
@@ -151,11 +134,7 @@ impl Tib {
                 oop_map_blocks: vec![],
                 instance_mirror_info: None,
             },
-            if AE {
-                Some(AlignmentEncodingPattern::RefArray as u8)
-            } else {
-                None
-            },
+            if AE { Some(REF_ARRAY_CODE) } else { None },
         )
     }
 
@@ -188,29 +167,9 @@ impl Tib {
         oop_map_blocks
     }
 
-    fn alignment_encode_omb(ombs: &[OopMapBlock]) -> AlignmentEncodingPattern {
-        let mut fields = FixedBitSet::with_capacity(7);
-        for omb in ombs {
-            let first_field = (omb.offset >> LOG_BYTES_IN_WORD) - 2;
-            let last_field = first_field + omb.count - 1;
-            if first_field > 6 || last_field > 6 {
-                return AlignmentEncodingPattern::Fallback;
-            }
-            fields.set_range(
-                (first_field as usize)..((first_field + omb.count) as usize),
-                true,
-            );
-        }
-        let bits = fields.as_slice()[0];
-        match bits {
-            0b0000000 => AlignmentEncodingPattern::NoRef,
-            0b0000001 => AlignmentEncodingPattern::Ref0,
-            0b0000011 => AlignmentEncodingPattern::Ref0_1,
-            0b0000100 => AlignmentEncodingPattern::Ref2,
-            0b0001110 => AlignmentEncodingPattern::Ref1_2_3,
-            0b1110000 => AlignmentEncodingPattern::Ref4_5_6,
-            _ => AlignmentEncodingPattern::Fallback,
-        }
+    fn alignment_encode_omb(ombs: &[OopMapBlock]) -> u8 {
+        let mask = field_mask(ombs.iter().map(|omb| (omb.offset, omb.count)));
+        ae_patterns().code_of(mask)
     }
 
     fn non_objarray<const AE: bool>(klass: u64, obj: &HeapObject) -> &'static Tib {
@@ -223,7 +182,7 @@ impl Tib {
             let count = obj.instance_mirror_count.unwrap();
             debug_assert_eq!(sum + count, obj.edges.len() as u64);
             let align_code = if AE {
-                Some(Self::alignment_encode_omb(&ombs) as u8)
+                Some(Self::alignment_encode_omb(&ombs))
             } else {
                 None
             };
@@ -237,7 +196,7 @@ impl Tib {
             )
         } else {
             let align_code = if AE {
-                Some(Self::alignment_encode_omb(&ombs) as u8)
+                Some(Self::alignment_encode_omb(&ombs))
             } else {
                 None
             };
@@ -261,55 +220,77 @@ impl Tib {
         sum
     }
 
-    unsafe fn scan_object_fallback<F>(tib: &Tib, o: u64, mut callback: F)
+    unsafe fn scan_object_fallback<F>(tib: &Tib, o: ObjectReference, mut callback: F)
     where
-        F: FnMut(*mut u64, u64),
+        F: FnMut(Address, u64),
     {
         // println!("Object: {}, Tib Ptr: {:?}, Tib: {:?}", o, tib_ptr, tib);
+        let o = o.to_address();
         let mut num_edges = 0;
         match tib.ttype {
             TibType::ObjArray => {
-                let objarray_length = *((o as *mut u64).wrapping_add(2) as *const u64);
+                let objarray_length = o.shift::<u64>(2).load::<u64>();
                 // println!("Objarray length: {}", objarray_length);
-                callback((o as *mut u64).wrapping_add(3), objarray_length);
+                callback(o.shift::<u64>(3), objarray_length);
                 num_edges += objarray_length;
             }
             TibType::InstanceMirror => {
                 for omb in &tib.oop_map_blocks {
-                    callback(
-                        (o as *mut u8).wrapping_add(omb.offset as usize) as *mut u64,
-                        omb.count,
-                    );
+                    callback(o.shift::<u8>(omb.offset as usize), omb.count);
                     num_edges += omb.count;
                 }
                 let (start, count) = &tib.instance_mirror_info.unwrap();
-                callback((*start) as *mut u64, *count);
+                callback(Address::from_raw(*start), *count);
                 num_edges += *count;
             }
             TibType::Ordinary => {
                 for omb in &tib.oop_map_blocks {
-                    callback(
-                        (o as *mut u8).wrapping_add(omb.offset as usize) as *mut u64,
-                        omb.count,
-                    );
+                    callback(o.shift::<u8>(omb.offset as usize), omb.count);
                     num_edges += omb.count;
                 }
             }
         }
         // println!("{:?}", objects.get(&o).unwrap());
-        debug_assert_eq!(
-            num_edges,
-            OBJECT_MAPS.lock().unwrap().get(&o).unwrap().edges.len() as u64
-        );
+        debug_assert_eq!(num_edges, recorded_edges(o.as_u64()));
     }
 
-    unsafe fn scan_object<const AE: bool, F>(o: u64, mut callback: F)
+    /// The number of slots `scan_object` would call back with, from the
+    /// length of arrays and the oop maps or alignment encoding alone.
+    unsafe fn num_edges_of<const AE: bool>(o: ObjectReference) -> u64 {
+        let tib_ptr = OpenJDKObjectModel::<AE>::get_tib(o);
+        if tib_ptr.is_null() {
+            panic!("Object {} has a null tib pointer", o);
+        }
+        let length = || o.to_address().shift::<u64>(2).load::<u64>();
+        let pattern = if AE {
+            AlignmentEncoding::get_tib_code_for_region(tib_ptr as usize)
+        } else {
+            AlignmentEncodingPattern::Fallback
+        };
+        match pattern {
+            AlignmentEncodingPattern::Fallback => {
+                let tib: &Tib = &*tib_ptr;
+                match tib.ttype {
+                    TibType::ObjArray => length(),
+                    _ => tib.num_edges(),
+                }
+            }
+            AlignmentEncodingPattern::RefArray => length(),
+            AlignmentEncodingPattern::Fields(code) => ae_patterns()
+                .runs(code)
+                .iter()
+                .map(|&(_, count)| count)
+                .sum(),
+        }
+    }
+
+    unsafe fn scan_object<const AE: bool, F>(o: ObjectReference, mut callback: F)
     where
-        F: FnMut(*mut u64, u64),
+        F: FnMut(Address, u64),
     {
         let tib_ptr = OpenJDKObjectModel::<AE>::get_tib(o);
         if tib_ptr.is_null() {
-            panic!("Object 0x{:x} has a null tib pointer", { o });
+            panic!("Object {} has a null tib pointer", o);
         }
         if !AE {
             let tib: &Tib = &*tib_ptr;
@@ -323,29 +304,26 @@ impl Tib {
                 Self::scan_object_fallback(tib, o, callback);
             }
             AlignmentEncodingPattern::RefArray => {
-                let objarray_length = *((o as *mut u64).wrapping_add(2) as *const u64);
-                callback((o as *mut u64).wrapping_add(3), objarray_length);
-            }
-            AlignmentEncodingPattern::NoRef => {}
-            AlignmentEncodingPattern::Ref0 => {
-                callback((o as *mut u64).wrapping_add(2), 1);
-            }
-            AlignmentEncodingPattern::Ref1_2_3 => {
-                callback((o as *mut u64).wrapping_add(3), 3);
-            }
-            AlignmentEncodingPattern::Ref4_5_6 => {
-                callback((o as *mut u64).wrapping_add(6), 3);
-            }
-            AlignmentEncodingPattern::Ref2 => {
-                callback((o as *mut u64).wrapping_add(4), 1);
+                let o = o.to_address();
+                let objarray_length = o.shift::<u64>(2).load::<u64>();
+                callback(o.shift::<u64>(3), objarray_length);
             }
-            AlignmentEncodingPattern::Ref0_1 => {
-                callback((o as *mut u64).wrapping_add(2), 2);
+            AlignmentEncodingPattern::Fields(code) => {
+                for &(word, count) in ae_patterns().runs(code) {
+                    callback(o.to_address().shift::<u64>(word), count);
+                }
             }
         }
     }
 }
 
+/// The reference fields of `obj` as alignment encoding matches them against
+/// its patterns, without the static fields of instance mirrors.
+pub(crate) fn ae_field_mask(obj: &HeapObject) -> Option<u8> {
+    let ombs = Tib::encode_oop_map_blocks(obj);
+    field_mask(ombs.iter().map(|omb| (omb.offset, omb.count)))
+}
+
 #[repr(C)]
 #[derive(Debug)]
 struct OopMapBlock {
@@ -353,14 +331,62 @@ struct OopMapBlock {
     count: u64,
 }
 
+// Restored objects, kept to check that scanning an object visits as many
+// edges as the heapdump recorded. A full copy of every `HeapObject` doubles
+// the memory needed by a heapdump, so it is only kept with the `object_maps`
+// feature, for debugging. Otherwise, debug builds only remember the number
+// of edges of each object, and release builds nothing at all. The edge counts
+// are written once per restore and only read while scanning, so parallel
+// tracers share the read lock rather than queueing on a mutex.
+#[cfg(feature = "object_maps")]
 lazy_static! {
     static ref OBJECT_MAPS: Mutex<HashMap<u64, HeapObject>> = Mutex::new(HashMap::new());
 }
 
+#[cfg(not(feature = "object_maps"))]
+lazy_static! {
+    static ref EDGE_COUNTS: RwLock<HashMap<u64, u64>> = RwLock::new(HashMap::new());
+}
+
+#[cfg(feature = "object_maps")]
+fn record_objects(objects: &[HeapObject]) {
+    let mut maps = OBJECT_MAPS.lock().unwrap();
+    for object in objects {
+        maps.insert(object.start, object.clone());
+    }
+}
+
+#[cfg(not(feature = "object_maps"))]
+fn record_objects(objects: &[HeapObject]) {
+    if cfg!(debug_assertions) {
+        let counts: HashMap<u64, u64> = objects
+            .iter()
+            .map(|o| (o.start, o.edges.len() as u64))
+            .collect();
+        EDGE_COUNTS.write().unwrap().extend(counts);
+    }
+}
+
+#[cfg(feature = "object_maps")]
+fn recorded_edges(o: u64) -> u64 {
+    OBJECT_MAPS.lock().unwrap().get(&o).unwrap().edges.len() as u64
+}
+
+#[cfg(not(feature = "object_maps"))]
+fn recorded_edges(o: u64) -> u64 {
+    *EDGE_COUNTS.read().unwrap().get(&o).unwrap()
+}
+
+fn clear_recorded_objects() {
+    #[cfg(feature = "object_maps")]
+    OBJECT_MAPS.lock().unwrap().clear();
+    #[cfg(not(feature = "object_maps"))]
+    EDGE_COUNTS.write().unwrap().clear();
+}
+
 pub struct OpenJDKObjectModel<const AE: bool> {
-    objects: Vec<u64>,
-    roots: Vec<u64>,
-    object_sizes: HashMap<u64, u64>,
+    objects: ObjectTable,
+    roots: Arc<[u64]>,
 }
 
 impl<const AE: bool> Default for OpenJDKObjectModel<AE> {
@@ -372,51 +398,56 @@ impl<const AE: bool> Default for OpenJDKObjectModel<AE> {
 impl<const AE: bool> OpenJDKObjectModel<AE> {
     pub fn new() -> Self {
         OpenJDKObjectModel {
-            objects: vec![],
-            roots: vec![],
-            object_sizes: HashMap::new(),
+            objects: ObjectTable::new(false),
+            roots: Arc::new([]),
         }
     }
+
+    /// Keeps the objects in side metadata rather than a list and a map,
+    /// see `--object-map`.
+    pub fn with_object_map(mut self, object_map: bool) -> Self {
+        self.objects = ObjectTable::new(object_map);
+        self
+    }
 }
 
 impl<const AE: bool> ObjectModel for OpenJDKObjectModel<AE> {
     type Tib = Tib;
 
     fn reset(&mut self) {
-        OBJECT_MAPS.lock().unwrap().clear();
-        self.roots.clear();
+        clear_recorded_objects();
+        self.roots = Arc::new([]);
         self.objects.clear();
-        self.object_sizes.clear();
     }
 
     fn restore_tibs(&mut self, heapdump: &HeapDump) -> usize {
-        let before_size = TIBS.lock().unwrap().len();
-        for object in &heapdump.objects {
-            let is_objarray = object.objarray_length.is_some();
-            if is_objarray {
-                let _tib = Tib::objarray::<AE>(object.klass);
-            } else if object.instance_mirror_start.is_none() {
-                let _tib = Tib::non_objarray::<AE>(object.klass, object);
-            };
-        }
-        let after_size = TIBS.lock().unwrap().len();
+        let before_size = TIBS.len();
+        restore_klass_tibs::<AE>(
+            heapdump
+                .objects
+                .iter()
+                .filter(|o| o.instance_mirror_start.is_none()),
+        );
+        let after_size = TIBS.len();
         after_size - before_size
     }
 
     fn restore_objects(&mut self, heapdump: &HeapDump) {
-        for object in &heapdump.objects {
-            OBJECT_MAPS
-                .lock()
-                .unwrap()
-                .insert(object.start, object.clone());
-            self.objects.push(object.start);
-        }
+        record_objects(&heapdump.objects);
+        self.objects
+            .set(heapdump.objects.iter().map(|o| (o.start, o.size)).collect());
 
-        for root in &heapdump.roots {
-            self.roots.push(root.objref);
-        }
+        self.roots = heapdump.all_roots().map(|r| r.objref).collect();
 
-        for o in &heapdump.objects {
+        let progress = Progress::new(
+            "Restoring objects",
+            "objects",
+            Some(heapdump.objects.len() as u64),
+        );
+        // Objects never overlap, so they can be written out in parallel once
+        // every TIB exists and the threads only look them up.
+        restore_klass_tibs::<AE>(heapdump.objects.iter());
+        heapdump.objects.par_iter().for_each(|o| {
             // unsafe {
             //     std::ptr::write::<u64>((o.start + 8) as *mut u64, o.start);
             // }
@@ -435,64 +466,78 @@ impl<const AE: bool> ObjectModel for OpenJDKObjectModel<AE> {
             // );
             // Initialize the object
             // Set tib
+            let start = Address::from_raw(o.start);
             unsafe {
-                std::ptr::write::<u64>((o.start + 8) as *mut u64, tib_ptr as u64);
+                start.shift::<u64>(1).store(tib_ptr as u64);
             }
             // Write out array length for obj array
             if let Some(l) = o.objarray_length {
                 unsafe {
-                    std::ptr::write::<u64>((o.start + 16) as *mut u64, l);
+                    start.shift::<u64>(2).store(l);
                 }
             }
             // Write out each non-zero ref field
             for e in &o.edges {
                 unsafe {
-                    std::ptr::write::<u64>(e.slot as *mut u64, e.objref);
+                    Address::from_raw(e.slot).store(e.objref);
                 }
             }
-            self.object_sizes.insert(o.start, o.size);
-        }
+            progress.add(1);
+        });
+        progress.finish();
     }
 
-    fn scan_object<F>(o: u64, callback: F)
+    fn scan_object<F>(o: ObjectReference, callback: F)
     where
-        F: FnMut(*mut u64, u64),
+        F: FnMut(Address, u64),
     {
         unsafe {
             Tib::scan_object::<AE, _>(o, callback);
         }
     }
 
+    fn num_edges(o: ObjectReference) -> u64 {
+        unsafe { Tib::num_edges_of::<AE>(o) }
+    }
+
     fn roots(&self) -> &[u64] {
         &self.roots
     }
 
-    fn objects(&self) -> &[u64] {
-        &self.objects
+    fn shared_roots(&self) -> Arc<[u64]> {
+        self.roots.clone()
+    }
+
+    fn objects_iter(&self) -> Box<dyn Iterator<Item = ObjectReference> + '_> {
+        self.objects.iter()
+    }
+
+    fn object_size(&self, o: ObjectReference) -> Option<u64> {
+        self.objects.size(o)
     }
 
-    fn object_sizes(&self) -> &HashMap<u64, u64> {
-        &self.object_sizes
+    fn num_objects(&self) -> usize {
+        self.objects.len()
     }
 
-    unsafe fn is_objarray(o: u64) -> bool {
+    unsafe fn is_objarray(o: ObjectReference) -> bool {
         let tib_ptr = Self::get_tib(o);
         if tib_ptr.is_null() {
-            panic!("Object 0x{:x} has a null tib pointer", { o });
+            panic!("Object {} has a null tib pointer", o);
         }
         let tib: &Tib = &*tib_ptr;
         matches!(tib.ttype, TibType::ObjArray)
     }
 
-    fn get_tib(o: u64) -> *const Self::Tib {
-        unsafe { *((o as *mut u64).wrapping_add(1) as *const *const Tib) }
+    fn get_tib(o: ObjectReference) -> *const Self::Tib {
+        unsafe { o.to_address().shift::<u64>(1).load::<*const Tib>() }
     }
 
-    fn tib_lookup_required(o: u64) -> bool {
+    fn tib_lookup_required(o: ObjectReference) -> bool {
         if AE {
             let tib_ptr = OpenJDKObjectModel::<AE>::get_tib(o);
             if tib_ptr.is_null() {
-                panic!("Object 0x{:x} has a null tib pointer", { o });
+                panic!("Object {} has a null tib pointer", o);
             }
             let pattern = AlignmentEncoding::get_tib_code_for_region(tib_ptr as usize);
             matches!(pattern, AlignmentEncodingPattern::Fallback)
@@ -501,4 +546,25 @@ impl<const AE: bool> ObjectModel for OpenJDKObjectModel<AE> {
             true
         }
     }
+
+    fn tib_records() -> Vec<TibRecord> {
+        TIBS.entries()
+            .into_iter()
+            .map(|(klass, tib)| TibRecord {
+                klass,
+                tib: tib as *const Tib as u64,
+                ttype: tib.ttype as u8,
+                pattern: if AE {
+                    AlignmentEncoding::get_tib_code_for_region(tib as *const Tib as usize).into()
+                } else {
+                    FALLBACK_PATTERN
+                },
+                blocks: tib
+                    .oop_map_blocks
+                    .iter()
+                    .map(|omb| (omb.offset, omb.count))
+                    .collect(),
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,145 @@
+use crate::util::bump::{AllocationStats, BumpAllocator};
+use crate::HeapObject;
+use std::alloc::{self, Layout};
+use std::collections::{BTreeMap, HashMap};
+use std::mem::{align_of, size_of};
+use std::ptr;
+use std::sync::Mutex;
+
+const LOG_NUM_SHARDS: u32 = 6;
+const NUM_SHARDS: usize = 1 << LOG_NUM_SHARDS;
+
+/// The TIBs of an object model by klass, shared by all threads restoring a
+/// heapdump.
+///
+/// The map is split into shards behind their own lock, so that threads
+/// restoring objects of different klasses rarely wait for each other. A TIB
+/// is allocated while its shard is locked, so every klass still gets exactly
+/// one TIB, at the address its alignment encoding asked for.
+pub(super) struct TibCache<T: 'static> {
+    shards: Vec<Mutex<HashMap<u64, &'static T>>>,
+}
+
+impl<T: Sync + 'static> TibCache<T> {
+    pub(super) fn new() -> Self {
+        TibCache {
+            shards: (0..NUM_SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    fn shard(&self, klass: u64) -> &Mutex<HashMap<u64, &'static T>> {
+        // Klass pointers are word aligned and often close to each other, so
+        // use the high bits of a multiplicative hash.
+        let hash = (klass >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        &self.shards[(hash >> (64 - LOG_NUM_SHARDS)) as usize]
+    }
+
+    pub(super) fn get_or_insert_with(
+        &self,
+        klass: u64,
+        alloc: impl FnOnce() -> &'static T,
+    ) -> &'static T {
+        let mut shard = self.shard(klass).lock().unwrap();
+        if let Some(&tib) = shard.get(&klass) {
+            return tib;
+        }
+        let tib = alloc();
+        shard.insert(klass, tib);
+        tib
+    }
+
+    /// Every klass with its TIB, in no particular order.
+    pub(super) fn entries(&self) -> Vec<(u64, &'static T)> {
+        self.shards
+            .iter()
+            .flat_map(|s| {
+                let shard = s.lock().unwrap();
+                shard.iter().map(|(&k, &t)| (k, t)).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+}
+
+/// The first of `objects` of each klass, in klass order. TIBs are created
+/// from these one at a time, rather than by whichever thread restoring the
+/// heapdump gets to a klass first, so that every run puts the TIB of a
+/// klass at the same address.
+pub(super) fn first_of_each_klass<'a>(
+    objects: impl Iterator<Item = &'a HeapObject>,
+) -> BTreeMap<u64, &'a HeapObject> {
+    let mut first = BTreeMap::new();
+    for object in objects {
+        first.entry(object.klass).or_insert(object);
+    }
+    first
+}
+
+/// Bytes of memory TIBs are carved out of at a time
+const TIB_CHUNK_BYTES: u64 = 1 << 20;
+
+lazy_static! {
+    pub(super) static ref TIB_ARENA: Mutex<TibArena> = Mutex::new(TibArena::default());
+}
+
+/// Memory for the TIBs of every object model. TIBs live as long as the
+/// process, so they are bump-allocated from chunks that are never freed,
+/// which also makes asking for an alignment cheap.
+#[derive(Default)]
+pub(super) struct TibArena {
+    current: Option<BumpAllocator>,
+    /// Stats of the chunks before the current one
+    retired: AllocationStats,
+}
+
+// The arena only hands out addresses of memory it leaked
+unsafe impl Send for TibArena {}
+
+impl TibArena {
+    /// Moves `tib` into the arena, at an address `offset` bytes past a
+    /// multiple of `align`.
+    pub(super) fn alloc<T>(&mut self, tib: T, align: u64, offset: u64) -> &'static T {
+        let size = size_of::<T>() as u64;
+        debug_assert!(
+            align >= align_of::<T>() as u64 && offset.is_multiple_of(align_of::<T>() as u64)
+        );
+        let start = match self
+            .current
+            .as_mut()
+            .and_then(|c| c.alloc_aligned(size, align, offset))
+        {
+            Some(start) => start,
+            None => {
+                if let Some(full) = self.current.take() {
+                    self.retired += full.stats;
+                }
+                let layout = Layout::from_size_align(TIB_CHUNK_BYTES as usize, 4096).unwrap();
+                let chunk = unsafe { alloc::alloc(layout) } as u64;
+                if chunk == 0 {
+                    alloc::handle_alloc_error(layout);
+                }
+                let mut current = BumpAllocator::new(chunk, chunk + TIB_CHUNK_BYTES);
+                let start = current
+                    .alloc_aligned(size, align, offset)
+                    .expect("A TIB is larger than a chunk");
+                self.current = Some(current);
+                start
+            }
+        };
+        unsafe {
+            ptr::write(start as *mut T, tib);
+            &*(start as *const T)
+        }
+    }
+
+    pub(super) fn stats(&self) -> AllocationStats {
+        let mut stats = self.retired;
+        if let Some(current) = &self.current {
+            stats += current.stats;
+        }
+        stats
+    }
+}
@@ -1,17 +1,45 @@
+use crate::simulate::{AddressMapping, LOG_LINE_SIZE};
 use crate::*;
 use anyhow::Result;
 use polars::prelude::*;
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 
-type CountMap = HashMap<u32, usize>;
+/// Slots of objarrays start after the header, the TIB and the length
+const OBJARRAY_FIRST_SLOT: u64 = 24;
+
+/// What the chunks of a size class cover, a streaming scan engine reading
+/// each chunk with as few row activations as these allow.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+struct ChunkStats {
+    edges: usize,
+    chunks: usize,
+    /// Cache lines holding the slots of the chunks
+    cache_lines: usize,
+    /// DRAM rows, under the simulator's address mapping, holding the slots
+    /// of the chunks
+    dram_rows: usize,
+}
+
+impl ChunkStats {
+    fn add(&mut self, other: &ChunkStats) {
+        self.edges += other.edges;
+        self.chunks += other.chunks;
+        self.cache_lines += other.cache_lines;
+        self.dram_rows += other.dram_rows;
+    }
+}
+
+type CountMap = HashMap<u32, ChunkStats>;
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
 struct EdgeChunk {
     chunk_size_log: u32,
     edge_count: usize,
+    /// Address of the first slot of the chunk
+    first_slot: u64,
 }
 
 impl EdgeChunk {
@@ -19,22 +47,61 @@ impl EdgeChunk {
         usize::BITS - x.leading_zeros()
     }
 
+    fn new(edge_count: usize, first_slot: u64) -> Self {
+        EdgeChunk {
+            chunk_size_log: Self::log2_ceil(edge_count),
+            edge_count,
+            first_slot,
+        }
+    }
+
+    /// The cache lines and the DRAM rows the slots of the chunk span.
+    fn stats(&self) -> ChunkStats {
+        if self.edge_count == 0 {
+            return ChunkStats::default();
+        }
+        let end = self.first_slot + self.edge_count as u64 * 8;
+        let first_line = self.first_slot >> LOG_LINE_SIZE;
+        let last_line = (end - 1) >> LOG_LINE_SIZE;
+        // Consecutive lines interleave across channels and banks, so a row
+        // can come back after lines of other rows
+        let rows: HashSet<(u8, u8, u8, u8, u16)> = (first_line..=last_line)
+            .map(|line| {
+                let mapping = AddressMapping(line << LOG_LINE_SIZE);
+                (
+                    mapping.channel(),
+                    mapping.dimm(),
+                    mapping.rank(),
+                    mapping.bank(),
+                    mapping.row(),
+                )
+            })
+            .collect();
+        ChunkStats {
+            edges: self.edge_count,
+            chunks: 1,
+            cache_lines: (last_line - first_line + 1) as usize,
+            dram_rows: rows.len(),
+        }
+    }
+
     fn from_object(obj: &HeapObject, object_model: ObjectModelChoice) -> Vec<EdgeChunk> {
         if matches!(object_model, ObjectModelChoice::Bidirectional)
             || matches!(object_model, ObjectModelChoice::BidirectionalFallback)
         {
             let chunk_size = obj.edges.len();
-            return vec![EdgeChunk {
-                chunk_size_log: Self::log2_ceil(chunk_size),
-                edge_count: chunk_size,
-            }];
+            // Objarrays keep their layout, other objects end with their
+            // references
+            let first_slot = if obj.objarray_length.is_some() {
+                obj.start + OBJARRAY_FIRST_SLOT
+            } else {
+                obj.start + obj.size - chunk_size as u64 * 8
+            };
+            return vec![EdgeChunk::new(chunk_size, first_slot)];
         }
 
         if let Some(l) = obj.objarray_length {
-            vec![EdgeChunk {
-                chunk_size_log: Self::log2_ceil(l as usize),
-                edge_count: l as usize,
-            }]
+            vec![EdgeChunk::new(l as usize, obj.start + OBJARRAY_FIRST_SLOT)]
         } else if obj.edges.is_empty() {
             vec![]
         } else {
@@ -46,25 +113,21 @@ impl EdgeChunk {
             offsets.sort();
             let mut i = 1;
             let mut chunk_size: usize = 1;
+            let mut chunk_start = (obj.start as i64 + offsets[0]) as u64;
             let mut counts = vec![];
             while i < offsets.len() {
                 if offsets[i] != offsets[i - 1] + 8 {
                     // not contiguous, push old chunk
-                    counts.push(EdgeChunk {
-                        chunk_size_log: Self::log2_ceil(chunk_size),
-                        edge_count: chunk_size,
-                    });
+                    counts.push(EdgeChunk::new(chunk_size, chunk_start));
                     // start a new chunk
                     chunk_size = 1;
+                    chunk_start = (obj.start as i64 + offsets[i]) as u64;
                 } else {
                     chunk_size += 1;
                 }
                 i += 1;
             }
-            counts.push(EdgeChunk {
-                chunk_size_log: Self::log2_ceil(chunk_size),
-                edge_count: chunk_size,
-            });
+            counts.push(EdgeChunk::new(chunk_size, chunk_start));
             // println!("{:?} {:?}", offsets, counts);
             counts
         }
@@ -73,7 +136,7 @@ impl EdgeChunk {
 
 fn merge_counts(count_a: &mut CountMap, count_b: &CountMap) {
     for (key, val) in count_b.iter() {
-        *count_a.entry(*key).or_default() += val;
+        count_a.entry(*key).or_default().add(val);
     }
 }
 
@@ -87,7 +150,10 @@ fn analyze_one_file(path: &Path, object_model: ObjectModelChoice) -> Result<Coun
             |mut partial_count: CountMap, object: &HeapObject| {
                 let chunks = EdgeChunk::from_object(object, object_model);
                 chunks.iter().for_each(|c| {
-                    *partial_count.entry(c.chunk_size_log).or_default() += c.edge_count
+                    partial_count
+                        .entry(c.chunk_size_log)
+                        .or_default()
+                        .add(&c.stats())
                 });
                 partial_count
             },
@@ -151,11 +217,19 @@ pub(super) fn edge_chunks(
 
     let mut lfs = vec![];
     for (bm, count_map) in bm_countmaps {
-        let (chunk_size_log, edges): (Vec<u32>, Vec<u64>) =
-            count_map.iter().map(|(a, b)| (*a, *b as u64)).unzip();
+        let chunk_size_log: Vec<u32> = count_map.keys().copied().collect();
+        let column = |f: fn(&ChunkStats) -> usize| -> Vec<u64> {
+            chunk_size_log
+                .iter()
+                .map(|k| f(&count_map[k]) as u64)
+                .collect()
+        };
         let lf: LazyFrame = df!(
             "chunk_size_log" => &chunk_size_log,
-            "edges" => &edges,
+            "edges" => &column(|c| c.edges),
+            "chunks" => &column(|c| c.chunks),
+            "cache_lines" => &column(|c| c.cache_lines),
+            "dram_rows" => &column(|c| c.dram_rows),
         )
         .unwrap()
         .lazy();
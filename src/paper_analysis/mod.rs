@@ -1,8 +1,9 @@
 use crate::*;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 mod degrees;
 mod edges;
+mod reuse;
 mod shape;
 
 pub fn reified_paper_analysis<O: ObjectModel>(mut _object_model: O, args: Args) -> Result<()> {
@@ -11,6 +12,11 @@ pub fn reified_paper_analysis<O: ObjectModel>(mut _object_model: O, args: Args)
     } else {
         panic!("Incorrect dispatch");
     };
+    if analysis_args.shape_inventory.is_some()
+        && analysis_args.analysis_name != PaperAnalysisChoice::ShapeDemographic
+    {
+        bail!("--shape-inventory only goes with ShapeDemographic");
+    }
 
     match analysis_args.analysis_name {
         PaperAnalysisChoice::ShapeDemographic => {
@@ -22,5 +28,8 @@ pub fn reified_paper_analysis<O: ObjectModel>(mut _object_model: O, args: Args)
         PaperAnalysisChoice::Degrees => {
             degrees::degrees(&args.paths, analysis_args, args.object_model)
         }
+        PaperAnalysisChoice::ReuseDistance => {
+            reuse::reuse_distance(&args.paths, analysis_args, args.object_model)
+        }
     }
 }
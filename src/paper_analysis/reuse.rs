@@ -0,0 +1,194 @@
+use crate::heapdump::NormalEdge;
+use crate::simulate::LOG_LINE_SIZE;
+use crate::*;
+use anyhow::Result;
+use polars::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+
+/// Cache lines in the order an EdgeSlot closure over the heapdump touches
+/// them: the header of every object it tries to mark, which scanning the
+/// object reads again, and every slot it loads.
+fn edge_slot_lines(heapdump: &HeapDump) -> Vec<u64> {
+    let objects: HashMap<u64, &HeapObject> =
+        heapdump.objects.iter().map(|o| (o.start, o)).collect();
+    let mut marked = HashSet::new();
+    let mut lines = vec![];
+    let mut mark_queue: Vec<&NormalEdge> = vec![];
+    let mut trace = |o: u64, lines: &mut Vec<u64>| {
+        lines.push(o >> LOG_LINE_SIZE);
+        marked.insert(o).then(|| &objects[&o].edges)
+    };
+    for root in heapdump.all_roots() {
+        if let Some(edges) = trace(root.objref, &mut lines) {
+            mark_queue.extend(edges);
+        }
+    }
+    while let Some(edge) = mark_queue.pop() {
+        lines.push(edge.slot >> LOG_LINE_SIZE);
+        if edge.objref == 0 {
+            continue;
+        }
+        if let Some(edges) = trace(edge.objref, &mut lines) {
+            mark_queue.extend(edges);
+        }
+    }
+    lines
+}
+
+/// How many distinct lines were touched between two accesses to a line,
+/// bucketed by powers of two.
+#[derive(Debug, Default, PartialEq)]
+struct ReuseHistogram {
+    /// Bucket 0 holds the reuses at distance 0, bucket `k` those at a
+    /// distance from 2^(k-1) up to 2^k, which all hit in a fully
+    /// associative LRU cache of 2^k lines
+    buckets: Vec<u64>,
+    /// First accesses to a line, which miss in any cache
+    cold: u64,
+    accesses: u64,
+}
+
+impl ReuseHistogram {
+    /// Computes the stack distance of every access in one pass, as Olken
+    /// does: a Fenwick tree over the accesses holds a one at the latest
+    /// access to each line, so the distinct lines since the previous access
+    /// to a line are the ones between that access and now.
+    fn new(lines: &[u64]) -> Self {
+        let mut histogram = ReuseHistogram {
+            accesses: lines.len() as u64,
+            ..Default::default()
+        };
+        let mut latest = FenwickTree::new(lines.len());
+        let mut last_access: HashMap<u64, usize> = HashMap::new();
+        for (i, &line) in lines.iter().enumerate() {
+            match last_access.insert(line, i) {
+                Some(previous) => {
+                    let distance = latest.prefix_sum(i) - latest.prefix_sum(previous + 1);
+                    let bucket = (u64::BITS - distance.leading_zeros()) as usize;
+                    if histogram.buckets.len() <= bucket {
+                        histogram.buckets.resize(bucket + 1, 0);
+                    }
+                    histogram.buckets[bucket] += 1;
+                    latest.add(previous, -1);
+                }
+                None => histogram.cold += 1,
+            }
+            latest.add(i, 1);
+        }
+        histogram
+    }
+
+    /// Miss rate of a fully associative LRU cache of 2^`log_lines` lines.
+    fn miss_rate(&self, log_lines: usize) -> f64 {
+        let hits: u64 = self.buckets.iter().take(log_lines + 1).sum();
+        (self.accesses - hits) as f64 / self.accesses.max(1) as f64
+    }
+}
+
+struct FenwickTree(Vec<i64>);
+
+impl FenwickTree {
+    fn new(len: usize) -> Self {
+        FenwickTree(vec![0; len + 1])
+    }
+
+    fn add(&mut self, index: usize, delta: i64) {
+        let mut i = index + 1;
+        while i < self.0.len() {
+            self.0[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Sum of the first `len` entries
+    fn prefix_sum(&self, len: usize) -> u64 {
+        let mut sum = 0;
+        let mut i = len;
+        while i > 0 {
+            sum += self.0[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum as u64
+    }
+}
+
+/// The reuse-distance distribution of the cache lines an EdgeSlot closure
+/// touches, and the miss rate it gives every power-of-two cache size
+/// without simulating each. The caches are fully associative LRU, so
+/// conflict misses are not counted.
+// cargo run -- ../heapdumps/sampled/fop/heapdump.2.binpb.zst -o OpenJDK paper-analyze --analysis-name ReuseDistance --output-path fop.parquet
+pub(super) fn reuse_distance(
+    paths: &[String],
+    analysis_args: PaperAnalysisArgs,
+    // the closure touches the addresses of the heapdump, whatever the
+    // object model
+    _object_model: ObjectModelChoice,
+) -> Result<()> {
+    let mut lfs = vec![];
+    for p in paths {
+        let heapdump = HeapDump::from_path(p)?;
+        let histogram = ReuseHistogram::new(&edge_slot_lines(&heapdump));
+        println!(
+            "{}: {} accesses to {} cache lines",
+            p, histogram.accesses, histogram.cold
+        );
+        let log_lines: Vec<u32> = (0..histogram.buckets.len() as u32).collect();
+        let miss_rates: Vec<f64> = log_lines
+            .iter()
+            .map(|&k| histogram.miss_rate(k as usize))
+            .collect();
+        for (&k, miss_rate) in log_lines.iter().zip(&miss_rates) {
+            println!(
+                "  {:>12} bytes: {:>6.2}% misses",
+                1u64 << (k as usize + LOG_LINE_SIZE),
+                miss_rate * 100.0
+            );
+        }
+        let lf = df!(
+            "log_cache_lines" => &log_lines,
+            "cache_bytes" => log_lines.iter().map(|&k| 1u64 << (k as usize + LOG_LINE_SIZE)).collect::<Vec<_>>(),
+            "reuses" => &histogram.buckets,
+            "miss_rate" => &miss_rates,
+        )?
+        .lazy()
+        .with_column(lit(histogram.cold).alias("cold_misses"))
+        .with_column(lit(p.as_str()).alias("heapdump"));
+        lfs.push(lf);
+    }
+    let mut df = concat(lfs, UnionArgs::default())?.collect()?;
+    df.as_single_chunk_par();
+    let file = File::create(analysis_args.output_path)?;
+    ParquetWriter::new(file).finish(&mut df)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::LeafObjectArrayHeapDump;
+
+    #[test]
+    fn test_reuse_histogram() {
+        // Distances 1, 2 and 2 after the first accesses to 1, 2 and 3
+        let histogram = ReuseHistogram::new(&[1, 2, 1, 3, 2, 1]);
+        assert_eq!(
+            histogram,
+            ReuseHistogram {
+                buckets: vec![0, 1, 2],
+                cold: 3,
+                accesses: 6,
+            }
+        );
+        assert_eq!(histogram.miss_rate(0), 1.0);
+        assert_eq!(histogram.miss_rate(1), 5.0 / 6.0);
+        assert_eq!(histogram.miss_rate(2), 0.5);
+        // Back to back accesses to a line are at distance 0
+        assert_eq!(ReuseHistogram::new(&[7, 7]).buckets, vec![1]);
+
+        let heapdump = LeafObjectArrayHeapDump::new("objarray_4").to_heapdump();
+        let lines = edge_slot_lines(&heapdump);
+        // The array, then a slot and an object for each element
+        assert_eq!(lines.len(), 1 + 2 * 4);
+    }
+}
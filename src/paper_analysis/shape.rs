@@ -4,6 +4,7 @@ use polars::prelude::*;
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs::{self, File};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
@@ -30,6 +31,20 @@ impl ObjectShape {
         }
     }
 
+    /// The oop map as text: `noref`, `objarray`, or the offsets of the
+    /// reference slots separated by semicolons.
+    fn signature(&self) -> String {
+        match self {
+            ObjectShape::NoRef => "noref".to_string(),
+            ObjectShape::RefArray => "objarray".to_string(),
+            ObjectShape::Refs(r) => r
+                .iter()
+                .map(|o| o.to_string())
+                .collect::<Vec<_>>()
+                .join(";"),
+        }
+    }
+
     fn into_array(self) -> Vec<i64> {
         match self {
             ObjectShape::NoRef => vec![],
@@ -39,11 +54,30 @@ impl ObjectShape {
     }
 }
 
-type CountMap = HashMap<ObjectShape, usize>;
+/// Objects of a shape and size, and the slots scanning them takes.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+struct Usage {
+    objects: usize,
+    slots: usize,
+}
+
+/// Keyed by shape and size in bytes. Objarrays, whose size goes with their
+/// length, all have size 0.
+type CountMap = HashMap<(ObjectShape, u64), Usage>;
 
 fn merge_counts(count_a: &mut CountMap, count_b: &CountMap) {
     for (key, val) in count_b.iter() {
-        *count_a.entry(key.clone()).or_default() += val;
+        let usage = count_a.entry(key.clone()).or_default();
+        usage.objects += val.objects;
+        usage.slots += val.slots;
+    }
+}
+
+fn usage_key(obj: &HeapObject) -> ((ObjectShape, u64), usize) {
+    let shape = ObjectShape::from_object(obj);
+    match obj.objarray_length {
+        Some(length) => ((shape, 0), length as usize),
+        None => ((shape, obj.size), obj.edges.len()),
     }
 }
 
@@ -55,9 +89,10 @@ fn analyze_one_file(path: &Path) -> Result<CountMap> {
         .fold(
             HashMap::new,
             |mut partial_count: CountMap, object: &HeapObject| {
-                *partial_count
-                    .entry(ObjectShape::from_object(object))
-                    .or_default() += 1;
+                let (key, slots) = usage_key(object);
+                let usage = partial_count.entry(key).or_default();
+                usage.objects += 1;
+                usage.slots += slots;
                 partial_count
             },
         )
@@ -124,11 +159,19 @@ pub(super) fn shape_demographic(
         })
         .collect();
 
+    if let Some(path) = &analysis_args.shape_inventory {
+        write_inventory(path, &bm_countmaps)?;
+    }
+
     let mut lfs = vec![];
     for (bm, count_map) in bm_countmaps {
-        let (shapes, counts): (Vec<Series>, Vec<u64>) = count_map
-            .iter()
-            .map(|(a, b)| (a.clone().into_array().iter().collect::<Series>(), *b as u64))
+        let mut shape_counts: HashMap<ObjectShape, usize> = HashMap::new();
+        for ((shape, _), usage) in count_map {
+            *shape_counts.entry(shape).or_default() += usage.objects;
+        }
+        let (shapes, counts): (Vec<Series>, Vec<u64>) = shape_counts
+            .into_iter()
+            .map(|(a, b)| (a.into_array().iter().collect::<Series>(), b as u64))
             .unzip();
         let lf: LazyFrame = df!(
             "shape" => &shapes,
@@ -153,3 +196,52 @@ pub(super) fn shape_demographic(
     writer.finish(&mut df)?;
     Ok(())
 }
+
+/// Writes every distinct shape and size of each benchmark, most common
+/// first, with the fraction of the objects and of the slots of the benchmark
+/// that the shapes up to it cover, i.e., what a shape cache holding that
+/// many entries could serve.
+fn write_inventory(path: &str, bm_countmaps: &[(&str, CountMap)]) -> Result<()> {
+    let mut output_file = File::create(path)?;
+    writeln!(
+        output_file,
+        "bm,rank,oop_map,size,objects,slots,object_coverage,slot_coverage"
+    )?;
+    for (bm, count_map) in bm_countmaps {
+        let mut shapes: Vec<(String, u64, Usage)> = count_map
+            .iter()
+            .map(|((shape, size), usage)| (shape.signature(), *size, *usage))
+            .collect();
+        // Ties broken by signature so that the ranks are stable across runs
+        shapes.sort_by(|a, b| {
+            (b.2.objects, b.2.slots, &a.0, a.1).cmp(&(a.2.objects, a.2.slots, &b.0, b.1))
+        });
+        let total_objects: usize = shapes.iter().map(|s| s.2.objects).sum();
+        let total_slots: usize = shapes.iter().map(|s| s.2.slots).sum();
+        let fraction = |n: usize, total: usize| {
+            if total == 0 {
+                0.0
+            } else {
+                n as f64 / total as f64
+            }
+        };
+        let (mut objects, mut slots) = (0, 0);
+        for (rank, (signature, size, usage)) in shapes.iter().enumerate() {
+            objects += usage.objects;
+            slots += usage.slots;
+            writeln!(
+                output_file,
+                "{},{},{},{},{},{},{:.6},{:.6}",
+                bm,
+                rank + 1,
+                signature,
+                size,
+                usage.objects,
+                usage.slots,
+                fraction(objects, total_objects),
+                fraction(slots, total_slots)
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Periodic progress lines on stderr for the phases that can run for hours
+//! on big heaps, enabled with `--progress`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Nanoseconds between two lines
+static INTERVAL_NS: AtomicU64 = AtomicU64::new(0);
+
+/// `add` only looks at the clock once per this many units, which keeps it
+/// cheap enough for per-object loops.
+const CHECK_EVERY: u64 = 1 << 12;
+
+/// Prints a line about each long phase every `interval` from now on.
+pub fn enable_progress(interval: Duration) {
+    INTERVAL_NS.store(interval.as_nanos() as u64, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn human(v: f64) -> String {
+    match v {
+        v if v >= 1e9 => format!("{:.2}G", v / 1e9),
+        v if v >= 1e6 => format!("{:.2}M", v / 1e6),
+        v if v >= 1e3 => format!("{:.2}K", v / 1e3),
+        v => format!("{:.0}", v),
+    }
+}
+
+/// Progress of one phase, counted in some unit. Can be shared by the
+/// threads of a parallel loop.
+pub(crate) struct Progress {
+    enabled: bool,
+    what: &'static str,
+    unit: &'static str,
+    /// Units in the whole phase, if known, for the percentage and the ETA
+    total: Option<u64>,
+    start: Instant,
+    done: AtomicU64,
+    /// Nanoseconds since `start` at which the next line is due
+    next_report_ns: AtomicU64,
+}
+
+impl Progress {
+    pub(crate) fn new(what: &'static str, unit: &'static str, total: Option<u64>) -> Self {
+        let interval = INTERVAL_NS.load(Ordering::Relaxed);
+        Progress {
+            enabled: ENABLED.load(Ordering::Relaxed),
+            what,
+            unit,
+            total,
+            start: Instant::now(),
+            done: AtomicU64::new(0),
+            next_report_ns: AtomicU64::new(interval),
+        }
+    }
+
+    /// Counts `n` more units done.
+    pub(crate) fn add(&self, n: u64) {
+        if !self.enabled {
+            return;
+        }
+        let done = self.done.fetch_add(n, Ordering::Relaxed) + n;
+        if (done - n) / CHECK_EVERY != done / CHECK_EVERY {
+            self.report_if_due(done, |_| String::new());
+        }
+    }
+
+    /// Sets the units done so far, for loops that count them anyway.
+    /// `detail` adds to the line, given the time since the start.
+    pub(crate) fn set(&self, done: u64, detail: impl FnOnce(Duration) -> String) {
+        if !self.enabled {
+            return;
+        }
+        self.done.store(done, Ordering::Relaxed);
+        self.report_if_due(done, detail);
+    }
+
+    fn report_if_due(&self, done: u64, detail: impl FnOnce(Duration) -> String) {
+        let elapsed = self.start.elapsed();
+        let now = elapsed.as_nanos() as u64;
+        let due = self.next_report_ns.load(Ordering::Relaxed);
+        if now < due {
+            return;
+        }
+        // Only one of the threads that see the line due prints it
+        let next = now + INTERVAL_NS.load(Ordering::Relaxed);
+        if self
+            .next_report_ns
+            .compare_exchange(due, next, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        eprintln!("{}", self.line(done, elapsed, &detail(elapsed)));
+    }
+
+    fn line(&self, done: u64, elapsed: Duration, detail: &str) -> String {
+        let secs = elapsed.as_secs_f64();
+        let rate = done as f64 / secs;
+        let mut line = format!("[progress] {}: {}", self.what, done);
+        if let Some(total) = self.total {
+            line += &format!(
+                "/{} {} ({:.1}%)",
+                total,
+                self.unit,
+                done as f64 * 100.0 / total.max(1) as f64
+            );
+        } else {
+            line += &format!(" {}", self.unit);
+        }
+        line += &format!(", {} {}/s", human(rate), self.unit);
+        if !detail.is_empty() {
+            line += &format!(", {}", detail);
+        }
+        if let Some(total) = self.total {
+            if done > 0 && done < total {
+                let eta = (total - done) as f64 / rate;
+                line += &format!(", ETA {:.0}s", eta);
+            }
+        }
+        line
+    }
+
+    /// Prints how long the phase took.
+    pub(crate) fn finish(&self) {
+        if !self.enabled {
+            return;
+        }
+        let done = self.done.load(Ordering::Relaxed);
+        eprintln!(
+            "[progress] {}: {} {} in {:.1}s",
+            self.what,
+            done,
+            self.unit,
+            self.start.elapsed().as_secs_f64()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        let progress = Progress::new("Restoring", "objects", Some(4000));
+        assert_eq!(
+            progress.line(1000, Duration::from_secs(2), ""),
+            "[progress] Restoring: 1000/4000 objects (25.0%), 500 objects/s, ETA 6s"
+        );
+        let progress = Progress::new("Simulating", "objects", None);
+        assert_eq!(
+            progress.line(3_000_000, Duration::from_secs(1), "12 ticks"),
+            "[progress] Simulating: 3000000 objects, 3.00M objects/s, 12 ticks"
+        );
+    }
+}
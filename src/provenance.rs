@@ -0,0 +1,126 @@
+//! Versions of the heapdump format, and where each heapdump was taken.
+//!
+//! A heapdump records the version of `heapdump.proto` it was written in,
+//! and loading one newer than this build reads fails, rather than going on
+//! without the fields it does not know. Version 0 is that of heapdumps
+//! written before the format was versioned, which read the same as version
+//! 1 without provenance. Once loaded, and converted to this host, a heapdump
+//! is in the current version, which is what gets written back out.
+//!
+//! The provenance of every heapdump loaded is kept by path, so that the
+//! commands can print it and `--results` can record it, and a run over
+//! heapdumps of different JVM or MMTk builds is warned about, as their
+//! object layouts need not agree.
+
+use crate::heapdump::generated_src::Provenance;
+use crate::HeapDump;
+use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Version of `heapdump.proto` this build reads and writes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The provenance of the heapdumps loaded, by path
+static LOADED: Lazy<Mutex<HashMap<String, Option<Provenance>>>> = Lazy::new(Default::default);
+
+impl HeapDump {
+    /// Fails if the heapdump at `path` is of a newer version than this
+    /// build reads.
+    pub(crate) fn check_schema_version(&self, path: &str) -> Result<()> {
+        if self.schema_version > SCHEMA_VERSION {
+            bail!(
+                "{} is a version {} heapdump, but this build of hwgc-soft only reads up to version {}; update hwgc-soft to read it",
+                path,
+                self.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown = |s: &str| if s.is_empty() { "unknown" } else { s }.to_string();
+        write!(
+            f,
+            "{} iteration {}, JVM {}, MMTk {}",
+            or_unknown(&self.benchmark),
+            self.iteration,
+            or_unknown(&self.jvm_version),
+            or_unknown(&self.mmtk_build)
+        )
+    }
+}
+
+/// Keeps the provenance of the heapdump loaded from `path`.
+pub(crate) fn record(path: &str, heapdump: &HeapDump) {
+    LOADED
+        .lock()
+        .unwrap()
+        .insert(path.to_string(), heapdump.provenance.clone());
+}
+
+/// The provenance of the heapdump loaded from `path`, if it has any.
+pub(crate) fn of(path: &str) -> Option<Provenance> {
+    LOADED.lock().unwrap().get(path).cloned().flatten()
+}
+
+/// Prints where each heapdump at `paths` was taken, and warns if they come
+/// from different builds.
+pub fn report_provenance(paths: &[String]) {
+    let mut builds: Vec<(&str, &str, String)> = vec![];
+    let loaded = LOADED.lock().unwrap();
+    for path in paths {
+        match loaded.get(path).and_then(Option::as_ref) {
+            Some(provenance) => {
+                println!("Heapdump {}: {}", path, provenance);
+                builds.push((
+                    &provenance.jvm_version,
+                    &provenance.mmtk_build,
+                    path.clone(),
+                ));
+            }
+            None => println!("Heapdump {}: no provenance recorded", path),
+        }
+    }
+    if let Some((jvm, mmtk, first)) = builds.first() {
+        for (other_jvm, other_mmtk, path) in &builds[1..] {
+            if (jvm, mmtk) != (other_jvm, other_mmtk) {
+                warn!(
+                    "{} and {} come from different builds (JVM {} and {}, MMTk {} and {}), whose object layouts may differ",
+                    first, path, jvm, other_jvm, mmtk, other_mmtk
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_version() {
+        let mut heapdump = HeapDump {
+            provenance: Some(Provenance {
+                benchmark: "lusearch".into(),
+                iteration: 5,
+                jvm_version: "11.0.19".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(heapdump.check_schema_version("old").is_ok());
+        heapdump.schema_version = SCHEMA_VERSION + 1;
+        let error = heapdump.check_schema_version("new").unwrap_err();
+        assert!(error.to_string().contains("update hwgc-soft"));
+        assert_eq!(
+            heapdump.provenance.unwrap().to_string(),
+            "lusearch iteration 5, JVM 11.0.19, MMTk unknown"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Python bindings, built with the `python` feature. Tables are returned as
+//! dicts of equal-length columns, which `pandas.DataFrame` takes as is, so
+//! notebooks do not have to parse the printed tables.
+
+use crate::analysis::analyze_heapdump;
+use crate::analysis::depth::critical_path;
+use crate::heap_stats::{HeapStats, Histogram, SpaceStats};
+use crate::*;
+use clap::{Parser, ValueEnum};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+fn histogram<'py>(py: Python<'py>, histogram: &Histogram) -> PyResult<Bound<'py, PyDict>> {
+    // Bucket i holds the values in [2^(i-1), 2^i), see `Histogram`
+    let dict = PyDict::new(py);
+    let lower: Vec<u64> = (0..histogram.buckets.len())
+        .map(|i| if i == 0 { 0 } else { 1 << (i - 1) })
+        .collect();
+    dict.set_item("lower", lower)?;
+    dict.set_item("count", &histogram.buckets)?;
+    Ok(dict)
+}
+
+fn restored_critical_path<O: ObjectModel>(
+    mut object_model: O,
+    heapdump: &HeapDump,
+) -> anyhow::Result<(u64, u64, f64)> {
+    heapdump.map_spaces()?;
+    object_model.restore_tibs(heapdump);
+    object_model.restore_objects(heapdump);
+    let cp = critical_path(&object_model);
+    heapdump.unmap_spaces()?;
+    Ok((cp.length, cp.total_work, cp.speedup_bound()))
+}
+
+#[pyclass(name = "HeapDump")]
+struct PyHeapDump(HeapDump);
+
+#[pymethods]
+impl PyHeapDump {
+    /// Loads a heapdump, or a synthetic one such as
+    /// "[synthetic]objarray_4000".
+    #[new]
+    fn new(path: &str) -> PyResult<Self> {
+        Ok(PyHeapDump(HeapDump::from_path(path)?))
+    }
+
+    /// Keeps only the roots of the named root sets, see `--roots`.
+    fn select_roots(&mut self, names: Vec<String>) -> PyResult<()> {
+        Ok(self.0.select_roots(&names)?)
+    }
+
+    /// One row per object. `objarray_length` is -1 for other objects.
+    fn objects<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let objects = &self.0.objects;
+        let dict = PyDict::new(py);
+        let column = |f: fn(&HeapObject) -> u64| objects.iter().map(f).collect::<Vec<u64>>();
+        dict.set_item("start", column(|o| o.start))?;
+        dict.set_item("klass", column(|o| o.klass))?;
+        dict.set_item("size", column(|o| o.size))?;
+        dict.set_item("slots", column(|o| o.edges.len() as u64))?;
+        dict.set_item(
+            "objarray_length",
+            objects
+                .iter()
+                .map(|o| o.objarray_length.map_or(-1, |l| l as i64))
+                .collect::<Vec<i64>>(),
+        )?;
+        dict.set_item(
+            "pinned",
+            objects.iter().map(|o| o.pinned).collect::<Vec<bool>>(),
+        )?;
+        Ok(dict)
+    }
+
+    /// One row per slot, with the index of the object it belongs to. Null
+    /// slots have an `objref` of 0.
+    fn edges<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let mut object = vec![];
+        let mut slot = vec![];
+        let mut objref = vec![];
+        for (i, o) in self.0.objects.iter().enumerate() {
+            for e in &o.edges {
+                object.push(i as u64);
+                slot.push(e.slot);
+                objref.push(e.objref);
+            }
+        }
+        let dict = PyDict::new(py);
+        dict.set_item("object", object)?;
+        dict.set_item("slot", slot)?;
+        dict.set_item("objref", objref)?;
+        Ok(dict)
+    }
+
+    /// Every root, ungrouped or in a root set.
+    fn roots(&self) -> Vec<u64> {
+        self.0.all_roots().map(|r| r.objref).collect()
+    }
+
+    fn spaces<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let spaces = &self.0.spaces;
+        let dict = PyDict::new(py);
+        dict.set_item(
+            "name",
+            spaces.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+        )?;
+        dict.set_item("start", spaces.iter().map(|s| s.start).collect::<Vec<_>>())?;
+        dict.set_item("end", spaces.iter().map(|s| s.end).collect::<Vec<_>>())?;
+        Ok(dict)
+    }
+
+    /// What the `stats` subcommand prints, with the per-space table under
+    /// "spaces" and the histograms under "sizes" and "outdegrees".
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = HeapStats::new(&self.0);
+        let dict = PyDict::new(py);
+        dict.set_item("objects", stats.objects)?;
+        dict.set_item("live_objects", stats.live_objects)?;
+        dict.set_item("roots", stats.roots)?;
+        dict.set_item("klasses", stats.klasses)?;
+        dict.set_item("slots", stats.slots)?;
+        dict.set_item("objarrays", stats.objarrays)?;
+        dict.set_item("pinned", stats.pinned)?;
+        dict.set_item("root_sets", stats.root_sets)?;
+        let spaces = PyDict::new(py);
+        let column = |f: fn(&SpaceStats) -> u64| {
+            stats.spaces.iter().map(|(_, s)| f(s)).collect::<Vec<u64>>()
+        };
+        spaces.set_item(
+            "name",
+            stats.spaces.iter().map(|(n, _)| n).collect::<Vec<_>>(),
+        )?;
+        spaces.set_item("objects", column(|s| s.objects))?;
+        spaces.set_item("bytes", column(|s| s.bytes))?;
+        spaces.set_item("live_objects", column(|s| s.live_objects))?;
+        spaces.set_item("live_bytes", column(|s| s.live_bytes))?;
+        dict.set_item("spaces", spaces)?;
+        dict.set_item("sizes", histogram(py, &stats.sizes)?)?;
+        dict.set_item("outdegrees", histogram(py, &stats.outdegrees)?)?;
+        Ok(dict)
+    }
+
+    /// The tabulated statistics of the `analyze` subcommand, by column
+    /// name. Arguments left out take the defaults of the subcommand.
+    #[pyo3(signature = (owner_shift=None, log_num_threads=None, rle=false, eager_load=false, owner_hash=None))]
+    fn analyze<'py>(
+        &self,
+        py: Python<'py>,
+        owner_shift: Option<usize>,
+        log_num_threads: Option<usize>,
+        rle: bool,
+        eager_load: bool,
+        owner_hash: Option<u64>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let mut args = AnalysisArgs::parse_from(["analyze"]);
+        args.owner_shift = owner_shift.unwrap_or(args.owner_shift);
+        args.log_num_threads = log_num_threads.unwrap_or(args.log_num_threads);
+        args.rle = rle;
+        args.eager_load = eager_load;
+        args.owner_hash = owner_hash;
+        let dict = PyDict::new(py);
+        for (name, value) in analyze_heapdump(&self.0, args)? {
+            dict.set_item(name, value)?;
+        }
+        Ok(dict)
+    }
+
+    /// Length of the scan-cost weighted critical path, total scan work and
+    /// the speedup bound they imply, see the `depth` subcommand.
+    #[pyo3(signature = (object_model="OpenJDK"))]
+    fn critical_path<'py>(
+        &self,
+        py: Python<'py>,
+        object_model: &str,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let choice =
+            ObjectModelChoice::from_str(object_model, false).map_err(PyValueError::new_err)?;
+        let heapdump = &self.0;
+        let (length, total_work, speedup_bound) = match choice {
+            ObjectModelChoice::OpenJDK => {
+                restored_critical_path(OpenJDKObjectModel::<false>::new(), heapdump)
+            }
+            ObjectModelChoice::OpenJDKAE => {
+                restored_critical_path(OpenJDKObjectModel::<true>::new(), heapdump)
+            }
+            ObjectModelChoice::Bidirectional => {
+                restored_critical_path(BidirectionalObjectModel::<true>::new(), heapdump)
+            }
+            ObjectModelChoice::BidirectionalFallback => {
+                restored_critical_path(BidirectionalObjectModel::<false>::new(), heapdump)
+            }
+        }?;
+        let dict = PyDict::new(py);
+        dict.set_item("length", length)?;
+        dict.set_item("total_work", total_work)?;
+        dict.set_item("speedup_bound", speedup_bound)?;
+        Ok(dict)
+    }
+}
+
+#[pymodule]
+fn hwgc_soft(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyHeapDump>()?;
+    Ok(())
+}
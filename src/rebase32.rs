@@ -0,0 +1,360 @@
+//! Moving the live objects of a heapdump below 4 GiB (`rebase32`), for
+//! accelerator prototypes that only address 32 bits. Spaces are packed one
+//! after the other from `--base`, keeping the page offsets of their objects.
+//! With `--compress-slots`, every slot shrinks to 4 bytes, as with
+//! compressed oops, and the objects of each space are packed back to back.
+//!
+//! The addresses no longer carry the space type bits the object models and
+//! the simulator rely on, so the result is meant for memdump generators and
+//! other tools outside of this one.
+
+use crate::heapdump::generated_src::{NormalEdge, RootSet, Space};
+use crate::util::bump::BumpAllocator;
+use crate::*;
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+const LIMIT: u64 = 1 << 32;
+const PAGE_SIZE: u64 = 4096;
+/// Bytes of a compressed slot
+const COMPRESSED_SLOT: u64 = 4;
+
+/// Objects reachable from every root of `heapdump`, by index.
+fn live_objects(heapdump: &HeapDump) -> Vec<bool> {
+    let index: HashMap<u64, usize> = heapdump
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(i, o)| (o.start, i))
+        .collect();
+    let mut live = vec![false; heapdump.objects.len()];
+    let mut stack: Vec<u64> = heapdump.all_roots().map(|r| r.objref).collect();
+    while let Some(o) = stack.pop() {
+        let Some(&i) = index.get(&o) else {
+            continue;
+        };
+        if !live[i] {
+            live[i] = true;
+            let edges = &heapdump.objects[i].edges;
+            stack.extend(edges.iter().map(|e| e.objref).filter(|&r| r != 0));
+        }
+    }
+    live
+}
+
+/// Where `offset` into an object ends up once every slot before it shrinks,
+/// given the sorted offsets of its slots.
+fn compressed_offset(slot_offsets: &[u64], offset: u64) -> u64 {
+    let slots_before = slot_offsets.partition_point(|&s| s < offset) as u64;
+    offset - slots_before * (8 - COMPRESSED_SLOT)
+}
+
+fn compressed_size(object: &HeapObject) -> u64 {
+    (object.size - object.edges.len() as u64 * (8 - COMPRESSED_SLOT)).next_multiple_of(8)
+}
+
+/// The live objects of a space and where they go.
+struct SpaceLayout {
+    name: String,
+    /// Indices of the live objects, by address
+    objects: Vec<usize>,
+    live_bytes: u64,
+    /// Bytes from the start of the first live object to the end of the last
+    extent: u64,
+    new_start: u64,
+}
+
+/// What a heapdump needs to fit below 4 GiB.
+struct Layout {
+    spaces: Vec<SpaceLayout>,
+    /// End of the last space
+    end: u64,
+    live_objects: usize,
+    /// Live objects outside of every space, which cannot be placed
+    outside: usize,
+    live_bytes: u64,
+    /// Live bytes with compressed slots
+    compressed_bytes: u64,
+}
+
+impl Layout {
+    fn new(heapdump: &HeapDump, base: u64, compress_slots: bool) -> Layout {
+        let live = live_objects(heapdump);
+        let mut spaces: Vec<&Space> = heapdump.spaces.iter().collect();
+        spaces.sort_by_key(|s| s.start);
+        let mut memory = BumpAllocator::new(base, u64::MAX);
+        memory.align_to(PAGE_SIZE);
+        let mut layouts = vec![];
+        let (mut live_objects, mut live_bytes, mut compressed_bytes) = (0, 0, 0);
+        for space in spaces {
+            let mut objects: Vec<usize> = (0..heapdump.objects.len())
+                .filter(|&i| {
+                    live[i] && (space.start..space.end).contains(&heapdump.objects[i].start)
+                })
+                .collect();
+            if objects.is_empty() {
+                continue;
+            }
+            objects.sort_by_key(|&i| heapdump.objects[i].start);
+            let sizes = objects.iter().map(|&i| &heapdump.objects[i]);
+            let space_bytes: u64 = sizes.clone().map(|o| o.size).sum();
+            let space_compressed: u64 = sizes.map(compressed_size).sum();
+            let first = &heapdump.objects[objects[0]];
+            let last = &heapdump.objects[*objects.last().unwrap()];
+            // Spaces start on a page of their own, at the same page offset
+            // unless the objects are packed
+            let (offset, extent) = if compress_slots {
+                (0, space_compressed)
+            } else {
+                (
+                    first.start % PAGE_SIZE,
+                    last.start + last.size - first.start,
+                )
+            };
+            let new_start = memory.alloc_aligned(extent, PAGE_SIZE, offset).unwrap();
+            memory.align_to(PAGE_SIZE);
+            live_objects += objects.len();
+            live_bytes += space_bytes;
+            compressed_bytes += space_compressed;
+            layouts.push(SpaceLayout {
+                name: space.name.clone(),
+                objects,
+                live_bytes: space_bytes,
+                extent,
+                new_start,
+            });
+        }
+        Layout {
+            spaces: layouts,
+            end: memory.cursor(),
+            outside: live.iter().filter(|&&l| l).count() - live_objects,
+            live_objects,
+            live_bytes,
+            compressed_bytes,
+        }
+    }
+
+    fn print(&self, heapdump: &HeapDump) {
+        println!(
+            "{} of {} objects live, {} bytes ({} with 4-byte slots), laid out up to 0x{:x} ({:.1}% of 4 GiB)",
+            self.live_objects,
+            heapdump.objects.len(),
+            self.live_bytes,
+            self.compressed_bytes,
+            self.end,
+            self.end as f64 * 100.0 / LIMIT as f64
+        );
+        for s in &self.spaces {
+            println!(
+                "  {:<16} {:>10} objects {:>14} live bytes {:>14} bytes from 0x{:x}",
+                s.name,
+                s.objects.len(),
+                s.live_bytes,
+                s.extent,
+                s.new_start
+            );
+        }
+    }
+}
+
+/// A copy of the live objects of `heapdump`, moved as `layout` says.
+fn rebased(heapdump: &HeapDump, layout: &Layout, compress_slots: bool) -> Result<HeapDump> {
+    // Old start of every live object, and its new start
+    let mut moved: HashMap<u64, u64> = HashMap::new();
+    for s in &layout.spaces {
+        let first = heapdump.objects[s.objects[0]].start;
+        let mut cursor = s.new_start;
+        for &i in &s.objects {
+            let o = &heapdump.objects[i];
+            if compress_slots {
+                moved.insert(o.start, cursor);
+                cursor += compressed_size(o);
+            } else {
+                moved.insert(o.start, s.new_start + (o.start - first));
+            }
+        }
+    }
+    let reference = |r: u64| -> Result<u64> {
+        if r == 0 {
+            return Ok(0);
+        }
+        moved
+            .get(&r)
+            .copied()
+            .ok_or_else(|| anyhow!("0x{:x} is not the start of a live object", r))
+    };
+    let mut objects = vec![];
+    for s in &layout.spaces {
+        for &i in &s.objects {
+            let o = &heapdump.objects[i];
+            let start = moved[&o.start];
+            let mut slot_offsets: Vec<u64> = o.edges.iter().map(|e| e.slot - o.start).collect();
+            slot_offsets.sort_unstable();
+            let offset = |addr: u64| {
+                let offset = addr - o.start;
+                start
+                    + if compress_slots {
+                        compressed_offset(&slot_offsets, offset)
+                    } else {
+                        offset
+                    }
+            };
+            let edges = o
+                .edges
+                .iter()
+                .map(|e| {
+                    Ok(NormalEdge {
+                        slot: offset(e.slot),
+                        objref: reference(e.objref)?,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            objects.push(HeapObject {
+                start,
+                klass: o.klass,
+                size: if compress_slots {
+                    compressed_size(o)
+                } else {
+                    o.size
+                },
+                objarray_length: o.objarray_length,
+                instance_mirror_start: o.instance_mirror_start.map(offset),
+                instance_mirror_count: o.instance_mirror_count,
+                edges,
+                pinned: o.pinned,
+            });
+        }
+    }
+    let rebase_roots = |roots: &[RootEdge]| {
+        roots
+            .iter()
+            .map(|r| {
+                Ok(RootEdge {
+                    objref: reference(r.objref)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    let root_sets = heapdump
+        .root_sets
+        .iter()
+        .map(|s| {
+            Ok(RootSet {
+                name: s.name.clone(),
+                roots: rebase_roots(&s.roots)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let spaces = layout
+        .spaces
+        .iter()
+        .map(|s| {
+            let start = s.new_start - s.new_start % PAGE_SIZE;
+            Space {
+                name: s.name.clone(),
+                start,
+                end: (s.new_start + s.extent).next_multiple_of(PAGE_SIZE),
+            }
+        })
+        .collect();
+    Ok(HeapDump {
+        objects,
+        roots: rebase_roots(&heapdump.roots)?,
+        spaces,
+        root_sets,
+        ..Default::default()
+    })
+}
+
+pub fn rebase32(args: &Args) -> Result<()> {
+    let Some(Commands::Rebase32(rebase_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    if args.paths.len() != 1 {
+        bail!("Can only rebase one heap dump at a time");
+    }
+    let mut heapdump = HeapDump::from_path(&args.paths[0])?;
+    heapdump.select_roots(&args.roots)?;
+    let layout = Layout::new(&heapdump, rebase_args.base, rebase_args.compress_slots);
+    layout.print(&heapdump);
+    if layout.outside > 0 {
+        bail!(
+            "{} live objects are outside of every space of the heapdump",
+            layout.outside
+        );
+    }
+    if layout.end > LIMIT {
+        let hint = if !rebase_args.compress_slots
+            && rebase_args.base + layout.compressed_bytes + layout.spaces.len() as u64 * PAGE_SIZE
+                <= LIMIT
+        {
+            "; --compress-slots would pack it into 32 bits"
+        } else {
+            "; select fewer root sets with --roots to shrink the live set"
+        };
+        bail!(
+            "The live set needs addresses up to 0x{:x}, {} bytes over 4 GiB{}",
+            layout.end,
+            layout.end - LIMIT,
+            hint
+        );
+    }
+    let Some(output_path) = &rebase_args.output_path else {
+        return Ok(());
+    };
+    let rebased = rebased(&heapdump, &layout, rebase_args.compress_slots)?;
+    rebased.to_binpb_zst(output_path)?;
+    info!(
+        "Wrote {} objects below 0x{:x} to {}",
+        rebased.objects.len(),
+        layout.end,
+        output_path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase32() {
+        let mut heapdump = HeapDump::from_path("[synthetic]objarray_64").unwrap();
+        // Unreachable, and far enough to not fit below 4 GiB otherwise
+        let far = heapdump.spaces[0].start + (5 << 30);
+        heapdump.spaces[0].end = far + 0x1000;
+        heapdump.objects.push(HeapObject {
+            start: far,
+            klass: 0x42,
+            size: 24,
+            objarray_length: None,
+            instance_mirror_start: None,
+            instance_mirror_count: None,
+            edges: vec![],
+            pinned: false,
+        });
+        let layout = Layout::new(&heapdump, 0x10000, false);
+        assert_eq!((layout.live_objects, layout.outside), (65, 0));
+        assert!(layout.end <= LIMIT);
+        let moved = rebased(&heapdump, &layout, false).unwrap();
+        assert_eq!(moved.objects.len(), 65);
+        let offset = heapdump.objects[0].start % PAGE_SIZE;
+        assert_eq!(moved.objects[0].start, 0x10000 + offset);
+        for (o, r) in heapdump.objects.iter().zip(&moved.objects) {
+            assert_eq!(o.size, r.size);
+            for (e, f) in o.edges.iter().zip(&r.edges) {
+                assert_eq!(e.slot - o.start, f.slot - r.start);
+            }
+        }
+
+        // The objarray of 64 slots shrinks by 4 bytes per slot
+        let layout = Layout::new(&heapdump, 0x10000, true);
+        let compressed = rebased(&heapdump, &layout, true).unwrap();
+        let array = &compressed.objects[0];
+        assert_eq!(array.start, 0x10000);
+        assert_eq!(array.size, heapdump.objects[0].size - 64 * 4);
+        assert_eq!(array.edges[1].slot - array.edges[0].slot, 4);
+        assert_eq!(array.edges[0].objref, compressed.objects[1].start);
+        assert_eq!(compressed.objects[1].start, array.start + array.size);
+    }
+}
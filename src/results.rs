@@ -0,0 +1,238 @@
+//! Results of a run in one structure, from which both the tabulate blocks on
+//! stdout and the JSON written with `--results` are generated, so that the
+//! two never disagree.
+
+use crate::*;
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+
+/// Version of the build, as the short commit hash with `-dirty` appended if
+/// the tree had uncommitted changes.
+pub fn git_version() -> String {
+    match (built_info::GIT_COMMIT_HASH, built_info::GIT_DIRTY) {
+        (Some(hash), Some(dirty)) => format!(
+            "{}{}",
+            hash.split_at(7).0,
+            if dirty { "-dirty" } else { "" }
+        ),
+        (Some(hash), None) => format!("{}{}", hash.split_at(7).0, "-?"),
+        _ => "unknown-git-version".to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Stat {
+    Count(u64),
+    Real(f64),
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stat::Count(v) => write!(f, "{}", v),
+            Stat::Real(v) => write!(f, "{:.3}", v),
+        }
+    }
+}
+
+impl From<u64> for Stat {
+    fn from(v: u64) -> Self {
+        Stat::Count(v)
+    }
+}
+
+impl From<f64> for Stat {
+    fn from(v: f64) -> Self {
+        Stat::Real(v)
+    }
+}
+
+/// One tabulate block: named statistics, in the order they are printed.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct StatsTable {
+    /// What produced the statistics, such as "trace"
+    pub(crate) name: String,
+    /// The heapdump after which the statistics were taken, for blocks
+    /// printed per heapdump
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) heapdump: Option<String>,
+    #[serde(serialize_with = "serialize_columns")]
+    pub(crate) columns: Vec<(String, Stat)>,
+}
+
+fn serialize_columns<S: serde::Serializer>(
+    columns: &[(String, Stat)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(columns.iter().map(|(k, v)| (k, v)))
+}
+
+impl StatsTable {
+    pub(crate) fn new(name: &str, heapdump: Option<&str>) -> Self {
+        StatsTable {
+            name: name.to_string(),
+            heapdump: heapdump.map(|h| h.to_string()),
+            columns: vec![],
+        }
+    }
+
+    pub(crate) fn push(&mut self, name: impl Into<String>, value: impl Into<Stat>) {
+        self.columns.push((name.into(), value.into()));
+    }
+
+    /// The header line and the value line, without the block delimiters.
+    fn lines(&self) -> (String, String) {
+        let (names, values): (Vec<&str>, Vec<String>) = self
+            .columns
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_string()))
+            .unzip();
+        (names.join("\t"), values.join("\t"))
+    }
+
+    pub(crate) fn print(&self) {
+        let (names, values) = self.lines();
+        println!("============================ Tabulate Statistics ============================");
+        println!("{}", names);
+        println!("{}", values);
+        println!("-------------------------- End Tabulate Statistics --------------------------");
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HeapdumpInfo {
+    path: String,
+    /// FNV-1a of the file, or of the name of a synthetic heapdump
+    checksum: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provenance: Option<ProvenanceInfo>,
+}
+
+/// The provenance recorded in a heapdump, see `heapdump.proto`.
+#[derive(Debug, Serialize)]
+struct ProvenanceInfo {
+    jvm_version: String,
+    mmtk_build: String,
+    benchmark: String,
+    iteration: u32,
+}
+
+/// Everything `--results` writes about a run.
+#[derive(Debug, Serialize)]
+pub(crate) struct RunResults {
+    #[serde(skip)]
+    output: Option<String>,
+    version: String,
+    dramsim3_version: &'static str,
+    command_line: Vec<String>,
+    heapdumps: Vec<HeapdumpInfo>,
+    /// Whether ctrl-C stopped the run, so that its statistics only cover
+    /// part of it
+    truncated: bool,
+    tables: Vec<StatsTable>,
+}
+
+fn checksum(path: &str) -> Result<u64> {
+    if path.starts_with("[synthetic]") {
+        return Ok(util::fnv1a(path.bytes()));
+    }
+    Ok(util::fnv1a(std::fs::read(path)?))
+}
+
+impl RunResults {
+    /// Results of a run over the heapdumps at `paths`, written to `output`
+    /// as given to `--results`.
+    pub(crate) fn new(paths: &[String], output: Option<String>) -> Result<Self> {
+        let heapdumps = if output.is_some() {
+            paths
+                .iter()
+                .map(|path| {
+                    Ok(HeapdumpInfo {
+                        path: path.clone(),
+                        checksum: format!("{:016x}", checksum(path)?),
+                        provenance: provenance::of(path).map(|p| ProvenanceInfo {
+                            jvm_version: p.jvm_version,
+                            mmtk_build: p.mmtk_build,
+                            benchmark: p.benchmark,
+                            iteration: p.iteration,
+                        }),
+                    })
+                })
+                .collect::<Result<_>>()?
+        } else {
+            // Reading every heapdump once more is not free
+            vec![]
+        };
+        Ok(RunResults {
+            output,
+            version: git_version(),
+            dramsim3_version: env!("DRAMSIM3_GIT_HASH"),
+            command_line: std::env::args().collect(),
+            heapdumps,
+            truncated: false,
+            tables: vec![],
+        })
+    }
+
+    /// Prints `table` as a tabulate block and keeps it for `--results`.
+    pub(crate) fn add_table(&mut self, table: StatsTable) {
+        table.print();
+        self.tables.push(table);
+    }
+
+    /// Marks the run, and `table` with the statistics of the part that was
+    /// cut short, as truncated.
+    pub(crate) fn truncate(&mut self, table: &mut StatsTable) {
+        println!("===== Run stopped early with ctrl-C, the statistics are partial =====");
+        self.truncated = true;
+        table.push("truncated", 1u64);
+    }
+
+    /// Keeps `table` for `--results` only, for tables printed another way.
+    pub(crate) fn keep_table(&mut self, table: StatsTable) {
+        self.tables.push(table);
+    }
+
+    /// Writes the results as JSON to the path given to `--results`, or to
+    /// stdout between delimiter lines if the path is "-".
+    pub(crate) fn write(&self) -> Result<()> {
+        let Some(path) = &self.output else {
+            return Ok(());
+        };
+        if path == "-" {
+            println!(
+                "================================ JSON Results ================================"
+            );
+            println!("{}", serde_json::to_string(self)?);
+            println!(
+                "------------------------------ End JSON Results ------------------------------"
+            );
+        } else {
+            serde_json::to_writer_pretty(File::create(path)?, self)?;
+            info!("Wrote the results to {}", path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_lines_and_json() {
+        let mut table = StatsTable::new("test", None);
+        table.push("objects", 42u64);
+        table.push("ratio", 0.5);
+        let (names, values) = table.lines();
+        assert_eq!(names, "objects\tratio");
+        assert_eq!(values, "42\t0.500");
+        assert_eq!(
+            serde_json::to_string(&table).unwrap(),
+            r#"{"name":"test","columns":{"objects":42,"ratio":0.5}}"#
+        );
+    }
+}
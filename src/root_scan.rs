@@ -0,0 +1,170 @@
+//! Thread stacks synthesized from the roots of a heapdump (`--root-stacks`),
+//! so that enumerating the roots has a cost. Heapdumps only record the roots
+//! as a flat array, so the roots are dealt out to the stacks in order, a few
+//! per frame, and scanning a frame means looking up its stack map and
+//! reading the slots the map points at.
+
+use crate::util::bump::BumpAllocator;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Where the synthesized stacks live, one after the other, out of the way
+/// of the spaces of the heapdumps
+const STACK_BASE: u64 = 0x700_0000_0000;
+const STACK_LIMIT: u64 = 0x780_0000_0000;
+const STACK_ALIGNMENT: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Frame {
+    /// Address of the first word of the frame
+    pub(crate) start: u64,
+    pub(crate) words: usize,
+    /// The stack map of the frame: the word offset of every slot holding a
+    /// reference, with the reference
+    pub(crate) roots: Vec<(usize, u64)>,
+}
+
+impl Frame {
+    pub(crate) fn slot(&self, offset: usize) -> u64 {
+        self.start + offset as u64 * 8
+    }
+}
+
+pub(crate) struct RootStacks {
+    /// Frames of each stack, from the bottom of the stack
+    pub(crate) stacks: Vec<Vec<Frame>>,
+}
+
+impl RootStacks {
+    /// Checks the shape of the stacks before any heapdump is loaded.
+    pub(crate) fn check(threads: usize, frame_roots: usize, frame_words: usize) -> Result<()> {
+        if threads == 0 || frame_roots == 0 {
+            bail!("Root stacks need at least one thread and one root per frame");
+        }
+        if frame_words < frame_roots {
+            bail!(
+                "Frames of {} words cannot hold {} roots",
+                frame_words,
+                frame_roots
+            );
+        }
+        Ok(())
+    }
+
+    /// Deals `roots` out to `threads` stacks, in contiguous runs, and each
+    /// run into frames of `frame_words` words holding `frame_roots` roots
+    /// spread evenly over the frame.
+    pub(crate) fn synthesize(
+        roots: &[u64],
+        threads: usize,
+        frame_roots: usize,
+        frame_words: usize,
+    ) -> Result<Self> {
+        Self::check(threads, frame_roots, frame_words)?;
+        let per_stack = roots.len().div_ceil(threads).max(1);
+        let mut memory = BumpAllocator::new(STACK_BASE, STACK_LIMIT);
+        let mut stacks = vec![];
+        for t in 0..threads {
+            let run = roots.get(t * per_stack..).unwrap_or(&[]);
+            let run = &run[..run.len().min(per_stack)];
+            let mut frames = vec![];
+            for chunk in run.chunks(frame_roots) {
+                let roots = chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &r)| (i * frame_words / frame_roots, r))
+                    .collect();
+                let Some(start) = memory.alloc(frame_words as u64 * 8, 8) else {
+                    bail!("The root stacks do not fit below 0x{:x}", STACK_LIMIT);
+                };
+                frames.push(Frame {
+                    start,
+                    words: frame_words,
+                    roots,
+                });
+            }
+            memory.align_to(STACK_ALIGNMENT);
+            stacks.push(frames);
+        }
+        Ok(RootStacks { stacks })
+    }
+
+    pub(crate) fn frames(&self) -> usize {
+        self.stacks.iter().map(|s| s.len()).sum()
+    }
+
+    pub(crate) fn root_slots(&self) -> usize {
+        self.stacks.iter().flatten().map(|f| f.roots.len()).sum()
+    }
+
+    /// Lays the stacks out in memory and times walking them from the top
+    /// frame down, reading every slot of the stack maps. Returns the time
+    /// and the non-null references found.
+    pub(crate) fn scan_on_host(&self) -> (Duration, Vec<u64>) {
+        let memory: Vec<Vec<u64>> = self
+            .stacks
+            .iter()
+            .map(|frames| {
+                let mut words = vec![0u64; frames.iter().map(|f| f.words).sum()];
+                let mut base = 0;
+                for f in frames {
+                    for &(offset, r) in &f.roots {
+                        words[base + offset] = r;
+                    }
+                    base += f.words;
+                }
+                words
+            })
+            .collect();
+        let maps: Vec<Vec<Vec<usize>>> = self
+            .stacks
+            .iter()
+            .map(|frames| {
+                frames
+                    .iter()
+                    .map(|f| f.roots.iter().map(|&(offset, _)| offset).collect())
+                    .collect()
+            })
+            .collect();
+        let mut found = Vec::with_capacity(self.root_slots());
+        let start = Instant::now();
+        for (stack, (words, maps)) in self.stacks.iter().zip(memory.iter().zip(&maps)) {
+            let mut top = words.len();
+            for (frame, map) in stack.iter().zip(maps).rev() {
+                top -= frame.words;
+                for &offset in map {
+                    let r = std::hint::black_box(words[top + offset]);
+                    if r != 0 {
+                        found.push(r);
+                    }
+                }
+            }
+        }
+        (start.elapsed(), found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthesize() {
+        let roots: Vec<u64> = (1..=10).map(|r| r * 16).collect();
+        let stacks = RootStacks::synthesize(&roots, 3, 2, 8).unwrap();
+        // 4 roots on each of the first two stacks and 2 on the last
+        let frames: Vec<usize> = stacks.stacks.iter().map(|s| s.len()).collect();
+        assert_eq!(frames, vec![2, 2, 1]);
+        assert_eq!(stacks.frames(), 5);
+        assert_eq!(stacks.root_slots(), 10);
+        let first = &stacks.stacks[0][0];
+        assert_eq!(first.roots, vec![(0, 16), (4, 32)]);
+        assert_eq!(first.slot(4), STACK_BASE + 32);
+        assert_eq!(stacks.stacks[1][0].start, STACK_BASE + STACK_ALIGNMENT);
+        let (_, mut found) = stacks.scan_on_host();
+        found.sort_unstable();
+        assert_eq!(found, roots);
+        assert!(RootStacks::synthesize(&roots, 1, 4, 2).is_err());
+    }
+}
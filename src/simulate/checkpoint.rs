@@ -0,0 +1,135 @@
+//! Checkpoints of long simulations, so that a crash or a preemption does not
+//! lose days of simulated ticks. The heap is restored from the heapdump as
+//! usual on resume, so a checkpoint only holds its mark bits, as a side
+//! bitmap over the objects, and the state of the simulated architecture.
+
+use super::SimulationArchitecture;
+use crate::object_model::Header;
+use crate::*;
+use anyhow::{bail, Result};
+use fixedbitset::FixedBitSet;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+/// The mark byte of objects marked by the simulated closure
+const MARKED: u8 = 1;
+
+/// What a checkpoint has to match to be resumed.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct CheckpointKey {
+    version: String,
+    heapdump: String,
+    objects: u64,
+    /// The simulation arguments, other than those about checkpoints
+    config: String,
+}
+
+impl CheckpointKey {
+    pub(super) fn new<O: ObjectModel>(
+        heapdump: &str,
+        args: &SimulationArgs,
+        object_model: &O,
+    ) -> Self {
+        let mut args = args.clone();
+        args.checkpoint = None;
+        args.checkpoint_interval = 0;
+        args.resume = None;
+        CheckpointKey {
+            version: git_version(),
+            heapdump: heapdump.to_string(),
+            objects: object_model.num_objects() as u64,
+            config: format!("{:?}", args),
+        }
+    }
+
+    fn check(&self, saved: &CheckpointKey) -> Result<()> {
+        if saved.version != self.version {
+            bail!(
+                "The checkpoint was saved by version {}, not {}",
+                saved.version,
+                self.version
+            );
+        }
+        if saved.heapdump != self.heapdump || saved.objects != self.objects {
+            bail!(
+                "The checkpoint is of {} ({} objects), not {} ({} objects)",
+                saved.heapdump,
+                saved.objects,
+                self.heapdump,
+                self.objects
+            );
+        }
+        if saved.config != self.config {
+            bail!(
+                "The checkpoint was saved with different simulation arguments: {}",
+                saved.config
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The objects in address order, which unlike `ObjectModel::objects` does
+/// not depend on the order in which they were restored.
+fn objects_in_order<O: ObjectModel>(object_model: &O) -> Vec<ObjectReference> {
+    let mut objects: Vec<ObjectReference> = object_model.objects_iter().collect();
+    objects.sort_unstable();
+    objects
+}
+
+pub(super) fn save<O: ObjectModel, A: SimulationArchitecture>(
+    path: &str,
+    key: &CheckpointKey,
+    object_model: &O,
+    architecture: &A,
+) -> Result<()> {
+    let objects = objects_in_order(object_model);
+    let mut marks = FixedBitSet::with_capacity(objects.len());
+    for (i, &o) in objects.iter().enumerate() {
+        marks.set(i, Header::load(o).get_mark_byte() == MARKED);
+    }
+    // The previous checkpoint stays in place until this one is complete
+    let tmp = format!("{}.tmp", path);
+    let mut writer = zstd::Encoder::new(BufWriter::new(File::create(&tmp)?), 3)?;
+    bincode::serialize_into(&mut writer, key)?;
+    bincode::serialize_into(&mut writer, marks.as_slice())?;
+    architecture.save(&mut writer)?;
+    writer.finish()?.flush()?;
+    fs::rename(&tmp, path)?;
+    info!(
+        "Saved a checkpoint at tick {} to {}",
+        architecture.ticks(),
+        path
+    );
+    Ok(())
+}
+
+/// Loads the checkpoint at `path` into a simulation that has just been
+/// created over a freshly restored heap.
+pub(super) fn load<O: ObjectModel, A: SimulationArchitecture>(
+    path: &str,
+    key: &CheckpointKey,
+    object_model: &O,
+    architecture: &mut A,
+) -> Result<()> {
+    let mut reader = zstd::Decoder::new(File::open(path)?)?;
+    let saved: CheckpointKey = bincode::deserialize_from(&mut reader)?;
+    key.check(&saved)?;
+    let objects = objects_in_order(object_model);
+    let blocks: Vec<usize> = bincode::deserialize_from(&mut reader)?;
+    let marks = FixedBitSet::with_capacity_and_blocks(objects.len(), blocks);
+    for i in marks.ones() {
+        let o = objects[i];
+        let mut header = Header::load(o);
+        header.set_mark_byte(MARKED);
+        header.store(o);
+    }
+    architecture.load(&mut reader)?;
+    info!(
+        "Resuming from tick {} with {} objects marked",
+        architecture.ticks(),
+        marks.count_ones(..)
+    );
+    Ok(())
+}
@@ -0,0 +1,404 @@
+//! Turns the event logs of `simulate --event-log` into Perfetto traces
+//! (`convert-trace`), one event at a time, so that a log of any length
+//! converts in constant memory, optionally keeping only a window of time
+//! or some of the processors.
+
+use super::event_log::{EventLogReader, LoggedEvent};
+use super::tracing::TracingEvent;
+use crate::util::ticks_to_us;
+use crate::*;
+use anyhow::{bail, Result};
+use flate2::{write::GzEncoder, Compression};
+use prost::Message;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+mod perfetto {
+    include!(concat!(env!("OUT_DIR"), "/perfetto.protos.rs"));
+}
+use perfetto::{trace_packet, track_event, TracePacket, TrackDescriptor, TrackEvent};
+
+/// Where the events converted go.
+trait TraceWriter {
+    fn process(&mut self, pid: u32, name: &str) -> Result<()>;
+    fn thread(&mut self, pid: u32, tid: u32, name: &str) -> Result<()>;
+    /// Times are in microseconds
+    fn span(&mut self, pid: u32, tid: u32, name: &str, begin: f64, end: f64) -> Result<()>;
+    fn counter(&mut self, pid: u32, name: &str, ts: f64, values: &[(String, u64)]) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// The gzipped JSON array of trace events `simulate -t` writes.
+struct JsonTrace {
+    out: BufWriter<GzEncoder<File>>,
+    first: bool,
+}
+
+impl JsonTrace {
+    fn create(path: &str) -> Result<Self> {
+        let mut out = BufWriter::new(GzEncoder::new(File::create(path)?, Compression::default()));
+        out.write_all(b"[")?;
+        Ok(JsonTrace { out, first: true })
+    }
+
+    fn push(&mut self, event: &TracingEvent) -> Result<()> {
+        if !std::mem::take(&mut self.first) {
+            self.out.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.out, event)?;
+        Ok(())
+    }
+}
+
+impl TraceWriter for JsonTrace {
+    fn process(&mut self, pid: u32, name: &str) -> Result<()> {
+        self.push(&TracingEvent::new_processname_event(pid, name.to_string()))
+    }
+
+    fn thread(&mut self, pid: u32, tid: u32, name: &str) -> Result<()> {
+        self.push(&TracingEvent::new_threadname_event(
+            pid,
+            tid,
+            name.to_string(),
+        ))
+    }
+
+    fn span(&mut self, pid: u32, tid: u32, name: &str, begin: f64, end: f64) -> Result<()> {
+        self.push(&TracingEvent::new_duration_event(
+            pid,
+            tid,
+            name.to_string(),
+            begin,
+            HashMap::default(),
+            true,
+            Some(end - begin),
+        ))
+    }
+
+    fn counter(&mut self, pid: u32, name: &str, ts: f64, values: &[(String, u64)]) -> Result<()> {
+        let values: HashMap<String, Value> = values
+            .iter()
+            .map(|(key, value)| (key.clone(), (*value).into()))
+            .collect();
+        self.push(&TracingEvent::new_counter_event(
+            pid,
+            name.to_string(),
+            ts,
+            values,
+        ))
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.write_all(b"]")?;
+        self.out.into_inner()?.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Perfetto trace packets, each written as one `packet` of a `Trace`.
+struct ProtoTrace {
+    out: BufWriter<File>,
+    buffer: Vec<u8>,
+    /// Track of each process, thread and counter series, by pid, tid and
+    /// series name
+    tracks: HashMap<(u32, Option<u32>, String), u64>,
+}
+
+/// Sequence of every packet, as the packets do not use interning
+const SEQUENCE_ID: u32 = 1;
+
+impl ProtoTrace {
+    fn create(path: &str) -> Result<Self> {
+        Ok(ProtoTrace {
+            out: BufWriter::new(File::create(path)?),
+            buffer: vec![],
+            tracks: HashMap::new(),
+        })
+    }
+
+    fn push(&mut self, packet: TracePacket) -> Result<()> {
+        self.buffer.clear();
+        // Field 1 of `Trace`, length-delimited
+        self.buffer.push(0x0a);
+        packet.encode_length_delimited(&mut self.buffer)?;
+        self.out.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    fn describe(&mut self, descriptor: TrackDescriptor) -> Result<()> {
+        self.push(TracePacket {
+            timestamp: None,
+            trusted_packet_sequence_id: Some(SEQUENCE_ID),
+            data: Some(trace_packet::Data::TrackDescriptor(descriptor)),
+        })
+    }
+
+    fn track(&mut self, key: (u32, Option<u32>, String)) -> (u64, bool) {
+        let next = self.tracks.len() as u64 + 1;
+        let mut new = false;
+        let uuid = *self.tracks.entry(key).or_insert_with(|| {
+            new = true;
+            next
+        });
+        (uuid, new)
+    }
+
+    fn event(&mut self, ts: f64, event: TrackEvent) -> Result<()> {
+        self.push(TracePacket {
+            timestamp: Some((ts * 1000.0).round() as u64),
+            trusted_packet_sequence_id: Some(SEQUENCE_ID),
+            data: Some(trace_packet::Data::TrackEvent(event)),
+        })
+    }
+}
+
+impl TraceWriter for ProtoTrace {
+    fn process(&mut self, pid: u32, name: &str) -> Result<()> {
+        let (uuid, _) = self.track((pid, None, String::new()));
+        self.describe(TrackDescriptor {
+            uuid: Some(uuid),
+            process: Some(perfetto::ProcessDescriptor {
+                pid: Some(pid as i32),
+                process_name: Some(name.to_string()),
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn thread(&mut self, pid: u32, tid: u32, name: &str) -> Result<()> {
+        let (uuid, _) = self.track((pid, Some(tid), String::new()));
+        self.describe(TrackDescriptor {
+            uuid: Some(uuid),
+            thread: Some(perfetto::ThreadDescriptor {
+                pid: Some(pid as i32),
+                // Perfetto merges threads by tid across processes
+                tid: Some((pid << 16 | tid) as i32),
+                thread_name: Some(name.to_string()),
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn span(&mut self, pid: u32, tid: u32, name: &str, begin: f64, end: f64) -> Result<()> {
+        let (uuid, _) = self.track((pid, Some(tid), String::new()));
+        self.event(
+            begin,
+            TrackEvent {
+                r#type: Some(track_event::Type::SliceBegin as i32),
+                track_uuid: Some(uuid),
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+        )?;
+        self.event(
+            end,
+            TrackEvent {
+                r#type: Some(track_event::Type::SliceEnd as i32),
+                track_uuid: Some(uuid),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn counter(&mut self, pid: u32, name: &str, ts: f64, values: &[(String, u64)]) -> Result<()> {
+        let (process, _) = self.track((pid, None, String::new()));
+        for (key, value) in values {
+            let series = format!("{} {}", name, key);
+            let (uuid, new) = self.track((pid, None, series.clone()));
+            if new {
+                self.describe(TrackDescriptor {
+                    uuid: Some(uuid),
+                    parent_uuid: Some(process),
+                    name: Some(series),
+                    counter: Some(perfetto::CounterDescriptor {}),
+                    ..Default::default()
+                })?;
+            }
+            self.event(
+                ts,
+                TrackEvent {
+                    r#type: Some(track_event::Type::Counter as i32),
+                    track_uuid: Some(uuid),
+                    counter_value: Some(*value as i64),
+                    ..Default::default()
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// The events to keep.
+struct Filter<'a> {
+    from_us: f64,
+    until_us: f64,
+    threads: &'a [u32],
+}
+
+impl Filter<'_> {
+    fn thread(&self, tid: u32) -> bool {
+        self.threads.is_empty() || self.threads.contains(&tid)
+    }
+
+    /// The part of a span within the window, if any
+    fn clip(&self, begin: f64, end: f64) -> Option<(f64, f64)> {
+        (begin <= self.until_us && end >= self.from_us)
+            .then(|| (begin.max(self.from_us), end.min(self.until_us)))
+    }
+}
+
+fn convert(
+    reader: &mut EventLogReader,
+    filter: &Filter,
+    trace: &mut dyn TraceWriter,
+    pid: &mut u32,
+) -> Result<u64> {
+    let mut frequency_ghz = 1.0;
+    let mut events = 0;
+    let us = |tick: u64, frequency_ghz: f64| ticks_to_us(tick, frequency_ghz);
+    while let Some(event) = reader.next_event()? {
+        match event {
+            LoggedEvent::Process {
+                name,
+                frequency_ghz: f,
+            } => {
+                *pid += 1;
+                frequency_ghz = f;
+                trace.process(*pid, &name)?;
+            }
+            LoggedEvent::Thread { tid, name } => {
+                if filter.thread(tid) {
+                    trace.thread(*pid, tid, &name)?;
+                }
+            }
+            LoggedEvent::Span {
+                tid,
+                name,
+                begin,
+                end,
+            } => {
+                if !filter.thread(tid) {
+                    continue;
+                }
+                let span = filter.clip(us(begin, frequency_ghz), us(end, frequency_ghz));
+                if let Some((begin, end)) = span {
+                    trace.span(*pid, tid, &name, begin, end)?;
+                    events += 1;
+                }
+            }
+            LoggedEvent::Counter {
+                tid,
+                name,
+                tick,
+                values,
+            } => {
+                let ts = us(tick, frequency_ghz);
+                if filter.thread(tid) && filter.clip(ts, ts).is_some() {
+                    trace.counter(*pid, &name, ts, &values)?;
+                    events += 1;
+                }
+            }
+        }
+    }
+    Ok(events)
+}
+
+pub fn convert_trace(args: &Args) -> Result<()> {
+    let Some(Commands::ConvertTrace(convert_args)) = &args.command else {
+        panic!("Incorrect dispatch");
+    };
+    let filter = Filter {
+        from_us: convert_args.from_us.unwrap_or(f64::NEG_INFINITY),
+        until_us: convert_args.until_us.unwrap_or(f64::INFINITY),
+        threads: &convert_args.threads,
+    };
+    if filter.from_us > filter.until_us {
+        bail!("--from-us has to be before --until-us");
+    }
+    let mut trace: Box<dyn TraceWriter> = match convert_args.format {
+        TraceFormatChoice::Json => Box::new(JsonTrace::create(&convert_args.output_path)?),
+        TraceFormatChoice::Proto => Box::new(ProtoTrace::create(&convert_args.output_path)?),
+    };
+    // Each simulation in the logs is a process of its own
+    let mut pid = 0;
+    for path in &args.paths {
+        let mut reader = EventLogReader::open(path)?;
+        let events = convert(&mut reader, &filter, &mut *trace, &mut pid)?;
+        println!("{}: {} events converted", path, events);
+    }
+    trace.finish()?;
+    println!("Wrote {} simulations to {}", pid, convert_args.output_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::event_log::EventLog;
+    use super::*;
+
+    #[test]
+    fn test_convert_trace_proto() {
+        let dir = std::env::temp_dir();
+        let log_path = dir.join("test_convert_trace.hwgcevt");
+        let log_path = log_path.to_str().unwrap();
+        let trace_path = dir.join("test_convert_trace.pftrace");
+        let trace_path = trace_path.to_str().unwrap();
+        // One tick a nanosecond
+        let mut log = EventLog::create(log_path).unwrap();
+        log.process("heapdump", 1.0).unwrap();
+        log.thread(0, "R0").unwrap();
+        log.thread(1, "R1").unwrap();
+        log.span(0, "busy", 0, 2999).unwrap();
+        log.span(1, "busy", 0, 2999).unwrap();
+        log.counter(0, "R0 queue depth", 500, &[("works", 3)])
+            .unwrap();
+        log.counter(0, "R0 queue depth", 2500, &[("works", 1)])
+            .unwrap();
+        log.finish().unwrap();
+
+        let filter = Filter {
+            from_us: 1.0,
+            until_us: 2.0,
+            threads: &[0],
+        };
+        let mut trace = Box::new(ProtoTrace::create(trace_path).unwrap());
+        let mut pid = 0;
+        let mut reader = EventLogReader::open(log_path).unwrap();
+        // Only the span of R0, clipped, as both samples are out of the
+        // window
+        assert_eq!(
+            convert(&mut reader, &filter, &mut *trace, &mut pid).unwrap(),
+            1
+        );
+        trace.finish().unwrap();
+
+        let bytes = std::fs::read(trace_path).unwrap();
+        let mut buf = &bytes[..];
+        let mut packets = vec![];
+        while !buf.is_empty() {
+            assert_eq!(buf[0], 0x0a);
+            buf = &buf[1..];
+            packets.push(TracePacket::decode_length_delimited(&mut buf).unwrap());
+        }
+        // The process, the thread and the two ends of the span
+        assert_eq!(packets.len(), 4);
+        let timestamps: Vec<Option<u64>> = packets.iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, [None, None, Some(1000), Some(2000)]);
+        let Some(trace_packet::Data::TrackDescriptor(thread)) = &packets[1].data else {
+            panic!("Not a thread track: {:?}", packets[1]);
+        };
+        assert_eq!(
+            thread.thread.as_ref().unwrap().thread_name.as_deref(),
+            Some("R0")
+        );
+        std::fs::remove_file(log_path).unwrap();
+        std::fs::remove_file(trace_path).unwrap();
+    }
+}
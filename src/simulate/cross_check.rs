@@ -0,0 +1,97 @@
+//! Checks that a simulation marked what the host does: the objects marked
+//! in total and in each space of the heapdump against an EdgeSlot closure of
+//! the same heap on the host. The simulators only model timing around
+//! `trace_object`, so a change to a timing model that drops or duplicates
+//! work shows up here rather than as a suspiciously good speedup.
+
+use crate::failure::Failure;
+use crate::object_model::Header;
+use crate::trace::verify::host_closure;
+use crate::*;
+use anyhow::{anyhow, Result};
+
+/// Mark byte the simulators leave on the objects they mark
+pub(super) const SIMULATED_MARK: u8 = 1;
+/// Mark byte of the host closure, which no simulator uses
+const HOST_MARK: u8 = 2;
+
+/// Marked objects of each space of `heapdump`, and outside all of them.
+fn count_marked<O: ObjectModel>(object_model: &O, heapdump: &HeapDump, mark: u8) -> Vec<usize> {
+    let mut counts = vec![0; heapdump.spaces.len() + 1];
+    for o in object_model.objects_iter() {
+        if Header::load(o).get_mark_byte() != mark {
+            continue;
+        }
+        let space = heapdump
+            .spaces
+            .iter()
+            .position(|s| (s.start..s.end).contains(&o.to_raw()))
+            .unwrap_or(heapdump.spaces.len());
+        counts[space] += 1;
+    }
+    counts
+}
+
+/// Compares the marks left by a simulation of `heapdump` with those of a
+/// host closure, which replaces them.
+pub(super) fn cross_check<O: ObjectModel>(object_model: &O, heapdump: &HeapDump) -> Result<()> {
+    let simulated = count_marked(object_model, heapdump, SIMULATED_MARK);
+    host_closure(HOST_MARK, object_model);
+    let host = count_marked(object_model, heapdump, HOST_MARK);
+    // Marked by the simulation but not reachable from the roots
+    let stray = count_marked(object_model, heapdump, SIMULATED_MARK);
+    let total = |counts: &[usize]| counts.iter().sum::<usize>();
+    if simulated == host && total(&stray) == 0 {
+        println!(
+            "Cross-check: {} objects marked, as by the host",
+            total(&host)
+        );
+        return Ok(());
+    }
+    let names = heapdump
+        .spaces
+        .iter()
+        .map(|s| s.name.as_str())
+        .chain(["(no space)"]);
+    for (i, name) in names.enumerate() {
+        if simulated[i] != host[i] || stray[i] > 0 {
+            error!(
+                "{}: {} objects marked by the simulation, {} by the host, {} unreachable",
+                name, simulated[i], host[i], stray[i]
+            );
+        }
+    }
+    Err(anyhow!(
+        "Cross-check failed: the simulation marked {} objects and the host {}",
+        total(&simulated),
+        total(&host)
+    )
+    .context(Failure::Verification))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+
+    #[test]
+    fn test_cross_check() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        // A simulation that marks like the host
+        host_closure(SIMULATED_MARK, &object_model);
+        assert!(cross_check(&object_model, &heapdump).is_ok());
+        // and one that misses an object
+        host_closure(SIMULATED_MARK, &object_model);
+        let missed = object_model.objects_iter().last().unwrap();
+        let mut header = Header::load(missed);
+        header.set_mark_byte(0);
+        header.store(missed);
+        assert!(cross_check(&object_model, &heapdump).is_err());
+        heapdump.unmap_spaces().unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+//! Simulations that will not finish, such as those of a configuration that
+//! deadlocks or livelocks, are stopped with a [`SimulationDiverged`] error
+//! instead of ticking forever, so that a sweep can go on to the next run.
+//! That is once they reach `--max-ticks`, or once they go `--stall-ticks`
+//! ticks without marking an object while there is still work or messages
+//! about.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Divergence {
+    /// The tick budget ran out
+    MaxTicks,
+    /// No object was marked since this tick
+    Stalled { since: usize },
+}
+
+/// Why and where a simulation was stopped, with the state of the simulated
+/// hardware at that point.
+#[derive(Debug)]
+pub(crate) struct SimulationDiverged {
+    pub(crate) heapdump: String,
+    pub(crate) divergence: Divergence,
+    pub(crate) ticks: usize,
+    pub(crate) marked_objects: usize,
+    pub(crate) diagnostics: String,
+}
+
+impl fmt::Display for SimulationDiverged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Simulation of {} diverged: ", self.heapdump)?;
+        match self.divergence {
+            Divergence::MaxTicks => write!(f, "reached --max-ticks")?,
+            Divergence::Stalled { since } => write!(
+                f,
+                "no object marked in the {} ticks since tick {}",
+                self.ticks - since,
+                since
+            )?,
+        }
+        write!(
+            f,
+            " at tick {} with {} objects marked",
+            self.ticks, self.marked_objects
+        )?;
+        if !self.diagnostics.is_empty() {
+            write!(f, "\n{}", self.diagnostics)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SimulationDiverged {}
+
+/// Watches the ticks and the marked objects of a simulation for divergence.
+pub(super) struct Watchdog {
+    max_ticks: Option<usize>,
+    /// 0 to never consider a simulation stalled
+    stall_ticks: usize,
+    marked_objects: usize,
+    /// Tick the marked objects last grew at
+    progress_tick: usize,
+}
+
+impl Watchdog {
+    pub(super) fn new(max_ticks: Option<usize>, stall_ticks: usize) -> Self {
+        Watchdog {
+            max_ticks,
+            stall_ticks,
+            marked_objects: 0,
+            progress_tick: 0,
+        }
+    }
+
+    /// Checked every tick, as it is cheap.
+    pub(super) fn check_ticks(&self, ticks: usize) -> Option<Divergence> {
+        self.max_ticks
+            .filter(|&max| ticks >= max)
+            .map(|_| Divergence::MaxTicks)
+    }
+
+    /// Checked every so often, as counting the marked objects is not cheap.
+    pub(super) fn check_progress(
+        &mut self,
+        ticks: usize,
+        marked_objects: usize,
+    ) -> Option<Divergence> {
+        if marked_objects != self.marked_objects {
+            self.marked_objects = marked_objects;
+            self.progress_tick = ticks;
+            return None;
+        }
+        (self.stall_ticks > 0 && ticks - self.progress_tick >= self.stall_ticks).then_some(
+            Divergence::Stalled {
+                since: self.progress_tick,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog() {
+        let mut watchdog = Watchdog::new(Some(1000), 100);
+        assert_eq!(watchdog.check_ticks(999), None);
+        assert_eq!(watchdog.check_ticks(1000), Some(Divergence::MaxTicks));
+        assert_eq!(watchdog.check_progress(50, 10), None);
+        assert_eq!(watchdog.check_progress(149, 10), None);
+        assert_eq!(
+            watchdog.check_progress(150, 10),
+            Some(Divergence::Stalled { since: 50 })
+        );
+        assert_eq!(watchdog.check_progress(160, 11), None);
+        assert_eq!(watchdog.check_progress(250, 11), None);
+        assert!(Watchdog::new(None, 0).check_progress(1 << 40, 0).is_none());
+    }
+}
@@ -0,0 +1,378 @@
+//! A compact binary log of what the simulated processors do, written as the
+//! simulation goes (`simulate --event-log`), so that long simulations do not
+//! keep every event in memory until the end, and turned into a Perfetto
+//! trace afterwards by `convert-trace`.
+//!
+//! The log is the magic bytes followed by records, each a tag and LEB128
+//! varints. Names are interned: a `NAME` record gives the next name id, and
+//! the other records refer to names by id. Timestamps are in ticks, each
+//! the zigzag-encoded difference from the previous timestamp of the
+//! process, so the spans and samples of a processor mostly take a byte or
+//! two each.
+
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 8] = b"HWGCEVT\x01";
+
+const NAME: u8 = 0;
+/// A simulation, with its clock, that the following records belong to
+const PROCESS: u8 = 1;
+const THREAD: u8 = 2;
+const SPAN: u8 = 3;
+const COUNTER: u8 = 4;
+
+fn write_varint(out: &mut impl Write, mut value: u64) -> io::Result<()> {
+    let mut bytes = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+    out.write_all(&bytes[..len])
+}
+
+fn read_varint(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        input.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Varint longer than 64 bits",
+            ));
+        }
+    }
+}
+
+fn zigzag(delta: i64) -> u64 {
+    ((delta << 1) ^ (delta >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Writes the events of one or more simulations to a log.
+pub(crate) struct EventLog<W: Write = BufWriter<File>> {
+    out: W,
+    names: HashMap<String, u64>,
+    /// Threads of the current process already named
+    threads: HashSet<u32>,
+    last_tick: u64,
+}
+
+impl EventLog {
+    pub(crate) fn create(path: &str) -> Result<Self> {
+        Ok(EventLog::new(BufWriter::new(File::create(path)?))?)
+    }
+}
+
+impl<W: Write> EventLog<W> {
+    fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        Ok(EventLog {
+            out,
+            names: HashMap::new(),
+            threads: HashSet::new(),
+            last_tick: 0,
+        })
+    }
+
+    fn name_id(&mut self, name: &str) -> io::Result<u64> {
+        if let Some(&id) = self.names.get(name) {
+            return Ok(id);
+        }
+        let id = self.names.len() as u64;
+        self.out.write_all(&[NAME])?;
+        write_varint(&mut self.out, name.len() as u64)?;
+        self.out.write_all(name.as_bytes())?;
+        self.names.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    fn tick(&mut self, tick: u64) -> io::Result<()> {
+        let delta = tick as i64 - self.last_tick as i64;
+        self.last_tick = tick;
+        write_varint(&mut self.out, zigzag(delta))
+    }
+
+    /// Starts the events of a simulation whose ticks are cycles of a
+    /// `frequency_ghz` clock.
+    pub(crate) fn process(&mut self, name: &str, frequency_ghz: f64) -> io::Result<()> {
+        let id = self.name_id(name)?;
+        self.out.write_all(&[PROCESS])?;
+        write_varint(&mut self.out, id)?;
+        self.out.write_all(&frequency_ghz.to_le_bytes())?;
+        self.threads.clear();
+        self.last_tick = 0;
+        Ok(())
+    }
+
+    /// Names thread `tid` of the current process, unless it already is.
+    pub(crate) fn thread(&mut self, tid: u32, name: &str) -> io::Result<()> {
+        if !self.threads.insert(tid) {
+            return Ok(());
+        }
+        let id = self.name_id(name)?;
+        self.out.write_all(&[THREAD])?;
+        write_varint(&mut self.out, tid as u64)?;
+        write_varint(&mut self.out, id)
+    }
+
+    /// Thread `tid` spent ticks `begin` to `end` on `name`.
+    pub(crate) fn span(&mut self, tid: u32, name: &str, begin: u64, end: u64) -> io::Result<()> {
+        let id = self.name_id(name)?;
+        self.out.write_all(&[SPAN])?;
+        write_varint(&mut self.out, tid as u64)?;
+        write_varint(&mut self.out, id)?;
+        self.tick(begin)?;
+        write_varint(&mut self.out, end.saturating_sub(begin))
+    }
+
+    /// A sample of counter track `name` of thread `tid` at `tick`, with one
+    /// series per key of `values`.
+    pub(crate) fn counter(
+        &mut self,
+        tid: u32,
+        name: &str,
+        tick: u64,
+        values: &[(&str, u64)],
+    ) -> io::Result<()> {
+        let id = self.name_id(name)?;
+        let keys = values
+            .iter()
+            .map(|(key, _)| self.name_id(key))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.out.write_all(&[COUNTER])?;
+        write_varint(&mut self.out, tid as u64)?;
+        write_varint(&mut self.out, id)?;
+        self.tick(tick)?;
+        write_varint(&mut self.out, values.len() as u64)?;
+        for (key, (_, value)) in keys.into_iter().zip(values) {
+            write_varint(&mut self.out, key)?;
+            write_varint(&mut self.out, *value)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LoggedEvent {
+    Process {
+        name: String,
+        frequency_ghz: f64,
+    },
+    Thread {
+        tid: u32,
+        name: String,
+    },
+    Span {
+        tid: u32,
+        name: String,
+        begin: u64,
+        end: u64,
+    },
+    Counter {
+        tid: u32,
+        name: String,
+        tick: u64,
+        values: Vec<(String, u64)>,
+    },
+}
+
+/// Reads the events of a log back one at a time.
+pub(crate) struct EventLogReader<R: Read = BufReader<File>> {
+    input: R,
+    names: Vec<String>,
+    last_tick: u64,
+}
+
+impl EventLogReader {
+    pub(crate) fn open(path: &str) -> Result<Self> {
+        EventLogReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> EventLogReader<R> {
+    fn new(mut input: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("Not an event log of `simulate --event-log`");
+        }
+        Ok(EventLogReader {
+            input,
+            names: vec![],
+            last_tick: 0,
+        })
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let id = read_varint(&mut self.input)? as usize;
+        match self.names.get(id) {
+            Some(name) => Ok(name.clone()),
+            None => bail!("Event log refers to name {} before defining it", id),
+        }
+    }
+
+    fn tick(&mut self) -> io::Result<u64> {
+        let delta = unzigzag(read_varint(&mut self.input)?);
+        self.last_tick = (self.last_tick as i64 + delta) as u64;
+        Ok(self.last_tick)
+    }
+
+    /// The next event, or `None` at the end of the log.
+    pub(crate) fn next_event(&mut self) -> Result<Option<LoggedEvent>> {
+        loop {
+            let mut tag = [0u8];
+            if self.input.read(&mut tag)? == 0 {
+                return Ok(None);
+            }
+            let event = match tag[0] {
+                NAME => {
+                    let len = read_varint(&mut self.input)? as usize;
+                    let mut bytes = vec![0u8; len];
+                    self.input.read_exact(&mut bytes)?;
+                    self.names.push(String::from_utf8(bytes)?);
+                    continue;
+                }
+                PROCESS => {
+                    let name = self.name()?;
+                    let mut frequency = [0u8; 8];
+                    self.input.read_exact(&mut frequency)?;
+                    self.last_tick = 0;
+                    LoggedEvent::Process {
+                        name,
+                        frequency_ghz: f64::from_le_bytes(frequency),
+                    }
+                }
+                THREAD => LoggedEvent::Thread {
+                    tid: read_varint(&mut self.input)? as u32,
+                    name: self.name()?,
+                },
+                SPAN => {
+                    let tid = read_varint(&mut self.input)? as u32;
+                    let name = self.name()?;
+                    let begin = self.tick()?;
+                    let end = begin + read_varint(&mut self.input)?;
+                    LoggedEvent::Span {
+                        tid,
+                        name,
+                        begin,
+                        end,
+                    }
+                }
+                COUNTER => {
+                    let tid = read_varint(&mut self.input)? as u32;
+                    let name = self.name()?;
+                    let tick = self.tick()?;
+                    let n = read_varint(&mut self.input)?;
+                    let mut values = vec![];
+                    for _ in 0..n {
+                        let key = self.name()?;
+                        values.push((key, read_varint(&mut self.input)?));
+                    }
+                    LoggedEvent::Counter {
+                        tid,
+                        name,
+                        tick,
+                        values,
+                    }
+                }
+                tag => bail!("Unknown event log record {}", tag),
+            };
+            return Ok(Some(event));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log_round_trip() {
+        let mut log = EventLog::new(vec![]).unwrap();
+        log.process("heapdump", 1.6).unwrap();
+        log.thread(0, "R0").unwrap();
+        // Named once per process
+        log.thread(0, "R0").unwrap();
+        log.span(0, "busy", 0, 99).unwrap();
+        log.counter(0, "R0 queue depth", 64, &[("works", 3), ("inbox", 0)])
+            .unwrap();
+        log.span(0, "idle", 100, 1 << 40).unwrap();
+        log.process("heapdump", 1.6).unwrap();
+        log.thread(0, "R0").unwrap();
+        let bytes = log.finish().unwrap();
+
+        let mut reader = EventLogReader::new(&bytes[..]).unwrap();
+        let mut events = vec![];
+        while let Some(event) = reader.next_event().unwrap() {
+            events.push(event);
+        }
+        let process = LoggedEvent::Process {
+            name: "heapdump".into(),
+            frequency_ghz: 1.6,
+        };
+        let thread = LoggedEvent::Thread {
+            tid: 0,
+            name: "R0".into(),
+        };
+        assert_eq!(
+            events,
+            [
+                process.clone(),
+                thread.clone(),
+                LoggedEvent::Span {
+                    tid: 0,
+                    name: "busy".into(),
+                    begin: 0,
+                    end: 99
+                },
+                LoggedEvent::Counter {
+                    tid: 0,
+                    name: "R0 queue depth".into(),
+                    tick: 64,
+                    values: vec![("works".into(), 3), ("inbox".into(), 0)]
+                },
+                LoggedEvent::Span {
+                    tid: 0,
+                    name: "idle".into(),
+                    begin: 100,
+                    end: 1 << 40
+                },
+                process,
+                thread,
+            ]
+        );
+        assert!(EventLogReader::new(&b"not a log"[..]).is_err());
+
+        // After the name, back to back spans take a few bytes each
+        let mut log = EventLog::new(vec![]).unwrap();
+        log.span(0, "busy", 0, 99).unwrap();
+        log.span(0, "busy", 100, 199).unwrap();
+        assert_eq!(log.finish().unwrap().len(), MAGIC.len() + 6 + 5 + 6);
+    }
+}
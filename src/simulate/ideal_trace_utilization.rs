@@ -1,4 +1,5 @@
 use super::SimulationArchitecture;
+use crate::analysis::depth::{sized_scan_cost, weighted_critical_path, CriticalPath};
 use crate::{trace::trace_object, *};
 use polars::prelude::*;
 use std::{
@@ -8,27 +9,31 @@ use std::{
 
 pub(crate) struct IdealTraceUtilization {
     processors: Vec<ITUProcessor>,
-    tracing_queue: VecDeque<u64>,
+    tracing_queue: VecDeque<ObjectReference>,
     ticks: usize,
     frontier_sizes: Vec<u64>, // Polars column can't be usize
     frontier_ticks: Vec<u64>,
+    cost: IdealCostChoice,
+    /// Under the same costs as the processors, computed before any marking
+    critical_path: Option<CriticalPath>,
 }
 
 impl SimulationArchitecture for IdealTraceUtilization {
     fn new<O: ObjectModel>(args: &SimulationArgs, object_model: &O) -> Self {
-        // Convert &[u64] into Vec<u64>
-        let mut queue: VecDeque<u64> = VecDeque::new();
+        let mut queue: VecDeque<ObjectReference> = VecDeque::new();
         for root in object_model.roots() {
-            let o = *root;
-            queue.push_back(o);
-            debug_assert_ne!(o, 0);
+            queue.push_back(ObjectReference::from_raw_unchecked(*root));
         }
+        let critical_path = (args.ideal_cost == IdealCostChoice::Size)
+            .then(|| weighted_critical_path(object_model, sized_scan_cost::<O>));
         IdealTraceUtilization {
             processors: vec![ITUProcessor::new(); args.processors],
             tracing_queue: queue,
             ticks: 0,
             frontier_sizes: vec![],
             frontier_ticks: vec![],
+            cost: args.ideal_cost,
+            critical_path,
         }
     }
 
@@ -42,10 +47,16 @@ impl SimulationArchitecture for IdealTraceUtilization {
         self.ticks += 1;
         let mut append_to_queue = Vec::new();
         for processor in &mut self.processors {
-            append_to_queue.extend(processor.tick::<O>(self.tracing_queue.pop_front()));
+            let o = if processor.scanning.is_none() {
+                self.tracing_queue.pop_front()
+            } else {
+                None
+            };
+            append_to_queue.extend(processor.tick::<O>(o, self.cost));
         }
         self.tracing_queue.extend(append_to_queue);
-        let terminate = self.tracing_queue.is_empty();
+        let terminate =
+            self.tracing_queue.is_empty() && self.processors.iter().all(|p| p.scanning.is_none());
         if terminate {
             // Before we terminate, dump the frontier stats
             self.frontier_sizes.push(self.tracing_queue.len() as u64); // 0 in this case
@@ -62,6 +73,14 @@ impl SimulationArchitecture for IdealTraceUtilization {
         terminate
     }
 
+    fn ticks(&self) -> usize {
+        self.ticks
+    }
+
+    fn marked_objects(&self) -> usize {
+        self.processors.iter().map(|p| p.marked_objects).sum()
+    }
+
     fn stats(&self) -> HashMap<String, f64> {
         let mut stats = HashMap::new();
         let mut total_marked_objects = 0;
@@ -78,6 +97,22 @@ impl SimulationArchitecture for IdealTraceUtilization {
             "utilization".into(),
             total_busy_ticks as f64 / (self.ticks * self.processors.len()) as f64,
         );
+        if let Some(cp) = self.critical_path {
+            println!(
+                "Size-weighted critical path: {} of {} total scan work, speedup bound {:.1}, simulated speedup {:.1}",
+                cp.length,
+                cp.total_work,
+                cp.speedup_bound(),
+                cp.total_work as f64 / self.ticks as f64
+            );
+            stats.insert("ideal_cost.critical_path".into(), cp.length as f64);
+            stats.insert("ideal_cost.total_work".into(), cp.total_work as f64);
+            stats.insert("ideal_cost.speedup_bound".into(), cp.speedup_bound());
+            stats.insert(
+                "ideal_cost.ticks_over_critical_path".into(),
+                self.ticks as f64 / cp.length as f64,
+            );
+        }
         stats
     }
 }
@@ -86,6 +121,9 @@ impl SimulationArchitecture for IdealTraceUtilization {
 struct ITUProcessor {
     busy_ticks: usize,
     marked_objects: usize,
+    /// Ticks left to scan the current object, and its children, which are
+    /// only released once it is scanned
+    scanning: Option<(u64, Vec<ObjectReference>)>,
 }
 
 impl ITUProcessor {
@@ -93,28 +131,44 @@ impl ITUProcessor {
         ITUProcessor {
             busy_ticks: 0,
             marked_objects: 0,
+            scanning: None,
         }
     }
-    fn tick<O: ObjectModel>(&mut self, o: Option<u64>) -> Vec<u64> {
-        if o.is_none() {
-            return vec![];
+
+    /// Starts on `o` if given, which the processor must only be given when
+    /// it is not scanning, and returns the children of the object it
+    /// finishes scanning in this tick.
+    fn tick<O: ObjectModel>(
+        &mut self,
+        o: Option<ObjectReference>,
+        cost: IdealCostChoice,
+    ) -> Vec<ObjectReference> {
+        if let Some(o) = o {
+            let mut children: Vec<ObjectReference> = vec![];
+            let mut slots = 0;
+            let marked = unsafe { trace_object(o, 1) };
+            if marked {
+                self.marked_objects += 1;
+                for e in O::slots_range(o) {
+                    slots += 1;
+                    children.extend(unsafe { e.load_object() });
+                }
+            }
+            // Finding an object already marked takes a tick either way
+            let ticks = match cost {
+                IdealCostChoice::Size if marked => sized_scan_cost::<O>(o, slots),
+                _ => 1,
+            };
+            self.scanning = Some((ticks, children));
         }
-        let o = o.unwrap();
+        let Some((ticks, _)) = &mut self.scanning else {
+            return vec![];
+        };
         self.busy_ticks += 1;
-        let mut children: Vec<u64> = vec![];
-        if unsafe { trace_object(o, 1) } {
-            self.marked_objects += 1;
-            O::scan_object(o, |edge, repeat| {
-                for i in 0..repeat {
-                    let e = edge.wrapping_add(i as usize);
-                    let child = unsafe { *e };
-                    if child != 0 {
-                        children.push(child);
-                    }
-                }
-            });
+        *ticks -= 1;
+        if *ticks > 0 {
+            return vec![];
         }
-
-        children
+        self.scanning.take().unwrap().1
     }
 }
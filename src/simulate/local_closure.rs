@@ -0,0 +1,103 @@
+//! How much of the heap NMPGC processors could trace without talking to
+//! each other. The local closure of a processor is what it reaches from the
+//! roots it owns through objects it owns, and the objects outside every
+//! local closure are only reachable through an edge from an object of
+//! another owner, so each of them costs at least one message whatever the
+//! schedule.
+
+use super::AddressMapping;
+use crate::*;
+use std::collections::HashSet;
+
+#[derive(Debug, PartialEq)]
+pub(super) struct LocalClosures {
+    /// Objects in the local closure of each processor
+    pub(super) local: Vec<u64>,
+    /// Objects reachable from the roots
+    pub(super) reachable: u64,
+}
+
+impl LocalClosures {
+    /// Objects only reachable through an edge between owners
+    pub(super) fn remote_only(&self) -> u64 {
+        self.reachable - self.local.iter().sum::<u64>()
+    }
+
+    /// Computes the closures of the objects `roots` reach through
+    /// `children`. An object is only in the local closure of its owner, so
+    /// one set of visited objects serves every processor.
+    fn new(
+        roots: &[u64],
+        children: impl Fn(u64) -> Vec<u64>,
+        owner: impl Fn(u64) -> usize,
+        processors: usize,
+    ) -> Self {
+        let mut visited: HashSet<u64> = roots.iter().copied().collect();
+        let mut stack: Vec<u64> = visited.iter().copied().collect();
+        while let Some(o) = stack.pop() {
+            stack.extend(children(o).into_iter().filter(|&c| visited.insert(c)));
+        }
+        let reachable = visited.len() as u64;
+        let mut local = vec![0; processors];
+        visited.clear();
+        let mut stack: Vec<u64> = roots
+            .iter()
+            .copied()
+            .filter(|&r| visited.insert(r))
+            .collect();
+        while let Some(o) = stack.pop() {
+            let owner_of_o = owner(o);
+            local[owner_of_o] += 1;
+            stack.extend(
+                children(o)
+                    .into_iter()
+                    .filter(|&c| owner(c) == owner_of_o && visited.insert(c)),
+            );
+        }
+        LocalClosures { local, reachable }
+    }
+}
+
+/// The local closures of the heap of `object_model` under the address
+/// mapping in use.
+pub(super) fn local_closures<O: ObjectModel>(object_model: &O, processors: usize) -> LocalClosures {
+    let children = |o: u64| {
+        let mut children = vec![];
+        O::scan_object(ObjectReference::from_raw_unchecked(o), |edge, repeat| {
+            for i in 0..repeat {
+                if let Some(c) = unsafe { edge.shift::<u64>(i as usize).load_object() } {
+                    children.push(c.to_raw());
+                }
+            }
+        });
+        children
+    };
+    LocalClosures::new(
+        object_model.roots(),
+        children,
+        |o| AddressMapping(o).get_owner_id(),
+        processors,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_closures() {
+        // Owned by processor o / 10: 10 -> 11 -> 20 -> 21 and 20 -> 12,
+        // with 10 and 21 as roots
+        let children = |o: u64| match o {
+            10 => vec![11],
+            11 => vec![20],
+            20 => vec![21, 12],
+            _ => vec![],
+        };
+        let closures = LocalClosures::new(&[10, 21], children, |o| (o / 10) as usize, 3);
+        assert_eq!(closures.local, vec![0, 2, 1]);
+        assert_eq!(closures.reachable, 5);
+        // 20 and 12 are only reached from another owner
+        assert_eq!(closures.remote_only(), 2);
+    }
+}
@@ -1,13 +1,22 @@
+use crate::cli::AddressMappingChoice;
+use crate::util::owner_hash;
+use crate::{Address, ObjectReference};
+use anyhow::{bail, Result};
 use bitfield::bitfield;
 use clap::ValueEnum;
 use lru::LruCache;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Display};
 use std::num::NonZeroUsize;
+use std::ops::AddAssign;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 /// log2 of the cache line size in bytes.
-const LOG_LINE_SIZE: usize = 6;
+pub(crate) const LOG_LINE_SIZE: usize = 6;
 /// Cache line size in bytes.
 const LINE_SIZE: usize = 1 << LOG_LINE_SIZE;
 
@@ -30,6 +39,18 @@ impl VirtualAddress {
     }
 }
 
+impl From<Address> for VirtualAddress {
+    fn from(addr: Address) -> Self {
+        VirtualAddress(addr.as_u64())
+    }
+}
+
+impl From<ObjectReference> for VirtualAddress {
+    fn from(o: ObjectReference) -> Self {
+        VirtualAddress(o.to_raw())
+    }
+}
+
 /// A physical address after address translation.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct PhysicalAddress(pub u64);
@@ -54,7 +75,7 @@ impl PhysicalAddress {
 // ---------------------------------------------------------------------------
 
 /// Supported x86_64 page sizes.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
 #[clap(rename_all = "verbatim")]
 pub enum PageSize {
     FourKB,
@@ -84,11 +105,56 @@ impl PageSize {
     }
 }
 
+/// Geometry of a TLB, written `<entries>x<ways>` on the command line, or
+/// `none` for a physically addressed processor that translates nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TlbGeometry {
+    None,
+    SetAssociative { entries: usize, ways: usize },
+}
+
+impl FromStr for TlbGeometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(TlbGeometry::None);
+        }
+        let fields: Vec<usize> = s
+            .split('x')
+            .map(|f| f.trim().parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid TLB geometry {:?}: {}", s, e))?;
+        let [entries, ways] = fields[..] else {
+            return Err(format!(
+                "invalid TLB geometry {:?}: expected <entries>x<ways> or none",
+                s
+            ));
+        };
+        if ways == 0 || entries == 0 || !entries.is_multiple_of(ways) {
+            return Err(format!(
+                "number of TLB entries ({}) must be a non-zero multiple of the ways ({})",
+                entries, ways
+            ));
+        }
+        Ok(TlbGeometry::SetAssociative { entries, ways })
+    }
+}
+
+impl Display for TlbGeometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlbGeometry::None => write!(f, "none"),
+            TlbGeometry::SetAssociative { entries, ways } => write!(f, "{}x{}", entries, ways),
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // TLB statistics
 // ---------------------------------------------------------------------------
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub(super) struct TlbStats {
     pub(super) read_hits: usize,
     pub(super) read_misses: usize,
@@ -116,6 +182,7 @@ impl TlbStats {
 /// Latency varies by page size, modelling the number of page table levels
 /// traversed in an Sv39/Sv48-style radix tree (as used by RISC-V and
 /// similar to x86_64 four-level paging).
+#[derive(Serialize, Deserialize)]
 struct PageTableWalker;
 
 impl PageTableWalker {
@@ -153,13 +220,18 @@ pub(super) struct TlbResp {
     pub(super) hit: bool,
 }
 
+#[derive(Serialize, Deserialize)]
 pub(super) struct Tlb {
     /// Each set is an LRU cache mapping VPN → PPN.  Both VPN and PPN are
     /// zero-padded to the full address width (i.e., the page-offset bits are
     /// zeroed, not stripped).
+    /// Empty if the processor is physically addressed
+    #[serde(with = "lru_sets")]
     sets: Vec<LruCache<u64, u64>>,
     page_size: PageSize,
     ptw: PageTableWalker,
+    /// Latency of a miss, overriding that of the page table walk
+    miss_penalty: Option<usize>,
     pub(super) stats: TlbStats,
 }
 
@@ -189,9 +261,29 @@ impl Tlb {
         }
     }
 
+    /// The TLB for `page_size` of a Golden Cove P-core.
+    pub(super) fn default_geometry(page_size: PageSize) -> TlbGeometry {
+        TlbGeometry::SetAssociative {
+            entries: Self::tlb_entries(page_size),
+            ways: Self::tlb_ways(page_size),
+        }
+    }
+
     pub fn new(page_size: PageSize) -> Self {
-        let entries = Self::tlb_entries(page_size);
-        let ways = Self::tlb_ways(page_size);
+        Self::with_geometry(page_size, Self::default_geometry(page_size), None)
+    }
+
+    /// A TLB of the given geometry, whose misses take `miss_penalty` ticks
+    /// if given, or the time of a page table walk for `page_size` otherwise.
+    pub fn with_geometry(
+        page_size: PageSize,
+        geometry: TlbGeometry,
+        miss_penalty: Option<usize>,
+    ) -> Self {
+        let (entries, ways) = match geometry {
+            TlbGeometry::None => (0, 1),
+            TlbGeometry::SetAssociative { entries, ways } => (entries, ways),
+        };
         debug_assert!(
             entries.is_multiple_of(ways),
             "TLB entries ({}) must be divisible by ways ({})",
@@ -206,41 +298,76 @@ impl Tlb {
             sets,
             page_size,
             ptw: PageTableWalker,
+            miss_penalty,
             stats: TlbStats::default(),
         }
     }
 
+    /// Whether addresses are translated at all.
+    pub(super) fn is_present(&self) -> bool {
+        !self.sets.is_empty()
+    }
+
     fn get_setidx(&self, vpn: u64) -> usize {
         (vpn >> self.page_size.page_shift()) as usize % self.sets.len()
     }
 
     /// Translates a virtual address to a physical address via the TLB.
     pub fn translate(&mut self, vaddr: VirtualAddress, is_write: bool) -> TlbResp {
+        let resp = self.lookup(vaddr);
+        if !self.is_present() {
+            return resp;
+        }
+        match (resp.hit, is_write) {
+            (true, true) => self.stats.write_hits += 1,
+            (true, false) => self.stats.read_hits += 1,
+            (false, true) => self.stats.write_misses += 1,
+            (false, false) => self.stats.read_misses += 1,
+        }
+        resp
+    }
+
+    /// Returns the translation of `vaddr` without touching any TLB state.
+    fn peek(&self, vaddr: VirtualAddress) -> PhysicalAddress {
+        if !self.is_present() {
+            return PhysicalAddress(vaddr.0);
+        }
+        let vpn = vaddr.vpn(self.page_size);
+        let setidx = self.get_setidx(vpn);
+        match self.sets[setidx].peek(&vpn) {
+            Some(&ppn) => vaddr.to_physical(ppn, self.page_size),
+            None => self.ptw.walk(vaddr, self.page_size).0,
+        }
+    }
+
+    /// Same as [`translate`](Self::translate) but without updating the
+    /// statistics, so that speculative accesses (e.g., prefetches) do not
+    /// skew the demand hit rates.
+    fn lookup(&mut self, vaddr: VirtualAddress) -> TlbResp {
+        // Like the walker, a physically addressed processor sees VA == PA,
+        // and the translation takes no time
+        if !self.is_present() {
+            return TlbResp {
+                paddr: PhysicalAddress(vaddr.0),
+                latency: 0,
+                hit: true,
+            };
+        }
         let vpn = vaddr.vpn(self.page_size);
         let setidx = self.get_setidx(vpn);
         if let Some(&ppn) = self.sets[setidx].get(&vpn) {
-            if is_write {
-                self.stats.write_hits += 1;
-            } else {
-                self.stats.read_hits += 1;
-            }
             TlbResp {
                 paddr: vaddr.to_physical(ppn, self.page_size),
                 latency: Self::HIT_LATENCY,
                 hit: true,
             }
         } else {
-            if is_write {
-                self.stats.write_misses += 1;
-            } else {
-                self.stats.read_misses += 1;
-            }
             let (paddr, ptw_latency) = self.ptw.walk(vaddr, self.page_size);
             let ppn = paddr.ppn(self.page_size);
             self.sets[setidx].put(vpn, ppn);
             TlbResp {
                 paddr,
-                latency: ptw_latency,
+                latency: self.miss_penalty.unwrap_or(ptw_latency),
                 hit: false,
             }
         }
@@ -257,12 +384,241 @@ pub(super) trait DataCache {
     fn write(&mut self, addr: VirtualAddress) -> usize;
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(super) struct CacheStats {
     pub(super) read_hits: usize,
     pub(super) read_misses: usize,
     pub(super) write_hits: usize,
     pub(super) write_misses: usize,
+    /// Lines installed by prefetches
+    pub(super) prefetch_fills: usize,
+    /// Demand reads that hit a prefetched line before it was evicted
+    pub(super) useful_prefetches: usize,
+    /// Lines dropped because another processor wrote them
+    pub(super) invalidations: usize,
+}
+
+/// Geometry of a set-associative cache, written `<sets>x<ways>x<line size>`
+/// on the command line, or `none` for no cache at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheGeometry {
+    None,
+    SetAssociative {
+        sets: usize,
+        ways: usize,
+        line_size: usize,
+    },
+}
+
+impl CacheGeometry {
+    /// The 32 KB processor cache of MAGC-DIMM
+    pub const DEFAULT: CacheGeometry = CacheGeometry::SetAssociative {
+        sets: 64,
+        ways: 8,
+        line_size: LINE_SIZE,
+    };
+
+    pub(super) fn capacity(&self) -> usize {
+        match *self {
+            CacheGeometry::None => 0,
+            CacheGeometry::SetAssociative {
+                sets,
+                ways,
+                line_size,
+            } => sets * ways * line_size,
+        }
+    }
+
+    fn log_line_size(&self) -> usize {
+        match *self {
+            CacheGeometry::None => LOG_LINE_SIZE,
+            CacheGeometry::SetAssociative { line_size, .. } => line_size.trailing_zeros() as usize,
+        }
+    }
+
+    /// Whether the set index is within the page offset, as a virtually
+    /// indexed, physically tagged cache needs, see `SetAssociativeCache::new`.
+    pub(crate) fn fits_page(&self, page_size: PageSize) -> bool {
+        let sets = match *self {
+            CacheGeometry::None => 1,
+            CacheGeometry::SetAssociative { sets, .. } => sets,
+        };
+        self.log_line_size() + sets.trailing_zeros() as usize <= page_size.page_shift() as usize
+    }
+}
+
+impl FromStr for CacheGeometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(CacheGeometry::None);
+        }
+        let fields: Vec<usize> = s
+            .split('x')
+            .map(|f| f.trim().parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid cache geometry {:?}: {}", s, e))?;
+        let [sets, ways, line_size] = fields[..] else {
+            return Err(format!(
+                "invalid cache geometry {:?}: expected <sets>x<ways>x<line size> or none",
+                s
+            ));
+        };
+        if !sets.is_power_of_two() {
+            return Err(format!(
+                "number of sets must be a power of two, got {}",
+                sets
+            ));
+        }
+        if ways == 0 {
+            return Err("number of ways must be greater than zero".into());
+        }
+        // Slots are words, so a line must hold at least one of them
+        if !line_size.is_power_of_two() || line_size < 8 {
+            return Err(format!(
+                "line size must be a power of two of at least 8 bytes, got {}",
+                line_size
+            ));
+        }
+        Ok(CacheGeometry::SetAssociative {
+            sets,
+            ways,
+            line_size,
+        })
+    }
+}
+
+impl Display for CacheGeometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheGeometry::None => write!(f, "none"),
+            CacheGeometry::SetAssociative {
+                sets,
+                ways,
+                line_size,
+            } => write!(f, "{}x{}x{}", sets, ways, line_size),
+        }
+    }
+}
+
+/// A physically indexed cache shared by the processors of a DIMM, which
+/// they consult on a miss in their own cache before going to DRAM.
+///
+/// It only tracks which lines are present. Writes allocate but are still
+/// forwarded to DRAM, like in the processor caches, so the shared copy never
+/// needs to be written back.
+///
+/// If the processors of a DIMM access each other's memory, the processor
+/// caches are kept coherent with a write-invalidate protocol. Since they are
+/// write-through, a line is either valid or invalid in each of them, and a
+/// directory next to the shared cache records which processors may hold it.
+/// The directory is not limited by the capacity of the shared cache, and
+/// processors evict lines silently, so some invalidations find nothing to
+/// invalidate.
+#[derive(Serialize, Deserialize)]
+pub(super) struct LastLevelCache {
+    #[serde(with = "lru_sets")]
+    cache_sets: Vec<LruCache<u64, ()>>,
+    log_line_size: usize,
+    /// Bitmask of the processors that may cache each line, if coherent
+    directory: Option<HashMap<u64, u64>>,
+    /// Lines each processor has to drop before its next access
+    pending_invalidations: Vec<Vec<u64>>,
+    pub(super) stats: CacheStats,
+    pub(super) coherence: CoherenceStats,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(super) struct CoherenceStats {
+    /// Invalidations sent to other processors by writes
+    pub(super) invalidations: usize,
+    /// Misses for lines that another processor may hold
+    pub(super) shared_fills: usize,
+}
+
+/// Connection of a processor cache to the last-level cache of its DIMM.
+pub(super) struct LlcPort {
+    pub(super) llc: Arc<Mutex<LastLevelCache>>,
+    /// Processor id, which identifies its cache in the directory
+    pub(super) requester: usize,
+}
+
+impl LastLevelCache {
+    /// Tag and data array access, on top of the miss in the processor cache
+    pub(super) const HIT_LATENCY: usize = 12;
+
+    pub(super) fn new(geometry: CacheGeometry, coherent: bool) -> Self {
+        let CacheGeometry::SetAssociative { sets, ways, .. } = geometry else {
+            panic!("A last-level cache needs at least one set");
+        };
+        LastLevelCache {
+            cache_sets: (0..sets)
+                .map(|_| LruCache::new(NonZeroUsize::new(ways).unwrap()))
+                .collect(),
+            log_line_size: geometry.log_line_size(),
+            directory: coherent.then(HashMap::new),
+            pending_invalidations: vec![],
+            stats: CacheStats::default(),
+            coherence: CoherenceStats::default(),
+        }
+    }
+
+    /// Records that `requester` may now cache the line holding `addr`, and
+    /// on a write, invalidates it everywhere else.
+    fn update_directory(&mut self, line: u64, is_write: bool, requester: usize) {
+        let Some(directory) = self.directory.as_mut() else {
+            return;
+        };
+        let sharers = directory.entry(line).or_insert(0);
+        let others = *sharers & !(1 << requester);
+        if is_write {
+            *sharers = 1 << requester;
+            for other in (0..u64::BITS as usize).filter(|i| others & (1 << i) != 0) {
+                if self.pending_invalidations.len() <= other {
+                    self.pending_invalidations.resize_with(other + 1, Vec::new);
+                }
+                self.pending_invalidations[other].push(line);
+                self.coherence.invalidations += 1;
+            }
+        } else {
+            *sharers |= 1 << requester;
+            if others != 0 {
+                self.coherence.shared_fills += 1;
+            }
+        }
+    }
+
+    /// Takes the physical address ranges that `requester` has to invalidate.
+    fn take_invalidations(&mut self, requester: usize) -> Vec<std::ops::Range<u64>> {
+        let Some(lines) = self.pending_invalidations.get_mut(requester) else {
+            return vec![];
+        };
+        std::mem::take(lines)
+            .into_iter()
+            .map(|line| line << self.log_line_size..(line + 1) << self.log_line_size)
+            .collect()
+    }
+
+    /// Looks up the line holding `addr` and allocates it on a miss.
+    /// Returns whether it hit.
+    fn access(&mut self, addr: PhysicalAddress, is_write: bool, requester: usize) -> bool {
+        let line = addr.0 >> self.log_line_size;
+        self.update_directory(line, is_write, requester);
+        let setidx = (line as usize) & (self.cache_sets.len() - 1);
+        let set = &mut self.cache_sets[setidx];
+        let hit = set.get(&line).is_some();
+        if !hit {
+            set.push(line, ());
+        }
+        match (is_write, hit) {
+            (false, true) => self.stats.read_hits += 1,
+            (false, false) => self.stats.read_misses += 1,
+            (true, true) => self.stats.write_hits += 1,
+            (true, false) => self.stats.write_misses += 1,
+        }
+        hit
+    }
 }
 
 #[allow(dead_code)]
@@ -322,8 +678,20 @@ impl DataCache for FullyAssociativeCache {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub(super) struct SetAssociativeCache {
+    /// Empty if the processor has no cache, in which case every access misses
+    #[serde(with = "lru_sets")]
     cache_sets: Vec<LruCache<u64, ()>>,
+    log_line_size: usize,
+    /// Not part of a checkpoint, see [`take_llc_port`](Self::take_llc_port)
+    #[serde(skip)]
+    llc: Option<LlcPort>,
+    /// Buffers write-through traffic if present
+    pub(super) write_buffer: Option<WriteCombiningBuffer>,
+    /// Physical tags of lines that were brought in by a prefetch and have not
+    /// been touched by a demand access yet
+    prefetched_lines: HashSet<u64>,
     rank: DDR4Rank,
     pub(super) stats: CacheStats,
     pub(super) tlb: Tlb,
@@ -331,12 +699,16 @@ pub(super) struct SetAssociativeCache {
 
 impl Debug for SetAssociativeCache {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "SetAssociativeCache: {}-set {}-way",
-            self.cache_sets.len(),
-            self.cache_sets[0].cap()
-        )
+        match self.cache_sets.first() {
+            Some(set) => write!(
+                f,
+                "SetAssociativeCache: {}-set {}-way {}-byte lines",
+                self.cache_sets.len(),
+                set.cap(),
+                1 << self.log_line_size
+            ),
+            None => write!(f, "SetAssociativeCache: none"),
+        }
     }
 }
 
@@ -354,30 +726,33 @@ impl SetAssociativeCache {
     /// of the same physical page index the same cache set and cannot cause
     /// consistency issues.
     /// See <https://comp.anu.edu.au/courses/comp3710-uarch/assets/lectures/week11-part2.pdf>.
-    pub fn new(
-        num_sets: usize,
-        num_ways: usize,
+    ///
+    /// Misses go to `llc` before DRAM if there is one.
+    pub(super) fn new(
+        geometry: CacheGeometry,
+        llc: Option<LlcPort>,
         rank_option: DDR4RankOption,
         page_size: PageSize,
     ) -> Self {
-        assert!(
-            num_sets > 0 && num_ways > 0,
-            "Number of sets and ways must be greater than zero"
-        );
+        let log_line_size = geometry.log_line_size();
+        let (num_sets, num_ways) = match geometry {
+            CacheGeometry::None => (0, 0),
+            CacheGeometry::SetAssociative { sets, ways, .. } => (sets, ways),
+        };
         // VIPT invariant: the highest set-index bit must be below the page
-        // offset.  Set index uses bits [LOG_LINE_SIZE .. LOG_LINE_SIZE + log2(num_sets)).
+        // offset.  Set index uses bits [log_line_size .. log_line_size + log2(num_sets)).
         // The page offset covers bits [0 .. page_shift).  For VIPT to work
-        // correctly, LOG_LINE_SIZE + log2(num_sets) <= page_shift.
+        // correctly, log_line_size + log2(num_sets) <= page_shift.
         debug_assert!(
-            num_sets.is_power_of_two(),
+            num_sets == 0 || num_sets.is_power_of_two(),
             "num_sets must be a power of two"
         );
-        let set_index_bits = num_sets.trailing_zeros() as usize;
+        let set_index_bits = num_sets.max(1).trailing_zeros() as usize;
         debug_assert!(
-            LOG_LINE_SIZE + set_index_bits <= page_size.page_shift() as usize,
+            geometry.fits_page(page_size),
             "VIPT invariant violated: set-index bits [{}..{}) exceed page offset {} for {:?}",
-            LOG_LINE_SIZE,
-            LOG_LINE_SIZE + set_index_bits,
+            log_line_size,
+            log_line_size + set_index_bits,
             page_size.page_shift(),
             page_size,
         );
@@ -386,6 +761,10 @@ impl SetAssociativeCache {
             .collect();
         SetAssociativeCache {
             cache_sets,
+            log_line_size,
+            llc,
+            write_buffer: None,
+            prefetched_lines: HashSet::new(),
             stats: CacheStats::default(),
             rank: DDR4Rank::new(rank_option),
             tlb: Tlb::new(page_size),
@@ -398,15 +777,177 @@ impl SetAssociativeCache {
     ///
     /// Note that the following implementation is probably equivalent
     /// ```rust,ignore
-    /// let line = vaddr.0 >> LOG_LINE_SIZE;
+    /// let line = vaddr.0 >> self.log_line_size;
     /// (line as usize) % self.cache_sets.len()
     /// ```
     ///
     /// But the following implementation is a more straightforward implementation
     /// of the spec.
     fn get_setidx(&self, vaddr: VirtualAddress) -> usize {
-        let set_index_mask = (self.cache_sets.len() - 1) as u64;
-        ((vaddr.0 >> LOG_LINE_SIZE) & set_index_mask) as usize
+        let set_index_mask = self.cache_sets.len().saturating_sub(1) as u64;
+        ((vaddr.0 >> self.log_line_size) & set_index_mask) as usize
+    }
+
+    /// Moves the connection to the last-level cache over from `other`, for
+    /// a cache loaded from a checkpoint in place of `other`.
+    pub(super) fn take_llc_port(&mut self, other: &mut SetAssociativeCache) {
+        self.llc = other.llc.take();
+    }
+
+    /// Makes the DRAM behind this cache refresh, which only the naive DRAM
+    /// model supports, DRAMsim3 taking it from its config.
+    pub(super) fn set_refresh(&mut self, refresh: RefreshTiming) {
+        self.rank.set_refresh(refresh);
+    }
+
+    /// Makes the DRAM behind this cache serve co-running mutators in
+    /// windows of `traffic`, which only the naive DRAM model supports.
+    pub(super) fn set_mutator_traffic(&mut self, traffic: MutatorTraffic) {
+        self.rank.set_mutator_traffic(traffic);
+    }
+
+    /// Makes the controller of the DRAM behind this cache close rows as
+    /// `policy` says, which only the naive DRAM model supports.
+    pub(super) fn set_page_policy(&mut self, policy: PagePolicy) {
+        self.rank.set_page_policy(policy);
+    }
+
+    /// Tells the DRAM model the tick of the accesses that follow.
+    pub(super) fn set_clock(&mut self, now: usize) {
+        self.rank.set_clock(now);
+    }
+
+    pub(super) fn rank_stats(&self) -> RankStats {
+        self.rank.stats()
+    }
+
+    /// The rows and banks of the DRAM accesses, `None` with DRAMsim3.
+    pub(super) fn row_locality(&self) -> Option<&RowLocality> {
+        self.rank.locality()
+    }
+
+    /// Line size in bytes
+    pub(super) fn line_size(&self) -> usize {
+        1 << self.log_line_size
+    }
+
+    /// Returns the physical line number of `addr` without side effects.
+    pub(super) fn line_of(&self, addr: VirtualAddress) -> u64 {
+        self.tlb.peek(addr).0 >> self.log_line_size
+    }
+
+    /// Whether a read of `addr` would hit, without side effects.
+    pub(super) fn contains(&self, addr: VirtualAddress) -> bool {
+        self.cache_sets
+            .get(self.get_setidx(addr))
+            .is_some_and(|set| set.contains(&self.line_of(addr)))
+    }
+
+    /// Allocates a line, forgetting the prefetch bookkeeping of the victim.
+    fn insert_line(&mut self, setidx: usize, physical_tag: u64) {
+        let Some(set) = self.cache_sets.get_mut(setidx) else {
+            return;
+        };
+        if let Some((victim, _)) = set.push(physical_tag, ()) {
+            if victim != physical_tag {
+                self.prefetched_lines.remove(&victim);
+            }
+        }
+    }
+
+    /// Whether the line is present, updating the LRU order if it is.
+    fn touch_line(&mut self, setidx: usize, physical_tag: u64) -> bool {
+        self.cache_sets
+            .get_mut(setidx)
+            .is_some_and(|set| set.get(&physical_tag).is_some())
+    }
+
+    /// Latency from the access until a miss can leave the processor.
+    ///
+    /// See the VIPT latency model in [`read`](DataCache::read). Without a
+    /// cache there is no tag comparison to hide the translation behind.
+    fn lookup_latency(&self, tlb_resp: &TlbResp) -> usize {
+        if self.cache_sets.is_empty() {
+            tlb_resp.latency
+        } else if tlb_resp.hit {
+            Self::HIT_LATENCY
+        } else {
+            tlb_resp.latency + Self::HIT_LATENCY
+        }
+    }
+
+    /// Reads a missing line from the last-level cache of the DIMM, or from
+    /// DRAM if there is none or it misses too.
+    fn fetch_line(&mut self, addr: PhysicalAddress) -> usize {
+        // Lines of the other ranks of the DIMM are timed on the DRAM model
+        // of this rank, which does not know their row buffer state.
+        match &self.llc {
+            Some(port) if port.llc.lock().unwrap().access(addr, false, port.requester) => {
+                LastLevelCache::HIT_LATENCY
+            }
+            Some(_) => LastLevelCache::HIT_LATENCY + self.rank.transaction(addr, false),
+            None => self.rank.transaction(addr, false),
+        }
+    }
+
+    /// Drops the lines that other processors of the DIMM wrote since the
+    /// last call.
+    pub(super) fn apply_invalidations(&mut self) {
+        let Some(port) = &self.llc else {
+            return;
+        };
+        let ranges = port.llc.lock().unwrap().take_invalidations(port.requester);
+        for range in ranges {
+            let first = range.start >> self.log_line_size;
+            let last = (range.end - 1) >> self.log_line_size;
+            for line in first..=last {
+                // Translation is the identity, so the physical address has
+                // the same set index bits as the virtual one.
+                let setidx = self.get_setidx(VirtualAddress(line << self.log_line_size));
+                let Some(set) = self.cache_sets.get_mut(setidx) else {
+                    continue;
+                };
+                if set.pop(&line).is_some() {
+                    self.prefetched_lines.remove(&line);
+                    self.stats.invalidations += 1;
+                }
+            }
+        }
+    }
+
+    /// Starts fetching the line holding `addr` without allocating it yet.
+    ///
+    /// Returns the latency until the line can be installed with
+    /// [`fill_prefetch`](Self::fill_prefetch), or `None` if the line is
+    /// already cached and no memory traffic is needed.
+    pub(super) fn prefetch(&mut self, addr: VirtualAddress) -> Option<usize> {
+        let setidx = self.get_setidx(addr);
+        let tlb_resp = self.tlb.lookup(addr);
+        let physical_tag = tlb_resp.paddr.0 >> self.log_line_size;
+        if self
+            .cache_sets
+            .get(setidx)
+            .is_some_and(|set| set.contains(&physical_tag))
+        {
+            return None;
+        }
+        let translation = if tlb_resp.hit { 0 } else { tlb_resp.latency };
+        Some(translation + self.fetch_line(tlb_resp.paddr))
+    }
+
+    /// Installs a line whose prefetch has completed.
+    pub(super) fn fill_prefetch(&mut self, addr: VirtualAddress) {
+        let setidx = self.get_setidx(addr);
+        let physical_tag = self.tlb.lookup(addr).paddr.0 >> self.log_line_size;
+        // A demand miss may have brought the line in while the prefetch was
+        // in flight, in which case the prefetch was late and is not credited.
+        // Without a cache there is nowhere to install it.
+        if self.cache_sets.is_empty() || self.cache_sets[setidx].contains(&physical_tag) {
+            return;
+        }
+        self.insert_line(setidx, physical_tag);
+        self.prefetched_lines.insert(physical_tag);
+        self.stats.prefetch_fills += 1;
     }
 }
 
@@ -432,22 +973,18 @@ impl DataCache for SetAssociativeCache {
         // The tag bits here actually include some of the index bits
         // But since we are going to index into a set, this shouldn't affect
         // the implemented cache capacity.
-        let physical_tag = tlb_resp.paddr.cache_line();
-        if self.cache_sets[setidx].get(&physical_tag).is_some() {
+        let physical_tag = tlb_resp.paddr.0 >> self.log_line_size;
+        let lookup_latency = self.lookup_latency(&tlb_resp);
+        if self.touch_line(setidx, physical_tag) {
             self.stats.read_hits += 1;
-            if tlb_resp.hit {
-                Self::HIT_LATENCY
-            } else {
-                tlb_resp.latency + Self::HIT_LATENCY
+            if self.prefetched_lines.remove(&physical_tag) {
+                self.stats.useful_prefetches += 1;
             }
+            lookup_latency
         } else {
-            self.cache_sets[setidx].put(physical_tag, ());
+            self.insert_line(setidx, physical_tag);
             self.stats.read_misses += 1;
-            if tlb_resp.hit {
-                Self::HIT_LATENCY + self.rank.transaction(tlb_resp.paddr, false)
-            } else {
-                tlb_resp.latency + Self::HIT_LATENCY + self.rank.transaction(tlb_resp.paddr, false)
-            }
+            lookup_latency + self.fetch_line(tlb_resp.paddr)
         }
     }
 
@@ -456,23 +993,204 @@ impl DataCache for SetAssociativeCache {
     /// can hit.
     ///
     /// The VIPT latency model is the same as [`read`](Self::read), except that
-    /// the DRAM write latency is always added on top due to the write-through policy.
+    /// the DRAM write latency is always added on top due to the write-through
+    /// policy, unless the write goes to a write-combining buffer.
     fn write(&mut self, addr: VirtualAddress) -> usize {
         let setidx = self.get_setidx(addr);
         let tlb_resp = self.tlb.translate(addr, true);
-        let physical_tag = tlb_resp.paddr.cache_line();
-        if self.cache_sets[setidx].get(&physical_tag).is_some() {
+        let physical_tag = tlb_resp.paddr.0 >> self.log_line_size;
+        if self.touch_line(setidx, physical_tag) {
             self.stats.write_hits += 1;
         } else {
-            self.cache_sets[setidx].put(physical_tag, ());
+            self.insert_line(setidx, physical_tag);
             self.stats.write_misses += 1;
         }
-        let base = if tlb_resp.hit {
-            Self::HIT_LATENCY
-        } else {
-            tlb_resp.latency + Self::HIT_LATENCY
+        // The last-level cache is updated on the way to DRAM, off the
+        // critical path.
+        if let Some(port) = &self.llc {
+            port.llc
+                .lock()
+                .unwrap()
+                .access(tlb_resp.paddr, true, port.requester);
+        }
+        let Some(buffer) = self.write_buffer.as_mut() else {
+            return self.lookup_latency(&tlb_resp) + self.rank.transaction(tlb_resp.paddr, true);
         };
-        base + self.rank.transaction(tlb_resp.paddr, true)
+        // The buffer drains in the background, so only its DRAM bank state
+        // is simulated, not the latency of the writeback.
+        if let Some(victim) = buffer.write(physical_tag) {
+            self.rank
+                .transaction(PhysicalAddress(victim << self.log_line_size), true);
+        }
+        self.lookup_latency(&tlb_resp)
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub(super) struct WriteBufferStats {
+    pub(super) writes: usize,
+    /// Writes merged into a line that was already waiting in the buffer
+    pub(super) coalesced: usize,
+    /// Lines written back to DRAM to make room
+    pub(super) writebacks: usize,
+}
+
+/// A write-combining buffer between a processor cache and DRAM, which keeps
+/// the most recently written lines so that further writes to them, such as
+/// marking neighbouring objects, go to DRAM as a single line write.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct WriteCombiningBuffer {
+    depth: usize,
+    /// Buffered lines, oldest first
+    lines: VecDeque<u64>,
+    pub(super) stats: WriteBufferStats,
+}
+
+impl WriteCombiningBuffer {
+    pub(super) fn new(depth: usize) -> Self {
+        assert!(depth > 0, "Write buffer depth must be greater than zero");
+        WriteCombiningBuffer {
+            depth,
+            lines: VecDeque::with_capacity(depth),
+            stats: WriteBufferStats::default(),
+        }
+    }
+
+    /// Buffers a write to `line`, returning the line written back to DRAM
+    /// to make room, if any.
+    fn write(&mut self, line: u64) -> Option<u64> {
+        self.stats.writes += 1;
+        if self.lines.contains(&line) {
+            self.stats.coalesced += 1;
+            return None;
+        }
+        let victim = (self.lines.len() == self.depth).then(|| self.lines.pop_front().unwrap());
+        if victim.is_some() {
+            self.stats.writebacks += 1;
+        }
+        self.lines.push_back(line);
+        victim
+    }
+
+    /// Lines still waiting to be written back.
+    pub(super) fn pending(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub(super) struct MshrStats {
+    /// Primary misses that allocated an entry
+    pub(super) allocations: usize,
+    /// Secondary misses merged into an outstanding entry for the same line
+    pub(super) merges: usize,
+    /// Sum over sampled ticks of the number of outstanding entries
+    pub(super) occupancy: usize,
+    pub(super) sampled_ticks: usize,
+}
+
+impl MshrStats {
+    pub(super) fn average_outstanding(&self) -> f64 {
+        if self.sampled_ticks == 0 {
+            0.0
+        } else {
+            self.occupancy as f64 / self.sampled_ticks as f64
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MshrEntry<T> {
+    line: u64,
+    ready: usize,
+    targets: Vec<T>,
+}
+
+/// Miss-status holding registers: track outstanding line fills so that the
+/// requester can keep working while misses are serviced, and so that later
+/// misses to a line that is already being fetched wait on the same fill.
+///
+/// `T` is whatever the requester needs to finish the access once the line
+/// arrives.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct MissStatusHoldingRegisters<T> {
+    capacity: usize,
+    entries: Vec<MshrEntry<T>>,
+    pub(super) stats: MshrStats,
+}
+
+impl<T> MissStatusHoldingRegisters<T> {
+    pub(super) fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Need at least one MSHR");
+        MissStatusHoldingRegisters {
+            capacity,
+            entries: Vec::with_capacity(capacity),
+            stats: MshrStats::default(),
+        }
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.entries.len() >= self.capacity
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(super) fn outstanding(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Tick at which the earliest outstanding fill completes.
+    pub(super) fn earliest_ready(&self) -> Option<usize> {
+        self.entries.iter().map(|e| e.ready).min()
+    }
+
+    /// Attaches `target` to an outstanding fill of `line`, if there is one.
+    /// Gives the target back otherwise.
+    pub(super) fn merge(&mut self, line: u64, target: T) -> Result<(), T> {
+        match self.entries.iter_mut().find(|e| e.line == line) {
+            Some(entry) => {
+                entry.targets.push(target);
+                self.stats.merges += 1;
+                Ok(())
+            }
+            None => Err(target),
+        }
+    }
+
+    /// Starts tracking a fill of `line` that completes at tick `ready`.
+    pub(super) fn allocate(&mut self, line: u64, ready: usize, target: T) {
+        assert!(!self.is_full(), "No free MSHR");
+        debug_assert!(self.entries.iter().all(|e| e.line != line));
+        self.entries.push(MshrEntry {
+            line,
+            ready,
+            targets: vec![target],
+        });
+        self.stats.allocations += 1;
+    }
+
+    /// Frees the entries whose fill has completed by `now`, returning the
+    /// line and targets of each.
+    pub(super) fn retire(&mut self, now: usize) -> Vec<(u64, Vec<T>)> {
+        let mut completed = vec![];
+        let mut i = 0;
+        while i < self.entries.len() {
+            if self.entries[i].ready <= now {
+                let entry = self.entries.swap_remove(i);
+                completed.push((entry.line, entry.targets));
+            } else {
+                i += 1;
+            }
+        }
+        completed
+    }
+
+    /// Records the current occupancy, called once per tick.
+    pub(super) fn sample(&mut self) {
+        self.stats.occupancy += self.entries.len();
+        self.stats.sampled_ticks += 1;
     }
 }
 
@@ -481,35 +1199,436 @@ impl DataCache for SetAssociativeCache {
 // 64 GB system (4 DIMMs in two channels, 2 ranks per DIMM)
 // A particular bank is 65536x128x64 (each column has 8 bits, and reads in bursts of 8)
 // So when you read a cache line, you are implictly changing the lower 3 bits of the column address
+// Which bits above the block offset hold which field is up to the
+// `AddressLayout` in use, by default
 // row     rank     bank   channel col    blkoffset
 // [35:20] [19:18] [17:14] [13:13] [12:6] [5:0]
-bitfield! {
-    pub struct AddressMapping(u64);
-    impl Debug;
-    pub u8, blkoffset, set_blkoffset: 5, 0;
-    pub u8, col, set_col: 12, 6;
-    pub u8, channel, set_channel: 13, 13;
-    pub u8, bank, set_bank: 17, 14;
-    pub u8, dimm, set_dimm: 18, 18;
-    pub u8, rank, set_rank: 19, 19;
-    pub u16, row, set_row: 35, 20;
-    pub u32, rest, set_rest: 63, 36;
+// With 16 and 32 processors, the DIMMs have 4 and 8 ranks, and the rank
+// takes 2 and 3 bits, pushing the row up.
+#[derive(Debug)]
+pub struct AddressMapping(pub u64);
+
+/// A field of a physical address, above the block offset
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum AddressField {
+    Column,
+    Channel,
+    Bank,
+    Dimm,
+    Rank,
+    Row,
+}
+
+/// Fields in the order `AddressLayout::encode` takes them
+const FIELDS: [AddressField; 6] = [
+    AddressField::Column,
+    AddressField::Channel,
+    AddressField::Bank,
+    AddressField::Dimm,
+    AddressField::Rank,
+    AddressField::Row,
+];
+
+/// Processors NMPGC can simulate, one per rank of the 4 DIMMs in two
+/// channels
+pub(crate) const NMPGC_PROCESSORS: [usize; 3] = [8, 16, 32];
+
+/// Where the fields of a physical address are, as runs of bits from the
+/// block offset up. A field can be split over several runs, low bits first,
+/// as when the channel is interleaved within the columns of a row. The one
+/// run of the rank takes the rank bits of the configuration, whatever the
+/// width given. With an owner hash, the channel, DIMM and rank are XORed
+/// with a hash of the bits above them, which keeps the owner constant within
+/// an owner block but breaks the correlation of owners with address order.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AddressLayout {
+    runs: &'static [(AddressField, u32)],
+    rank_bits: u32,
+    /// Seed of `--owner-hash`
+    owner_hash: Option<u64>,
+}
+
+/// The layouts of `--address-mapping`, in the order of
+/// `AddressMappingChoice`. They leave out the XOR of upper bits into the
+/// channel and bank that real controllers hash with, which `--owner-hash`
+/// approximates for the owner fields.
+const LAYOUTS: [&[(AddressField, u32)]; 4] = {
+    use AddressField::*;
+    [
+        &[
+            (Column, 7),
+            (Channel, 1),
+            (Bank, 4),
+            (Dimm, 1),
+            (Rank, 1),
+            (Row, 16),
+        ],
+        &[
+            (Column, 2),
+            (Channel, 1),
+            (Column, 5),
+            (Bank, 4),
+            (Dimm, 1),
+            (Rank, 1),
+            (Row, 16),
+        ],
+        &[
+            (Channel, 1),
+            (Column, 7),
+            (Bank, 4),
+            (Rank, 1),
+            (Dimm, 1),
+            (Row, 16),
+        ],
+        &[
+            (Column, 7),
+            (Channel, 1),
+            (Dimm, 1),
+            (Rank, 1),
+            (Bank, 4),
+            (Row, 16),
+        ],
+    ]
+};
+
+/// Index in `LAYOUTS` of the layout in use
+static LAYOUT: AtomicUsize = AtomicUsize::new(0);
+/// Rank bits of the configuration in use, 1 for 8 processors
+static RANK_BITS: AtomicU32 = AtomicU32::new(1);
+/// Whether owners are hashed, and with which seed
+static OWNER_HASHED: AtomicBool = AtomicBool::new(false);
+static OWNER_HASH_SEED: AtomicU64 = AtomicU64::new(0);
+
+/// Checks the layout of `choice` for `processors` processors, with owners
+/// hashed with the seed `owner_hash` if given, and decodes physical
+/// addresses with it from now on.
+pub(crate) fn set_address_mapping(
+    choice: AddressMappingChoice,
+    processors: usize,
+    owner_hash: Option<u64>,
+) -> Result<()> {
+    let layout = AddressLayout::of(choice, processors)?.with_owner_hash(owner_hash);
+    layout.self_test()?;
+    LAYOUT.store(choice as usize, Ordering::Relaxed);
+    RANK_BITS.store(layout.rank_bits, Ordering::Relaxed);
+    OWNER_HASHED.store(owner_hash.is_some(), Ordering::Relaxed);
+    OWNER_HASH_SEED.store(owner_hash.unwrap_or(0), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Ranks on each DIMM in the configuration in use
+pub(crate) fn ranks_per_dimm() -> usize {
+    1 << RANK_BITS.load(Ordering::Relaxed)
+}
+
+/// Banks in the system, 16 in each rank of [`AddressMapping`]
+pub(crate) fn num_banks() -> usize {
+    16 << (2 + RANK_BITS.load(Ordering::Relaxed))
+}
+
+impl AddressLayout {
+    pub(crate) fn of(choice: AddressMappingChoice, processors: usize) -> Result<AddressLayout> {
+        if !NMPGC_PROCESSORS.contains(&processors) {
+            bail!(
+                "Unsupported number of processors for NMPGC: {} (one of {:?})",
+                processors,
+                NMPGC_PROCESSORS
+            );
+        }
+        Ok(AddressLayout {
+            runs: LAYOUTS[choice as usize],
+            // Two channels of two DIMMs take the other 2 bits of the owner
+            rank_bits: processors.trailing_zeros() - 2,
+            owner_hash: None,
+        })
+    }
+
+    pub(crate) fn with_owner_hash(mut self, seed: Option<u64>) -> Self {
+        self.owner_hash = seed;
+        self
+    }
+
+    pub(crate) fn current() -> AddressLayout {
+        AddressLayout {
+            runs: LAYOUTS[LAYOUT.load(Ordering::Relaxed)],
+            rank_bits: RANK_BITS.load(Ordering::Relaxed),
+            owner_hash: OWNER_HASHED
+                .load(Ordering::Relaxed)
+                .then(|| OWNER_HASH_SEED.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// The same layout with owners not hashed, to compare the balance of a
+    /// hashed layout with.
+    pub(crate) fn linear(&self) -> Self {
+        self.with_owner_hash(None)
+    }
+
+    /// Bits of a run, after widening the rank
+    fn run_bits(&self, field: AddressField, bits: u32) -> u32 {
+        if field == AddressField::Rank {
+            self.rank_bits
+        } else {
+            bits
+        }
+    }
+
+    /// Bits each field should have, in the order of `FIELDS`
+    fn field_widths(&self) -> [u32; 6] {
+        FIELDS.map(|f| match f {
+            AddressField::Column => 7,
+            AddressField::Channel | AddressField::Dimm => 1,
+            AddressField::Bank => 4,
+            AddressField::Rank => self.rank_bits,
+            AddressField::Row => 16,
+        })
+    }
+
+    /// Bits of the physical addresses the layout decodes, above which
+    /// addresses wrap around the memory of the system, 8 GB per rank
+    fn log_memory_size(&self) -> u32 {
+        LOG_LINE_SIZE as u32 + self.field_widths().iter().sum::<u32>()
+    }
+
+    pub(crate) fn decode(&self, addr: u64, field: AddressField) -> u64 {
+        let mut shift = LOG_LINE_SIZE as u32;
+        let mut value = 0;
+        let mut width = 0;
+        for &(f, bits) in self.runs {
+            let bits = self.run_bits(f, bits);
+            if f == field {
+                value |= ((addr >> shift) & ((1 << bits) - 1)) << width;
+                width += bits;
+            }
+            shift += bits;
+        }
+        value ^ self.owner_hash_of(addr, field)
+    }
+
+    /// The bits of the hash of the address to XOR into `field`, none unless
+    /// owners are hashed and `field` is part of the owner. The hash is laid
+    /// out as a [`RankId`], so that it XORs into the owner as a whole.
+    fn owner_hash_of(&self, addr: u64, field: AddressField) -> u64 {
+        let Some(seed) = self.owner_hash else {
+            return 0;
+        };
+        let (shift, bits) = match field {
+            AddressField::Channel => (0, 1),
+            AddressField::Dimm => (1, 1),
+            AddressField::Rank => (2, self.rank_bits),
+            _ => return 0,
+        };
+        let addr = addr & ((1 << self.log_memory_size()) - 1);
+        let hash = owner_hash(addr >> self.log_owner_top(), seed, 2 + self.rank_bits);
+        (hash >> shift) & ((1 << bits) - 1)
+    }
+
+    /// log2 of the bytes of the blocks above which no bit is part of the
+    /// owner, and which the owner hash hashes.
+    fn log_owner_top(&self) -> u32 {
+        let mut shift = LOG_LINE_SIZE as u32;
+        let mut top = shift;
+        for &(f, bits) in self.runs {
+            shift += self.run_bits(f, bits);
+            if matches!(
+                f,
+                AddressField::Channel | AddressField::Dimm | AddressField::Rank
+            ) {
+                top = shift;
+            }
+        }
+        top
+    }
+
+    /// The address of the first byte of the block with the given value of
+    /// each field, in the order of `FIELDS`.
+    pub(crate) fn encode(&self, mut fields: [u64; 6]) -> u64 {
+        if self.owner_hash.is_some() {
+            // The hash only depends on the bits above the owner, which the
+            // linear encoding already has
+            let linear = self.linear().encode(fields);
+            for (i, &f) in FIELDS.iter().enumerate() {
+                fields[i] ^= self.owner_hash_of(linear, f);
+            }
+        }
+        let mut shift = LOG_LINE_SIZE as u32;
+        let mut consumed = [0u32; 6];
+        let mut addr = 0;
+        for &(f, bits) in self.runs {
+            let bits = self.run_bits(f, bits);
+            let i = FIELDS.iter().position(|&g| g == f).unwrap();
+            addr |= ((fields[i] >> consumed[i]) & ((1 << bits) - 1)) << shift;
+            consumed[i] += bits;
+            shift += bits;
+        }
+        addr
+    }
+
+    /// log2 of the bytes of the aligned blocks whose bytes all have the
+    /// same owner, the lowest bit of the channel, DIMM and rank.
+    pub(crate) fn log_owner_block(&self) -> u32 {
+        let mut shift = LOG_LINE_SIZE as u32;
+        for &(f, bits) in self.runs {
+            if matches!(
+                f,
+                AddressField::Channel | AddressField::Dimm | AddressField::Rank
+            ) {
+                return shift;
+            }
+            shift += self.run_bits(f, bits);
+        }
+        unreachable!("Address layouts have owner bits")
+    }
+
+    /// Cross-checks the layout with what the simulator assumes of it: that
+    /// it has the bits of the DDR4 geometry, that addresses round-trip
+    /// through the fields, that the owner of an address is the rank its
+    /// channel, DIMM and rank name, grouped into DIMMs as NMPGC groups its
+    /// processors, and that owners only change at the owner blocks.
+    pub(crate) fn self_test(&self) -> Result<()> {
+        let ranks = self.runs.iter().filter(|&&(f, _)| f == AddressField::Rank);
+        if ranks.count() != 1 {
+            bail!("The rank has to be one run of bits");
+        }
+        for (field, width) in FIELDS.into_iter().zip(self.field_widths()) {
+            let bits: u32 = self
+                .runs
+                .iter()
+                .filter(|&&(f, _)| f == field)
+                .map(|&(f, bits)| self.run_bits(f, bits))
+                .sum();
+            if bits != width {
+                bail!("{:?} has {} bits instead of {}", field, bits, width);
+            }
+        }
+        let log_memory_size = self.log_memory_size();
+        let mask = (1u64 << log_memory_size) - (1 << LOG_LINE_SIZE);
+        // Every bit on its own, then addresses drawn from an LCG
+        let mut addrs: Vec<u64> = (LOG_LINE_SIZE as u32..log_memory_size)
+            .map(|bit| 1 << bit)
+            .collect();
+        let mut x: u64 = 0x9e37_79b9_7f4a_7c15;
+        for _ in 0..1024 {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            addrs.push(x & mask);
+        }
+        for addr in addrs {
+            let fields = FIELDS.map(|f| self.decode(addr, f));
+            if self.encode(fields) != addr {
+                bail!("0x{:x} does not round-trip through {:?}", addr, fields);
+            }
+        }
+        let processors = 4u64 << self.rank_bits;
+        let mut owners = HashSet::new();
+        for owner in 0..processors {
+            let rank_id = RankId(owner as u8);
+            let (channel, dimm, rank) = (rank_id.channel(), rank_id.dimm(), rank_id.rank());
+            for (bank, row) in [(0, 0), (15, 0xffff), (5, 0x1234)] {
+                let addr = self.encode([3, channel as u64, bank, dimm as u64, rank as u64, row]);
+                let mapping = AddressMapping(addr);
+                let found = mapping.owner_id_in(self);
+                if found != owner as usize {
+                    bail!("0x{:x} of rank {} is owned by {}", addr, rank_id, found);
+                }
+                let dimm_id = DimmId::from(RankId(found as u8));
+                if (dimm_id.channel(), dimm_id.dimm()) != (channel, dimm) {
+                    bail!("Rank {} is grouped into DIMM {}", rank_id, dimm_id);
+                }
+                if self.decode(addr, AddressField::Bank) != bank
+                    || self.decode(addr, AddressField::Row) != row
+                {
+                    bail!(
+                        "0x{:x} does not decode to bank {} and row {}",
+                        addr,
+                        bank,
+                        row
+                    );
+                }
+                owners.insert(found);
+            }
+        }
+        if owners.len() as u64 != processors {
+            bail!(
+                "Only {} of the {} ranks own addresses",
+                owners.len(),
+                processors
+            );
+        }
+        let block = 1u64 << self.log_owner_block();
+        let owner_of = |addr| AddressMapping(addr).owner_id_in(self);
+        for start in [0, block, 5 * block, 0x1234 * block] {
+            if owner_of(start) != owner_of(start + block - 1) {
+                bail!("The owner changes within the block at 0x{:x}", start);
+            }
+        }
+        if owner_of(0) == owner_of(block) {
+            bail!("The owner does not change after the first owner block");
+        }
+        Ok(())
+    }
 }
 
 impl AddressMapping {
+    fn field(&self, field: AddressField) -> u64 {
+        AddressLayout::current().decode(self.0, field)
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.field(AddressField::Channel) as u8
+    }
+
+    pub fn bank(&self) -> u8 {
+        self.field(AddressField::Bank) as u8
+    }
+
+    pub fn dimm(&self) -> u8 {
+        self.field(AddressField::Dimm) as u8
+    }
+
+    pub fn rank(&self) -> u8 {
+        self.field(AddressField::Rank) as u8
+    }
+
+    pub fn row(&self) -> u16 {
+        self.field(AddressField::Row) as u16
+    }
+
+    /// The address without its column and block offset, which tells the
+    /// DRAM rows apart.
+    pub(crate) fn row_key(&self) -> u64 {
+        let layout = AddressLayout::current();
+        let mut shift = LOG_LINE_SIZE as u32;
+        let mut column = (1 << LOG_LINE_SIZE) - 1;
+        for &(f, bits) in layout.runs {
+            let bits = layout.run_bits(f, bits);
+            if f == AddressField::Column {
+                column |= ((1 << bits) - 1) << shift;
+            }
+            shift += bits;
+        }
+        self.0 & !column
+    }
+
     /// Returns the owner thread ID based on the channel and rank.
     /// This needs to be consistent with the TopologyLocation encoding.
     pub(super) fn get_owner_id(&self) -> usize {
+        self.owner_id_in(&AddressLayout::current())
+    }
+
+    pub(super) fn owner_id_in(&self, layout: &AddressLayout) -> usize {
+        let field = |f| layout.decode(self.0, f) as u8;
         let mut rank_id = RankId(0);
-        rank_id.set_channel(self.channel());
-        rank_id.set_dimm(self.dimm());
-        rank_id.set_rank(self.rank());
+        rank_id.set_channel(field(AddressField::Channel));
+        rank_id.set_dimm(field(AddressField::Dimm));
+        rank_id.set_rank(field(AddressField::Rank));
         rank_id.0 as usize
     }
 }
 
 bitfield! {
-    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
     pub struct DimmId(u8);
     impl Debug;
     pub u8, channel, set_channel: 0, 0;
@@ -532,12 +1651,12 @@ impl From<RankId> for DimmId {
 }
 
 bitfield! {
-    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
     pub struct RankId(u8);
     impl Debug;
     pub u8, channel, set_channel: 0, 0;
     pub u8, dimm, set_dimm: 1, 1;
-    pub u8, rank, set_rank: 2, 2;
+    pub u8, rank, set_rank: 4, 2;
 }
 
 impl Display for RankId {
@@ -557,32 +1676,263 @@ impl RankId {
     }
 }
 
-#[derive(Clone, Default, Debug)]
+/// The DRAM rows and banks a stream of accesses touches, for the row buffer
+/// locality of tracing, which decides how much NMP placement can gain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RowLocality {
+    /// Rows, as the address bits above the column
+    rows: HashSet<u64>,
+    /// Accesses of each bank, by rank and bank
+    bank_accesses: Vec<usize>,
+}
+
+impl Default for RowLocality {
+    fn default() -> Self {
+        RowLocality {
+            rows: HashSet::new(),
+            bank_accesses: vec![0; num_banks()],
+        }
+    }
+}
+
+impl RowLocality {
+    /// Counts an access to `addr`, returning its bank, by rank and bank,
+    /// and its row.
+    pub(crate) fn record(&mut self, addr: u64) -> (usize, u16) {
+        let mapping = AddressMapping(addr);
+        let bank = (mapping.get_owner_id() << 4) | mapping.bank() as usize;
+        if bank >= self.bank_accesses.len() {
+            self.bank_accesses.resize(bank + 1, 0);
+        }
+        self.bank_accesses[bank] += 1;
+        self.rows.insert(mapping.row_key());
+        (bank, mapping.row())
+    }
+
+    pub(crate) fn merge(&mut self, other: &RowLocality) {
+        self.rows.extend(&other.rows);
+        if self.bank_accesses.len() < other.bank_accesses.len() {
+            self.bank_accesses.resize(other.bank_accesses.len(), 0);
+        }
+        for (total, accesses) in self.bank_accesses.iter_mut().zip(&other.bank_accesses) {
+            *total += accesses;
+        }
+    }
+
+    pub(crate) fn rows_touched(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub(crate) fn banks_touched(&self) -> usize {
+        self.bank_accesses.iter().filter(|&&a| a > 0).count()
+    }
+
+    /// Accesses of the busiest bank over the mean of all banks, 1 when the
+    /// accesses are spread evenly and the number of banks when they all go
+    /// to one.
+    pub(crate) fn bank_imbalance(&self) -> f64 {
+        let total: usize = self.bank_accesses.iter().sum();
+        let busiest = self.bank_accesses.iter().copied().max().unwrap_or(0);
+        if total == 0 {
+            return 0.0;
+        }
+        busiest as f64 * self.bank_accesses.len() as f64 / total as f64
+    }
+}
+
+/// When the memory controller closes the open row of a bank.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PagePolicy {
+    /// Keep the row open until another row of the bank is accessed
+    #[default]
+    Open,
+    /// Precharge after every access
+    Closed,
+    /// Precharge once the bank has not been accessed for this many cycles
+    Timeout(usize),
+}
+
+impl Display for PagePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PagePolicy::Open => write!(f, "open-page"),
+            PagePolicy::Closed => write!(f, "closed-page"),
+            PagePolicy::Timeout(cycles) => write!(f, "{}-cycle timeout", cycles),
+        }
+    }
+}
+
+/// How a transaction found the row buffer of its bank
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RowBufferOutcome {
+    Hit,
+    /// The bank was precharged, so only the activation is on the critical
+    /// path
+    Empty,
+    /// Another row was open, or the state of the bank is not known
+    Conflict,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 struct BankState {
     current_row: Option<u16>,
+    /// Whether the bank was closed by a precharge that has completed, as
+    /// opposed to never having been accessed
+    #[serde(default)]
+    precharged: bool,
+    /// Cycle of the last access, for the timeout page policy
+    #[serde(default)]
+    last_access: usize,
 }
 
 impl BankState {
     /// Performs a transaction and returns the latency in cycles.
+    #[cfg(test)]
     fn transaction(&mut self, addr: PhysicalAddress) -> usize {
+        self.access(addr).0
+    }
+
+    /// Performs a transaction and returns the latency in cycles and how it
+    /// found the row buffer.
+    fn access(&mut self, addr: PhysicalAddress) -> (usize, RowBufferOutcome) {
         let mapping = AddressMapping(addr.0);
-        let latency = if self.current_row.is_none() || self.current_row.unwrap() != mapping.row() {
-            // DDR4-3200 Speed Bin -062Y
-            // https://www.mouser.com/datasheet/2/671/Micron_05092023_8gb_ddr4_sdram-3175546.pdf
-            //  tRP + tRCD + tCAS + 4 (double data rate, and burst of 8)
-            22 + 22 + 22 + 4
-        } else {
+        // DDR4-3200 Speed Bin -062Y
+        // https://www.mouser.com/datasheet/2/671/Micron_05092023_8gb_ddr4_sdram-3175546.pdf
+        let outcome = if self.current_row == Some(mapping.row()) {
             // tCAS + 4 (double data rate, and burst of 8)
-            22 + 4
+            (22 + 4, RowBufferOutcome::Hit)
+        } else if self.precharged {
+            // tRCD + tCAS + 4
+            (22 + 22 + 4, RowBufferOutcome::Empty)
+        } else {
+            //  tRP + tRCD + tCAS + 4 (double data rate, and burst of 8)
+            (22 + 22 + 22 + 4, RowBufferOutcome::Conflict)
         };
         self.current_row = Some(mapping.row());
-        latency
+        self.precharged = false;
+        outcome
+    }
+
+    /// Closes the open row, returning whether there was one.
+    fn precharge(&mut self) -> bool {
+        self.precharged = true;
+        self.current_row.take().is_some()
+    }
+
+    /// Leaves a row of another requester open, which the next access
+    /// conflicts with.
+    fn open_foreign_row(&mut self) {
+        self.current_row = None;
+        self.precharged = false;
+    }
+}
+
+/// Periodic refresh of a rank, in DRAM cycles: every `interval` (tREFI)
+/// the rank refreshes for `latency` (tRFC), during which it cannot be
+/// accessed and after which every bank is precharged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefreshTiming {
+    pub interval: usize,
+    pub latency: usize,
+}
+
+impl Default for RefreshTiming {
+    /// 7.8 us and 350 ns, of the 8 Gb DDR4-3200 parts of [`BankState`], at
+    /// 1600 MHz
+    fn default() -> Self {
+        RefreshTiming {
+            interval: 12480,
+            latency: 560,
+        }
+    }
+}
+
+/// Memory time-sliced between the collector and co-running mutators, in
+/// DRAM cycles: the last `window` cycles of every `slice` serve the
+/// mutators, which the collector waits out, and leave rows of theirs open
+/// in every bank. The mutators get `window / slice` of the bandwidth of the
+/// rank, in place of the idle memory the collector otherwise has to itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MutatorTraffic {
+    pub slice: usize,
+    pub window: usize,
+}
+
+impl MutatorTraffic {
+    /// Mutators taking `share` of the bandwidth, in slices of `slice`
+    /// cycles, leaving the collector at least a cycle of each.
+    pub(crate) fn new(slice: usize, share: f64) -> Self {
+        MutatorTraffic {
+            slice,
+            window: ((slice as f64 * share).round() as usize).min(slice - 1),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
+pub(super) struct RankStats {
+    pub(super) transactions: usize,
+    /// Cycles the transactions took, refresh stalls included
+    pub(super) ticks: usize,
+    /// Transactions that found their row open, their bank precharged, and
+    /// another row open
+    pub(super) row_hits: usize,
+    pub(super) row_empties: usize,
+    pub(super) row_conflicts: usize,
+    /// Rows closed by the page policy, rather than by a conflict
+    pub(super) closures: usize,
+    /// Transactions that waited for a refresh to finish, and how long
+    pub(super) refresh_stalls: usize,
+    pub(super) refresh_ticks: usize,
+    /// Transactions that waited for the window of the mutators to end, and
+    /// how long
+    #[serde(default)]
+    pub(super) mutator_stalls: usize,
+    #[serde(default)]
+    pub(super) mutator_ticks: usize,
+}
+
+impl AddAssign for RankStats {
+    fn add_assign(&mut self, other: Self) {
+        self.transactions += other.transactions;
+        self.ticks += other.ticks;
+        self.row_hits += other.row_hits;
+        self.row_empties += other.row_empties;
+        self.row_conflicts += other.row_conflicts;
+        self.closures += other.closures;
+        self.refresh_stalls += other.refresh_stalls;
+        self.refresh_ticks += other.refresh_ticks;
+        self.mutator_stalls += other.mutator_stalls;
+        self.mutator_ticks += other.mutator_ticks;
     }
 }
 
 trait DDR4RankModel: Debug + Send + Sync {
     fn transaction(&mut self, addr: PhysicalAddress, is_write: bool) -> usize;
     fn clone_box(&self) -> Box<dyn DDR4RankModel>;
+    /// Moves the clock of the rank to `now`, for the models that do not
+    /// keep their own
+    fn set_clock(&mut self, _now: usize) {}
+    fn set_refresh(&mut self, _refresh: RefreshTiming) {
+        panic!("{:?} does not take a refresh timing", self);
+    }
+    fn set_mutator_traffic(&mut self, _traffic: MutatorTraffic) {
+        panic!("{:?} does not take mutator traffic", self);
+    }
+    fn set_page_policy(&mut self, _policy: PagePolicy) {
+        panic!("{:?} does not take a page policy", self);
+    }
+    fn stats(&self) -> RankStats {
+        RankStats::default()
+    }
+    fn locality(&self) -> Option<&RowLocality> {
+        None
+    }
+    /// The model as a naive one, the only kind whose state can be
+    /// checkpointed
+    fn as_naive(&self) -> Option<&DDR4RankNaive> {
+        None
+    }
 }
 
 impl Clone for Box<dyn DDR4RankModel> {
@@ -591,34 +1941,211 @@ impl Clone for Box<dyn DDR4RankModel> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct DDR4RankNaive {
     banks: Vec<BankState>,
+    /// No refresh if `None`, as DRAM was originally modelled
+    refresh: Option<RefreshTiming>,
+    #[serde(default)]
+    page_policy: PagePolicy,
+    now: usize,
+    /// Refreshes up to the last transaction, which closed the open rows
+    refreshes: usize,
+    /// The memory of the collector alone if `None`
+    #[serde(default)]
+    mutator: Option<MutatorTraffic>,
+    /// Windows of the mutators started up to the last transaction
+    #[serde(default)]
+    mutator_windows: usize,
+    stats: RankStats,
+    #[serde(default)]
+    locality: RowLocality,
 }
 
 impl Default for DDR4RankNaive {
     fn default() -> Self {
         Self {
             banks: vec![BankState::default(); 16],
+            refresh: None,
+            page_policy: PagePolicy::Open,
+            now: 0,
+            refreshes: 0,
+            mutator: None,
+            mutator_windows: 0,
+            stats: RankStats::default(),
+            locality: RowLocality::default(),
+        }
+    }
+}
+
+impl DDR4RankNaive {
+    /// Cycles until the refresh in progress at the current tick finishes,
+    /// closing the rows of every bank if one happened since the last
+    /// transaction.
+    fn wait_for_refresh(&mut self) -> usize {
+        let Some(refresh) = self.refresh else {
+            return 0;
+        };
+        // Refreshes start at `interval`, `2 * interval`, ..., not at tick 0
+        let refreshes = self.now / refresh.interval;
+        if refreshes > self.refreshes {
+            self.refreshes = refreshes;
+            for bank in &mut self.banks {
+                bank.precharge();
+            }
+        }
+        let since = self.now % refresh.interval;
+        if refreshes == 0 || since >= refresh.latency {
+            return 0;
         }
+        self.stats.refresh_stalls += 1;
+        self.stats.refresh_ticks += refresh.latency - since;
+        refresh.latency - since
+    }
+
+    /// Cycles from `now` until the window of the mutators in progress then
+    /// ends, leaving a row of the mutators open in every bank if a window
+    /// started since the last transaction.
+    fn wait_for_mutator(&mut self, now: usize) -> usize {
+        let Some(traffic) = self.mutator else {
+            return 0;
+        };
+        let since = now % traffic.slice;
+        let window_start = traffic.slice - traffic.window;
+        let windows = now / traffic.slice + (since >= window_start) as usize;
+        if windows > self.mutator_windows {
+            self.mutator_windows = windows;
+            for bank in &mut self.banks {
+                bank.open_foreign_row();
+            }
+        }
+        if since < window_start {
+            return 0;
+        }
+        self.stats.mutator_stalls += 1;
+        self.stats.mutator_ticks += traffic.slice - since;
+        traffic.slice - since
     }
 }
 
 impl DDR4RankModel for DDR4RankNaive {
     fn transaction(&mut self, addr: PhysicalAddress, _is_write: bool) -> usize {
+        let stall = self.wait_for_refresh();
+        let stall = stall + self.wait_for_mutator(self.now + stall);
+        self.locality.record(addr.0);
         let mapping = AddressMapping(addr.0);
-        let bank_idx = mapping.bank() as usize;
-        self.banks[bank_idx].transaction(addr)
+        let bank = &mut self.banks[mapping.bank() as usize];
+        if let PagePolicy::Timeout(cycles) = self.page_policy {
+            // The controller precharged the bank in the background
+            if self.now.saturating_sub(bank.last_access) >= cycles && bank.precharge() {
+                self.stats.closures += 1;
+            }
+        }
+        let (latency, outcome) = bank.access(addr);
+        bank.last_access = self.now;
+        if self.page_policy == PagePolicy::Closed {
+            // Auto-precharge, off the critical path of this access
+            bank.precharge();
+            self.stats.closures += 1;
+        }
+        match outcome {
+            RowBufferOutcome::Hit => self.stats.row_hits += 1,
+            RowBufferOutcome::Empty => self.stats.row_empties += 1,
+            RowBufferOutcome::Conflict => self.stats.row_conflicts += 1,
+        }
+        let latency = stall + latency;
+        self.stats.transactions += 1;
+        self.stats.ticks += latency;
+        latency
     }
 
     fn clone_box(&self) -> Box<dyn DDR4RankModel> {
         Box::new(self.clone())
     }
+
+    fn set_clock(&mut self, now: usize) {
+        self.now = now;
+    }
+
+    fn set_refresh(&mut self, refresh: RefreshTiming) {
+        assert!(
+            refresh.interval > refresh.latency,
+            "A rank cannot refresh for longer than the refresh interval"
+        );
+        self.refresh = Some(refresh);
+    }
+
+    fn set_mutator_traffic(&mut self, traffic: MutatorTraffic) {
+        assert!(
+            traffic.window < traffic.slice,
+            "The mutators cannot take every cycle of a slice"
+        );
+        self.mutator = (traffic.window > 0).then_some(traffic);
+    }
+
+    fn set_page_policy(&mut self, policy: PagePolicy) {
+        self.page_policy = policy;
+    }
+
+    fn stats(&self) -> RankStats {
+        self.stats
+    }
+
+    fn locality(&self) -> Option<&RowLocality> {
+        Some(&self.locality)
+    }
+
+    fn as_naive(&self) -> Option<&DDR4RankNaive> {
+        Some(self)
+    }
+}
+
+/// Naive ranks on their own, to replay a stream of line accesses outside
+/// of a simulation. Each rank serves its accesses back to back, in
+/// parallel with the other ranks.
+#[derive(Debug, Default)]
+pub(crate) struct DramReplay {
+    ranks: HashMap<usize, DDR4RankNaive>,
+}
+
+impl DramReplay {
+    /// The clock of the DRAM cycles of the ranks, DDR4-3200
+    pub(crate) const FREQUENCY_GHZ: f64 = 1.6;
+
+    /// Performs an access to the line of `addr` on its rank, returning its
+    /// latency in cycles.
+    pub(crate) fn access(&mut self, addr: u64, is_write: bool) -> usize {
+        let owner = AddressMapping(addr).get_owner_id();
+        let rank = self.ranks.entry(owner).or_default();
+        let latency = rank.transaction(PhysicalAddress(addr), is_write);
+        rank.now += latency;
+        latency
+    }
+
+    /// Cycles of the busiest rank, which the whole stream takes.
+    pub(crate) fn ticks(&self) -> usize {
+        self.ranks
+            .values()
+            .map(|r| r.stats.ticks)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn ranks(&self) -> usize {
+        self.ranks.len()
+    }
+
+    pub(crate) fn row_hit_rate(&self) -> f64 {
+        let mut stats = RankStats::default();
+        for rank in self.ranks.values() {
+            stats += rank.stats;
+        }
+        stats.row_hits as f64 / stats.transactions.max(1) as f64
+    }
 }
 
 use crate::shim::ffi;
 use std::ffi::CString;
-use std::sync::Mutex;
 
 #[derive(Debug)]
 struct DRAMSim3 {
@@ -773,6 +2300,50 @@ impl DDR4Rank {
     fn transaction(&mut self, addr: PhysicalAddress, is_write: bool) -> usize {
         self.inner.transaction(addr, is_write)
     }
+
+    fn set_clock(&mut self, now: usize) {
+        self.inner.set_clock(now);
+    }
+
+    fn set_refresh(&mut self, refresh: RefreshTiming) {
+        self.inner.set_refresh(refresh);
+    }
+
+    fn set_mutator_traffic(&mut self, traffic: MutatorTraffic) {
+        self.inner.set_mutator_traffic(traffic);
+    }
+
+    fn set_page_policy(&mut self, policy: PagePolicy) {
+        self.inner.set_page_policy(policy);
+    }
+
+    fn stats(&self) -> RankStats {
+        self.inner.stats()
+    }
+
+    fn locality(&self) -> Option<&RowLocality> {
+        self.inner.locality()
+    }
+}
+
+impl Serialize for DDR4Rank {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // DRAMsim3 keeps its state on the C++ side
+        match self.inner.as_naive() {
+            Some(naive) => naive.serialize(serializer),
+            None => Err(serde::ser::Error::custom(
+                "the state of DRAMsim3 cannot be checkpointed",
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DDR4Rank {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(DDR4Rank {
+            inner: Box::new(DDR4RankNaive::deserialize(deserializer)?),
+        })
+    }
 }
 
 impl Default for DDR4Rank {
@@ -781,11 +2352,61 @@ impl Default for DDR4Rank {
     }
 }
 
+/// Serializes LRU caches as their capacity and entries from the least to
+/// the most recently used, which rebuilds the same recency order on load.
+mod lru_sets {
+    use lru::LruCache;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::num::NonZeroUsize;
+
+    type Set<V> = (usize, Vec<(u64, V)>);
+
+    pub(super) fn serialize<S: Serializer, V: Serialize + Clone>(
+        sets: &[LruCache<u64, V>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let sets: Vec<Set<V>> = sets
+            .iter()
+            .map(|set| {
+                let entries = set.iter().rev().map(|(&k, v)| (k, v.clone())).collect();
+                (set.cap().get(), entries)
+            })
+            .collect();
+        sets.serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<LruCache<u64, V>>, D::Error> {
+        let sets: Vec<Set<V>> = Vec::deserialize(deserializer)?;
+        sets.into_iter()
+            .map(|(cap, entries)| {
+                let cap = NonZeroUsize::new(cap)
+                    .ok_or_else(|| serde::de::Error::custom("empty cache set"))?;
+                let mut set = LruCache::new(cap);
+                for (k, v) in entries {
+                    set.put(k, v);
+                }
+                Ok(set)
+            })
+            .collect()
+    }
+}
+
 // Unit tests for FullyAssociativeCache
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn set_associative(sets: usize, ways: usize) -> SetAssociativeCache {
+        let geometry = CacheGeometry::SetAssociative {
+            sets,
+            ways,
+            line_size: LINE_SIZE,
+        };
+        SetAssociativeCache::new(geometry, None, DDR4RankOption::Naive, PageSize::FourKB)
+    }
+
     #[test]
     fn test_fully_associative_cache() {
         let mut cache = FullyAssociativeCache::new(64, DDR4RankOption::Naive, PageSize::FourKB);
@@ -823,7 +2444,7 @@ mod tests {
 
     #[test]
     fn test_set_associative_cache() {
-        let mut cache = SetAssociativeCache::new(2, 1, DDR4RankOption::Naive, PageSize::FourKB);
+        let mut cache = set_associative(2, 1);
         // First access: TLB miss + cache miss
         assert!(cache.read(VirtualAddress(0)) > SetAssociativeCache::HIT_LATENCY);
         // Same page + same line: TLB hit + cache hit
@@ -862,6 +2483,203 @@ mod tests {
         assert_eq!(cache.tlb.stats.write_misses, 0);
     }
 
+    #[test]
+    fn test_prefetch_fill_and_use() {
+        let mut cache = set_associative(2, 1);
+        // Warm up the TLB so that the prefetch latency is just DRAM
+        cache.read(VirtualAddress(0));
+        assert!(cache.prefetch(VirtualAddress(0)).is_none());
+        let latency = cache.prefetch(VirtualAddress(64)).unwrap();
+        assert!(latency > SetAssociativeCache::HIT_LATENCY);
+        // Prefetches don't count as demand accesses
+        assert_eq!(cache.tlb.stats.read_hits, 0);
+        cache.fill_prefetch(VirtualAddress(64));
+        assert_eq!(cache.stats.prefetch_fills, 1);
+        assert_eq!(
+            cache.read(VirtualAddress(64)),
+            SetAssociativeCache::HIT_LATENCY
+        );
+        assert_eq!(cache.stats.useful_prefetches, 1);
+        // Only the first demand hit is credited to the prefetch
+        cache.read(VirtualAddress(64));
+        assert_eq!(cache.stats.useful_prefetches, 1);
+        // A prefetched line evicted before use is not useful
+        cache.prefetch(VirtualAddress(192)).unwrap();
+        cache.fill_prefetch(VirtualAddress(192));
+        cache.read(VirtualAddress(320));
+        cache.read(VirtualAddress(192));
+        assert_eq!(cache.stats.prefetch_fills, 2);
+        assert_eq!(cache.stats.useful_prefetches, 1);
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut cache = set_associative(2, 2);
+        for addr in [0, 128, 256, 64, 0] {
+            cache.read(VirtualAddress(addr));
+        }
+        let mut loaded: SetAssociativeCache =
+            bincode::deserialize(&bincode::serialize(&cache).unwrap()).unwrap();
+        assert_eq!(loaded.stats.read_hits, cache.stats.read_hits);
+        // The recency order survives, so both evict the same lines
+        for addr in [384, 128, 0, 64, 256] {
+            assert_eq!(
+                loaded.read(VirtualAddress(addr)),
+                cache.read(VirtualAddress(addr))
+            );
+        }
+        assert_eq!(loaded.stats.read_misses, cache.stats.read_misses);
+    }
+
+    #[test]
+    fn test_cache_geometry_parse() {
+        assert_eq!(
+            "64x8x64".parse::<CacheGeometry>(),
+            Ok(CacheGeometry::DEFAULT)
+        );
+        assert_eq!(CacheGeometry::DEFAULT.capacity(), 32 * 1024);
+        assert_eq!("none".parse::<CacheGeometry>(), Ok(CacheGeometry::None));
+        assert_eq!(CacheGeometry::DEFAULT.to_string(), "64x8x64");
+        assert!("64x8".parse::<CacheGeometry>().is_err());
+        assert!("48x8x64".parse::<CacheGeometry>().is_err());
+        assert!("64x0x64".parse::<CacheGeometry>().is_err());
+        assert!("64x8x4".parse::<CacheGeometry>().is_err());
+    }
+
+    #[test]
+    fn test_no_cache() {
+        let mut cache = SetAssociativeCache::new(
+            CacheGeometry::None,
+            None,
+            DDR4RankOption::Naive,
+            PageSize::FourKB,
+        );
+        cache.read(VirtualAddress(0));
+        // Every access goes to DRAM, and nothing hides the TLB hit
+        assert!(cache.read(VirtualAddress(0)) > Tlb::HIT_LATENCY);
+        assert!(!cache.contains(VirtualAddress(0)));
+        assert_eq!(cache.stats.read_hits, 0);
+        assert_eq!(cache.stats.read_misses, 2);
+    }
+
+    #[test]
+    fn test_line_size_and_llc() {
+        let geometry = CacheGeometry::SetAssociative {
+            sets: 1,
+            ways: 1,
+            line_size: 128,
+        };
+        let llc = shared_llc(false);
+        let mut a = behind_llc(geometry, &llc, 0);
+        let mut b = behind_llc(geometry, &llc, 1);
+        a.read(VirtualAddress(0));
+        // Same 128-byte line
+        assert_eq!(a.read(VirtualAddress(64)), SetAssociativeCache::HIT_LATENCY);
+        // Both caches translate with identity mappings, so `b` finds the line
+        // `a` brought into the shared cache, also after evicting it locally.
+        b.read(VirtualAddress(0));
+        b.read(VirtualAddress(4096));
+        assert_eq!(
+            b.read(VirtualAddress(0)),
+            SetAssociativeCache::HIT_LATENCY + LastLevelCache::HIT_LATENCY
+        );
+        let llc = llc.lock().unwrap();
+        assert_eq!(llc.stats.read_hits, 2);
+        assert_eq!(llc.stats.read_misses, 2);
+    }
+
+    fn shared_llc(coherent: bool) -> Arc<Mutex<LastLevelCache>> {
+        let geometry = CacheGeometry::SetAssociative {
+            sets: 16,
+            ways: 4,
+            line_size: 128,
+        };
+        Arc::new(Mutex::new(LastLevelCache::new(geometry, coherent)))
+    }
+
+    fn behind_llc(
+        geometry: CacheGeometry,
+        llc: &Arc<Mutex<LastLevelCache>>,
+        requester: usize,
+    ) -> SetAssociativeCache {
+        let port = LlcPort {
+            llc: llc.clone(),
+            requester,
+        };
+        SetAssociativeCache::new(
+            geometry,
+            Some(port),
+            DDR4RankOption::Naive,
+            PageSize::FourKB,
+        )
+    }
+
+    #[test]
+    fn test_llc_coherence() {
+        let llc = shared_llc(true);
+        let mut a = behind_llc(CacheGeometry::DEFAULT, &llc, 0);
+        let mut b = behind_llc(CacheGeometry::DEFAULT, &llc, 1);
+        a.read(VirtualAddress(0));
+        b.read(VirtualAddress(0));
+        assert_eq!(llc.lock().unwrap().coherence.shared_fills, 1);
+        // The write invalidates the 128-byte line of the shared cache, which
+        // covers two lines of the processor caches.
+        b.read(VirtualAddress(64));
+        a.write(VirtualAddress(0));
+        assert_eq!(llc.lock().unwrap().coherence.invalidations, 1);
+        b.apply_invalidations();
+        assert_eq!(b.stats.invalidations, 2);
+        assert!(!b.contains(VirtualAddress(0)));
+        assert!(!b.contains(VirtualAddress(64)));
+        // The writer keeps its copy
+        a.apply_invalidations();
+        assert!(a.contains(VirtualAddress(0)));
+        assert_eq!(
+            b.read(VirtualAddress(0)),
+            SetAssociativeCache::HIT_LATENCY + LastLevelCache::HIT_LATENCY
+        );
+    }
+
+    #[test]
+    fn test_write_combining() {
+        let mut cache = set_associative(64, 8);
+        cache.write_buffer = Some(WriteCombiningBuffer::new(2));
+        cache.write(VirtualAddress(0));
+        // Same line, so the write is combined and does not go to DRAM
+        assert_eq!(
+            cache.write(VirtualAddress(16)),
+            SetAssociativeCache::HIT_LATENCY
+        );
+        cache.write(VirtualAddress(64));
+        cache.write(VirtualAddress(128));
+        let buffer = cache.write_buffer.as_ref().unwrap();
+        assert_eq!(buffer.stats.writes, 4);
+        assert_eq!(buffer.stats.coalesced, 1);
+        assert_eq!(buffer.stats.writebacks, 1);
+        assert_eq!(buffer.pending(), 2);
+    }
+
+    #[test]
+    fn test_mshrs() {
+        let mut mshrs: MissStatusHoldingRegisters<u64> = MissStatusHoldingRegisters::new(2);
+        assert!(mshrs.is_empty());
+        mshrs.allocate(1, 10, 100);
+        assert_eq!(mshrs.merge(1, 101), Ok(()));
+        assert_eq!(mshrs.merge(2, 200), Err(200));
+        mshrs.allocate(2, 5, 200);
+        assert!(mshrs.is_full());
+        assert_eq!(mshrs.earliest_ready(), Some(5));
+        mshrs.sample();
+        assert!(mshrs.retire(4).is_empty());
+        assert_eq!(mshrs.retire(5), vec![(2, vec![200])]);
+        mshrs.sample();
+        assert_eq!(mshrs.retire(10), vec![(1, vec![100, 101])]);
+        assert!(mshrs.is_empty());
+        assert_eq!(mshrs.stats.allocations, 2);
+        assert_eq!(mshrs.stats.merges, 1);
+        assert_eq!(mshrs.stats.average_outstanding(), 1.5);
+    }
+
     #[test]
     fn test_bank_state() {
         let mut bank_state = BankState::default();
@@ -885,6 +2703,153 @@ mod tests {
         assert_eq!(bank_state.transaction(addr), 22 + 4);
     }
 
+    #[test]
+    fn test_rank_refresh() {
+        let mut rank = DDR4Rank::default();
+        rank.set_refresh(RefreshTiming {
+            interval: 1000,
+            latency: 100,
+        });
+        let addr = PhysicalAddress(0);
+        // No refresh before the first interval
+        assert_eq!(rank.transaction(addr, false), 22 + 22 + 22 + 4);
+        rank.set_clock(999);
+        assert_eq!(rank.transaction(addr, false), 22 + 4);
+        // Waits out the refresh, which precharged the bank
+        rank.set_clock(1040);
+        assert_eq!(rank.transaction(addr, false), 60 + 22 + 22 + 4);
+        rank.set_clock(1100);
+        assert_eq!(rank.transaction(addr, false), 22 + 4);
+        let stats = rank.stats();
+        assert_eq!(stats.transactions, 4);
+        assert_eq!((stats.refresh_stalls, stats.refresh_ticks), (1, 60));
+        assert_eq!(stats.ticks, 70 + 26 + 108 + 26);
+    }
+
+    #[test]
+    fn test_rank_mutator_traffic() {
+        let mut rank = DDR4Rank::default();
+        rank.set_mutator_traffic(MutatorTraffic::new(1000, 0.25));
+        let addr = PhysicalAddress(0);
+        assert_eq!(rank.transaction(addr, false), 22 + 22 + 22 + 4);
+        rank.set_clock(700);
+        assert_eq!(rank.transaction(addr, false), 22 + 4);
+        // Waits out the window of the mutators, which opened rows of theirs
+        rank.set_clock(800);
+        assert_eq!(rank.transaction(addr, false), 200 + 22 + 22 + 22 + 4);
+        rank.set_clock(1100);
+        assert_eq!(rank.transaction(addr, false), 22 + 4);
+        let stats = rank.stats();
+        assert_eq!((stats.mutator_stalls, stats.mutator_ticks), (1, 200));
+        assert_eq!(rank.inner.as_naive().unwrap().mutator_windows, 1);
+    }
+
+    #[test]
+    fn test_row_locality() {
+        let mut locality = RowLocality::default();
+        // Two columns of one row, then another bank, then another rank
+        assert_eq!(locality.record(0), (0, 0));
+        assert_eq!(locality.record(1 << 6), (0, 0));
+        assert_eq!(locality.record(1 << 14), (1, 0));
+        assert_eq!(locality.record(1 << 19), (0b100_0000, 0));
+        assert_eq!(locality.rows_touched(), 3);
+        assert_eq!(locality.banks_touched(), 3);
+        assert_eq!(locality.bank_imbalance(), 2.0 * num_banks() as f64 / 4.0);
+        let mut other = RowLocality::default();
+        other.record(1 << 20);
+        locality.merge(&other);
+        assert_eq!(locality.rows_touched(), 4);
+        assert_eq!(locality.bank_accesses[0], 3);
+    }
+
+    #[test]
+    fn test_address_layouts() {
+        for &choice in AddressMappingChoice::value_variants() {
+            for processors in NMPGC_PROCESSORS {
+                let layout = AddressLayout::of(choice, processors).unwrap();
+                layout.self_test().unwrap();
+                layout.with_owner_hash(Some(42)).self_test().unwrap();
+                let addr = layout.encode([0x55, 1, 9, 0, 1, 0xbeef]);
+                assert_eq!(layout.decode(addr, AddressField::Bank), 9);
+                assert_eq!(layout.decode(addr, AddressField::Row), 0xbeef);
+            }
+        }
+        assert!(AddressLayout::of(AddressMappingChoice::Baseline, 4).is_err());
+        // Hashing moves the owner of whole rows, keeping the bank and row
+        let hashed = AddressLayout::of(AddressMappingChoice::Baseline, 8)
+            .unwrap()
+            .with_owner_hash(Some(42));
+        let owners: HashSet<usize> = (0..64u64)
+            .map(|row| {
+                let addr = row << 20;
+                assert_eq!(hashed.decode(addr, AddressField::Row), row);
+                AddressMapping(addr).owner_id_in(&hashed)
+            })
+            .collect();
+        assert_eq!(owners.len(), 8);
+        // The default layout is the one the rest of the simulator was
+        // written against
+        let baseline = AddressLayout::of(AddressMappingChoice::Baseline, 8).unwrap();
+        assert_eq!(baseline.log_owner_block(), 13);
+        assert_eq!(baseline.encode([0, 1, 0, 0, 1, 0]), (1 << 13) | (1 << 19));
+        assert_eq!(
+            AddressLayout::of(AddressMappingChoice::ChannelLine, 8)
+                .unwrap()
+                .log_owner_block(),
+            6
+        );
+        // With 32 processors the rank takes 3 bits, pushing the row up
+        let wide = AddressLayout::of(AddressMappingChoice::Baseline, 32).unwrap();
+        assert_eq!(wide.encode([0, 0, 0, 0, 7, 0]), 7 << 19);
+        assert_eq!(wide.encode([0, 0, 0, 0, 0, 1]), 1 << 22);
+        assert_eq!(wide.log_memory_size(), 38);
+        // A layout missing the DIMM bit leaves half of the ranks without
+        // memory
+        let broken = AddressLayout {
+            runs: &[
+                (AddressField::Column, 7),
+                (AddressField::Channel, 1),
+                (AddressField::Bank, 4),
+                (AddressField::Rank, 1),
+                (AddressField::Row, 17),
+            ],
+            rank_bits: 1,
+            owner_hash: None,
+        };
+        assert!(broken.self_test().is_err());
+    }
+
+    #[test]
+    fn test_page_policies() {
+        let row0 = PhysicalAddress(0);
+        let row1 = PhysicalAddress(0b1_00_0000_0_0000000_000000);
+        let latencies = |policy: PagePolicy| {
+            let mut rank = DDR4Rank::default();
+            rank.set_page_policy(policy);
+            let latencies: Vec<usize> = [(0, row0), (10, row0), (20, row1), (500, row1)]
+                .into_iter()
+                .map(|(now, addr)| {
+                    rank.set_clock(now);
+                    rank.transaction(addr, false)
+                })
+                .collect();
+            (latencies, rank.stats())
+        };
+        let (open, stats) = latencies(PagePolicy::Open);
+        assert_eq!(open, [70, 26, 70, 26]);
+        assert_eq!(
+            (stats.row_hits, stats.row_conflicts, stats.closures),
+            (2, 2, 0)
+        );
+        let (closed, stats) = latencies(PagePolicy::Closed);
+        assert_eq!(closed, [70, 48, 48, 48]);
+        assert_eq!((stats.row_empties, stats.closures), (3, 4));
+        // Only the idle gap before the last access closes the row
+        let (timeout, stats) = latencies(PagePolicy::Timeout(100));
+        assert_eq!(timeout, [70, 26, 70, 48]);
+        assert_eq!((stats.row_empties, stats.closures), (1, 1));
+    }
+
     // ------- TLB-specific tests -------
 
     #[test]
@@ -967,11 +2932,44 @@ mod tests {
         assert_eq!(tlb.stats.total_misses(), 2);
     }
 
+    #[test]
+    fn test_tlb_geometry() {
+        assert_eq!("none".parse::<TlbGeometry>(), Ok(TlbGeometry::None));
+        assert_eq!(
+            "16x4".parse::<TlbGeometry>(),
+            Ok(TlbGeometry::SetAssociative {
+                entries: 16,
+                ways: 4
+            })
+        );
+        assert!("16x5".parse::<TlbGeometry>().is_err());
+        assert!("16".parse::<TlbGeometry>().is_err());
+
+        // A fully associative TLB of two entries with a fixed miss penalty
+        let geometry = TlbGeometry::SetAssociative {
+            entries: 2,
+            ways: 2,
+        };
+        let mut tlb = Tlb::with_geometry(PageSize::FourKB, geometry, Some(100));
+        assert_eq!(tlb.translate(VirtualAddress(0x1000), false).latency, 100);
+        assert_eq!(tlb.translate(VirtualAddress(0x2000), false).latency, 100);
+        assert!(tlb.translate(VirtualAddress(0x1000), false).hit);
+        tlb.translate(VirtualAddress(0x3000), false);
+        assert!(!tlb.translate(VirtualAddress(0x2000), false).hit);
+
+        // Physically addressed: no translation, no time, no stats
+        let mut tlb = Tlb::with_geometry(PageSize::FourKB, TlbGeometry::None, Some(100));
+        let resp = tlb.translate(VirtualAddress(0x1234), true);
+        assert_eq!(resp.paddr, PhysicalAddress(0x1234));
+        assert_eq!(resp.latency, 0);
+        assert_eq!(tlb.stats.total_hits() + tlb.stats.total_misses(), 0);
+    }
+
     // ------- VIPT combination tests -------
 
     #[test]
     fn test_vipt_tlb_hit_cache_hit() {
-        let mut cache = SetAssociativeCache::new(16, 4, DDR4RankOption::Naive, PageSize::FourKB);
+        let mut cache = set_associative(16, 4);
         // Warm up both TLB and cache
         cache.read(VirtualAddress(0x1000));
         // TLB hit + cache hit
@@ -985,7 +2983,7 @@ mod tests {
 
     #[test]
     fn test_vipt_tlb_hit_cache_miss() {
-        let mut cache = SetAssociativeCache::new(16, 4, DDR4RankOption::Naive, PageSize::FourKB);
+        let mut cache = set_associative(16, 4);
         // Warm up TLB for 0x1xxx page
         cache.read(VirtualAddress(0x1000));
         // Access different line on same page: TLB hit, cache miss
@@ -1000,7 +2998,7 @@ mod tests {
     fn test_vipt_tlb_miss_cache_hit() {
         // 64 sets is the maximum for VIPT with 4KB pages (set-index bits [6..12)
         // must stay within the 12-bit page offset).
-        let mut cache = SetAssociativeCache::new(64, 4, DDR4RankOption::Naive, PageSize::FourKB);
+        let mut cache = set_associative(64, 4);
         let ptw = PageTableWalker::latency(PageSize::FourKB);
         // Warm TLB + cache for page 0x1000 (VPN page number 1, TLB set 1).
         cache.read(VirtualAddress(0x1000));
@@ -1026,7 +3024,7 @@ mod tests {
 
     #[test]
     fn test_vipt_tlb_miss_cache_miss() {
-        let mut cache = SetAssociativeCache::new(16, 4, DDR4RankOption::Naive, PageSize::FourKB);
+        let mut cache = set_associative(16, 4);
         let ptw = PageTableWalker::latency(PageSize::FourKB);
         // Very first access: TLB miss + cache miss
         let lat = cache.read(VirtualAddress(0x1000));
@@ -1,42 +1,212 @@
+use crate::object_model::Header;
+use crate::progress::Progress;
+use crate::results::{RunResults, StatsTable};
+use crate::root_scan::RootStacks;
+use crate::trace::marker_map::{self, MarkerMap};
+use crate::util::imbalance;
 use crate::{simulate::tracing::serialize_to_gzip_json, *};
-use anyhow::Result;
+use anyhow::{bail, Result};
+use polars::prelude::DataFrame;
+use std::io::{Read, Write};
+use std::sync::Arc;
 use std::{collections::HashMap, path::Path};
 
+/// Evaluates `$body` with the const `$log` bound to the log2 of
+/// `$processors`, for each number of processors NMPGC can simulate (see
+/// `NMPGC_PROCESSORS`).
+macro_rules! with_nmpgc_processors {
+    ($processors:expr, $log:ident => $body:expr) => {
+        match $processors {
+            8 => {
+                const $log: u8 = 3;
+                $body
+            }
+            16 => {
+                const $log: u8 = 4;
+                $body
+            }
+            32 => {
+                const $log: u8 = 5;
+                $body
+            }
+            n => bail!(
+                "Unsupported number of processors for NMPGC: {} (one of {:?})",
+                n,
+                NMPGC_PROCESSORS
+            ),
+        }
+    };
+}
+
+mod checkpoint;
+use checkpoint::CheckpointKey;
+mod convert_trace;
+pub use convert_trace::convert_trace;
+mod cross_check;
+mod divergence;
+pub(crate) use divergence::SimulationDiverged;
+use divergence::Watchdog;
+mod event_log;
+use event_log::EventLog;
 mod ideal_trace_utilization;
 use ideal_trace_utilization::IdealTraceUtilization;
+mod local_closure;
+use local_closure::local_closures;
 mod nmpgc;
 use nmpgc::NMPGC;
+pub(crate) use nmpgc::{ProcessorTimingModel, TopologyFile};
 mod memory;
-pub(crate) use memory::PageSize;
-mod tracing;
+mod multi_tenant;
+pub(crate) use memory::{
+    num_banks, set_address_mapping, AddressLayout, AddressMapping, CacheGeometry, DramReplay,
+    PageSize, RefreshTiming, RowLocality, TlbGeometry, LOG_LINE_SIZE, NMPGC_PROCESSORS,
+};
+pub(crate) mod tracing;
+
+/// Ticks between two looks at the progress of a simulation and at ctrl-C
+const POLL_TICKS: usize = 1 << 12;
 
 trait SimulationArchitecture {
     fn tick<O: ObjectModel>(&mut self) -> bool;
     fn new<O: ObjectModel>(args: &SimulationArgs, object_model: &O) -> Self;
     fn stats(&self) -> HashMap<String, f64>;
+    /// Ticks simulated so far
+    fn ticks(&self) -> usize;
+    /// Objects marked so far
+    fn marked_objects(&self) -> usize;
     fn events(&self) -> Vec<tracing::TracingEvent> {
         vec![]
     }
+    /// Clock of the simulated hardware, that ticks are cycles of
+    fn frequency_ghz(&self) -> f64 {
+        1.0
+    }
+    /// Writes the events since the last call to `log`, and with `last`
+    /// closes the spans still open, so that they need not be kept until the
+    /// end.
+    fn log_events(&mut self, _log: &mut EventLog, _last: bool) -> Result<()> {
+        Ok(())
+    }
+    /// The state of the simulated hardware, for telling why a simulation
+    /// diverged.
+    fn diagnostics(&self) -> String {
+        String::new()
+    }
+    /// Writes the state of the simulation for a checkpoint, apart from the
+    /// heap.
+    fn save(&self, _writer: &mut dyn Write) -> Result<()> {
+        bail!("Only NMPGC simulations can be checkpointed")
+    }
+    /// Replaces the state of a new simulation with the one written by
+    /// `save`.
+    fn load(&mut self, _reader: &mut dyn Read) -> Result<()> {
+        bail!("Only NMPGC simulations can be checkpointed")
+    }
 }
 
 struct Simulation<A: SimulationArchitecture> {
     architecture: A,
+    heapdump: String,
+    /// Objects in the heap, which bounds how many get marked
+    objects: u64,
+    key: CheckpointKey,
+    checkpoint: Option<String>,
+    checkpoint_interval: usize,
+    watchdog: Watchdog,
 }
 
 impl<A: SimulationArchitecture> Simulation<A> {
-    fn new<O: ObjectModel>(args: &SimulationArgs, object_model: &O) -> Self {
+    fn new<O: ObjectModel>(heapdump: &str, args: &SimulationArgs, object_model: &O) -> Self {
         Simulation {
             architecture: A::new(args, object_model),
+            heapdump: heapdump.to_string(),
+            objects: object_model.num_objects() as u64,
+            key: CheckpointKey::new(heapdump, args, object_model),
+            checkpoint: args.checkpoint.clone(),
+            checkpoint_interval: args.checkpoint_interval,
+            watchdog: Watchdog::new(args.max_ticks, args.stall_ticks),
+        }
+    }
+
+    fn resume<O: ObjectModel>(&mut self, path: &str, object_model: &O) -> Result<()> {
+        checkpoint::load(path, &self.key, object_model, &mut self.architecture)
+    }
+
+    fn save_checkpoint<O: ObjectModel>(&self, object_model: &O) -> Result<()> {
+        match &self.checkpoint {
+            Some(path) => checkpoint::save(path, &self.key, object_model, &self.architecture),
+            None => Ok(()),
         }
     }
 
-    fn run<O: ObjectModel>(&mut self) {
-        loop {
+    /// Runs the simulation to the end, or until ctrl-C, writing its events
+    /// to `log` as it goes. Returns whether it ran to the end, or a
+    /// [`SimulationDiverged`] error.
+    fn run<O: ObjectModel>(
+        &mut self,
+        object_model: &O,
+        mut log: Option<&mut EventLog>,
+    ) -> Result<bool> {
+        let progress = Progress::new("Simulating", "objects", Some(self.objects));
+        if let Some(log) = log.as_deref_mut() {
+            log.process(&self.heapdump, self.architecture.frequency_ghz())?;
+        }
+        let finished = loop {
+            let before = self.architecture.ticks();
             let stop = self.architecture.tick::<O>();
             if stop {
-                break;
+                break true;
+            }
+            let ticks = self.architecture.ticks();
+            if let Some(divergence) = self.watchdog.check_ticks(ticks) {
+                return Err(self.diverged(divergence));
+            }
+            // Sharded simulations advance a quantum of ticks at a time
+            let passed = |every: usize| ticks / every != before / every;
+            if self.checkpoint_interval > 0 && passed(self.checkpoint_interval) {
+                self.save_checkpoint(object_model)?;
+            }
+            // Summing the processors' counts every tick would slow the
+            // simulation down
+            if passed(POLL_TICKS) {
+                if let Some(log) = log.as_deref_mut() {
+                    self.architecture.log_events(log, false)?;
+                }
+                let marked_objects = self.architecture.marked_objects();
+                if let Some(divergence) = self.watchdog.check_progress(ticks, marked_objects) {
+                    return Err(self.diverged(divergence));
+                }
+                progress.set(marked_objects as u64, |elapsed| {
+                    format!(
+                        "{} ticks, {:.0} ticks/s",
+                        ticks,
+                        ticks as f64 / elapsed.as_secs_f64()
+                    )
+                });
+                if stop::requested() {
+                    self.save_checkpoint(object_model)?;
+                    break false;
+                }
             }
+        };
+        if let Some(log) = log {
+            self.architecture.log_events(log, true)?;
+        }
+        if finished {
+            progress.finish();
+        }
+        Ok(finished)
+    }
+
+    fn diverged(&self, divergence: divergence::Divergence) -> anyhow::Error {
+        SimulationDiverged {
+            heapdump: self.heapdump.clone(),
+            divergence,
+            ticks: self.architecture.ticks(),
+            marked_objects: self.architecture.marked_objects(),
+            diagnostics: self.architecture.diagnostics(),
         }
+        .into()
     }
 
     fn stats(&self) -> HashMap<String, f64> {
@@ -49,76 +219,386 @@ impl<A: SimulationArchitecture> Simulation<A> {
 }
 
 pub fn reified_simulation<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
-    let simulation_args = if let Some(Commands::Simulate(sim_args)) = args.command {
+    let simulation_args: &SimulationArgs = if let Some(Commands::Simulate(sim_args)) = &args.command
+    {
         sim_args
     } else {
         panic!("Incorrect dispatch");
     };
-    for path in &args.paths {
-        let p: &Path = path.as_ref();
-        // Fake a DaCapo iteration for easier parsing
-        println!(
-            "===== DaCapo hwgc-soft {:?} starting =====",
-            p.file_name().unwrap()
-        );
-        let start = std::time::Instant::now();
-        // reset object model internal states
-        object_model.reset();
-        let heapdump = HeapDump::from_path(path)?;
-        // mmap
-        heapdump.map_spaces()?;
-        // write objects to the heap
-        object_model.restore_objects(&heapdump);
-        let (stats, events) = match simulation_args.architecture {
-            SimulationArchitectureChoice::IdealTraceUtilization => {
-                let mut simuation: Simulation<IdealTraceUtilization> =
-                    Simulation::new(&simulation_args, &object_model);
-                simuation.run::<O>();
-                (simuation.stats(), simuation.events())
+    for (flag, geometry) in [
+        ("--cache", Some(simulation_args.cache)),
+        ("--tib-cache", simulation_args.tib_cache),
+    ] {
+        if let Some(geometry) = geometry.filter(|g| !g.fits_page(simulation_args.page_size)) {
+            anyhow::bail!(
+                "{} {} indexes its sets with address bits past the page offset of --page-size {:?}; use fewer sets or larger pages",
+                flag,
+                geometry,
+                simulation_args.page_size
+            );
+        }
+    }
+    let checkpointing = simulation_args.checkpoint.is_some() || simulation_args.resume.is_some();
+    if checkpointing && simulation_args.architecture != SimulationArchitectureChoice::NMPGC {
+        bail!("Only NMPGC simulations can be checkpointed");
+    }
+    if simulation_args.sample_window.is_some()
+        && simulation_args.architecture != SimulationArchitectureChoice::NMPGC
+    {
+        bail!("Only NMPGC simulations can be sampled");
+    }
+    if let Some(threads) = simulation_args.root_stacks.root_stacks {
+        if simulation_args.architecture != SimulationArchitectureChoice::NMPGC {
+            bail!("Only NMPGC simulations model root scanning");
+        }
+        let stacks = &simulation_args.root_stacks;
+        RootStacks::check(threads, stacks.frame_roots, stacks.frame_words)?;
+    }
+    if simulation_args.resume.is_some() && args.paths.len() != 1 {
+        bail!("A simulation can only be resumed with the one heapdump it was checkpointed on");
+    }
+    if simulation_args.tenant.is_some()
+        && simulation_args.architecture != SimulationArchitectureChoice::NMPGC
+    {
+        bail!("Only NMPGC simulations can have tenants");
+    }
+    if simulation_args.marker_map.is_some()
+        && (simulation_args.architecture != SimulationArchitectureChoice::NMPGC
+            || simulation_args.tenant.is_some()
+            || simulation_args.resume.is_some())
+    {
+        bail!("Only NMPGC simulations of one tenant from the start record which processor marked each object");
+    }
+    if let Some(share) = simulation_args.mutator_share {
+        if simulation_args.architecture != SimulationArchitectureChoice::NMPGC {
+            bail!("Only NMPGC simulations model mutator traffic");
+        }
+        if !(0.0..1.0).contains(&share) || simulation_args.mutator_slice == 0 {
+            bail!("--mutator-share has to be from 0 up to 1, in slices of at least a cycle");
+        }
+    }
+    if simulation_args.event_log.is_some()
+        && (simulation_args.architecture != SimulationArchitectureChoice::NMPGC
+            || simulation_args.tenant.is_some())
+    {
+        bail!("Only NMPGC simulations of one tenant log their events");
+    }
+    if !simulation_args.link_weights.is_empty()
+        && (simulation_args.topology != TopologyChoice::Ring
+            || simulation_args.link_weights.len() != 4
+            || simulation_args.link_weights.contains(&0))
+    {
+        bail!("--link-weights takes a weight of at least 1 for each of the 4 links of --topology Ring");
+    }
+    if simulation_args.aslr_runs > 1 && args.aslr_seed.is_none() {
+        bail!("--aslr-runs needs an --aslr-seed to draw the layouts from");
+    }
+    if simulation_args.architecture == SimulationArchitectureChoice::NMPGC {
+        set_address_mapping(
+            simulation_args.address_mapping,
+            simulation_args.processors,
+            simulation_args.owner_hash,
+        )?;
+    }
+    if let Some(file) = &simulation_args.topology_file {
+        if simulation_args.architecture != SimulationArchitectureChoice::NMPGC {
+            bail!("Only NMPGC simulations have a topology");
+        }
+        // Known once the address mapping is installed
+        let dimms = simulation_args.processors / memory::ranks_per_dimm();
+        if file.dimms() != dimms {
+            bail!(
+                "The topology file has {} DIMMs, but {} processors are on {}",
+                file.dimms(),
+                simulation_args.processors,
+                dimms
+            );
+        }
+    }
+    if simulation_args.architecture == SimulationArchitectureChoice::NMPGC {
+        nmpgc::check_network(simulation_args)?;
+    }
+    let mut results = RunResults::new(&args.paths, args.results.clone())?;
+    let mut marker_frames = vec![];
+    let mut event_log = match &simulation_args.event_log {
+        Some(path) => Some(EventLog::create(path)?),
+        None => None,
+    };
+    'paths: for path in &args.paths {
+        if simulation_args.tenant.is_some() {
+            let finished = with_nmpgc_processors!(simulation_args.processors, LOG => {
+                multi_tenant::simulate_tenants::<LOG, O>(
+                    &mut object_model,
+                    &args,
+                    simulation_args,
+                    path,
+                    &mut results,
+                )?
+            });
+            if !finished {
+                break 'paths;
             }
-            SimulationArchitectureChoice::NMPGC => match simulation_args.processors {
-                8 => {
-                    let mut simulation: Simulation<NMPGC<3>> =
-                        Simulation::new(&simulation_args, &object_model);
-                    simulation.run::<O>();
-                    (simulation.stats(), simulation.events())
-                }
-                _ => {
-                    panic!(
-                        "Unsupported number of processors for NMPGC: {}",
-                        simulation_args.processors
-                    );
+            continue;
+        }
+        let mut layout_ticks = vec![];
+        for run in 0..simulation_args.aslr_runs {
+            let seed = args.aslr_seed.map(|seed| seed + run as u64);
+            let (finished, ticks) = simulate_heapdump(
+                &mut object_model,
+                &args,
+                simulation_args,
+                path,
+                seed,
+                &mut results,
+                &mut marker_frames,
+                event_log.as_mut(),
+            )?;
+            if !finished {
+                break 'paths;
+            }
+            layout_ticks.push(ticks);
+        }
+        if layout_ticks.len() > 1 {
+            results.add_table(layout_variance(path, &layout_ticks));
+        }
+    }
+    if let Some(path) = &simulation_args.marker_map {
+        marker_map::write_parquet(path, &marker_frames)?;
+    }
+    if let Some(log) = event_log {
+        log.finish()?;
+    }
+    results.write()
+}
+
+/// Restores and simulates one heapdump, in the layout drawn from `seed` if
+/// given, adding its rows to `marker_frames` with `--marker-map` and its
+/// events to `event_log` with `--event-log`. Returns whether the simulation
+/// ran to the end.
+#[allow(clippy::too_many_arguments)]
+fn simulate_heapdump<O: ObjectModel>(
+    object_model: &mut O,
+    args: &Args,
+    simulation_args: &SimulationArgs,
+    path: &str,
+    seed: Option<u64>,
+    results: &mut RunResults,
+    marker_frames: &mut Vec<DataFrame>,
+    event_log: Option<&mut EventLog>,
+) -> Result<(bool, f64)> {
+    let p: &Path = path.as_ref();
+    // Fake a DaCapo iteration for easier parsing
+    println!(
+        "===== DaCapo hwgc-soft {:?} starting =====",
+        p.file_name().unwrap()
+    );
+    let start = std::time::Instant::now();
+    // reset object model internal states
+    object_model.reset();
+    let mut heapdump = HeapDump::from_path(path)?;
+    heapdump.select_roots(&args.roots)?;
+    if let Some(seed) = seed {
+        heapdump.randomize_layout(seed)?;
+    }
+    // A checkpoint is only good for the layout it was saved with
+    let name = match seed {
+        Some(seed) => format!("{} (ASLR seed {})", path, seed),
+        None => path.to_string(),
+    };
+    // mmap
+    heapdump.map_spaces()?;
+    // write objects to the heap
+    object_model.restore_objects(&heapdump);
+    // Computed before the simulation as the simulation leaves the heap marked
+    let critical_path = simulation_args
+        .critical_path
+        .then(|| crate::analysis::depth::critical_path(&*object_model));
+    let markers = simulation_args.marker_map.is_some().then(|| {
+        let map = Arc::new(MarkerMap::new(&*object_model));
+        marker_map::install(Some(map.clone()));
+        map
+    });
+    let (mut stats, events, finished) = match simulation_args.architecture {
+        SimulationArchitectureChoice::IdealTraceUtilization => {
+            let mut simuation: Simulation<IdealTraceUtilization> =
+                Simulation::new(&name, simulation_args, &*object_model);
+            let finished = simuation.run(&*object_model, event_log)?;
+            (simuation.stats(), simuation.events(), finished)
+        }
+        SimulationArchitectureChoice::NMPGC => {
+            with_nmpgc_processors!(simulation_args.processors, LOG => {
+                let mut simulation: Simulation<NMPGC<LOG>> =
+                    Simulation::new(&name, simulation_args, &*object_model);
+                if let Some(checkpoint) = &simulation_args.resume {
+                    simulation.resume(checkpoint, &*object_model)?;
                 }
-            },
-        };
-        let duration = start.elapsed();
+                let finished = simulation.run(&*object_model, event_log)?;
+                (simulation.stats(), simulation.events(), finished)
+            })
+        }
+    };
+    if let Some(map) = markers {
+        marker_map::install(None);
+        // The owner only depends on the address mapping installed, not on
+        // the number of processors
+        let owner = |o: ObjectReference| NMPGC::<3>::get_owner_processor(o.to_address());
+        let (df, ownership) = map.to_dataframe(&heapdump, &name, Some(&owner))?;
+        marker_map::report(&name, &ownership, true);
+        stats.insert("marker_map.owned_fraction".into(), ownership.fraction());
+        marker_frames.push(df);
+    }
+    if finished && simulation_args.architecture == SimulationArchitectureChoice::NMPGC {
+        let [owned, linear] = owned_objects(&*object_model, simulation_args.processors);
         println!(
-            "===== DaCapo hwgc-soft {:?} PASSED in {} msec =====",
-            p.file_name().unwrap(),
-            duration.as_millis()
+            "Owned objects: imbalance {:.3} ({:.3} with the linear mapping), per processor {:?}",
+            imbalance(&owned),
+            imbalance(&linear),
+            owned
         );
-        println!("============================ Tabulate Statistics ============================");
-        let mut stats_pairs: Vec<(String, f64)> = stats.into_iter().collect();
-        stats_pairs.sort_by(|a, b| a.0.cmp(&b.0));
-        for (i, (key, _)) in stats_pairs.iter().enumerate() {
-            if i > 0 {
-                print!("\t");
-            }
-            print!("{}", key);
+        for (i, count) in owned.iter().enumerate() {
+            stats.insert(format!("owned_objects.{}", i), *count as f64);
         }
-        println!();
-        for (i, (_, value)) in stats_pairs.iter().enumerate() {
-            if i > 0 {
-                print!("\t");
-            }
-            print!("{:.3}", value);
+        stats.insert("owned_objects.imbalance".into(), imbalance(&owned));
+        stats.insert("owned_objects.linear_imbalance".into(), imbalance(&linear));
+        let closures = local_closures(&*object_model, simulation_args.processors);
+        let local: u64 = closures.local.iter().sum();
+        println!(
+            "Local closures: {} of {} reachable objects ({:.1}%) without crossing owners, {} only through edges between owners, per processor {:?}",
+            local,
+            closures.reachable,
+            local as f64 / closures.reachable.max(1) as f64 * 100.0,
+            closures.remote_only(),
+            closures.local
+        );
+        for (i, count) in closures.local.iter().enumerate() {
+            stats.insert(format!("local_closure.{}", i), *count as f64);
         }
-        println!();
-        println!("-------------------------- End Tabulate Statistics --------------------------");
-        if let Some(ref p) = simulation_args.trace_path {
-            serialize_to_gzip_json(&events, p)?;
+        stats.insert("local_closure.sum".into(), local as f64);
+        stats.insert(
+            "local_closure.remote_only".into(),
+            closures.remote_only() as f64,
+        );
+    }
+    if finished && !simulation_args.no_cross_check {
+        cross_check::cross_check(&*object_model, &heapdump)?;
+    }
+    if let Some(cp) = critical_path {
+        println!(
+            "Critical path: {} of {} total scan work, speedup bound {:.1} (simulated {} processors)",
+            cp.length,
+            cp.total_work,
+            cp.speedup_bound(),
+            simulation_args.processors
+        );
+        stats.insert("critical_path.length".into(), cp.length as f64);
+        stats.insert("critical_path.total_work".into(), cp.total_work as f64);
+        stats.insert("critical_path.speedup_bound".into(), cp.speedup_bound());
+    }
+    let duration = start.elapsed();
+    // Harnesses only take the statistics of passed iterations
+    println!(
+        "===== DaCapo hwgc-soft {:?} {} in {} msec =====",
+        p.file_name().unwrap(),
+        if finished { "PASSED" } else { "TRUNCATED" },
+        duration.as_millis()
+    );
+    let ticks = stats["ticks"];
+    let mut stats_pairs: Vec<(String, f64)> = stats.into_iter().collect();
+    stats_pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut table = StatsTable::new("simulate", Some(path));
+    for (key, value) in stats_pairs {
+        table.push(key, value);
+    }
+    if let Some(seed) = seed {
+        table.push("aslr_seed", seed);
+    }
+    if !finished {
+        results.truncate(&mut table);
+    }
+    results.add_table(table);
+    if let Some(ref p) = simulation_args.trace_path {
+        serialize_to_gzip_json(&events, p)?;
+    }
+    heapdump.unmap_spaces()?;
+    Ok((finished, ticks))
+}
+
+/// An NMPGC closure, for commands that build on the time it takes rather
+/// than report on the simulation.
+pub(crate) struct SimulatedClosure {
+    pub ticks: usize,
+    /// Tick the last root stack was scanned at, 0 without `--root-stacks`
+    pub root_scan_ticks: usize,
+    pub frequency_ghz: f64,
+    /// Mark byte left on the objects marked
+    pub mark_sense: u8,
+}
+
+/// Simulates the closure of the heap restored in `object_model` on NMPGC
+/// with `args`, failing unless it runs to the end.
+pub(crate) fn simulate_closure<O: ObjectModel>(
+    name: &str,
+    args: &SimulationArgs,
+    object_model: &O,
+) -> Result<SimulatedClosure> {
+    if args.architecture != SimulationArchitectureChoice::NMPGC {
+        bail!("Only closures on NMPGC can be simulated");
+    }
+    set_address_mapping(args.address_mapping, args.processors, args.owner_hash)?;
+    with_nmpgc_processors!(args.processors, LOG => {
+        let mut simulation: Simulation<NMPGC<LOG>> = Simulation::new(name, args, object_model);
+        if !simulation.run(object_model, None)? {
+            bail!("The simulation of {} was stopped before the end", name);
+        }
+        let stats = simulation.stats();
+        Ok(SimulatedClosure {
+            ticks: simulation.architecture.ticks(),
+            root_scan_ticks: stats.get("root_scan_ticks").copied().unwrap_or(0.0) as usize,
+            frequency_ghz: simulation.architecture.frequency_ghz(),
+            mark_sense: cross_check::SIMULATED_MARK,
+        })
+    })
+}
+
+/// Objects marked by the simulation that each processor owns, under the
+/// address mapping in use and under the same mapping without `--owner-hash`.
+fn owned_objects<O: ObjectModel>(object_model: &O, processors: usize) -> [Vec<u64>; 2] {
+    let layouts = [AddressLayout::current(), AddressLayout::current().linear()];
+    let mut counts = [vec![0; processors], vec![0; processors]];
+    for o in object_model.objects_iter() {
+        if Header::load(o).get_mark_byte() != cross_check::SIMULATED_MARK {
+            continue;
+        }
+        for (layout, counts) in layouts.iter().zip(&mut counts) {
+            counts[AddressMapping(o.to_raw()).owner_id_in(layout)] += 1;
         }
-        heapdump.unmap_spaces()?;
     }
-    Ok(())
+    counts
+}
+
+/// Prints how the ticks of the layouts of a heapdump vary, and returns them
+/// as a table.
+fn layout_variance(path: &str, ticks: &[f64]) -> StatsTable {
+    let n = ticks.len() as f64;
+    let mean = ticks.iter().sum::<f64>() / n;
+    let stddev = (ticks.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    let min = ticks.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = ticks.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    println!(
+        "Ticks over {} layouts: mean {:.0}, stddev {:.0} ({:.2}%), min {:.0}, max {:.0}",
+        ticks.len(),
+        mean,
+        stddev,
+        stddev / mean * 100.0,
+        min,
+        max
+    );
+    let mut table = StatsTable::new("simulate.aslr", Some(path));
+    table.push("layouts", ticks.len() as u64);
+    table.push("ticks.mean", mean);
+    table.push("ticks.stddev", stddev);
+    table.push("ticks.cv", stddev / mean);
+    table.push("ticks.min", min);
+    table.push("ticks.max", max);
+    table
 }
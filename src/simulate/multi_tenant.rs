@@ -0,0 +1,147 @@
+//! How much two heapdumps slow each other down when traced at once on the
+//! same NMPGC hardware (`--tenant`), for studying whether collections of
+//! several heaps can be consolidated onto memory-side accelerators. Each
+//! tenant is simulated alone, then both together, with the second tenant at
+//! the same addresses throughout. The slowdown of a tenant is when its
+//! tracing work ends together over when it ends alone, and the fairness is
+//! Jain's index of the slowdowns, 1 when both are slowed down alike.
+
+use super::nmpgc::tenancy::{tenant_of, LOG_TENANT_OFFSET, TENANTS};
+use super::*;
+
+/// Restores `heapdump` and simulates it to the end, or returns `None` if
+/// stopped with ctrl-C.
+fn simulate_alone<const LOG_NUM_THREADS: u8, O: ObjectModel>(
+    object_model: &mut O,
+    simulation_args: &SimulationArgs,
+    name: &str,
+    heapdump: &HeapDump,
+) -> Result<Option<Simulation<NMPGC<LOG_NUM_THREADS>>>> {
+    object_model.reset();
+    heapdump.map_spaces()?;
+    object_model.restore_objects(heapdump);
+    let mut simulation: Simulation<NMPGC<LOG_NUM_THREADS>> =
+        Simulation::new(name, simulation_args, &*object_model);
+    let finished = simulation.run(&*object_model, None)?;
+    if finished && !simulation_args.no_cross_check {
+        super::cross_check::cross_check(&*object_model, heapdump)?;
+    }
+    heapdump.unmap_spaces()?;
+    Ok(finished.then_some(simulation))
+}
+
+/// Simulates the heapdump at `path` alone, the `--tenant` one alone, then
+/// both together. Returns whether every simulation ran to the end.
+pub(super) fn simulate_tenants<const LOG_NUM_THREADS: u8, O: ObjectModel>(
+    object_model: &mut O,
+    args: &Args,
+    simulation_args: &SimulationArgs,
+    path: &str,
+    results: &mut RunResults,
+) -> Result<bool> {
+    let tenant_path = simulation_args.tenant.as_deref().unwrap();
+    let paths = [path, tenant_path];
+    let mut heapdumps = vec![];
+    for p in paths {
+        let mut heapdump = HeapDump::from_path(p)?;
+        heapdump.select_roots(&args.roots)?;
+        if heapdump
+            .spaces
+            .iter()
+            .any(|s| tenant_of(s.start) != 0 || tenant_of(s.end - 1) != 0)
+        {
+            bail!("{} has spaces where those of the second tenant go", p);
+        }
+        heapdumps.push(heapdump);
+    }
+    heapdumps[1].offset_layout(1 << LOG_TENANT_OFFSET)?;
+    object_model.restore_tibs(&heapdumps[1]);
+
+    let p: &Path = path.as_ref();
+    println!(
+        "===== DaCapo hwgc-soft {:?} starting =====",
+        p.file_name().unwrap()
+    );
+    let start = std::time::Instant::now();
+    let mut alone = [0; TENANTS];
+    let mut back_to_back = 0;
+    for (tenant, heapdump) in heapdumps.iter().enumerate() {
+        println!("Tenant {} alone: {}", tenant, paths[tenant]);
+        let Some(simulation) = simulate_alone::<LOG_NUM_THREADS, O>(
+            object_model,
+            simulation_args,
+            paths[tenant],
+            heapdump,
+        )?
+        else {
+            return Ok(false);
+        };
+        alone[tenant] = simulation.architecture.tenant_finish_ticks()[tenant];
+        back_to_back += simulation.architecture.ticks();
+    }
+    let [mut shared_heapdump, mut second] = <[HeapDump; TENANTS]>::try_from(heapdumps).unwrap();
+    shared_heapdump.objects.append(&mut second.objects);
+    shared_heapdump.roots.append(&mut second.roots);
+    shared_heapdump.root_sets.append(&mut second.root_sets);
+    shared_heapdump.spaces.append(&mut second.spaces);
+    println!("Both tenants together");
+    let name = format!("{} with tenant {}", path, tenant_path);
+    let Some(simulation) = simulate_alone::<LOG_NUM_THREADS, O>(
+        object_model,
+        simulation_args,
+        &name,
+        &shared_heapdump,
+    )?
+    else {
+        return Ok(false);
+    };
+    let stats = simulation.stats();
+    let shared = simulation.architecture.tenant_finish_ticks();
+    let ticks = simulation.architecture.ticks();
+    let duration = start.elapsed();
+    println!(
+        "===== DaCapo hwgc-soft {:?} PASSED in {} msec =====",
+        p.file_name().unwrap(),
+        duration.as_millis()
+    );
+
+    let slowdowns: Vec<f64> = (0..TENANTS)
+        .map(|t| shared[t] as f64 / alone[t].max(1) as f64)
+        .collect();
+    let fairness = slowdowns.iter().sum::<f64>().powi(2)
+        / (TENANTS as f64 * slowdowns.iter().map(|s| s * s).sum::<f64>());
+    println!(
+        "Tenancy ({:?} arbitration):",
+        simulation_args.tenant_arbitration
+    );
+    let mut tenancy = StatsTable::new("simulate.tenancy", Some(path));
+    for t in 0..TENANTS {
+        println!(
+            "  Tenant {} ({}): done tracing at tick {} alone, {} together, slowdown {:.3}",
+            t, paths[t], alone[t], shared[t], slowdowns[t]
+        );
+        tenancy.push(format!("tenant.{}.alone_ticks", t), alone[t] as u64);
+        tenancy.push(format!("tenant.{}.shared_ticks", t), shared[t] as u64);
+        tenancy.push(format!("tenant.{}.slowdown", t), slowdowns[t]);
+    }
+    println!(
+        "  Fairness: {:.3}, together in {} ticks against {} back to back ({:.2}x)",
+        fairness,
+        ticks,
+        back_to_back,
+        back_to_back as f64 / ticks as f64
+    );
+    tenancy.push("fairness", fairness);
+    tenancy.push("ticks", ticks as u64);
+    tenancy.push("back_to_back_ticks", back_to_back as u64);
+
+    let mut stats_pairs: Vec<(String, f64)> = stats.into_iter().collect();
+    stats_pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut table = StatsTable::new("simulate", Some(path));
+    for (key, value) in stats_pairs {
+        table.push(key, value);
+    }
+    results.add_table(table);
+    results.add_table(tenancy);
+    Ok(true)
+}
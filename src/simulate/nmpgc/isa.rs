@@ -0,0 +1,158 @@
+use super::work::NMPProcessorWorkType;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::{Add, AddAssign};
+
+/// Estimated number of instructions, by class, that a RISC-V-like core
+/// retires for one work item.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct InstructionMix {
+    pub(crate) loads: usize,
+    pub(crate) stores: usize,
+    pub(crate) branches: usize,
+    pub(crate) alu: usize,
+}
+
+impl InstructionMix {
+    const fn new(loads: usize, stores: usize, branches: usize, alu: usize) -> Self {
+        InstructionMix {
+            loads,
+            stores,
+            branches,
+            alu,
+        }
+    }
+
+    pub(super) fn total(&self) -> usize {
+        self.loads + self.stores + self.branches + self.alu
+    }
+
+    fn times(&self, n: usize) -> Self {
+        InstructionMix::new(
+            self.loads * n,
+            self.stores * n,
+            self.branches * n,
+            self.alu * n,
+        )
+    }
+}
+
+impl Add for InstructionMix {
+    type Output = InstructionMix;
+
+    fn add(self, rhs: Self) -> Self {
+        InstructionMix::new(
+            self.loads + rhs.loads,
+            self.stores + rhs.stores,
+            self.branches + rhs.branches,
+            self.alu + rhs.alu,
+        )
+    }
+}
+
+impl AddAssign for InstructionMix {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// Per-work-type instruction mixes used to expand work counts into
+/// instruction counts, so that the simulator's "instructions" are comparable
+/// with what the FireSim RTL runs retire.
+///
+/// The defaults follow a straightforward RV64 implementation of each work
+/// item; an `[isa.<work>]` table in the `--timing-model` file replaces the
+/// mix of that work type and has to give all four instruction classes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct IsaModel {
+    /// ld header, test mark bit, sd header, ld TIB and set up the scan
+    pub(crate) mark: InstructionMix,
+    /// ld header, test mark bit, and bail out
+    pub(crate) mark_already_marked: InstructionMix,
+    /// ld slot, null check, compute the owner of the referent and compare
+    pub(crate) load: InstructionMix,
+    /// Bump the slot cursor, check chunk bounds, compute the slot owner
+    pub(crate) continue_scan: InstructionMix,
+    /// Encode the message and store it to the link controller
+    pub(crate) send_message: InstructionMix,
+    /// Load a message from the link controller and dispatch on its kind
+    pub(crate) read_inbox: InstructionMix,
+    /// One iteration of the inbox polling loop
+    pub(crate) idle: InstructionMix,
+}
+
+impl Default for IsaModel {
+    fn default() -> Self {
+        IsaModel {
+            mark: InstructionMix::new(2, 1, 2, 4),
+            mark_already_marked: InstructionMix::new(1, 0, 1, 1),
+            load: InstructionMix::new(1, 0, 2, 3),
+            continue_scan: InstructionMix::new(0, 0, 2, 4),
+            send_message: InstructionMix::new(0, 1, 0, 1),
+            read_inbox: InstructionMix::new(1, 0, 1, 1),
+            idle: InstructionMix::new(1, 0, 1, 0),
+        }
+    }
+}
+
+/// Instructions retired by one processor.
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct IsaCounts {
+    /// Instructions of productive work
+    pub(super) work: InstructionMix,
+    /// Instructions spent polling an empty inbox
+    pub(super) idle: InstructionMix,
+}
+
+impl IsaModel {
+    /// Expands the work counts of a processor into instruction counts.
+    pub(super) fn account(
+        &self,
+        work_count: &HashMap<NMPProcessorWorkType, usize>,
+        marked_objects: usize,
+    ) -> IsaCounts {
+        let count = |t: NMPProcessorWorkType| work_count.get(&t).copied().unwrap_or(0);
+        let marks = count(NMPProcessorWorkType::Mark);
+        let work = self.mark.times(marked_objects)
+            + self.mark_already_marked.times(marks - marked_objects)
+            + self.load.times(count(NMPProcessorWorkType::Load))
+            + self
+                .continue_scan
+                .times(count(NMPProcessorWorkType::ContinueScan))
+            + self
+                .send_message
+                .times(count(NMPProcessorWorkType::SendMessage))
+            + self
+                .read_inbox
+                .times(count(NMPProcessorWorkType::ReadInbox));
+        IsaCounts {
+            work,
+            idle: self.idle.times(count(NMPProcessorWorkType::Idle)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account() {
+        let model = IsaModel::default();
+        let mut work_count = HashMap::new();
+        work_count.insert(NMPProcessorWorkType::Mark, 3);
+        work_count.insert(NMPProcessorWorkType::Load, 2);
+        work_count.insert(NMPProcessorWorkType::Idle, 5);
+        work_count.insert(NMPProcessorWorkType::Stall, 100);
+        let counts = model.account(&work_count, 2);
+        let expected = model.mark.times(2) + model.mark_already_marked + model.load.times(2);
+        assert_eq!(counts.work, expected);
+        assert_eq!(counts.idle, model.idle.times(5));
+        assert_eq!(
+            counts.work.total(),
+            2 * model.mark.total() + model.mark_already_marked.total() + 2 * model.load.total()
+        );
+    }
+}
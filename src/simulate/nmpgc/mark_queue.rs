@@ -0,0 +1,234 @@
+//! Finite work queues (`--mark-queue`). The marks and loads an NMPGC
+//! processor queues for itself, and the messages carrying them to other
+//! processors, take an entry each, and work that finds every entry taken
+//! overflows under `--overflow-policy`:
+//!
+//! - `Spill` writes it to a stack in memory of the processor's own, and
+//!   reads it back once the queue has drained.
+//! - `Drop` forgets it, and a rescan finds it again once the processor runs
+//!   out of work. The rescan is optimistic, reading exactly the objects and
+//!   slots dropped, where a collector would look through the marked objects
+//!   for children that are not marked.
+//! - `Backpressure` stalls the scan of an object while the queue is full,
+//!   working off its loads and messages in the meantime. Work that does not
+//!   come from a scan, such as the marks of loaded slots, cannot wait and
+//!   goes in past the capacity.
+
+use super::*;
+use crate::cli::OverflowPolicyChoice;
+use crate::simulate::memory::{DataCache, VirtualAddress};
+
+/// The spill stacks, out of the way of the spaces of the heapdumps, the
+/// side mark tables and the private mark bitmaps
+const SPILL_BASE: u64 = 0x780_0000_0000;
+/// log2 of the bytes of the spill stack of each processor
+const LOG_SPILL_BYTES: u32 = 36;
+/// Bytes of a spilled entry
+const ENTRY_BYTES: u64 = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct MarkQueue {
+    pub(super) capacity: usize,
+    pub(super) policy: OverflowPolicyChoice,
+    /// Work spilled or dropped and not brought back yet, newest last
+    overflowed: Vec<NMPProcessorWork>,
+    /// Work that found the queue full, and the work brought back by refills
+    /// or rescans
+    pub(super) overflows: usize,
+    pub(super) refilled: usize,
+    /// Ticks spent writing spilled work, and reading it or rescanning for
+    /// it when it is brought back
+    pub(super) overflow_ticks: usize,
+    /// Times the scan of an object waited for the queue to drain
+    pub(super) scan_stalls: usize,
+    /// The most work queued at once
+    pub(super) peak: usize,
+}
+
+impl MarkQueue {
+    pub(super) fn new(capacity: usize, policy: OverflowPolicyChoice) -> Self {
+        MarkQueue {
+            capacity: capacity.max(1),
+            policy,
+            overflowed: vec![],
+            overflows: 0,
+            refilled: 0,
+            overflow_ticks: 0,
+            scan_stalls: 0,
+            peak: 0,
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.overflowed.is_empty()
+    }
+}
+
+/// Where the spill stack of processor `id` keeps its `index`th entry
+fn spill_address(id: usize, index: usize) -> VirtualAddress {
+    VirtualAddress(SPILL_BASE + ((id as u64) << LOG_SPILL_BYTES) + index as u64 * ENTRY_BYTES)
+}
+
+/// The object or slot a rescan reads to find `work` again
+fn rescan_address(work: &NMPProcessorWork) -> VirtualAddress {
+    VirtualAddress::from(work.target())
+}
+
+impl NMPProcessorWork {
+    /// Work that takes an entry of the queue
+    fn is_tracing(&self) -> bool {
+        match self {
+            NMPProcessorWork::Mark(_) | NMPProcessorWork::Load(_) => true,
+            NMPProcessorWork::SendMessage(msg) => msg.is_data(),
+            _ => false,
+        }
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
+    fn queued_tracing_work(&self) -> usize {
+        self.works.iter().filter(|w| w.is_tracing()).count()
+    }
+
+    /// Queues `work` behind the rest if the queue has room for it, or lets
+    /// it overflow.
+    pub(super) fn push_bounded(&mut self, work: NMPProcessorWork) {
+        let queued = self.queued_tracing_work();
+        let id = self.id;
+        let queue = self.mark_queue.as_mut().unwrap();
+        if queued < queue.capacity {
+            queue.peak = queue.peak.max(queued + 1);
+            self.works.push_back(work);
+            return;
+        }
+        queue.overflows += 1;
+        match queue.policy {
+            OverflowPolicyChoice::Spill => {
+                let addr = spill_address(id, queue.overflowed.len());
+                queue.overflowed.push(work);
+                let latency = self.cache.write(addr);
+                self.mark_queue.as_mut().unwrap().overflow_ticks += latency;
+                self.push_stall(latency);
+            }
+            OverflowPolicyChoice::Drop => queue.overflowed.push(work),
+            OverflowPolicyChoice::Backpressure => {
+                queue.peak = queue.peak.max(queued + 1);
+                self.works.push_back(work);
+            }
+        }
+    }
+
+    /// Brings back as much of the overflowed work as the queue holds, once
+    /// it has drained, reading it from the spill stack or rescanning for it.
+    pub(super) fn refill_mark_queue(&mut self) {
+        let Some(queue) = self.mark_queue.as_mut() else {
+            return;
+        };
+        if !self.works.is_empty() || queue.overflowed.is_empty() {
+            return;
+        }
+        let mut latency = 0;
+        for _ in 0..queue.capacity {
+            let Some(work) = queue.overflowed.pop() else {
+                break;
+            };
+            let addr = match queue.policy {
+                OverflowPolicyChoice::Spill => spill_address(self.id, queue.overflowed.len()),
+                _ => rescan_address(&work),
+            };
+            latency += self.cache.read(addr);
+            queue.refilled += 1;
+            self.works.push_back(work);
+        }
+        queue.overflow_ticks += latency;
+        self.push_stall(latency);
+    }
+
+    /// With backpressure, swaps the scan about to continue for the oldest
+    /// queued load or message if the queue is full, leaving the scan to
+    /// continue after it. Marks are left alone, as they would start a scan
+    /// of their own.
+    pub(super) fn apply_backpressure(&mut self, work: NMPProcessorWork) -> NMPProcessorWork {
+        let full = match &self.mark_queue {
+            Some(queue) if queue.policy == OverflowPolicyChoice::Backpressure => {
+                self.queued_tracing_work() >= queue.capacity
+            }
+            _ => false,
+        };
+        if !full || !matches!(work, NMPProcessorWork::ContinueScan) {
+            return work;
+        }
+        let Some(i) = self.works.iter().position(|w| {
+            matches!(
+                w,
+                NMPProcessorWork::Load(_) | NMPProcessorWork::SendMessage(_)
+            ) && w.is_tracing()
+        }) else {
+            return work;
+        };
+        let next = self.works.remove(i).unwrap();
+        self.works.push_front(work);
+        self.mark_queue.as_mut().unwrap().scan_stalls += 1;
+        next
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    /// Prints the mark queue section of the summary and records its stats.
+    pub(super) fn report_mark_queue_stats(&self, stats: &mut HashMap<String, f64>) {
+        let queues: Vec<&MarkQueue> = self
+            .processors
+            .iter()
+            .filter_map(|p| p.mark_queue.as_ref())
+            .collect();
+        let sum = |f: fn(&MarkQueue) -> usize| queues.iter().map(|q| f(q)).sum::<usize>();
+        let overflows = sum(|q| q.overflows);
+        let refilled = sum(|q| q.refilled);
+        let overflow_ticks = sum(|q| q.overflow_ticks);
+        let scan_stalls = sum(|q| q.scan_stalls);
+        let peak = queues.iter().map(|q| q.peak).max().unwrap_or(0);
+        println!(
+            "Mark queues (aggregate, {} entries, {:?} on overflow):",
+            queues[0].capacity, queues[0].policy
+        );
+        println!(
+            "  Overflows:    {:>10}    Brought back: {:>10}    Peak occupancy: {:>10}",
+            Self::format_thousands(overflows),
+            Self::format_thousands(refilled),
+            Self::format_thousands(peak)
+        );
+        println!(
+            "  Overflow ticks: {:>10}    Scan stalls: {:>10}",
+            Self::format_thousands(overflow_ticks),
+            Self::format_thousands(scan_stalls)
+        );
+        stats.insert("mark_queue.overflows.sum".into(), overflows as f64);
+        stats.insert("mark_queue.refilled.sum".into(), refilled as f64);
+        stats.insert(
+            "mark_queue.overflow_ticks.sum".into(),
+            overflow_ticks as f64,
+        );
+        stats.insert("mark_queue.scan_stalls.sum".into(), scan_stalls as f64);
+        stats.insert("mark_queue.peak".into(), peak as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_addresses() {
+        assert_eq!(spill_address(0, 1).0 - spill_address(0, 0).0, ENTRY_BYTES);
+        // The stacks of two processors do not overlap
+        assert!(spill_address(0, 1 << 20).0 < spill_address(1, 0).0);
+        assert_eq!(
+            rescan_address(&NMPProcessorWork::Mark(
+                ObjectReference::from_raw_unchecked(0x1000)
+            )),
+            VirtualAddress(0x1000)
+        );
+        assert!(NMPProcessorWork::Load(Address::from_raw(0x1008)).is_tracing());
+        assert!(!NMPProcessorWork::ContinueScan.is_tracing());
+    }
+}
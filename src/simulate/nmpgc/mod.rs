@@ -1,17 +1,52 @@
 use super::SimulationArchitecture;
-use crate::simulate::memory::{AddressMapping, DDR4RankOption, PageSize};
+use crate::root_scan::RootStacks;
+use crate::simulate::memory::{
+    num_banks, ranks_per_dimm, AddressMapping, CacheGeometry, CacheStats, CoherenceStats,
+    DDR4RankOption, LastLevelCache, LlcPort, MissStatusHoldingRegisters, MutatorTraffic,
+    PagePolicy, RankStats, RefreshTiming, RowLocality, Tlb, WriteBufferStats, WriteCombiningBuffer,
+};
 use crate::simulate::memory::{DimmId, RankId};
 use crate::util::ticks_to_us;
-use crate::{ObjectModel, SimulationArgs};
-use std::collections::{HashMap, VecDeque};
+use crate::{Address, ObjectModel, ObjectReference, SimulationArgs};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
 
+mod isa;
+mod mark_queue;
 mod network;
+mod power;
+mod prefetch;
+mod private_marks;
+mod sampling;
+mod scan_engine;
+mod shard;
+mod side_marks;
+pub(crate) mod tenancy;
+mod termination;
+mod tib_fetch;
+mod timing;
 mod topology;
 mod work;
-use network::Network;
+use mark_queue::MarkQueue;
+use network::{Network, NetworkConfig};
+use power::{PowerOptions, PowerStates};
+use prefetch::Prefetcher;
+use private_marks::PrivateMarks;
+use sampling::Sampling;
+use scan_engine::ScanEngine;
+use side_marks::SideMarks;
+use tenancy::Tenancy;
+use termination::TerminationDetector;
+use tib_fetch::TibFetch;
+pub(crate) use timing::ProcessorTimingModel;
 use topology::Topology;
+pub(crate) use topology::TopologyFile;
 use work::{NMPMessage, NMPProcessorWork, NMPProcessorWorkType};
 
+use super::event_log::EventLog;
 use super::memory::SetAssociativeCache;
 use super::tracing::TracingEvent;
 
@@ -22,6 +57,41 @@ pub(crate) struct NMPGC<const LOG_NUM_THREADS: u8> {
     frequency_ghz: f64,
     topology: Box<dyn Topology>,
     network: Network,
+    isa_accounting: bool,
+    distributed_termination: bool,
+    cache_geometry: CacheGeometry,
+    /// Shared last-level cache of each DIMM, if enabled
+    llcs: BTreeMap<DimmId, Arc<Mutex<LastLevelCache>>>,
+    llc_geometry: Option<CacheGeometry>,
+    /// Ticks between two exchanges of messages between DIMMs when their
+    /// processors tick in threads of their own, 0 to tick them all here
+    shard_quantum: usize,
+    /// Messages of sharded simulations refused by the network, by sender,
+    /// behind the `blocked_message` the sender stalls on
+    refused: Vec<VecDeque<NMPMessage>>,
+    /// Set with `--sample-window`
+    sampling: Option<Sampling>,
+    /// Whether the roots are scanned from `--root-stacks`
+    root_stacks: bool,
+    /// With `--sleep-after`
+    power: Option<PowerOptions>,
+    /// With `--refresh`
+    refresh: Option<RefreshTiming>,
+    /// With `--mutator-share`
+    mutator: Option<MutatorTraffic>,
+    /// `None` with DRAMsim3
+    page_policy: Option<PagePolicy>,
+}
+
+/// What a checkpoint holds of an NMPGC simulation. The rest, such as the
+/// topology, is rebuilt from the arguments on resume.
+#[derive(Serialize, Deserialize)]
+struct NMPGCState<P, N, L, R> {
+    ticks: usize,
+    processors: P,
+    network: N,
+    llcs: L,
+    refused: R,
 }
 
 impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
@@ -43,14 +113,639 @@ impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
         s
     }
 
-    fn get_owner_processor(o: u64) -> usize {
-        let mapping = AddressMapping(o);
+    /// Processors (ranks) located on `dimm`.
+    fn processors_on(&self, dimm: DimmId) -> impl Iterator<Item = usize> {
+        (0..self.processors.len()).filter(move |&id| DimmId::from(RankId(id as u8)) == dimm)
+    }
+
+    pub(super) fn get_owner_processor(addr: Address) -> usize {
+        let mapping = AddressMapping(addr.as_u64());
         mapping.get_owner_id()
     }
+
+    /// Ticks every processor, then the network. Returns whether the closure
+    /// is complete.
+    fn tick_sequential<O: ObjectModel>(&mut self) -> bool {
+        self.ticks += 1;
+        let mut messages = Vec::new();
+
+        for p in &mut self.processors {
+            let msg = p.tick::<O>();
+            if let Some(m) = msg {
+                messages.push((p.id, m));
+            }
+        }
+
+        // Inject outgoing messages into the network fabric, retrying the ones
+        // that were refused for lack of link credits first.
+        let blocked = self
+            .processors
+            .iter_mut()
+            .filter_map(|p| p.blocked_message.take().map(|m| (p.id, m)))
+            .collect::<Vec<_>>();
+        for (sender_id, msg) in blocked.into_iter().chain(messages) {
+            let sender_rank = RankId(sender_id as u8);
+            let recipient_rank = RankId(msg.recipient as u8);
+            let sender_dimm = DimmId::from(sender_rank);
+            let recipient_dimm = DimmId::from(recipient_rank);
+
+            if msg.is_broadcast() {
+                for id in self
+                    .processors_on(sender_dimm)
+                    .filter(|&id| id != sender_id)
+                {
+                    self.processors[id].inbox.push(msg.copy_for(id));
+                }
+                let tree = self.topology.broadcast_tree(sender_dimm);
+                self.network.broadcast(msg, sender_dimm, tree);
+            } else if sender_dimm == recipient_dimm {
+                // Same DIMM: deliver directly (no network traversal needed).
+                self.processors[msg.recipient].inbox.push(msg);
+            } else {
+                let route = self.topology.get_route(sender_dimm, recipient_dimm);
+                if let Err(msg) = self.network.inject(msg, route) {
+                    self.processors[sender_id].blocked_message = Some(msg);
+                }
+            }
+        }
+
+        self.tick_network();
+        self.finished()
+    }
+
+    /// Advances the messages in flight and delivers the ones that arrive.
+    fn tick_network(&mut self) {
+        let delivered = self.network.tick();
+        for msg in delivered {
+            self.processors[msg.recipient].inbox.push(msg);
+        }
+        for (dimm, msg) in self.network.take_broadcast_arrivals() {
+            for id in self.processors_on(dimm) {
+                self.processors[id].inbox.push(msg.copy_for(id));
+            }
+        }
+    }
+
+    /// Whether the closure is complete.
+    fn finished(&self) -> bool {
+        // Check if all processors are done AND no messages in flight.
+        // FIXME: without --distributed-termination, this assumes magical
+        // global knowledge rather than running the detection protocol
+        let all_done = self.processors.iter().all(|p| p.locally_done()) && self.network.is_empty();
+        if self.distributed_termination {
+            // Everyone has to have heard about termination, but the
+            // simulator may still see the quiescent state long before.
+            all_done && self.processors.iter().all(|p| p.terminated)
+        } else {
+            all_done
+        }
+    }
+
+    /// Prints the instruction-level accounting section of the summary and
+    /// records its stats.
+    fn report_isa_stats(&self, stats: &mut HashMap<String, f64>) {
+        println!("ISA Accounting (estimated instructions):");
+        println!(
+            "  {:<4} {:>10} {:>10} {:>10} {:>10} {:>12} {:>12} {:>8} {:>8}",
+            "P", "Loads", "Stores", "Branches", "ALU", "Instrs", "Idle Instrs", "CPI", "BusyCPI"
+        );
+        let mut total = isa::IsaCounts::default();
+        for p in &self.processors {
+            let counts = p.timing.isa.account(&p.work_count, p.marked_objects);
+            let instructions = counts.work.total() + counts.idle.total();
+            // CPI over the whole run, including the polling loop, is what
+            // FireSim reports; the busy CPI excludes idle time altogether.
+            let cpi = self.ticks as f64 / instructions as f64;
+            let busy_cpi = if counts.work.total() > 0 {
+                p.busy_ticks as f64 / counts.work.total() as f64
+            } else {
+                0.0
+            };
+            println!(
+                "  {:<4} {:>10} {:>10} {:>10} {:>10} {:>12} {:>12} {:>8.3} {:>8.3}",
+                p.id,
+                Self::format_thousands(counts.work.loads),
+                Self::format_thousands(counts.work.stores),
+                Self::format_thousands(counts.work.branches),
+                Self::format_thousands(counts.work.alu),
+                Self::format_thousands(counts.work.total()),
+                Self::format_thousands(counts.idle.total()),
+                cpi,
+                busy_cpi
+            );
+            total.work += counts.work;
+            total.idle += counts.idle;
+        }
+        let cores = self.processors.len();
+        stats.insert("isa_loads.sum".into(), total.work.loads as f64);
+        stats.insert("isa_stores.sum".into(), total.work.stores as f64);
+        stats.insert("isa_branches.sum".into(), total.work.branches as f64);
+        stats.insert("isa_alu.sum".into(), total.work.alu as f64);
+        stats.insert("isa_instructions.sum".into(), total.work.total() as f64);
+        stats.insert(
+            "isa_idle_instructions.sum".into(),
+            total.idle.total() as f64,
+        );
+        stats.insert(
+            "isa_cpi".into(),
+            (self.ticks * cores) as f64 / (total.work.total() + total.idle.total()) as f64,
+        );
+        let total_busy_ticks: usize = self.processors.iter().map(|p| p.busy_ticks).sum();
+        stats.insert(
+            "isa_busy_cpi".into(),
+            total_busy_ticks as f64 / total.work.total() as f64,
+        );
+    }
+
+    /// Prints the termination detection section of the summary and records
+    /// its stats.
+    fn report_termination_stats(&self, stats: &mut HashMap<String, f64>) {
+        let coordinator = &self.processors[work::TERMINATION_COORDINATOR];
+        let waves = coordinator.termination.as_ref().unwrap().waves;
+        let last_trace_tick = self
+            .processors
+            .iter()
+            .map(|p| p.last_trace_tick)
+            .max()
+            .unwrap_or(0);
+        // Time from the end of the actual tracing work until every processor
+        // knew about it
+        let overhead = self.ticks - last_trace_tick;
+        let broadcast_forwarded: usize = self
+            .network
+            .bandwidth_stats()
+            .iter()
+            .map(|l| l.broadcast_forwarded)
+            .sum();
+        println!("Termination Detection:");
+        println!(
+            "  Waves:        {:>10}    Broadcasts:   {:>10}    Bcast link hops: {:>10}",
+            Self::format_thousands(waves),
+            Self::format_thousands(self.network.broadcasts()),
+            Self::format_thousands(broadcast_forwarded)
+        );
+        println!(
+            "  Overhead:     {:>10} ticks after the last tracing work (mark sense now {})",
+            Self::format_thousands(overhead),
+            coordinator.mark_sense
+        );
+        stats.insert("termination_waves".into(), waves as f64);
+        stats.insert("termination_overhead_ticks".into(), overhead as f64);
+        stats.insert("broadcasts".into(), self.network.broadcasts() as f64);
+        stats.insert("broadcast_forwarded.sum".into(), broadcast_forwarded as f64);
+    }
+
+    /// Prints the messaging section of the summary and records its stats.
+    fn report_messaging_stats(&self, stats: &mut HashMap<String, f64>) {
+        let count =
+            |p: &NMPProcessor<LOG_NUM_THREADS>, t| p.work_count.get(&t).copied().unwrap_or(0);
+        let sent: usize = self
+            .processors
+            .iter()
+            .map(|p| count(p, NMPProcessorWorkType::SendMessage))
+            .sum();
+        let reads: usize = self
+            .processors
+            .iter()
+            .map(|p| count(p, NMPProcessorWorkType::ReadInbox))
+            .sum();
+        let messages_saved: usize = self.processors.iter().map(|p| p.messages_saved).sum();
+        let reads_saved: usize = self.processors.iter().map(|p| p.inbox_reads_saved).sum();
+        let priority_reads: usize = self.processors.iter().map(|p| p.priority_inbox_reads).sum();
+        let messaging = self.processors[0].messaging;
+        println!(
+            "Messaging (inbox batch {}, up to {} loads per message, inbox priority {}):",
+            messaging.inbox_batch, messaging.coalesce_loads, messaging.inbox_priority
+        );
+        println!(
+            "  Messages sent: {:>10}    Saved by coalescing: {:>10}",
+            Self::format_thousands(sent),
+            Self::format_thousands(messages_saved)
+        );
+        println!(
+            "  Inbox reads:   {:>10}    Saved by batching:   {:>10}",
+            Self::format_thousands(reads),
+            Self::format_thousands(reads_saved)
+        );
+        println!(
+            "  Ahead of queued work: {:>10}",
+            Self::format_thousands(priority_reads)
+        );
+        stats.insert("messages_sent.sum".into(), sent as f64);
+        stats.insert("messages_saved.sum".into(), messages_saved as f64);
+        stats.insert("inbox_reads.sum".into(), reads as f64);
+        stats.insert("inbox_reads_saved.sum".into(), reads_saved as f64);
+        stats.insert("priority_inbox_reads.sum".into(), priority_reads as f64);
+    }
+
+    /// Prints the shared last-level cache section of the summary and records
+    /// its stats.
+    fn report_llc_stats(&self, stats: &mut HashMap<String, f64>) {
+        let geometry = self.llc_geometry.unwrap();
+        println!(
+            "Last-level cache ({} = {} KB per DIMM, {} DIMMs):",
+            geometry,
+            geometry.capacity() / 1024,
+            self.llcs.len()
+        );
+        let mut total = CacheStats::default();
+        let mut coherence = CoherenceStats::default();
+        for (dimm, llc) in &self.llcs {
+            let llc = llc.lock().unwrap();
+            coherence.invalidations += llc.coherence.invalidations;
+            coherence.shared_fills += llc.coherence.shared_fills;
+            let s = &llc.stats;
+            info!(
+                "[{}] LLC read hits: {}, read misses: {}, write hits: {}, write misses: {}",
+                dimm, s.read_hits, s.read_misses, s.write_hits, s.write_misses
+            );
+            total.read_hits += s.read_hits;
+            total.read_misses += s.read_misses;
+            total.write_hits += s.write_hits;
+            total.write_misses += s.write_misses;
+        }
+        let read_hit_rate = if total.read_hits + total.read_misses > 0 {
+            total.read_hits as f64 / (total.read_hits + total.read_misses) as f64
+        } else {
+            0.0
+        };
+        println!(
+            "  Read hits:    {:>10}    Read misses:  {:>10}    Hit rate: {:.3}",
+            Self::format_thousands(total.read_hits),
+            Self::format_thousands(total.read_misses),
+            read_hit_rate
+        );
+        println!(
+            "  Write hits:   {:>10}    Write misses: {:>10}",
+            Self::format_thousands(total.write_hits),
+            Self::format_thousands(total.write_misses)
+        );
+        stats.insert("llc_capacity".into(), geometry.capacity() as f64);
+        stats.insert("llc_read_hits.sum".into(), total.read_hits as f64);
+        stats.insert("llc_read_misses.sum".into(), total.read_misses as f64);
+        stats.insert("llc_write_hits.sum".into(), total.write_hits as f64);
+        stats.insert("llc_write_misses.sum".into(), total.write_misses as f64);
+        stats.insert("llc_read_hit_rate".into(), read_hit_rate);
+        if self.processors[0].dimm_sharing {
+            let local: usize = self.processors.iter().map(|p| p.dimm_local_accesses).sum();
+            let invalidated: usize = self
+                .processors
+                .iter()
+                .map(|p| p.cache.stats.invalidations)
+                .sum();
+            println!(
+                "  On-DIMM work: {:>10}    Shared fills: {:>10}",
+                Self::format_thousands(local),
+                Self::format_thousands(coherence.shared_fills)
+            );
+            println!(
+                "  Invalidations:{:>10}    Lines dropped:{:>10}",
+                Self::format_thousands(coherence.invalidations),
+                Self::format_thousands(invalidated)
+            );
+            stats.insert("dimm_local_accesses.sum".into(), local as f64);
+            stats.insert("llc_shared_fills.sum".into(), coherence.shared_fills as f64);
+            stats.insert(
+                "llc_invalidations.sum".into(),
+                coherence.invalidations as f64,
+            );
+            stats.insert("cache_invalidations.sum".into(), invalidated as f64);
+        }
+    }
+
+    /// The DRAM stats of the naive model summed over the caches of every
+    /// processor.
+    fn total_rank_stats(&self) -> RankStats {
+        let mut total = RankStats::default();
+        let caches = self.processors.iter().flat_map(|p| {
+            let tib_cache = p.tib_fetch.as_ref().and_then(|t| t.cache.as_ref());
+            std::iter::once(&p.cache).chain(tib_cache)
+        });
+        for cache in caches {
+            total += cache.rank_stats();
+        }
+        total
+    }
+
+    /// Prints the DRAM row buffer section of the summary and records its
+    /// stats.
+    fn report_row_buffer_stats(&self, policy: &PagePolicy, stats: &mut HashMap<String, f64>) {
+        let total = self.total_rank_stats();
+        let mut locality = RowLocality::default();
+        for p in &self.processors {
+            let tib_cache = p.tib_fetch.as_ref().and_then(|t| t.cache.as_ref());
+            for cache in std::iter::once(&p.cache).chain(tib_cache) {
+                locality.merge(cache.row_locality().unwrap());
+            }
+        }
+        let percent = |n: usize| n as f64 * 100.0 / total.transactions.max(1) as f64;
+        println!("DRAM row buffers (aggregate, {} policy):", policy);
+        println!(
+            "  Transactions: {:>10}    Row hits: {:>10} ({:.2}%)    Empty: {:>10} ({:.2}%)    Conflicts: {:>10} ({:.2}%)    Closures: {:>10}",
+            Self::format_thousands(total.transactions),
+            Self::format_thousands(total.row_hits),
+            percent(total.row_hits),
+            Self::format_thousands(total.row_empties),
+            percent(total.row_empties),
+            Self::format_thousands(total.row_conflicts),
+            percent(total.row_conflicts),
+            Self::format_thousands(total.closures)
+        );
+        println!(
+            "  Rows touched: {:>10}    Banks touched: {:>3} of {}    Busiest bank: {:.2}x the mean",
+            Self::format_thousands(locality.rows_touched()),
+            locality.banks_touched(),
+            num_banks(),
+            locality.bank_imbalance()
+        );
+        stats.insert("dram_transactions.sum".into(), total.transactions as f64);
+        stats.insert("dram_ticks.sum".into(), total.ticks as f64);
+        stats.insert("row_hits.sum".into(), total.row_hits as f64);
+        stats.insert("row_empties.sum".into(), total.row_empties as f64);
+        stats.insert("row_conflicts.sum".into(), total.row_conflicts as f64);
+        stats.insert("row_closures.sum".into(), total.closures as f64);
+        stats.insert(
+            "row_hit_rate".into(),
+            total.row_hits as f64 / total.transactions.max(1) as f64,
+        );
+        stats.insert("rows_touched".into(), locality.rows_touched() as f64);
+        stats.insert("banks_touched".into(), locality.banks_touched() as f64);
+        stats.insert("bank_imbalance".into(), locality.bank_imbalance());
+    }
+
+    /// Prints the DRAM refresh section of the summary and records its stats.
+    fn report_refresh_stats(&self, refresh: &RefreshTiming, stats: &mut HashMap<String, f64>) {
+        let total = self.total_rank_stats();
+        let fraction = total.refresh_ticks as f64 / total.ticks.max(1) as f64;
+        println!(
+            "DRAM refresh (aggregate, {} cycles every {}):",
+            refresh.latency, refresh.interval
+        );
+        println!(
+            "  Transactions: {:>10}    Refresh stalls: {:>10}    Stall ticks: {:>10} ({:.2}% of DRAM latency)",
+            Self::format_thousands(total.transactions),
+            Self::format_thousands(total.refresh_stalls),
+            Self::format_thousands(total.refresh_ticks),
+            fraction * 100.0
+        );
+        stats.insert("refresh_stalls.sum".into(), total.refresh_stalls as f64);
+        stats.insert("refresh_ticks.sum".into(), total.refresh_ticks as f64);
+        stats.insert("refresh_latency_fraction".into(), fraction);
+    }
+
+    /// Prints the mutator interference section of the summary and records
+    /// its stats.
+    fn report_mutator_stats(&self, traffic: &MutatorTraffic, stats: &mut HashMap<String, f64>) {
+        let total = self.total_rank_stats();
+        let fraction = total.mutator_ticks as f64 / total.ticks.max(1) as f64;
+        println!(
+            "Mutator traffic (aggregate, {} of every {} cycles):",
+            traffic.window, traffic.slice
+        );
+        println!(
+            "  Transactions: {:>10}    Mutator stalls: {:>10}    Stall ticks: {:>10} ({:.2}% of DRAM latency)",
+            Self::format_thousands(total.transactions),
+            Self::format_thousands(total.mutator_stalls),
+            Self::format_thousands(total.mutator_ticks),
+            fraction * 100.0
+        );
+        stats.insert("mutator_stalls.sum".into(), total.mutator_stalls as f64);
+        stats.insert("mutator_ticks.sum".into(), total.mutator_ticks as f64);
+        stats.insert("mutator_latency_fraction".into(), fraction);
+    }
+
+    /// Prints the mark buffer section of the summary and records its stats.
+    fn report_mark_buffer_stats(&self, stats: &mut HashMap<String, f64>) {
+        let mut total = WriteBufferStats::default();
+        let mut pending = 0;
+        for p in &self.processors {
+            let buffer = p.cache.write_buffer.as_ref().unwrap();
+            total.writes += buffer.stats.writes;
+            total.coalesced += buffer.stats.coalesced;
+            total.writebacks += buffer.stats.writebacks;
+            pending += buffer.pending();
+        }
+        // Whatever is still buffered is written back when the buffer is
+        // drained at the end of the collection.
+        let line_writes = total.writebacks + pending;
+        let reduction = if total.writes > 0 {
+            1.0 - line_writes as f64 / total.writes as f64
+        } else {
+            0.0
+        };
+        println!("Mark buffer (aggregate):");
+        println!(
+            "  Mark writes:  {:>10}    Coalesced:    {:>10}",
+            Self::format_thousands(total.writes),
+            Self::format_thousands(total.coalesced)
+        );
+        println!(
+            "  Line writes:  {:>10}    Write traffic reduction: {:.3}",
+            Self::format_thousands(line_writes),
+            reduction
+        );
+        stats.insert("mark_buffer_writes.sum".into(), total.writes as f64);
+        stats.insert("mark_buffer_coalesced.sum".into(), total.coalesced as f64);
+        stats.insert("mark_buffer_line_writes.sum".into(), line_writes as f64);
+        stats.insert("mark_buffer_traffic_reduction".into(), reduction);
+    }
+
+    /// Prints the root scanning section of the summary and records its
+    /// stats.
+    fn report_root_scan_stats(&self, stats: &mut HashMap<String, f64>) {
+        let frames: usize = self.processors.iter().map(|p| p.root_frames).sum();
+        let slots: usize = self.processors.iter().map(|p| p.root_slots).sum();
+        let end = self
+            .processors
+            .iter()
+            .map(|p| p.root_scan_end)
+            .max()
+            .unwrap_or(0);
+        println!("Root scanning:");
+        println!(
+            "  Frames:       {:>10}    Root slots:   {:>10}    Done at tick: {:>10}",
+            Self::format_thousands(frames),
+            Self::format_thousands(slots),
+            Self::format_thousands(end)
+        );
+        stats.insert("root_frames.sum".into(), frames as f64);
+        stats.insert("root_slots.sum".into(), slots as f64);
+        stats.insert("root_scan_ticks".into(), end as f64);
+    }
+
+    /// Prints the demand MSHR section of the summary and records its stats.
+    fn report_mshr_stats(&self, stats: &mut HashMap<String, f64>) {
+        println!("Demand MSHRs:");
+        println!(
+            "  {:<4} {:>10} {:>10} {:>12} {:>16}",
+            "P", "Misses", "Merged", "Full stalls", "Avg outstanding"
+        );
+        let mut allocations = 0;
+        let mut merges = 0;
+        let mut full_stalls = 0;
+        let mut occupancy = 0;
+        let mut sampled_ticks = 0;
+        for p in &self.processors {
+            let m = &p.mshrs.as_ref().unwrap().stats;
+            println!(
+                "  {:<4} {:>10} {:>10} {:>12} {:>16.3}",
+                p.id,
+                Self::format_thousands(m.allocations),
+                Self::format_thousands(m.merges),
+                Self::format_thousands(p.mshr_full_stalls),
+                m.average_outstanding()
+            );
+            allocations += m.allocations;
+            merges += m.merges;
+            full_stalls += p.mshr_full_stalls;
+            occupancy += m.occupancy;
+            sampled_ticks += m.sampled_ticks;
+        }
+        let avg_outstanding = if sampled_ticks > 0 {
+            occupancy as f64 / sampled_ticks as f64
+        } else {
+            0.0
+        };
+        stats.insert("mshr_allocations.sum".into(), allocations as f64);
+        stats.insert("mshr_merges.sum".into(), merges as f64);
+        stats.insert("mshr_full_stalls.sum".into(), full_stalls as f64);
+        stats.insert("mshr_avg_outstanding".into(), avg_outstanding);
+    }
+
+    /// Prints the prefetcher section of the summary and records its stats.
+    fn report_prefetch_stats(
+        &self,
+        stats: &mut HashMap<String, f64>,
+        total_read_hits: usize,
+        total_read_misses: usize,
+    ) {
+        let mut total = prefetch::PrefetcherStats::default();
+        let mut fills = 0;
+        let mut useful = 0;
+        for p in &self.processors {
+            let s = &p.prefetcher.as_ref().unwrap().stats;
+            total.requested += s.requested;
+            total.dropped += s.dropped;
+            total.redundant += s.redundant;
+            total.issued += s.issued;
+            total.mshr_full_ticks += s.mshr_full_ticks;
+            fills += p.cache.stats.prefetch_fills;
+            useful += p.cache.stats.useful_prefetches;
+        }
+        // Coverage: fraction of would-be demand misses removed by prefetching.
+        // Accuracy: fraction of prefetched lines that were used before eviction.
+        let coverage = if useful + total_read_misses > 0 {
+            useful as f64 / (useful + total_read_misses) as f64
+        } else {
+            0.0
+        };
+        let accuracy = if fills > 0 {
+            useful as f64 / fills as f64
+        } else {
+            0.0
+        };
+        // Without the prefetcher, every useful prefetch would have been a
+        // demand miss instead of a hit.
+        let reads = (total_read_hits + total_read_misses).max(1) as f64;
+        let miss_rate = total_read_misses as f64 / reads;
+        let baseline_miss_rate = (total_read_misses + useful) as f64 / reads;
+        println!("Prefetcher (aggregate):");
+        println!(
+            "  Requested:    {:>10}    Dropped:      {:>10}    Redundant: {:>10}",
+            Self::format_thousands(total.requested),
+            Self::format_thousands(total.dropped),
+            Self::format_thousands(total.redundant)
+        );
+        println!(
+            "  Issued:       {:>10}    Filled:       {:>10}    Useful:    {:>10}",
+            Self::format_thousands(total.issued),
+            Self::format_thousands(fills),
+            Self::format_thousands(useful)
+        );
+        println!(
+            "  Coverage:     {:>10.3}    Accuracy:     {:>10.3}    MSHR full: {:>10}",
+            coverage,
+            accuracy,
+            Self::format_thousands(total.mshr_full_ticks)
+        );
+        println!(
+            "  Miss rate:    {:>10.3}    Baseline:     {:>10.3}    Change:    {:>+10.3}",
+            miss_rate,
+            baseline_miss_rate,
+            miss_rate - baseline_miss_rate
+        );
+        stats.insert("prefetch_requested.sum".into(), total.requested as f64);
+        stats.insert("prefetch_dropped.sum".into(), total.dropped as f64);
+        stats.insert("prefetch_redundant.sum".into(), total.redundant as f64);
+        stats.insert("prefetch_issued.sum".into(), total.issued as f64);
+        stats.insert("prefetch_fills.sum".into(), fills as f64);
+        stats.insert("prefetch_useful.sum".into(), useful as f64);
+        stats.insert(
+            "prefetch_mshr_full_ticks.sum".into(),
+            total.mshr_full_ticks as f64,
+        );
+        stats.insert("prefetch_coverage".into(), coverage);
+        stats.insert("prefetch_accuracy".into(), accuracy);
+        stats.insert("prefetch_miss_rate".into(), miss_rate);
+        stats.insert("prefetch_baseline_miss_rate".into(), baseline_miss_rate);
+        stats.insert(
+            "prefetch_miss_rate_delta".into(),
+            miss_rate - baseline_miss_rate,
+        );
+    }
+}
+
+fn network_config(args: &SimulationArgs) -> NetworkConfig {
+    NetworkConfig {
+        link_latency: args.link_latency,
+        link_width: args.link_width,
+        link_capacity: (args.link_buffer > 0).then_some(args.link_buffer),
+    }
+}
+
+fn build_topology(args: &SimulationArgs, network_config: NetworkConfig) -> Box<dyn Topology> {
+    match (&args.topology_file, args.topology) {
+        (Some(file), _) => Box::new(topology::GraphTopology::new(file, network_config)),
+        (None, crate::cli::TopologyChoice::Line) => Box::new(topology::LineTopology::new()),
+        (None, crate::cli::TopologyChoice::Ring) => {
+            let mut ring = topology::RingTopology::new();
+            if let Ok(weights) = <[u64; 4]>::try_from(args.link_weights.as_slice()) {
+                ring = ring.with_link_weights(weights);
+            }
+            Box::new(ring)
+        }
+        (None, crate::cli::TopologyChoice::FullyConnected) => {
+            Box::new(topology::FullyConnectedTopology::new(4))
+        }
+    }
+}
+
+/// Refuses `--link-buffer` on topologies that could deadlock with it, since
+/// the network has no escape channels. The routes of a ring only stay clear
+/// of a circle of links waiting for each other through the way around they
+/// pick, so a ring is refused outright.
+pub(crate) fn check_network(args: &SimulationArgs) -> Result<()> {
+    if args.link_buffer == 0 {
+        return Ok(());
+    }
+    if args.topology_file.is_none() && args.topology == crate::cli::TopologyChoice::Ring {
+        bail!("--link-buffer can deadlock a ring, use another --topology");
+    }
+    if build_topology(args, network_config(args)).has_cyclic_routes() {
+        bail!("--link-buffer can deadlock the routes of the topology file");
+    }
+    Ok(())
 }
 
 impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS> {
     fn new<O: ObjectModel>(args: &SimulationArgs, object_model: &O) -> Self {
+        // Ownership is decoded from addresses with the ranks of the mapping
+        // installed
+        assert_eq!(
+            Self::NUM_THREADS as usize,
+            4 * ranks_per_dimm(),
+            "The address mapping is set up for another number of processors"
+        );
         let rank_option = if args.use_dramsim3 {
             DDR4RankOption::DRAMsim3 {
                 config_file: args.dramsim3_config.clone(),
@@ -60,32 +755,171 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
             DDR4RankOption::Naive
         };
 
-        let topology: Box<dyn Topology> = match args.topology {
-            crate::cli::TopologyChoice::Line => Box::new(topology::LineTopology::new()),
-            crate::cli::TopologyChoice::Ring => Box::new(topology::RingTopology::new()),
-            crate::cli::TopologyChoice::FullyConnected => {
-                Box::new(topology::FullyConnectedTopology::new(4))
-            }
+        let refresh = args.refresh.then_some(RefreshTiming {
+            interval: args.refresh_interval,
+            latency: args.refresh_latency,
+        });
+        let mutator = args
+            .mutator_share
+            .map(|share| MutatorTraffic::new(args.mutator_slice, share));
+
+        let page_policy = (!args.use_dramsim3).then_some(match args.page_policy {
+            crate::cli::PagePolicyChoice::Open => PagePolicy::Open,
+            crate::cli::PagePolicyChoice::Closed => PagePolicy::Closed,
+            crate::cli::PagePolicyChoice::Timeout => PagePolicy::Timeout(args.page_timeout),
+        });
+
+        let network_config = network_config(args);
+        let topology = build_topology(args, network_config);
+        let network = Network::new(&*topology, network_config);
+
+        let llc_geometry = args.llc.filter(|g| *g != CacheGeometry::None);
+        let llcs: BTreeMap<DimmId, Arc<Mutex<LastLevelCache>>> = match llc_geometry {
+            Some(geometry) => (0..Self::NUM_THREADS)
+                .map(|id| DimmId::from(RankId(id as u8)))
+                .map(|dimm| {
+                    let llc = LastLevelCache::new(geometry, args.dimm_sharing);
+                    (dimm, Arc::new(Mutex::new(llc)))
+                })
+                .collect(),
+            None => BTreeMap::new(),
         };
-        let network = Network::new(&*topology);
-        let dimm_to_rank_latency = network::DIMM_TO_RANK_LATENCY;
 
         // Convert &[u64] into Vec<u64>
         let mut processors: Vec<NMPProcessor<LOG_NUM_THREADS>> = (0..Self::NUM_THREADS)
             .map(|id| {
-                NMPProcessor::new(
-                    id as usize,
+                let mut cache = SetAssociativeCache::new(
+                    args.cache,
+                    llcs.get(&DimmId::from(RankId(id as u8)))
+                        .map(|llc| LlcPort {
+                            llc: llc.clone(),
+                            requester: id as usize,
+                        }),
                     rank_option.clone(),
-                    dimm_to_rank_latency,
                     args.page_size,
+                );
+                if args.tlb.is_some() || args.tlb_miss_penalty.is_some() {
+                    cache.tlb = Tlb::with_geometry(
+                        args.page_size,
+                        args.tlb.unwrap_or(Tlb::default_geometry(args.page_size)),
+                        args.tlb_miss_penalty,
+                    );
+                }
+                if let Some(refresh) = refresh {
+                    cache.set_refresh(refresh);
+                }
+                if let Some(traffic) = mutator {
+                    cache.set_mutator_traffic(traffic);
+                }
+                if let Some(policy) = page_policy {
+                    cache.set_page_policy(policy);
+                }
+                if args.mark_buffer > 0 {
+                    cache.write_buffer = Some(WriteCombiningBuffer::new(args.mark_buffer));
+                }
+                let prefetcher = (args.prefetch_depth > 0).then(|| {
+                    Prefetcher::new(args.prefetch_depth, args.prefetch_mshrs, cache.line_size())
+                });
+                NMPProcessor::new(
+                    id as usize,
+                    cache,
+                    args.timing_model.as_deref().cloned().unwrap_or_default(),
+                    prefetcher,
+                    args.mshrs,
+                    MessagingOptions {
+                        inbox_batch: args.inbox_batch,
+                        inbox_priority: args.inbox_priority,
+                        coalesce_loads: args.coalesce_loads,
+                    },
                 )
             })
             .collect();
-        for root in object_model.roots() {
-            let o = *root;
-            debug_assert_ne!(o, 0);
-            let owner = Self::get_owner_processor(o);
-            processors[owner].works.push_back(NMPProcessorWork::Mark(o));
+        if args.tenant.is_some() {
+            // Before the roots are queued, as they go to the queues of their
+            // tenants
+            for p in &mut processors {
+                p.tenancy = Some(Tenancy::new(args.tenant_arbitration));
+            }
+        }
+        if let Some(threads) = args.root_stacks.root_stacks {
+            // Each stack is scanned by one processor, from the top frame down
+            let stacks = RootStacks::synthesize(
+                object_model.roots(),
+                threads,
+                args.root_stacks.frame_roots,
+                args.root_stacks.frame_words,
+            )
+            .unwrap();
+            let num_processors = processors.len();
+            for (i, stack) in stacks.stacks.into_iter().enumerate() {
+                for frame in stack.into_iter().rev() {
+                    processors[i % num_processors]
+                        .works
+                        .push_back(NMPProcessorWork::ScanFrame(frame));
+                }
+            }
+        } else {
+            for &root in object_model.roots() {
+                let o = ObjectReference::from_raw_unchecked(root);
+                let owner = Self::get_owner_processor(o.to_address());
+                processors[owner].push_tracing_work(NMPProcessorWork::Mark(o));
+            }
+        }
+        if let Some(capacity) = args.mark_queue {
+            // After the roots are queued, as a collection starts with all
+            // of them in memory
+            for p in &mut processors {
+                p.mark_queue = Some(MarkQueue::new(capacity, args.overflow_policy));
+            }
+        }
+        let power = args.sleep_after.map(|sleep_after| PowerOptions {
+            sleep_after,
+            wake_latency: args.wake_latency,
+            idle_power: args.idle_power,
+            sleep_power: args.sleep_power,
+        });
+        let sizes = (args.line_marks.is_some() || args.block_marks.is_some()).then(|| {
+            let sizes = object_model
+                .objects_iter()
+                .map(|o| (o, object_model.object_size(o).unwrap()))
+                .collect();
+            Arc::new(sizes)
+        });
+        for p in &mut processors {
+            p.queue_sample_interval = args.queue_sample_interval;
+            p.dimm_sharing = args.dimm_sharing;
+            p.side_marks = sizes
+                .clone()
+                .map(|sizes| SideMarks::new(args.line_marks, args.block_marks, sizes));
+            p.private_marks = args.private_marks.then(PrivateMarks::default);
+            if args.tib_fetch {
+                let cache = args
+                    .tib_cache
+                    .filter(|g| *g != CacheGeometry::None)
+                    .map(|g| {
+                        let mut cache =
+                            SetAssociativeCache::new(g, None, rank_option.clone(), args.page_size);
+                        if let Some(refresh) = refresh {
+                            cache.set_refresh(refresh);
+                        }
+                        if let Some(traffic) = mutator {
+                            cache.set_mutator_traffic(traffic);
+                        }
+                        if let Some(policy) = page_policy {
+                            cache.set_page_policy(policy);
+                        }
+                        cache
+                    });
+                p.tib_fetch = Some(TibFetch::new(cache));
+            }
+            p.power = power.as_ref().map(PowerStates::new);
+            p.scan_engine = args
+                .scan_engine
+                .map(|min_slots| ScanEngine::new(min_slots, args.scan_engine_bandwidth));
+        }
+        if args.distributed_termination {
+            processors[work::TERMINATION_COORDINATOR].termination =
+                Some(TerminationDetector::new(processors.len()));
         }
         NMPGC {
             processors,
@@ -94,47 +928,103 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
             frequency_ghz: 1.6,
             topology,
             network,
+            isa_accounting: args.isa_accounting,
+            distributed_termination: args.distributed_termination,
+            cache_geometry: args.cache,
+            llcs,
+            llc_geometry,
+            shard_quantum: args.shard_quantum,
+            refused: vec![VecDeque::new(); Self::NUM_THREADS as usize],
+            sampling: args
+                .sample_window
+                .zip(args.fast_forward)
+                .map(|(window, fast_forward)| Sampling::new(window, fast_forward)),
+            root_stacks: args.root_stacks.root_stacks.is_some(),
+            power,
+            refresh,
+            mutator,
+            page_policy,
         }
     }
 
     fn tick<O: ObjectModel>(&mut self) -> bool {
-        self.ticks += 1;
-        let mut messages = Vec::new();
-
-        for p in &mut self.processors {
-            let msg = p.tick::<O>();
-            if let Some(m) = msg {
-                messages.push((p.id, m));
-            }
+        let finished = if self.shard_quantum > 0 {
+            self.tick_sharded::<O>()
+        } else if self.sampling.is_some() {
+            self.tick_sampled::<O>()
+        } else {
+            self.tick_sequential::<O>()
+        };
+        if finished && self.processors[0].private_marks.is_some() {
+            self.union_private_marks();
         }
+        finished
+    }
 
-        // Inject outgoing messages into the network fabric.
-        for (sender_id, msg) in messages {
-            let sender_rank = RankId(sender_id as u8);
-            let recipient_rank = RankId(msg.recipient as u8);
-            let sender_dimm = DimmId::from(sender_rank);
-            let recipient_dimm = DimmId::from(recipient_rank);
+    fn ticks(&self) -> usize {
+        self.ticks
+    }
 
-            if sender_dimm == recipient_dimm {
-                // Same DIMM: deliver directly (no network traversal needed).
-                self.processors[msg.recipient].inbox.push(msg);
-            } else {
-                let route = self.topology.get_route(sender_dimm, recipient_dimm);
-                self.network.inject(msg, route);
-            }
-        }
+    fn marked_objects(&self) -> usize {
+        self.processors.iter().map(|p| p.marked_objects).sum()
+    }
 
-        // Tick the network: advance in-flight messages.
-        let delivered = self.network.tick();
-        for msg in delivered {
-            self.processors[msg.recipient].inbox.push(msg);
+    fn diagnostics(&self) -> String {
+        let mut lines: Vec<String> = self
+            .processors
+            .iter()
+            .map(|p| format!("  {}", p.describe()))
+            .collect();
+        lines.push(format!("  Network: {}", self.network.describe()));
+        let refused: usize = self.refused.iter().map(|r| r.len()).sum();
+        if refused > 0 {
+            lines.push(format!("  {} messages refused by the network", refused));
         }
+        lines.join("\n")
+    }
 
-        // Check if all processors are done AND no messages in flight.
-        // FIXME: this assumes magical global knowledge, but
-        // this actually requires a distributed termination detection algorithm
-        let all_done = self.processors.iter().all(|p| p.locally_done()) && self.network.is_empty();
-        all_done
+    fn save(&self, writer: &mut dyn Write) -> Result<()> {
+        let state = NMPGCState {
+            ticks: self.ticks,
+            processors: &self.processors,
+            network: &self.network,
+            llcs: &self.llcs,
+            refused: &self.refused,
+        };
+        bincode::serialize_into(writer, &state)?;
+        Ok(())
+    }
+
+    fn load(&mut self, reader: &mut dyn Read) -> Result<()> {
+        let state: NMPGCState<
+            Vec<NMPProcessor<LOG_NUM_THREADS>>,
+            Network,
+            BTreeMap<DimmId, LastLevelCache>,
+            Vec<VecDeque<NMPMessage>>,
+        > = bincode::deserialize_from(reader)?;
+        if state.processors.len() != self.processors.len()
+            || !state.llcs.keys().eq(self.llcs.keys())
+        {
+            bail!("The checkpoint is of a different NMPGC configuration");
+        }
+        // The last-level caches are shared with the processor caches, so
+        // they are loaded in place.
+        for (dimm, llc) in state.llcs {
+            *self.llcs[&dimm].lock().unwrap() = llc;
+        }
+        for (p, mut loaded) in self.processors.iter_mut().zip(state.processors) {
+            loaded.cache.take_llc_port(&mut p.cache);
+            if let (Some(loaded), Some(side_marks)) =
+                (loaded.side_marks.as_mut(), p.side_marks.as_mut())
+            {
+                loaded.take_sizes(side_marks);
+            }
+            *p = loaded;
+        }
+        self.network = state.network;
+        self.refused = state.refused;
+        self.ticks = state.ticks;
+        Ok(())
     }
 
     fn stats(&self) -> HashMap<String, f64> {
@@ -202,10 +1092,9 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
             );
         }
 
-        // Network bandwidth stats (8 B per message, i.e. a 64-bit address)
-        const MESSAGE_SIZE_BYTES: usize = 8;
-        assert_eq!(MESSAGE_SIZE_BYTES % network::PER_HOP_LATENCY, 0);
-        const FLIT_SIZE_BYTES: usize = MESSAGE_SIZE_BYTES / network::PER_HOP_LATENCY;
+        // Network bandwidth stats
+        const MESSAGE_WORD_BYTES: usize = network::MESSAGE_WORD_BYTES;
+        let network_config = *self.network.config();
         let total_time_s = self.ticks as f64 / (self.frequency_ghz * 1e9);
         for link in self.network.bandwidth_stats() {
             let key_prefix = format!("link_{}_to_{}", link.from_dimm, link.to_dimm);
@@ -217,14 +1106,36 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
                 format!("{}.peak_flits_per_tick", key_prefix),
                 link.peak_flits_per_tick as f64,
             );
+            stats.insert(
+                format!("{}.flits_forwarded", key_prefix),
+                link.flits_forwarded as f64,
+            );
+            // Average flits on the link per tick, above 1 once the offered
+            // load exceeds what a single link can serialize.
+            stats.insert(
+                format!("{}.avg_flits_per_tick", key_prefix),
+                link.flits_forwarded as f64 / self.ticks as f64,
+            );
             // Peak throughput demand in GB/s
             let peak_gbps =
-                link.peak_flits_per_tick as f64 * FLIT_SIZE_BYTES as f64 * self.frequency_ghz;
+                link.peak_flits_per_tick as f64 * link.link_width as f64 * self.frequency_ghz;
             stats.insert(format!("{}.peak_throughput_gbps", key_prefix), peak_gbps);
+            if self.distributed_termination {
+                stats.insert(
+                    format!("{}.broadcast_forwarded", key_prefix),
+                    link.broadcast_forwarded as f64,
+                );
+            }
+            if network_config.link_capacity.is_some() {
+                stats.insert(
+                    format!("{}.blocked_ticks", key_prefix),
+                    link.blocked_ticks as f64,
+                );
+            }
             // Average throughput in GB/s
             if total_time_s > 0.0 {
                 let avg_gbps =
-                    link.messages_forwarded as f64 * MESSAGE_SIZE_BYTES as f64 / total_time_s / 1e9;
+                    link.words_forwarded as f64 * MESSAGE_WORD_BYTES as f64 / total_time_s / 1e9;
                 stats.insert(format!("{}.avg_throughput_gbps", key_prefix), avg_gbps);
             }
             info!(
@@ -249,6 +1160,11 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
         } else {
             0.0
         };
+        let tlb_miss_rate = if total_tlb_hits + total_tlb_misses > 0 {
+            total_tlb_misses as f64 / (total_tlb_hits + total_tlb_misses) as f64
+        } else {
+            0.0
+        };
         let tlb_read_hit_rate = if total_tlb_read_hits + total_tlb_read_misses > 0 {
             total_tlb_read_hits as f64 / (total_tlb_read_hits + total_tlb_read_misses) as f64
         } else {
@@ -279,7 +1195,16 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
         );
         println!("  Utilization:        {:.3}", utilization);
         println!();
-        println!("Cache (aggregate):");
+        self.processors[0].timing.print();
+        println!();
+        match self.cache_geometry {
+            CacheGeometry::None => println!("Cache (aggregate, none):"),
+            geometry => println!(
+                "Cache (aggregate, {} = {} KB per processor):",
+                geometry,
+                geometry.capacity() / 1024
+            ),
+        }
         println!(
             "  Read hits:    {:>10}    Read misses:  {:>10}    Hit rate: {:.3}",
             Self::format_thousands(total_read_hits),
@@ -293,20 +1218,101 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
             write_hit_rate
         );
         println!();
-        println!("TLB (aggregate):");
-        println!(
-            "  Read hits:    {:>10}    Read misses:  {:>10}    Hit rate: {:.3}",
-            Self::format_thousands(total_tlb_read_hits),
-            Self::format_thousands(total_tlb_read_misses),
-            tlb_read_hit_rate
-        );
-        println!(
-            "  Write hits:   {:>10}    Write misses: {:>10}    Hit rate: {:.3}",
-            Self::format_thousands(total_tlb_write_hits),
-            Self::format_thousands(total_tlb_write_misses),
-            tlb_write_hit_rate
-        );
+        if self.llc_geometry.is_some() {
+            self.report_llc_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].cache.tlb.is_present() {
+            println!("TLB (aggregate):");
+            println!(
+                "  Read hits:    {:>10}    Read misses:  {:>10}    Hit rate: {:.3}",
+                Self::format_thousands(total_tlb_read_hits),
+                Self::format_thousands(total_tlb_read_misses),
+                tlb_read_hit_rate
+            );
+            println!(
+                "  Write hits:   {:>10}    Write misses: {:>10}    Hit rate: {:.3}",
+                Self::format_thousands(total_tlb_write_hits),
+                Self::format_thousands(total_tlb_write_misses),
+                tlb_write_hit_rate
+            );
+            println!("  Miss rate:    {:>10.3}", tlb_miss_rate);
+        } else {
+            println!("TLB: none (physically addressed)");
+        }
         println!();
+        if self.isa_accounting {
+            self.report_isa_stats(&mut stats);
+            println!();
+        }
+        if self.distributed_termination {
+            self.report_termination_stats(&mut stats);
+            println!();
+        }
+        if !self.processors[0].messaging.is_default() {
+            self.report_messaging_stats(&mut stats);
+            println!();
+        }
+        if let Some(policy) = &self.page_policy {
+            self.report_row_buffer_stats(policy, &mut stats);
+            println!();
+        }
+        if let Some(refresh) = &self.refresh {
+            self.report_refresh_stats(refresh, &mut stats);
+            println!();
+        }
+        if let Some(traffic) = &self.mutator {
+            self.report_mutator_stats(traffic, &mut stats);
+            println!();
+        }
+        if self.processors[0].cache.write_buffer.is_some() {
+            self.report_mark_buffer_stats(&mut stats);
+            println!();
+        }
+        if self.root_stacks {
+            self.report_root_scan_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].side_marks.is_some() {
+            self.report_side_mark_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].private_marks.is_some() {
+            self.report_private_mark_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].tib_fetch.is_some() {
+            self.report_tib_fetch_stats(&mut stats);
+            println!();
+        }
+        if let Some(options) = &self.power {
+            self.report_power_stats(options, &mut stats);
+            println!();
+        }
+        if self.processors[0].scan_engine.is_some() {
+            self.report_scan_engine_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].mark_queue.is_some() {
+            self.report_mark_queue_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].tenancy.is_some() {
+            self.report_tenancy_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].mshrs.is_some() {
+            self.report_mshr_stats(&mut stats);
+            println!();
+        }
+        if self.processors[0].prefetcher.is_some() {
+            self.report_prefetch_stats(&mut stats, total_read_hits, total_read_misses);
+            println!();
+        }
+        if self.sampling.is_some() {
+            self.report_sampling_stats(&mut stats);
+            println!();
+        }
         println!("Per-Processor:");
         println!(
             "  {:<4} {:>10} {:>10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
@@ -343,23 +1349,40 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
         println!();
         self.topology.print_diagram();
         println!();
-        println!("Network Links:");
-        println!(
+        let flow_control = network_config.link_capacity.is_some();
+        print!(
+            "Network Links ({} B/tick, {} + {} ticks per single-word hop",
+            network_config.link_width,
+            network_config.flits(1),
+            network_config.link_latency
+        );
+        if let Some(capacity) = network_config.link_capacity {
+            print!(", {} buffer slots per link", capacity);
+        }
+        println!("):");
+        print!(
             "  {:<16} {:>10} {:>10} {:>12} {:>12}",
             "Link", "Msgs Fwd", "Peak Flits", "Peak GB/s", "Avg GB/s"
         );
+        if self.distributed_termination {
+            print!(" {:>10}", "Bcast Fwd");
+        }
+        if flow_control {
+            print!(" {:>12}", "Blocked");
+        }
+        println!();
         // Sort link stats by physical connection order.
         let mut link_stats = self.network.bandwidth_stats();
         link_stats.sort_by_key(|s| self.topology.link_sort_key(s.from_dimm, s.to_dimm));
         for link in &link_stats {
             let peak_gbps =
-                link.peak_flits_per_tick as f64 * FLIT_SIZE_BYTES as f64 * self.frequency_ghz;
+                link.peak_flits_per_tick as f64 * link.link_width as f64 * self.frequency_ghz;
             let avg_gbps = if total_time_s > 0.0 {
-                link.messages_forwarded as f64 * MESSAGE_SIZE_BYTES as f64 / total_time_s / 1e9
+                link.words_forwarded as f64 * MESSAGE_WORD_BYTES as f64 / total_time_s / 1e9
             } else {
                 0.0
             };
-            println!(
+            print!(
                 "  {} -> {}    {:>10} {:>10} {:>12.3} {:>12.3}",
                 link.from_dimm,
                 link.to_dimm,
@@ -368,6 +1391,29 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
                 peak_gbps,
                 avg_gbps
             );
+            if self.distributed_termination {
+                print!(" {:>10}", Self::format_thousands(link.broadcast_forwarded));
+            }
+            if flow_control {
+                print!(" {:>12}", Self::format_thousands(link.blocked_ticks));
+            }
+            println!();
+        }
+        let (messages, mean_hops) = self.network.hops();
+        println!(
+            "  Mean hops: {:.3} over {} messages",
+            mean_hops,
+            Self::format_thousands(messages)
+        );
+        stats.insert("network_messages.sum".into(), messages as f64);
+        stats.insert("network_hops.mean".into(), mean_hops);
+        if flow_control {
+            let link_stall_ticks: usize = self.processors.iter().map(|p| p.link_stall_ticks).sum();
+            println!(
+                "  Sender stall ticks: {}",
+                Self::format_thousands(link_stall_ticks)
+            );
+            stats.insert("link_stall_ticks.sum".into(), link_stall_ticks as f64);
         }
         println!("######################### End Human-Readable Summary ######################");
 
@@ -381,6 +1427,21 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
         stats.insert("write_misses.sum".into(), total_write_misses as f64);
         stats.insert("read_hit_rate".into(), read_hit_rate);
         stats.insert("write_hit_rate".into(), write_hit_rate);
+        // Recorded so that sweeps over the cache geometry can be tabulated
+        if let CacheGeometry::SetAssociative {
+            sets,
+            ways,
+            line_size,
+        } = self.cache_geometry
+        {
+            stats.insert("cache_sets".into(), sets as f64);
+            stats.insert("cache_ways".into(), ways as f64);
+            stats.insert("cache_line_size".into(), line_size as f64);
+        }
+        stats.insert(
+            "cache_capacity".into(),
+            self.cache_geometry.capacity() as f64,
+        );
         stats.insert("tlb_read_hits.sum".into(), total_tlb_read_hits as f64);
         stats.insert("tlb_read_misses.sum".into(), total_tlb_read_misses as f64);
         stats.insert("tlb_write_hits.sum".into(), total_tlb_write_hits as f64);
@@ -388,6 +1449,7 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
         stats.insert("tlb_read_hit_rate".into(), tlb_read_hit_rate);
         stats.insert("tlb_write_hit_rate".into(), tlb_write_hit_rate);
         stats.insert("tlb_hit_rate".into(), tlb_hit_rate);
+        stats.insert("tlb_miss_rate".into(), tlb_miss_rate);
         // in ms
         stats.insert("time".into(), time_ms);
 
@@ -397,9 +1459,20 @@ impl<const LOG_NUM_THREADS: u8> SimulationArchitecture for NMPGC<LOG_NUM_THREADS
     fn events(&self) -> Vec<TracingEvent> {
         self.processors.iter().flat_map(|p| p.events()).collect()
     }
+
+    fn frequency_ghz(&self) -> f64 {
+        self.frequency_ghz
+    }
+
+    fn log_events(&mut self, log: &mut EventLog, last: bool) -> Result<()> {
+        for p in &mut self.processors {
+            p.log_events(log, last)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct NMPProcessor<const LOG_NUM_THREADS: u8> {
     id: usize,
     ticks: usize, // This is synchronized with the global ticks
@@ -413,19 +1486,104 @@ struct NMPProcessor<const LOG_NUM_THREADS: u8> {
     idle_ranges: Vec<(usize, usize)>,
     idle_start: Option<usize>,
     frequency_ghz: f64, // Only valid for DDR4-3200
-    /// Local overhead for handing a message to the DIMM link controller.
-    dimm_to_rank_latency: usize,
-    edge_chunks: Vec<(u64, u64)>,
+    timing: ProcessorTimingModel,
+    edge_chunks: Vec<(Address, u64)>,
     edge_chunk_cursor: (usize, u64),
+    prefetcher: Option<Prefetcher>,
+    /// Outstanding demand misses of `Load` work, `None` for a blocking cache
+    mshrs: Option<MissStatusHoldingRegisters<Address>>,
+    mshr_full_stalls: usize,
+    messaging: MessagingOptions,
+    /// Mark sense of the current collection, flipped when termination is
+    /// detected
+    mark_sense: u8,
+    /// Data messages sent and read, for termination detection
+    data_sent: usize,
+    data_received: usize,
+    /// Set by the termination broadcast
+    terminated: bool,
+    /// Only present on the coordinator with `--distributed-termination`
+    termination: Option<TerminationDetector>,
+    /// Last tick spent on `Mark`, `Load` or `ContinueScan` work
+    last_trace_tick: usize,
+    /// Sample the queue depths every this many ticks, 0 to disable
+    queue_sample_interval: usize,
+    /// `(tick, pending work items, inbox messages)`
+    queue_samples: Vec<(usize, usize, usize)>,
+    /// Message refused by a full link buffer; the processor stalls until the
+    /// network takes it
+    blocked_message: Option<NMPMessage>,
+    link_stall_ticks: usize,
+    /// Remote `Load` targets that rode along in an existing message
+    messages_saved: usize,
+    /// Messages handled by an inbox read that was already paid for
+    inbox_reads_saved: usize,
+    /// Inbox reads that went ahead of queued work, see `--inbox-priority`
+    priority_inbox_reads: usize,
+    /// Whether to work on memory of the other ranks of the DIMM through the
+    /// shared cache instead of sending messages to them
+    dimm_sharing: bool,
+    /// Marks and loads of memory owned by another rank of the DIMM done here
+    dimm_local_accesses: usize,
+    /// Set while fast-forwarding between the detailed windows of a sampled
+    /// simulation
+    fast_forward: bool,
+    /// Line and block mark tables written when marking, if enabled
+    side_marks: Option<SideMarks>,
+    /// Bitmap marked instead of the headers, with `--private-marks`
+    private_marks: Option<PrivateMarks>,
+    /// Where TIBs are loaded from before scanning, with `--tib-fetch`
+    tib_fetch: Option<TibFetch>,
+    /// Idle, sleeping and waking ticks, with `--sleep-after`
+    power: Option<PowerStates>,
+    /// Streams the slots of big object arrays, with `--scan-engine`
+    scan_engine: Option<ScanEngine>,
+    /// Bounds the work queue, with `--mark-queue`
+    mark_queue: Option<MarkQueue>,
+    /// Queues of the tenants and what they got, with `--tenant`, which
+    /// cannot be checkpointed
+    #[serde(skip)]
+    tenancy: Option<Tenancy>,
+    /// Frames and root slots of `--root-stacks` scanned here
+    root_frames: usize,
+    root_slots: usize,
+    /// Last tick spent on `ScanFrame` work
+    root_scan_end: usize,
+    /// Ticks before this are in the spans written to `--event-log`
+    logged_ticks: usize,
+}
+
+/// Link-protocol knobs of an NMP processor.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct MessagingOptions {
+    /// Maximum number of messages handled per `ReadInbox`
+    inbox_batch: usize,
+    /// Inbox length at which `ReadInbox` goes ahead of the queued work, 0
+    /// to only read the inbox when there is no other work
+    inbox_priority: usize,
+    /// Maximum number of `Load` targets per outgoing message
+    coalesce_loads: usize,
+}
+
+impl MessagingOptions {
+    fn is_default(&self) -> bool {
+        self.inbox_batch <= 1 && self.coalesce_loads <= 1 && self.inbox_priority == 0
+    }
 }
 
 impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
     fn new(
         id: usize,
-        rank_option: DDR4RankOption,
-        dimm_to_rank_latency: usize,
-        page_size: PageSize,
+        cache: SetAssociativeCache,
+        timing: ProcessorTimingModel,
+        prefetcher: Option<Prefetcher>,
+        mshrs: usize,
+        messaging: MessagingOptions,
     ) -> Self {
+        assert!(
+            messaging.inbox_batch > 0,
+            "Inbox batch must be at least one"
+        );
         NMPProcessor {
             id,
             busy_ticks: 0,
@@ -433,30 +1591,164 @@ impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
             inbox: vec![],
             works: VecDeque::new(),
             ticks: 0,
-            // 32 KB
-            cache: SetAssociativeCache::new(64, 8, rank_option, page_size),
+            cache,
             work_count: HashMap::new(),
             idle_ranges: vec![],
             idle_start: None,
             frequency_ghz: 1.6,
             idle_readinbox_ticks: 0,
-            dimm_to_rank_latency,
+            timing,
             edge_chunks: vec![],
             edge_chunk_cursor: (0, 0),
+            prefetcher,
+            mshrs: (mshrs > 0).then(|| MissStatusHoldingRegisters::new(mshrs)),
+            mshr_full_stalls: 0,
+            messaging,
+            mark_sense: 1,
+            data_sent: 0,
+            data_received: 0,
+            terminated: false,
+            termination: None,
+            last_trace_tick: 0,
+            queue_sample_interval: 0,
+            queue_samples: vec![],
+            blocked_message: None,
+            link_stall_ticks: 0,
+            messages_saved: 0,
+            inbox_reads_saved: 0,
+            priority_inbox_reads: 0,
+            dimm_sharing: false,
+            dimm_local_accesses: 0,
+            fast_forward: false,
+            side_marks: None,
+            private_marks: None,
+            tib_fetch: None,
+            power: None,
+            scan_engine: None,
+            mark_queue: None,
+            tenancy: None,
+            root_frames: 0,
+            root_slots: 0,
+            root_scan_end: 0,
+            logged_ticks: 0,
         }
     }
 
     fn locally_done(&self) -> bool {
-        self.works.is_empty() && self.inbox.is_empty()
+        self.works.is_empty()
+            && self.inbox.is_empty()
+            && self.blocked_message.is_none()
+            && self.mshrs.as_ref().is_none_or(|m| m.is_empty())
+            && self.scan_engine.as_ref().is_none_or(|s| s.is_empty())
+            && self.mark_queue.as_ref().is_none_or(|q| q.is_empty())
+            && self.tenancy.as_ref().is_none_or(|t| t.pending() == 0)
+    }
+
+    /// What the processor has left to do and is waiting on, for telling
+    /// why a simulation diverged.
+    fn describe(&self) -> String {
+        let mut s = format!(
+            "{}: {} work items (next {:?}), {} inbox messages, {} objects marked, last traced at tick {}",
+            RankId(self.id as u8),
+            self.works.len(),
+            self.works.front(),
+            self.inbox.len(),
+            self.marked_objects,
+            self.last_trace_tick
+        );
+        if let Some(tenancy) = &self.tenancy {
+            s += &format!(", {} tenant work items", tenancy.pending());
+        }
+        if let Some(msg) = &self.blocked_message {
+            s += &format!(", blocked sending {:?}", msg);
+        }
+        if let Some(mshrs) = self.mshrs.as_ref().filter(|m| !m.is_empty()) {
+            s += &format!(
+                ", {} outstanding misses, the first ready at tick {}",
+                mshrs.outstanding(),
+                mshrs.earliest_ready().unwrap()
+            );
+        }
+        if self.scan_engine.as_ref().is_some_and(|e| !e.is_empty()) {
+            s += ", scan engine streaming";
+        }
+        if self.mark_queue.as_ref().is_some_and(|q| !q.is_empty()) {
+            s += ", overflowed work left";
+        }
+        if self.terminated {
+            s += ", terminated";
+        }
+        s
     }
 
     fn to_thread_name_event(&self) -> TracingEvent {
         TracingEvent::new_threadname_event(0, self.id as u32, RankId(self.id as u8).to_string())
     }
 
+    /// Records the queue depths if this tick is a sampling point.
+    fn sample_queues(&mut self) {
+        if self.queue_sample_interval == 0 || !self.ticks.is_multiple_of(self.queue_sample_interval)
+        {
+            return;
+        }
+        // A pending stall is the tail of the current work item, not more work
+        let works = self
+            .works
+            .iter()
+            .filter(|w| !matches!(w, NMPProcessorWork::Stall(_)))
+            .count()
+            + self.tenancy.as_ref().map_or(0, |t| t.pending());
+        self.queue_samples
+            .push((self.ticks, works, self.inbox.len()));
+    }
+
+    fn queue_depth_events(&self) -> impl Iterator<Item = TracingEvent> + '_ {
+        let name = format!("{} queue depth", RankId(self.id as u8));
+        self.queue_samples.iter().map(move |&(tick, works, inbox)| {
+            let mut values = HashMap::new();
+            values.insert("works".to_string(), works.into());
+            values.insert("inbox".to_string(), inbox.into());
+            TracingEvent::new_counter_event(
+                0,
+                name.clone(),
+                ticks_to_us(tick as u64, self.frequency_ghz),
+                values,
+            )
+        })
+    }
+
+    /// Writes the idle ranges and queue samples recorded since the last
+    /// call to `log` and forgets them, with the busy spans in between.
+    fn log_events(&mut self, log: &mut EventLog, last: bool) -> std::io::Result<()> {
+        let tid = self.id as u32;
+        log.thread(tid, &RankId(self.id as u8).to_string())?;
+        let name = format!("{} queue depth", RankId(self.id as u8));
+        for (tick, works, inbox) in self.queue_samples.drain(..) {
+            let values = [("works", works as u64), ("inbox", inbox as u64)];
+            log.counter(tid, &name, tick as u64, &values)?;
+        }
+        let mut idle_ranges = std::mem::take(&mut self.idle_ranges);
+        if let (true, Some(start)) = (last, self.idle_start) {
+            idle_ranges.push((start, self.ticks));
+        }
+        for (begin, end) in idle_ranges {
+            if begin > self.logged_ticks {
+                log.span(tid, "busy", self.logged_ticks as u64, begin as u64 - 1)?;
+            }
+            log.span(tid, "idle", begin as u64, end as u64)?;
+            self.logged_ticks = end + 1;
+        }
+        if last && self.logged_ticks < self.ticks {
+            log.span(tid, "busy", self.logged_ticks as u64, self.ticks as u64)?;
+            self.logged_ticks = self.ticks;
+        }
+        Ok(())
+    }
+
     fn events(&self) -> Vec<TracingEvent> {
         let mut events = Vec::new();
         events.push(self.to_thread_name_event());
+        events.extend(self.queue_depth_events());
         let mut timestamp_cursor: usize = 0;
         let mut idle_ranges = self.idle_ranges.clone();
         if let Some(start) = self.idle_start {
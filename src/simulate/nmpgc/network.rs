@@ -1,35 +1,110 @@
 use super::super::memory::DimmId;
 use super::topology::Topology;
 use super::work::NMPMessage;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::rc::Rc;
+
+type Link = (DimmId, DimmId);
 
 /// A message in transit through the network.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct InFlightMessage {
     message: NMPMessage,
     /// Full route of directed links to traverse.
     route: Vec<(DimmId, DimmId)>,
     /// Index of the current hop in `route`.
     current_hop: usize,
-    /// Cycles remaining on the current hop.
+    /// Cycles remaining on the current hop. Stays at zero while the message
+    /// waits for a credit on its next link.
     remaining_hop_latency: usize,
+    /// For a copy of a broadcast, the tree it is spreading along. The copy
+    /// only covers one link, and forks into the children of the DIMM it
+    /// arrives at.
+    broadcast_tree: Option<Rc<[Link]>>,
 }
 
 /// Per-directed-link statistics.
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct DirectedLinkStats {
     /// Total messages that have traversed this directed link.
     messages_forwarded: usize,
+    /// Broadcast copies that have traversed this directed link, not
+    /// included in `messages_forwarded`.
+    broadcast_forwarded: usize,
+    /// Total payload words carried, more than the message count once
+    /// messages are coalesced.
+    words_forwarded: usize,
+    /// Total flits serialized onto this link.
+    flits_forwarded: usize,
+    /// Message-ticks spent waiting for a free buffer slot on this link,
+    /// either at the sender or at an intermediate DIMM.
+    blocked_ticks: usize,
 }
 
-/// The network fabric that models hop-by-hop message forwarding with
-/// per-link bandwidth tracking.
-pub(super) const PER_HOP_LATENCY: usize = 4;
 pub(super) const DIMM_TO_RANK_LATENCY: usize = 2;
+/// Messages carry 64-bit addresses
+pub(super) const MESSAGE_WORD_BYTES: usize = 8;
+
+/// Link parameters shared by all links of a topology.
+///
+/// A hop takes one tick per flit to serialize the message onto the link,
+/// followed by `link_latency` ticks of wire and router delay. The defaults
+/// give the 4-tick hop of a single-word message the model always had.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(super) struct NetworkConfig {
+    /// Wire and router delay of a hop, after the last flit has been sent
+    pub(super) link_latency: usize,
+    /// Bytes a link moves per tick, i.e. the flit size
+    pub(super) link_width: usize,
+    /// Buffer slots per directed link, `None` for unbounded buffers. A
+    /// message holds a slot (credit) of a link from the moment it enters the
+    /// link until it has moved on to the next one or has been delivered.
+    pub(super) link_capacity: Option<usize>,
+}
 
-#[derive(Debug)]
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            link_latency: 0,
+            link_width: 2,
+            link_capacity: None,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Flits of a message with `words` payload words.
+    pub(super) fn flits(&self, words: usize) -> usize {
+        (words * MESSAGE_WORD_BYTES).div_ceil(self.link_width)
+    }
+
+    /// Ticks a message with `words` payload words spends on each hop.
+    pub(super) fn hop_latency(&self, words: usize) -> usize {
+        self.link_latency + self.flits(words)
+    }
+}
+
+/// The network fabric that models hop-by-hop message forwarding with
+/// per-link bandwidth tracking.
+#[derive(Debug, Serialize, Deserialize)]
 pub(super) struct Network {
     in_flight: Vec<InFlightMessage>,
+    config: NetworkConfig,
+    /// Keyed by directed link, `config` but for the links a topology file
+    /// gives parameters of their own
+    link_configs: HashMap<(DimmId, DimmId), NetworkConfig>,
+    /// Broadcast copies waiting to enter their link.
+    pending_broadcast_hops: Vec<(Link, NMPMessage, Rc<[Link]>)>,
+    /// Broadcast copies that arrived at a DIMM in the last tick.
+    broadcast_arrivals: Vec<(DimmId, NMPMessage)>,
+    broadcasts: usize,
+    /// Unicast messages that entered the network, and the hops of their
+    /// routes
+    injected: usize,
+    injected_hops: usize,
+    /// Messages currently holding a slot of each directed link.
+    link_occupancy: HashMap<(DimmId, DimmId), usize>,
     /// Keyed by directed link `(from_dimm, to_dimm)`.
     link_stats: HashMap<(DimmId, DimmId), DirectedLinkStats>,
 
@@ -46,18 +121,42 @@ pub(super) struct LinkBandwidthStats {
     pub(super) from_dimm: DimmId,
     pub(super) to_dimm: DimmId,
     pub(super) messages_forwarded: usize,
+    pub(super) broadcast_forwarded: usize,
+    pub(super) words_forwarded: usize,
+    pub(super) flits_forwarded: usize,
+    pub(super) blocked_ticks: usize,
     /// Peak flits (message fragments) in a single tick on this directed link.
     pub(super) peak_flits_per_tick: usize,
+    /// Bytes of a flit on this link
+    pub(super) link_width: usize,
 }
 
 impl Network {
-    pub(super) fn new(topology: &dyn Topology) -> Self {
+    /// Creates the fabric for `topology`. With a finite `link_capacity`, the
+    /// routes must not wait for each other in a circle (see
+    /// `Topology::has_cyclic_routes`), or every link of the circle can fill
+    /// up with messages waiting for the next one.
+    pub(super) fn new(topology: &dyn Topology, config: NetworkConfig) -> Self {
+        assert_ne!(
+            config.link_capacity,
+            Some(0),
+            "Link buffers need at least one slot"
+        );
+        assert!(config.link_width > 0, "Links must be at least a byte wide");
         let mut link_stats = HashMap::new();
         let mut current_tick_flits = HashMap::new();
         let mut peak_tick_flits = HashMap::new();
+        let mut link_configs = HashMap::new();
 
         // Register both directions for each undirected link.
         for (a, b) in topology.get_links() {
+            let link_config = topology.link_config(a, b, config);
+            assert!(
+                link_config.link_width > 0,
+                "Links must be at least a byte wide"
+            );
+            link_configs.insert((a, b), link_config);
+            link_configs.insert((b, a), link_config);
             link_stats.insert((a, b), DirectedLinkStats::default());
             link_stats.insert((b, a), DirectedLinkStats::default());
             current_tick_flits.insert((a, b), 0);
@@ -66,8 +165,17 @@ impl Network {
             peak_tick_flits.insert((b, a), 0);
         }
 
+        let link_occupancy = link_stats.keys().map(|&link| (link, 0)).collect();
         Network {
             in_flight: Vec::new(),
+            config,
+            link_configs,
+            pending_broadcast_hops: Vec::new(),
+            broadcast_arrivals: Vec::new(),
+            broadcasts: 0,
+            injected: 0,
+            injected_hops: 0,
+            link_occupancy,
             link_stats,
 
             current_tick_flits,
@@ -76,23 +184,99 @@ impl Network {
     }
 
     /// Inject a new message into the network. The route must be non-empty.
-    pub(super) fn inject(&mut self, msg: NMPMessage, route: Vec<(DimmId, DimmId)>) {
+    /// Hands the message back if the first link has no credit left, in which
+    /// case the sender has to retry later.
+    pub(super) fn inject(
+        &mut self,
+        msg: NMPMessage,
+        route: Vec<(DimmId, DimmId)>,
+    ) -> Result<(), NMPMessage> {
         debug_assert!(!route.is_empty());
+        if !self.has_credit(route[0]) {
+            self.link_stats.get_mut(&route[0]).unwrap().blocked_ticks += 1;
+            return Err(msg);
+        }
         // Record the first link traversal immediately.
-        self.record_link_traversal(route[0]);
+        let words = msg.words();
+        self.record_link_traversal(route[0], words, false);
+        *self.link_occupancy.get_mut(&route[0]).unwrap() += 1;
+        self.injected += 1;
+        self.injected_hops += route.len();
+        let remaining_hop_latency = self.link_configs[&route[0]].hop_latency(words);
         self.in_flight.push(InFlightMessage {
             message: msg,
             route,
             current_hop: 0,
-            remaining_hop_latency: PER_HOP_LATENCY,
+            remaining_hop_latency,
+            broadcast_tree: None,
         });
+        Ok(())
+    }
+
+    /// Sends a copy of `msg` to every DIMM other than `root` along `tree`
+    /// (see `Topology::broadcast_tree`). Copies are forwarded as soon as
+    /// they arrive at a DIMM, and show up in `take_broadcast_arrivals`.
+    /// Unlike `inject`, a broadcast is never refused: copies that find their
+    /// link full wait for a credit inside the network.
+    pub(super) fn broadcast(&mut self, msg: NMPMessage, root: DimmId, tree: Vec<Link>) {
+        self.broadcasts += 1;
+        let tree: Rc<[Link]> = tree.into();
+        self.fork_broadcast(root, &msg, &tree);
+    }
+
+    fn fork_broadcast(&mut self, at: DimmId, msg: &NMPMessage, tree: &Rc<[Link]>) {
+        for &link in tree.iter().filter(|(from, _)| *from == at) {
+            self.pending_broadcast_hops
+                .push((link, msg.clone(), tree.clone()));
+        }
     }
 
-    fn record_link_traversal(&mut self, link: (DimmId, DimmId)) {
-        self.link_stats
+    /// Moves broadcast copies that have a credit onto their link.
+    fn launch_broadcast_hops(&mut self) {
+        let pending = std::mem::take(&mut self.pending_broadcast_hops);
+        for (link, msg, tree) in pending {
+            if !self.has_credit(link) {
+                self.link_stats.get_mut(&link).unwrap().blocked_ticks += 1;
+                self.pending_broadcast_hops.push((link, msg, tree));
+                continue;
+            }
+            let words = msg.words();
+            self.record_link_traversal(link, words, true);
+            *self.link_occupancy.get_mut(&link).unwrap() += 1;
+            self.in_flight.push(InFlightMessage {
+                message: msg,
+                route: vec![link],
+                current_hop: 0,
+                remaining_hop_latency: self.link_configs[&link].hop_latency(words),
+                broadcast_tree: Some(tree),
+            });
+        }
+    }
+
+    /// Broadcast copies that reached a DIMM in the last tick, to be handed
+    /// to the ranks of that DIMM.
+    pub(super) fn take_broadcast_arrivals(&mut self) -> Vec<(DimmId, NMPMessage)> {
+        std::mem::take(&mut self.broadcast_arrivals)
+    }
+
+    fn has_credit(&self, link: (DimmId, DimmId)) -> bool {
+        self.config
+            .link_capacity
+            .is_none_or(|capacity| self.link_occupancy[&link] < capacity)
+    }
+
+    fn record_link_traversal(&mut self, link: Link, words: usize, broadcast: bool) {
+        let stats = self
+            .link_stats
             .get_mut(&link)
-            .expect("link not registered in topology")
-            .messages_forwarded += 1;
+            .expect("link not registered in topology");
+        if broadcast {
+            stats.broadcast_forwarded += 1;
+        } else {
+            stats.messages_forwarded += 1;
+        }
+        stats.words_forwarded += words;
+        stats.flits_forwarded += self.link_configs[&link].flits(words);
     }
 
     /// Advance all in-flight messages by one cycle.
@@ -100,13 +284,18 @@ impl Network {
     /// The recipient is responsible for adding the DIMM-to-rank latency
     /// stall when reading from its inbox
     pub(super) fn tick(&mut self) -> Vec<NMPMessage> {
+        self.launch_broadcast_hops();
         // Calculate flits traversing each link in this tick.
         for count in self.current_tick_flits.values_mut() {
             *count = 0;
         }
         for msg in &self.in_flight {
+            // Only messages still serializing put a flit on the wire; the
+            // rest are in the wire/router delay or waiting for a credit.
             let link = msg.route[msg.current_hop];
-            *self.current_tick_flits.get_mut(&link).unwrap() += 1;
+            if msg.remaining_hop_latency > self.link_configs[&link].link_latency {
+                *self.current_tick_flits.get_mut(&link).unwrap() += 1;
+            }
         }
 
         // Flush per-tick counts: update peaks.
@@ -120,32 +309,86 @@ impl Network {
         let mut delivered = Vec::new();
         let mut i = 0;
         while i < self.in_flight.len() {
-            self.in_flight[i].remaining_hop_latency -= 1;
-            if self.in_flight[i].remaining_hop_latency == 0 {
-                // Current hop complete — advance cursor.
-                self.in_flight[i].current_hop += 1;
-                if self.in_flight[i].current_hop >= self.in_flight[i].route.len() {
-                    // Message has arrived at the destination DIMM.
-                    let msg = self.in_flight.swap_remove(i);
-                    delivered.push(msg.message);
-                    // Don't increment i; swap_remove moved the last element here.
-                } else {
-                    // Move to the next hop.
-                    let next_link = self.in_flight[i].route[self.in_flight[i].current_hop];
-                    self.record_link_traversal(next_link);
-                    self.in_flight[i].remaining_hop_latency = PER_HOP_LATENCY;
-                    i += 1;
+            let msg = &mut self.in_flight[i];
+            msg.remaining_hop_latency = msg.remaining_hop_latency.saturating_sub(1);
+            if msg.remaining_hop_latency > 0 {
+                i += 1;
+                continue;
+            }
+            // Current hop complete
+            let link = msg.route[msg.current_hop];
+            if msg.current_hop + 1 >= msg.route.len() {
+                // Message has arrived at the destination DIMM.
+                *self.link_occupancy.get_mut(&link).unwrap() -= 1;
+                let msg = self.in_flight.swap_remove(i);
+                match msg.broadcast_tree {
+                    Some(tree) => {
+                        self.fork_broadcast(link.1, &msg.message, &tree);
+                        self.broadcast_arrivals.push((link.1, msg.message));
+                    }
+                    None => delivered.push(msg.message),
                 }
-            } else {
+                // Don't increment i; swap_remove moved the last element here.
+                continue;
+            }
+            let next_link = msg.route[msg.current_hop + 1];
+            if !self.has_credit(next_link) {
+                // Wait at the intermediate DIMM, still holding the slot of
+                // the current link, which back-pressures its upstream.
+                self.link_stats.get_mut(&next_link).unwrap().blocked_ticks += 1;
                 i += 1;
+                continue;
             }
+            // Move to the next hop.
+            let msg = &mut self.in_flight[i];
+            msg.current_hop += 1;
+            let words = msg.message.words();
+            msg.remaining_hop_latency = self.link_configs[&next_link].hop_latency(words);
+            *self.link_occupancy.get_mut(&link).unwrap() -= 1;
+            *self.link_occupancy.get_mut(&next_link).unwrap() += 1;
+            self.record_link_traversal(next_link, words, false);
+            i += 1;
         }
         delivered
     }
 
+    pub(super) fn config(&self) -> &NetworkConfig {
+        &self.config
+    }
+
+    /// Number of broadcasts started, each of which may cross many links.
+    pub(super) fn broadcasts(&self) -> usize {
+        self.broadcasts
+    }
+
+    /// Unicast messages that entered the network, and their mean hops
+    pub(super) fn hops(&self) -> (usize, f64) {
+        (
+            self.injected,
+            self.injected_hops as f64 / self.injected.max(1) as f64,
+        )
+    }
+
     /// Returns true if there are no messages in flight.
+    /// What is in flight and which links are occupied, for diagnostics.
+    pub(super) fn describe(&self) -> String {
+        let mut occupied: Vec<_> = self
+            .link_occupancy
+            .iter()
+            .filter(|(_, &n)| n > 0)
+            .map(|((from, to), n)| format!("{}->{}: {}", from, to, n))
+            .collect();
+        occupied.sort();
+        format!(
+            "{} messages in flight, {} broadcast hops pending, link occupancy [{}]",
+            self.in_flight.len(),
+            self.pending_broadcast_hops.len(),
+            occupied.join(", ")
+        )
+    }
+
     pub(super) fn is_empty(&self) -> bool {
-        self.in_flight.is_empty()
+        self.in_flight.is_empty() && self.pending_broadcast_hops.is_empty()
     }
 
     /// Returns per-directed-link bandwidth statistics.
@@ -157,7 +400,12 @@ impl Network {
                 from_dimm: from,
                 to_dimm: to,
                 messages_forwarded: link.messages_forwarded,
+                broadcast_forwarded: link.broadcast_forwarded,
+                words_forwarded: link.words_forwarded,
+                flits_forwarded: link.flits_forwarded,
+                blocked_ticks: link.blocked_ticks,
                 peak_flits_per_tick: *self.peak_tick_flits.get(&(from, to)).unwrap_or(&0),
+                link_width: self.link_configs[&(from, to)].link_width,
             })
             .collect();
         stats.sort_by_key(|s| (s.from_dimm, s.to_dimm));
@@ -171,25 +419,26 @@ mod tests {
     use super::super::topology::Topology;
     use super::super::work::NMPMessage;
     use super::*;
+    use crate::{Address, ObjectReference};
 
     fn make_msg(recipient: usize) -> NMPMessage {
-        NMPMessage::new_mark(recipient, 0x1000)
+        NMPMessage::new_mark(recipient, ObjectReference::from_raw_unchecked(0x1000))
     }
 
     #[test]
     fn test_network_single_hop_delivery() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
 
         // DIMM 0 -> DIMM 2: single hop
         let route = topo.get_route(DimmId(0), DimmId(2));
         assert_eq!(route.len(), 1);
 
-        net.inject(make_msg(2), route);
+        net.inject(make_msg(2), route).unwrap();
         assert!(!net.is_empty());
 
-        // Tick for per_hop_latency cycles
-        let hop = PER_HOP_LATENCY;
+        // Tick for one hop latency
+        let hop = NetworkConfig::default().hop_latency(1);
         for tick in 0..hop {
             let delivered = net.tick();
             if tick < hop - 1 {
@@ -208,15 +457,15 @@ mod tests {
     #[test]
     fn test_network_multi_hop_delivery() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
 
         // DIMM 0 -> DIMM 3: 3 hops (0->2->1->3)
         let route = topo.get_route(DimmId(0), DimmId(3));
         assert_eq!(route.len(), 3);
 
-        net.inject(make_msg(3), route);
+        net.inject(make_msg(3), route).unwrap();
 
-        let hop = PER_HOP_LATENCY;
+        let hop = NetworkConfig::default().hop_latency(1);
         let total_ticks = 3 * hop;
         let mut delivered_count = 0;
         for _ in 0..total_ticks {
@@ -230,13 +479,13 @@ mod tests {
     #[test]
     fn test_network_link_stats() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
 
         // Send from DIMM 0 -> DIMM 3 (3 hops: 0->2, 2->1, 1->3)
         let route = topo.get_route(DimmId(0), DimmId(3));
-        net.inject(make_msg(3), route);
+        net.inject(make_msg(3), route).unwrap();
 
-        let hop = PER_HOP_LATENCY;
+        let hop = NetworkConfig::default().hop_latency(1);
         for _ in 0..(3 * hop) {
             net.tick();
         }
@@ -269,15 +518,15 @@ mod tests {
     #[test]
     fn test_network_peak_bandwidth() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
 
         // Inject 3 messages on the same single-hop link in the same tick.
         for _ in 0..3 {
             let route = topo.get_route(DimmId(0), DimmId(2));
-            net.inject(make_msg(2), route);
+            net.inject(make_msg(2), route).unwrap();
         }
 
-        let hop = PER_HOP_LATENCY;
+        let hop = NetworkConfig::default().hop_latency(1);
         for _ in 0..hop {
             net.tick();
         }
@@ -296,7 +545,7 @@ mod tests {
     #[test]
     fn test_network_empty_tick() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
         assert!(net.is_empty());
         let delivered = net.tick();
         assert!(delivered.is_empty());
@@ -306,17 +555,17 @@ mod tests {
     #[test]
     fn test_network_concurrent_overlapping_traffic() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
 
         // Two messages crossing on link (2,1)/(1,2):
         // Message A: DIMM 0 -> DIMM 3 (route: 0->2, 2->1, 1->3)
         // Message B: DIMM 3 -> DIMM 0 (route: 3->1, 1->2, 2->0)
         let route_a = topo.get_route(DimmId(0), DimmId(3));
         let route_b = topo.get_route(DimmId(3), DimmId(0));
-        net.inject(make_msg(3), route_a);
-        net.inject(make_msg(0), route_b);
+        net.inject(make_msg(3), route_a).unwrap();
+        net.inject(make_msg(0), route_b).unwrap();
 
-        let hop = PER_HOP_LATENCY;
+        let hop = NetworkConfig::default().hop_latency(1);
         // Both messages are 3 hops, need 3 * hop ticks
         let mut delivered = Vec::new();
         for _ in 0..(3 * hop) {
@@ -343,18 +592,18 @@ mod tests {
     #[test]
     fn test_network_pipelined_flits() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
 
         // Inject first message at tick 0
         let route1 = topo.get_route(DimmId(0), DimmId(2));
-        net.inject(make_msg(2), route1);
+        net.inject(make_msg(2), route1).unwrap();
 
         // Tick once
         net.tick();
 
         // Inject second message at tick 1
         let route2 = topo.get_route(DimmId(0), DimmId(2));
-        net.inject(make_msg(2), route2);
+        net.inject(make_msg(2), route2).unwrap();
 
         // Tick for the remaining time
         while !net.is_empty() {
@@ -368,21 +617,21 @@ mod tests {
             .unwrap();
         assert_eq!(link.messages_forwarded, 2);
         // The peak flits per tick should be 2, because msg1 and msg2 overlap
-        // for `PER_HOP_LATENCY - 1` ticks
+        // for `hop - 1` ticks
         assert_eq!(link.peak_flits_per_tick, 2);
     }
 
     #[test]
     fn test_network_separated_flits() {
         let topo = LineTopology::new();
-        let mut net = Network::new(&topo);
+        let mut net = Network::new(&topo, NetworkConfig::default());
 
         // Inject first message at tick 0
         let route1 = topo.get_route(DimmId(0), DimmId(2));
-        net.inject(make_msg(2), route1);
+        net.inject(make_msg(2), route1).unwrap();
 
         // Tick enough times for the first message to completely clear the link
-        let hop = PER_HOP_LATENCY;
+        let hop = NetworkConfig::default().hop_latency(1);
         for _ in 0..hop {
             net.tick();
         }
@@ -391,7 +640,7 @@ mod tests {
 
         // Inject second message
         let route2 = topo.get_route(DimmId(0), DimmId(2));
-        net.inject(make_msg(2), route2);
+        net.inject(make_msg(2), route2).unwrap();
 
         while !net.is_empty() {
             net.tick();
@@ -406,4 +655,126 @@ mod tests {
         // Since they do not overlap in time, the peak flits per tick should just be 1.
         assert_eq!(link.peak_flits_per_tick, 1);
     }
+
+    #[test]
+    fn test_network_credit_flow_control() {
+        let topo = LineTopology::new();
+        let mut net = Network::new(
+            &topo,
+            NetworkConfig {
+                link_capacity: Some(1),
+                ..Default::default()
+            },
+        );
+
+        // DIMM 0 -> DIMM 3 (0->2, 2->1, 1->3), one buffer slot per link
+        net.inject(make_msg(3), topo.get_route(DimmId(0), DimmId(3)))
+            .unwrap();
+        // Occupies link 2->1 when the first message wants to enter it
+        net.inject(make_msg(1), topo.get_route(DimmId(2), DimmId(1)))
+            .unwrap();
+        // Link 0->2 is full, so the sender gets the message back.
+        let rejected = net
+            .inject(make_msg(2), topo.get_route(DimmId(0), DimmId(2)))
+            .unwrap_err();
+        assert_eq!(rejected.recipient, 2);
+
+        let hop = NetworkConfig::default().hop_latency(1);
+        let mut delivered = 0;
+        let mut ticks = 0;
+        while !net.is_empty() {
+            delivered += net.tick().len();
+            ticks += 1;
+        }
+        assert_eq!(delivered, 2);
+        // The first message waited one tick at DIMM 2 for the slot of 2->1.
+        assert_eq!(ticks, 3 * hop + 1);
+
+        let stats = net.bandwidth_stats();
+        let blocked = |from, to| {
+            stats
+                .iter()
+                .find(|s| s.from_dimm == DimmId(from) && s.to_dimm == DimmId(to))
+                .unwrap()
+                .blocked_ticks
+        };
+        assert_eq!(blocked(0, 2), 1);
+        assert_eq!(blocked(2, 1), 1);
+        assert_eq!(blocked(1, 3), 0);
+    }
+
+    #[test]
+    fn test_network_multi_flit_serialization() {
+        let topo = LineTopology::new();
+        let config = NetworkConfig {
+            link_latency: 3,
+            link_width: 4,
+            link_capacity: None,
+        };
+        let mut net = Network::new(&topo, config);
+
+        // Three words over a 4 B link: 6 flits, then 3 ticks on the wire
+        let mut msg = NMPMessage::new_load(2, Address::from_raw(0x1000));
+        assert!(msg.coalesce_load(Address::from_raw(0x1008), 8));
+        assert!(msg.coalesce_load(Address::from_raw(0x1010), 8));
+        assert_eq!(msg.words(), 3);
+        assert_eq!(config.hop_latency(3), 9);
+        net.inject(msg, topo.get_route(DimmId(0), DimmId(2)))
+            .unwrap();
+        net.inject(make_msg(2), topo.get_route(DimmId(0), DimmId(2)))
+            .unwrap();
+
+        let mut ticks = 0;
+        let mut delivered = 0;
+        while !net.is_empty() {
+            ticks += 1;
+            let arrived = net.tick().len();
+            if ticks == config.hop_latency(1) {
+                assert_eq!(arrived, 1, "single-word message arrives first");
+            }
+            delivered += arrived;
+        }
+        assert_eq!(delivered, 2);
+        assert_eq!(ticks, 9);
+
+        let stats = net.bandwidth_stats();
+        let link = stats
+            .iter()
+            .find(|s| s.from_dimm == DimmId(0) && s.to_dimm == DimmId(2))
+            .unwrap();
+        assert_eq!(link.words_forwarded, 4);
+        assert_eq!(link.flits_forwarded, 8);
+        // Both messages serialize during the first two ticks
+        assert_eq!(link.peak_flits_per_tick, 2);
+    }
+
+    #[test]
+    fn test_network_broadcast() {
+        let topo = LineTopology::new();
+        let mut net = Network::new(&topo, NetworkConfig::default());
+
+        // Line 0-2-1-3: DIMM 2 is one hop away, DIMM 1 two, DIMM 3 three
+        net.broadcast(make_msg(0), DimmId(0), topo.broadcast_tree(DimmId(0)));
+        let hop = NetworkConfig::default().hop_latency(1);
+        let mut arrivals = vec![];
+        let mut ticks = 0;
+        while !net.is_empty() {
+            assert!(net.tick().is_empty(), "broadcasts are not unicast");
+            ticks += 1;
+            for (dimm, _) in net.take_broadcast_arrivals() {
+                arrivals.push((dimm, ticks));
+            }
+        }
+        assert_eq!(
+            arrivals,
+            vec![(DimmId(2), hop), (DimmId(1), 2 * hop), (DimmId(3), 3 * hop)]
+        );
+        assert_eq!(net.broadcasts(), 1);
+        for link in net.bandwidth_stats() {
+            assert_eq!(link.messages_forwarded, 0);
+            let outward = topo.position_of[link.from_dimm.0 as usize]
+                < topo.position_of[link.to_dimm.0 as usize];
+            assert_eq!(link.broadcast_forwarded, outward as usize);
+        }
+    }
 }
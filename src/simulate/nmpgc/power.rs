@@ -0,0 +1,154 @@
+//! Power states of the NMP processors (`--sleep-after`). A processor with
+//! nothing to do for `--sleep-after` ticks in a row goes to sleep, and the
+//! next work or message that arrives wakes it up, which takes
+//! `--wake-latency` ticks before it can start. Energy is estimated from the
+//! ticks spent in each state, relative to the power of an active processor,
+//! against the same run with processors that idle instead of sleeping.
+
+use super::*;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct PowerOptions {
+    pub(super) sleep_after: usize,
+    pub(super) wake_latency: usize,
+    /// Power drawn idle and asleep, as fractions of the active power
+    pub(super) idle_power: f64,
+    pub(super) sleep_power: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct PowerStates {
+    sleep_after: usize,
+    wake_latency: usize,
+    /// Idle ticks in a row so far
+    idle_streak: usize,
+    asleep: bool,
+    /// Ticks idle but awake, and asleep
+    pub(super) idle_ticks: usize,
+    pub(super) sleep_ticks: usize,
+    /// Ticks spent waking up, which count as busy
+    pub(super) wake_ticks: usize,
+    pub(super) wakeups: usize,
+}
+
+impl PowerStates {
+    pub(super) fn new(options: &PowerOptions) -> Self {
+        assert!(
+            options.sleep_after > 0,
+            "Sleeping takes at least one idle tick"
+        );
+        PowerStates {
+            sleep_after: options.sleep_after,
+            wake_latency: options.wake_latency.max(1),
+            idle_streak: 0,
+            asleep: false,
+            idle_ticks: 0,
+            sleep_ticks: 0,
+            wake_ticks: 0,
+            wakeups: 0,
+        }
+    }
+
+    /// Counts a tick with nothing to do.
+    pub(super) fn idle(&mut self) {
+        if self.asleep {
+            self.sleep_ticks += 1;
+            return;
+        }
+        self.idle_ticks += 1;
+        self.idle_streak += 1;
+        if self.idle_streak >= self.sleep_after {
+            self.asleep = true;
+        }
+    }
+
+    /// Notes that there is work to do, and returns the ticks it has to wait
+    /// for the processor to wake up, 0 if it is awake.
+    pub(super) fn wake(&mut self) -> usize {
+        self.idle_streak = 0;
+        if !self.asleep {
+            return 0;
+        }
+        self.asleep = false;
+        self.wakeups += 1;
+        self.wake_ticks += self.wake_latency;
+        self.wake_latency
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    pub(super) fn report_power_stats(
+        &self,
+        options: &PowerOptions,
+        stats: &mut HashMap<String, f64>,
+    ) {
+        let states: Vec<&PowerStates> = self
+            .processors
+            .iter()
+            .filter_map(|p| p.power.as_ref())
+            .collect();
+        let total = (self.ticks * states.len()) as f64;
+        let idle: usize = states.iter().map(|s| s.idle_ticks).sum();
+        let sleep: usize = states.iter().map(|s| s.sleep_ticks).sum();
+        let wake: usize = states.iter().map(|s| s.wake_ticks).sum();
+        let wakeups: usize = states.iter().map(|s| s.wakeups).sum();
+        let active = total - (idle + sleep) as f64;
+        // In active-tick units, against idling through the sleeping ticks
+        // without waking up; the longer run that wake-ups cause is not
+        // charged to the baseline.
+        let energy = active + idle as f64 * options.idle_power + sleep as f64 * options.sleep_power;
+        let baseline = active - wake as f64 + (idle + sleep) as f64 * options.idle_power;
+        let saving = 1.0 - energy / baseline.max(1.0);
+        println!(
+            "Power states (aggregate, sleep after {} idle ticks, wake in {}):",
+            options.sleep_after, options.wake_latency
+        );
+        println!(
+            "  Active: {:>7.2}%    Idle: {:>7.2}%    Asleep: {:>7.2}%    Waking: {:>7.2}%    Wake-ups: {:>10}",
+            active * 100.0 / total,
+            idle as f64 * 100.0 / total,
+            sleep as f64 * 100.0 / total,
+            wake as f64 * 100.0 / total,
+            Self::format_thousands(wakeups)
+        );
+        println!(
+            "  Energy: {:.0} active ticks, {:.2}% less than without sleeping (idle at {:.2}, asleep at {:.2} of active power)",
+            energy,
+            saving * 100.0,
+            options.idle_power,
+            options.sleep_power
+        );
+        stats.insert("power.idle_ticks.sum".into(), idle as f64);
+        stats.insert("power.sleep_ticks.sum".into(), sleep as f64);
+        stats.insert("power.wake_ticks.sum".into(), wake as f64);
+        stats.insert("power.wakeups.sum".into(), wakeups as f64);
+        stats.insert("power.energy".into(), energy);
+        stats.insert("power.energy_saving".into(), saving);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_states() {
+        let mut power = PowerStates::new(&PowerOptions {
+            sleep_after: 3,
+            wake_latency: 10,
+            idle_power: 0.5,
+            sleep_power: 0.1,
+        });
+        power.idle();
+        power.idle();
+        // Work before the third idle tick does not wait
+        assert_eq!(power.wake(), 0);
+        for _ in 0..5 {
+            power.idle();
+        }
+        assert_eq!((power.idle_ticks, power.sleep_ticks), (5, 2));
+        assert_eq!(power.wake(), 10);
+        assert_eq!(power.wake(), 0);
+        assert_eq!((power.wakeups, power.wake_ticks), (1, 10));
+    }
+}
@@ -0,0 +1,98 @@
+use crate::simulate::memory::{MissStatusHoldingRegisters, SetAssociativeCache, VirtualAddress};
+use crate::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(super) struct PrefetcherStats {
+    /// Distinct lines handed to the engine by scanned objects
+    pub(super) requested: usize,
+    /// Requests dropped because the lookahead window was full
+    pub(super) dropped: usize,
+    /// Requests whose line was already cached when the engine got to them
+    pub(super) redundant: usize,
+    /// Prefetches that went to DRAM
+    pub(super) issued: usize,
+    /// Ticks in which a request was ready but all MSHRs were occupied
+    pub(super) mshr_full_ticks: usize,
+}
+
+/// A per-processor engine that prefetches the slots of freshly scanned
+/// objects, so that the `Load` work generated by `ContinueScan` later on finds
+/// the line in the cache.
+///
+/// The engine runs at most `depth` slots ahead of the scan, and each prefetch
+/// occupies one of `mshrs` miss-status holding registers until the line
+/// arrives, which bounds how much memory traffic it can have in flight.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Prefetcher {
+    depth: usize,
+    pending: VecDeque<Address>,
+    in_flight: MissStatusHoldingRegisters<Address>,
+    last_requested_line: Option<u64>,
+    /// log2 of the line size of the cache being prefetched into
+    log_line_size: u32,
+    pub(super) stats: PrefetcherStats,
+}
+
+impl Prefetcher {
+    pub(super) fn new(depth: usize, mshrs: usize, line_size: usize) -> Self {
+        assert!(depth > 0, "Prefetch depth must be greater than zero");
+        Prefetcher {
+            depth,
+            pending: VecDeque::with_capacity(depth),
+            in_flight: MissStatusHoldingRegisters::new(mshrs),
+            last_requested_line: None,
+            log_line_size: line_size.trailing_zeros(),
+            stats: PrefetcherStats::default(),
+        }
+    }
+
+    /// Asks the engine to bring in the line holding the slot `addr`.
+    pub(super) fn request(&mut self, addr: Address) {
+        // Slots of an object are contiguous, so collapse consecutive requests
+        // to the same line here rather than wasting window entries on them.
+        let line = addr.as_u64() >> self.log_line_size;
+        if self.last_requested_line == Some(line) {
+            return;
+        }
+        self.last_requested_line = Some(line);
+        self.stats.requested += 1;
+        if self.pending.len() >= self.depth {
+            self.stats.dropped += 1;
+        } else {
+            self.pending.push_back(addr);
+        }
+    }
+
+    /// Retires completed prefetches and issues at most one new one.
+    pub(super) fn tick(&mut self, now: usize, cache: &mut SetAssociativeCache) {
+        for (_, targets) in self.in_flight.retire(now) {
+            for addr in targets {
+                cache.fill_prefetch(VirtualAddress::from(addr));
+            }
+        }
+        while let Some(&addr) = self.pending.front() {
+            if self.in_flight.is_full() {
+                self.stats.mshr_full_ticks += 1;
+                return;
+            }
+            self.pending.pop_front();
+            let line = cache.line_of(VirtualAddress::from(addr));
+            if self.in_flight.merge(line, addr).is_ok() {
+                self.stats.redundant += 1;
+                continue;
+            }
+            match cache.prefetch(VirtualAddress::from(addr)) {
+                Some(latency) => {
+                    self.stats.issued += 1;
+                    self.in_flight.allocate(line, now + latency, addr);
+                    return;
+                }
+                // Looking up a line that is already present does not use
+                // the memory port, so try the next request in the same tick.
+                None => self.stats.redundant += 1,
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Private mark bitmaps (`--private-marks`): instead of writing the mark
+//! byte in the header of an object, which with `--dimm-sharing` is often
+//! memory of another rank, each processor sets a bit in a bitmap of its own,
+//! one bit per word of the heap, and scans the objects it has not marked
+//! itself. The processors cannot see each other's bitmaps, so an object
+//! reached by several of them is marked and scanned by each. When the
+//! closure is over, a union pass ORs every line of each bitmap into the
+//! bitmap of the processor owning the heap it covers, which is when the
+//! marks become visible to the sweep.
+//!
+//! The simulator still marks the headers, without charging for it, to tell
+//! duplicates from first marks and to cross-check the closure.
+
+use super::*;
+use crate::simulate::memory::{DataCache, SetAssociativeCache, VirtualAddress};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// The bitmaps, out of the way of the spaces of the heapdumps and of the
+/// side mark tables
+const PRIVATE_MARK_BASE: u64 = 0x700_0000_0000;
+/// log2 of the heap bytes a byte of bitmap covers, a bit per 8-byte word
+const LOG_BYTES_PER_MARK_BYTE: u32 = 6;
+/// log2 of the bytes of each processor's bitmap, covering 4 TiB of heap
+const LOG_BITMAP_BYTES: u32 = 36;
+/// log2 of the bytes of a line of bitmap, the unit of the union pass
+const LOG_BITMAP_LINE: u32 = 6;
+/// Words of a line of bitmap sent to its owner
+const BITMAP_LINE_WORDS: usize = 8;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct PrivateMarks {
+    /// Objects marked in this bitmap
+    marked: HashSet<ObjectReference>,
+    /// Objects marked here that another processor had already marked, and
+    /// the slots scanned again because of them
+    pub(super) duplicates: usize,
+    pub(super) duplicate_slots: usize,
+    /// Marks of objects owned by another rank, each of which would have
+    /// written that rank's header
+    pub(super) remote_writes_avoided: usize,
+    /// Lines of this bitmap sent to their owner by the union pass
+    pub(super) union_lines_sent: usize,
+    /// Ticks this processor spent on the union pass
+    pub(super) union_ticks: usize,
+}
+
+/// Where the bitmap of processor `id` has the bit of `o`
+fn bitmap_address(id: usize, o: u64) -> u64 {
+    let offset = (o >> LOG_BYTES_PER_MARK_BYTE) & ((1 << LOG_BITMAP_BYTES) - 1);
+    PRIVATE_MARK_BASE + ((id as u64) << LOG_BITMAP_BYTES) + offset
+}
+
+impl PrivateMarks {
+    /// Sets the bit of `o` in the bitmap of processor `id`, returning whether
+    /// it was clear and how long reading and writing it take.
+    pub(super) fn mark(
+        &mut self,
+        id: usize,
+        o: ObjectReference,
+        cache: &mut SetAssociativeCache,
+    ) -> (bool, usize) {
+        let addr = VirtualAddress(bitmap_address(id, o.to_raw()));
+        let latency = cache.read(addr);
+        if !self.marked.insert(o) {
+            return (false, latency);
+        }
+        (true, latency + cache.write(addr))
+    }
+
+    /// The owners of the objects marked in each line of the bitmap, by the
+    /// offset of the line in the bitmap.
+    fn line_owners(&self, owner_of: impl Fn(Address) -> usize) -> BTreeMap<u64, BTreeSet<usize>> {
+        let mut lines: BTreeMap<u64, BTreeSet<usize>> = BTreeMap::new();
+        for &o in &self.marked {
+            let line = (bitmap_address(0, o.to_raw()) - PRIVATE_MARK_BASE) >> LOG_BITMAP_LINE;
+            lines
+                .entry(line)
+                .or_default()
+                .insert(owner_of(o.to_address()));
+        }
+        lines
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    /// Runs the union pass once the closure is over: every processor reads
+    /// the lines of its bitmap it set bits in and sends each to the owners
+    /// of the objects marked in it, which OR it into their own bitmap. The
+    /// processors work in parallel, so the pass takes as long as the
+    /// busiest, which is added to the ticks of the closure. The network is
+    /// taken to keep up.
+    pub(super) fn union_private_marks(&mut self) {
+        let mut received: Vec<Vec<u64>> = vec![vec![]; self.processors.len()];
+        for p in &mut self.processors {
+            let timing = p.timing.clone();
+            let private_marks = p.private_marks.as_mut().unwrap();
+            let lines = private_marks.line_owners(Self::get_owner_processor);
+            let mut ticks = 0;
+            for (line, owners) in lines {
+                let addr = bitmap_address(p.id, 0) + (line << LOG_BITMAP_LINE);
+                ticks += p.cache.read(VirtualAddress(addr));
+                for owner in owners.into_iter().filter(|&owner| owner != p.id) {
+                    ticks += timing.send_message(BITMAP_LINE_WORDS);
+                    private_marks.union_lines_sent += 1;
+                    received[owner].push(line);
+                }
+            }
+            private_marks.union_ticks = ticks;
+        }
+        for (p, lines) in self.processors.iter_mut().zip(received) {
+            let timing = p.timing.clone();
+            let mut ticks = 0;
+            for &line in &lines {
+                let addr = bitmap_address(p.id, 0) + (line << LOG_BITMAP_LINE);
+                ticks += timing.read_inbox(BITMAP_LINE_WORDS);
+                ticks += p.cache.read(VirtualAddress(addr));
+                ticks += p.cache.write(VirtualAddress(addr));
+            }
+            p.private_marks.as_mut().unwrap().union_ticks += ticks;
+        }
+        self.ticks += self.union_ticks();
+    }
+
+    fn union_ticks(&self) -> usize {
+        self.processors
+            .iter()
+            .filter_map(|p| p.private_marks.as_ref())
+            .map(|m| m.union_ticks)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Prints the private mark section of the summary and records its stats.
+    pub(super) fn report_private_mark_stats(&self, stats: &mut HashMap<String, f64>) {
+        let marks: Vec<&PrivateMarks> = self
+            .processors
+            .iter()
+            .filter_map(|p| p.private_marks.as_ref())
+            .collect();
+        let sum = |f: fn(&PrivateMarks) -> usize| marks.iter().map(|m| f(m)).sum::<usize>();
+        let duplicates = sum(|m| m.duplicates);
+        let duplicate_slots = sum(|m| m.duplicate_slots);
+        let remote_writes_avoided = sum(|m| m.remote_writes_avoided);
+        let union_lines = sum(|m| m.union_lines_sent);
+        println!("Private mark bitmaps (aggregate):");
+        println!(
+            "  Duplicate marks: {:>10}    Slots scanned again: {:>10}",
+            Self::format_thousands(duplicates),
+            Self::format_thousands(duplicate_slots)
+        );
+        println!(
+            "  Remote header writes avoided: {:>10}",
+            Self::format_thousands(remote_writes_avoided)
+        );
+        println!(
+            "  Union pass: {:>10} lines sent in {:>10} ticks",
+            Self::format_thousands(union_lines),
+            Self::format_thousands(self.union_ticks())
+        );
+        stats.insert("private_marks.duplicates.sum".into(), duplicates as f64);
+        stats.insert(
+            "private_marks.duplicate_slots.sum".into(),
+            duplicate_slots as f64,
+        );
+        stats.insert(
+            "private_marks.remote_writes_avoided.sum".into(),
+            remote_writes_avoided as f64,
+        );
+        stats.insert("private_marks.union_lines.sum".into(), union_lines as f64);
+        stats.insert(
+            "private_marks.union_ticks".into(),
+            self.union_ticks() as f64,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::memory::DDR4RankOption;
+
+    #[test]
+    fn test_private_marks() {
+        let mut cache = SetAssociativeCache::new(
+            CacheGeometry::DEFAULT,
+            None,
+            DDR4RankOption::Naive,
+            crate::simulate::PageSize::FourKB,
+        );
+        let object = ObjectReference::from_raw_unchecked;
+        let mut marks = PrivateMarks::default();
+        assert!(marks.mark(3, object(0x1000), &mut cache).0);
+        assert!(!marks.mark(3, object(0x1000), &mut cache).0);
+        // Neighbouring words share a byte of bitmap, so the second mark hits
+        let misses = cache.stats.read_misses + cache.stats.write_misses;
+        assert!(marks.mark(3, object(0x1008), &mut cache).0);
+        assert_eq!(cache.stats.read_misses + cache.stats.write_misses, misses);
+        // The bitmaps of two processors do not overlap
+        assert_ne!(bitmap_address(0, 0x1000), bitmap_address(1, 0x1000));
+        // A line of bitmap covers 4 KiB of heap
+        marks.mark(3, object(0x1ff8), &mut cache);
+        marks.mark(3, object(0x2000), &mut cache);
+        let lines = marks.line_owners(|o| (o.as_u64() >> 12) as usize);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines.values().flatten().copied().collect::<Vec<_>>(),
+            [1, 2]
+        );
+    }
+}
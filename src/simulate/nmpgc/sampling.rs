@@ -0,0 +1,260 @@
+//! SMARTS-style sampled simulation (`--sample-window`): detailed windows of
+//! a fixed number of ticks alternate with fast-forwarding, where processors
+//! do their work functionally, taking no time, and messages are delivered at
+//! once. Caches and TLBs still see every access while fast-forwarding, so
+//! the next window starts warm. As in SMARTS, where the cycles per
+//! instruction measured in the windows are applied to the instructions
+//! fast-forwarded, the total ticks are extrapolated from the items of work
+//! done per tick in the windows.
+
+use super::*;
+
+/// z for a two-sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+pub(super) struct Sampling {
+    /// Ticks of each detailed window
+    window: usize,
+    /// Steps of each fast-forward
+    fast_forward: usize,
+    /// Ticks simulated in the current window
+    window_ticks: usize,
+    /// Work done when the current window started
+    window_start_work: usize,
+    /// `(ticks, items of work done)` of each window so far
+    windows: Vec<(usize, usize)>,
+    fast_forward_steps: usize,
+    fast_forward_work: usize,
+    fast_forward_marked: usize,
+}
+
+/// Total ticks extrapolated from the windows and a 95% confidence interval.
+#[derive(Debug, PartialEq)]
+struct Estimate {
+    ticks: f64,
+    low: f64,
+    high: f64,
+}
+
+impl Sampling {
+    pub(super) fn new(window: usize, fast_forward: usize) -> Self {
+        Sampling {
+            window: window.max(1),
+            fast_forward,
+            window_ticks: 0,
+            window_start_work: 0,
+            windows: vec![],
+            fast_forward_steps: 0,
+            fast_forward_work: 0,
+            fast_forward_marked: 0,
+        }
+    }
+
+    fn end_window(&mut self, work: usize) {
+        self.windows
+            .push((self.window_ticks, work - self.window_start_work));
+        self.window_ticks = 0;
+    }
+
+    /// The windows so far, including the current one, given the work done
+    /// in total.
+    fn all_windows(&self, work: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let current = (self.window_ticks, work - self.window_start_work);
+        self.windows
+            .iter()
+            .copied()
+            .chain(std::iter::once(current))
+            .filter(|w| w.0 > 0)
+    }
+
+    /// The detailed ticks plus the fast-forwarded work at the mean rate of
+    /// the windows. The interval only accounts for the variation of that
+    /// rate between windows.
+    fn estimate(&self, work: usize) -> Estimate {
+        let rates: Vec<f64> = self
+            .all_windows(work)
+            .map(|(ticks, work)| work as f64 / ticks as f64)
+            .collect();
+        let detailed = self.all_windows(work).map(|w| w.0).sum::<usize>() as f64;
+        let extrapolated = self.fast_forward_work as f64;
+        let at_rate = |rate: f64| {
+            if extrapolated == 0.0 {
+                detailed
+            } else if rate > 0.0 {
+                detailed + extrapolated / rate
+            } else {
+                f64::INFINITY
+            }
+        };
+        let n = rates.len() as f64;
+        let mean = rates.iter().sum::<f64>() / n;
+        if rates.len() < 2 {
+            let ticks = at_rate(mean);
+            return Estimate {
+                ticks,
+                low: ticks,
+                high: ticks,
+            };
+        }
+        let variance = rates.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        let margin = Z_95 * (variance / n).sqrt();
+        Estimate {
+            ticks: at_rate(mean),
+            low: at_rate(mean + margin),
+            high: at_rate(mean - margin),
+        }
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    /// Simulates a tick of the current window, or fast-forwards first if
+    /// the window is over. Returns whether the closure is complete.
+    pub(super) fn tick_sampled<O: ObjectModel>(&mut self) -> bool {
+        let work = self.work_done();
+        let sampling = self.sampling.as_mut().unwrap();
+        if sampling.window_ticks == sampling.window {
+            sampling.end_window(work);
+            let steps = sampling.fast_forward;
+            if self.fast_forward::<O>(steps) {
+                return true;
+            }
+            let work = self.work_done();
+            self.sampling.as_mut().unwrap().window_start_work = work;
+        }
+        self.sampling.as_mut().unwrap().window_ticks += 1;
+        self.tick_sequential::<O>()
+    }
+
+    /// Runs up to `steps` steps in which every processor does one item of
+    /// work without stalling. Returns whether the closure is complete.
+    fn fast_forward<O: ObjectModel>(&mut self, steps: usize) -> bool {
+        let work = self.work_done();
+        let marked = self.marked_objects();
+        for p in &mut self.processors {
+            p.fast_forward = true;
+        }
+        let mut finished = false;
+        let mut taken = 0;
+        while taken < steps && !finished {
+            self.ticks += 1;
+            taken += 1;
+            let mut messages: Vec<(usize, NMPMessage)> = self
+                .processors
+                .iter_mut()
+                .filter_map(|p| p.blocked_message.take().map(|m| (p.id, m)))
+                .collect();
+            for p in &mut self.processors {
+                messages.extend(p.tick::<O>().map(|m| (p.id, m)));
+            }
+            for (sender_id, msg) in messages {
+                if msg.is_broadcast() {
+                    for p in self.processors.iter_mut().filter(|p| p.id != sender_id) {
+                        p.inbox.push(msg.copy_for(p.id));
+                    }
+                } else {
+                    self.processors[msg.recipient].inbox.push(msg);
+                }
+            }
+            // Drains what the last window left in flight
+            self.tick_network();
+            finished = self.finished();
+        }
+        for p in &mut self.processors {
+            p.fast_forward = false;
+        }
+        let work = self.work_done() - work;
+        let marked = self.marked_objects() - marked;
+        let sampling = self.sampling.as_mut().unwrap();
+        sampling.fast_forward_steps += taken;
+        sampling.fast_forward_work += work;
+        sampling.fast_forward_marked += marked;
+        finished
+    }
+
+    /// Items of work done by all processors, other than stalling and idling.
+    fn work_done(&self) -> usize {
+        self.processors
+            .iter()
+            .flat_map(|p| &p.work_count)
+            .filter(|(t, _)| !matches!(t, NMPProcessorWorkType::Stall | NMPProcessorWorkType::Idle))
+            .map(|(_, n)| n)
+            .sum()
+    }
+
+    /// Prints the sampling section of the summary and records its stats.
+    pub(super) fn report_sampling_stats(&self, stats: &mut HashMap<String, f64>) {
+        let sampling = self.sampling.as_ref().unwrap();
+        let work = self.work_done();
+        let windows = sampling.all_windows(work).count();
+        let detailed: usize = sampling.all_windows(work).map(|w| w.0).sum();
+        let estimate = sampling.estimate(work);
+        println!(
+            "Sampling ({} detailed ticks, then {} fast-forward steps):",
+            Self::format_thousands(sampling.window),
+            Self::format_thousands(sampling.fast_forward)
+        );
+        println!(
+            "  Windows:         {:>12}    Detailed ticks:  {:>12}    Work:   {:>12}",
+            Self::format_thousands(windows),
+            Self::format_thousands(detailed),
+            Self::format_thousands(work - sampling.fast_forward_work)
+        );
+        println!(
+            "  Fast-forwarded:  {:>12}    Marked:          {:>12}    Work:   {:>12}",
+            Self::format_thousands(sampling.fast_forward_steps),
+            Self::format_thousands(sampling.fast_forward_marked),
+            Self::format_thousands(sampling.fast_forward_work)
+        );
+        println!(
+            "  Estimated ticks: {:>12}    95% CI:          {:.0} - {:.0}",
+            Self::format_thousands(estimate.ticks.round() as usize),
+            estimate.low,
+            estimate.high
+        );
+        println!("  (Ticks above count each fast-forward step as one tick)");
+        stats.insert("sampling.windows".into(), windows as f64);
+        stats.insert("sampling.detailed_ticks".into(), detailed as f64);
+        stats.insert(
+            "sampling.fast_forward_steps".into(),
+            sampling.fast_forward_steps as f64,
+        );
+        stats.insert(
+            "sampling.fast_forward_marked".into(),
+            sampling.fast_forward_marked as f64,
+        );
+        stats.insert(
+            "sampling.fast_forward_work".into(),
+            sampling.fast_forward_work as f64,
+        );
+        stats.insert("sampling.estimated_ticks".into(), estimate.ticks);
+        stats.insert("sampling.estimated_ticks_low".into(), estimate.low);
+        stats.insert("sampling.estimated_ticks_high".into(), estimate.high);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let mut sampling = Sampling::new(100, 1000);
+        sampling.windows = vec![(100, 50), (100, 50)];
+        sampling.window_ticks = 10;
+        sampling.window_start_work = 600;
+        sampling.fast_forward_work = 500;
+        // 210 detailed ticks, and 500 items of work at half an item per tick
+        assert_eq!(
+            sampling.estimate(605),
+            Estimate {
+                ticks: 1210.0,
+                low: 1210.0,
+                high: 1210.0
+            }
+        );
+        sampling.windows = vec![(100, 40), (100, 60)];
+        let estimate = sampling.estimate(605);
+        assert_eq!(estimate.ticks, 1210.0);
+        assert!(estimate.low < 1210.0 && estimate.high > 1210.0);
+    }
+}
@@ -0,0 +1,191 @@
+//! Streaming scan engines for big object arrays (`--scan-engine`). Instead
+//! of a `ContinueScan` and a `Load` work item per slot, marking an object
+//! array with at least `--scan-engine` slots hands its slots to the DMA-like
+//! engine next to each rank that owns some of them, which reads them at
+//! `--scan-engine-bandwidth` bytes per tick and queues the children it finds
+//! for its processor to mark. The processor is free to do other work
+//! meanwhile.
+
+use super::*;
+use crate::simulate::memory::AddressLayout;
+
+/// Bytes of a slot
+const SLOT_BYTES: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct ScanEngine {
+    /// Object arrays with fewer slots are scanned by the processor
+    pub(super) min_slots: usize,
+    bytes_per_tick: usize,
+    /// Slots left to stream, as `(first slot, slots)`
+    ranges: VecDeque<(Address, u64)>,
+    /// Bandwidth of the previous ticks not enough for a whole slot
+    credit: usize,
+    /// Arrays handed to the engines by this processor
+    pub(super) arrays: usize,
+    /// Ranges streamed by this engine, and their slots
+    pub(super) ranges_streamed: usize,
+    pub(super) slots: usize,
+    /// Ticks with slots to stream
+    pub(super) busy_ticks: usize,
+}
+
+impl ScanEngine {
+    pub(super) fn new(min_slots: usize, bytes_per_tick: usize) -> Self {
+        assert!(bytes_per_tick > 0, "The scan engine has no bandwidth");
+        ScanEngine {
+            min_slots,
+            bytes_per_tick,
+            ranges: VecDeque::new(),
+            credit: 0,
+            arrays: 0,
+            ranges_streamed: 0,
+            slots: 0,
+            busy_ticks: 0,
+        }
+    }
+
+    pub(super) fn push(&mut self, first: Address, slots: u64) {
+        self.ranges.push_back((first, slots));
+        self.ranges_streamed += 1;
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Streams the slots one tick of bandwidth reads, in order.
+    pub(super) fn tick(&mut self) -> Vec<Address> {
+        if self.ranges.is_empty() {
+            return vec![];
+        }
+        self.busy_ticks += 1;
+        self.credit += self.bytes_per_tick;
+        let mut streamed = vec![];
+        while self.credit >= SLOT_BYTES {
+            let Some((first, slots)) = self.ranges.front_mut() else {
+                // Bandwidth cannot be saved up while there is nothing to read
+                self.credit = 0;
+                break;
+            };
+            streamed.push(*first);
+            *first = first.shift::<u64>(1);
+            *slots -= 1;
+            if *slots == 0 {
+                self.ranges.pop_front();
+            }
+            self.credit -= SLOT_BYTES;
+        }
+        self.slots += streamed.len();
+        streamed
+    }
+}
+
+/// Splits `slots` slots from `first` into the runs owned by one rank each.
+pub(super) fn owner_runs(first: Address, slots: u64) -> Vec<(Address, u64)> {
+    let mut runs = vec![];
+    let end = first.as_u64() + slots * SLOT_BYTES as u64;
+    let mut start = first.as_u64();
+    // The blocks of memory owned by one rank, see `AddressMapping`
+    let log_owner_block = AddressLayout::current().log_owner_block();
+    while start < end {
+        let block_end = ((start >> log_owner_block) + 1) << log_owner_block;
+        let run_end = block_end.min(end);
+        runs.push((
+            Address::from_raw(start),
+            (run_end - start) / SLOT_BYTES as u64,
+        ));
+        start = run_end;
+    }
+    runs
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
+    /// Whether the slots just found by scanning `o` go to the scan engines.
+    pub(super) fn streams<O: ObjectModel>(&self, o: ObjectReference) -> bool {
+        let Some(engine) = &self.scan_engine else {
+            return false;
+        };
+        let slots: u64 = self.edge_chunks.iter().map(|&(_, count)| count).sum();
+        slots as usize >= engine.min_slots && unsafe { O::is_objarray(o) }
+    }
+
+    /// Hands the slots of the object just scanned to the engine of the rank
+    /// owning each run of them.
+    pub(super) fn offload_scan(&mut self) {
+        let chunks = std::mem::take(&mut self.edge_chunks);
+        self.edge_chunk_cursor = (0, 0);
+        self.scan_engine.as_mut().unwrap().arrays += 1;
+        for (first, count) in chunks {
+            for (start, slots) in owner_runs(first, count) {
+                let owner = NMPGC::<LOG_NUM_THREADS>::get_owner_processor(start);
+                if owner == self.id {
+                    self.scan_engine.as_mut().unwrap().push(start, slots);
+                } else {
+                    self.works.push_front(NMPProcessorWork::SendMessage(
+                        NMPMessage::new_scan_range(owner, start, slots),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    pub(super) fn report_scan_engine_stats(&self, stats: &mut HashMap<String, f64>) {
+        let engines: Vec<&ScanEngine> = self
+            .processors
+            .iter()
+            .filter_map(|p| p.scan_engine.as_ref())
+            .collect();
+        let arrays: usize = engines.iter().map(|e| e.arrays).sum();
+        let ranges: usize = engines.iter().map(|e| e.ranges_streamed).sum();
+        let slots: usize = engines.iter().map(|e| e.slots).sum();
+        let busy: usize = engines.iter().map(|e| e.busy_ticks).sum();
+        let utilization = busy as f64 / (self.ticks * engines.len()).max(1) as f64;
+        println!(
+            "Scan engines (aggregate, arrays of at least {} slots):",
+            engines[0].min_slots
+        );
+        println!(
+            "  Arrays:       {:>10}    Ranges:       {:>10}    Slots:     {:>10}",
+            Self::format_thousands(arrays),
+            Self::format_thousands(ranges),
+            Self::format_thousands(slots)
+        );
+        println!(
+            "  Busy ticks:   {:>10}    Utilization:  {:>9.2}%",
+            Self::format_thousands(busy),
+            utilization * 100.0
+        );
+        stats.insert("scan_engine.arrays.sum".into(), arrays as f64);
+        stats.insert("scan_engine.ranges.sum".into(), ranges as f64);
+        stats.insert("scan_engine.slots.sum".into(), slots as f64);
+        stats.insert("scan_engine.busy_ticks.sum".into(), busy as f64);
+        stats.insert("scan_engine.utilization".into(), utilization);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_engine() {
+        let a = Address::from_raw;
+        assert_eq!(
+            owner_runs(a(0x1ff8), 1026),
+            vec![(a(0x1ff8), 1), (a(0x2000), 1024), (a(0x4000), 1)]
+        );
+        // 1.5 slots a tick
+        let mut engine = ScanEngine::new(64, 12);
+        engine.push(a(0x1000), 2);
+        engine.push(a(0x2000), 2);
+        assert_eq!(engine.tick(), vec![a(0x1000)]);
+        assert_eq!(engine.tick(), vec![a(0x1008), a(0x2000)]);
+        assert_eq!(engine.tick(), vec![a(0x2008)]);
+        assert!(engine.is_empty());
+        assert!(engine.tick().is_empty());
+        assert_eq!((engine.slots, engine.busy_ticks), (4, 3));
+    }
+}
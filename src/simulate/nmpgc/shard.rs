@@ -0,0 +1,207 @@
+//! Sharded NMPGC simulation, where the processors of each DIMM tick in a
+//! thread of their own (`--shard-quantum`). The threads only share the heap,
+//! and every word of it is written by the processors of one DIMM, those of
+//! the owner's, so they can run a quantum of ticks without synchronizing.
+//! The messages between DIMMs are then put through the network, tick by
+//! tick, on the main thread. What the network delivers during a quantum is
+//! only read from the next one, so messages arrive up to a quantum late and
+//! senders only stall on a full link buffer from the next quantum; with a
+//! quantum of one tick, the simulation is the same as the sequential one.
+
+use super::*;
+
+/// The processors of one DIMM and what they sent to other DIMMs during a
+/// quantum.
+struct Shard<const LOG_NUM_THREADS: u8> {
+    dimm: DimmId,
+    processors: Vec<NMPProcessor<LOG_NUM_THREADS>>,
+    /// `(tick in the quantum, sender, message)`, in the order sent
+    outbox: Vec<(usize, usize, NMPMessage)>,
+    /// The first tick of the quantum from whose end the processors stayed done
+    idle_from: usize,
+}
+
+// SAFETY: the raw pointers in the work and the messages of the processors
+// point into the heap, which the processors of different DIMMs never write
+// to the same words of: objects are marked and slots loaded by their owner,
+// or by another rank of its DIMM with `--dimm-sharing`.
+unsafe impl<const LOG_NUM_THREADS: u8> Send for Shard<LOG_NUM_THREADS> {}
+
+impl<const LOG_NUM_THREADS: u8> Shard<LOG_NUM_THREADS> {
+    /// Ticks the processors `quantum` times, delivering the messages within
+    /// the DIMM as the sequential simulation does.
+    fn run<O: ObjectModel>(&mut self, quantum: usize, distributed_termination: bool) {
+        self.idle_from = 0;
+        for t in 0..quantum {
+            let mut messages = Vec::new();
+            for p in &mut self.processors {
+                if let Some(m) = p.tick::<O>() {
+                    messages.push((p.id, m));
+                }
+            }
+            for (sender_id, msg) in messages {
+                if msg.is_broadcast() {
+                    for p in self.processors.iter_mut().filter(|p| p.id != sender_id) {
+                        p.inbox.push(msg.copy_for(p.id));
+                    }
+                    self.outbox.push((t, sender_id, msg));
+                } else if DimmId::from(RankId(msg.recipient as u8)) == self.dimm {
+                    let recipient = self
+                        .processors
+                        .iter_mut()
+                        .find(|p| p.id == msg.recipient)
+                        .unwrap();
+                    recipient.inbox.push(msg);
+                } else {
+                    self.outbox.push((t, sender_id, msg));
+                }
+            }
+            let done = self
+                .processors
+                .iter()
+                .all(|p| p.locally_done() && (!distributed_termination || p.terminated));
+            if !done {
+                self.idle_from = t + 1;
+            }
+        }
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    /// Simulates a quantum of ticks with a thread per DIMM. Returns whether
+    /// the closure was complete at the end of it.
+    pub(super) fn tick_sharded<O: ObjectModel>(&mut self) -> bool {
+        let quantum = self.shard_quantum;
+        let distributed_termination = self.distributed_termination;
+        let mut shards: BTreeMap<DimmId, Shard<LOG_NUM_THREADS>> = BTreeMap::new();
+        for p in std::mem::take(&mut self.processors) {
+            let dimm = DimmId::from(RankId(p.id as u8));
+            shards
+                .entry(dimm)
+                .or_insert_with(|| Shard {
+                    dimm,
+                    processors: vec![],
+                    outbox: vec![],
+                    idle_from: 0,
+                })
+                .processors
+                .push(p);
+        }
+        let shards: Vec<Shard<LOG_NUM_THREADS>> = std::thread::scope(|s| {
+            let handles: Vec<_> = shards
+                .into_values()
+                .map(|mut shard| {
+                    s.spawn(move || {
+                        shard.run::<O>(quantum, distributed_termination);
+                        shard
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let mut outbox = Vec::new();
+        // The closure can complete partway through the quantum, once every
+        // shard and then the network have gone idle
+        let mut idle_from = 0;
+        for shard in shards {
+            idle_from = idle_from.max(shard.idle_from);
+            self.processors.extend(shard.processors);
+            outbox.extend(shard.outbox);
+        }
+        self.processors.sort_by_key(|p| p.id);
+        // A processor sends at most one message per tick
+        outbox.sort_by_key(|&(t, sender_id, _)| (t, sender_id));
+
+        let mut outbox = outbox.into_iter().peekable();
+        for t in 0..quantum {
+            // Refused messages first, as in the sequential tick
+            let mut messages = Vec::new();
+            for p in &mut self.processors {
+                messages.extend(p.blocked_message.take().map(|m| (p.id, m)));
+                messages.extend(self.refused[p.id].drain(..).map(|m| (p.id, m)));
+            }
+            while let Some((_, sender_id, msg)) = outbox.next_if(|&(tick, _, _)| tick == t) {
+                messages.push((sender_id, msg));
+            }
+            for (sender_id, msg) in messages {
+                // Messages of a sender leave in the order it sent them
+                if !self.refused[sender_id].is_empty() {
+                    self.refused[sender_id].push_back(msg);
+                    continue;
+                }
+                let sender_dimm = DimmId::from(RankId(sender_id as u8));
+                if msg.is_broadcast() {
+                    let tree = self.topology.broadcast_tree(sender_dimm);
+                    self.network.broadcast(msg, sender_dimm, tree);
+                } else {
+                    let recipient_dimm = DimmId::from(RankId(msg.recipient as u8));
+                    let route = self.topology.get_route(sender_dimm, recipient_dimm);
+                    if let Err(msg) = self.network.inject(msg, route) {
+                        self.refused[sender_id].push_back(msg);
+                    }
+                }
+            }
+            self.tick_network();
+            if !self.network.is_empty() || self.refused.iter().any(|r| !r.is_empty()) {
+                idle_from = idle_from.max(t + 1);
+            }
+        }
+        // The senders stall on their oldest refused message, as they would
+        // have from the tick it was refused
+        for p in &mut self.processors {
+            p.blocked_message = self.refused[p.id].pop_front();
+        }
+        let finished = self.finished();
+        self.ticks += if finished { idle_from + 1 } else { quantum };
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+    use crate::*;
+    use clap::Parser;
+
+    /// Ticks, marked objects and statistics of a simulation of a fresh copy
+    /// of `heapdump`.
+    fn simulate(heapdump: &HeapDump, extra_args: &[&str]) -> (usize, usize, Vec<(String, f64)>) {
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(heapdump);
+        object_model.restore_objects(heapdump);
+        let args = SimulationArgs::parse_from(
+            ["simulate", "-p", "8", "-a", "NMPGC"]
+                .iter()
+                .chain(extra_args),
+        );
+        let mut nmpgc = NMPGC::<3>::new(&args, &object_model);
+        while !nmpgc.tick::<OpenJDKObjectModel<false>>() {}
+        let mut stats: Vec<(String, f64)> = nmpgc.stats().into_iter().collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        heapdump.unmap_spaces().unwrap();
+        (nmpgc.ticks(), nmpgc.marked_objects(), stats)
+    }
+
+    #[test]
+    fn test_sharded_matches_sequential() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_2000").unwrap();
+        let sequential = simulate(&heapdump, &["--link-buffer", "2"]);
+        let sharded = simulate(&heapdump, &["--link-buffer", "2", "--shard-quantum", "1"]);
+        assert_eq!(sharded, sequential);
+        // Longer quanta delay messages, but the closure is the same
+        let (ticks, marked, _) =
+            simulate(&heapdump, &["--link-buffer", "2", "--shard-quantum", "64"]);
+        assert_eq!(marked, sequential.1);
+        assert_ne!(ticks, 0);
+        // Without messages between DIMMs, no quantum delays anything, and the
+        // closure ends on the same tick even partway through a quantum
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        let sequential = simulate(&heapdump, &[]);
+        for quantum in ["7", "64"] {
+            assert_eq!(simulate(&heapdump, &["--shard-quantum", quantum]), sequential);
+        }
+    }
+}
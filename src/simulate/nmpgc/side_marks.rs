@@ -0,0 +1,177 @@
+//! Immix-style line and block marks (`--line-marks`, `--block-marks`): on
+//! top of its mark byte, marking an object sets a byte in a side table for
+//! every line, and every block, the object covers, so that the collector
+//! knows which lines and blocks hold live objects. The side tables are
+//! written through the cache of the marking processor, which is where the
+//! extra traffic shows up.
+
+use super::*;
+use crate::simulate::memory::{DataCache, VirtualAddress};
+use std::collections::HashSet;
+
+/// One byte per line, and one per block, from these addresses, out of the
+/// way of the spaces of the heapdumps, as MMTk puts its side metadata
+const LINE_MARK_BASE: u64 = 0x600_0000_0000;
+const BLOCK_MARK_BASE: u64 = 0x680_0000_0000;
+
+/// A side table of one mark byte per granule of the heap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct SideMarkTable {
+    log_granule: u32,
+    base: u64,
+    /// Mark bytes written, one per granule an object covers
+    pub(super) writes: usize,
+    /// Those that missed in the cache of the processor
+    pub(super) write_misses: usize,
+    /// Ticks the writes took
+    pub(super) ticks: usize,
+    /// Granules marked by this processor
+    pub(super) marked: HashSet<u64>,
+}
+
+impl SideMarkTable {
+    fn new(granule: u64, base: u64) -> Self {
+        assert!(
+            granule.is_power_of_two(),
+            "Lines and blocks must be a power of two bytes"
+        );
+        SideMarkTable {
+            log_granule: granule.trailing_zeros(),
+            base,
+            writes: 0,
+            write_misses: 0,
+            ticks: 0,
+            marked: HashSet::new(),
+        }
+    }
+
+    /// Marks the granules of `[start, start + size)` and returns how long
+    /// the writes take.
+    fn mark(&mut self, start: u64, size: u64, cache: &mut SetAssociativeCache) -> usize {
+        let first = start >> self.log_granule;
+        let last = (start + size.max(1) - 1) >> self.log_granule;
+        let misses = cache.stats.write_misses;
+        let mut latency = 0;
+        for granule in first..=last {
+            self.marked.insert(granule);
+            latency += cache.write(VirtualAddress(self.base + granule));
+        }
+        self.writes += (last - first + 1) as usize;
+        self.write_misses += cache.stats.write_misses - misses;
+        self.ticks += latency;
+        latency
+    }
+}
+
+/// The line and block mark tables a processor writes to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct SideMarks {
+    pub(super) lines: Option<SideMarkTable>,
+    pub(super) blocks: Option<SideMarkTable>,
+    /// Size of every object, by reference. Not part of a checkpoint, as it
+    /// comes from the object model, see [`take_sizes`](Self::take_sizes)
+    #[serde(skip)]
+    sizes: Arc<HashMap<ObjectReference, u64>>,
+}
+
+impl SideMarks {
+    pub(super) fn new(
+        line: Option<u64>,
+        block: Option<u64>,
+        sizes: Arc<HashMap<ObjectReference, u64>>,
+    ) -> Self {
+        SideMarks {
+            lines: line.map(|l| SideMarkTable::new(l, LINE_MARK_BASE)),
+            blocks: block.map(|b| SideMarkTable::new(b, BLOCK_MARK_BASE)),
+            sizes,
+        }
+    }
+
+    /// Keeps the object sizes of `other` when it is replaced by a copy
+    /// loaded from a checkpoint.
+    pub(super) fn take_sizes(&mut self, other: &mut SideMarks) {
+        self.sizes = std::mem::take(&mut other.sizes);
+    }
+
+    /// Marks the lines and blocks covered by `o` and returns how long the
+    /// writes take. Objects are taken to extend `size` bytes from their
+    /// reference, which is their start except for the object models that
+    /// put fields before it.
+    pub(super) fn mark(&mut self, o: ObjectReference, cache: &mut SetAssociativeCache) -> usize {
+        let size = self.sizes.get(&o).copied().unwrap_or(8);
+        let mut latency = 0;
+        if let Some(lines) = self.lines.as_mut() {
+            latency += lines.mark(o.to_raw(), size, cache);
+        }
+        if let Some(blocks) = self.blocks.as_mut() {
+            latency += blocks.mark(o.to_raw(), size, cache);
+        }
+        latency
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    /// Prints the line and block mark section of the summary and records
+    /// its stats.
+    pub(super) fn report_side_mark_stats(&self, stats: &mut HashMap<String, f64>) {
+        println!("Side mark tables (aggregate):");
+        for name in ["line", "block"] {
+            let tables: Vec<&SideMarkTable> = self
+                .processors
+                .iter()
+                .filter_map(|p| p.side_marks.as_ref())
+                .filter_map(|m| match name {
+                    "line" => m.lines.as_ref(),
+                    _ => m.blocks.as_ref(),
+                })
+                .collect();
+            let Some(first) = tables.first() else {
+                continue;
+            };
+            let marked: HashSet<u64> = tables.iter().flat_map(|t| &t.marked).copied().collect();
+            let writes: usize = tables.iter().map(|t| t.writes).sum();
+            let write_misses: usize = tables.iter().map(|t| t.write_misses).sum();
+            let ticks: usize = tables.iter().map(|t| t.ticks).sum();
+            println!(
+                "  {:<6} ({:>6} B) Marked: {:>10}    Writes: {:>10}    Write misses: {:>10}    Ticks: {:>10}",
+                name,
+                1u64 << first.log_granule,
+                Self::format_thousands(marked.len()),
+                Self::format_thousands(writes),
+                Self::format_thousands(write_misses),
+                Self::format_thousands(ticks)
+            );
+            stats.insert(format!("{}_marks.marked", name), marked.len() as f64);
+            stats.insert(format!("{}_marks.writes.sum", name), writes as f64);
+            stats.insert(
+                format!("{}_marks.write_misses.sum", name),
+                write_misses as f64,
+            );
+            stats.insert(format!("{}_marks.ticks.sum", name), ticks as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_mark_table() {
+        let mut cache = SetAssociativeCache::new(
+            CacheGeometry::DEFAULT,
+            None,
+            DDR4RankOption::Naive,
+            crate::simulate::PageSize::FourKB,
+        );
+        let mut table = SideMarkTable::new(256, LINE_MARK_BASE);
+        // Within one line, then straddling two, then spanning three
+        table.mark(0x1000, 64, &mut cache);
+        table.mark(0x10f0, 32, &mut cache);
+        table.mark(0x2000, 600, &mut cache);
+        assert_eq!(table.writes, 6);
+        assert_eq!(table.marked.len(), 5);
+        // The mark bytes of neighbouring lines share a cache line
+        assert_eq!(table.write_misses, 1);
+    }
+}
@@ -0,0 +1,162 @@
+//! Two heapdumps, or tenants, traced at once on the same processors and
+//! network (`--tenant`). The spaces of the second tenant are moved up by
+//! `1 << LOG_TENANT_OFFSET`, so that the tenant of any address is one bit
+//! of it. Unless the arbitration is `Shared`, each processor queues the
+//! `Mark` and `Load` work of each tenant, and the messages carrying it,
+//! apart, and picks the queue to take from whenever it has nothing else to
+//! do.
+
+use super::*;
+use crate::cli::TenantArbitrationChoice;
+
+pub(crate) const TENANTS: usize = 2;
+/// Below the bits of the space types, and far above those picking ranks,
+/// banks and cache sets, so that each tenant maps onto the hardware the same
+/// way whether it runs alone or not
+pub(crate) const LOG_TENANT_OFFSET: u32 = 40;
+
+pub(crate) fn tenant_of(addr: u64) -> usize {
+    ((addr >> LOG_TENANT_OFFSET) & 1) as usize
+}
+
+#[derive(Debug)]
+pub(super) struct Tenancy {
+    arbitration: TenantArbitrationChoice,
+    queues: [VecDeque<NMPProcessorWork>; TENANTS],
+    /// Tenant whose queue `RoundRobin` tries first
+    next: usize,
+    /// Last tick spent on `Mark`, `Load` or `ContinueScan` work of each
+    /// tenant
+    last_trace_tick: [usize; TENANTS],
+    /// `Mark`, `Load` and `ContinueScan` work items of each tenant
+    work_items: [usize; TENANTS],
+}
+
+impl Tenancy {
+    pub(super) fn new(arbitration: TenantArbitrationChoice) -> Self {
+        Tenancy {
+            arbitration,
+            queues: Default::default(),
+            next: 0,
+            last_trace_tick: [0; TENANTS],
+            work_items: [0; TENANTS],
+        }
+    }
+
+    pub(super) fn pending(&self) -> usize {
+        self.queues.iter().map(|q| q.len()).sum()
+    }
+
+    fn pop(&mut self) -> Option<NMPProcessorWork> {
+        let first = match self.arbitration {
+            TenantArbitrationChoice::Shared => return None,
+            TenantArbitrationChoice::RoundRobin => self.next,
+            TenantArbitrationChoice::Priority => 0,
+        };
+        let tenant = (0..TENANTS)
+            .map(|i| (first + i) % TENANTS)
+            .find(|&t| !self.queues[t].is_empty())?;
+        self.next = (tenant + 1) % TENANTS;
+        self.queues[tenant].pop_front()
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
+    /// Queues `Mark` or `Load` work, or a message carrying it, behind the
+    /// rest, in the queue of its tenant if tenants are kept apart.
+    pub(super) fn push_tracing_work(&mut self, work: NMPProcessorWork) {
+        match self.tenancy.as_mut() {
+            Some(tenancy) if tenancy.arbitration != TenantArbitrationChoice::Shared => {
+                let tenant = tenant_of(work.target().as_u64());
+                tenancy.queues[tenant].push_back(work);
+            }
+            _ if self.mark_queue.is_some() => self.push_bounded(work),
+            _ => self.works.push_back(work),
+        }
+    }
+
+    /// The next item of the queue picked by the arbitration, once the
+    /// processor has nothing else queued.
+    pub(super) fn pop_tenant_work(&mut self) -> Option<NMPProcessorWork> {
+        self.tenancy.as_mut().and_then(|t| t.pop())
+    }
+
+    /// Attributes `work`, about to be done, to its tenant.
+    pub(super) fn record_tenant_work(&mut self, work: &NMPProcessorWork) {
+        let Some(tenancy) = self.tenancy.as_mut() else {
+            return;
+        };
+        let addr = match work {
+            NMPProcessorWork::Mark(_) | NMPProcessorWork::Load(_) => work.target(),
+            NMPProcessorWork::ContinueScan => self.edge_chunks[self.edge_chunk_cursor.0].0,
+            _ => return,
+        };
+        let tenant = tenant_of(addr.as_u64());
+        tenancy.last_trace_tick[tenant] = self.ticks;
+        tenancy.work_items[tenant] += 1;
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    /// Last tick spent tracing each tenant, 0 for a tenant without work.
+    pub(crate) fn tenant_finish_ticks(&self) -> [usize; TENANTS] {
+        let mut ticks = [0; TENANTS];
+        for tenancy in self.processors.iter().filter_map(|p| p.tenancy.as_ref()) {
+            for (t, &last) in ticks.iter_mut().zip(&tenancy.last_trace_tick) {
+                *t = (*t).max(last);
+            }
+        }
+        ticks
+    }
+
+    pub(super) fn report_tenancy_stats(&self, stats: &mut HashMap<String, f64>) {
+        let finish_ticks = self.tenant_finish_ticks();
+        println!(
+            "Tenants ({:?} arbitration):",
+            self.processors[0].tenancy.as_ref().unwrap().arbitration
+        );
+        for (tenant, &finish) in finish_ticks.iter().enumerate() {
+            let work_items: usize = self
+                .processors
+                .iter()
+                .map(|p| p.tenancy.as_ref().unwrap().work_items[tenant])
+                .sum();
+            println!(
+                "  Tenant {}:     {:>10} work items, done tracing at tick {}",
+                tenant,
+                Self::format_thousands(work_items),
+                Self::format_thousands(finish)
+            );
+            stats.insert(format!("tenant.{}.work_items", tenant), work_items as f64);
+            stats.insert(format!("tenant.{}.finish_ticks", tenant), finish as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_arbitration() {
+        assert_eq!(tenant_of(0x200_0000_1000), 0);
+        assert_eq!(tenant_of(0x300_0000_1000), 1);
+        let queued = |tenancy: &mut Tenancy| {
+            for o in [0x200_0000_0000, 0x200_0000_0010, 0x300_0000_0000] {
+                let o = ObjectReference::from_raw_unchecked(o);
+                tenancy.queues[tenant_of(o.to_raw())].push_back(NMPProcessorWork::Mark(o));
+            }
+            std::iter::from_fn(|| tenancy.pop())
+                .map(|w| tenant_of(w.target().as_u64()))
+                .collect::<Vec<_>>()
+        };
+        let mut round_robin = Tenancy::new(TenantArbitrationChoice::RoundRobin);
+        assert_eq!(queued(&mut round_robin), [0, 1, 0]);
+        let mut priority = Tenancy::new(TenantArbitrationChoice::Priority);
+        assert_eq!(queued(&mut priority), [0, 0, 1]);
+        assert_eq!(priority.pending(), 0);
+        // Shared work never leaves the queue of the processor
+        let mut shared = Tenancy::new(TenantArbitrationChoice::Shared);
+        assert!(shared.pop().is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+/// What a processor reports in response to a termination probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct ProbeReply {
+    /// Data (`Mark`/`Load`) messages sent so far
+    pub(super) sent: usize,
+    /// Data messages taken out of the inbox so far
+    pub(super) received: usize,
+    /// Whether the processor had no work left when it saw the probe
+    pub(super) idle: bool,
+}
+
+/// Coordinator side of the termination detection, a variant of Mattern's
+/// four-counter method run by one processor.
+///
+/// The coordinator broadcasts a probe whenever it runs out of work, and
+/// every other processor answers with its message counters and whether it
+/// is idle. A single wave is not enough, since a processor that reported
+/// idle can be woken up by a message that was still in flight. Tracing has
+/// terminated once two consecutive waves find every processor idle with
+/// identical counters and as many messages received as sent: any message
+/// sent in between would have changed the counters.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct TerminationDetector {
+    num_processors: usize,
+    /// Replies of the wave in progress, `None` when no wave is out
+    replies: Option<Vec<ProbeReply>>,
+    /// Total counters of the last wave if it found everyone idle
+    previous: Option<(usize, usize)>,
+    /// Tick the last wave completed at, to pace the waves
+    last_wave_end: usize,
+    pub(super) waves: usize,
+}
+
+/// Minimum ticks between the end of one wave and the start of the next, so
+/// that an idle coordinator does not flood the network with probes while
+/// the rest of the machine is still tracing.
+const PROBE_BACKOFF_TICKS: usize = 64;
+
+impl TerminationDetector {
+    pub(super) fn new(num_processors: usize) -> Self {
+        TerminationDetector {
+            num_processors,
+            replies: None,
+            previous: None,
+            last_wave_end: 0,
+            waves: 0,
+        }
+    }
+
+    /// Starts a new wave if none is out and the last one ended long enough
+    /// ago, returning the wave number to put in the probe.
+    pub(super) fn start_wave(&mut self, now: usize) -> Option<usize> {
+        if self.replies.is_some()
+            || (self.waves > 0 && now < self.last_wave_end + PROBE_BACKOFF_TICKS)
+        {
+            return None;
+        }
+        self.waves += 1;
+        self.replies = Some(Vec::with_capacity(self.num_processors));
+        Some(self.waves)
+    }
+
+    /// Records the reply of another processor to the current wave.
+    pub(super) fn record(&mut self, wave: usize, reply: ProbeReply) {
+        debug_assert_eq!(
+            wave, self.waves,
+            "replies always belong to the current wave"
+        );
+        self.replies
+            .as_mut()
+            .expect("reply without a wave in progress")
+            .push(reply);
+    }
+
+    /// Whether all other processors have replied to the current wave.
+    pub(super) fn wave_complete(&self) -> bool {
+        self.replies
+            .as_ref()
+            .is_some_and(|r| r.len() + 1 == self.num_processors)
+    }
+
+    /// Closes the current wave with the coordinator's own state and decides
+    /// whether tracing has terminated.
+    pub(super) fn finish_wave(&mut self, now: usize, own: ProbeReply) -> bool {
+        let mut replies = self.replies.take().expect("no wave in progress");
+        replies.push(own);
+        self.last_wave_end = now;
+        let all_idle = replies.iter().all(|r| r.idle);
+        let sent = replies.iter().map(|r| r.sent).sum();
+        let received = replies.iter().map(|r| r.received).sum();
+        let terminated = all_idle && sent == received && self.previous == Some((sent, received));
+        self.previous = all_idle.then_some((sent, received));
+        terminated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(sent: usize, received: usize, idle: bool) -> ProbeReply {
+        ProbeReply {
+            sent,
+            received,
+            idle,
+        }
+    }
+
+    #[test]
+    fn test_two_quiet_waves() {
+        let mut detector = TerminationDetector::new(2);
+        let wave = detector.start_wave(10).unwrap();
+        assert!(detector.start_wave(11).is_none(), "one wave at a time");
+        detector.record(wave, reply(3, 2, true));
+        assert!(detector.wave_complete());
+        // Everyone idle and balanced, but a single wave proves nothing
+        assert!(!detector.finish_wave(20, reply(2, 3, true)));
+
+        assert!(detector.start_wave(21).is_none(), "backing off");
+        let wave = detector.start_wave(20 + PROBE_BACKOFF_TICKS).unwrap();
+        // The counters moved, so something happened between the waves
+        detector.record(wave, reply(4, 3, true));
+        assert!(!detector.finish_wave(100, reply(3, 4, true)));
+
+        let wave = detector.start_wave(200).unwrap();
+        detector.record(wave, reply(4, 3, true));
+        assert!(detector.finish_wave(210, reply(3, 4, true)));
+        assert_eq!(detector.waves, 3);
+    }
+
+    #[test]
+    fn test_busy_processor_resets() {
+        let mut detector = TerminationDetector::new(2);
+        let wave = detector.start_wave(0).unwrap();
+        detector.record(wave, reply(1, 1, true));
+        assert!(!detector.finish_wave(10, reply(1, 1, true)));
+        let wave = detector.start_wave(100).unwrap();
+        detector.record(wave, reply(1, 1, false));
+        assert!(!detector.finish_wave(110, reply(1, 1, true)));
+        // The previous wave did not count because of the busy processor
+        let wave = detector.start_wave(200).unwrap();
+        detector.record(wave, reply(1, 1, true));
+        assert!(!detector.finish_wave(210, reply(1, 1, true)));
+    }
+}
@@ -0,0 +1,129 @@
+//! Fetching the TIB of an object before scanning it (`--tib-fetch`). When
+//! neither the header nor the alignment of the TIB pointer says where the
+//! slots of an object are, marking it queues a `LoadTIB`, and the scan waits
+//! for the TIB to arrive. As in HotSpot, where the oop maps are embedded in
+//! the klass, the TIB is taken to be followed by one oop map entry per run
+//! of slots. The reads go through the data cache of the processor, or
+//! through a metadata cache of its own with `--tib-cache`.
+
+use super::*;
+use crate::simulate::memory::{DataCache, VirtualAddress};
+
+/// Bytes of the oop map entry of a run of slots, an offset and a count
+pub(super) const OOP_MAP_ENTRY_BYTES: u64 = 16;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct TibFetch {
+    /// The metadata cache, if TIBs do not go through the data cache
+    pub(super) cache: Option<SetAssociativeCache>,
+    /// TIBs loaded
+    pub(super) loads: usize,
+    /// Lines of TIBs and oop maps read, and those that missed
+    pub(super) reads: usize,
+    pub(super) read_misses: usize,
+    /// Ticks the reads took
+    pub(super) ticks: usize,
+}
+
+impl TibFetch {
+    pub(super) fn new(cache: Option<SetAssociativeCache>) -> Self {
+        TibFetch {
+            cache,
+            loads: 0,
+            reads: 0,
+            read_misses: 0,
+            ticks: 0,
+        }
+    }
+
+    /// Reads the `bytes` of metadata at `tib`, through `data_cache` if there
+    /// is no metadata cache, and returns how long the reads take.
+    pub(super) fn load(
+        &mut self,
+        tib: Address,
+        bytes: u64,
+        data_cache: &mut SetAssociativeCache,
+    ) -> usize {
+        let cache = self.cache.as_mut().unwrap_or(data_cache);
+        let line = cache.line_size() as u64;
+        let tib = tib.as_u64();
+        let misses = cache.stats.read_misses;
+        let mut latency = 0;
+        let (first, last) = (tib / line, (tib + bytes.max(1) - 1) / line);
+        for l in first..=last {
+            latency += cache.read(VirtualAddress(l * line));
+        }
+        self.loads += 1;
+        self.reads += (last - first + 1) as usize;
+        self.read_misses += cache.stats.read_misses - misses;
+        self.ticks += latency;
+        latency
+    }
+}
+
+impl<const LOG_NUM_THREADS: u8> NMPGC<LOG_NUM_THREADS> {
+    /// Prints the TIB fetch section of the summary and records its stats,
+    /// kept apart from the data cache stats as metadata traffic.
+    pub(super) fn report_tib_fetch_stats(&self, stats: &mut HashMap<String, f64>) {
+        let fetches: Vec<&TibFetch> = self
+            .processors
+            .iter()
+            .filter_map(|p| p.tib_fetch.as_ref())
+            .collect();
+        let loads: usize = fetches.iter().map(|f| f.loads).sum();
+        let reads: usize = fetches.iter().map(|f| f.reads).sum();
+        let read_misses: usize = fetches.iter().map(|f| f.read_misses).sum();
+        let ticks: usize = fetches.iter().map(|f| f.ticks).sum();
+        let through = if fetches.iter().any(|f| f.cache.is_some()) {
+            "metadata cache"
+        } else {
+            "data cache"
+        };
+        println!("TIB fetch (aggregate, through the {}):", through);
+        println!(
+            "  Loads: {:>10}    Line reads: {:>10}    Read misses: {:>10} ({:.2}%)    Ticks: {:>10}",
+            Self::format_thousands(loads),
+            Self::format_thousands(reads),
+            Self::format_thousands(read_misses),
+            read_misses as f64 * 100.0 / reads.max(1) as f64,
+            Self::format_thousands(ticks)
+        );
+        stats.insert("tib.loads.sum".into(), loads as f64);
+        stats.insert("tib.reads.sum".into(), reads as f64);
+        stats.insert("tib.read_misses.sum".into(), read_misses as f64);
+        stats.insert("tib.ticks.sum".into(), ticks as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> SetAssociativeCache {
+        SetAssociativeCache::new(
+            CacheGeometry::DEFAULT,
+            None,
+            DDR4RankOption::Naive,
+            crate::simulate::PageSize::FourKB,
+        )
+    }
+
+    #[test]
+    fn test_tib_fetch() {
+        let mut data_cache = cache();
+        let mut through_data = TibFetch::new(None);
+        // A TIB straddling two lines, then the same TIB again
+        let tib = Address::from_raw(0x1030);
+        through_data.load(tib, 48, &mut data_cache);
+        through_data.load(tib, 48, &mut data_cache);
+        assert_eq!((through_data.loads, through_data.reads), (2, 4));
+        assert_eq!(through_data.read_misses, 2);
+        assert_eq!(data_cache.stats.read_misses, 2);
+
+        let mut data_cache = cache();
+        let mut dedicated = TibFetch::new(Some(cache()));
+        dedicated.load(tib, 48, &mut data_cache);
+        assert_eq!(dedicated.read_misses, 2);
+        assert_eq!(data_cache.stats.read_misses, 0);
+    }
+}
@@ -0,0 +1,150 @@
+use super::isa::IsaModel;
+use super::network::DIMM_TO_RANK_LATENCY;
+use serde::{Deserialize, Serialize};
+
+/// Latencies, in ticks, that make up the cost of each kind of NMP processor
+/// work.
+///
+/// The cost of a work item is split into a dispatch component (decoding and
+/// issuing the instructions implementing it) and a memory component (the
+/// cache/DRAM access or the hand-off to the DIMM link controller). The memory
+/// component of `Mark` and `Load` comes from the cache model, so only the
+/// link hand-off is configured here. Every work item occupies the processor
+/// for at least one tick.
+///
+/// The defaults reproduce the hard-coded latencies the simulator used before
+/// the model was configurable, and any field can be overridden from a TOML
+/// file passed with `--timing-model`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ProcessorTimingModel {
+    /// Dispatch cost of `Mark`, on top of the header read and write
+    pub(crate) mark_dispatch: usize,
+    /// Dispatch cost of `Load`, on top of the slot read
+    pub(crate) load_dispatch: usize,
+    /// Dispatch cost of `ContinueScan`, which computes the next slot address
+    pub(crate) continue_scan_dispatch: usize,
+    /// Dispatch cost of `SendMessage`, on top of the link hand-off
+    pub(crate) send_message_dispatch: usize,
+    /// Dispatch cost of `ReadInbox`, on top of the link hand-off
+    pub(crate) read_inbox_dispatch: usize,
+    /// Memory component of moving a message between a rank and the DIMM link
+    /// controller, paid by both `SendMessage` and `ReadInbox`
+    pub(crate) dimm_to_rank_latency: usize,
+    /// Extra cost of moving each additional 8-byte word between a rank and
+    /// the link controller, paid by coalesced messages and batched inbox reads
+    pub(crate) extra_word_latency: usize,
+    /// Dispatch cost of `ScanFrame`, which finds the stack map of a frame,
+    /// on top of reading the slots it lists
+    pub(crate) frame_scan_dispatch: usize,
+    /// Dispatch cost of `LoadTIB`, on top of reading the TIB and its oop maps
+    pub(crate) load_tib_dispatch: usize,
+    /// Instruction mixes used by `--isa-accounting`
+    pub(crate) isa: IsaModel,
+}
+
+impl Default for ProcessorTimingModel {
+    fn default() -> Self {
+        ProcessorTimingModel {
+            // The dispatch of memory operations overlaps with the cache tag
+            // check, which is already part of the cache hit latency.
+            mark_dispatch: 0,
+            load_dispatch: 0,
+            continue_scan_dispatch: 1,
+            send_message_dispatch: 0,
+            read_inbox_dispatch: 0,
+            dimm_to_rank_latency: DIMM_TO_RANK_LATENCY,
+            extra_word_latency: 1,
+            // A lookup of the return address in a table of stack maps
+            frame_scan_dispatch: 4,
+            load_tib_dispatch: 0,
+            isa: IsaModel::default(),
+        }
+    }
+}
+
+impl ProcessorTimingModel {
+    /// Parses a timing model from a TOML file, used as a clap value parser.
+    /// Boxed to keep `SimulationArgs` small.
+    pub(crate) fn from_file(path: &str) -> Result<Box<Self>, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read timing model {}: {}", path, e))?;
+        toml::from_str(&content)
+            .map(Box::new)
+            .map_err(|e| format!("Failed to parse timing model {}: {}", path, e))
+    }
+
+    /// Cost of handing a message of `words` 8-byte words to the link controller.
+    pub(super) fn send_message(&self, words: usize) -> usize {
+        self.send_message_dispatch + self.transfer(words)
+    }
+
+    /// Cost of reading `words` 8-byte words, possibly spread over several
+    /// messages, from the link controller.
+    pub(super) fn read_inbox(&self, words: usize) -> usize {
+        self.read_inbox_dispatch + self.transfer(words)
+    }
+
+    fn transfer(&self, words: usize) -> usize {
+        self.dimm_to_rank_latency + words.saturating_sub(1) * self.extra_word_latency
+    }
+
+    pub(super) fn print(&self) {
+        println!("Timing Model (ticks, dispatch + memory):");
+        println!("  Mark:          {} + cache read/write", self.mark_dispatch);
+        println!("  Load:          {} + cache read", self.load_dispatch);
+        println!("  ContinueScan:  {} + 0", self.continue_scan_dispatch);
+        println!(
+            "  SendMessage:   {} + {}",
+            self.send_message_dispatch, self.dimm_to_rank_latency
+        );
+        println!(
+            "  ReadInbox:     {} + {}",
+            self.read_inbox_dispatch, self.dimm_to_rank_latency
+        );
+        println!(
+            "  Extra word:    {} (coalesced messages, batched inbox reads)",
+            self.extra_word_latency
+        );
+        println!(
+            "  ScanFrame:     {} + cache read per root slot",
+            self.frame_scan_dispatch
+        );
+        println!(
+            "  LoadTIB:       {} + TIB and oop map reads",
+            self.load_tib_dispatch
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_override() {
+        let model: ProcessorTimingModel =
+            toml::from_str("send_message_dispatch = 8\ndimm_to_rank_latency = 3").unwrap();
+        assert_eq!(model.send_message(1), 11);
+        assert_eq!(model.read_inbox(1), 3);
+        assert_eq!(model.read_inbox(4), 3 + 3 * model.extra_word_latency);
+        assert_eq!(
+            model.continue_scan_dispatch,
+            ProcessorTimingModel::default().continue_scan_dispatch
+        );
+        assert!(toml::from_str::<ProcessorTimingModel>("no_such_field = 1").is_err());
+        let model: ProcessorTimingModel =
+            toml::from_str("[isa.load]\nloads = 1\nstores = 0\nbranches = 2\nalu = 7").unwrap();
+        assert_eq!(model.isa.load.alu, 7);
+        assert_eq!(model.isa.mark, IsaModel::default().mark);
+        assert!(toml::from_str::<ProcessorTimingModel>("[isa.load]\nalu = 7").is_err());
+    }
+
+    #[test]
+    fn test_config_file() {
+        // The file the README points to lists the defaults
+        let model = ProcessorTimingModel::from_file("configs/nmpgc_timing.toml").unwrap();
+        assert_eq!(*model, ProcessorTimingModel::default());
+        assert!(ProcessorTimingModel::from_file("configs/no_such_file.toml").is_err());
+    }
+}
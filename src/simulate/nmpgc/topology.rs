@@ -1,4 +1,7 @@
-use super::super::memory::{DimmId, RankId};
+use super::super::memory::{ranks_per_dimm, DimmId, RankId};
+use super::network::NetworkConfig;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 
 pub(super) trait Topology: Debug {
@@ -39,12 +42,83 @@ pub(super) trait Topology: Debug {
     /// Returns a sort key for a directed link so that link stats can be
     /// printed in some "natural" physical arrangement order.
     fn link_sort_key(&self, from_dimm: DimmId, to_dimm: DimmId) -> (usize, bool);
+
+    /// Parameters of the link between `a` and `b`, those of `config`
+    /// unless the topology gives the link its own.
+    fn link_config(&self, _a: DimmId, _b: DimmId, config: NetworkConfig) -> NetworkConfig {
+        config
+    }
+
+    /// Directed links of a spanning tree rooted at `root`, along which a
+    /// broadcast reaches every other DIMM crossing each link at most once.
+    fn broadcast_tree(&self, root: DimmId) -> Vec<(DimmId, DimmId)> {
+        // Built from the unicast routes so that a broadcast reaches each DIMM
+        // in as many hops as a unicast message would.
+        let mut reached = vec![false; self.get_num_dimms() as usize];
+        reached[root.0 as usize] = true;
+        let mut tree = Vec::new();
+        for d in 0..self.get_num_dimms() {
+            if d == root.0 {
+                continue;
+            }
+            for (from, to) in self.get_route(root, DimmId(d)) {
+                if !reached[to.0 as usize] {
+                    reached[to.0 as usize] = true;
+                    tree.push((from, to));
+                }
+            }
+        }
+        tree
+    }
+
+    /// Whether the routes can deadlock with bounded link buffers. A message
+    /// holds its slot of a link while it waits for the next link of its
+    /// route, so buffers can fill up in a circle of links each waiting for
+    /// the next one exactly when these waits form a cycle (Dally and Seitz).
+    fn has_cyclic_routes(&self) -> bool {
+        let n = self.get_num_dimms();
+        let mut waits_for: HashMap<(u8, u8), HashSet<(u8, u8)>> = HashMap::new();
+        for from in 0..n {
+            for to in (0..n).filter(|&to| to != from) {
+                let route = self.get_route(DimmId(from), DimmId(to));
+                for hops in route.windows(2) {
+                    waits_for
+                        .entry((hops[0].0 .0, hops[0].1 .0))
+                        .or_default()
+                        .insert((hops[1].0 .0, hops[1].1 .0));
+                }
+            }
+        }
+        // Depth-first search for a link that waits for itself, where a link
+        // on the stack is `false` and a finished one `true`
+        fn visit(
+            link: (u8, u8),
+            waits_for: &HashMap<(u8, u8), HashSet<(u8, u8)>>,
+            finished: &mut HashMap<(u8, u8), bool>,
+        ) -> bool {
+            match finished.get(&link) {
+                Some(&done) => return !done,
+                None => finished.insert(link, false),
+            };
+            let cyclic = waits_for
+                .get(&link)
+                .is_some_and(|next| next.iter().any(|&l| visit(l, waits_for, finished)));
+            finished.insert(link, true);
+            cyclic
+        }
+        let mut finished = HashMap::new();
+        let mut links: Vec<_> = waits_for.keys().copied().collect();
+        links.sort();
+        links
+            .into_iter()
+            .any(|link| visit(link, &waits_for, &mut finished))
+    }
 }
 
 /// Builds a label for a DIMM showing its ID, physical location, and the processor/rank IDs on it.
 fn dimm_label(dimm_id: DimmId) -> String {
     let mut ranks = Vec::new();
-    for rank_bit in 0..2u8 {
+    for rank_bit in 0..ranks_per_dimm() as u8 {
         let mut rid = RankId(0);
         rid.set_channel(dimm_id.channel());
         rid.set_dimm(dimm_id.dimm());
@@ -136,6 +210,9 @@ pub(super) struct RingTopology {
     dimm_at: [DimmId; 4],
     /// Inverse: `position_of[dimm_id]` gives its index in the ring.
     pub(super) position_of: [usize; 4],
+    /// Cost of the link from each position to the next, which routes
+    /// minimize before the number of hops
+    weights: [u64; 4],
 }
 
 impl RingTopology {
@@ -152,8 +229,32 @@ impl RingTopology {
         RingTopology {
             dimm_at,
             position_of,
+            weights: [1; 4],
         }
     }
+
+    /// The ring with link `i` between positions `i` and `i + 1` costing
+    /// `weights[i]`, such as its latency, or more for links that are
+    /// slower or shared with other traffic.
+    pub(super) fn with_link_weights(mut self, weights: [u64; 4]) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Cost of going `hops` links from `from_pos`, clockwise or not
+    fn cost(&self, from_pos: usize, hops: usize, clockwise: bool) -> u64 {
+        let n = Self::N;
+        (0..hops)
+            .map(|step| {
+                let link = if clockwise {
+                    (from_pos + step) % n
+                } else {
+                    (from_pos + 2 * n - step - 1) % n
+                };
+                self.weights[link]
+            })
+            .sum()
+    }
 }
 
 impl Topology for RingTopology {
@@ -168,10 +269,14 @@ impl Topology for RingTopology {
         // Counter-clockwise distance
         let ccw_dist = (from_pos + n - to_pos) % n;
 
+        // The lighter direction, then the one with fewer hops
+        let cw = (self.cost(from_pos, cw_dist, true), cw_dist);
+        let ccw = (self.cost(from_pos, ccw_dist, false), ccw_dist);
+
         let mut route = Vec::new();
         // Break ties for diametrically opposite nodes: even source positions route clockwise,
         // odd source positions route counter-clockwise. This balances traffic across the ring.
-        if cw_dist < ccw_dist || (cw_dist == ccw_dist && from_pos.is_multiple_of(2)) {
+        if cw < ccw || (cw == ccw && from_pos.is_multiple_of(2)) {
             // Go clockwise
             for step in 0..cw_dist {
                 let cur = (from_pos + step) % n;
@@ -266,6 +371,230 @@ impl Topology for FullyConnectedTopology {
     }
 }
 
+// ─── Topology From a File ───────────────────────────────────────────────────
+
+/// A link of a [`TopologyFile`], between two DIMMs numbered as [`DimmId`]s
+/// (channel in bit 0, DIMM of the channel in bit 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LinkDescription {
+    between: [u8; 2],
+    /// Ticks of wire and router delay, `--link-latency` if not given
+    latency: Option<usize>,
+    /// Bytes of a flit, `--link-width` if not given
+    width: Option<usize>,
+}
+
+/// The DIMMs and links of `--topology-file`, such as
+///
+/// ```toml
+/// dimms = 4
+/// links = [
+///     { between = [0, 2] },
+///     { between = [2, 1], latency = 4 },
+///     { between = [1, 3] },
+///     { between = [0, 1], width = 8 },
+/// ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TopologyFile {
+    dimms: u8,
+    links: Vec<LinkDescription>,
+}
+
+impl TopologyFile {
+    /// Parses and checks a topology from a TOML file, used as a clap value
+    /// parser. Boxed to keep `SimulationArgs` small.
+    pub(crate) fn from_file(path: &str) -> Result<Box<Self>, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read topology {}: {}", path, e))?;
+        let topology: TopologyFile = toml::from_str(&content)
+            .map_err(|e| format!("Failed to parse topology {}: {}", path, e))?;
+        topology
+            .check()
+            .map_err(|e| format!("Invalid topology {}: {}", path, e))?;
+        Ok(Box::new(topology))
+    }
+
+    /// DIMMs of the topology, which have to be those the processors are on
+    pub(crate) fn dimms(&self) -> usize {
+        self.dimms as usize
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let mut seen = HashSet::new();
+        for link in &self.links {
+            let [a, b] = link.between;
+            if a >= self.dimms || b >= self.dimms {
+                return Err(format!(
+                    "link {:?} to a DIMM past the {}",
+                    link.between, self.dimms
+                ));
+            }
+            if a == b {
+                return Err(format!("link {:?} from a DIMM to itself", link.between));
+            }
+            if !seen.insert((a.min(b), a.max(b))) {
+                return Err(format!("link {:?} given twice", link.between));
+            }
+            if link.width == Some(0) {
+                return Err(format!("link {:?} is not a byte wide", link.between));
+            }
+        }
+        // Every DIMM has to reach DIMM 0
+        let mut reached = vec![false; self.dimms as usize];
+        let mut stack = vec![0u8];
+        while let Some(d) = stack.pop() {
+            if std::mem::replace(&mut reached[d as usize], true) {
+                continue;
+            }
+            for link in &self.links {
+                let [a, b] = link.between;
+                if a == d || b == d {
+                    stack.push(if a == d { b } else { a });
+                }
+            }
+        }
+        match reached.iter().position(|&r| !r) {
+            Some(d) if self.dimms > 0 => Err(format!("DIMM {} is not connected to DIMM 0", d)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A topology from a [`TopologyFile`]. Each message takes the route on
+/// which a one-word message arrives first, then the one with the fewest
+/// hops, then the first found, from a table of all-pairs shortest paths.
+pub(super) struct GraphTopology {
+    links: Vec<LinkDescription>,
+    config: NetworkConfig,
+    /// `next_hop[from][to]`, the DIMM a message goes to first
+    next_hop: Vec<Vec<u8>>,
+}
+
+impl GraphTopology {
+    /// The topology of `file` with the links not given their own parameters
+    /// taking those of `config`.
+    pub(super) fn new(file: &TopologyFile, config: NetworkConfig) -> Self {
+        let n = file.dimms as usize;
+        let mut topology = GraphTopology {
+            links: file.links.clone(),
+            config,
+            next_hop: vec![vec![0; n]; n],
+        };
+        // Floyd-Warshall over (ticks, hops), only taking strictly better
+        // paths so that ties go to the first found
+        let mut distance = vec![vec![(usize::MAX, usize::MAX); n]; n];
+        for (i, row) in distance.iter_mut().enumerate() {
+            row[i] = (0, 0);
+            topology.next_hop[i][i] = i as u8;
+        }
+        for link in &file.links {
+            let [a, b] = link.between;
+            let ticks = topology
+                .link_config(DimmId(a), DimmId(b), config)
+                .hop_latency(1);
+            for (from, to) in [(a, b), (b, a)] {
+                distance[from as usize][to as usize] = (ticks, 1);
+                topology.next_hop[from as usize][to as usize] = to;
+            }
+        }
+        for k in 0..n {
+            for i in 0..n {
+                for j in 0..n {
+                    let (ik, kj) = (distance[i][k], distance[k][j]);
+                    if ik.0 == usize::MAX || kj.0 == usize::MAX {
+                        continue;
+                    }
+                    let through = (ik.0 + kj.0, ik.1 + kj.1);
+                    if through < distance[i][j] {
+                        distance[i][j] = through;
+                        topology.next_hop[i][j] = topology.next_hop[i][k];
+                    }
+                }
+            }
+        }
+        topology
+    }
+
+    fn link(&self, a: DimmId, b: DimmId) -> Option<(usize, &LinkDescription)> {
+        self.links
+            .iter()
+            .enumerate()
+            .find(|(_, l)| l.between == [a.0, b.0] || l.between == [b.0, a.0])
+    }
+}
+
+impl Debug for GraphTopology {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GraphTopology {{ links: [")?;
+        for (i, link) in self.links.iter().enumerate() {
+            let config = self.link_config(
+                DimmId(link.between[0]),
+                DimmId(link.between[1]),
+                self.config,
+            );
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}-{} ({} + {} ticks, {} B)",
+                link.between[0],
+                link.between[1],
+                config.flits(1),
+                config.link_latency,
+                config.link_width
+            )?;
+        }
+        write!(f, "] }}")
+    }
+}
+
+impl Topology for GraphTopology {
+    fn get_route(&self, from_dimm: DimmId, to_dimm: DimmId) -> Vec<(DimmId, DimmId)> {
+        debug_assert_ne!(from_dimm, to_dimm);
+        let mut route = Vec::new();
+        let mut at = from_dimm;
+        while at != to_dimm {
+            let next = DimmId(self.next_hop[at.0 as usize][to_dimm.0 as usize]);
+            route.push((at, next));
+            at = next;
+        }
+        route
+    }
+
+    fn get_links(&self) -> Vec<(DimmId, DimmId)> {
+        self.links
+            .iter()
+            .map(|l| {
+                let [a, b] = l.between;
+                (DimmId(a.min(b)), DimmId(a.max(b)))
+            })
+            .collect()
+    }
+
+    fn get_num_dimms(&self) -> u8 {
+        self.next_hop.len() as u8
+    }
+
+    fn link_sort_key(&self, from_dimm: DimmId, to_dimm: DimmId) -> (usize, bool) {
+        let (index, link) = self
+            .link(from_dimm, to_dimm)
+            .expect("link not in the topology file");
+        (index, link.between[0] != from_dimm.0)
+    }
+
+    fn link_config(&self, a: DimmId, b: DimmId, mut config: NetworkConfig) -> NetworkConfig {
+        if let Some((_, link)) = self.link(a, b) {
+            config.link_latency = link.latency.unwrap_or(config.link_latency);
+            config.link_width = link.width.unwrap_or(config.link_width);
+        }
+        config
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -488,6 +817,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_ring_topology_link_weights() {
+        // Ring 0-2-1-3-0 with a slow link between 2 and 1
+        let topology = RingTopology::new().with_link_weights([1, 5, 1, 1]);
+        // Around the slow link, which takes the same hops
+        assert_eq!(
+            topology.get_route(DimmId(0), DimmId(1)),
+            vec![(DimmId(0), DimmId(3)), (DimmId(3), DimmId(1))]
+        );
+        // Three hops rather than the one slow one
+        assert_eq!(
+            topology.get_route(DimmId(1), DimmId(2)),
+            vec![
+                (DimmId(1), DimmId(3)),
+                (DimmId(3), DimmId(0)),
+                (DimmId(0), DimmId(2))
+            ]
+        );
+        // Equal costs fall back to the fewest hops
+        let topology = RingTopology::new().with_link_weights([1, 3, 1, 1]);
+        assert_eq!(
+            topology.get_route(DimmId(1), DimmId(2)),
+            vec![(DimmId(1), DimmId(2))]
+        );
+        // Then to the parity of the source, whatever the weights
+        let topology = RingTopology::new().with_link_weights([2, 1, 1, 2]);
+        assert_eq!(
+            topology.get_route(DimmId(0), DimmId(1)),
+            vec![(DimmId(0), DimmId(2)), (DimmId(2), DimmId(1))]
+        );
+        // Unless they make one way lighter
+        assert_eq!(
+            topology.get_route(DimmId(2), DimmId(3)),
+            vec![(DimmId(2), DimmId(1)), (DimmId(1), DimmId(3))]
+        );
+    }
+
     #[test]
     fn test_ring_topology_route_symmetry() {
         let topology = RingTopology::new();
@@ -544,4 +910,109 @@ mod tests {
             }
         }
     }
+
+    // ─── Topology From a File ───────────────────────────────────────────
+
+    #[test]
+    fn test_graph_topology() {
+        // A line 0-2-1-3 with a slow chord from 0 to 3 and a wide one from
+        // 0 to 1
+        let file: TopologyFile = toml::from_str(
+            r#"
+            dimms = 4
+            links = [
+                { between = [0, 2] },
+                { between = [2, 1] },
+                { between = [1, 3] },
+                { between = [0, 3], latency = 20 },
+                { between = [1, 0], width = 8 },
+            ]
+            "#,
+        )
+        .unwrap();
+        file.check().unwrap();
+        let topology = GraphTopology::new(&file, NetworkConfig::default());
+        assert_eq!(topology.get_links().len(), 5);
+        // The wide chord takes a word in one flit
+        assert_eq!(
+            topology.get_route(DimmId(0), DimmId(1)),
+            vec![(DimmId(0), DimmId(1))]
+        );
+        // Two fast hops rather than the slow chord
+        assert_eq!(
+            topology.get_route(DimmId(3), DimmId(0)),
+            vec![(DimmId(3), DimmId(1)), (DimmId(1), DimmId(0))]
+        );
+        let chord = topology.link_config(DimmId(3), DimmId(0), NetworkConfig::default());
+        assert_eq!(chord.link_latency, 20);
+        assert_eq!(chord.link_width, NetworkConfig::default().link_width);
+        // Links sort in the order of the file
+        assert_eq!(topology.link_sort_key(DimmId(1), DimmId(2)), (1, true));
+
+        let invalid = |toml: &str| toml::from_str::<TopologyFile>(toml).unwrap().check();
+        assert!(invalid("dimms = 4\nlinks = [{ between = [0, 4] }]").is_err());
+        assert!(
+            invalid("dimms = 2\nlinks = [{ between = [0, 1] }, { between = [1, 0] }]").is_err()
+        );
+        // DIMM 2 is on its own
+        assert!(invalid("dimms = 3\nlinks = [{ between = [0, 1] }]").is_err());
+    }
+
+    #[test]
+    fn test_cyclic_routes() {
+        assert!(!LineTopology::new().has_cyclic_routes());
+        assert!(!FullyConnectedTopology::new(4).has_cyclic_routes());
+        // Around a ring of five, 0→2 waits for 1→2 while 1→3 waits for 2→3
+        // and so on all the way around
+        let file: TopologyFile = toml::from_str(
+            r#"
+            dimms = 5
+            links = [
+                { between = [0, 1] },
+                { between = [1, 2] },
+                { between = [2, 3] },
+                { between = [3, 4] },
+                { between = [4, 0] },
+            ]
+            "#,
+        )
+        .unwrap();
+        let ring = GraphTopology::new(&file, NetworkConfig::default());
+        assert!(ring.has_cyclic_routes());
+        let chord: TopologyFile =
+            toml::from_str(include_str!("../../../configs/topology_chord.toml")).unwrap();
+        let chord = GraphTopology::new(&chord, NetworkConfig::default());
+        assert!(!chord.has_cyclic_routes());
+    }
+
+    // ─── Broadcast Trees ────────────────────────────────────────────────
+
+    #[test]
+    fn test_broadcast_tree() {
+        // Line 0-2-1-3 rooted in the middle fans out both ways
+        let tree = LineTopology::new().broadcast_tree(DimmId(2));
+        assert_eq!(
+            tree,
+            vec![
+                (DimmId(2), DimmId(0)),
+                (DimmId(2), DimmId(1)),
+                (DimmId(1), DimmId(3))
+            ]
+        );
+        // Every non-root DIMM has exactly one parent in every topology
+        let topologies: Vec<Box<dyn Topology>> = vec![
+            Box::new(LineTopology::new()),
+            Box::new(RingTopology::new()),
+            Box::new(FullyConnectedTopology::new(4)),
+        ];
+        for topology in &topologies {
+            for root in 0u8..4 {
+                let tree = topology.broadcast_tree(DimmId(root));
+                let mut children: Vec<_> = tree.iter().map(|&(_, to)| to.0).collect();
+                children.sort();
+                let expected: Vec<_> = (0u8..4).filter(|&d| d != root).collect();
+                assert_eq!(children, expected);
+            }
+        }
+    }
 }
@@ -1,15 +1,18 @@
+use super::termination::ProbeReply;
+use super::tib_fetch::OOP_MAP_ENTRY_BYTES;
 use super::NMPProcessor;
 use crate::{
+    root_scan::Frame,
     simulate::{
-        memory::{DataCache, VirtualAddress},
+        memory::{DataCache, DimmId, RankId, SetAssociativeCache, VirtualAddress},
         nmpgc::NMPGC,
     },
-    trace::trace_object,
+    trace::{marker_map, trace_object},
     *,
 };
-use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Each processor generates at most one message per tick
 pub(super) struct NMPMessage {
     pub(super) recipient: usize,
@@ -18,34 +21,138 @@ pub(super) struct NMPMessage {
 
 impl NMPMessage {
     #[cfg(test)]
-    pub(super) fn new_mark(recipient: usize, addr: u64) -> Self {
+    pub(super) fn new_mark(recipient: usize, o: ObjectReference) -> Self {
         NMPMessage {
             recipient,
-            work: NMPMessageWork::Mark(addr),
+            work: NMPMessageWork::Mark(o),
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn new_load(recipient: usize, e: Address) -> Self {
+        NMPMessage {
+            recipient,
+            work: NMPMessageWork::Load(e),
+        }
+    }
+
+    pub(super) fn new_scan_range(recipient: usize, first: Address, slots: u64) -> Self {
+        NMPMessage {
+            recipient,
+            work: NMPMessageWork::ScanRange(first, slots),
+        }
+    }
+
+    /// Size of the message payload in 8-byte words.
+    pub(super) fn words(&self) -> usize {
+        match &self.work {
+            NMPMessageWork::LoadBatch(slots) => slots.len(),
+            NMPMessageWork::ScanRange(..) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Messages that go to every other processor rather than `recipient`.
+    pub(super) fn is_broadcast(&self) -> bool {
+        matches!(
+            self.work,
+            NMPMessageWork::Probe(_) | NMPMessageWork::Terminate { .. }
+        )
+    }
+
+    /// The object or first slot the tracing work of a data message is about.
+    pub(super) fn target(&self) -> Option<Address> {
+        match &self.work {
+            NMPMessageWork::Mark(o) => Some(o.to_address()),
+            NMPMessageWork::Load(e) => Some(*e),
+            NMPMessageWork::LoadBatch(slots) => Some(slots[0]),
+            NMPMessageWork::ScanRange(first, _) => Some(*first),
+            _ => None,
+        }
+    }
+
+    /// Messages that carry tracing work, as opposed to control messages.
+    pub(super) fn is_data(&self) -> bool {
+        matches!(
+            self.work,
+            NMPMessageWork::Mark(_)
+                | NMPMessageWork::Load(_)
+                | NMPMessageWork::LoadBatch(_)
+                | NMPMessageWork::ScanRange(..)
+        )
+    }
+
+    /// The copy of a broadcast delivered to `recipient`.
+    pub(super) fn copy_for(&self, recipient: usize) -> Self {
+        NMPMessage {
+            recipient,
+            work: self.work.clone(),
+        }
+    }
+
+    fn broadcast(sender: usize, work: NMPMessageWork) -> Self {
+        NMPMessage {
+            // Filled in for each copy on delivery
+            recipient: sender,
+            work,
+        }
+    }
+
+    /// Tries to append another `Load` target to this message, so that slots
+    /// of the same object owned by the same remote processor travel together.
+    pub(super) fn coalesce_load(&mut self, e: Address, max_targets: usize) -> bool {
+        match &mut self.work {
+            NMPMessageWork::Load(first) if max_targets > 1 => {
+                self.work = NMPMessageWork::LoadBatch(vec![*first, e]);
+                true
+            }
+            NMPMessageWork::LoadBatch(slots) if slots.len() < max_targets => {
+                slots.push(e);
+                true
+            }
+            _ => false,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(super) enum NMPMessageWork {
-    Mark(u64),
-    Load(*mut u64),
+    Mark(ObjectReference),
+    Load(Address),
+    /// Several `Load` targets coalesced by the sender
+    LoadBatch(Vec<Address>),
+    /// Slots of an object array for the scan engine of the recipient, as
+    /// the first slot and the number of slots
+    ScanRange(Address, u64),
+    /// Broadcast by the termination coordinator to start a wave
+    Probe(usize),
+    /// Answer to a probe, sent to the coordinator
+    ProbeReply(usize, ProbeReply),
+    /// Broadcast once termination is detected, carrying the mark sense of
+    /// the next collection
+    Terminate {
+        mark_sense: u8,
+    },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(super) enum NMPProcessorWork {
-    Mark(u64),
-    Load(*mut u64),
+    Mark(ObjectReference),
+    Load(Address),
     Idle,
     ReadInbox,
     SendMessage(NMPMessage),
     ContinueScan,
     /// Placeholder work representing remaining stall cycles from a previous operation.
     Stall(usize),
+    /// Reads the root slots of a frame of a `--root-stacks` thread stack
+    ScanFrame(Frame),
+    /// Reads the TIB of an object being scanned, with `--tib-fetch`
+    LoadTIB(ObjectReference),
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, Serialize, Deserialize)]
 pub(super) enum NMPProcessorWorkType {
     Mark = 0,
     Load = 1,
@@ -54,9 +161,22 @@ pub(super) enum NMPProcessorWorkType {
     SendMessage = 4,
     ContinueScan = 5,
     Stall = 6,
+    ScanFrame = 7,
+    LoadTIB = 8,
 }
 
 impl NMPProcessorWork {
+    /// The object or slot of `Mark` and `Load` work, or of the message
+    /// carrying it.
+    pub(super) fn target(&self) -> Address {
+        match self {
+            NMPProcessorWork::Mark(o) => o.to_address(),
+            NMPProcessorWork::Load(e) => *e,
+            NMPProcessorWork::SendMessage(msg) => msg.target().unwrap(),
+            _ => unreachable!("{:?} is not tracing work", self),
+        }
+    }
+
     fn get_type(&self) -> NMPProcessorWorkType {
         match self {
             NMPProcessorWork::Mark(_) => NMPProcessorWorkType::Mark,
@@ -66,22 +186,251 @@ impl NMPProcessorWork {
             NMPProcessorWork::SendMessage(_) => NMPProcessorWorkType::SendMessage,
             NMPProcessorWork::ContinueScan => NMPProcessorWorkType::ContinueScan,
             NMPProcessorWork::Stall(_) => NMPProcessorWorkType::Stall,
+            NMPProcessorWork::ScanFrame(_) => NMPProcessorWorkType::ScanFrame,
+            NMPProcessorWork::LoadTIB(_) => NMPProcessorWorkType::LoadTIB,
         }
     }
 }
 
-/// Inserts `Stall` items at the front of the work queue if `latency > 1`.
-fn push_stall(works: &mut VecDeque<NMPProcessorWork>, latency: usize) {
-    if latency > 1 {
-        works.push_front(NMPProcessorWork::Stall(latency - 1));
-    }
-}
+/// The processor that runs the termination detection
+pub(super) const TERMINATION_COORDINATOR: usize = 0;
 
 impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
+    /// Inserts `Stall` items at the front of the work queue if `latency > 1`,
+    /// unless fast-forwarding, where work takes no time.
+    pub(super) fn push_stall(&mut self, latency: usize) {
+        if latency > 1 && !self.fast_forward {
+            self.works.push_front(NMPProcessorWork::Stall(latency - 1));
+        }
+    }
+
+    /// Whether work on memory owned by `owner` is done here instead of being
+    /// sent to it, which processors on the same DIMM can do through their
+    /// shared cache.
+    fn keep_local(&mut self, owner: usize) -> bool {
+        if owner == self.id {
+            return true;
+        }
+        let same_dimm = DimmId::from(RankId(owner as u8)) == DimmId::from(RankId(self.id as u8));
+        if self.dimm_sharing && same_dimm {
+            self.dimm_local_accesses += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Marks `o` in its header, or in the private bitmap of this processor
+    /// with `--private-marks`. Returns whether this processor has to scan
+    /// it, and how long marking took.
+    fn mark<O: ObjectModel>(&mut self, o: ObjectReference) -> (bool, usize) {
+        let addr = VirtualAddress::from(o);
+        let Some(private_marks) = self.private_marks.as_mut() else {
+            let read_latency = self.cache.read(addr);
+            if !unsafe { trace_object(o, self.mark_sense) } {
+                return (false, read_latency);
+            }
+            marker_map::record(o, self.id);
+            self.marked_objects += 1;
+            return (true, read_latency + self.cache.write(addr));
+        };
+        let (first, latency) = private_marks.mark(self.id, o, &mut self.cache);
+        if !first {
+            return (false, latency);
+        }
+        if NMPGC::<LOG_NUM_THREADS>::get_owner_processor(o.to_address()) != self.id {
+            private_marks.remote_writes_avoided += 1;
+        }
+        // The header mark is free, standing in for the union pass
+        if unsafe { trace_object(o, self.mark_sense) } {
+            marker_map::record(o, self.id);
+            self.marked_objects += 1;
+        } else {
+            private_marks.duplicates += 1;
+            let mut slots = 0;
+            O::scan_object(o, |_, repeat| slots += repeat as usize);
+            private_marks.duplicate_slots += slots;
+        }
+        // The header is still read to scan the object
+        (true, latency + self.cache.read(addr))
+    }
+
+    /// Queues up the follow-up work for a slot whose value is available.
+    fn enqueue_loaded_slot(&mut self, e: Address) {
+        self.enqueue_child(unsafe { e.load_object() });
+    }
+
+    /// Marks `child` here, or sends it to its owner.
+    fn enqueue_child(&mut self, child: Option<ObjectReference>) {
+        if let Some(child) = child {
+            let owner = NMPGC::<LOG_NUM_THREADS>::get_owner_processor(child.to_address());
+            if self.keep_local(owner) {
+                self.push_tracing_work(NMPProcessorWork::Mark(child));
+            } else {
+                let msg = NMPMessage {
+                    recipient: owner,
+                    work: NMPMessageWork::Mark(child),
+                };
+                self.push_tracing_work(NMPProcessorWork::SendMessage(msg));
+            }
+        }
+    }
+
+    /// Loads a slot without waiting for a miss to be serviced. The slot is
+    /// parked in an MSHR and its follow-up work is queued once the line
+    /// arrives, so the processor can overlap several independent misses.
+    fn non_blocking_load(&mut self, e: Address) {
+        let addr = VirtualAddress::from(e);
+        let line = self.cache.line_of(addr);
+        let mshrs = self.mshrs.as_mut().unwrap();
+        if mshrs.merge(line, e).is_ok() {
+            // The line is already being fetched, only the tag check is paid
+            self.push_stall(self.timing.load_dispatch + SetAssociativeCache::HIT_LATENCY);
+            return;
+        }
+        if self.cache.contains(addr) {
+            let latency = self.cache.read(addr);
+            self.push_stall(self.timing.load_dispatch + latency);
+            self.enqueue_loaded_slot(e);
+            return;
+        }
+        if mshrs.is_full() {
+            // Structural hazard: retry once the earliest fill frees an MSHR
+            let wait = mshrs.earliest_ready().unwrap() - self.ticks;
+            self.mshr_full_stalls += 1;
+            self.works.push_front(NMPProcessorWork::Load(e));
+            self.push_stall(wait);
+            return;
+        }
+        let latency = self.cache.read(addr);
+        mshrs.allocate(line, self.ticks + latency, e);
+        self.push_stall(self.timing.load_dispatch + SetAssociativeCache::HIT_LATENCY);
+    }
+
+    /// Appends a remote slot to the message for the previous slot if that
+    /// message is still waiting at the front of the queue and goes to the
+    /// same processor.
+    fn coalesce_remote_load(&mut self, owner: usize, e: Address) -> bool {
+        match self.works.front_mut() {
+            Some(NMPProcessorWork::SendMessage(msg)) if msg.recipient == owner => {
+                msg.coalesce_load(e, self.messaging.coalesce_loads)
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads the inbox next, ahead of the queued work, once it holds
+    /// `--inbox-priority` messages. A stall at the front is an operation
+    /// still in progress, so it is never overtaken.
+    fn prioritize_inbox(&mut self) {
+        let threshold = self.messaging.inbox_priority;
+        if threshold == 0 || self.inbox.len() < threshold {
+            return;
+        }
+        match self.works.front() {
+            None | Some(NMPProcessorWork::Stall(_)) | Some(NMPProcessorWork::ReadInbox) => {}
+            Some(_) => {
+                self.works.push_front(NMPProcessorWork::ReadInbox);
+                self.priority_inbox_reads += 1;
+            }
+        }
+    }
+
+    /// Counters and state reported to the termination coordinator.
+    fn probe_reply(&self) -> ProbeReply {
+        ProbeReply {
+            sent: self.data_sent,
+            received: self.data_received,
+            idle: self.locally_done(),
+        }
+    }
+
+    /// Starts a termination wave if this is the coordinator and it has run
+    /// out of work.
+    fn maybe_probe_termination(&mut self) {
+        if self.terminated || !self.locally_done() {
+            return;
+        }
+        let Some(detector) = self.termination.as_mut() else {
+            return;
+        };
+        if let Some(wave) = detector.start_wave(self.ticks) {
+            trace!("[P{}] starting termination wave {}", self.id, wave);
+            let probe = NMPMessage::broadcast(self.id, NMPMessageWork::Probe(wave));
+            self.works.push_back(NMPProcessorWork::SendMessage(probe));
+        }
+    }
+
+    fn handle_probe_reply(&mut self, wave: usize, reply: ProbeReply) {
+        let own = self.probe_reply();
+        let detector = self
+            .termination
+            .as_mut()
+            .expect("only the coordinator receives probe replies");
+        detector.record(wave, reply);
+        if detector.wave_complete() && detector.finish_wave(self.ticks, own) {
+            trace!("[P{}] termination detected in wave {}", self.id, wave);
+            // Flip the mark sense so that the next collection does not need
+            // to clear the mark bits of this one.
+            self.mark_sense ^= 1;
+            self.terminated = true;
+            let terminate = NMPMessage::broadcast(
+                self.id,
+                NMPMessageWork::Terminate {
+                    mark_sense: self.mark_sense,
+                },
+            );
+            self.works
+                .push_front(NMPProcessorWork::SendMessage(terminate));
+        }
+    }
+
     pub(super) fn tick<O: ObjectModel>(&mut self) -> Option<NMPMessage> {
         self.ticks += 1;
+        self.sample_queues();
+        self.cache.apply_invalidations();
+        self.cache.set_clock(self.ticks);
+        if let Some(cache) = self.tib_fetch.as_mut().and_then(|t| t.cache.as_mut()) {
+            cache.set_clock(self.ticks);
+        }
+
+        if let Some(prefetcher) = self.prefetcher.as_mut() {
+            prefetcher.tick(self.ticks, &mut self.cache);
+        }
+        if let Some(mshrs) = self.mshrs.as_mut() {
+            mshrs.sample();
+            for (_, slots) in mshrs.retire(self.ticks) {
+                for e in slots {
+                    self.enqueue_loaded_slot(e);
+                }
+            }
+        }
+        if let Some(engine) = self.scan_engine.as_mut() {
+            for e in engine.tick() {
+                self.enqueue_loaded_slot(e);
+            }
+        }
+
+        if self.blocked_message.is_some() {
+            // The link controller cannot take the message we sent, so the
+            // processor cannot retire anything until it gets a credit.
+            self.busy_ticks += 1;
+            self.link_stall_ticks += 1;
+            self.work_count
+                .entry(NMPProcessorWorkType::Stall)
+                .and_modify(|e| *e += 1)
+                .or_insert(1);
+            trace!("[P{}] stalling on a full link buffer", self.id);
+            return None;
+        }
 
-        let work = self.works.pop_front().unwrap_or(NMPProcessorWork::Idle);
+        self.refill_mark_queue();
+        self.prioritize_inbox();
+        let work = self
+            .works
+            .pop_front()
+            .or_else(|| self.pop_tenant_work())
+            .unwrap_or(NMPProcessorWork::Idle);
+        let work = self.apply_backpressure(work);
 
         // Stall: the processor is busy waiting for a previous operation to complete
         if let NMPProcessorWork::Stall(remaining) = work {
@@ -102,6 +451,30 @@ impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
             return None;
         }
 
+        if let Some(power) = self.power.as_mut() {
+            let wake_latency = if matches!(work, NMPProcessorWork::Idle) && self.inbox.is_empty() {
+                power.idle();
+                0
+            } else {
+                power.wake()
+            };
+            if wake_latency > 0 {
+                // Nothing runs until the processor is awake, this tick
+                // included
+                if !matches!(work, NMPProcessorWork::Idle) {
+                    self.works.push_front(work);
+                }
+                self.push_stall(wake_latency);
+                self.busy_ticks += 1;
+                self.work_count
+                    .entry(NMPProcessorWorkType::Stall)
+                    .and_modify(|e| *e += 1)
+                    .or_insert(1);
+                trace!("[P{}] waking up in {} ticks", self.id, wake_latency);
+                return None;
+            }
+        }
+
         if !matches!(work, NMPProcessorWork::Idle) {
             self.busy_ticks += 1;
         }
@@ -114,6 +487,18 @@ impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
             }
         }
 
+        if matches!(
+            work,
+            NMPProcessorWork::Mark(_)
+                | NMPProcessorWork::Load(_)
+                | NMPProcessorWork::ContinueScan
+                | NMPProcessorWork::ScanFrame(_)
+                | NMPProcessorWork::LoadTIB(_)
+        ) {
+            self.last_trace_tick = self.ticks;
+            self.record_tenant_work(&work);
+        }
+
         let mut ret = None;
         self.work_count
             .entry(work.get_type())
@@ -122,43 +507,57 @@ impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
         match work {
             NMPProcessorWork::Mark(o) => {
                 trace!("[P{}] marking object {}", self.id, o);
-                let read_latency = self.cache.read(VirtualAddress(o));
-                if unsafe { trace_object(o, 1) } {
-                    let write_latency = self.cache.write(VirtualAddress(o));
-                    push_stall(&mut self.works, read_latency + write_latency);
-                    self.marked_objects += 1;
+                let (marked, latency) = self.mark::<O>(o);
+                if marked {
+                    let mut write_latency = 0;
+                    if let Some(side_marks) = self.side_marks.as_mut() {
+                        write_latency += side_marks.mark(o, &mut self.cache);
+                    }
+                    self.push_stall(self.timing.mark_dispatch + latency + write_latency);
                     O::scan_object(o, |edge, repeat| {
                         // To avoid edges getting dereferenced when there's no edge
                         if repeat > 0 {
-                            self.edge_chunks.push((edge as u64, repeat));
+                            self.edge_chunks.push((edge, repeat));
                         }
                     });
                     self.edge_chunk_cursor = (0, 0);
+                    if self.streams::<O>(o) {
+                        self.offload_scan();
+                    }
+                    if let Some(prefetcher) = self.prefetcher.as_mut() {
+                        // Only slots we own turn into local Load work; the
+                        // others are loaded by their owners.
+                        for &(first_edge, count) in &self.edge_chunks {
+                            for i in 0..count {
+                                let e = first_edge.shift::<u64>(i as usize);
+                                if NMPGC::<LOG_NUM_THREADS>::get_owner_processor(e) == self.id {
+                                    prefetcher.request(e);
+                                }
+                            }
+                        }
+                    }
                     if !self.edge_chunks.is_empty() {
                         // To make sure we finish scanning the current object first
                         // Otherwise, we might end up doing other work, such as loading edges and marking objects
                         // and disrupts the current scanning process
                         self.works.push_front(NMPProcessorWork::ContinueScan);
                     }
+                    if self.tib_fetch.is_some() && O::tib_lookup_required(o) {
+                        // Where the slots are is only known once the TIB
+                        // arrives, so it goes before the scan
+                        self.works.push_front(NMPProcessorWork::LoadTIB(o));
+                    }
                 } else {
-                    push_stall(&mut self.works, read_latency);
+                    self.push_stall(self.timing.mark_dispatch + latency);
                 }
             }
             NMPProcessorWork::Load(e) => {
-                let child = unsafe { *e };
-                let latency = self.cache.read(VirtualAddress(e as u64));
-                push_stall(&mut self.works, latency);
-                if child != 0 {
-                    let owner = NMPGC::<LOG_NUM_THREADS>::get_owner_processor(child);
-                    if owner == self.id {
-                        self.works.push_back(NMPProcessorWork::Mark(child));
-                    } else {
-                        let msg = NMPMessage {
-                            recipient: owner,
-                            work: NMPMessageWork::Mark(child),
-                        };
-                        self.works.push_back(NMPProcessorWork::SendMessage(msg));
-                    }
+                if self.mshrs.is_some() {
+                    self.non_blocking_load(e);
+                } else {
+                    let latency = self.cache.read(VirtualAddress::from(e));
+                    self.push_stall(self.timing.load_dispatch + latency);
+                    self.enqueue_loaded_slot(e);
                 }
             }
             NMPProcessorWork::Idle => {
@@ -170,43 +569,83 @@ impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
                     if self.idle_start.is_none() {
                         self.idle_start = Some(self.ticks);
                     }
+                    self.maybe_probe_termination();
                 }
             }
             NMPProcessorWork::SendMessage(msg) => {
                 // Sender pays only the local DIMM-to-rank latency to hand the
                 // message to the link controller; the network fabric handles
                 // hop-by-hop transit.
-                push_stall(&mut self.works, self.dimm_to_rank_latency);
+                self.push_stall(self.timing.send_message(msg.words()));
                 trace!(
                     "[P{}] sending message to P{}: {:?}",
                     self.id,
                     msg.recipient,
                     msg.work
                 );
+                if msg.is_data() {
+                    self.data_sent += 1;
+                }
                 ret = Some(msg);
             }
             NMPProcessorWork::ReadInbox => {
-                push_stall(&mut self.works, self.dimm_to_rank_latency);
-                if let Some(msg) = self.inbox.pop() {
+                let mut words = 0;
+                let mut messages = 0;
+                while messages < self.messaging.inbox_batch {
+                    let Some(msg) = self.inbox.pop() else {
+                        break;
+                    };
                     trace!("[P{}] reading inbox message: {:?}", self.id, msg);
+                    words += msg.words();
+                    messages += 1;
+                    if msg.is_data() {
+                        self.data_received += 1;
+                    }
                     match msg.work {
                         NMPMessageWork::Load(e) => {
-                            self.works.push_back(NMPProcessorWork::Load(e));
+                            self.push_tracing_work(NMPProcessorWork::Load(e));
+                        }
+                        NMPMessageWork::LoadBatch(slots) => {
+                            for e in slots {
+                                self.push_tracing_work(NMPProcessorWork::Load(e));
+                            }
                         }
                         NMPMessageWork::Mark(o) => {
-                            self.works.push_back(NMPProcessorWork::Mark(o));
+                            self.push_tracing_work(NMPProcessorWork::Mark(o));
+                        }
+                        NMPMessageWork::ScanRange(first, slots) => {
+                            self.scan_engine.as_mut().unwrap().push(first, slots);
+                        }
+                        NMPMessageWork::Probe(wave) => {
+                            let reply = self.probe_reply();
+                            self.works
+                                .push_front(NMPProcessorWork::SendMessage(NMPMessage {
+                                    recipient: TERMINATION_COORDINATOR,
+                                    work: NMPMessageWork::ProbeReply(wave, reply),
+                                }));
+                        }
+                        NMPMessageWork::ProbeReply(wave, reply) => {
+                            self.handle_probe_reply(wave, reply);
+                        }
+                        NMPMessageWork::Terminate { mark_sense } => {
+                            self.mark_sense = mark_sense;
+                            self.terminated = true;
                         }
                     }
                 }
+                self.inbox_reads_saved += messages.saturating_sub(1);
+                self.push_stall(self.timing.read_inbox(words.max(1)));
             }
             NMPProcessorWork::ContinueScan => {
                 let (chunk_idx, edge_idx) = self.edge_chunk_cursor;
                 let (first_edge_in_chunk, edges_in_chunk) =
                     *self.edge_chunks.get(chunk_idx).unwrap();
-                let e = (first_edge_in_chunk as *mut u64).wrapping_add(edge_idx as usize);
-                let owner = NMPGC::<LOG_NUM_THREADS>::get_owner_processor(e as u64);
-                if owner == self.id {
-                    self.works.push_back(NMPProcessorWork::Load(e));
+                let e = first_edge_in_chunk.shift::<u64>(edge_idx as usize);
+                let owner = NMPGC::<LOG_NUM_THREADS>::get_owner_processor(e);
+                if self.keep_local(owner) {
+                    self.push_tracing_work(NMPProcessorWork::Load(e));
+                } else if self.coalesce_remote_load(owner, e) {
+                    self.messages_saved += 1;
                 } else {
                     // Eagerly publish work so others have work to do
                     self.works
@@ -228,6 +667,33 @@ impl<const LOG_NUM_THREADS: u8> NMPProcessor<LOG_NUM_THREADS> {
                     self.edge_chunks.clear();
                     self.edge_chunk_cursor = (0, 0);
                 }
+                // Pushed last so that the stall is at the front of the queue
+                self.push_stall(self.timing.continue_scan_dispatch);
+            }
+            NMPProcessorWork::ScanFrame(frame) => {
+                let mut latency = self.timing.frame_scan_dispatch;
+                for &(offset, r) in &frame.roots {
+                    latency += self.cache.read(VirtualAddress(frame.slot(offset)));
+                    self.enqueue_child(ObjectReference::from_raw(r));
+                }
+                self.root_frames += 1;
+                self.root_slots += frame.roots.len();
+                self.root_scan_end = self.ticks + latency.max(1) - 1;
+                self.push_stall(latency);
+            }
+            NMPProcessorWork::LoadTIB(o) => {
+                // Object arrays only need the TIB for their type, their
+                // slots follow the length
+                let runs = if unsafe { O::is_objarray(o) } {
+                    0
+                } else {
+                    self.edge_chunks.len() as u64
+                };
+                let bytes = std::mem::size_of::<O::Tib>() as u64 + runs * OOP_MAP_ENTRY_BYTES;
+                let tib_fetch = self.tib_fetch.as_mut().unwrap();
+                let latency =
+                    tib_fetch.load(Address::from_ptr(O::get_tib(o)), bytes, &mut self.cache);
+                self.push_stall(self.timing.load_tib_dispatch + latency);
             }
             NMPProcessorWork::Stall(_) => unreachable!("handled above"),
         }
@@ -28,6 +28,21 @@ pub(crate) struct TracingEvent {
 }
 
 impl TracingEvent {
+    pub(crate) fn new_processname_event(pid: u32, name: String) -> Self {
+        let mut args = HashMap::new();
+        args.insert("name".to_string(), Value::String(name));
+        Self {
+            name: "process_name".to_string(),
+            ph: "M".to_string(),
+            ts: 0.0,
+            pid,
+            tid: 0,
+            args,
+            dur: None,
+            s: None,
+        }
+    }
+
     pub(crate) fn new_threadname_event(pid: u32, tid: u32, name: String) -> Self {
         let mut args = HashMap::new();
         args.insert("name".to_string(), Value::String(name));
@@ -70,6 +85,26 @@ impl TracingEvent {
         }
     }
 
+    /// A sample of one or more counters, shown by Perfetto as a counter
+    /// track named `name` with one series per key of `values`.
+    pub(crate) fn new_counter_event(
+        pid: u32,
+        name: String,
+        ts: f64,
+        values: HashMap<String, Value>,
+    ) -> Self {
+        Self {
+            name,
+            ph: "C".to_string(),
+            ts,
+            pid,
+            tid: 0,
+            args: values,
+            dur: None,
+            s: None,
+        }
+    }
+
     #[allow(dead_code)]
     pub(crate) fn new_instant_event(
         pid: u32,
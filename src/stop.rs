@@ -0,0 +1,23 @@
+//! Stopping long runs early with ctrl-C while keeping what they measured so
+//! far. The first ctrl-C asks the simulation or tracing loop to stop at the
+//! next point where its statistics are consistent, the second one exits.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+pub fn install_stop_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if STOP.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        eprintln!("Stopping early and printing partial statistics, ctrl-C again to exit now");
+    })?;
+    Ok(())
+}
+
+/// Whether the run should stop as soon as it can.
+pub(crate) fn requested() -> bool {
+    STOP.load(Ordering::Relaxed)
+}
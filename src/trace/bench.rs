@@ -0,0 +1,192 @@
+//! Closures with precise phase boundaries for harnesses that time or
+//! simulate them from outside, such as zsim, which only want the closures
+//! themselves between their region-of-interest markers.
+//!
+//! [`bench_prepare`] restores a heapdump and starts the workers of the
+//! tracing loop, [`bench_iter`] runs one closure and nothing else, and
+//! [`bench_release`] stops the workers and unmaps the heap.
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use hwgc_soft::*;
+//!
+//! let args = TraceArgs::parse_from(["trace", "-t", "ParEdgeSlot"]);
+//! let heapdump = HeapDump::from_path("[synthetic]objarray_4000").unwrap();
+//! let mut bench = bench_prepare(OpenJDKObjectModel::<false>::new(), heapdump, args).unwrap();
+//! for _ in 0..5 {
+//!     // zsim_roi_begin()
+//!     let closure = bench_iter(&mut bench);
+//!     // zsim_roi_end()
+//!     println!("{} objects marked in {:?}", bench.marked_objects(), closure.time);
+//! }
+//! bench_release(bench).unwrap();
+//! ```
+
+use super::shape_cache::{self, ShapeLruCache};
+use super::util::tracer::Tracer;
+use super::{create_tracer, reset_marks, transitive_closure, TimedTracingStats};
+use crate::object_model::Header;
+use crate::*;
+use anyhow::Result;
+
+/// A restored heap and the workers tracing it, from [`bench_prepare`] to
+/// [`bench_release`].
+pub struct Bench<O: ObjectModel> {
+    heapdump: HeapDump,
+    object_model: O,
+    args: TraceArgs,
+    shape_cache: ShapeLruCache<O>,
+    tracer: Option<Box<dyn Tracer<O>>>,
+    mark_sense: u8,
+    iterations: usize,
+    mapped: bool,
+}
+
+impl<O: ObjectModel> Bench<O> {
+    /// The mark byte of the objects marked by the last closure, or 0 before
+    /// the first
+    pub fn mark_sense(&self) -> u8 {
+        self.mark_sense
+    }
+
+    /// Closures run so far
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Objects marked by the last closure, counted on the heap, so with or
+    /// without `detailed_stats`. This walks the whole heap, so call it
+    /// outside the timed region.
+    pub fn marked_objects(&self) -> usize {
+        if self.iterations == 0 {
+            return 0;
+        }
+        self.object_model
+            .objects_iter()
+            .filter(|&o| Header::load(o).get_mark_byte() == self.mark_sense)
+            .count()
+    }
+
+    fn release(&mut self) -> Result<()> {
+        if let Some(tracer) = self.tracer.take() {
+            tracer.teardown();
+        }
+        if std::mem::take(&mut self.mapped) {
+            self.heapdump.unmap_spaces()?;
+        }
+        Ok(())
+    }
+}
+
+impl<O: ObjectModel> Drop for Bench<O> {
+    fn drop(&mut self) {
+        if let Err(e) = self.release() {
+            warn!("Failed to unmap the heap of a bench: {}", e);
+        }
+    }
+}
+
+/// Restores `heapdump` in `object_model` and starts the workers of the
+/// tracing loop of `args`, so that none of it lands in the first closure.
+///
+/// Heaps are mapped at the addresses of the heapdump, so only one bench per
+/// heap address range can be prepared at a time.
+pub fn bench_prepare<O: ObjectModel>(
+    mut object_model: O,
+    heapdump: HeapDump,
+    args: TraceArgs,
+) -> Result<Bench<O>> {
+    shape_cache::check_args(&args)?;
+    heapdump.map_spaces()?;
+    object_model.restore_tibs(&heapdump);
+    object_model.restore_objects(&heapdump);
+    let tracer = create_tracer::<O>(&args);
+    if let Some(tracer) = tracer.as_ref() {
+        tracer.startup(&object_model)?;
+    }
+    Ok(Bench {
+        heapdump,
+        object_model,
+        shape_cache: ShapeLruCache::new(args.shape_cache_size)
+            .with_associativity(args.shape_cache_ways, args.shape_cache_index)
+            .with_levels(args.shape_victim_cache, args.shape_l2_cache),
+        args,
+        tracer,
+        // Restored objects have a zero mark byte
+        mark_sense: 0,
+        iterations: 0,
+        mapped: true,
+    })
+}
+
+/// Runs one transitive closure from the roots of the heapdump. The mark
+/// sense flips between 1 and 0 like in `trace`, starting from the 0 of
+/// restored objects, and every mark byte is set to the other sense before
+/// the closure, outside of its time, so that objects the closure does not
+/// reach are never counted as marked.
+pub fn bench_iter<O: ObjectModel>(bench: &mut Bench<O>) -> TimedTracingStats {
+    bench.mark_sense ^= 1;
+    bench.iterations += 1;
+    reset_marks(&bench.object_model, bench.mark_sense ^ 1);
+    transitive_closure(
+        &bench.args,
+        bench.mark_sense,
+        &mut bench.object_model,
+        &mut bench.shape_cache,
+        bench.tracer.as_deref(),
+    )
+}
+
+/// Stops the workers of the tracing loop and unmaps the heap.
+pub fn bench_release<O: ObjectModel>(mut bench: Bench<O>) -> Result<()> {
+    bench.release()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+    use clap::Parser;
+
+    #[test]
+    fn test_bench() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        for tracing_loop in ["EdgeSlot", "ParEdgeSlot"] {
+            let args = TraceArgs::parse_from(["trace", "-t", tracing_loop, "--threads", "2"]);
+            let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+            let mut bench =
+                bench_prepare(OpenJDKObjectModel::<false>::new(), heapdump, args).unwrap();
+            assert_eq!(bench.marked_objects(), 0);
+            let mut senses = vec![];
+            for _ in 0..3 {
+                bench_iter(&mut bench);
+                senses.push(bench.mark_sense());
+                assert_eq!(bench.marked_objects(), 65, "{}", tracing_loop);
+            }
+            assert_eq!(senses, [1, 0, 1]);
+            assert_eq!(bench.iterations(), 3);
+            bench_release(bench).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bench_unreachable() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        // Rooted at one of the elements instead of the array, the other 64
+        // objects are unreachable and are not counted under either sense
+        let mut heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        let element = heapdump
+            .objects
+            .iter()
+            .find(|o| o.edges.is_empty())
+            .unwrap();
+        heapdump.roots[0].objref = element.start;
+        let args = TraceArgs::parse_from(["trace", "-t", "EdgeSlot"]);
+        let mut bench = bench_prepare(OpenJDKObjectModel::<false>::new(), heapdump, args).unwrap();
+        for _ in 0..3 {
+            bench_iter(&mut bench);
+            assert_eq!(bench.marked_objects(), 1);
+        }
+        bench_release(bench).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+//! DRAM traffic of compaction (`--compact-traffic`): the lines each moved
+//! object is read from and the destination lines it is written to, replayed
+//! in the order of the copies through the naive DDR4 ranks of the
+//! simulator, as if the heap were physically addressed. Sliding compaction
+//! stands in for evacuation here, since both copy the same candidates, and
+//! the write set is what a copying collector adds to the reads of tracing.
+
+use crate::heapdump::generated_src::Space;
+use crate::simulate::{DramReplay, LOG_LINE_SIZE};
+use crate::*;
+use std::collections::HashSet;
+
+/// Lines of the objects moved out of a space.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(super) struct SpaceTraffic {
+    pub(super) name: String,
+    pub(super) objects_moved: u64,
+    pub(super) lines_read: u64,
+    pub(super) lines_written: u64,
+}
+
+impl SpaceTraffic {
+    pub(super) fn write_fraction(&self) -> f64 {
+        self.lines_written as f64 / (self.lines_read + self.lines_written).max(1) as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct CompactionTraffic {
+    /// Spaces that any object moved out of, in the order of the heapdump
+    pub(super) spaces: Vec<SpaceTraffic>,
+    /// DRAM cycles of the busiest rank
+    pub(super) dram_ticks: usize,
+    pub(super) ranks: usize,
+    pub(super) row_hit_rate: f64,
+}
+
+impl CompactionTraffic {
+    pub(super) fn bytes(&self) -> u64 {
+        let lines: u64 = self
+            .spaces
+            .iter()
+            .map(|s| s.lines_read + s.lines_written)
+            .sum();
+        lines << LOG_LINE_SIZE
+    }
+
+    /// Bandwidth in GB/s the copy needs to take as long as the ranks do.
+    pub(super) fn bandwidth_gbps(&self) -> f64 {
+        let seconds = self.dram_ticks as f64 / (DramReplay::FREQUENCY_GHZ * 1e9);
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.bytes() as f64 / seconds / 1e9
+    }
+}
+
+/// Reads each source line and writes each destination line of `moves`
+/// once, a line shared by neighbouring objects counting for the first.
+pub(super) fn compaction_traffic(spaces: &[Space], moves: &[ObjectMove]) -> CompactionTraffic {
+    let mut traffic: Vec<SpaceTraffic> = spaces
+        .iter()
+        .map(|s| SpaceTraffic {
+            name: s.name.clone(),
+            ..Default::default()
+        })
+        .collect();
+    let mut read_set: HashSet<u64> = HashSet::new();
+    let mut write_set: HashSet<u64> = HashSet::new();
+    let mut dram = DramReplay::default();
+    let lines =
+        |start: u64, bytes: u64| (start >> LOG_LINE_SIZE)..=((start + bytes - 1) >> LOG_LINE_SIZE);
+    for m in moves {
+        let space = spaces
+            .iter()
+            .position(|s| (s.start..s.end).contains(&m.from))
+            .expect("Object moved from outside of any space");
+        let space = &mut traffic[space];
+        space.objects_moved += 1;
+        for line in lines(m.from, m.bytes) {
+            if read_set.insert(line) {
+                dram.access(line << LOG_LINE_SIZE, false);
+                space.lines_read += 1;
+            }
+        }
+        for line in lines(m.to, m.bytes) {
+            if write_set.insert(line) {
+                dram.access(line << LOG_LINE_SIZE, true);
+                space.lines_written += 1;
+            }
+        }
+    }
+    traffic.retain(|s| s.objects_moved > 0);
+    CompactionTraffic {
+        spaces: traffic,
+        dram_ticks: dram.ticks(),
+        ranks: dram.ranks(),
+        row_hit_rate: dram.row_hit_rate(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compaction_traffic() {
+        let spaces = [
+            Space {
+                name: "immix".into(),
+                start: 0,
+                end: 1 << 20,
+            },
+            Space {
+                name: "los".into(),
+                start: 1 << 20,
+                end: 2 << 20,
+            },
+            Space {
+                name: "immortal".into(),
+                start: 2 << 20,
+                end: 3 << 20,
+            },
+        ];
+        let moves = [
+            // Two objects sharing their source and destination lines
+            ObjectMove {
+                from: 128,
+                to: 0,
+                bytes: 24,
+            },
+            ObjectMove {
+                from: 152,
+                to: 24,
+                bytes: 24,
+            },
+            // Across two source lines, into one
+            ObjectMove {
+                from: (1 << 20) + 96,
+                to: 1 << 20,
+                bytes: 64,
+            },
+        ];
+        let traffic = compaction_traffic(&spaces, &moves);
+        assert_eq!(
+            traffic.spaces,
+            [
+                SpaceTraffic {
+                    name: "immix".into(),
+                    objects_moved: 2,
+                    lines_read: 1,
+                    lines_written: 1,
+                },
+                SpaceTraffic {
+                    name: "los".into(),
+                    objects_moved: 1,
+                    lines_read: 2,
+                    lines_written: 1,
+                },
+            ]
+        );
+        assert_eq!(traffic.spaces[1].write_fraction(), 1.0 / 3.0);
+        assert_eq!(traffic.bytes(), 5 * 64);
+        assert!(traffic.dram_ticks > 0);
+        assert!(traffic.bandwidth_gbps() > 0.0);
+        assert_eq!(compaction_traffic(&spaces, &[]).bandwidth_gbps(), 0.0);
+    }
+}
@@ -0,0 +1,292 @@
+//! Conformance of the object models and tracing loops on random heaps: every
+//! combination has to mark exactly the objects reachable in the heapdump, and
+//! scan the same number of slots.
+
+use super::selftest::model_addresses;
+use super::shape_cache::ShapeLruCache;
+use super::{create_tracer, transitive_closure};
+use crate::heapdump::generated_src::{NormalEdge, Space};
+use crate::object_model::Header;
+use crate::*;
+use clap::{Parser, ValueEnum};
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const HEAP_START: u64 = 0x20000000000;
+
+/// Restoring a heapdump maps its spaces at fixed addresses, and the tracing
+/// loops keep their state in globals, so only one case, or any other test
+/// that restores a heap there, can run at a time.
+pub(crate) static HEAP: Mutex<()> = Mutex::new(());
+
+/// The TIB caches live for the whole process and are indexed by klass only,
+/// even though the variants of an object model build different TIBs. So
+/// every klass id is used for one layout under one object model at most.
+static NEXT_KLASS: AtomicU64 = AtomicU64::new(0x1000);
+
+fn next_klass() -> u64 {
+    NEXT_KLASS.fetch_add(8, Ordering::Relaxed)
+}
+
+/// A copy of `heapdump` whose klasses have not been seen by any model yet.
+fn with_fresh_klasses(heapdump: &HeapDump) -> HeapDump {
+    let mut klasses: HashMap<u64, u64> = HashMap::new();
+    let mut heapdump = heapdump.clone();
+    for object in &mut heapdump.objects {
+        object.klass = *klasses.entry(object.klass).or_insert_with(next_klass);
+    }
+    heapdump
+}
+
+#[derive(Clone, Debug)]
+struct RandomHeap(HeapDump);
+
+#[derive(Clone)]
+enum Shape {
+    /// Number of fields after the header and klass words, and which of them
+    /// are references
+    Ordinary(Vec<bool>),
+    ObjArray,
+}
+
+impl Arbitrary for RandomHeap {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let num_klasses = 1 + usize::arbitrary(g) % 4;
+        let mut klasses: Vec<(u64, Shape)> = (0..num_klasses)
+            .map(|_| {
+                // Enough fields to exercise the alignment encoding fallback
+                // once in a while, and enough references to overflow the
+                // bidirectional header even more rarely.
+                let fields = match u8::arbitrary(g) % 16 {
+                    0 => vec![true; 300],
+                    n => (0..n % 10).map(|_| bool::arbitrary(g)).collect(),
+                };
+                (next_klass(), Shape::Ordinary(fields))
+            })
+            .collect();
+        klasses.push((next_klass(), Shape::ObjArray));
+
+        let num_objects = 1 + usize::arbitrary(g) % g.size();
+        let mut cursor = HEAP_START;
+        let mut objects: Vec<HeapObject> = (0..num_objects)
+            .map(|_| {
+                let (klass, shape) = g.choose(&klasses).unwrap().clone();
+                let start = cursor;
+                let (size, objarray_length, slots): (u64, _, Vec<u64>) = match shape {
+                    Shape::Ordinary(fields) => (
+                        16 + fields.len() as u64 * 8,
+                        None,
+                        (0..fields.len() as u64)
+                            .filter(|&i| fields[i as usize])
+                            .map(|i| start + 16 + i * 8)
+                            .collect(),
+                    ),
+                    Shape::ObjArray => {
+                        let length = u64::arbitrary(g) % 24;
+                        (
+                            24 + length * 8,
+                            Some(length),
+                            (0..length).map(|i| start + 24 + i * 8).collect(),
+                        )
+                    }
+                };
+                cursor += size;
+                HeapObject {
+                    start,
+                    klass,
+                    size,
+                    objarray_length,
+                    instance_mirror_start: None,
+                    instance_mirror_count: None,
+                    edges: slots
+                        .into_iter()
+                        .map(|slot| NormalEdge { slot, objref: 0 })
+                        .collect(),
+                    pinned: false,
+                }
+            })
+            .collect();
+        // Objects are laid out first, so that edges can point anywhere,
+        // including backwards and to the object itself.
+        let starts: Vec<u64> = objects.iter().map(|o| o.start).collect();
+        for object in &mut objects {
+            for edge in &mut object.edges {
+                if u8::arbitrary(g) % 4 != 0 {
+                    edge.objref = *g.choose(&starts).unwrap();
+                }
+            }
+        }
+        let roots = (0..1 + usize::arbitrary(g) % 4)
+            .map(|_| RootEdge {
+                objref: *g.choose(&starts).unwrap(),
+            })
+            .collect();
+        RandomHeap(HeapDump {
+            objects,
+            roots,
+            spaces: vec![Space {
+                name: "immix".to_string(),
+                start: HEAP_START,
+                end: cursor.next_multiple_of(4096),
+            }],
+            root_sets: vec![],
+            ..Default::default()
+        })
+    }
+}
+
+/// Reachable objects, by index in the heapdump, and their total number of
+/// slots, computed directly from the heapdump.
+fn reference_closure(heapdump: &HeapDump) -> (HashSet<usize>, u64) {
+    let index: HashMap<u64, usize> = heapdump
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(i, o)| (o.start, i))
+        .collect();
+    let mut reachable = HashSet::new();
+    let mut stack: Vec<u64> = heapdump.roots.iter().map(|r| r.objref).collect();
+    let mut slots = 0;
+    while let Some(o) = stack.pop() {
+        let i = index[&o];
+        if reachable.insert(i) {
+            let object = &heapdump.objects[i];
+            slots += object.edges.len() as u64;
+            stack.extend(object.edges.iter().map(|e| e.objref).filter(|&r| r != 0));
+        }
+    }
+    (reachable, slots)
+}
+
+fn check_object_model<O: ObjectModel>(mut object_model: O, heapdump: &HeapDump) {
+    let (reachable, reachable_slots) = reference_closure(heapdump);
+    object_model.reset();
+    object_model.restore_objects(heapdump);
+    let addresses = model_addresses(&object_model, heapdump);
+    let mut shape_cache: ShapeLruCache<O> = ShapeLruCache::new(16);
+    let model_name = std::any::type_name::<O>();
+    // The work-packet loops must mark the same objects whatever order their
+    // queues run packets in, and when they run packets over the budget inline
+    let configs = TracingLoopChoice::value_variants().iter().flat_map(|l| {
+        let configs: &[&[&str]] = match l {
+            TracingLoopChoice::WPEdgeSlot | TracingLoopChoice::WPEdgeSlotDual => &[
+                &[],
+                &["--queue-discipline", "Fifo"],
+                &[
+                    "--queue-discipline",
+                    "Hierarchical",
+                    "--local-queue-limit",
+                    "1",
+                ],
+                &["--packet-budget", "1"],
+                &["--slot-order", "Sorted"],
+                &["--slot-order", "Page"],
+                &["--packet-aging", "1"],
+            ],
+            TracingLoopChoice::DistributedNodeObjref => &[&[], &["--send-batch", "16"]],
+            _ => &[&[]],
+        };
+        configs.iter().map(move |c| (l, *c))
+    });
+    for (i, (l, config)) in configs.enumerate() {
+        let name = l.to_possible_value().unwrap();
+        let args = TraceArgs::parse_from(
+            ["trace", "-t", name.get_name(), "-i", "1", "--threads", "2"]
+                .iter()
+                .chain(config),
+        );
+        // Unreachable objects keep a zero mark byte, so alternate between
+        // two other values to tell them apart from marked ones.
+        let mark_sense = 1 + (i % 2) as u8;
+        let tracer = create_tracer::<O>(&args);
+        if let Some(tracer) = tracer.as_ref() {
+            tracer.startup(&object_model).unwrap();
+        }
+        let timed_stats = transitive_closure(
+            &args,
+            mark_sense,
+            &mut object_model,
+            &mut shape_cache,
+            tracer.as_deref(),
+        );
+        if let Some(tracer) = tracer.as_ref() {
+            tracer.teardown();
+        }
+        let marked: HashSet<usize> = (0..addresses.len())
+            .filter(|&i| Header::load(addresses[i]).get_mark_byte() == mark_sense)
+            .collect();
+        assert_eq!(
+            marked, reachable,
+            "{} {:?} {:?} marked other objects",
+            model_name, l, config
+        );
+        let mut slots = 0;
+        for &i in &marked {
+            slots += O::num_edges(addresses[i]);
+        }
+        assert_eq!(
+            slots, reachable_slots,
+            "{} {:?} {:?} scanned other slots",
+            model_name, l, config
+        );
+        if cfg!(feature = "detailed_stats") {
+            assert_eq!(
+                timed_stats.stats.marked_objects,
+                reachable.len() as u64,
+                "{} {:?} counted other objects",
+                model_name,
+                l
+            );
+        }
+    }
+}
+
+fn conformance(heap: RandomHeap) {
+    let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+    let heapdump = heap.0;
+    heapdump.map_spaces().unwrap();
+    // Unmap even if a check fails, so that the next case can map the heap
+    struct Unmap<'a>(&'a HeapDump);
+    impl Drop for Unmap<'_> {
+        fn drop(&mut self) {
+            self.0.unmap_spaces().unwrap();
+        }
+    }
+    let _unmap = Unmap(&heapdump);
+    for model in ObjectModelChoice::value_variants() {
+        // Every model writes its own layout over the previous one, so start
+        // from a zeroed heap each time.
+        unsafe {
+            let space = &heapdump.spaces[0];
+            std::ptr::write_bytes(
+                space.start as *mut u8,
+                0,
+                (space.end - space.start) as usize,
+            );
+        }
+        let heapdump = with_fresh_klasses(&heapdump);
+        match model {
+            ObjectModelChoice::OpenJDK => {
+                check_object_model(OpenJDKObjectModel::<false>::new(), &heapdump)
+            }
+            ObjectModelChoice::OpenJDKAE => {
+                check_object_model(OpenJDKObjectModel::<true>::new(), &heapdump)
+            }
+            ObjectModelChoice::Bidirectional => {
+                check_object_model(BidirectionalObjectModel::<true>::new(), &heapdump)
+            }
+            ObjectModelChoice::BidirectionalFallback => {
+                check_object_model(BidirectionalObjectModel::<false>::new(), &heapdump)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_conformance() {
+    QuickCheck::new()
+        .tests(32)
+        .quickcheck(conformance as fn(RandomHeap));
+}
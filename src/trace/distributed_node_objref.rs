@@ -1,5 +1,5 @@
-use super::{trace_object, TracingStats};
-use crate::ObjectModel;
+use super::{klass_profile, marker_map, trace_object, TracingStats};
+use crate::{ObjectModel, ObjectReference, TraceArgs};
 use crossbeam::channel::{unbounded, Receiver, Sender};
 use std::{
     collections::VecDeque,
@@ -9,12 +9,14 @@ use std::{
     },
 };
 
-type DistGCMsg = u64;
+/// Objects for the recipient to mark, up to `--send-batch` of them
+type DistGCMsg = Vec<ObjectReference>;
 
 static MARKED_OBJECTS: AtomicU64 = AtomicU64::new(0);
 static SLOTS: AtomicU64 = AtomicU64::new(0);
 static NON_EMPTY_SLOTS: AtomicU64 = AtomicU64::new(0);
 static SENDS: AtomicU64 = AtomicU64::new(0);
+static MESSAGES: AtomicU64 = AtomicU64::new(0);
 static PARKED_THREADS: AtomicUsize = AtomicUsize::new(0);
 
 const LOG_NUM_TREADS: usize = 3;
@@ -22,16 +24,19 @@ const NUM_THREADS: usize = 1 << LOG_NUM_TREADS;
 // we spread cache lines (2^6 = 64B) across four memory channels
 const OWNER_SHIFT: usize = 6;
 
-fn get_owner_thread(o: u64) -> usize {
+pub(super) fn get_owner_thread(o: ObjectReference) -> usize {
     let mask = ((NUM_THREADS - 1) << OWNER_SHIFT) as u64;
-    ((o & mask) >> OWNER_SHIFT) as usize
+    ((o.to_raw() & mask) >> OWNER_SHIFT) as usize
 }
 
 struct DistGCThread {
     id: usize,
     receiver: Receiver<DistGCMsg>,
     senders: Vec<Sender<DistGCMsg>>,
-    scan_queue: VecDeque<u64>,
+    /// Objects waiting to be sent to each other thread
+    outboxes: Vec<Vec<ObjectReference>>,
+    batch: usize,
+    scan_queue: VecDeque<ObjectReference>,
     barrier: Arc<Barrier>,
 }
 
@@ -40,17 +45,41 @@ impl DistGCThread {
         id: usize,
         receiver: Receiver<DistGCMsg>,
         senders: &[Sender<DistGCMsg>],
+        batch: usize,
         barrier: Arc<Barrier>,
     ) -> DistGCThread {
         DistGCThread {
             id,
             receiver,
             senders: senders.to_vec(),
+            outboxes: vec![vec![]; senders.len()],
+            batch,
             scan_queue: VecDeque::new(),
             barrier,
         }
     }
 
+    fn send(&mut self, owner: usize, o: ObjectReference) {
+        if cfg!(feature = "detailed_stats") {
+            SENDS.fetch_add(1, Ordering::Relaxed);
+        }
+        self.outboxes[owner].push(o);
+        if self.outboxes[owner].len() >= self.batch {
+            self.flush(owner);
+        }
+    }
+
+    fn flush(&mut self, owner: usize) {
+        if self.outboxes[owner].is_empty() {
+            return;
+        }
+        if cfg!(feature = "detailed_stats") {
+            MESSAGES.fetch_add(1, Ordering::Relaxed);
+        }
+        let batch = std::mem::replace(&mut self.outboxes[owner], Vec::with_capacity(self.batch));
+        self.senders[owner].send(batch).unwrap();
+    }
+
     unsafe fn run<O>(&mut self, mark_sense: u8)
     where
         O: ObjectModel,
@@ -59,19 +88,20 @@ impl DistGCThread {
         loop {
             while let Some(o) = self.scan_queue.pop_front() {
                 debug_assert_eq!(get_owner_thread(o), self.id);
-                O::scan_object(o, |edge, repeat| {
+                klass_profile::scan_object::<O, _>(o, |edge, repeat| {
                     for i in 0..repeat {
-                        let child = *edge.wrapping_add(i as usize);
+                        let child = edge.shift::<u64>(i as usize).load_object();
                         if cfg!(feature = "detailed_stats") {
                             SLOTS.fetch_add(1, Ordering::Relaxed);
                         }
-                        if child != 0 {
+                        if let Some(child) = child {
                             if cfg!(feature = "detailed_stats") {
                                 NON_EMPTY_SLOTS.fetch_add(1, Ordering::Relaxed);
                             }
                             let owner = get_owner_thread(child);
                             if owner == self.id {
                                 if trace_object(child, mark_sense) {
+                                    marker_map::record(child, self.id);
                                     if cfg!(feature = "detailed_stats") {
                                         MARKED_OBJECTS.fetch_add(1, Ordering::Relaxed);
                                     }
@@ -79,15 +109,17 @@ impl DistGCThread {
                                 }
                             } else {
                                 // trace!("{} -> {} {}", self.id, owner, child);
-                                if cfg!(feature = "detailed_stats") {
-                                    SENDS.fetch_add(1, Ordering::Relaxed);
-                                }
-                                self.senders[owner].send(child).unwrap();
+                                self.send(owner, child);
                             }
                         }
                     }
                 });
             }
+            // Partial batches go out before this thread can look idle to
+            // the others, or their objects would never be marked
+            for owner in 0..NUM_THREADS {
+                self.flush(owner);
+            }
             if self.receiver.is_empty() {
                 info!("Thread {} entering barrier", self.id);
                 self.barrier.wait();
@@ -107,12 +139,14 @@ impl DistGCThread {
                     while PARKED_THREADS.load(Ordering::SeqCst) != 0 {}
                 }
             } else {
-                let child = self.receiver.recv().unwrap();
-                if trace_object(child, mark_sense) {
-                    if cfg!(feature = "detailed_stats") {
-                        MARKED_OBJECTS.fetch_add(1, Ordering::Relaxed);
+                for child in self.receiver.recv().unwrap() {
+                    if trace_object(child, mark_sense) {
+                        marker_map::record(child, self.id);
+                        if cfg!(feature = "detailed_stats") {
+                            MARKED_OBJECTS.fetch_add(1, Ordering::Relaxed);
+                        }
+                        self.scan_queue.push_back(child);
                     }
-                    self.scan_queue.push_back(child);
                 }
             }
         }
@@ -120,6 +154,7 @@ impl DistGCThread {
 }
 
 pub(super) unsafe fn transitive_closure_distributed_node_objref<O: ObjectModel>(
+    args: &TraceArgs,
     mark_sense: u8,
     object_model: &O,
 ) -> TracingStats {
@@ -128,6 +163,8 @@ pub(super) unsafe fn transitive_closure_distributed_node_objref<O: ObjectModel>(
     SLOTS.store(0, Ordering::SeqCst);
     NON_EMPTY_SLOTS.store(0, Ordering::SeqCst);
     SENDS.store(0, Ordering::SeqCst);
+    MESSAGES.store(0, Ordering::SeqCst);
+    let batch = args.send_batch.max(1);
 
     let mut senders: Vec<Sender<DistGCMsg>> = vec![];
     let mut receivers: Vec<Receiver<DistGCMsg>> = vec![];
@@ -142,19 +179,31 @@ pub(super) unsafe fn transitive_closure_distributed_node_objref<O: ObjectModel>(
     let threads = receivers
         .into_iter()
         .enumerate()
-        .map(|(id, r)| DistGCThread::new(id, r, &senders, Arc::clone(&barrier)));
+        .map(|(id, r)| DistGCThread::new(id, r, &senders, batch, Arc::clone(&barrier)));
 
+    // Roots go out in batches too, but are not counted as sends
+    let mut root_batches: Vec<Vec<ObjectReference>> = vec![vec![]; NUM_THREADS];
     for root in object_model.roots() {
-        let o = *root;
+        let o = ObjectReference::from_raw(*root);
         if cfg!(feature = "detailed_stats") {
             SLOTS.fetch_add(1, Ordering::Relaxed);
-            if o != 0 {
+            if o.is_some() {
                 NON_EMPTY_SLOTS.fetch_add(1, Ordering::Relaxed);
             }
         }
-        if o != 0 {
+        if let Some(o) = o {
             let owner = get_owner_thread(o);
-            senders[owner].send(o).unwrap();
+            root_batches[owner].push(o);
+            if root_batches[owner].len() >= batch {
+                senders[owner]
+                    .send(std::mem::take(&mut root_batches[owner]))
+                    .unwrap();
+            }
+        }
+    }
+    for (sender, roots) in senders.iter().zip(root_batches) {
+        if !roots.is_empty() {
+            sender.send(roots).unwrap();
         }
     }
 
@@ -167,6 +216,7 @@ pub(super) unsafe fn transitive_closure_distributed_node_objref<O: ObjectModel>(
     }
 
     let sends = SENDS.load(Ordering::SeqCst);
+    let messages = MESSAGES.load(Ordering::SeqCst);
     let marked_objects = MARKED_OBJECTS.load(Ordering::SeqCst);
     let slots = SLOTS.load(Ordering::SeqCst);
     let non_empty_slots = NON_EMPTY_SLOTS.load(Ordering::SeqCst);
@@ -176,6 +226,7 @@ pub(super) unsafe fn transitive_closure_distributed_node_objref<O: ObjectModel>(
         slots,
         non_empty_slots,
         sends,
+        messages,
         ..Default::default()
     }
 }
@@ -1,5 +1,5 @@
-use super::{trace_object, TracingStats};
-use crate::ObjectModel;
+use super::{klass_profile, trace_object, TracingStats};
+use crate::{ObjectModel, ObjectReference};
 use std::collections::VecDeque;
 
 pub(super) unsafe fn transitive_closure_edge_objref<O: ObjectModel>(
@@ -7,7 +7,7 @@ pub(super) unsafe fn transitive_closure_edge_objref<O: ObjectModel>(
     object_model: &O,
 ) -> TracingStats {
     // Edge-ObjRef enqueuing
-    let mut mark_queue: VecDeque<u64> = VecDeque::new();
+    let mut mark_queue: VecDeque<ObjectReference> = VecDeque::new();
     let mut slots = 0;
     let mut non_empty_slots = 0;
     for root in object_model.roots() {
@@ -17,7 +17,7 @@ pub(super) unsafe fn transitive_closure_edge_objref<O: ObjectModel>(
                 non_empty_slots += 1;
             }
         }
-        mark_queue.push_back(*root);
+        mark_queue.extend(ObjectReference::from_raw(*root));
     }
     let mut marked_objects: u64 = 0;
     while let Some(o) = mark_queue.pop_front() {
@@ -27,13 +27,13 @@ pub(super) unsafe fn transitive_closure_edge_objref<O: ObjectModel>(
             if cfg!(feature = "detailed_stats") {
                 marked_objects += 1;
             }
-            O::scan_object(o, |edge, repeat| {
+            klass_profile::scan_object::<O, _>(o, |edge, repeat| {
                 for i in 0..repeat {
-                    let o = *edge.wrapping_add(i as usize);
+                    let o = edge.shift::<u64>(i as usize).load_object();
                     if cfg!(feature = "detailed_stats") {
                         slots += 1;
                     }
-                    if o != 0 {
+                    if let Some(o) = o {
                         if cfg!(feature = "detailed_stats") {
                             non_empty_slots += 1;
                         }
@@ -1,50 +1,56 @@
-use super::{trace_object, TracingStats};
-use crate::ObjectModel;
+use super::referent_filter::ReferentFilter;
+use super::{klass_profile, trace_object, TracingStats};
+use crate::{Address, ObjectModel, ObjectReference};
 
 pub(super) unsafe fn transitive_closure_edge_slot<O: ObjectModel>(
     mark_sense: u8,
     object_model: &O,
+    referent_filter: Option<usize>,
 ) -> TracingStats {
+    let mut filter = referent_filter.map(ReferentFilter::new);
     // Edge-Slot enqueuing
-    let mut mark_queue: Vec<*mut u64> = vec![];
+    let mut mark_queue: Vec<Address> = vec![];
     let mut marked_objects: u64 = 0;
     let mut slots = 0;
     let mut non_empty_slots = 0;
     for root in object_model.roots() {
-        let o = *root;
+        let o = ObjectReference::from_raw(*root);
         if cfg!(feature = "detailed_stats") {
             slots += 1;
-            if o != 0 {
+            if o.is_some() {
                 non_empty_slots += 1;
             }
         }
-        if o != 0 && trace_object(o, mark_sense) {
+        if let Some(o) = o.filter(|&o| trace_object(o, mark_sense)) {
             if cfg!(feature = "detailed_stats") {
                 marked_objects += 1;
             }
-            O::scan_object(o, |edge, repeat| {
+            klass_profile::scan_object::<O, _>(o, |edge, repeat| {
                 for i in 0..repeat {
-                    mark_queue.push(edge.wrapping_add(i as usize));
+                    mark_queue.push(edge.shift::<u64>(i as usize));
                 }
             })
         }
     }
     while let Some(e) = mark_queue.pop() {
-        let o = *e;
+        let o = e.load_object();
         if cfg!(feature = "detailed_stats") {
             slots += 1;
         }
-        if o != 0 {
+        if let Some(o) = o {
             if cfg!(feature = "detailed_stats") {
                 non_empty_slots += 1;
             }
+            if filter.as_mut().is_some_and(|f| f.seen(o)) {
+                continue;
+            }
             if trace_object(o, mark_sense) {
                 if cfg!(feature = "detailed_stats") {
                     marked_objects += 1;
                 }
-                O::scan_object(o, |edge, repeat| {
+                klass_profile::scan_object::<O, _>(o, |edge, repeat| {
                     for i in 0..repeat {
-                        mark_queue.push(edge.wrapping_add(i as usize));
+                        mark_queue.push(edge.shift::<u64>(i as usize));
                     }
                 })
             }
@@ -54,6 +60,7 @@ pub(super) unsafe fn transitive_closure_edge_slot<O: ObjectModel>(
         marked_objects,
         slots,
         non_empty_slots,
+        filtered_marks: filter.map_or(0, |f| f.filtered),
         ..Default::default()
     }
 }
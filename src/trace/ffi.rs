@@ -0,0 +1,244 @@
+//! C interface to the tracing loops, so that testbenches and harnesses that
+//! are not written in Rust can use them as a golden reference. See
+//! `include/hwgc_soft.h` for the declarations.
+
+use super::bench::{bench_iter, bench_prepare, Bench};
+use crate::*;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(e: anyhow::Error) {
+    // Messages never contain NUL bytes, but do not panic across the FFI
+    // boundary if one does
+    let message = CString::new(format!("{:#}", e).replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|l| *l.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning a panic into an error, since unwinding into C is
+/// undefined behaviour.
+fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(anyhow!("Panicked: {}", message))
+    })
+}
+
+/// Statistics of one transitive closure. Apart from `marked_objects` and
+/// `time_ns`, the counts are only collected by builds with the
+/// `detailed_stats` feature.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HwgcTraceStats {
+    /// Objects whose mark byte was set by the closure
+    pub marked_objects: u64,
+    pub slots: u64,
+    pub non_empty_slots: u64,
+    pub sends: u64,
+    pub packets: u64,
+    pub time_ns: u64,
+}
+
+/// What the opaque session handle points to, independent of the object
+/// model.
+trait Closure {
+    fn trace(&mut self) -> HwgcTraceStats;
+}
+
+impl<O: ObjectModel> Closure for Bench<O> {
+    fn trace(&mut self) -> HwgcTraceStats {
+        let timed_stats = bench_iter(self);
+        let stats = timed_stats.stats;
+        HwgcTraceStats {
+            marked_objects: self.marked_objects() as u64,
+            slots: stats.slots,
+            non_empty_slots: stats.non_empty_slots,
+            sends: stats.sends,
+            packets: stats.packets,
+            time_ns: timed_stats.time.as_nanos() as u64,
+        }
+    }
+}
+
+/// Opaque to C
+pub struct HwgcSession(Box<dyn Closure>);
+
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(anyhow!("No {} given", what));
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+unsafe fn new_session(
+    heapdump_path: *const c_char,
+    object_model: *const c_char,
+    tracing_loop: *const c_char,
+    threads: usize,
+) -> Result<HwgcSession> {
+    let path = str_arg(heapdump_path, "heapdump path")?;
+    let object_model = ObjectModelChoice::from_str(str_arg(object_model, "object model")?, false)
+        .map_err(|e| anyhow!(e))?;
+    let tracing_loop = str_arg(tracing_loop, "tracing loop")?;
+    let threads = threads.max(1).to_string();
+    let args = TraceArgs::try_parse_from([
+        "trace",
+        "-t",
+        tracing_loop,
+        "-i",
+        "1",
+        "--threads",
+        &threads,
+    ])?;
+    let heapdump = HeapDump::from_path(path)?;
+    let closure: Box<dyn Closure> = match object_model {
+        ObjectModelChoice::OpenJDK => Box::new(bench_prepare(
+            OpenJDKObjectModel::<false>::new(),
+            heapdump,
+            args,
+        )?),
+        ObjectModelChoice::OpenJDKAE => Box::new(bench_prepare(
+            OpenJDKObjectModel::<true>::new(),
+            heapdump,
+            args,
+        )?),
+        ObjectModelChoice::Bidirectional => Box::new(bench_prepare(
+            BidirectionalObjectModel::<true>::new(),
+            heapdump,
+            args,
+        )?),
+        ObjectModelChoice::BidirectionalFallback => Box::new(bench_prepare(
+            BidirectionalObjectModel::<false>::new(),
+            heapdump,
+            args,
+        )?),
+    };
+    Ok(HwgcSession(closure))
+}
+
+/// Restores the heapdump at `heapdump_path` with the object model and
+/// tracing loop named as on the command line. Returns NULL on error, see
+/// `hwgc_last_error`.
+///
+/// Heaps are mapped at the addresses of the heapdump, so only one session
+/// per heap address range can be open at a time.
+///
+/// # Safety
+/// The strings must be NUL-terminated or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hwgc_session_new(
+    heapdump_path: *const c_char,
+    object_model: *const c_char,
+    tracing_loop: *const c_char,
+    threads: usize,
+) -> *mut HwgcSession {
+    match catch_panic(|| new_session(heapdump_path, object_model, tracing_loop, threads)) {
+        Ok(session) => Box::into_raw(Box::new(session)),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Runs one transitive closure from the roots of the heapdump, and writes
+/// its statistics to `stats` unless it is NULL. Returns 0 on success. After
+/// a closure that panicked, the session can only be freed.
+///
+/// # Safety
+/// `session` must come from `hwgc_session_new` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn hwgc_trace(
+    session: *mut HwgcSession,
+    stats: *mut HwgcTraceStats,
+) -> c_int {
+    let Some(session) = session.as_mut() else {
+        set_last_error(anyhow!("No session given"));
+        return -1;
+    };
+    match catch_panic(|| Ok(session.0.trace())) {
+        Ok(result) => {
+            if let Some(stats) = stats.as_mut() {
+                *stats = result;
+            }
+            0
+        }
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Unmaps the heap of `session` and frees it.
+///
+/// # Safety
+/// `session` must come from `hwgc_session_new` or be NULL, and must not be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn hwgc_session_free(session: *mut HwgcSession) {
+    if !session.is_null() {
+        if let Err(e) = catch_panic(|| {
+            drop(Box::from_raw(session));
+            Ok(())
+        }) {
+            set_last_error(e);
+        }
+    }
+}
+
+/// The error of the last failed call on this thread, or NULL. Valid until
+/// the next failed call.
+#[no_mangle]
+pub extern "C" fn hwgc_last_error() -> *const c_char {
+    LAST_ERROR.with(|l| l.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let _guard = super::super::conformance::HEAP
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let path = CString::new("[synthetic]objarray_64_false").unwrap();
+        let model = CString::new("OpenJDK").unwrap();
+        let bad_loop = CString::new("Nope").unwrap();
+        let tracing_loop = CString::new("EdgeSlot").unwrap();
+        unsafe {
+            let session = hwgc_session_new(path.as_ptr(), model.as_ptr(), bad_loop.as_ptr(), 1);
+            assert!(session.is_null());
+            assert!(!hwgc_last_error().is_null());
+
+            let session = hwgc_session_new(path.as_ptr(), model.as_ptr(), tracing_loop.as_ptr(), 1);
+            assert!(!session.is_null());
+            // The mark sense flips between closures, so every one marks the
+            // whole heap again
+            for _ in 0..3 {
+                let mut stats = HwgcTraceStats::default();
+                assert_eq!(hwgc_trace(session, &mut stats), 0);
+                assert_eq!(stats.marked_objects, 65);
+            }
+            hwgc_session_free(session);
+        }
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let e = catch_panic::<()>(|| panic!("Out of {}", "slots")).unwrap_err();
+        assert_eq!(e.to_string(), "Panicked: Out of slots");
+    }
+}
@@ -0,0 +1,207 @@
+//! Where host tracing time goes by klass (`--klass-profile <N>`). Every
+//! thread times one in `N` of the objects it scans with the TSC, including
+//! pushing the slots found, and adds the time to its own table keyed by the
+//! TIB of the object, which stands for its klass. The tables are merged
+//! when their threads exit, so that sampling takes no locks, and the
+//! estimates are the sampled totals times `N`.
+
+use super::util::wp::{timestamp, TIMESTAMP_UNIT};
+use crate::results::StatsTable;
+use crate::util::typed_obj::{Address, ObjectReference};
+use crate::ObjectModel;
+use once_cell::sync::Lazy;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// One in how many scans is timed, 0 for none
+static SAMPLE_EVERY: AtomicU64 = AtomicU64::new(0);
+/// The tables of the threads that have exited or flushed theirs
+static PROFILE: Lazy<Mutex<HashMap<u64, KlassSamples>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct KlassSamples {
+    objects: u64,
+    slots: u64,
+    ticks: u64,
+}
+
+impl KlassSamples {
+    fn add(&mut self, other: &KlassSamples) {
+        self.objects += other.objects;
+        self.slots += other.slots;
+        self.ticks += other.ticks;
+    }
+}
+
+#[derive(Default)]
+struct LocalProfile {
+    /// Scans until the next sampled one
+    countdown: u64,
+    samples: HashMap<u64, KlassSamples>,
+}
+
+impl LocalProfile {
+    fn flush(&mut self) {
+        let mut profile = PROFILE.lock().unwrap();
+        for (tib, samples) in self.samples.drain() {
+            profile.entry(tib).or_default().add(&samples);
+        }
+    }
+}
+
+impl Drop for LocalProfile {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+thread_local! {
+    static LOCAL: RefCell<LocalProfile> = RefCell::new(LocalProfile::default());
+}
+
+/// Starts a profile timing one in `every` scans, or stops profiling if 0.
+pub(crate) fn start(every: u64) {
+    PROFILE.lock().unwrap().clear();
+    SAMPLE_EVERY.store(every, Ordering::Relaxed);
+}
+
+/// [`ObjectModel::scan_object`], timed if its turn has come.
+#[inline(always)]
+pub(crate) fn scan_object<O: ObjectModel, F: FnMut(Address, u64)>(o: ObjectReference, callback: F) {
+    let every = SAMPLE_EVERY.load(Ordering::Relaxed);
+    if every == 0 {
+        O::scan_object(o, callback);
+    } else {
+        sampled_scan_object::<O, F>(o, callback, every);
+    }
+}
+
+#[inline(never)]
+fn sampled_scan_object<O: ObjectModel, F: FnMut(Address, u64)>(
+    o: ObjectReference,
+    mut callback: F,
+    every: u64,
+) {
+    let sample = LOCAL.with(|local| {
+        let mut local = local.borrow_mut();
+        if local.countdown == 0 {
+            local.countdown = every - 1;
+            true
+        } else {
+            local.countdown -= 1;
+            false
+        }
+    });
+    if !sample {
+        O::scan_object(o, callback);
+        return;
+    }
+    let mut slots = 0;
+    let start = timestamp();
+    O::scan_object(o, |first, count| {
+        slots += count;
+        callback(first, count)
+    });
+    let ticks = timestamp() - start;
+    let tib = O::get_tib(o) as u64;
+    LOCAL.with(|local| {
+        local
+            .borrow_mut()
+            .samples
+            .entry(tib)
+            .or_default()
+            .add(&KlassSamples {
+                objects: 1,
+                slots,
+                ticks,
+            })
+    });
+}
+
+/// Prints the `top` klasses by estimated scan time and by marked objects,
+/// once every thread that scanned has exited.
+pub(crate) fn report<O: ObjectModel>(top: usize, table: &mut StatsTable) {
+    LOCAL.with(|local| local.borrow_mut().flush());
+    let every = SAMPLE_EVERY.load(Ordering::Relaxed);
+    let klasses: HashMap<u64, u64> = O::tib_records()
+        .into_iter()
+        .map(|r| (r.tib, r.klass))
+        .collect();
+    let profile = PROFILE.lock().unwrap();
+    let total = profile
+        .values()
+        .fold(KlassSamples::default(), |mut total, s| {
+            total.add(s);
+            total
+        });
+    println!(
+        "Klass profile: 1 in {} scans timed, {} samples of {} klasses, an estimated {} {} of scanning",
+        every,
+        total.objects,
+        profile.len(),
+        total.ticks * every,
+        TIMESTAMP_UNIT
+    );
+    let mut entries: Vec<(u64, KlassSamples)> = profile.iter().map(|(&t, &s)| (t, s)).collect();
+    let name = |tib: u64| match klasses.get(&tib) {
+        Some(klass) => format!("klass 0x{:x}", klass),
+        // Instance mirrors have TIBs of their own
+        None => format!("TIB 0x{:x}", tib),
+    };
+    for (by, key) in [
+        (
+            "scan time",
+            (|s: &KlassSamples| s.ticks) as fn(&KlassSamples) -> u64,
+        ),
+        ("marked objects", |s: &KlassSamples| s.objects),
+    ] {
+        // Ties broken by TIB so that the order is stable across runs
+        entries.sort_by_key(|(tib, s)| (std::cmp::Reverse(key(s)), *tib));
+        println!("  Top {} klasses by {}:", top.min(entries.len()), by);
+        for (tib, s) in entries.iter().take(top) {
+            println!(
+                "    {}: {} {} ({:.2}%), {} objects ({:.2}%), {:.1} {} and {:.1} slots per object",
+                name(*tib),
+                s.ticks * every,
+                TIMESTAMP_UNIT,
+                s.ticks as f64 * 100.0 / total.ticks.max(1) as f64,
+                s.objects * every,
+                s.objects as f64 * 100.0 / total.objects.max(1) as f64,
+                s.ticks as f64 / s.objects as f64,
+                TIMESTAMP_UNIT,
+                s.slots as f64 / s.objects as f64
+            );
+        }
+    }
+    table.push("klass_profile.samples", total.objects);
+    table.push("klass_profile.klasses", profile.len() as u64);
+    table.push("klass_profile.scan_ticks", total.ticks * every);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_profile() {
+        let mut local = LocalProfile::default();
+        let sample = KlassSamples {
+            objects: 1,
+            slots: 4,
+            ticks: 100,
+        };
+        local.samples.entry(0x7e57_0000).or_default().add(&sample);
+        local.samples.entry(0x7e57_0000).or_default().add(&sample);
+        drop(local);
+        assert_eq!(
+            PROFILE.lock().unwrap().get(&0x7e57_0000),
+            Some(&KlassSamples {
+                objects: 2,
+                slots: 8,
+                ticks: 200
+            })
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Which worker, or simulated processor, marked each object (`--marker-map
+//! <FILE>`), for plotting the spatial ownership of the live heap and checking
+//! that an address-partitioning policy sends each object to its owner. The
+//! map is a side array over the objects of the heap, in address order, that
+//! the marking loops write to when their mark of an object succeeds. It is
+//! written to Parquet with a row per object and heapdump: the `heapdump`, the
+//! `address` of the object, the `space` it is in, the `worker` that marked it,
+//! null if none did, and its `owner` under the policy of the loop, null if
+//! the loop has none.
+//!
+//! Recording takes a lock, so closures with a marker map are not timed as
+//! they would be without.
+
+use crate::util::typed_obj::ObjectReference;
+use crate::{HeapDump, ObjectModel};
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use polars::functions::concat_df_diagonal;
+use polars::prelude::*;
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// No worker marked the object
+const UNMARKED: u16 = u16::MAX;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CURRENT: Lazy<RwLock<Option<Arc<MarkerMap>>>> = Lazy::new(Default::default);
+
+pub(crate) struct MarkerMap {
+    objects: Vec<ObjectReference>,
+    markers: Vec<AtomicU16>,
+}
+
+/// How many of the objects marked were marked by their owner.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ownership {
+    pub marked: usize,
+    pub by_owner: usize,
+}
+
+impl Ownership {
+    pub fn fraction(&self) -> f64 {
+        self.by_owner as f64 / self.marked.max(1) as f64
+    }
+}
+
+impl MarkerMap {
+    pub fn new<O: ObjectModel>(object_model: &O) -> Self {
+        let mut objects: Vec<ObjectReference> = object_model.objects_iter().collect();
+        objects.sort_unstable();
+        let markers = objects.iter().map(|_| AtomicU16::new(UNMARKED)).collect();
+        MarkerMap { objects, markers }
+    }
+
+    /// Records that `worker` marked `o`, replacing whoever marked it in an
+    /// earlier closure.
+    pub fn record(&self, o: ObjectReference, worker: usize) {
+        debug_assert!(worker < UNMARKED as usize);
+        if let Ok(i) = self.objects.binary_search(&o) {
+            self.markers[i].store(worker as u16, Ordering::Relaxed);
+        }
+    }
+
+    pub fn marker(&self, o: ObjectReference) -> Option<usize> {
+        let i = self.objects.binary_search(&o).ok()?;
+        match self.markers[i].load(Ordering::Relaxed) {
+            UNMARKED => None,
+            worker => Some(worker as usize),
+        }
+    }
+
+    /// The rows of the objects of `heapdump`, named `name`, with their owner
+    /// under `owner` if given.
+    pub fn to_dataframe(
+        &self,
+        heapdump: &HeapDump,
+        name: &str,
+        owner: Option<&dyn Fn(ObjectReference) -> usize>,
+    ) -> Result<(DataFrame, Ownership)> {
+        let mut ownership = Ownership::default();
+        let mut spaces = Vec::with_capacity(self.objects.len());
+        let mut workers = Vec::with_capacity(self.objects.len());
+        let mut owners = Vec::with_capacity(self.objects.len());
+        for &o in &self.objects {
+            let space = heapdump
+                .spaces
+                .iter()
+                .find(|s| (s.start..s.end).contains(&o.to_raw()))
+                .map(|s| s.name.as_str());
+            let worker = self.marker(o);
+            let object_owner = owner.map(|f| f(o));
+            if let Some(worker) = worker {
+                ownership.marked += 1;
+                if object_owner == Some(worker) {
+                    ownership.by_owner += 1;
+                }
+            }
+            spaces.push(space);
+            workers.push(worker.map(|w| w as u32));
+            owners.push(object_owner.map(|w| w as u32));
+        }
+        let addresses: Vec<u64> = self.objects.iter().map(|o| o.to_raw()).collect();
+        let df = df! {
+            "heapdump" => vec![name; self.objects.len()],
+            "address" => addresses,
+            "space" => spaces,
+            "worker" => workers,
+            "owner" => owners,
+        }?;
+        Ok((df, ownership))
+    }
+}
+
+/// Starts recording markers in `map`, or stops recording if `None`.
+pub(crate) fn install(map: Option<Arc<MarkerMap>>) {
+    let mut current = CURRENT.write().unwrap();
+    ENABLED.store(map.is_some(), Ordering::SeqCst);
+    *current = map;
+}
+
+/// Records that `worker` marked `o` in the installed map, if any.
+#[inline(always)]
+pub(crate) fn record(o: ObjectReference, worker: usize) {
+    if ENABLED.load(Ordering::Relaxed) {
+        if let Some(map) = CURRENT.read().unwrap().as_ref() {
+            map.record(o, worker);
+        }
+    }
+}
+
+/// Prints how many marked objects their owner marked, if the rows have
+/// owners.
+pub(crate) fn report(name: &str, ownership: &Ownership, has_owners: bool) {
+    if has_owners {
+        println!(
+            "Marker map of {}: {} objects marked, {:.2}% by their owner",
+            name,
+            ownership.marked,
+            ownership.fraction() * 100.0
+        );
+    } else {
+        println!(
+            "Marker map of {}: {} objects marked",
+            name, ownership.marked
+        );
+    }
+}
+
+/// Writes the rows of every heapdump to `path`.
+pub(crate) fn write_parquet(path: &str, dfs: &[DataFrame]) -> Result<()> {
+    let mut df = concat_df_diagonal(dfs)?;
+    df.as_single_chunk_par();
+    let file = File::create(path)?;
+    ParquetWriter::new(file).finish(&mut df)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+    use crate::OpenJDKObjectModel;
+
+    #[test]
+    fn test_marker_map() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        heapdump.map_spaces().unwrap();
+        // Also with `--object-map`, where there is no list of the objects
+        for object_map in [false, true] {
+            let mut object_model = OpenJDKObjectModel::<false>::new().with_object_map(object_map);
+            object_model.restore_tibs(&heapdump);
+            object_model.restore_objects(&heapdump);
+            let map = MarkerMap::new(&object_model);
+            let mut objects: Vec<ObjectReference> = object_model.objects_iter().collect();
+            objects.sort_unstable();
+            let (first, second) = (objects[0], objects[1]);
+            map.record(first, 3);
+            map.record(second, 2);
+            // A later closure replaces the marker
+            map.record(second, 1);
+            assert_eq!(map.marker(first), Some(3));
+            assert_eq!(map.marker(second), Some(1));
+            assert_eq!(map.marker(objects[2]), None);
+            let (df, ownership) = map
+                .to_dataframe(&heapdump, "objarray", Some(&|_| 1))
+                .unwrap();
+            assert_eq!(df.height(), objects.len());
+            assert_eq!(df.column("worker").unwrap().null_count(), objects.len() - 2);
+            assert_eq!(
+                ownership,
+                Ownership {
+                    marked: 2,
+                    by_owner: 1
+                }
+            );
+        }
+        heapdump.unmap_spaces().unwrap();
+    }
+}
@@ -0,0 +1,529 @@
+//! `MMTk` (`--features mmtk`): the heap restored from a heapdump, put behind
+//! the VM-binding traits of MMTk, so that the work-packet scheduler of MMTk
+//! traces it with the Immix plan, as a software baseline for the WP tracers
+//! on the same addresses.
+//!
+//! The chunks of the heap become regions of MMTk's VM space, which marks
+//! objects in side metadata and never moves them, so nothing of the heap
+//! needs to be allocated by MMTk. MMTk's own spaces, which stay empty, are
+//! moved below the heapdumps (see [`MMTK_SPACES`]). Every object MMTk scans
+//! also gets the mark byte of the other loops, for `--verify` and the rest of
+//! the trace command.
+
+use super::{trace_object, TracingStats};
+use crate::util::tracer::Tracer;
+use crate::{Address, ObjectModel, ObjectReference, TraceArgs};
+use anyhow::{bail, Result};
+use crossbeam::utils::CachePadded;
+use mmtk::util::heap::vm_layout::{VMLayout, LOG_BYTES_IN_CHUNK};
+use mmtk::util::options::PlanSelector;
+use mmtk::util::{OpaquePointer, VMMutatorThread, VMThread, VMWorkerThread};
+use mmtk::vm::slot::{SimpleSlot, UnimplementedMemorySlice};
+use mmtk::vm::{
+    ActivePlan, Collection, GCThreadContext, ReferenceGlue, RootsWorkFactory, Scanning,
+    SlotVisitor, VMBinding, VMGlobalLogBitSpec, VMLocalForwardingBitsSpec,
+    VMLocalForwardingPointerSpec, VMLocalLOSMarkNurserySpec, VMLocalMarkBitSpec,
+};
+use mmtk::{memory_manager, MMTKBuilder, Mutator, MMTK};
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// log2 of the extent of each of MMTk's spaces, 64 GiB.
+const LOG_SPACE_EXTENT: usize = 36;
+
+/// The addresses of MMTk's own spaces: 64 GiB to 1 TiB, in place of its
+/// default of 2 TiB to 34 TiB, which is where the heapdumps of OpenJDK on
+/// MMTk are. MMTk cannot have its VM space there.
+const MMTK_SPACES: Range<u64> = (1 << LOG_SPACE_EXTENT)..(16 << LOG_SPACE_EXTENT);
+
+/// Root slots per work packet, as many as MMTk puts in its own.
+const ROOTS_PER_PACKET: usize = 4096;
+
+const NO_WEAK_REFERENCES: &str = "No weak references are registered with MMTk";
+const NON_MOVING: &str = "The VM space does not move objects";
+
+#[derive(Default)]
+pub(crate) struct Hwgc;
+
+impl VMBinding for Hwgc {
+    type VMObjectModel = Hwgc;
+    type VMScanning = Hwgc;
+    type VMCollection = Hwgc;
+    type VMActivePlan = Hwgc;
+    type VMReferenceGlue = Hwgc;
+    type VMSlot = SimpleSlot;
+    type VMMemorySlice = UnimplementedMemorySlice;
+}
+
+/// The closure in progress, for the hooks of the binding, which MMTk calls
+/// without any context of their own.
+struct Closure {
+    /// A copy of the root slots, which MMTk may store the objects back to
+    roots: Vec<AtomicU64>,
+    scan: fn(ObjectReference, &mut dyn FnMut(Address, u64)),
+    mark_sense: u8,
+    /// Objects and slots scanned by each worker, by ordinal
+    counts: Vec<CachePadded<[AtomicU64; 2]>>,
+}
+
+impl Closure {
+    fn count(&self, worker: usize, slots: u64) {
+        // Each worker only ever adds to its own counts
+        let [objects, all_slots] = &*self.counts[worker];
+        objects.store(objects.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        all_slots.store(all_slots.load(Ordering::Relaxed) + slots, Ordering::Relaxed);
+    }
+
+    fn totals(&self) -> (u64, u64) {
+        self.counts.iter().fold((0, 0), |(objects, slots), c| {
+            (
+                objects + c[0].load(Ordering::Relaxed),
+                slots + c[1].load(Ordering::Relaxed),
+            )
+        })
+    }
+}
+
+/// The closure of the last GC. It stays until the next GC replaces it, so
+/// that a hook running late never sees it freed, see [`MMTkTracer::trace`].
+static CLOSURE: AtomicPtr<Closure> = AtomicPtr::new(std::ptr::null_mut());
+
+fn closure() -> &'static Closure {
+    unsafe { &*CLOSURE.load(Ordering::Acquire) }
+}
+
+fn scan<O: ObjectModel>(o: ObjectReference, callback: &mut dyn FnMut(Address, u64)) {
+    O::scan_object(o, callback)
+}
+
+fn to_mmtk(a: Address) -> mmtk::util::Address {
+    unsafe { mmtk::util::Address::from_usize(a.as_u64() as usize) }
+}
+
+fn from_mmtk(o: mmtk::util::ObjectReference) -> ObjectReference {
+    ObjectReference::from_raw_unchecked(o.to_raw_address().as_usize() as u64)
+}
+
+/// Workers are numbered from 1 in their thread pointer, so that none of
+/// them has the null one.
+fn worker_thread(ordinal: usize) -> VMWorkerThread {
+    let pointer = unsafe { mmtk::util::Address::from_usize(ordinal + 1) };
+    VMWorkerThread(VMThread(OpaquePointer::from_address(pointer)))
+}
+
+fn worker_ordinal(tls: VMWorkerThread) -> usize {
+    tls.0 .0.to_address().as_usize() - 1
+}
+
+impl Scanning<Hwgc> for Hwgc {
+    fn scan_object<SV: SlotVisitor<SimpleSlot>>(
+        tls: VMWorkerThread,
+        object: mmtk::util::ObjectReference,
+        slot_visitor: &mut SV,
+    ) {
+        let closure = closure();
+        let o = from_mmtk(object);
+        // Only the worker scanning an object touches its header
+        unsafe { trace_object(o, closure.mark_sense) };
+        let mut slots = 0;
+        (closure.scan)(o, &mut |first, count| {
+            slots += count;
+            for i in 0..count as usize {
+                slot_visitor.visit_slot(SimpleSlot::from_address(to_mmtk(first.shift::<u64>(i))));
+            }
+        });
+        closure.count(worker_ordinal(tls), slots);
+    }
+
+    fn notify_initial_thread_scan_complete(_partial_scan: bool, _tls: VMWorkerThread) {}
+
+    fn scan_roots_in_mutator_thread(
+        _tls: VMWorkerThread,
+        _mutator: &'static mut Mutator<Hwgc>,
+        _factory: impl RootsWorkFactory<SimpleSlot>,
+    ) {
+        unreachable!("There are no mutators")
+    }
+
+    fn scan_vm_specific_roots(
+        _tls: VMWorkerThread,
+        mut factory: impl RootsWorkFactory<SimpleSlot>,
+    ) {
+        for roots in closure().roots.chunks(ROOTS_PER_PACKET) {
+            let slots = roots
+                .iter()
+                .map(|root| SimpleSlot::from_address(mmtk::util::Address::from_ref(root)))
+                .collect();
+            factory.create_process_roots_work(slots);
+        }
+    }
+
+    fn supports_return_barrier() -> bool {
+        false
+    }
+
+    fn prepare_for_roots_re_scanning() {}
+}
+
+/// Whether the GC requested last has resumed the mutators, which the thread
+/// that requested it waits for.
+static GC_DONE: Mutex<bool> = Mutex::new(false);
+static GC_DONE_CHANGED: Condvar = Condvar::new();
+
+impl Collection<Hwgc> for Hwgc {
+    fn stop_all_mutators<F>(_tls: VMWorkerThread, _mutator_visitor: F)
+    where
+        F: FnMut(&'static mut Mutator<Hwgc>),
+    {
+        // The only thread of the heap is the one waiting for the GC
+    }
+
+    fn resume_mutators(_tls: VMWorkerThread) {
+        *GC_DONE.lock().unwrap() = true;
+        GC_DONE_CHANGED.notify_all();
+    }
+
+    fn block_for_gc(_tls: VMMutatorThread) {
+        let mut done = GC_DONE.lock().unwrap();
+        while !*done {
+            done = GC_DONE_CHANGED.wait(done).unwrap();
+        }
+    }
+
+    fn spawn_gc_thread(_tls: VMThread, ctx: GCThreadContext<Hwgc>) {
+        let GCThreadContext::Worker(worker) = ctx;
+        std::thread::Builder::new()
+            .name(format!("mmtk-worker-{}", worker.ordinal))
+            .spawn(move || {
+                memory_manager::start_worker(mmtk(), worker_thread(worker.ordinal), worker)
+            })
+            .expect("Failed to spawn an MMTk worker");
+    }
+}
+
+impl ActivePlan<Hwgc> for Hwgc {
+    fn is_mutator(_tls: VMThread) -> bool {
+        false
+    }
+
+    fn mutator(_tls: VMMutatorThread) -> &'static mut Mutator<Hwgc> {
+        unreachable!("There are no mutators")
+    }
+
+    fn mutators<'a>() -> Box<dyn Iterator<Item = &'a mut Mutator<Hwgc>> + 'a> {
+        Box::new(std::iter::empty())
+    }
+
+    fn number_of_mutators() -> usize {
+        0
+    }
+}
+
+impl ReferenceGlue<Hwgc> for Hwgc {
+    type FinalizableType = mmtk::util::ObjectReference;
+
+    fn clear_referent(_new_reference: mmtk::util::ObjectReference) {
+        unreachable!("{}", NO_WEAK_REFERENCES)
+    }
+
+    fn get_referent(_object: mmtk::util::ObjectReference) -> Option<mmtk::util::ObjectReference> {
+        unreachable!("{}", NO_WEAK_REFERENCES)
+    }
+
+    fn set_referent(_reff: mmtk::util::ObjectReference, _referent: mmtk::util::ObjectReference) {
+        unreachable!("{}", NO_WEAK_REFERENCES)
+    }
+
+    fn enqueue_references(_references: &[mmtk::util::ObjectReference], _tls: VMWorkerThread) {
+        unreachable!("{}", NO_WEAK_REFERENCES)
+    }
+}
+
+impl mmtk::vm::ObjectModel<Hwgc> for Hwgc {
+    // All on the side, as the headers belong to the object models
+    const GLOBAL_LOG_BIT_SPEC: VMGlobalLogBitSpec = VMGlobalLogBitSpec::side_first();
+    // Unused, since nothing is forwarded
+    const LOCAL_FORWARDING_POINTER_SPEC: VMLocalForwardingPointerSpec =
+        VMLocalForwardingPointerSpec::in_header(0);
+    const LOCAL_FORWARDING_BITS_SPEC: VMLocalForwardingBitsSpec =
+        VMLocalForwardingBitsSpec::side_first();
+    const LOCAL_MARK_BIT_SPEC: VMLocalMarkBitSpec =
+        VMLocalMarkBitSpec::side_after(Self::LOCAL_FORWARDING_BITS_SPEC.as_spec());
+    const LOCAL_LOS_MARK_NURSERY_SPEC: VMLocalLOSMarkNurserySpec =
+        VMLocalLOSMarkNurserySpec::side_after(Self::LOCAL_MARK_BIT_SPEC.as_spec());
+
+    const OBJECT_REF_OFFSET_LOWER_BOUND: isize = 0;
+
+    fn copy(
+        _from: mmtk::util::ObjectReference,
+        _semantics: mmtk::util::copy::CopySemantics,
+        _copy_context: &mut mmtk::util::copy::GCWorkerCopyContext<Hwgc>,
+    ) -> mmtk::util::ObjectReference {
+        unreachable!("{}", NON_MOVING)
+    }
+
+    fn copy_to(
+        _from: mmtk::util::ObjectReference,
+        _to: mmtk::util::ObjectReference,
+        _region: mmtk::util::Address,
+    ) -> mmtk::util::Address {
+        unreachable!("{}", NON_MOVING)
+    }
+
+    fn get_reference_when_copied_to(
+        _from: mmtk::util::ObjectReference,
+        _to: mmtk::util::Address,
+    ) -> mmtk::util::ObjectReference {
+        unreachable!("{}", NON_MOVING)
+    }
+
+    fn get_current_size(_object: mmtk::util::ObjectReference) -> usize {
+        // Only copying and counting live bytes need sizes
+        unreachable!("{}", NON_MOVING)
+    }
+
+    fn get_size_when_copied(_object: mmtk::util::ObjectReference) -> usize {
+        unreachable!("{}", NON_MOVING)
+    }
+
+    fn get_align_when_copied(_object: mmtk::util::ObjectReference) -> usize {
+        unreachable!("{}", NON_MOVING)
+    }
+
+    fn get_align_offset_when_copied(_object: mmtk::util::ObjectReference) -> usize {
+        unreachable!("{}", NON_MOVING)
+    }
+
+    fn get_type_descriptor(_reference: mmtk::util::ObjectReference) -> &'static [i8] {
+        // Heapdumps only keep klass ids, not names
+        &[]
+    }
+
+    fn ref_to_object_start(object: mmtk::util::ObjectReference) -> mmtk::util::Address {
+        object.to_raw_address()
+    }
+
+    fn ref_to_header(object: mmtk::util::ObjectReference) -> mmtk::util::Address {
+        object.to_raw_address()
+    }
+
+    fn dump_object(object: mmtk::util::ObjectReference) {
+        debug!("{}", object);
+    }
+}
+
+/// The MMTk instance of the process. MMTk keeps global state of its own,
+/// such as the space of each chunk, so there can only be one, and it keeps
+/// the workers of the first tracer to start.
+static INSTANCE: AtomicPtr<MMTK<Hwgc>> = AtomicPtr::new(std::ptr::null_mut());
+
+/// The chunks of the VM space, once MMTk has started. Held by whoever starts
+/// MMTk or runs a closure, so that only one GC runs at a time.
+static VM_SPACE: Mutex<Option<BTreeSet<u64>>> = Mutex::new(None);
+
+fn mmtk() -> &'static MMTK<Hwgc> {
+    unsafe { &*INSTANCE.load(Ordering::Acquire) }
+}
+
+/// The chunks the objects of `object_model` are in.
+fn heap_chunks<O: ObjectModel>(object_model: &O) -> BTreeSet<u64> {
+    let log_chunk = LOG_BYTES_IN_CHUNK as u64;
+    let mut chunks = BTreeSet::new();
+    for o in object_model.objects_iter() {
+        let start = o.to_raw();
+        let end = start + object_model.object_size(o).unwrap_or(1);
+        chunks.extend((start >> log_chunk)..=((end - 1) >> log_chunk));
+    }
+    chunks
+}
+
+/// Runs of consecutive chunks in `chunks`.
+fn runs(chunks: &BTreeSet<u64>) -> Vec<Range<u64>> {
+    let mut runs: Vec<Range<u64>> = vec![];
+    for &chunk in chunks {
+        match runs.last_mut() {
+            Some(run) if run.end == chunk => run.end += 1,
+            _ => runs.push(chunk..chunk + 1),
+        }
+    }
+    runs
+}
+
+/// Starts MMTk with `chunks` as its VM space. MMTk only adds to the VM space
+/// through an exclusive reference, so it is fixed before the instance is
+/// shared with the workers.
+fn start_mmtk(threads: usize, chunks: &BTreeSet<u64>) -> Result<()> {
+    let log_chunk = LOG_BYTES_IN_CHUNK as u64;
+    let regions: Vec<Range<u64>> = runs(chunks)
+        .into_iter()
+        .map(|run| (run.start << log_chunk)..(run.end << log_chunk))
+        .collect();
+    for region in &regions {
+        if region.start < MMTK_SPACES.end && MMTK_SPACES.start < region.end {
+            bail!(
+                "The heap at {:#x}..{:#x} overlaps the spaces of MMTk at {:#x}..{:#x}",
+                region.start,
+                region.end,
+                MMTK_SPACES.start,
+                MMTK_SPACES.end
+            );
+        }
+    }
+    let mut builder = MMTKBuilder::new_no_env_vars();
+    builder.options.plan.set(PlanSelector::Immix);
+    builder.options.threads.set(threads);
+    builder.set_vm_layout(VMLayout {
+        log_address_space: 47,
+        heap_start: unsafe { mmtk::util::Address::from_usize(MMTK_SPACES.start as usize) },
+        heap_end: unsafe { mmtk::util::Address::from_usize(MMTK_SPACES.end as usize) },
+        log_space_extent: LOG_SPACE_EXTENT,
+        force_use_contiguous_spaces: true,
+    });
+    let instance = Box::into_raw(memory_manager::mmtk_init::<Hwgc>(&builder));
+    for region in regions {
+        // Nothing else refers to the instance yet
+        memory_manager::set_vm_space(
+            unsafe { &mut *instance },
+            unsafe { mmtk::util::Address::from_usize(region.start as usize) },
+            (region.end - region.start) as usize,
+        );
+    }
+    INSTANCE.store(instance, Ordering::Release);
+    memory_manager::initialize_collection(mmtk(), VMThread::UNINITIALIZED);
+    Ok(())
+}
+
+struct MMTkTracer<O: ObjectModel> {
+    threads: usize,
+    _p: PhantomData<O>,
+}
+
+impl<O: ObjectModel> Tracer<O> for MMTkTracer<O> {
+    fn startup(&self, object_model: &O) -> Result<()> {
+        info!("Use {} worker threads.", self.threads);
+        let chunks = heap_chunks(object_model);
+        let mut vm_space = VM_SPACE.lock().unwrap();
+        let Some(registered) = vm_space.as_ref() else {
+            start_mmtk(self.threads, &chunks)?;
+            *vm_space = Some(chunks);
+            return Ok(());
+        };
+        if !chunks.is_subset(registered) {
+            bail!(
+                "The VM space of MMTk is fixed to the {} chunks of the first heap traced, \
+                 which do not cover the heap at {:#x}..{:#x}",
+                registered.len(),
+                chunks.first().unwrap() << LOG_BYTES_IN_CHUNK,
+                (chunks.last().unwrap() + 1) << LOG_BYTES_IN_CHUNK
+            );
+        }
+        let workers = *mmtk().get_options().threads;
+        if workers != self.threads {
+            warn!(
+                "MMTk keeps the {} workers it started with, not {}",
+                workers, self.threads
+            );
+        }
+        Ok(())
+    }
+
+    fn trace(&self, mark_sense: u8, object_model: &O) -> TracingStats {
+        let _vm_space = VM_SPACE.lock().unwrap();
+        let current = Box::new(Closure {
+            roots: object_model
+                .roots()
+                .iter()
+                .map(|&root| AtomicU64::new(root))
+                .collect(),
+            scan: scan::<O>,
+            mark_sense,
+            counts: (0..*mmtk().get_options().threads)
+                .map(|_| Default::default())
+                .collect(),
+        });
+        // The previous GC resumed the mutators after its last packet, and no
+        // other GC runs while the VM space is locked, so no hook can still
+        // be using its closure
+        let previous = CLOSURE.swap(Box::into_raw(current), Ordering::AcqRel);
+        if !previous.is_null() {
+            drop(unsafe { Box::from_raw(previous) });
+        }
+        *GC_DONE.lock().unwrap() = false;
+        let tls = VMMutatorThread(VMThread::UNINITIALIZED);
+        assert!(
+            mmtk().handle_user_collection_request(tls, true, true),
+            "MMTk ignored the request for a GC"
+        );
+        let (marked_objects, slots) = closure().totals();
+        TracingStats {
+            marked_objects,
+            slots,
+            ..Default::default()
+        }
+    }
+
+    fn teardown(&self) {
+        // MMTk cannot start again once its workers stop, so they stay for
+        // the next tracer
+    }
+}
+
+pub fn create_tracer<O: ObjectModel>(args: &TraceArgs) -> Box<dyn Tracer<O>> {
+    Box::new(MMTkTracer::<O> {
+        threads: args.threads,
+        _p: PhantomData,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_model::Header;
+    use crate::trace::conformance::HEAP;
+    use crate::trace::shape_cache::ShapeLruCache;
+    use crate::trace::transitive_closure;
+    use crate::{HeapDump, OpenJDKObjectModel};
+    use clap::Parser;
+
+    #[test]
+    fn test_runs() {
+        let chunks = BTreeSet::from([1, 2, 3, 5, 8, 9]);
+        assert_eq!(runs(&chunks), [1..4, 5..6, 8..10]);
+        assert!(runs(&BTreeSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_mmtk_tracer() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let args = TraceArgs::parse_from(["trace", "-t", "MMTk", "--threads", "2"]);
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        let tracer = create_tracer::<OpenJDKObjectModel<false>>(&args);
+        tracer.startup(&object_model).unwrap();
+        let mut shape_cache = ShapeLruCache::new(args.shape_cache_size);
+        // Every closure clears the side marks of the one before
+        for mark_sense in [1, 0, 1] {
+            let stats = transitive_closure(
+                &args,
+                mark_sense,
+                &mut object_model,
+                &mut shape_cache,
+                Some(tracer.as_ref()),
+            );
+            assert_eq!(stats.stats.marked_objects, 65);
+            let marked = object_model
+                .objects_iter()
+                .filter(|&o| Header::load(o).get_mark_byte() == mark_sense)
+                .count();
+            assert_eq!(marked, 65);
+        }
+        tracer.teardown();
+        heapdump.unmap_spaces().unwrap();
+    }
+}
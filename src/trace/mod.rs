@@ -3,11 +3,17 @@ use clap::ValueEnum;
 use crate::object_model::Header;
 use crate::trace::shape_cache::ShapeLruCache;
 
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crate::failure::Failure;
+use crate::memory_usage::{reset_peak_rss, MemoryUsage, COUNTING};
 use crate::probes::*;
+use crate::results::{RunResults, StatsTable};
+use crate::root_scan::RootStacks;
+use crate::simulate::tracing::serialize_to_gzip_json;
 use crate::*;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 #[cfg(feature = "zsim")]
 use zsim_hooks::*;
 
@@ -22,6 +28,8 @@ pub enum TracingLoopChoice {
     WPEdgeSlot,
     WPEdgeSlotDual,
     ParEdgeSlot,
+    #[cfg(feature = "mmtk")]
+    MMTk,
 }
 
 #[derive(Debug, Default)]
@@ -30,7 +38,31 @@ pub struct TracingStats {
     pub slots: u64,
     pub non_empty_slots: u64,
     pub sends: u64,
+    /// Messages the sends went in, fewer than `sends` with `--send-batch`
+    pub messages: u64,
+    /// Work packets executed, only counted by the work-packet based loops
+    pub packets: u64,
+    /// Packets taken from the queues of other workers, only counted by the
+    /// work-packet based loops
+    pub steals: u64,
+    /// Packets pushed to the global queue by the hierarchical discipline
+    pub overflows: u64,
+    /// Packets run inline because the queues held `--packet-budget` packets
+    pub spilled_packets: u64,
+    /// Packets promoted to the global queue by `--packet-aging`
+    pub aged_packets: u64,
+    /// The most packets queued at once
+    pub peak_queued_packets: u64,
+    /// Slots put in order by `--slot-order`, and how often consecutive ones
+    /// were on different pages before and after
+    pub sorted_slots: u64,
+    pub unsorted_page_switches: u64,
+    pub sorted_page_switches: u64,
+    /// Mark attempts skipped by `--referent-filter`
+    pub filtered_marks: u64,
     pub shape_cache_stats: ShapeCacheStats,
+    /// Only timed by the work-packet based loops with `--packet-latency`
+    pub(crate) packet_latency: PacketLatency,
 }
 
 impl TracingStats {
@@ -39,20 +71,141 @@ impl TracingStats {
         self.slots += other.slots;
         self.non_empty_slots += other.non_empty_slots;
         self.sends += other.sends;
+        self.messages += other.messages;
+        self.packets += other.packets;
+        self.steals += other.steals;
+        self.overflows += other.overflows;
+        self.spilled_packets += other.spilled_packets;
+        self.aged_packets += other.aged_packets;
+        self.peak_queued_packets = self.peak_queued_packets.max(other.peak_queued_packets);
+        self.sorted_slots += other.sorted_slots;
+        self.unsorted_page_switches += other.unsorted_page_switches;
+        self.sorted_page_switches += other.sorted_page_switches;
+        self.filtered_marks += other.filtered_marks;
         self.shape_cache_stats.add(&other.shape_cache_stats);
+        self.packet_latency.add(&other.packet_latency);
     }
 }
 
+/// One row of the per-iteration report, see `--all-iterations`
+struct IterationRecord {
+    heapdump: usize,
+    iteration: usize,
+    time: Duration,
+    marked_objects: u64,
+    slots: u64,
+    packets: u64,
+    /// Deltas of the `--perf-counters`
+    perf: Vec<u64>,
+}
+
+/// Wall-clock time and memory usage of the phases of tracing a heapdump.
+/// Each phase lasts from the end of the one before, so that they add up to
+/// the time spent on the heapdump, including what is not timed on its own,
+/// such as logging.
+struct PhaseTimes {
+    heapdump: usize,
+    /// In order, with a "closure" for every iteration. The memory usage has
+    /// the allocations made during the phase and the peak RSS reached in it.
+    phases: Vec<(&'static str, Duration, MemoryUsage)>,
+    last: Instant,
+    last_usage: MemoryUsage,
+}
+
+impl PhaseTimes {
+    fn start(heapdump: usize) -> Self {
+        reset_peak_rss();
+        PhaseTimes {
+            heapdump,
+            phases: vec![],
+            last: Instant::now(),
+            last_usage: MemoryUsage::now(),
+        }
+    }
+
+    /// Ends `phase`, which started when the previous phase ended.
+    fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        let usage = MemoryUsage::now();
+        self.phases
+            .push((phase, now - self.last, usage.since(&self.last_usage)));
+        reset_peak_rss();
+        self.last = now;
+        self.last_usage = usage;
+    }
+
+    fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, d, _)| *d).sum()
+    }
+
+    /// The allocations of all the phases and the highest peak RSS.
+    fn total_usage(&self) -> MemoryUsage {
+        self.phases
+            .iter()
+            .fold(MemoryUsage::default(), |total, (_, _, usage)| {
+                add_usage(total, usage)
+            })
+    }
+
+    /// Microseconds and memory usage of each phase, with the iterations of
+    /// the closure added up, for `--results`.
+    fn table(&self, path: &str) -> StatsTable {
+        let mut totals: Vec<(&str, Duration, MemoryUsage)> = vec![];
+        for &(phase, duration, usage) in &self.phases {
+            match totals.iter_mut().find(|(p, _, _)| *p == phase) {
+                Some((_, total, total_usage)) => {
+                    *total += duration;
+                    *total_usage = add_usage(*total_usage, &usage);
+                }
+                None => totals.push((phase, duration, usage)),
+            }
+        }
+        let mut table = StatsTable::new("trace.phase", Some(path));
+        for &(phase, duration, _) in &totals {
+            table.push(phase, duration.as_micros() as u64);
+        }
+        table.push("total", self.total().as_micros() as u64);
+        totals.push(("total", self.total(), self.total_usage()));
+        for (phase, _, usage) in &totals {
+            if COUNTING {
+                table.push(format!("{}.allocations", phase), usage.allocations);
+                table.push(format!("{}.allocated_bytes", phase), usage.allocated_bytes);
+            }
+            if let Some(peak_rss) = usage.peak_rss {
+                table.push(format!("{}.peak_rss", phase), peak_rss);
+            }
+        }
+        table
+    }
+}
+
+/// Adds up the allocations of two phases, keeping the higher peak RSS.
+fn add_usage(total: MemoryUsage, usage: &MemoryUsage) -> MemoryUsage {
+    MemoryUsage {
+        allocations: total.allocations + usage.allocations,
+        allocated_bytes: total.allocated_bytes + usage.allocated_bytes,
+        peak_rss: total.peak_rss.max(usage.peak_rss),
+    }
+}
+
+/// Root scanning of the heapdumps, see `--root-stacks`
+#[derive(Default)]
+struct RootScanTotals {
+    frames: u64,
+    slots: u64,
+    /// Microseconds
+    time: u128,
+}
+
 #[derive(Debug)]
 pub struct TimedTracingStats {
     pub stats: TracingStats,
     pub time: Duration,
 }
 
-pub(crate) unsafe fn trace_object(o: u64, mark_sense: u8) -> bool {
+pub(crate) unsafe fn trace_object(o: ObjectReference, mark_sense: u8) -> bool {
     // mark sense is 1 intially, and flip every epoch
     // println!("Trace object: 0x{:x}", o as u64);
-    debug_assert_ne!(o, 0);
     let mut header = Header::load(o);
     // Return false if already marked
     let mark_byte = header.get_mark_byte();
@@ -65,20 +218,54 @@ pub(crate) unsafe fn trace_object(o: u64, mark_sense: u8) -> bool {
     }
 }
 
+/// Sets the mark byte of every object to `mark_byte`, so that a closure
+/// marking with the other sense leaves exactly the objects it does not reach
+/// with this one. Heapdumps can hold objects unreachable from the roots
+/// traced, which otherwise keep the mark byte of an earlier closure, or the
+/// 0 of restored objects.
+pub(crate) fn reset_marks<O: ObjectModel>(object_model: &O, mark_byte: u8) {
+    for o in object_model.objects_iter() {
+        let mut header = Header::load(o);
+        header.set_mark_byte(mark_byte);
+        header.store(o);
+    }
+}
+
+mod bench;
+mod compaction_traffic;
+#[cfg(test)]
+pub(crate) mod conformance;
 mod distributed_node_objref;
 mod edge_objref;
 mod edge_slot;
+mod ffi;
+pub(crate) mod klass_profile;
+pub(crate) mod marker_map;
+#[cfg(feature = "mmtk")]
+mod mmtk_plan;
+mod mutate;
 mod node_objref;
 mod par_edge_slot;
+mod perf_counters;
+pub(crate) mod referent_filter;
+mod roofline;
+mod row_buffer;
 mod sanity;
+mod selftest;
+mod session;
 mod shape_cache;
+pub(crate) mod verify;
 mod wp_edge_slot;
 mod wp_edge_slot_dual;
 
 use self::util::tracer::Tracer;
+pub use bench::{bench_iter, bench_prepare, bench_release, Bench};
 use sanity::sanity_trace;
+pub use selftest::selftest;
+pub use session::{HeapdumpTrace, TraceSession};
 
 use self::shape_cache::ShapeCacheStats;
+use self::util::wp::{PacketKind, PacketLatency, TIMESTAMP_UNIT};
 
 fn create_tracer<O: ObjectModel>(args: &TraceArgs) -> Option<Box<dyn Tracer<O>>> {
     // Only WPEdgeSlot supports the tracer interface for now.
@@ -86,12 +273,14 @@ fn create_tracer<O: ObjectModel>(args: &TraceArgs) -> Option<Box<dyn Tracer<O>>>
         TracingLoopChoice::WPEdgeSlot => Some(wp_edge_slot::create_tracer::<O>(args)),
         TracingLoopChoice::WPEdgeSlotDual => Some(wp_edge_slot_dual::create_tracer::<O>(args)),
         TracingLoopChoice::ParEdgeSlot => Some(par_edge_slot::create_tracer::<O>(args)),
+        #[cfg(feature = "mmtk")]
+        TracingLoopChoice::MMTk => Some(mmtk_plan::create_tracer::<O>(args)),
         _ => None,
     }
 }
 
 fn transitive_closure<O: ObjectModel>(
-    args: TraceArgs,
+    args: &TraceArgs,
     mark_sense: u8,
     object_model: &mut O,
     shape_cache: &mut ShapeLruCache<O>,
@@ -104,14 +293,17 @@ fn transitive_closure<O: ObjectModel>(
             TracingLoopChoice::EdgeObjref => {
                 edge_objref::transitive_closure_edge_objref(mark_sense, object_model)
             }
-            TracingLoopChoice::EdgeSlot => {
-                edge_slot::transitive_closure_edge_slot(mark_sense, object_model)
-            }
+            TracingLoopChoice::EdgeSlot => edge_slot::transitive_closure_edge_slot(
+                mark_sense,
+                object_model,
+                args.referent_filter,
+            ),
             TracingLoopChoice::NodeObjref => {
                 node_objref::transitive_closure_node_objref(mark_sense, object_model)
             }
             TracingLoopChoice::DistributedNodeObjref => {
                 distributed_node_objref::transitive_closure_distributed_node_objref(
+                    args,
                     mark_sense,
                     object_model,
                 )
@@ -131,6 +323,14 @@ fn transitive_closure<O: ObjectModel>(
                     unreachable!()
                 }
             }
+            #[cfg(feature = "mmtk")]
+            TracingLoopChoice::MMTk => {
+                if let Some(tracer) = tracer {
+                    tracer.trace(mark_sense, object_model)
+                } else {
+                    unreachable!()
+                }
+            }
         }
     };
     let elapsed = start.elapsed();
@@ -140,39 +340,173 @@ fn transitive_closure<O: ObjectModel>(
     }
 }
 
-fn verify_mark<O: ObjectModel>(mark_sense: u8, object_model: &mut O) {
-    for o in object_model.objects() {
-        let header = Header::load(*o);
-        if header.get_mark_byte() != mark_sense {
-            error!("0x{:x} not marked by transitive closure", o);
+fn compact<O: ObjectModel>(
+    mark_sense: u8,
+    object_model: &mut O,
+    heapdump: &HeapDump,
+    report_traffic: bool,
+) {
+    let start = Instant::now();
+    let Some(stats) = object_model.compact(mark_sense, report_traffic) else {
+        warn!("The object model does not support compaction");
+        return;
+    };
+    info!(
+        "Finished compaction in {} ms",
+        start.elapsed().as_micros() as f64 / 1000f64
+    );
+    println!(
+        "Compaction: {} of {} live objects moved ({} bytes), {} bytes reclaimed",
+        stats.objects_moved, stats.live_objects, stats.bytes_moved, stats.bytes_reclaimed
+    );
+    println!(
+        "Compaction: {} slots scanned, {} updated ({} bytes of slot writes)",
+        stats.slots_scanned,
+        stats.slots_updated,
+        stats.slots_updated * 8
+    );
+    if stats.pinned_objects > 0 {
+        println!(
+            "Compaction: {} pinned objects ({} bytes) left in place, {} free bytes below them",
+            stats.pinned_objects, stats.pinned_bytes, stats.bytes_fragmented
+        );
+    }
+    if let Some(moves) = &stats.moves {
+        let traffic = compaction_traffic::compaction_traffic(&heapdump.spaces, moves);
+        for space in &traffic.spaces {
+            println!(
+                "Compaction traffic: {}: {} objects moved, {} lines read, {} lines written ({:.1}% writes)",
+                space.name,
+                space.objects_moved,
+                space.lines_read,
+                space.lines_written,
+                space.write_fraction() * 100.0
+            );
+        }
+        println!(
+            "Compaction traffic: {} bytes over {} ranks in {} DRAM cycles ({:.1}% row hits), {:.2} GB/s",
+            traffic.bytes(),
+            traffic.ranks,
+            traffic.dram_ticks,
+            traffic.row_hit_rate * 100.0,
+            traffic.bandwidth_gbps()
+        );
+    }
+    // The closure over the compacted heap has to find every object at its
+    // new address, which checks both the moves and the forwarded slots.
+    if cfg!(debug_assertions) {
+        let mark_sense = mark_sense ^ 1;
+        let stats =
+            unsafe { edge_slot::transitive_closure_edge_slot(mark_sense, object_model, None) };
+        if cfg!(feature = "detailed_stats") {
+            assert_eq!(stats.marked_objects as usize, object_model.num_objects());
         }
+        verify::count_unmarked(mark_sense, object_model);
     }
 }
 
 pub fn reified_trace<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
     let trace_args = if let Some(Commands::Trace(a)) = args.command {
-        a
+        *a
     } else {
         panic!("Incorrect dispatch");
     };
 
+    if trace_args.marker_map.is_some()
+        && !matches!(
+            trace_args.tracing_loop,
+            TracingLoopChoice::DistributedNodeObjref
+                | TracingLoopChoice::WPEdgeSlot
+                | TracingLoopChoice::WPEdgeSlotDual
+                | TracingLoopChoice::ParEdgeSlot
+        )
+    {
+        bail!("Only the parallel tracing loops record which thread marked each object");
+    }
+    if trace_args.referent_filter.is_some()
+        && !matches!(
+            trace_args.tracing_loop,
+            TracingLoopChoice::EdgeSlot
+                | TracingLoopChoice::WPEdgeSlot
+                | TracingLoopChoice::WPEdgeSlotDual
+        )
+    {
+        bail!("Only EdgeSlot, WPEdgeSlot and WPEdgeSlotDual filter referents");
+    }
+    if let Some(fraction) = trace_args.mutate {
+        if !(0.0..=1.0).contains(&fraction) {
+            bail!("--mutate takes a fraction of slots, not {}", fraction);
+        }
+        if trace_args.verify == VerifyChoice::Full {
+            bail!("--mutate rewrites slots, which --verify Full takes for a perturbed heap");
+        }
+    }
+    shape_cache::check_args(&trace_args)?;
+    // Compaction takes every object with the mark of the last closure for
+    // live, which objects unreachable from the roots traced can also have
+    if trace_args.compact && (!args.roots.is_empty() || trace_args.mutate.is_some()) {
+        bail!("--compact needs every object of the heapdump reachable, so it cannot follow --roots or --mutate");
+    }
     if trace_args.tracing_loop == TracingLoopChoice::ShapeCache && trace_args.iterations != 1 {
         panic!("Only one iteration per heapdump is supported when doing shape cache analysis for avoiding warming up the shape cache");
     }
     let mut time = 0;
-    let mut pauses = 0;
+    let mut pauses: u64 = 0;
     let mut total_stats: TracingStats = Default::default();
+    let mut iteration_records: Vec<IterationRecord> = vec![];
+    let mut phase_times: Vec<PhaseTimes> = vec![];
+    let mut results = RunResults::new(&args.paths, args.results.clone())?;
 
-    let mut shape_cache: ShapeLruCache<O> = ShapeLruCache::new(trace_args.shape_cache_size);
+    let mut shape_cache: ShapeLruCache<O> = ShapeLruCache::new(trace_args.shape_cache_size)
+        .with_associativity(trace_args.shape_cache_ways, trace_args.shape_cache_index)
+        .with_levels(trace_args.shape_victim_cache, trace_args.shape_l2_cache);
+    if let Some(path) = &trace_args.shape_cache_import {
+        shape_cache.import(path)?;
+    }
+    let mut events = vec![];
+    let mut perf_totals = vec![0u64; trace_args.perf_counters.len()];
+    let mut root_scan = RootScanTotals::default();
+    // Bytes of heap touched by the closures counted in `time`
+    let mut total_bytes = roofline::HeapBytes::default();
+    let mut row_buffers = row_buffer::RowBufferStats::default();
+    let mut marker_frames = vec![];
+    let mut mutator = trace_args
+        .mutate
+        .map(|fraction| mutate::Mutator::new(fraction, trace_args.mutate_seed));
+    let mut mutations = mutate::MutationStats::default();
+    // Objects of the heap at each mutation, to put the retraces in proportion
+    let mut mutated_heap_objects = 0;
+    klass_profile::start(trace_args.klass_profile.unwrap_or(0));
+    let peak_bandwidth = trace_args.roofline.then(|| {
+        trace_args.peak_bandwidth.unwrap_or_else(|| {
+            let peak = roofline::detect_peak_bandwidth();
+            info!("Measured a peak memory bandwidth of {:.2} GB/s", peak);
+            peak
+        })
+    });
 
-    for path in &args.paths {
+    for (heapdump_idx, path) in args.paths.iter().enumerate() {
+        if stop::requested() {
+            break;
+        }
+        let mut phases = PhaseTimes::start(heapdump_idx);
         // reset object model internal states
         object_model.reset();
-        let heapdump = HeapDump::from_path(path)?;
+        let mut heapdump = HeapDump::from_path(path)?;
+        heapdump.select_roots(&args.roots)?;
+        if let Some(seed) = args.aslr_seed {
+            heapdump.randomize_layout(seed)?;
+        }
+        heapdump.pin_spaces(&trace_args.pinned_spaces)?;
+        phases.lap("decode");
+        // Heapdumps only hold objects reachable from all of their roots
+        // and until mutated, which can leave objects unreachable
+        let all_reachable = args.roots.is_empty() && trace_args.mutate.is_none();
         let path_cstr = std::ffi::CString::new(path.as_str()).unwrap();
         trace_heapdump_begin(path_cstr.as_ptr());
         // mmap
         heapdump.map_spaces()?;
+        phases.lap("mmap");
         // write objects to the heap
         {
             let start = Instant::now();
@@ -184,16 +518,62 @@ pub fn reified_trace<O: ObjectModel>(mut object_model: O, args: Args) -> Result<
                 elapsed.as_micros() as f64 / 1000f64
             );
         }
+        phases.lap("restore");
+        let snapshot = (trace_args.verify == VerifyChoice::Full)
+            .then(|| verify::HeapSnapshot::take(&object_model));
         // sanity check
-        {
-            if cfg!(debug_assertions) {
-                let sanity_traced_objects = sanity_trace(&heapdump);
-                info!(
-                    "Sanity trace reporting {} reachable objects",
-                    sanity_traced_objects
-                );
-                assert_eq!(sanity_traced_objects, heapdump.objects.len());
+        if cfg!(debug_assertions) || trace_args.sanity {
+            let stats = sanity_trace(&heapdump);
+            info!(
+                "Sanity trace reporting {} reachable objects",
+                stats.reachable_objects
+            );
+            if trace_args.sanity {
+                stats.print();
             }
+            if all_reachable && stats.reachable_objects != heapdump.objects.len() {
+                return Err(anyhow!(
+                    "Sanity trace of {} reached {} of {} objects",
+                    path,
+                    stats.reachable_objects,
+                    heapdump.objects.len()
+                )
+                .context(Failure::Verification));
+            }
+            phases.lap("sanity");
+        }
+        if let Some(threads) = trace_args.root_stacks.root_stacks {
+            let stacks = RootStacks::synthesize(
+                object_model.roots(),
+                threads,
+                trace_args.root_stacks.frame_roots,
+                trace_args.root_stacks.frame_words,
+            )?;
+            let (elapsed, found) = stacks.scan_on_host();
+            debug_assert_eq!(found.len(), object_model.roots().len());
+            info!(
+                "Scanned {} root slots in {} frames in {} ms",
+                found.len(),
+                stacks.frames(),
+                elapsed.as_micros() as f64 / 1000f64
+            );
+            root_scan.frames += stacks.frames() as u64;
+            root_scan.slots += stacks.root_slots() as u64;
+            root_scan.time += elapsed.as_micros();
+            phases.lap("root_scan");
+        }
+        let heap_bytes = trace_args
+            .roofline
+            .then(|| roofline::heap_bytes(&object_model));
+        if trace_args.row_buffer {
+            let stats = row_buffer::row_buffer_stats(&object_model);
+            info!(
+                "{} line accesses, {:.2}% row hits, {} rows",
+                stats.accesses,
+                stats.row_hit_rate() * 100.0,
+                stats.locality.rows_touched()
+            );
+            row_buffers.add(&stats);
         }
         // main tracing loop
         let mut mark_sense: u8 = 0;
@@ -204,20 +584,46 @@ pub fn reified_trace<O: ObjectModel>(mut object_model: O, args: Args) -> Result<
         #[cfg(feature = "zsim")]
         zsim_roi_begin();
         let iterations = trace_args.iterations;
+        // Opened before the tracer starts its workers, which then inherit
+        // the counters
+        let perf = if trace_args.perf_counters.is_empty() {
+            None
+        } else {
+            Some(perf_counters::PerfCounters::open(
+                &trace_args.perf_counters,
+            )?)
+        };
+        let markers = trace_args.marker_map.is_some().then(|| {
+            let map = Arc::new(marker_map::MarkerMap::new(&object_model));
+            marker_map::install(Some(map.clone()));
+            map
+        });
         let tracer = create_tracer::<O>(&trace_args);
         if let Some(tracer) = tracer.as_ref() {
-            tracer.startup();
+            tracer.startup(&object_model)?;
         }
+        phases.lap("setup");
         for i in 0..iterations {
             mark_sense = (i % 2 == 0) as u8;
             trace_iteration_begin(i);
+            let perf_before = perf.as_ref().map(|p| p.read()).transpose()?;
             let timed_stats = transitive_closure(
-                trace_args,
+                &trace_args,
                 mark_sense,
                 &mut object_model,
                 &mut shape_cache,
                 tracer.as_deref(),
             );
+            phases.lap("closure");
+            let perf_deltas: Vec<u64> = match (&perf, perf_before) {
+                (Some(perf), Some(before)) => perf
+                    .read()?
+                    .iter()
+                    .zip(before)
+                    .map(|(after, before)| after - before)
+                    .collect(),
+                _ => vec![],
+            };
             trace_iteration_end(i);
             let millis = timed_stats.time.as_micros() as f64 / 1000f64;
             let stats = timed_stats.stats;
@@ -231,6 +637,13 @@ pub fn reified_trace<O: ObjectModel>(mut object_model: O, args: Args) -> Result<
                 stats.slots as f64 / millis,
                 stats.non_empty_slots as f64 / millis
             );
+            if let Some(bytes) = heap_bytes {
+                info!(
+                    "Touched {} bytes of heap, {:.2} GB/s",
+                    bytes.total(),
+                    roofline::bandwidth(bytes.total(), timed_stats.time.as_micros())
+                );
+            }
             if stats.non_empty_slots != 0 {
                 info!(
                     "Total communication: {}, {:.1}% of non-empty slots",
@@ -238,19 +651,50 @@ pub fn reified_trace<O: ObjectModel>(mut object_model: O, args: Args) -> Result<
                     stats.sends as f64 / stats.non_empty_slots as f64 * 100f64
                 );
             }
-            if cfg!(feature = "detailed_stats") {
+            if cfg!(feature = "detailed_stats") && all_reachable {
                 debug_assert_eq!(stats.marked_objects as usize, heapdump.objects.len());
             }
-            if i == iterations - 1 {
+            if trace_args.all_iterations {
+                iteration_records.push(IterationRecord {
+                    heapdump: heapdump_idx,
+                    iteration: i,
+                    time: timed_stats.time,
+                    marked_objects: stats.marked_objects,
+                    slots: stats.slots,
+                    packets: stats.packets,
+                    perf: perf_deltas.clone(),
+                });
+            }
+            // A stopped run reports its last closure as the final one
+            let last = i == iterations - 1 || stop::requested();
+            if last {
                 pauses += 1;
                 time += timed_stats.time.as_micros();
                 // println!("{:?}", stats);
                 total_stats.add(&stats);
+                if let Some(bytes) = &heap_bytes {
+                    total_bytes.add(bytes);
+                }
+                for (total, delta) in perf_totals.iter_mut().zip(&perf_deltas) {
+                    *total += delta;
+                }
             }
             info!(
                 "Final iteration {} ms",
                 timed_stats.time.as_micros() as f64 / 1000f64
             );
+            if last {
+                break;
+            }
+            if let Some(mutator) = mutator.as_mut() {
+                let stats = mutator.mutate(mark_sense, &object_model);
+                info!(
+                    "Rewrote {} slots, retracing {} objects and {} slots from the remembered buffer",
+                    stats.writes, stats.retraced_objects, stats.retraced_slots
+                );
+                mutations.add(&stats);
+                mutated_heap_objects += object_model.num_objects() as u64;
+            }
         }
         #[cfg(feature = "m5")]
         unsafe {
@@ -258,29 +702,421 @@ pub fn reified_trace<O: ObjectModel>(mut object_model: O, args: Args) -> Result<
         }
         #[cfg(feature = "zsim")]
         zsim_roi_end();
-        verify_mark(mark_sense, &mut object_model);
+        if let Some(map) = markers {
+            marker_map::install(None);
+            // Only DistributedNodeObjref partitions the heap between threads
+            let owner = (trace_args.tracing_loop == TracingLoopChoice::DistributedNodeObjref)
+                .then_some(&distributed_node_objref::get_owner_thread as &dyn Fn(_) -> _);
+            let (df, ownership) = map.to_dataframe(&heapdump, path, owner)?;
+            marker_map::report(path, &ownership, owner.is_some());
+            marker_frames.push(df);
+        }
+        if trace_args.verify != VerifyChoice::Off {
+            // Objects that the roots chosen cannot reach stay unmarked
+            let unmarked = if all_reachable {
+                verify::count_unmarked(mark_sense, &object_model)
+            } else {
+                0
+            };
+            let perturbed = snapshot.as_ref().map_or(0, |s| s.count_perturbed::<O>());
+            phases.lap("verify");
+            if unmarked + perturbed > 0 {
+                return Err(anyhow!(
+                    "Verification of {} failed: {} objects unmarked, {} objects perturbed",
+                    path,
+                    unmarked,
+                    perturbed
+                )
+                .context(Failure::Verification));
+            }
+        }
+        if trace_args.compact {
+            compact(
+                mark_sense,
+                &mut object_model,
+                &heapdump,
+                trace_args.compact_traffic,
+            );
+            phases.lap("compact");
+        }
         heapdump.unmap_spaces()?;
+        phases.lap("unmap");
         if let Some(tracer) = tracer.as_ref() {
             tracer.teardown();
+            // One Perfetto process per heapdump, as every tracer has its own
+            // workers and timeline.
+            events.extend(tracer.take_events().into_iter().map(|mut e| {
+                e.pid = heapdump_idx as u32;
+                e
+            }));
+            phases.lap("teardown");
         }
         trace_heapdump_end();
+        phase_times.push(phases);
+    }
+
+    let mut table = StatsTable::new("trace", None);
+    table.push("pauses", pauses);
+    table.push("time", time as u64);
+    table.push("objects", total_stats.marked_objects);
+    table.push("slots", total_stats.slots);
+    table.push("non_empty_slots", total_stats.non_empty_slots);
+    table.push("sends", total_stats.sends);
+    if trace_args.tracing_loop == TracingLoopChoice::DistributedNodeObjref {
+        println!(
+            "Communication: {} sends in {} messages of up to {} slots, {:.2} sends per message",
+            total_stats.sends,
+            total_stats.messages,
+            trace_args.send_batch,
+            total_stats.sends as f64 / total_stats.messages.max(1) as f64
+        );
+        table.push("messages", total_stats.messages);
+    }
+    total_stats.shape_cache_stats.add_columns(&mut table);
+    if let Some(path) = &trace_args.shape_cache_export {
+        shape_cache.export(path)?;
+    }
+    for (name, total) in trace_args.perf_counters.iter().zip(&perf_totals) {
+        table.push(format!("perf.{}", name), *total);
+    }
+    if trace_args.root_stacks.root_stacks.is_some() {
+        println!(
+            "Root scanning: {} root slots in {} frames in {} us, a pause of {} us with the closures",
+            root_scan.slots,
+            root_scan.frames,
+            root_scan.time,
+            root_scan.time + time
+        );
+        table.push("root_frames", root_scan.frames);
+        table.push("root_slots", root_scan.slots);
+        table.push("root_scan_time", root_scan.time as u64);
+        table.push("pause_time", (root_scan.time + time) as u64);
+    }
+    if matches!(
+        trace_args.tracing_loop,
+        TracingLoopChoice::WPEdgeSlot | TracingLoopChoice::WPEdgeSlotDual
+    ) {
+        report_load_balancing(&trace_args, &total_stats, &mut table);
+    }
+    if let Some(entries) = trace_args.referent_filter {
+        // Every non-empty slot is a mark attempt without the filter, but
+        // EdgeSlot and WPEdgeSlot only count them with `detailed_stats`
+        let share = match total_stats.non_empty_slots {
+            0 => String::new(),
+            attempts => format!(
+                " of {} ({:.1}%)",
+                attempts,
+                total_stats.filtered_marks as f64 / attempts as f64 * 100.0
+            ),
+        };
+        println!(
+            "Referent filter: {} mark attempts filtered{} by {} entries per worker",
+            total_stats.filtered_marks, share, entries
+        );
+        table.push("filtered_marks", total_stats.filtered_marks);
+    }
+    if trace_args.packet_latency {
+        report_packet_latency(&total_stats.packet_latency, &mut table);
+    }
+    if trace_args.klass_profile.is_some() {
+        klass_profile::report::<O>(trace_args.klass_profile_top, &mut table);
+    }
+    if let Some(path) = &trace_args.marker_map {
+        marker_map::write_parquet(path, &marker_frames)?;
+    }
+    if trace_args.mutate.is_some() {
+        println!(
+            "Mutation: {} slots rewritten, {} remembered, retracing {} objects and {} slots, {:.2}% of the objects of the heaps mutated",
+            mutations.writes,
+            mutations.remembered,
+            mutations.retraced_objects,
+            mutations.retraced_slots,
+            mutations.retraced_objects as f64 / mutated_heap_objects.max(1) as f64 * 100.0
+        );
+        table.push("mutate.writes", mutations.writes);
+        table.push("mutate.remembered", mutations.remembered);
+        table.push("mutate.retraced_objects", mutations.retraced_objects);
+        table.push("mutate.retraced_slots", mutations.retraced_slots);
+    }
+    if let Some(peak) = peak_bandwidth {
+        let achieved = roofline::bandwidth(total_bytes.total(), time);
+        println!(
+            "Roofline: {} bytes of heap touched ({} of headers, {} of slots, {} of object array payload), {:.2} GB/s of {:.2} GB/s peak ({:.1}%)",
+            total_bytes.total(),
+            total_bytes.headers,
+            total_bytes.slots,
+            total_bytes.objarray_payload,
+            achieved,
+            peak,
+            achieved * 100.0 / peak
+        );
+        table.push("bytes_touched", total_bytes.total());
+        table.push("bandwidth_gbps", achieved);
+        table.push("peak_bandwidth_gbps", peak);
+        table.push("roofline_utilization", achieved / peak);
     }
+    if trace_args.row_buffer {
+        let locality = &row_buffers.locality;
+        println!(
+            "Row buffers: {} line accesses, {:.2}% row hits, {} rows, {} of {} banks, busiest bank {:.2}x the mean",
+            row_buffers.accesses,
+            row_buffers.row_hit_rate() * 100.0,
+            locality.rows_touched(),
+            locality.banks_touched(),
+            crate::simulate::num_banks(),
+            locality.bank_imbalance()
+        );
+        table.push("row_accesses", row_buffers.accesses);
+        table.push("row_hit_rate", row_buffers.row_hit_rate());
+        table.push("rows_touched", locality.rows_touched() as u64);
+        table.push("banks_touched", locality.banks_touched() as u64);
+        table.push("bank_imbalance", locality.bank_imbalance());
+    }
+    if stop::requested() {
+        results.truncate(&mut table);
+    }
+    results.add_table(table);
+    print_phase_times(&args.paths, &phase_times);
+    for phases in &phase_times {
+        results.keep_table(phases.table(&args.paths[phases.heapdump]));
+    }
+    if trace_args.all_iterations {
+        print_iteration_records(&args.paths, &trace_args.perf_counters, &iteration_records);
+        for r in &iteration_records {
+            let mut table = StatsTable::new("trace.iteration", Some(&args.paths[r.heapdump]));
+            table.push("iteration", r.iteration as u64);
+            table.push("time", r.time.as_micros() as u64);
+            table.push("objects", r.marked_objects);
+            table.push("slots", r.slots);
+            table.push("packets", r.packets);
+            for (name, delta) in trace_args.perf_counters.iter().zip(&r.perf) {
+                table.push(format!("perf.{}", name), *delta);
+            }
+            results.keep_table(table);
+        }
+    }
+    if let Some(path) = &trace_args.trace_events {
+        if events.is_empty() {
+            warn!(
+                "{:?} has no worker counters to write to {}",
+                trace_args.tracing_loop, path
+            );
+        } else {
+            serialize_to_gzip_json(&events, path)?;
+            info!("Wrote {} tracing events to {}", events.len(), path);
+        }
+    }
+    results.write()
+}
 
-    println!("============================ Tabulate Statistics ============================");
+/// How much work a steal moves under the queue discipline, which together
+/// with `--perf-counters` tells how the discipline trades balance for
+/// locality, and how much memory the packet queues took at their fullest.
+fn report_load_balancing(trace_args: &TraceArgs, stats: &TracingStats, table: &mut StatsTable) {
+    let per_steal = |n: u64| n as f64 / stats.steals.max(1) as f64;
     println!(
-        "pauses\ttime\tobjects\tslots\tnon_empty_slots\tsends\t{}",
-        total_stats.shape_cache_stats.get_stats_header()
+        "Load balancing with {:?} queues: {} packets, {} steals, {:.1} packets and {:.1} marked objects per steal, {} packets overflowed to the global queue",
+        trace_args.queue_discipline,
+        stats.packets,
+        stats.steals,
+        per_steal(stats.packets),
+        per_steal(stats.marked_objects),
+        stats.overflows
     );
+    table.push("packets", stats.packets);
+    table.push("steals", stats.steals);
+    table.push("objects_per_steal", per_steal(stats.marked_objects));
+    table.push("overflows", stats.overflows);
+    // Every queued packet holds up to `--wp-capacity` words
+    let peak_bytes = stats.peak_queued_packets * trace_args.wp_capacity as u64 * 8;
+    println!(
+        "Packet queues: at most {} packets queued (up to {} bytes of slots), {} packets spilled over the budget of {}",
+        stats.peak_queued_packets,
+        peak_bytes,
+        stats.spilled_packets,
+        match trace_args.packet_budget {
+            0 => "unlimited".to_string(),
+            budget => budget.to_string(),
+        }
+    );
+    table.push("peak_queued_packets", stats.peak_queued_packets);
+    table.push("peak_queued_bytes", peak_bytes);
+    table.push("spilled_packets", stats.spilled_packets);
+    if trace_args.packet_aging != 0 {
+        println!(
+            "Packet aging: {} packets promoted to the global queue after waiting {} us in a local queue",
+            stats.aged_packets, trace_args.packet_aging
+        );
+        table.push("aged_packets", stats.aged_packets);
+    }
+    if trace_args.slot_order != SlotOrderChoice::Unsorted {
+        let reduction =
+            1.0 - stats.sorted_page_switches as f64 / stats.unsorted_page_switches.max(1) as f64;
+        println!(
+            "Slot order {:?}: {} slots sorted, {} page switches between consecutive slots instead of {} ({:.1}% fewer)",
+            trace_args.slot_order,
+            stats.sorted_slots,
+            stats.sorted_page_switches,
+            stats.unsorted_page_switches,
+            reduction * 100.0
+        );
+        table.push("sorted_slots", stats.sorted_slots);
+        table.push("unsorted_page_switches", stats.unsorted_page_switches);
+        table.push("sorted_page_switches", stats.sorted_page_switches);
+    }
+}
+
+fn report_packet_latency(latency: &PacketLatency, table: &mut StatsTable) {
+    if latency.is_empty() {
+        warn!("--packet-latency only times the packets of WPEdgeSlot and WPEdgeSlotDual");
+        return;
+    }
+    for kind in PacketKind::ALL {
+        let histogram = latency.histogram(kind);
+        let count = histogram.count();
+        if count == 0 {
+            continue;
+        }
+        let (p50, p90, p99) = (
+            histogram.quantile(0.5),
+            histogram.quantile(0.9),
+            histogram.quantile(0.99),
+        );
+        println!(
+            "Packet latency of {}: {} packets, p50 <= {}, p90 <= {}, p99 <= {}, max {} {}",
+            kind.name(),
+            count,
+            p50,
+            p90,
+            p99,
+            latency.max(kind),
+            TIMESTAMP_UNIT
+        );
+        histogram.print(kind.name(), TIMESTAMP_UNIT);
+        let prefix = format!("packet_latency.{}", kind.name());
+        table.push(format!("{}.count", prefix), count);
+        table.push(format!("{}.p50", prefix), p50);
+        table.push(format!("{}.p90", prefix), p90);
+        table.push(format!("{}.p99", prefix), p99);
+        table.push(format!("{}.max", prefix), latency.max(kind));
+    }
+    // The tail of the wait is what `--packet-aging` shortens
+    let wait = latency.wait();
+    let (p50, p99) = (wait.quantile(0.5), wait.quantile(0.99));
     println!(
-        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
-        pauses,
-        time,
-        total_stats.marked_objects,
-        total_stats.slots,
-        total_stats.non_empty_slots,
-        total_stats.sends,
-        total_stats.shape_cache_stats.get_stats_value()
+        "Packet queue wait: {} packets, p50 <= {}, p99 <= {}, max {} {}",
+        wait.count(),
+        p50,
+        p99,
+        latency.max_wait(),
+        TIMESTAMP_UNIT
     );
-    println!("-------------------------- End Tabulate Statistics --------------------------");
-    Ok(())
+    wait.print("wait", TIMESTAMP_UNIT);
+    table.push("packet_latency.wait.p50", p50);
+    table.push("packet_latency.wait.p99", p99);
+    table.push("packet_latency.wait.max", latency.max_wait());
+}
+
+fn print_phase_times(paths: &[String], phase_times: &[PhaseTimes]) {
+    // Kept out of the tabulate block like the per-iteration statistics
+    println!("============================== Phase Breakdown ===============================");
+    println!("heapdump\tphase\ttime\tshare\tallocations\tallocated_bytes\tpeak_rss");
+    for times in phase_times {
+        let path = &paths[times.heapdump];
+        let total = times.total().as_micros().max(1) as f64;
+        let mut iteration = 0;
+        for &(phase, duration, usage) in &times.phases {
+            let name = if phase == "closure" {
+                iteration += 1;
+                format!("closure.{}", iteration - 1)
+            } else {
+                phase.to_string()
+            };
+            println!(
+                "{}\t{}\t{}\t{:.1}%\t{}",
+                path,
+                name,
+                duration.as_micros(),
+                duration.as_micros() as f64 * 100.0 / total,
+                usage_columns(&usage)
+            );
+        }
+        println!(
+            "{}\ttotal\t{}\t100.0%\t{}",
+            path,
+            times.total().as_micros(),
+            usage_columns(&times.total_usage())
+        );
+    }
+    println!("---------------------------- End Phase Breakdown -----------------------------");
+}
+
+/// The allocations and peak RSS in bytes of a phase, "-" for allocations
+/// not counted and for a peak RSS the platform does not report.
+fn usage_columns(usage: &MemoryUsage) -> String {
+    let column = |value: Option<u64>| value.map_or_else(|| "-".to_string(), |v| v.to_string());
+    format!(
+        "{}\t{}\t{}",
+        column(COUNTING.then_some(usage.allocations)),
+        column(COUNTING.then_some(usage.allocated_bytes)),
+        column(usage.peak_rss)
+    )
+}
+
+fn print_iteration_records(
+    paths: &[String],
+    perf_counters: &[String],
+    records: &[IterationRecord],
+) {
+    // Kept out of the tabulate block so that existing parsers, which expect a
+    // single header and value line, keep working.
+    println!("========================== Per-Iteration Statistics ==========================");
+    print!("heapdump\titeration\ttime\tobjects\tslots\tobjects_per_ms\tslots_per_ms\tpackets");
+    for name in perf_counters {
+        print!("\tperf.{}", name);
+    }
+    println!();
+    for r in records {
+        let millis = r.time.as_micros() as f64 / 1000f64;
+        print!(
+            "{}\t{}\t{}\t{}\t{}\t{:.1}\t{:.1}\t{}",
+            paths[r.heapdump],
+            r.iteration,
+            r.time.as_micros(),
+            r.marked_objects,
+            r.slots,
+            r.marked_objects as f64 / millis,
+            r.slots as f64 / millis,
+            r.packets
+        );
+        for delta in &r.perf {
+            print!("\t{}", delta);
+        }
+        println!();
+    }
+    println!("------------------------ End Per-Iteration Statistics ------------------------");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_compact_root_subset() {
+        let args = Args::parse_from([
+            "hwgc_soft",
+            "[synthetic]objarray_64_false",
+            "-o",
+            "Bidirectional",
+            "--roots",
+            "default",
+            "trace",
+            "-t",
+            "EdgeSlot",
+            "--compact",
+        ]);
+        let e = reified_trace(BidirectionalObjectModel::<true>::new(), args).unwrap_err();
+        assert!(e.to_string().contains("--roots"));
+    }
 }
@@ -0,0 +1,167 @@
+//! Mutator activity between the closures of a heapdump (`--mutate
+//! <FRACTION>`), for studying the work a write barrier leaves to the next
+//! collection. After every closure but the last, each non-empty slot of a
+//! live object is rewritten with that probability to point to another live
+//! object, drawn from `--mutate-seed`. Like a write barrier, every write
+//! logs its slot in a remembered buffer, a FIFO [`LocalQueue`] of the
+//! work-packet loops, which is then drained by retracing from the new
+//! targets of the slots logged, as an incremental-update collector would
+//! have to. The retrace marks with a byte of its own, so it never stops at
+//! an object because the full closure marked it, and puts back the mark
+//! bytes it overwrote once the buffer is drained, so that neither the next
+//! closure nor the next retrace sees its marks.
+
+use crate::object_model::Header;
+use crate::util::typed_obj::{Address, ObjectReference};
+use crate::util::wp::LocalQueue;
+use crate::{ObjectModel, QueueDisciplineChoice};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+/// Mark byte of the retraces, which neither mark sense of the closures is
+const RETRACE_MARK: u8 = 3;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MutationStats {
+    /// Slots rewritten
+    pub writes: u64,
+    /// Entries the remembered buffer took, one per write
+    pub remembered: u64,
+    pub retraced_objects: u64,
+    pub retraced_slots: u64,
+}
+
+impl MutationStats {
+    pub fn add(&mut self, other: &MutationStats) {
+        self.writes += other.writes;
+        self.remembered += other.remembered;
+        self.retraced_objects += other.retraced_objects;
+        self.retraced_slots += other.retraced_slots;
+    }
+}
+
+pub(crate) struct Mutator {
+    fraction: f64,
+    rng: SmallRng,
+    /// Slots written since the last retrace, oldest first
+    remembered: LocalQueue<Address>,
+}
+
+impl Mutator {
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        Mutator {
+            fraction,
+            rng: SmallRng::seed_from_u64(seed),
+            remembered: LocalQueue::new(QueueDisciplineChoice::Fifo, 0),
+        }
+    }
+
+    /// Rewrites slots of the objects marked with `mark_sense` to point to
+    /// other such objects, then retraces from the remembered buffer.
+    pub fn mutate<O: ObjectModel>(&mut self, mark_sense: u8, object_model: &O) -> MutationStats {
+        let live: Vec<ObjectReference> = object_model
+            .objects_iter()
+            .filter(|&o| Header::load(o).get_mark_byte() == mark_sense)
+            .collect();
+        let mut stats = MutationStats::default();
+        if live.is_empty() {
+            return stats;
+        }
+        for &o in &live {
+            O::scan_object(o, |first, n| {
+                for i in 0..n as usize {
+                    let slot = first.shift::<u64>(i);
+                    let Some(old) = (unsafe { slot.load_object() }) else {
+                        continue;
+                    };
+                    if !self.rng.random_bool(self.fraction) {
+                        continue;
+                    }
+                    let new = live[self.rng.random_range(0..live.len())];
+                    if new == old {
+                        continue;
+                    }
+                    unsafe { slot.store(new.to_raw()) };
+                    stats.writes += 1;
+                    self.remembered
+                        .push(slot)
+                        .expect("The remembered buffer is unbounded");
+                    stats.remembered += 1;
+                }
+            });
+        }
+        self.retrace::<O>(&mut stats);
+        stats
+    }
+
+    /// Drains the remembered buffer, marking everything reachable from the
+    /// slots in it, then restores the mark bytes of the objects retraced.
+    fn retrace<O: ObjectModel>(&mut self, stats: &mut MutationStats) {
+        let mut mark_stack = vec![];
+        let mut overwritten = vec![];
+        while let Some(slot) = self.remembered.pop() {
+            mark_stack.extend(unsafe { slot.load_object() });
+            while let Some(o) = mark_stack.pop() {
+                let mark_byte = Header::load(o).get_mark_byte();
+                if !Header::attempt_mark_byte(o, RETRACE_MARK) {
+                    continue;
+                }
+                overwritten.push((o, mark_byte));
+                stats.retraced_objects += 1;
+                O::scan_object(o, |first, n| {
+                    for i in 0..n as usize {
+                        stats.retraced_slots += 1;
+                        mark_stack.extend(unsafe { first.shift::<u64>(i).load_object() });
+                    }
+                });
+            }
+        }
+        for (o, mark_byte) in overwritten {
+            let mut header = Header::load(o);
+            header.set_mark_byte(mark_byte);
+            header.store(o);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+    use crate::trace::verify::host_closure;
+    use crate::{HeapDump, OpenJDKObjectModel};
+
+    #[test]
+    fn test_mutate() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        host_closure(1, &object_model);
+        // Nothing is written with a fraction of 0
+        let stats = Mutator::new(0.0, 0).mutate(1, &object_model);
+        assert_eq!(stats, MutationStats::default());
+        let stats = Mutator::new(1.0, 0).mutate(1, &object_model);
+        assert!(stats.writes > 0);
+        assert_eq!(stats.remembered, stats.writes);
+        assert!(stats.retraced_objects > 0 && stats.retraced_objects <= 65);
+        // The retrace leaves the marks of the closure as they were
+        let objects: Vec<ObjectReference> = object_model.objects_iter().collect();
+        for &o in &objects {
+            assert_eq!(Header::load(o).get_mark_byte(), 1);
+        }
+        // Every slot still points to an object of the heap
+        for &o in &objects {
+            OpenJDKObjectModel::<false>::scan_object(o, |first, n| {
+                for i in 0..n as usize {
+                    if let Some(target) = unsafe { first.shift::<u64>(i).load_object() } {
+                        assert!(objects.contains(&target));
+                    }
+                }
+            });
+        }
+        heapdump.unmap_spaces().unwrap();
+    }
+}
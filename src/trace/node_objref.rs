@@ -1,5 +1,5 @@
-use super::{trace_object, TracingStats};
-use crate::ObjectModel;
+use super::{klass_profile, trace_object, TracingStats};
+use crate::{ObjectModel, ObjectReference};
 use std::collections::VecDeque;
 
 pub(super) unsafe fn transitive_closure_node_objref<O: ObjectModel>(
@@ -7,19 +7,19 @@ pub(super) unsafe fn transitive_closure_node_objref<O: ObjectModel>(
     object_model: &O,
 ) -> TracingStats {
     // Node-ObjRef enqueuing
-    let mut scan_queue: VecDeque<u64> = VecDeque::new();
+    let mut scan_queue: VecDeque<ObjectReference> = VecDeque::new();
     let mut marked_objects: u64 = 0;
     let mut slots: u64 = 0;
     let mut non_empty_slots: u64 = 0;
     for root in object_model.roots() {
-        let o = *root;
+        let o = ObjectReference::from_raw(*root);
         if cfg!(feature = "detailed_stats") {
             slots += 1;
-            if o != 0 {
+            if o.is_some() {
                 non_empty_slots += 1;
             }
         }
-        if o != 0 && trace_object(o, mark_sense) {
+        if let Some(o) = o.filter(|&o| trace_object(o, mark_sense)) {
             if cfg!(feature = "detailed_stats") {
                 marked_objects += 1;
             }
@@ -27,13 +27,13 @@ pub(super) unsafe fn transitive_closure_node_objref<O: ObjectModel>(
         }
     }
     while let Some(o) = scan_queue.pop_front() {
-        O::scan_object(o, |edge, repeat| {
+        klass_profile::scan_object::<O, _>(o, |edge, repeat| {
             for i in 0..repeat {
-                let child = *edge.wrapping_add(i as usize);
+                let child = edge.shift::<u64>(i as usize).load_object();
                 if cfg!(feature = "detailed_stats") {
                     slots += 1;
                 }
-                if child != 0 {
+                if let Some(child) = child {
                     if cfg!(feature = "detailed_stats") {
                         non_empty_slots += 1;
                     }
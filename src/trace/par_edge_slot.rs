@@ -2,11 +2,15 @@ use crossbeam::deque::{Steal, Stealer, Worker};
 use crossbeam::queue::SegQueue;
 use once_cell::sync::Lazy;
 
-use super::TracingStats;
+use super::{marker_map, TracingStats};
+use crate::simulate::tracing::TracingEvent;
+use crate::util::roots::RootRegistry;
+use crate::util::sampler::Sampling;
 use crate::util::tracer::Tracer;
 use crate::util::typed_obj::Slot;
-use crate::util::workers::WorkerGroup;
+use crate::util::workers::{WorkerActivity, WorkerGroup};
 use crate::{ObjectModel, TraceArgs};
+use anyhow::Result;
 use std::ops::Range;
 use std::sync::atomic::{AtomicU64, AtomicU8};
 use std::sync::Weak;
@@ -15,10 +19,12 @@ use std::{
     sync::{atomic::Ordering, Arc},
 };
 
-static mut ROOTS: Option<*const [u64]> = None;
+static ROOTS: RootRegistry = RootRegistry::new();
 
 pub struct GlobalContext {
     pub root_segments: SegQueue<Range<usize>>,
+    /// Epoch of the roots the segments are of
+    pub roots_epoch: AtomicU64,
     pub mark_state: AtomicU8,
     pub objs: AtomicU64,
     pub edges: AtomicU64,
@@ -29,6 +35,7 @@ impl GlobalContext {
     pub fn new() -> Self {
         Self {
             root_segments: SegQueue::new(),
+            roots_epoch: AtomicU64::new(0),
             mark_state: AtomicU8::new(0),
             objs: AtomicU64::new(0),
             edges: AtomicU64::new(0),
@@ -86,8 +93,10 @@ impl<O: ObjectModel> crate::util::workers::Worker for ParTracingWorker<O> {
         self.ne_slots = 0;
         let group = self.group.upgrade().unwrap();
         let mark_state = self.global.mark_state();
+        let activity = &group.activity[self.id];
         // scan roots
-        let roots = unsafe { &*ROOTS.unwrap() };
+        let roots = ROOTS.current();
+        debug_assert_eq!(roots.epoch(), GLOBAL.roots_epoch.load(Ordering::SeqCst));
         while let Some(mut range) = GLOBAL.root_segments.pop() {
             while let Some(root) = roots.get(range.start) {
                 let slot = Slot::from_raw(root as *const u64 as *mut u64);
@@ -95,11 +104,16 @@ impl<O: ObjectModel> crate::util::workers::Worker for ParTracingWorker<O> {
                 range.start += 1;
             }
         }
+        // The root slots, and then each stolen batch, count as a packet
+        if !self.queue.is_empty() {
+            WorkerActivity::count(&activity.packets);
+        }
         // trace objects
         let mut process_slot = |slot: Slot| {
             self.slots += 1;
             if let Some(o) = slot.load() {
                 if o.mark(mark_state) {
+                    marker_map::record(o, self.id);
                     self.objs += 1;
                     o.scan::<O, _>(|s| self.queue.push(s));
                 }
@@ -120,6 +134,8 @@ impl<O: ObjectModel> crate::util::workers::Worker for ParTracingWorker<O> {
                 }
                 match stealer.steal_batch_and_pop(&self.queue) {
                     Steal::Success(slot) => {
+                        WorkerActivity::count(&activity.steals);
+                        WorkerActivity::count(&activity.packets);
                         process_slot(slot);
                         continue 'outer;
                     }
@@ -127,7 +143,7 @@ impl<O: ObjectModel> crate::util::workers::Worker for ParTracingWorker<O> {
                         retry = true;
                         continue;
                     }
-                    _ => {}
+                    Steal::Empty => WorkerActivity::count(&activity.failed_steals),
                 }
             }
             if retry {
@@ -145,29 +161,36 @@ impl<O: ObjectModel> crate::util::workers::Worker for ParTracingWorker<O> {
 
 struct ParEdgeSlotTracer<O: ObjectModel> {
     group: Arc<WorkerGroup<ParTracingWorker<O>>>,
+    sampling: Option<Sampling>,
     _p: PhantomData<O>,
 }
 
 impl<O: ObjectModel> Tracer<O> for ParEdgeSlotTracer<O> {
-    fn startup(&self) {
+    fn startup(&self, _object_model: &O) -> Result<()> {
         info!("Use {} worker threads.", self.group.workers.len());
         self.group.spawn();
+        Ok(())
     }
 
     fn trace(&self, mark_sense: u8, object_model: &O) -> TracingStats {
         GLOBAL.reset();
         GLOBAL.mark_state.store(mark_sense, Ordering::SeqCst);
         // Create initial root scanning tasks
-        let roots = object_model.roots();
-        let roots_len = roots.len();
-        unsafe { ROOTS = Some(roots) };
+        let epoch = ROOTS.publish(object_model.shared_roots());
+        GLOBAL.roots_epoch.store(epoch, Ordering::SeqCst);
+        let roots_len = object_model.roots().len();
         let num_segments = self.group.workers.len() * 2;
         for id in 0..num_segments {
             let range = (roots_len * id) / num_segments..(roots_len * (id + 1)) / num_segments;
             GLOBAL.root_segments.push(range);
         }
         // Wake up workers
-        self.group.run_epoch();
+        match &self.sampling {
+            Some(sampling) => sampling.run(&self.group, || self.group.run_epoch()),
+            None => self.group.run_epoch(),
+        }
+        // The epoch has run to completion, so no root slot is loaded after this
+        ROOTS.retire();
         TracingStats {
             marked_objects: GLOBAL.objs.load(Ordering::SeqCst),
             slots: GLOBAL.edges.load(Ordering::SeqCst),
@@ -179,17 +202,63 @@ impl<O: ObjectModel> Tracer<O> for ParEdgeSlotTracer<O> {
     fn teardown(&self) {
         self.group.finish();
     }
+
+    fn take_events(&self) -> Vec<TracingEvent> {
+        self.sampling
+            .as_ref()
+            .map(|s| s.take_events())
+            .unwrap_or_default()
+    }
 }
 
 impl<O: ObjectModel> ParEdgeSlotTracer<O> {
-    pub fn new(num_workers: usize) -> Self {
+    pub fn new(num_workers: usize, sampling: Option<Sampling>) -> Self {
         Self {
             group: WorkerGroup::new(num_workers),
+            sampling,
             _p: PhantomData,
         }
     }
 }
 
 pub fn create_tracer<O: ObjectModel>(args: &TraceArgs) -> Box<dyn Tracer<O>> {
-    Box::new(ParEdgeSlotTracer::<O>::new(args.threads))
+    Box::new(ParEdgeSlotTracer::<O>::new(
+        args.threads,
+        Sampling::from_args(args),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+    use crate::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sampled_packets() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_2000").unwrap();
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        let tracer = ParEdgeSlotTracer::<OpenJDKObjectModel<false>>::new(
+            2,
+            Some(Sampling::new(Duration::from_micros(10))),
+        );
+        tracer.startup(&object_model).unwrap();
+        let stats = tracer.trace(1, &object_model);
+        tracer.teardown();
+        heapdump.unmap_spaces().unwrap();
+        assert_eq!(stats.marked_objects, 2001);
+        // The worker that took the root slot processed at least that packet
+        let packets: u64 = tracer
+            .take_events()
+            .iter()
+            .filter_map(|e| e.args.get("packets"))
+            .map(|v| v.as_u64().unwrap())
+            .sum();
+        assert!(packets >= 1);
+    }
 }
@@ -0,0 +1,152 @@
+//! Hardware performance counters around the tracing iterations
+//! (`--perf-counters`), opened with `perf_event_open` so that no external
+//! `perf stat` wrapper is needed.
+
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::FromRawFd;
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_TYPE_HW_CACHE: u32 = 3;
+
+const PERF_COUNT_HW_CACHE_L1D: u64 = 0;
+const PERF_COUNT_HW_CACHE_LL: u64 = 2;
+const PERF_COUNT_HW_CACHE_DTLB: u64 = 3;
+const PERF_COUNT_HW_CACHE_OP_READ: u64 = 0;
+const PERF_COUNT_HW_CACHE_RESULT_ACCESS: u64 = 0;
+const PERF_COUNT_HW_CACHE_RESULT_MISS: u64 = 1;
+
+/// Bits of `PerfEventAttr::flags`
+const FLAG_INHERIT: u64 = 1 << 1;
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+/// The first version of `struct perf_event_attr` (`PERF_ATTR_SIZE_VER1`),
+/// which has every field needed here.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+}
+
+fn cache_event(cache: u64, result: u64) -> (u32, u64) {
+    (
+        PERF_TYPE_HW_CACHE,
+        cache | (PERF_COUNT_HW_CACHE_OP_READ << 8) | (result << 16),
+    )
+}
+
+/// The type and config of an event, named as by `perf list`.
+fn event(name: &str) -> Result<(u32, u64)> {
+    Ok(match name {
+        "cycles" => (PERF_TYPE_HARDWARE, 0),
+        "instructions" => (PERF_TYPE_HARDWARE, 1),
+        "cache-references" => (PERF_TYPE_HARDWARE, 2),
+        "cache-misses" => (PERF_TYPE_HARDWARE, 3),
+        "branches" => (PERF_TYPE_HARDWARE, 4),
+        "branch-misses" => (PERF_TYPE_HARDWARE, 5),
+        "L1-dcache-loads" => {
+            cache_event(PERF_COUNT_HW_CACHE_L1D, PERF_COUNT_HW_CACHE_RESULT_ACCESS)
+        }
+        "L1-dcache-load-misses" => {
+            cache_event(PERF_COUNT_HW_CACHE_L1D, PERF_COUNT_HW_CACHE_RESULT_MISS)
+        }
+        "LLC-loads" => cache_event(PERF_COUNT_HW_CACHE_LL, PERF_COUNT_HW_CACHE_RESULT_ACCESS),
+        "LLC-misses" | "LLC-load-misses" => {
+            cache_event(PERF_COUNT_HW_CACHE_LL, PERF_COUNT_HW_CACHE_RESULT_MISS)
+        }
+        "dTLB-loads" => cache_event(PERF_COUNT_HW_CACHE_DTLB, PERF_COUNT_HW_CACHE_RESULT_ACCESS),
+        "dTLB-misses" | "dTLB-load-misses" => {
+            cache_event(PERF_COUNT_HW_CACHE_DTLB, PERF_COUNT_HW_CACHE_RESULT_MISS)
+        }
+        "task-clock" => (PERF_TYPE_SOFTWARE, 1),
+        "page-faults" => (PERF_TYPE_SOFTWARE, 2),
+        _ => bail!(
+            "Unknown perf event {}, expected one of cycles, instructions, cache-references, \
+             cache-misses, branches, branch-misses, L1-dcache-loads, L1-dcache-load-misses, \
+             LLC-loads, LLC-misses, dTLB-loads, dTLB-misses, task-clock and page-faults",
+            name
+        ),
+    })
+}
+
+/// Counters of the calling thread and of the threads it starts afterwards,
+/// in user space.
+pub(super) struct PerfCounters {
+    counters: Vec<(String, File)>,
+}
+
+impl PerfCounters {
+    pub(super) fn open(names: &[String]) -> Result<Self> {
+        let mut counters = vec![];
+        for name in names {
+            let (type_, config) = event(name)?;
+            let attr = PerfEventAttr {
+                type_,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config,
+                flags: FLAG_INHERIT | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+                ..Default::default()
+            };
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const PerfEventAttr,
+                    0,  // this thread
+                    -1, // on any CPU
+                    -1, // no group
+                    PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                bail!(
+                    "Cannot open perf event {}: {} (the host may not support it, or /proc/sys/kernel/perf_event_paranoid not allow it)",
+                    name,
+                    std::io::Error::last_os_error()
+                );
+            }
+            counters.push((name.clone(), unsafe { File::from_raw_fd(fd as i32) }));
+        }
+        Ok(PerfCounters { counters })
+    }
+
+    /// The current value of every counter, in the order they were given.
+    pub(super) fn read(&self) -> Result<Vec<u64>> {
+        self.counters
+            .iter()
+            .map(|(_, file)| {
+                let mut value = [0u8; 8];
+                let mut file: &File = file;
+                file.read_exact(&mut value)?;
+                Ok(u64::from_ne_bytes(value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        assert_eq!(std::mem::size_of::<PerfEventAttr>(), 72);
+        assert_eq!(event("cycles").unwrap(), (PERF_TYPE_HARDWARE, 0));
+        assert_eq!(event("LLC-misses").unwrap(), (PERF_TYPE_HW_CACHE, 0x10002));
+        assert_eq!(event("dTLB-misses").unwrap(), (PERF_TYPE_HW_CACHE, 0x10003));
+        assert!(event("bogus").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+//! A small filter of the objects a worker recently tried to mark
+//! (`--referent-filter`), standing in for a hardware referent filter in
+//! front of the mark bits. Many slots point to objects that are already
+//! marked, such as klass mirrors and interned strings, and every such slot
+//! costs a load of the header. An object in the filter was marked in this
+//! closure, by this worker or another, so its slots can be skipped without
+//! looking at the header.
+//!
+//! The filter is direct-mapped, like a tag array indexed by the low bits of
+//! the word address, so a lookup is a load and a compare.
+
+use crate::ObjectReference;
+
+pub(crate) struct ReferentFilter {
+    entries: Vec<u64>,
+    /// Mark attempts skipped because the referent was in the filter
+    pub(crate) filtered: u64,
+}
+
+impl ReferentFilter {
+    pub(crate) fn new(entries: usize) -> Self {
+        ReferentFilter {
+            entries: vec![0; entries.max(1)],
+            filtered: 0,
+        }
+    }
+
+    /// Whether `o` was seen before, recording it if not.
+    /// The caller has to attempt to mark an object that was not seen, so
+    /// that every object in the filter is marked, whoever won the attempt.
+    pub(crate) fn seen(&mut self, o: ObjectReference) -> bool {
+        let addr = o.to_raw();
+        let index = (addr >> 3) as usize % self.entries.len();
+        if self.entries[index] == addr {
+            self.filtered += 1;
+            return true;
+        }
+        self.entries[index] = addr;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_referent_filter() {
+        let o = ObjectReference::from_raw_unchecked;
+        let mut filter = ReferentFilter::new(4);
+        assert!(!filter.seen(o(0x1000)));
+        assert!(filter.seen(o(0x1000)));
+        assert!(!filter.seen(o(0x1008)));
+        // 0x1020 maps to the entry of 0x1000 and evicts it
+        assert!(!filter.seen(o(0x1020)));
+        assert!(!filter.seen(o(0x1000)));
+        assert!(filter.seen(o(0x1008)));
+        assert_eq!(filter.filtered, 2);
+    }
+}
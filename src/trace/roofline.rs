@@ -0,0 +1,110 @@
+//! Roofline-style bandwidth accounting of the tracing loops (`--roofline`):
+//! the bytes of heap a closure touches, over its time, against the peak
+//! memory bandwidth of the host.
+
+use crate::*;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// Bytes of heap touched by a transitive closure, each counted once however
+/// often the loop reads or writes it.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct HeapBytes {
+    /// Mark words, TIB words of the objects scanned through their TIB, and
+    /// lengths of object arrays
+    pub(super) headers: u64,
+    /// Slots of objects other than object arrays
+    pub(super) slots: u64,
+    /// Slots of object arrays
+    pub(super) objarray_payload: u64,
+}
+
+impl HeapBytes {
+    pub(super) fn add(&mut self, other: &HeapBytes) {
+        self.headers += other.headers;
+        self.slots += other.slots;
+        self.objarray_payload += other.objarray_payload;
+    }
+
+    pub(super) fn total(&self) -> u64 {
+        self.headers + self.slots + self.objarray_payload
+    }
+}
+
+/// Walks the objects reachable from the roots, the same ones every closure
+/// marks.
+pub(super) fn heap_bytes<O: ObjectModel>(object_model: &O) -> HeapBytes {
+    let mut bytes = HeapBytes::default();
+    let mut visited: HashSet<ObjectReference> = HashSet::new();
+    let mut stack: Vec<ObjectReference> = object_model
+        .roots()
+        .iter()
+        .filter_map(|&r| ObjectReference::from_raw(r))
+        .collect();
+    while let Some(o) = stack.pop() {
+        if !visited.insert(o) {
+            continue;
+        }
+        let objarray = unsafe { O::is_objarray(o) };
+        bytes.headers += 8;
+        if O::tib_lookup_required(o) {
+            bytes.headers += 8;
+        }
+        if objarray {
+            bytes.headers += 8;
+        }
+        O::scan_object(o, |first, count| {
+            for i in 0..count as usize {
+                stack.extend(unsafe { first.shift::<u64>(i).load_object() });
+            }
+            if objarray {
+                bytes.objarray_payload += count * 8;
+            } else {
+                bytes.slots += count * 8;
+            }
+        });
+    }
+    bytes
+}
+
+/// Buffer read by `detect_peak_bandwidth`, big enough to not fit any cache
+const PEAK_BUFFER_BYTES: usize = 256 << 20;
+
+/// Measures the read bandwidth of all cores streaming through a large
+/// buffer, in GB/s, as the best of a few passes.
+pub(super) fn detect_peak_bandwidth() -> f64 {
+    let buffer: Vec<u64> = vec![1; PEAK_BUFFER_BYTES / 8];
+    let mut best: f64 = 0.0;
+    for _ in 0..3 {
+        let start = Instant::now();
+        let sum: u64 = buffer
+            .par_chunks(1 << 16)
+            .map(|chunk| chunk.iter().fold(0u64, |a, &b| a.wrapping_add(b)))
+            .sum();
+        let secs = start.elapsed().as_secs_f64();
+        std::hint::black_box(sum);
+        best = best.max(PEAK_BUFFER_BYTES as f64 / secs / 1e9);
+    }
+    best
+}
+
+/// Bandwidth in GB/s of touching `bytes` in `micros` microseconds.
+pub(super) fn bandwidth(bytes: u64, micros: u128) -> f64 {
+    if micros == 0 {
+        return 0.0;
+    }
+    bytes as f64 / micros as f64 / 1e3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth() {
+        // 1 GB in a second
+        assert_eq!(bandwidth(1_000_000_000, 1_000_000), 1.0);
+        assert_eq!(bandwidth(42, 0), 0.0);
+    }
+}
@@ -0,0 +1,103 @@
+//! DRAM row buffer locality of the closure (`--row-buffer`): the cache lines
+//! a depth-first closure touches, in order, put through the `AddressMapping`
+//! of the simulator as if the heap were physically addressed, with every
+//! bank keeping its last row open. This is the host side of the row buffer
+//! stats of the NMPGC simulation.
+
+use crate::simulate::{num_banks, RowLocality, LOG_LINE_SIZE};
+use crate::*;
+use std::collections::HashSet;
+
+#[derive(Debug, Default, Clone)]
+pub(super) struct RowBufferStats {
+    /// Line accesses, consecutive accesses to the same line counting once
+    /// as a cache would absorb them
+    pub(super) accesses: u64,
+    pub(super) row_hits: u64,
+    pub(super) locality: RowLocality,
+}
+
+impl RowBufferStats {
+    pub(super) fn add(&mut self, other: &RowBufferStats) {
+        self.accesses += other.accesses;
+        self.row_hits += other.row_hits;
+        self.locality.merge(&other.locality);
+    }
+
+    pub(super) fn row_hit_rate(&self) -> f64 {
+        self.row_hits as f64 / self.accesses.max(1) as f64
+    }
+}
+
+/// The open row of every bank under a stream of line accesses.
+struct RowBuffers {
+    open_rows: Vec<Option<u16>>,
+    last_line: Option<u64>,
+    stats: RowBufferStats,
+}
+
+impl RowBuffers {
+    fn new() -> Self {
+        RowBuffers {
+            open_rows: vec![None; num_banks()],
+            last_line: None,
+            stats: RowBufferStats::default(),
+        }
+    }
+
+    fn access(&mut self, addr: u64) {
+        let line = addr >> LOG_LINE_SIZE;
+        if self.last_line.replace(line) == Some(line) {
+            return;
+        }
+        let (bank, row) = self.stats.locality.record(line << LOG_LINE_SIZE);
+        self.stats.accesses += 1;
+        if self.open_rows[bank].replace(row) == Some(row) {
+            self.stats.row_hits += 1;
+        }
+    }
+}
+
+/// Replays the header and slot reads of the objects reachable from the
+/// roots, the same ones every closure marks.
+pub(super) fn row_buffer_stats<O: ObjectModel>(object_model: &O) -> RowBufferStats {
+    let mut buffers = RowBuffers::new();
+    let mut visited: HashSet<ObjectReference> = HashSet::new();
+    let mut stack: Vec<ObjectReference> = object_model
+        .roots()
+        .iter()
+        .filter_map(|&r| ObjectReference::from_raw(r))
+        .collect();
+    while let Some(o) = stack.pop() {
+        if !visited.insert(o) {
+            continue;
+        }
+        buffers.access(o.to_raw());
+        O::scan_object(o, |first, count| {
+            for i in 0..count as usize {
+                let slot = first.shift::<u64>(i);
+                buffers.access(slot.as_u64());
+                stack.extend(unsafe { slot.load_object() });
+            }
+        });
+    }
+    buffers.stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row_buffers() {
+        let mut buffers = RowBuffers::new();
+        // Same line, same row, another bank, then back to the first row
+        for addr in [0, 8, 1 << 6, 1 << 14, 2 << 6, 1 << 20] {
+            buffers.access(addr);
+        }
+        assert_eq!(buffers.stats.accesses, 5);
+        assert_eq!(buffers.stats.row_hits, 2);
+        assert_eq!(buffers.stats.locality.rows_touched(), 3);
+        assert_eq!(buffers.stats.locality.banks_touched(), 2);
+    }
+}
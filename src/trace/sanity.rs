@@ -1,34 +1,121 @@
+use crate::progress::Progress;
 use crate::HeapDump;
 
 use std::collections::HashMap;
-use std::collections::HashSet;
 
-pub fn sanity_trace(heapdump: &HeapDump) -> usize {
+/// What the sanity trace found out about the heap graph.
+#[derive(Debug, Default, PartialEq)]
+pub struct SanityStats {
+    pub reachable_objects: usize,
+    /// Edges back to an object on the current path of the depth-first
+    /// search, each of which closes at least one cycle
+    pub back_edges: usize,
+    /// Edges on the longest path of the depth-first search, which is what
+    /// a recursive tracer would need stack frames for
+    pub max_depth: usize,
+    /// Objects first reached from each root, in the order of the roots, so
+    /// that they add up to the reachable objects
+    pub reached_per_root: Vec<usize>,
+}
+
+impl SanityStats {
+    pub fn print(&self) {
+        let roots_reaching = self.reached_per_root.iter().filter(|&&n| n > 0).count();
+        println!(
+            "Sanity trace: {} reachable objects, {} back edges, max depth {}, {} of {} roots reach new objects, at most {} from one root",
+            self.reachable_objects,
+            self.back_edges,
+            self.max_depth,
+            roots_reaching,
+            self.reached_per_root.len(),
+            self.reached_per_root.iter().max().unwrap_or(&0)
+        );
+    }
+}
+
+/// Traces the heapdump itself, independently of any object model, with an
+/// explicit stack so that long linked lists do not overflow the native one.
+pub fn sanity_trace(heapdump: &HeapDump) -> SanityStats {
     let mut objects: HashMap<_, _> = HashMap::new();
     for object in &heapdump.objects {
-        objects.insert(object.start, object.clone());
+        objects.insert(object.start, object);
     }
 
-    let mut reachable_objects: HashSet<u64> = HashSet::new();
-    let mut mark_stack: Vec<u64> = vec![];
-    for root in &heapdump.roots {
+    // Everything is reachable from the full set of roots
+    let progress = Progress::new(
+        "Sanity trace",
+        "objects",
+        Some(heapdump.objects.len() as u64),
+    );
+    let mut stats = SanityStats::default();
+    // Whether each object reached so far is still on the path
+    let mut on_path: HashMap<u64, bool> = HashMap::new();
+    // The path, with the next edge to follow from each object on it
+    let mut path: Vec<(u64, usize)> = vec![];
+    for root in heapdump.all_roots() {
         debug_assert!(objects.contains_key(&root.objref));
-        mark_stack.push(root.objref);
-    }
-    // println!("Sanity mark stack {} objects", mark_stack.len());
-    while let Some(o) = mark_stack.pop() {
-        // println!("Sanity mark stack {} objects", mark_stack.len());
-        if reachable_objects.contains(&o) {
-            continue;
+        let before = on_path.len();
+        if on_path.insert(root.objref, true).is_none() {
+            progress.add(1);
+            path.push((root.objref, 0));
         }
-        reachable_objects.insert(o);
-        let obj = objects.get(&o).unwrap();
-        for edge in &obj.edges {
-            if edge.objref != 0 {
-                mark_stack.push(edge.objref);
-                // println!("Sanity mark stack {} objects", mark_stack.len());
+        while let Some((o, next)) = path.last_mut() {
+            let edges = &objects[o].edges;
+            let Some(edge) = edges.get(*next) else {
+                on_path.insert(*o, false);
+                path.pop();
+                continue;
+            };
+            *next += 1;
+            if edge.objref == 0 {
+                continue;
+            }
+            match on_path.get(&edge.objref) {
+                None => {
+                    on_path.insert(edge.objref, true);
+                    progress.add(1);
+                    path.push((edge.objref, 0));
+                    stats.max_depth = stats.max_depth.max(path.len() - 1);
+                }
+                Some(true) => stats.back_edges += 1,
+                Some(false) => {}
             }
         }
+        stats.reached_per_root.push(on_path.len() - before);
+    }
+    progress.finish();
+    stats.reachable_objects = on_path.len();
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heapdump::{NormalEdge, RootEdge};
+
+    #[test]
+    fn test_deep_list_with_cycle() {
+        // Deep enough to overflow the stack of a recursive trace
+        let mut heapdump = HeapDump::from_path("[synthetic]linked_list_1000000").unwrap();
+        let first = heapdump.objects[0].start;
+        let last = heapdump.objects.last_mut().unwrap();
+        last.edges.push(NormalEdge {
+            slot: last.start + 16,
+            objref: first,
+        });
+        // A second root in the middle of the list reaches nothing new
+        heapdump.roots.push(RootEdge {
+            objref: heapdump.objects[10].start,
+        });
+        let stats = sanity_trace(&heapdump);
+        assert_eq!(
+            stats,
+            SanityStats {
+                reachable_objects: 1_000_000,
+                back_edges: 1,
+                max_depth: 999_999,
+                reached_per_root: vec![1_000_000, 0],
+            }
+        );
     }
-    reachable_objects.len()
 }
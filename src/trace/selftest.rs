@@ -0,0 +1,264 @@
+//! Differential testing of the tracing loops (`selftest`): every selected
+//! loop traces the same heap as EdgeSlot, the reference, and has to mark
+//! the same objects and scan the same slots.
+
+use super::shape_cache::ShapeLruCache;
+use super::{create_tracer, transitive_closure, TracingLoopChoice, TracingStats};
+use crate::failure::Failure;
+use crate::object_model::Header;
+use crate::*;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+
+/// Where the model put each heapdump object, which is not necessarily its
+/// start if the model changes the layout of objects.
+pub(super) fn model_addresses<O: ObjectModel>(
+    object_model: &O,
+    heapdump: &HeapDump,
+) -> Vec<ObjectReference> {
+    let mut addresses: Vec<ObjectReference> = object_model.objects_iter().collect();
+    addresses.sort_unstable();
+    heapdump
+        .objects
+        .iter()
+        .map(|o| {
+            let i = addresses.partition_point(|&a| a.to_raw() < o.start);
+            let a = addresses.get(i).copied();
+            assert!(
+                a.is_some_and(|a| a.to_raw() < o.start + o.size),
+                "0x{:x} was not restored",
+                o.start
+            );
+            a.unwrap()
+        })
+        .collect()
+}
+
+/// One bit per heapdump object, set if the closure marked it.
+fn mark_bitmap(addresses: &[ObjectReference], mark_sense: u8) -> Vec<u64> {
+    let mut bitmap = vec![0u64; addresses.len().div_ceil(64)];
+    for (i, &a) in addresses.iter().enumerate() {
+        if Header::load(a).get_mark_byte() == mark_sense {
+            bitmap[i / 64] |= 1 << (i % 64);
+        }
+    }
+    bitmap
+}
+
+/// Unmarks every object, so that a closure cannot skip objects a previous
+/// one marked by mistake.
+fn clear_marks(addresses: &[ObjectReference]) {
+    for &a in addresses {
+        let mut header = Header::load(a);
+        header.set_mark_byte(0);
+        header.store(a);
+    }
+}
+
+fn is_set(bitmap: &[u64], i: usize) -> bool {
+    bitmap[i / 64] & (1 << (i % 64)) != 0
+}
+
+/// What a closure did to the heap.
+struct Outcome {
+    marked: Vec<u64>,
+    /// Slots of the marked objects
+    slots: u64,
+    stats: TracingStats,
+}
+
+fn run_loop<O: ObjectModel>(
+    selftest_args: &SelftestArgs,
+    l: TracingLoopChoice,
+    object_model: &mut O,
+    addresses: &[ObjectReference],
+) -> Result<Outcome> {
+    clear_marks(addresses);
+    let mark_sense = 1;
+    let threads = selftest_args.threads.to_string();
+    let wp_capacity = selftest_args.wp_capacity.to_string();
+    let name = l.to_possible_value().unwrap();
+    let args = TraceArgs::parse_from([
+        "trace",
+        "-t",
+        name.get_name(),
+        "-i",
+        "1",
+        "--threads",
+        &threads,
+        "--wp-capacity",
+        &wp_capacity,
+    ]);
+    let mut shape_cache: ShapeLruCache<O> = ShapeLruCache::new(args.shape_cache_size);
+    let tracer = create_tracer::<O>(&args);
+    if let Some(tracer) = tracer.as_ref() {
+        tracer.startup(object_model)?;
+    }
+    let timed_stats = transitive_closure(
+        &args,
+        mark_sense,
+        object_model,
+        &mut shape_cache,
+        tracer.as_deref(),
+    );
+    if let Some(tracer) = tracer.as_ref() {
+        tracer.teardown();
+    }
+    let marked = mark_bitmap(addresses, mark_sense);
+    let mut slots = 0;
+    for (i, &a) in addresses.iter().enumerate() {
+        if is_set(&marked, i) {
+            slots += O::num_edges(a);
+        }
+    }
+    Ok(Outcome {
+        marked,
+        slots,
+        stats: timed_stats.stats,
+    })
+}
+
+/// Prints how `outcome` of `l` differs from the reference, and returns
+/// whether it does.
+fn report_divergence(
+    heapdump: &HeapDump,
+    l: TracingLoopChoice,
+    reference: &Outcome,
+    outcome: &Outcome,
+) -> bool {
+    let mut diverged = false;
+    let mut only_reference = 0;
+    let mut only_loop = 0;
+    let mut first = None;
+    for i in 0..heapdump.objects.len() {
+        let (r, o) = (is_set(&reference.marked, i), is_set(&outcome.marked, i));
+        if r != o {
+            if r {
+                only_reference += 1;
+            } else {
+                only_loop += 1;
+            }
+            first.get_or_insert(i);
+        }
+    }
+    if let Some(i) = first {
+        diverged = true;
+        let object = &heapdump.objects[i];
+        println!(
+            "Selftest: {:?} marked {} objects EdgeSlot did not, and missed {}; first 0x{:x} (klass 0x{:x}) was {}marked by EdgeSlot",
+            l,
+            only_loop,
+            only_reference,
+            object.start,
+            object.klass,
+            if is_set(&reference.marked, i) { "" } else { "not " }
+        );
+    }
+    if outcome.slots != reference.slots {
+        diverged = true;
+        println!(
+            "Selftest: {:?} marked objects with {} slots, EdgeSlot {}",
+            l, outcome.slots, reference.slots
+        );
+    }
+    // The loops only count with detailed stats
+    if cfg!(feature = "detailed_stats")
+        && (outcome.stats.marked_objects, outcome.stats.slots)
+            != (reference.stats.marked_objects, reference.stats.slots)
+    {
+        diverged = true;
+        println!(
+            "Selftest: {:?} counted {} objects and {} slots, EdgeSlot {} and {}",
+            l,
+            outcome.stats.marked_objects,
+            outcome.stats.slots,
+            reference.stats.marked_objects,
+            reference.stats.slots
+        );
+    }
+    diverged
+}
+
+pub fn selftest<O: ObjectModel>(mut object_model: O, args: Args) -> Result<()> {
+    let selftest_args = if let Some(Commands::Selftest(a)) = args.command {
+        a
+    } else {
+        panic!("Incorrect dispatch");
+    };
+    let loops: Vec<TracingLoopChoice> = if selftest_args.tracing_loops.is_empty() {
+        TracingLoopChoice::value_variants()
+            .iter()
+            .copied()
+            .filter(|&l| l != TracingLoopChoice::EdgeSlot)
+            .collect()
+    } else {
+        selftest_args.tracing_loops.clone()
+    };
+    let mut divergent = vec![];
+    for path in &args.paths {
+        object_model.reset();
+        let mut heapdump = HeapDump::from_path(path)?;
+        heapdump.select_roots(&args.roots)?;
+        if let Some(seed) = args.aslr_seed {
+            heapdump.randomize_layout(seed)?;
+        }
+        heapdump.map_spaces()?;
+        object_model.restore_objects(&heapdump);
+        let addresses = model_addresses(&object_model, &heapdump);
+        let reference = run_loop(
+            &selftest_args,
+            TracingLoopChoice::EdgeSlot,
+            &mut object_model,
+            &addresses,
+        )?;
+        let marked: u32 = reference.marked.iter().map(|w| w.count_ones()).sum();
+        println!(
+            "Selftest: EdgeSlot marked {} of {} objects in {}, with {} slots",
+            marked,
+            heapdump.objects.len(),
+            path,
+            reference.slots
+        );
+        for &l in &loops {
+            let outcome = run_loop(&selftest_args, l, &mut object_model, &addresses)?;
+            if report_divergence(&heapdump, l, &reference, &outcome) {
+                divergent.push(format!("{:?} on {}", l, path));
+            } else {
+                println!("Selftest: {:?} agrees", l);
+            }
+        }
+        heapdump.unmap_spaces()?;
+    }
+    if !divergent.is_empty() {
+        return Err(anyhow!(
+            "Tracing loops diverged from EdgeSlot: {}",
+            divergent.join(", ")
+        )
+        .context(Failure::Verification));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+
+    #[test]
+    fn test_selftest() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_2000").unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        let args = Args::parse_from([
+            "hwgc_soft",
+            "[synthetic]objarray_2000",
+            "-o",
+            "OpenJDK",
+            "selftest",
+            "--threads",
+            "2",
+        ]);
+        selftest(object_model, args).unwrap();
+    }
+}
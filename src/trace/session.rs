@@ -0,0 +1,255 @@
+//! Tracing heapdumps from other crates and tests without building CLI
+//! argument structs. A [`TraceSession`] is configured with `with_*` methods
+//! and runs the closures of `trace` without its reporting, returning the
+//! statistics of the last iteration of each heapdump.
+//!
+//! ```no_run
+//! use hwgc_soft::{ObjectModelChoice, TraceSession, TracingLoopChoice};
+//!
+//! let traces = TraceSession::new()
+//!     .with_object_model(ObjectModelChoice::Bidirectional)
+//!     .with_heapdump("[synthetic]objarray_4000")
+//!     .with_tracing_loop(TracingLoopChoice::ParEdgeSlot)
+//!     .with_threads(4)
+//!     .run()
+//!     .unwrap();
+//! println!("{} objects marked", traces[0].marked_objects);
+//! ```
+
+use super::shape_cache::ShapeLruCache;
+use super::{create_tracer, transitive_closure, TimedTracingStats, TracingLoopChoice};
+use crate::object_model::Header;
+use crate::*;
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+
+#[derive(Debug, Clone)]
+pub struct TraceSession {
+    object_model: ObjectModelChoice,
+    object_map: bool,
+    paths: Vec<String>,
+    roots: Vec<String>,
+    tracing_loop: TracingLoopChoice,
+    threads: usize,
+    iterations: usize,
+    wp_capacity: usize,
+}
+
+/// The last closure of one heapdump.
+#[derive(Debug)]
+pub struct HeapdumpTrace {
+    pub path: String,
+    /// Most counts are only kept with the `detailed_stats` feature.
+    pub closure: TimedTracingStats,
+    /// Objects marked by the closure, counted on the heap after it, so
+    /// with or without `detailed_stats`
+    pub marked_objects: usize,
+}
+
+impl Default for TraceSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceSession {
+    /// An EdgeSlot session of one iteration with OpenJDK objects, and
+    /// threads as `trace` defaults to, with no heapdumps yet.
+    pub fn new() -> Self {
+        let defaults = TraceArgs::parse_from(["trace", "-t", "EdgeSlot"]);
+        TraceSession {
+            object_model: ObjectModelChoice::OpenJDK,
+            object_map: false,
+            paths: vec![],
+            roots: vec![],
+            tracing_loop: defaults.tracing_loop,
+            threads: defaults.threads,
+            iterations: 1,
+            wp_capacity: defaults.wp_capacity,
+        }
+    }
+
+    pub fn with_object_model(mut self, object_model: ObjectModelChoice) -> Self {
+        self.object_model = object_model;
+        self
+    }
+
+    /// See `--object-map`.
+    pub fn with_object_map(mut self, object_map: bool) -> Self {
+        self.object_map = object_map;
+        self
+    }
+
+    /// Adds a heapdump to trace, after those added before.
+    pub fn with_heapdump(mut self, path: impl Into<String>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Only traces from these root sets of each heapdump, see `--roots`.
+    pub fn with_roots<S: Into<String>>(mut self, roots: impl IntoIterator<Item = S>) -> Self {
+        self.roots = roots.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_tracing_loop(mut self, tracing_loop: TracingLoopChoice) -> Self {
+        self.tracing_loop = tracing_loop;
+        self
+    }
+
+    /// Worker threads of the parallel tracing loops.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Closures of each heapdump, of which the last is returned.
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// See `--wp-capacity`.
+    pub fn with_wp_capacity(mut self, wp_capacity: usize) -> Self {
+        self.wp_capacity = wp_capacity;
+        self
+    }
+
+    /// Traces every heapdump in turn.
+    pub fn run(&self) -> Result<Vec<HeapdumpTrace>> {
+        let object_map = self.object_map;
+        match self.object_model {
+            ObjectModelChoice::OpenJDK => {
+                self.run_with(OpenJDKObjectModel::<false>::new().with_object_map(object_map))
+            }
+            ObjectModelChoice::OpenJDKAE => {
+                self.run_with(OpenJDKObjectModel::<true>::new().with_object_map(object_map))
+            }
+            ObjectModelChoice::Bidirectional => {
+                self.run_with(BidirectionalObjectModel::<true>::new().with_object_map(object_map))
+            }
+            ObjectModelChoice::BidirectionalFallback => {
+                self.run_with(BidirectionalObjectModel::<false>::new().with_object_map(object_map))
+            }
+        }
+    }
+
+    fn trace_args(&self) -> TraceArgs {
+        let name = self.tracing_loop.to_possible_value().unwrap();
+        TraceArgs::parse_from([
+            "trace",
+            "-t",
+            name.get_name(),
+            "-i",
+            &self.iterations.to_string(),
+            "--threads",
+            &self.threads.to_string(),
+            "--wp-capacity",
+            &self.wp_capacity.to_string(),
+        ])
+    }
+
+    fn checked_trace_args(&self) -> Result<TraceArgs> {
+        let args = self.trace_args();
+        if args.tracing_loop == TracingLoopChoice::ShapeCache && args.iterations != 1 {
+            bail!("Only one iteration per heapdump is supported with the shape cache");
+        }
+        Ok(args)
+    }
+
+    /// Traces the heap already restored in `object_model`, ignoring the
+    /// heapdumps of the session. Returns the last closure with its mark
+    /// sense, if there was any.
+    pub(crate) fn trace_restored<O: ObjectModel>(
+        &self,
+        object_model: &mut O,
+    ) -> Result<Option<(TimedTracingStats, u8)>> {
+        let args = self.checked_trace_args()?;
+        let mut shape_cache: ShapeLruCache<O> = ShapeLruCache::new(args.shape_cache_size);
+        Self::trace_heap(&args, object_model, &mut shape_cache)
+    }
+
+    fn trace_heap<O: ObjectModel>(
+        args: &TraceArgs,
+        object_model: &mut O,
+        shape_cache: &mut ShapeLruCache<O>,
+    ) -> Result<Option<(TimedTracingStats, u8)>> {
+        let tracer = create_tracer::<O>(args);
+        if let Some(tracer) = tracer.as_ref() {
+            tracer.startup(object_model)?;
+        }
+        let mut last = None;
+        for i in 0..args.iterations {
+            let mark_sense = (i % 2 == 0) as u8;
+            let closure = transitive_closure(
+                args,
+                mark_sense,
+                object_model,
+                shape_cache,
+                tracer.as_deref(),
+            );
+            last = Some((closure, mark_sense));
+        }
+        if let Some(tracer) = tracer.as_ref() {
+            tracer.teardown();
+        }
+        Ok(last)
+    }
+
+    fn run_with<O: ObjectModel>(&self, mut object_model: O) -> Result<Vec<HeapdumpTrace>> {
+        let args = self.checked_trace_args()?;
+        let mut heapdumps = vec![];
+        for path in &self.paths {
+            let mut heapdump = HeapDump::from_path(path)?;
+            heapdump.select_roots(&self.roots)?;
+            object_model.restore_tibs(&heapdump);
+            heapdumps.push(heapdump);
+        }
+        let mut shape_cache: ShapeLruCache<O> = ShapeLruCache::new(args.shape_cache_size);
+        let mut traces = vec![];
+        for (path, heapdump) in self.paths.iter().zip(&heapdumps) {
+            object_model.reset();
+            heapdump.map_spaces()?;
+            object_model.restore_objects(heapdump);
+            let last = Self::trace_heap(&args, &mut object_model, &mut shape_cache)?;
+            if let Some((closure, mark_sense)) = last {
+                let marked_objects = object_model
+                    .objects_iter()
+                    .filter(|&o| Header::load(o).get_mark_byte() == mark_sense)
+                    .count();
+                traces.push(HeapdumpTrace {
+                    path: path.clone(),
+                    closure,
+                    marked_objects,
+                });
+            }
+            heapdump.unmap_spaces()?;
+        }
+        Ok(traces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+
+    #[test]
+    fn test_trace_session() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        for tracing_loop in [TracingLoopChoice::EdgeSlot, TracingLoopChoice::ParEdgeSlot] {
+            let traces = TraceSession::new()
+                .with_object_model(ObjectModelChoice::Bidirectional)
+                .with_heapdump("[synthetic]objarray_64_false")
+                .with_heapdump("[synthetic]objarray_16_false")
+                .with_tracing_loop(tracing_loop)
+                .with_threads(2)
+                .with_iterations(2)
+                .run()
+                .unwrap();
+            let marked: Vec<usize> = traces.iter().map(|t| t.marked_objects).collect();
+            assert_eq!(marked, [65, 17], "{:?}", tracing_loop);
+            assert_eq!(traces[1].path, "[synthetic]objarray_16_false");
+        }
+    }
+}
@@ -1,86 +1,333 @@
-use super::{trace_object, TracingStats};
-use crate::object_model::{HasTibType, TibType};
-use crate::{ObjectModel, TraceArgs};
+use super::{klass_profile, trace_object, TracingStats};
+use crate::object_model::{HasTibType, TibRecord, TibType};
+use crate::results::StatsTable;
+use crate::{Address, ObjectModel, ObjectReference, ShapeCacheIndexChoice, TraceArgs};
+use anyhow::{bail, Result};
 use lru::LruCache;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{BufReader, BufWriter},
     num::NonZeroUsize,
 };
 
 pub(crate) struct ShapeLruCache<O: ObjectModel> {
-    cache: LruCache<*const O::Tib, ()>,
+    /// A single set if the cache is fully associative
+    sets: Vec<LruCache<*const O::Tib, ()>>,
+    index: ShapeCacheIndexChoice,
+    /// A fully associative cache of the same capacity as `sets`, if that is
+    /// set associative, to tell conflict misses from capacity misses
+    shadow: Option<LruCache<*const O::Tib, ()>>,
+    /// Fully associative, holds what `sets` evicts
+    victim: Option<LruCache<*const O::Tib, ()>>,
+    /// Larger and slower, looked up when both `sets` and `victim` miss.
+    /// Every shape brought into `sets` is also put here.
+    l2: Option<LruCache<*const O::Tib, ()>>,
     stats: HashMap<ShapeCacheResponse, usize>,
     tib_seen: HashSet<*const O::Tib>,
+    /// Lookups of each shape, instance mirrors aside
+    accesses: HashMap<*const O::Tib, u64>,
 }
+
+/// A shape, by klass rather than by TIB so that another run can find it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ShapeEntry {
+    klass: u64,
+    ttype: u8,
+    /// Runs of reference fields, as `export-tibs` writes them
+    blocks: Vec<(u64, u64)>,
+    accesses: u64,
+}
+
+/// What `--shape-cache-export` writes, each level from the least to the
+/// most recently used shape.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct ShapeCacheContents {
+    /// Every set of the first level in turn
+    sets: Vec<ShapeEntry>,
+    victim: Vec<ShapeEntry>,
+    l2: Vec<ShapeEntry>,
+    /// Every shape looked up, so that a run warmed with them has no
+    /// compulsory misses for them
+    seen: Vec<ShapeEntry>,
+}
+
 #[derive(Default, Debug)]
-pub(crate) struct ShapeCacheStats {
+pub struct ShapeCacheStats {
     hits: usize,
+    victim_hits: usize,
+    l2_hits: usize,
+    /// Misses in every level
     capacity_misses: usize,
+    /// Misses in every level that a fully associative first level would
+    /// have hit
+    conflict_misses: usize,
     compulsory_misses_instance: usize,
     compulsory_misses_instance_mirror: usize,
 }
 
 impl ShapeCacheStats {
-    pub(crate) fn get_stats_header(&self) -> &str {
-        "shape_cache.hit\tshape_cache.cap_miss\tshape_cache.comp_miss_inst\tshape_cache.comp_miss_mirror"
-    }
-
-    pub(crate) fn get_stats_value(&self) -> String {
-        format!(
-            "{}\t{}\t{}\t{}",
-            self.hits,
-            self.capacity_misses,
-            self.compulsory_misses_instance,
-            self.compulsory_misses_instance_mirror
-        )
+    pub(crate) fn add_columns(&self, table: &mut StatsTable) {
+        table.push("shape_cache.hit", self.hits as u64);
+        table.push("shape_cache.victim_hit", self.victim_hits as u64);
+        table.push("shape_cache.l2_hit", self.l2_hits as u64);
+        table.push("shape_cache.cap_miss", self.capacity_misses as u64);
+        table.push("shape_cache.conflict_miss", self.conflict_misses as u64);
+        table.push(
+            "shape_cache.comp_miss_inst",
+            self.compulsory_misses_instance as u64,
+        );
+        table.push(
+            "shape_cache.comp_miss_mirror",
+            self.compulsory_misses_instance_mirror as u64,
+        );
     }
 
     pub(crate) fn add(&mut self, other: &Self) {
         self.hits += other.hits;
+        self.victim_hits += other.victim_hits;
+        self.l2_hits += other.l2_hits;
         self.capacity_misses += other.capacity_misses;
+        self.conflict_misses += other.conflict_misses;
         self.compulsory_misses_instance += other.compulsory_misses_instance;
         self.compulsory_misses_instance_mirror += other.compulsory_misses_instance_mirror;
     }
 }
 
+/// Checks that `--shape-cache-ways` divides `--shape-cache-size`, which
+/// `ShapeLruCache::with_associativity` relies on.
+pub(crate) fn check_args(args: &TraceArgs) -> Result<()> {
+    let (capacity, ways) = (args.shape_cache_size, args.shape_cache_ways);
+    if ways > 0 && !(ways <= capacity && capacity.is_multiple_of(ways)) {
+        bail!(
+            "--shape-cache-size {} is not a multiple of --shape-cache-ways {}",
+            capacity,
+            ways
+        );
+    }
+    Ok(())
+}
+
 impl<O: ObjectModel> ShapeLruCache<O> {
     pub(crate) fn new(capacity: usize) -> Self {
         ShapeLruCache {
-            cache: LruCache::new(NonZeroUsize::new(capacity).unwrap()),
+            sets: vec![LruCache::new(NonZeroUsize::new(capacity).unwrap())],
+            index: ShapeCacheIndexChoice::LowBits,
+            shadow: None,
+            victim: None,
+            l2: None,
             stats: HashMap::new(),
             tib_seen: HashSet::new(),
+            accesses: HashMap::new(),
+        }
+    }
+
+    /// Writes the shapes in every level, with how often each was looked up.
+    pub(crate) fn export(&self, path: &str) -> Result<()> {
+        let records: HashMap<u64, TibRecord> =
+            O::tib_records().into_iter().map(|r| (r.tib, r)).collect();
+        let contents = self.contents(&records);
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &contents)?;
+        info!(
+            "Wrote {} cached and {} seen shapes to {}",
+            contents.sets.len(),
+            contents.seen.len(),
+            path
+        );
+        Ok(())
+    }
+
+    /// Warms up the cache with the shapes written by [`Self::export`], for
+    /// the klasses that have the same shape in this run.
+    pub(crate) fn import(&mut self, path: &str) -> Result<()> {
+        let contents: ShapeCacheContents =
+            serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let missing = self.preload(&contents, &O::tib_records());
+        if missing > 0 {
+            warn!(
+                "{} shapes of {} are not the shapes of any klass restored",
+                missing, path
+            );
+        }
+        Ok(())
+    }
+
+    fn entries(
+        &self,
+        records: &HashMap<u64, TibRecord>,
+        tibs: impl Iterator<Item = *const O::Tib>,
+    ) -> Vec<ShapeEntry> {
+        tibs.filter_map(|tib| {
+            let r = records.get(&(tib as usize as u64))?;
+            Some(ShapeEntry {
+                klass: r.klass,
+                ttype: r.ttype,
+                blocks: r.blocks.clone(),
+                accesses: self.accesses.get(&tib).copied().unwrap_or(0),
+            })
+        })
+        .collect()
+    }
+
+    fn contents(&self, records: &HashMap<u64, TibRecord>) -> ShapeCacheContents {
+        // LRU caches iterate from the most recently used
+        let lru = |cache: &LruCache<*const O::Tib, ()>| {
+            self.entries(records, cache.iter().rev().map(|(&tib, _)| tib))
+        };
+        let mut seen: Vec<*const O::Tib> = self.tib_seen.iter().copied().collect();
+        // By address, so that the file is stable across runs
+        seen.sort_unstable();
+        ShapeCacheContents {
+            sets: self.sets.iter().flat_map(lru).collect(),
+            victim: self.victim.as_ref().map_or(vec![], lru),
+            l2: self.l2.as_ref().map_or(vec![], lru),
+            seen: self.entries(records, seen.into_iter()),
+        }
+    }
+
+    /// Puts the shapes of `contents` in the levels they were in, and
+    /// returns how many have no TIB of the same shape in `records`.
+    fn preload(&mut self, contents: &ShapeCacheContents, records: &[TibRecord]) -> usize {
+        let tibs: HashMap<u64, &TibRecord> = records.iter().map(|r| (r.klass, r)).collect();
+        let tib_of = |entry: &ShapeEntry| {
+            tibs.get(&entry.klass)
+                .filter(|r| r.ttype == entry.ttype && r.blocks == entry.blocks)
+                .map(|r| r.tib as usize as *const O::Tib)
+        };
+        let mut missing = 0;
+        for entry in &contents.seen {
+            match tib_of(entry) {
+                Some(tib) => {
+                    self.tib_seen.insert(tib);
+                    *self.accesses.entry(tib).or_default() += entry.accesses;
+                }
+                None => missing += 1,
+            }
+        }
+        for entry in &contents.l2 {
+            if let (Some(tib), Some(l2)) = (tib_of(entry), &mut self.l2) {
+                l2.put(tib, ());
+            }
+        }
+        for entry in &contents.victim {
+            if let (Some(tib), Some(victim)) = (tib_of(entry), &mut self.victim) {
+                victim.put(tib, ());
+            }
+        }
+        for entry in &contents.sets {
+            if let Some(tib) = tib_of(entry) {
+                if let Some(shadow) = &mut self.shadow {
+                    shadow.put(tib, ());
+                }
+                let set = self.set_of(tib);
+                self.sets[set].put(tib, ());
+            }
+        }
+        missing
+    }
+
+    /// Adds a victim cache and a second level of the given numbers of
+    /// entries, where 0 leaves a level out.
+    pub(crate) fn with_levels(mut self, victim: usize, l2: usize) -> Self {
+        self.victim = NonZeroUsize::new(victim).map(LruCache::new);
+        self.l2 = NonZeroUsize::new(l2).map(LruCache::new);
+        self
+    }
+
+    /// Splits the cache into sets of `ways` entries, picked by `index`.
+    /// Leaves it fully associative if `ways` is 0.
+    pub(crate) fn with_associativity(mut self, ways: usize, index: ShapeCacheIndexChoice) -> Self {
+        if ways == 0 {
+            return self;
         }
+        let capacity = self.sets[0].cap().get();
+        assert!(
+            ways <= capacity && capacity.is_multiple_of(ways),
+            "The shape cache size ({}) must be a multiple of its ways ({})",
+            capacity,
+            ways
+        );
+        let ways = NonZeroUsize::new(ways).unwrap();
+        self.sets = (0..capacity / ways.get())
+            .map(|_| LruCache::new(ways))
+            .collect();
+        self.index = index;
+        self.shadow = Some(LruCache::new(NonZeroUsize::new(capacity).unwrap()));
+        self
+    }
+
+    fn set_of(&self, tib: *const O::Tib) -> usize {
+        // TIBs are at least word aligned
+        let word = tib as usize as u64 >> 3;
+        let index = match self.index {
+            ShapeCacheIndexChoice::LowBits => word,
+            // Fibonacci hashing, whose high bits depend on every bit of the
+            // pointer
+            ShapeCacheIndexChoice::Hashed => word.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32,
+        };
+        (index % self.sets.len() as u64) as usize
     }
 
     fn update(&mut self, tib: *const O::Tib) {
         let ttype: TibType = unsafe { &*tib as &O::Tib }.get_tib_type();
-        if matches!(ttype, TibType::InstanceMirror) {
-            *self
-                .stats
-                .entry(ShapeCacheResponse::CompulsoryMissInstanceMirror)
-                .or_default() += 1;
+        self.access(tib, matches!(ttype, TibType::InstanceMirror));
+    }
+
+    fn access(&mut self, tib: *const O::Tib, mirror: bool) {
+        let response = if mirror {
+            ShapeCacheResponse::CompulsoryMissInstanceMirror
         } else if self.tib_seen.contains(&tib) {
+            *self.accesses.entry(tib).or_default() += 1;
             // We have seen this type before
-            if self.cache.get(&tib).is_some() {
+            let set = self.set_of(tib);
+            let shadow_hit = self
+                .shadow
+                .as_mut()
+                .map(|shadow| shadow.get(&tib).is_some());
+            if self.sets[set].get(&tib).is_some() {
                 // And it's in the cache, so it's a hit
-                *self.stats.entry(ShapeCacheResponse::Hit).or_default() += 1;
+                ShapeCacheResponse::Hit
+            } else if self.victim.as_mut().is_some_and(|v| v.pop(&tib).is_some()) {
+                self.fill(tib);
+                ShapeCacheResponse::VictimHit
+            } else if self.l2.as_mut().is_some_and(|l2| l2.get(&tib).is_some()) {
+                self.fill(tib);
+                ShapeCacheResponse::L2Hit
+            } else if shadow_hit == Some(true) {
+                // A fully associative cache would still have it
+                self.fill(tib);
+                ShapeCacheResponse::ConflictMiss
             } else {
-                // Now it's not in the cache, so it's a capacity miss
-                *self
-                    .stats
-                    .entry(ShapeCacheResponse::CapacityMiss)
-                    .or_default() += 1;
-                self.cache.put(tib, ());
+                // Now it's in no level, so it's a capacity miss
+                self.fill(tib);
+                ShapeCacheResponse::CapacityMiss
             }
         } else {
             // This is the first time we see this type, resulting in a
             // compulsory miss
-            self.cache.put(tib, ());
-            *self
-                .stats
-                .entry(ShapeCacheResponse::CompulsoryMissInstance)
-                .or_default() += 1;
+            self.fill(tib);
             self.tib_seen.insert(tib);
+            *self.accesses.entry(tib).or_default() += 1;
+            ShapeCacheResponse::CompulsoryMissInstance
+        };
+        *self.stats.entry(response).or_default() += 1;
+    }
+
+    /// Brings `tib` into the first level, and the second if there is one,
+    /// moving what the first level evicts to the victim cache.
+    fn fill(&mut self, tib: *const O::Tib) {
+        if let Some(shadow) = &mut self.shadow {
+            shadow.put(tib, ());
+        }
+        let set = self.set_of(tib);
+        if let Some((evicted, _)) = self.sets[set].push(tib, ()) {
+            if let Some(victim) = self.victim.as_mut().filter(|_| evicted != tib) {
+                victim.put(evicted, ());
+            }
+        }
+        if let Some(l2) = &mut self.l2 {
+            l2.put(tib, ());
         }
     }
 
@@ -88,6 +335,12 @@ impl<O: ObjectModel> ShapeLruCache<O> {
         // This is the stats for one iteration
         let ret = ShapeCacheStats {
             hits: *self.stats.get(&ShapeCacheResponse::Hit).unwrap_or(&0),
+            victim_hits: *self.stats.get(&ShapeCacheResponse::VictimHit).unwrap_or(&0),
+            l2_hits: *self.stats.get(&ShapeCacheResponse::L2Hit).unwrap_or(&0),
+            conflict_misses: *self
+                .stats
+                .get(&ShapeCacheResponse::ConflictMiss)
+                .unwrap_or(&0),
             capacity_misses: *self
                 .stats
                 .get(&ShapeCacheResponse::CapacityMiss)
@@ -113,43 +366,46 @@ enum ShapeCacheResponse {
     CapacityMiss = 1,
     CompulsoryMissInstance = 2,
     CompulsoryMissInstanceMirror = 3,
+    VictimHit = 4,
+    L2Hit = 5,
+    ConflictMiss = 6,
 }
 
 pub(super) unsafe fn transitive_closure_shape_cache<O: ObjectModel>(
-    _args: TraceArgs,
+    _args: &TraceArgs,
     mark_sense: u8,
     object_model: &O,
     shape_cache: &mut ShapeLruCache<O>,
 ) -> TracingStats {
     // Edge-Slot enqueuing
-    let mut mark_queue: VecDeque<*mut u64> = VecDeque::new();
+    let mut mark_queue: VecDeque<Address> = VecDeque::new();
     let mut marked_objects: u64 = 0;
     // println!("{}", shape_cache.len());
     // shape_cache.clear();
     for root in object_model.roots() {
-        let o = *root;
-        if o != 0 && trace_object(o, mark_sense) {
+        let o = ObjectReference::from_raw(*root);
+        if let Some(o) = o.filter(|&o| trace_object(o, mark_sense)) {
             marked_objects += 1;
             if O::tib_lookup_required(o) {
                 shape_cache.update(O::get_tib(o));
             }
-            O::scan_object(o, |edge, repeat| {
+            klass_profile::scan_object::<O, _>(o, |edge, repeat| {
                 for i in 0..repeat {
-                    mark_queue.push_back(edge.wrapping_add(i as usize));
+                    mark_queue.push_back(edge.shift::<u64>(i as usize));
                 }
             })
         }
     }
     while let Some(e) = mark_queue.pop_front() {
-        let o = *e;
-        if o != 0 && trace_object(o, mark_sense) {
+        let o = e.load_object();
+        if let Some(o) = o.filter(|&o| trace_object(o, mark_sense)) {
             marked_objects += 1;
             if O::tib_lookup_required(o) {
                 shape_cache.update(O::get_tib(o));
             }
-            O::scan_object(o, |edge, repeat| {
+            klass_profile::scan_object::<O, _>(o, |edge, repeat| {
                 for i in 0..repeat {
-                    mark_queue.push_back(edge.wrapping_add(i as usize));
+                    mark_queue.push_back(edge.shift::<u64>(i as usize));
                 }
             })
         }
@@ -160,3 +416,136 @@ pub(super) unsafe fn transitive_closure_shape_cache<O: ObjectModel>(
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OpenJDKObjectModel;
+    use clap::Parser;
+
+    type Cache = ShapeLruCache<OpenJDKObjectModel<false>>;
+
+    /// A distinct TIB pointer for each shape, never dereferenced
+    fn tib(i: usize) -> *const <OpenJDKObjectModel<false> as ObjectModel>::Tib {
+        (i * 64) as *const _
+    }
+
+    fn responses(cache: &mut Cache, shapes: &[usize]) -> ShapeCacheStats {
+        for &i in shapes {
+            cache.access(tib(i), false);
+        }
+        cache.get_stats_and_clear()
+    }
+
+    #[test]
+    fn test_levels() {
+        // 1, 2 and 3 cycle through a single entry
+        let warm_up = [1, 2, 3];
+        let mut single = Cache::new(1);
+        responses(&mut single, &warm_up);
+        assert_eq!(responses(&mut single, &[1, 2, 3]).capacity_misses, 3);
+
+        // 2 and 3 were evicted to the victim cache when 3 and 1 came in
+        let mut victim = Cache::new(1).with_levels(2, 0);
+        responses(&mut victim, &warm_up);
+        let stats = responses(&mut victim, &[1, 2, 3]);
+        assert_eq!((stats.victim_hits, stats.capacity_misses), (3, 0));
+
+        // A second level of two holds 2 and 3 but not 1
+        let mut l2 = Cache::new(1).with_levels(0, 2);
+        responses(&mut l2, &warm_up);
+        let stats = responses(&mut l2, &[3, 2, 3, 1]);
+        assert_eq!(
+            (stats.hits, stats.l2_hits, stats.capacity_misses),
+            (1, 2, 1)
+        );
+    }
+
+    #[test]
+    fn test_conflict_misses() {
+        // Shapes 0 and 2 share a set of two sets of one way by their low
+        // bits, but not by their hash
+        let shapes = [0, 2, 0, 2];
+        let mut low_bits = Cache::new(2).with_associativity(1, ShapeCacheIndexChoice::LowBits);
+        assert_eq!(low_bits.set_of(tib(0)), low_bits.set_of(tib(2)));
+        responses(&mut low_bits, &shapes[..2]);
+        let stats = responses(&mut low_bits, &shapes[2..]);
+        assert_eq!((stats.conflict_misses, stats.capacity_misses), (2, 0));
+
+        let mut hashed = Cache::new(2).with_associativity(1, ShapeCacheIndexChoice::Hashed);
+        assert_ne!(hashed.set_of(tib(0)), hashed.set_of(tib(2)));
+        responses(&mut hashed, &shapes[..2]);
+        assert_eq!(responses(&mut hashed, &shapes[2..]).hits, 2);
+
+        // Shapes the fully associative shadow evicted too are capacity misses
+        let mut small = Cache::new(2).with_associativity(1, ShapeCacheIndexChoice::LowBits);
+        responses(&mut small, &[0, 1, 2]);
+        let stats = responses(&mut small, &[0]);
+        assert_eq!((stats.conflict_misses, stats.capacity_misses), (0, 1));
+
+        // Ways that do not divide the size are refused up front
+        let args = |ways: &str| {
+            TraceArgs::try_parse_from(["trace", "-t", "ShapeCache", "--shape-cache-ways", ways])
+                .unwrap()
+        };
+        assert!(check_args(&args("4")).is_ok());
+        assert!(check_args(&args("3")).is_err());
+    }
+
+    #[test]
+    fn test_export_import() {
+        let records: Vec<TibRecord> = (1..=3)
+            .map(|i| TibRecord {
+                klass: 0x7e57_0000 + i as u64,
+                tib: tib(i) as u64,
+                ttype: 0,
+                pattern: 0,
+                blocks: vec![(16, i as u64)],
+            })
+            .collect();
+        let by_tib: HashMap<u64, TibRecord> = records.iter().map(|r| (r.tib, r.clone())).collect();
+        // 1 is evicted to the victim cache by 3
+        let mut cache = Cache::new(2).with_levels(1, 0);
+        responses(&mut cache, &[1, 2, 3, 3]);
+        let contents = cache.contents(&by_tib);
+        let klasses = |entries: &[ShapeEntry]| {
+            entries
+                .iter()
+                .map(|e| e.klass - 0x7e57_0000)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(klasses(&contents.sets), [2, 3]);
+        assert_eq!(klasses(&contents.victim), [1]);
+        assert_eq!(klasses(&contents.seen), [1, 2, 3]);
+        assert_eq!(contents.seen[2].accesses, 2);
+        let json = serde_json::to_string(&contents).unwrap();
+        let contents: ShapeCacheContents = serde_json::from_str(&json).unwrap();
+
+        let mut warm = Cache::new(2).with_levels(1, 0);
+        assert_eq!(warm.preload(&contents, &records), 0);
+        let stats = responses(&mut warm, &[3, 2, 1]);
+        assert_eq!(
+            (
+                stats.hits,
+                stats.victim_hits,
+                stats.compulsory_misses_instance
+            ),
+            (2, 1, 0)
+        );
+
+        // A klass whose shape changed is left out
+        let mut changed = records.clone();
+        changed[1].blocks = vec![(24, 1)];
+        let mut warm = Cache::new(2).with_levels(1, 0);
+        assert_eq!(warm.preload(&contents, &changed), 1);
+        let stats = responses(&mut warm, &[3, 2, 1]);
+        assert_eq!(
+            (
+                stats.hits,
+                stats.victim_hits,
+                stats.compulsory_misses_instance
+            ),
+            (1, 1, 1)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Checks of the heap after the closures (`--verify`). `Fast` counts the
+//! objects a closure left unmarked in a parallel pass over the objects,
+//! and `Full` also compares the slots of every object against a snapshot
+//! taken before tracing, which catches tracers writing where they should
+//! only read, such as over the length of an object array.
+
+use crate::object_model::Header;
+use crate::*;
+use rayon::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+/// Unmarked or perturbed objects reported individually, the rest are only
+/// counted
+const MAX_REPORTED: usize = 10;
+
+/// Objects that are not marked with `mark_sense`.
+pub(super) fn count_unmarked<O: ObjectModel>(mark_sense: u8, object_model: &O) -> usize {
+    let objects: Vec<ObjectReference> = object_model.objects_iter().collect();
+    let unmarked: Vec<ObjectReference> = objects
+        .par_iter()
+        .copied()
+        .filter(|&o| Header::load(o).get_mark_byte() != mark_sense)
+        .collect();
+    for o in unmarked.iter().take(MAX_REPORTED) {
+        error!("{} not marked by transitive closure", o);
+    }
+    if unmarked.len() > MAX_REPORTED {
+        error!("... and {} more", unmarked.len() - MAX_REPORTED);
+    }
+    unmarked.len()
+}
+
+/// Marks what an EdgeSlot closure on the host reaches with `mark_sense`,
+/// the golden model of the other tracers, such as the simulated ones.
+pub(crate) fn host_closure<O: ObjectModel>(mark_sense: u8, object_model: &O) {
+    unsafe { super::edge_slot::transitive_closure_edge_slot(mark_sense, object_model, None) };
+}
+
+/// The slots of an object, as their number, which is the length of an
+/// object array, and a hash of their contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slots {
+    count: u64,
+    hash: u64,
+}
+
+impl Slots {
+    fn of<O: ObjectModel>(o: ObjectReference) -> Self {
+        let mut count = 0;
+        let mut hasher = DefaultHasher::new();
+        O::scan_object(o, |first, n| {
+            for i in 0..n as usize {
+                hasher.write_u64(unsafe { first.shift::<u64>(i).load::<u64>() });
+            }
+            count += n;
+        });
+        Slots {
+            count,
+            hash: hasher.finish(),
+        }
+    }
+}
+
+/// The slots of every object of the heap as restored, for `--verify Full`.
+pub(super) struct HeapSnapshot {
+    objects: Vec<(ObjectReference, Slots)>,
+}
+
+impl HeapSnapshot {
+    pub(super) fn take<O: ObjectModel>(object_model: &O) -> Self {
+        let objects: Vec<ObjectReference> = object_model.objects_iter().collect();
+        HeapSnapshot {
+            objects: objects
+                .par_iter()
+                .map(|&o| (o, Slots::of::<O>(o)))
+                .collect(),
+        }
+    }
+
+    /// Objects whose slots changed since the snapshot.
+    pub(super) fn count_perturbed<O: ObjectModel>(&self) -> usize {
+        let perturbed: Vec<(ObjectReference, Slots, Slots)> = self
+            .objects
+            .par_iter()
+            .filter_map(|&(o, before)| {
+                let after = Slots::of::<O>(o);
+                (after != before).then_some((o, before, after))
+            })
+            .collect();
+        for (o, before, after) in perturbed.iter().take(MAX_REPORTED) {
+            if before.count != after.count {
+                let kind = if unsafe { O::is_objarray(*o) } {
+                    "object array"
+                } else {
+                    "object"
+                };
+                error!(
+                    "{} {} has {} slots after tracing, {} before",
+                    kind, o, after.count, before.count
+                );
+            } else {
+                error!("{} has slots overwritten by tracing", o);
+            }
+        }
+        if perturbed.len() > MAX_REPORTED {
+            error!("... and {} more", perturbed.len() - MAX_REPORTED);
+        }
+        perturbed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::conformance::HEAP;
+
+    #[test]
+    fn test_verify() {
+        let _guard = HEAP.lock().unwrap_or_else(|e| e.into_inner());
+        let heapdump = HeapDump::from_path("[synthetic]objarray_64_false").unwrap();
+        heapdump.map_spaces().unwrap();
+        let mut object_model = OpenJDKObjectModel::<false>::new();
+        object_model.restore_tibs(&heapdump);
+        object_model.restore_objects(&heapdump);
+        // Restored objects have a zero mark byte
+        assert_eq!(count_unmarked(0, &object_model), 0);
+        assert_eq!(count_unmarked(1, &object_model), 65);
+        let snapshot = HeapSnapshot::take(&object_model);
+        assert_eq!(snapshot.count_perturbed::<OpenJDKObjectModel<false>>(), 0);
+        let array = ObjectReference::from_raw_unchecked(object_model.roots()[0]);
+        let mut slot = None;
+        OpenJDKObjectModel::<false>::scan_object(array, |first, _| slot = Some(first));
+        let slot = slot.unwrap();
+        unsafe {
+            let child = slot.load::<u64>();
+            slot.store(0u64);
+            assert_eq!(snapshot.count_perturbed::<OpenJDKObjectModel<false>>(), 1);
+            slot.store(child);
+        }
+        heapdump.unmap_spaces().unwrap();
+    }
+}
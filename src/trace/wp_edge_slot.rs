@@ -1,16 +1,20 @@
-use super::TracingStats;
+use super::{marker_map, TracingStats};
+use crate::simulate::tracing::TracingEvent;
+use crate::util::roots::RootRegistry;
+use crate::util::sampler::Sampling;
 use crate::util::tracer::Tracer;
 use crate::util::typed_obj::Slot;
 use crate::util::workers::WorkerGroup;
-use crate::util::wp::{Packet, WPWorker, GLOBAL};
+use crate::util::wp::{Packet, PacketKind, WPWorker, GLOBAL};
 use crate::{ObjectModel, TraceArgs};
+use anyhow::Result;
 use std::ops::Range;
 use std::{
     marker::PhantomData,
     sync::{atomic::Ordering, Arc},
 };
 
-static mut ROOTS: Option<*const [u64]> = None;
+static ROOTS: RootRegistry = RootRegistry::new();
 
 struct TracePacket<O: ObjectModel> {
     slots: Vec<Slot>,
@@ -36,16 +40,26 @@ impl<O: ObjectModel> TracePacket<O> {
 }
 
 impl<O: ObjectModel> Packet for TracePacket<O> {
+    fn kind(&self) -> PacketKind {
+        PacketKind::Trace
+    }
+
     fn run(&mut self) {
         let capacity = GLOBAL.cap();
         let local = WPWorker::current();
         let mark_state = local.global.mark_state();
-        for slot in std::mem::take(&mut self.slots) {
+        let mut slots = std::mem::take(&mut self.slots);
+        local.order_slots(&mut slots);
+        for slot in slots {
             if cfg!(feature = "detailed_stats") {
                 local.slots += 1;
             }
             if let Some(o) = slot.load() {
+                if local.filter_referent(o) {
+                    continue;
+                }
                 if o.mark(mark_state) {
+                    marker_map::record(o, local.id());
                     if cfg!(feature = "detailed_stats") {
                         local.objs += 1;
                     }
@@ -69,27 +83,32 @@ impl<O: ObjectModel> Packet for TracePacket<O> {
 
 struct ScanRoots<O: ObjectModel> {
     range: Range<usize>,
+    /// Epoch of the roots the range is of
+    epoch: u64,
     _p: PhantomData<O>,
 }
 
 impl<O: ObjectModel> ScanRoots<O> {
-    fn new(range: Range<usize>) -> Self {
+    fn new(range: Range<usize>, epoch: u64) -> Self {
         ScanRoots {
             range,
+            epoch,
             _p: PhantomData,
         }
     }
 }
 
 impl<O: ObjectModel> Packet for ScanRoots<O> {
+    fn kind(&self) -> PacketKind {
+        PacketKind::ScanRoots
+    }
+
     fn run(&mut self) {
         let capacity = GLOBAL.cap();
         let local = WPWorker::current();
         let mut buf = vec![];
-        let Some(roots) = (unsafe { ROOTS }) else {
-            unreachable!()
-        };
-        let roots = unsafe { &*roots };
+        let roots = ROOTS.current();
+        debug_assert_eq!(roots.epoch(), self.epoch);
         for root in &roots[self.range.clone()] {
             let slot = Slot::from_raw(root as *const u64 as *mut u64);
             if buf.is_empty() {
@@ -109,42 +128,56 @@ impl<O: ObjectModel> Packet for ScanRoots<O> {
 
 struct WPEdgeSlotTracer<O: ObjectModel> {
     group: Arc<WorkerGroup<WPWorker>>,
+    sampling: Option<Sampling>,
     _p: PhantomData<O>,
 }
 
 impl<O: ObjectModel> Tracer<O> for WPEdgeSlotTracer<O> {
-    fn startup(&self) {
+    fn startup(&self, _object_model: &O) -> Result<()> {
         info!("Use {} worker threads.", self.group.workers.len());
         self.group.spawn();
+        Ok(())
     }
 
     fn trace(&self, mark_sense: u8, object_model: &O) -> TracingStats {
         GLOBAL.reset();
         GLOBAL.mark_state.store(mark_sense, Ordering::SeqCst);
         // Create initial root scanning packets
-        let roots = object_model.roots();
-        let roots_len = roots.len();
-        unsafe { ROOTS = Some(roots) };
+        let epoch = ROOTS.publish(object_model.shared_roots());
+        let roots_len = object_model.roots().len();
         let num_workers = self.group.workers.len();
         for id in 0..num_workers {
             let range = (roots_len * id) / num_workers..(roots_len * (id + 1)) / num_workers;
-            let packet = ScanRoots::<O>::new(range);
-            GLOBAL.queue.push(Box::new(packet));
+            let packet = ScanRoots::<O>::new(range, epoch);
+            GLOBAL.push(packet);
         }
         // Wake up workers
-        self.group.run_epoch();
+        match &self.sampling {
+            Some(sampling) => sampling.run(&self.group, || self.group.run_epoch()),
+            None => self.group.run_epoch(),
+        }
+        // The epoch has run to completion, so no root slot is loaded after this
+        ROOTS.retire();
         GLOBAL.get_stats()
     }
 
     fn teardown(&self) {
         self.group.finish();
     }
+
+    fn take_events(&self) -> Vec<TracingEvent> {
+        self.sampling
+            .as_ref()
+            .map(|s| s.take_events())
+            .unwrap_or_default()
+    }
 }
 
 impl<O: ObjectModel> WPEdgeSlotTracer<O> {
-    pub fn new(num_workers: usize) -> Self {
+    pub fn new(num_workers: usize, sampling: Option<Sampling>) -> Self {
         Self {
             group: WorkerGroup::new(num_workers),
+            sampling,
             _p: PhantomData,
         }
     }
@@ -152,5 +185,14 @@ impl<O: ObjectModel> WPEdgeSlotTracer<O> {
 
 pub fn create_tracer<O: ObjectModel>(args: &TraceArgs) -> Box<dyn Tracer<O>> {
     GLOBAL.set_cap(args.wp_capacity);
-    Box::new(WPEdgeSlotTracer::<O>::new(args.threads))
+    GLOBAL.set_time_packets(args.packet_latency);
+    GLOBAL.set_queue_discipline(args.queue_discipline, args.local_queue_limit);
+    GLOBAL.set_packet_budget(args.packet_budget);
+    GLOBAL.set_slot_order(args.slot_order);
+    GLOBAL.set_referent_filter(args.referent_filter);
+    GLOBAL.set_packet_aging(args.packet_aging);
+    Box::new(WPEdgeSlotTracer::<O>::new(
+        args.threads,
+        Sampling::from_args(args),
+    ))
 }
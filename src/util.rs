@@ -1,3 +1,6 @@
+pub mod bump;
+pub mod roots;
+pub mod sampler;
 pub mod tracer;
 pub mod typed_obj;
 pub mod workers;
@@ -39,3 +42,61 @@ pub fn dzmmap_noreplace(start: u64, size: usize) -> Result<()> {
 pub fn ticks_to_us(ticks: u64, frequency_ghz: f64) -> f64 {
     (ticks as f64) / (frequency_ghz * 1000.0)
 }
+
+/// An address in hex with a `0x` prefix, or in decimal.
+pub(crate) fn parse_address(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|e| anyhow::anyhow!("Invalid address {:?}: {}", s, e))
+}
+
+/// A number of bytes that is a power of two, such as a granule of memory.
+pub(crate) fn parse_power_of_two(s: &str) -> anyhow::Result<u64> {
+    let bytes: u64 = s.trim().parse()?;
+    if !bytes.is_power_of_two() {
+        anyhow::bail!("{} bytes is not a power of two", bytes);
+    }
+    Ok(bytes)
+}
+
+/// A hash of `block` seeded with `seed`, XOR-folded into `bits` bits. XORed
+/// into an owner taken from the address bits below `block`, it permutes the
+/// owners within the block, so each owner keeps its share of the block but
+/// which part it owns no longer follows the address order.
+pub(crate) fn owner_hash(block: u64, seed: u64, bits: u32) -> u64 {
+    if bits == 0 {
+        return 0;
+    }
+    let mut x = (block ^ seed).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let mut folded = 0;
+    while x != 0 {
+        folded ^= x & ((1 << bits) - 1);
+        x >>= bits;
+    }
+    folded
+}
+
+/// The largest of `counts` over their mean, 1 when they are even and the
+/// number of counts when one has them all.
+pub(crate) fn imbalance(counts: &[u64]) -> f64 {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let largest = counts.iter().copied().max().unwrap_or(0);
+    largest as f64 * counts.len() as f64 / total as f64
+}
+
+/// 64-bit FNV-1a, which unlike the hasher of the standard library gives the
+/// same result in every build.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    hash
+}
@@ -0,0 +1,128 @@
+//! Bump-pointer allocation within a region of address space, for laying
+//! out objects and metadata: the TIBs of the object models, the objects of a
+//! space when compacting, the spaces packed by `rebase32` and the frames of
+//! synthesized root stacks.
+
+use std::ops::AddAssign;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationStats {
+    pub allocations: u64,
+    pub bytes_allocated: u64,
+    /// Bytes left unused to align allocations
+    pub padding_bytes: u64,
+    /// Bytes left unused by moving the cursor past them, for example to an
+    /// object that stays in place
+    pub skipped_bytes: u64,
+}
+
+impl AddAssign for AllocationStats {
+    fn add_assign(&mut self, other: Self) {
+        self.allocations += other.allocations;
+        self.bytes_allocated += other.bytes_allocated;
+        self.padding_bytes += other.padding_bytes;
+        self.skipped_bytes += other.skipped_bytes;
+    }
+}
+
+/// Allocates upwards from the start of `[start, limit)`, never freeing.
+#[derive(Debug, Clone)]
+pub struct BumpAllocator {
+    cursor: u64,
+    limit: u64,
+    pub stats: AllocationStats,
+}
+
+impl BumpAllocator {
+    pub fn new(start: u64, limit: u64) -> Self {
+        assert!(start <= limit, "The region ends before it starts");
+        BumpAllocator {
+            cursor: start,
+            limit,
+            stats: AllocationStats::default(),
+        }
+    }
+
+    /// Where the next allocation goes, before alignment.
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// The first address at or after the cursor that is `offset` bytes past
+    /// a multiple of `align`, a power of two.
+    fn aligned(&self, align: u64, offset: u64) -> u64 {
+        debug_assert!(align.is_power_of_two() && offset < align);
+        match self.cursor.wrapping_sub(offset) & (align - 1) {
+            0 => self.cursor,
+            misalignment => self.cursor + (align - misalignment),
+        }
+    }
+
+    /// Allocates `size` bytes at an address `offset` bytes past a multiple
+    /// of `align`, or returns `None` if they do not fit below the limit.
+    pub fn alloc_aligned(&mut self, size: u64, align: u64, offset: u64) -> Option<u64> {
+        let start = self.aligned(align, offset);
+        if start.checked_add(size)? > self.limit {
+            return None;
+        }
+        self.stats.allocations += 1;
+        self.stats.bytes_allocated += size;
+        self.stats.padding_bytes += start - self.cursor;
+        self.cursor = start + size;
+        Some(start)
+    }
+
+    /// Allocates `size` bytes aligned to `align`, see
+    /// [`alloc_aligned`](Self::alloc_aligned).
+    pub fn alloc(&mut self, size: u64, align: u64) -> Option<u64> {
+        self.alloc_aligned(size, align, 0)
+    }
+
+    /// Pads the cursor up to a multiple of `align`.
+    pub fn align_to(&mut self, align: u64) {
+        let aligned = self.aligned(align, 0).min(self.limit);
+        self.stats.padding_bytes += aligned - self.cursor;
+        self.cursor = aligned;
+    }
+
+    /// Moves the cursor up to `addr`, which must not be behind it.
+    pub fn skip_to(&mut self, addr: u64) {
+        assert!(
+            (self.cursor..=self.limit).contains(&addr),
+            "Cannot skip from 0x{:x} to 0x{:x}",
+            self.cursor,
+            addr
+        );
+        self.stats.skipped_bytes += addr - self.cursor;
+        self.cursor = addr;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump_allocator() {
+        let mut bump = BumpAllocator::new(0x1000, 0x1100);
+        assert_eq!(bump.alloc(24, 8), Some(0x1000));
+        // The first address at 16 past a multiple of 64 after 0x1018
+        assert_eq!(bump.alloc_aligned(8, 64, 16), Some(0x1050));
+        assert_eq!(bump.stats.padding_bytes, 0x38);
+        bump.skip_to(0x1080);
+        bump.align_to(0x40);
+        assert_eq!(bump.cursor(), 0x1080);
+        assert_eq!(bump.alloc(0x81, 8), None);
+        assert_eq!(bump.alloc(0x80, 8), Some(0x1080));
+        assert_eq!(bump.cursor(), 0x1100);
+        assert_eq!(
+            bump.stats,
+            AllocationStats {
+                allocations: 3,
+                bytes_allocated: 0xa0,
+                padding_bytes: 0x38,
+                skipped_bytes: 0x28,
+            }
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! The roots of the current tracing epoch, shared with the worker threads of
+//! a parallel tracer. The tracer publishes the roots of the object model at
+//! the start of each `trace`, without copying them, and each worker takes a
+//! [`Roots`] guard of them once per root scanning task, so the per-slot
+//! loops only ever index a plain slice.
+//!
+//! Root slots handed out from a guard may be queued and loaded after the
+//! guard is dropped. They stay valid because the registry holds on to the
+//! roots until [`RootRegistry::retire`], so a tracer must only retire once
+//! its epoch has run to completion and no task can still load a root slot.
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub struct RootRegistry {
+    roots: RwLock<Option<Arc<[u64]>>>,
+    epoch: AtomicU64,
+}
+
+/// The roots of one epoch, kept alive while the guard is, even if the next
+/// epoch has been published or the current one retired since.
+pub struct Roots {
+    roots: Arc<[u64]>,
+    epoch: u64,
+}
+
+impl Deref for Roots {
+    type Target = [u64];
+
+    fn deref(&self) -> &[u64] {
+        &self.roots
+    }
+}
+
+impl Roots {
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+}
+
+impl RootRegistry {
+    pub const fn new() -> Self {
+        RootRegistry {
+            roots: RwLock::new(None),
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts a new epoch with `roots`, which root slots then point into.
+    /// The object model replaces its roots rather than writing to them, so
+    /// they stay valid however the model changes. Returns the new epoch.
+    pub fn publish(&self, roots: Arc<[u64]>) -> u64 {
+        let mut current = self.roots.write().unwrap();
+        *current = Some(roots);
+        self.epoch.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// The roots of the current epoch.
+    ///
+    /// Panics if none were published, or if they were retired.
+    pub fn current(&self) -> Roots {
+        let current = self.roots.read().unwrap();
+        Roots {
+            roots: current.clone().expect("No roots published"),
+            epoch: self.epoch.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Ends the current epoch, freeing its roots once no guard holds them.
+    /// Root slots of the epoch that outlived their guard must not be loaded
+    /// after this, so callers retire only once every task of the epoch has
+    /// finished, e.g. after `run_epoch` returns.
+    pub fn retire(&self) {
+        *self.roots.write().unwrap() = None;
+    }
+}
+
+impl Default for RootRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_registry() {
+        let registry = RootRegistry::new();
+        let epoch = registry.publish(vec![1, 2, 3].into());
+        let roots = registry.current();
+        assert_eq!((&*roots, roots.epoch()), (&[1, 2, 3][..], epoch));
+        // A guard outlives the publishing of the next epoch and retiring
+        assert_eq!(registry.publish(vec![4].into()), epoch + 1);
+        registry.retire();
+        assert_eq!(&*roots, &[1, 2, 3]);
+        assert!(std::panic::catch_unwind(|| registry.current()).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::simulate::tracing::TracingEvent;
+use crate::util::workers::{Worker, WorkerGroup};
+use crate::TraceArgs;
+use crossbeam::deque::Stealer;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shared worker handles whose backing deque length can be observed from
+/// another thread.
+pub trait QueueLen {
+    fn queue_len(&self) -> usize;
+}
+
+impl<T> QueueLen for Stealer<T> {
+    fn queue_len(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Periodic sampling of the workers of a tracer, exported as Perfetto
+/// counter tracks with `--trace-events`.
+///
+/// A sampler thread runs alongside each traced epoch and records every
+/// worker's deque length, along with the steals, failed steals and packets
+/// since the previous sample. Timestamps are relative to the creation of the
+/// tracer, so consecutive iterations line up one after the other.
+pub struct Sampling {
+    interval: Duration,
+    origin: Instant,
+    events: Mutex<Vec<TracingEvent>>,
+}
+
+impl Sampling {
+    pub fn new(interval: Duration) -> Self {
+        Sampling {
+            interval,
+            origin: Instant::now(),
+            events: Mutex::new(vec![]),
+        }
+    }
+
+    /// Sampling as requested by `--trace-events`, if at all.
+    pub fn from_args(args: &TraceArgs) -> Option<Self> {
+        args.trace_events
+            .as_ref()
+            .map(|_| Sampling::new(Duration::from_micros(args.sample_interval_us)))
+    }
+
+    fn now_us(&self) -> f64 {
+        self.origin.elapsed().as_nanos() as f64 / 1000.0
+    }
+
+    /// Runs `epoch` on the calling thread while sampling `group`.
+    pub fn run<W: Worker, R>(&self, group: &Arc<WorkerGroup<W>>, epoch: impl FnOnce() -> R) -> R
+    where
+        W::SharedWorker: QueueLen,
+    {
+        // Dropping the sender wakes the sampler as soon as the epoch ends,
+        // rather than after the rest of its interval.
+        let (stop, stopped) = mpsc::channel::<()>();
+        let counters = |id: usize| {
+            let activity = &group.activity[id];
+            [
+                activity.steals.load(Ordering::Relaxed),
+                activity.failed_steals.load(Ordering::Relaxed),
+                activity.packets.load(Ordering::Relaxed),
+            ]
+        };
+        // The counters are cumulative over the tracer's lifetime, so take a
+        // baseline before the epoch starts, not when the sampler gets to it.
+        let mut last: Vec<[u64; 3]> = (0..group.workers.len()).map(counters).collect();
+        let begin = self.now_us();
+        let (result, mut events) = std::thread::scope(|s| {
+            let sampler = s.spawn(move || {
+                let mut events = vec![];
+                let sample = |last: &mut Vec<[u64; 3]>, events: &mut Vec<TracingEvent>| {
+                    let ts = self.now_us();
+                    for (id, shared) in group.workers.iter().enumerate() {
+                        let now = counters(id);
+                        let mut values: HashMap<String, Value> = HashMap::new();
+                        values.insert("deque".into(), shared.queue_len().into());
+                        values.insert("steals".into(), (now[0] - last[id][0]).into());
+                        values.insert("failed_steals".into(), (now[1] - last[id][1]).into());
+                        values.insert("packets".into(), (now[2] - last[id][2]).into());
+                        last[id] = now;
+                        events.push(TracingEvent::new_counter_event(
+                            0,
+                            format!("worker {}", id),
+                            ts,
+                            values,
+                        ));
+                    }
+                };
+                loop {
+                    // Sample once more after the epoch ends, so that the
+                    // tail of the trace is not lost.
+                    let done = !matches!(
+                        stopped.recv_timeout(self.interval),
+                        Err(RecvTimeoutError::Timeout)
+                    );
+                    sample(&mut last, &mut events);
+                    if done {
+                        break;
+                    }
+                }
+                events
+            });
+            let result = epoch();
+            drop(stop);
+            (result, sampler.join().unwrap())
+        });
+        let end = self.now_us();
+        events.push(TracingEvent::new_duration_event(
+            0,
+            0,
+            "trace".to_string(),
+            begin,
+            HashMap::default(),
+            true,
+            Some(end - begin),
+        ));
+        self.events.lock().unwrap().append(&mut events);
+        result
+    }
+
+    pub fn take_events(&self) -> Vec<TracingEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
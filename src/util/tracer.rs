@@ -1,7 +1,15 @@
+use crate::simulate::tracing::TracingEvent;
 use crate::{trace::TracingStats, ObjectModel};
+use anyhow::Result;
 
 pub trait Tracer<O: ObjectModel> {
-    fn startup(&self);
+    /// Starts the workers, before the first closure of the heap restored
+    /// in `object_model`.
+    fn startup(&self, object_model: &O) -> Result<()>;
     fn trace(&self, mark_sense: u8, object_model: &O) -> TracingStats;
     fn teardown(&self);
+    /// Counter samples collected since the last call, see `--trace-events`.
+    fn take_events(&self) -> Vec<TracingEvent> {
+        vec![]
+    }
 }
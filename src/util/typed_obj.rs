@@ -1,44 +1,232 @@
+//! Typed addresses. An [`ObjectReference`] is the start of an object and
+//! never null, an [`Address`] is any other address, such as that of a slot,
+//! so that an object cannot be passed where a slot is expected or the other
+//! way around. Both are a bare `u64` in memory, and
+//! `Option<ObjectReference>` is too, with null as `None`. Both serialize
+//! as the bare address, as the checkpoints of the simulator store them.
+
+use crate::trace::klass_profile;
 use crate::{object_model::Header, ObjectModel};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::num::NonZeroU64;
+use std::ops::{Add, Sub};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct Slot(*mut u64);
+#[repr(transparent)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Address(u64);
 
-unsafe impl Send for Slot {}
-unsafe impl Sync for Slot {}
+impl Address {
+    pub const ZERO: Address = Address(0);
 
-impl Slot {
-    pub fn from_raw(ptr: *mut u64) -> Self {
-        Slot(ptr)
+    pub const fn from_raw(addr: u64) -> Self {
+        Address(addr)
     }
 
-    pub fn load(&self) -> Option<Object> {
-        let v = unsafe { *self.0 };
-        if v == 0 {
-            None
-        } else {
-            Some(Object(v))
-        }
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Address(ptr as u64)
     }
-}
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct Object(u64);
+    pub fn from_mut_ptr<T>(ptr: *mut T) -> Self {
+        Address(ptr as u64)
+    }
 
-impl Object {
-    fn raw(&self) -> u64 {
+    pub const fn as_u64(self) -> u64 {
         self.0
     }
 
+    pub fn to_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub fn to_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The address `n` values of `T` after this one, like `wrapping_add` on
+    /// a pointer to `T`.
+    pub const fn shift<T>(self, n: usize) -> Self {
+        Address(
+            self.0
+                .wrapping_add((n as u64).wrapping_mul(std::mem::size_of::<T>() as u64)),
+        )
+    }
+
+    /// # Safety
+    /// The address must be mapped and aligned for `T`.
+    pub unsafe fn load<T: Copy>(self) -> T {
+        *self.to_ptr::<T>()
+    }
+
+    /// # Safety
+    /// The address must be mapped, writable and aligned for `T`.
+    pub unsafe fn store<T>(self, value: T) {
+        *self.to_mut_ptr::<T>() = value
+    }
+
+    /// The object a slot at this address refers to, if any.
+    ///
+    /// # Safety
+    /// The address must be that of a mapped slot.
+    pub unsafe fn load_object(self) -> Option<ObjectReference> {
+        ObjectReference::from_raw(self.load::<u64>())
+    }
+}
+
+impl Add<u64> for Address {
+    type Output = Address;
+
+    fn add(self, bytes: u64) -> Address {
+        Address(self.0 + bytes)
+    }
+}
+
+impl Sub<u64> for Address {
+    type Output = Address;
+
+    fn sub(self, bytes: u64) -> Address {
+        Address(self.0 - bytes)
+    }
+}
+
+/// Bytes from `other` to `self`.
+impl Sub<Address> for Address {
+    type Output = u64;
+
+    fn sub(self, other: Address) -> u64 {
+        self.0 - other.0
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Address(0x{:x})", self.0)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+
+impl fmt::LowerHex for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+#[repr(transparent)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ObjectReference(NonZeroU64);
+
+impl ObjectReference {
+    /// `None` for null.
+    pub const fn from_raw(addr: u64) -> Option<Self> {
+        match NonZeroU64::new(addr) {
+            Some(addr) => Some(ObjectReference(addr)),
+            None => None,
+        }
+    }
+
+    /// For addresses known to be the start of an object, such as those of
+    /// a heapdump or of an object model.
+    ///
+    /// Panics on null.
+    pub fn from_raw_unchecked(addr: u64) -> Self {
+        Self::from_raw(addr).expect("Null object reference")
+    }
+
+    pub const fn to_raw(self) -> u64 {
+        self.0.get()
+    }
+
+    pub const fn to_address(self) -> Address {
+        Address(self.0.get())
+    }
+
     pub fn scan<O: ObjectModel, F: FnMut(Slot)>(&self, mut f: F) {
-        O::scan_object(self.raw(), |edge, repeat| {
+        klass_profile::scan_object::<O, _>(*self, |edge, repeat| {
             for i in 0..repeat {
-                let ptr = edge.wrapping_add(i as usize);
-                f(Slot(ptr));
+                f(Slot::from_address(edge.shift::<u64>(i as usize)));
             }
         })
     }
 
     pub fn mark(&self, mark_state: u8) -> bool {
-        Header::attempt_mark_byte(self.raw(), mark_state)
+        Header::attempt_mark_byte(*self, mark_state)
+    }
+}
+
+impl fmt::Debug for ObjectReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ObjectReference(0x{:x})", self.0)
+    }
+}
+
+impl fmt::Display for ObjectReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+
+impl fmt::LowerHex for ObjectReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Slot(*mut u64);
+
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    pub fn from_raw(ptr: *mut u64) -> Self {
+        Slot(ptr)
+    }
+
+    pub fn from_address(addr: Address) -> Self {
+        Slot(addr.to_mut_ptr())
+    }
+
+    pub fn addr(&self) -> Address {
+        Address::from_mut_ptr(self.0)
+    }
+
+    pub fn load(&self) -> Option<ObjectReference> {
+        ObjectReference::from_raw(unsafe { *self.0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_addresses() {
+        assert_eq!(
+            std::mem::size_of::<Option<ObjectReference>>(),
+            std::mem::size_of::<u64>()
+        );
+        assert_eq!(ObjectReference::from_raw(0), None);
+        let o = ObjectReference::from_raw_unchecked(0x1000);
+        assert_eq!(format!("{:?} {}", o, o), "ObjectReference(0x1000) 0x1000");
+        let slot = o.to_address().shift::<u64>(3);
+        assert_eq!(slot, Address::from_raw(0x1018));
+        assert_eq!(slot - o.to_address(), 24);
+        let cell = [0x2000u64, 0];
+        let first = Address::from_ptr(cell.as_ptr());
+        unsafe {
+            assert_eq!(first.load_object().map(|o| o.to_raw()), Some(0x2000));
+            assert_eq!(first.shift::<u64>(1).load_object(), None);
+        }
     }
 }
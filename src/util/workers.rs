@@ -1,5 +1,6 @@
+use crossbeam::utils::CachePadded;
 use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering},
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     Arc, Barrier, BarrierWaitResult, Condvar, Mutex, Weak,
 };
 
@@ -23,10 +24,28 @@ impl Monitor {
     }
 }
 
+/// Running totals of a worker's load-balancing activity, read by the
+/// sampler thread behind `--trace-events`. Updated with relaxed atomics, as
+/// only the owning worker writes them.
+#[derive(Default)]
+pub struct WorkerActivity {
+    pub steals: AtomicU64,
+    pub failed_steals: AtomicU64,
+    pub packets: AtomicU64,
+}
+
+impl WorkerActivity {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 pub struct WorkerGroup<W: Worker> {
     monitor: Arc<Monitor>,
     handles: Mutex<Vec<std::thread::JoinHandle<()>>>,
     pub workers: Vec<W::SharedWorker>,
+    /// Indexed by worker id, padded so that workers do not share cache lines
+    pub activity: Vec<CachePadded<WorkerActivity>>,
     local_workers: Mutex<Option<Vec<W>>>,
 }
 
@@ -44,6 +63,7 @@ impl<W: Worker> WorkerGroup<W> {
                 monitor: Arc::new(Monitor::new(num_workers)),
                 handles: Mutex::new(Vec::new()),
                 workers: shared,
+                activity: (0..num_workers).map(|_| Default::default()).collect(),
                 local_workers: Mutex::new(Some(workers)),
             }
         })
@@ -1,26 +1,209 @@
+use crate::heap_stats::Histogram;
+use crate::trace::referent_filter::ReferentFilter;
 use crate::trace::TracingStats;
-use crate::util::workers::WorkerGroup;
+use crate::util::typed_obj::{ObjectReference, Slot};
+use crate::util::workers::{WorkerActivity, WorkerGroup};
+use crate::{QueueDisciplineChoice, SlotOrderChoice};
 use crossbeam::deque::{Injector, Steal, Stealer, Worker};
 use once_cell::sync::Lazy;
 use std::cell::Cell;
-use std::sync::atomic::{AtomicU8, AtomicUsize};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize};
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
 };
 use std::sync::{Condvar, Mutex, Weak};
 
+/// What a packet does, to tell the latencies of packets apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    ScanRoots,
+    /// Loads slots and marks their objects
+    Trace,
+    /// Scans marked objects for their slots
+    Scan,
+}
+
+impl PacketKind {
+    pub const ALL: [PacketKind; 3] = [PacketKind::ScanRoots, PacketKind::Trace, PacketKind::Scan];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketKind::ScanRoots => "scan_roots",
+            PacketKind::Trace => "trace",
+            PacketKind::Scan => "scan",
+        }
+    }
+}
+
 pub trait Packet: Send {
+    fn kind(&self) -> PacketKind;
     fn run(&mut self);
 }
 
+/// A packet in a queue, with the [`timestamp`] of when it was queued if
+/// anything needs it, and 0 otherwise.
+pub struct QueuedPacket {
+    packet: Box<dyn Packet>,
+    queued: u64,
+}
+
+/// Unit of [`timestamp`]
+pub const TIMESTAMP_UNIT: &str = if cfg!(target_arch = "x86_64") {
+    "cycles"
+} else {
+    "ns"
+};
+
+/// A timestamp cheap enough to take around every packet: the TSC on x86-64,
+/// which costs tens of cycles to read and needs no system call, and
+/// nanoseconds elsewhere.
+#[inline(always)]
+pub(crate) fn timestamp() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        static START: Lazy<std::time::Instant> = Lazy::new(std::time::Instant::now);
+        START.elapsed().as_nanos() as u64
+    }
+}
+
+/// [`timestamp`] units per microsecond. The TSC runs at a fixed rate that
+/// is only known by timing it against the clock.
+static TIMESTAMPS_PER_US: Lazy<f64> = Lazy::new(|| {
+    if cfg!(target_arch = "x86_64") {
+        let start = (std::time::Instant::now(), timestamp());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        (timestamp() - start.1) as f64 / start.0.elapsed().as_micros() as f64
+    } else {
+        1000.0
+    }
+});
+
+/// Log-scaled histograms of how long each kind of packet runs, in
+/// [`TIMESTAMP_UNIT`]. A packet that runs another inline, as the trace
+/// packets of WPEdgeSlotDual run their scan packets, includes its time.
+#[derive(Debug, Default, Clone)]
+pub struct PacketLatency {
+    histograms: [Histogram; PacketKind::ALL.len()],
+    max: [u64; PacketKind::ALL.len()],
+    /// How long packets of any kind wait in the queues before they run
+    wait: Histogram,
+    max_wait: u64,
+}
+
+impl PacketLatency {
+    fn record(&mut self, kind: PacketKind, ticks: u64) {
+        self.histograms[kind as usize].record(ticks);
+        self.max[kind as usize] = self.max[kind as usize].max(ticks);
+    }
+
+    fn record_wait(&mut self, ticks: u64) {
+        self.wait.record(ticks);
+        self.max_wait = self.max_wait.max(ticks);
+    }
+
+    pub(crate) fn add(&mut self, other: &PacketLatency) {
+        for kind in PacketKind::ALL {
+            let i = kind as usize;
+            self.histograms[i].add(&other.histograms[i]);
+            self.max[i] = self.max[i].max(other.max[i]);
+        }
+        self.wait.add(&other.wait);
+        self.max_wait = self.max_wait.max(other.max_wait);
+    }
+
+    pub(crate) fn wait(&self) -> &Histogram {
+        &self.wait
+    }
+
+    pub(crate) fn max_wait(&self) -> u64 {
+        self.max_wait
+    }
+
+    pub(crate) fn histogram(&self, kind: PacketKind) -> &Histogram {
+        &self.histograms[kind as usize]
+    }
+
+    pub(crate) fn max(&self, kind: PacketKind) -> u64 {
+        self.max[kind as usize]
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.histograms.iter().all(|h| h.count() == 0)
+    }
+}
+
+/// Bytes of the pages `SlotOrderChoice::Page` buckets slots by
+const LOG_SLOT_PAGE: u32 = 12;
+
+/// Consecutive slots on different pages, how often loading them leaves the
+/// page of the previous one.
+fn page_switches(slots: &[Slot]) -> u64 {
+    slots
+        .windows(2)
+        .filter(|w| w[0].addr().as_u64() >> LOG_SLOT_PAGE != w[1].addr().as_u64() >> LOG_SLOT_PAGE)
+        .count() as u64
+}
+
+/// Puts the slots of a packet in the order `order` loads them in.
+fn order_slots(order: SlotOrderChoice, slots: &mut [Slot]) {
+    match order {
+        SlotOrderChoice::Unsorted => {}
+        SlotOrderChoice::Sorted => slots.sort_unstable_by_key(Slot::addr),
+        // Stable, so slots of an object stay together
+        SlotOrderChoice::Page => slots.sort_by_key(|s| s.addr().as_u64() >> LOG_SLOT_PAGE),
+    }
+}
+
+/// How many packets a worker runs inline, one inside the other, rather than
+/// queue over the budget. Deeper spills queue anyway, so that a long chain of
+/// packets cannot overflow the stack.
+const MAX_SPILL_DEPTH: usize = 64;
+
 pub struct GlobalContext {
-    pub queue: Injector<Box<dyn Packet>>,
+    pub queue: Injector<QueuedPacket>,
     pub mark_state: AtomicU8,
     pub objs: AtomicU64,
     pub edges: AtomicU64,
     pub ne_edges: AtomicU64,
+    pub packets: AtomicU64,
+    pub steals: AtomicU64,
+    /// Packets pushed to the global queue because a local queue was full
+    pub overflows: AtomicU64,
+    /// Packets run inline by the worker that made them, because the queues
+    /// held the budget
+    pub spilled: AtomicU64,
+    /// Packets promoted to the global queue for waiting longer than the
+    /// aging threshold at the bottom of a local queue
+    aged: AtomicU64,
+    /// See `--packet-aging`, in microseconds, 0 for no aging
+    packet_aging: AtomicU64,
+    /// Slots of the packets put in order with `--slot-order`, and the page
+    /// switches between consecutive slots before and after
+    sorted_slots: AtomicU64,
+    unsorted_page_switches: AtomicU64,
+    sorted_page_switches: AtomicU64,
+    slot_order: Mutex<SlotOrderChoice>,
+    /// Entries of the filter of each worker, see `--referent-filter`, 0 for
+    /// no filter, and the mark attempts the filters skipped
+    referent_filter: AtomicUsize,
+    filtered_marks: AtomicU64,
+    /// Packets in any queue
+    queued: AtomicUsize,
+    /// The most packets the queues held at once
+    peak_queued: AtomicUsize,
+    /// Packets the queues may hold before workers spill, 0 for no limit
+    packet_budget: AtomicUsize,
     pub cap: AtomicUsize,
+    /// Read by workers when they are created
+    queue_discipline: Mutex<(QueueDisciplineChoice, usize)>,
+    /// Whether workers time every packet, see `--packet-latency`
+    time_packets: AtomicBool,
+    packet_latency: Mutex<PacketLatency>,
     epoch_monitor: (Mutex<bool>, Condvar),
     yield_monitor: (Mutex<usize>, Condvar, AtomicUsize),
 }
@@ -33,7 +216,25 @@ impl GlobalContext {
             objs: AtomicU64::new(0),
             edges: AtomicU64::new(0),
             ne_edges: AtomicU64::new(0),
+            packets: AtomicU64::new(0),
+            steals: AtomicU64::new(0),
+            overflows: AtomicU64::new(0),
+            spilled: AtomicU64::new(0),
+            aged: AtomicU64::new(0),
+            packet_aging: AtomicU64::new(0),
+            sorted_slots: AtomicU64::new(0),
+            unsorted_page_switches: AtomicU64::new(0),
+            sorted_page_switches: AtomicU64::new(0),
+            slot_order: Mutex::new(SlotOrderChoice::Unsorted),
+            referent_filter: AtomicUsize::new(0),
+            filtered_marks: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            peak_queued: AtomicUsize::new(0),
+            packet_budget: AtomicUsize::new(0),
             cap: AtomicUsize::new(4096),
+            queue_discipline: Mutex::new((QueueDisciplineChoice::Lifo, 0)),
+            time_packets: AtomicBool::new(false),
+            packet_latency: Mutex::new(PacketLatency::default()),
             epoch_monitor: (Mutex::new(false), Condvar::new()),
             yield_monitor: (Mutex::new(0), Condvar::new(), AtomicUsize::new(0)),
         }
@@ -47,6 +248,51 @@ impl GlobalContext {
         self.cap.load(Ordering::Relaxed)
     }
 
+    /// Makes the workers created from now on use `discipline`, keeping up
+    /// to `local_limit` packets locally if it is hierarchical.
+    pub fn set_queue_discipline(&self, discipline: QueueDisciplineChoice, local_limit: usize) {
+        *self.queue_discipline.lock().unwrap() = (discipline, local_limit);
+    }
+
+    pub fn set_packet_budget(&self, packets: usize) {
+        self.packet_budget.store(packets, Ordering::SeqCst);
+    }
+
+    pub fn set_slot_order(&self, order: SlotOrderChoice) {
+        *self.slot_order.lock().unwrap() = order;
+    }
+
+    pub fn set_referent_filter(&self, entries: Option<usize>) {
+        self.referent_filter
+            .store(entries.unwrap_or(0), Ordering::SeqCst);
+    }
+
+    /// Makes workers promote packets that have waited `micros` at the bottom
+    /// of their local queues to the global queue, 0 for never.
+    pub fn set_packet_aging(&self, micros: u64) {
+        self.packet_aging.store(micros, Ordering::SeqCst);
+    }
+
+    /// Queues a packet for any worker, such as the root packets of a trace.
+    pub fn push<P: Packet + 'static>(&self, packet: P) {
+        self.count_queued();
+        let stamp = self.time_packets.load(Ordering::Relaxed)
+            || self.packet_aging.load(Ordering::Relaxed) != 0;
+        self.queue.push(QueuedPacket {
+            packet: Box::new(packet),
+            queued: if stamp { timestamp() } else { 0 },
+        });
+    }
+
+    fn count_queued(&self) {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_queued.fetch_max(queued, Ordering::Relaxed);
+    }
+
+    pub fn set_time_packets(&self, time_packets: bool) {
+        self.time_packets.store(time_packets, Ordering::SeqCst);
+    }
+
     pub fn mark_state(&self) -> u8 {
         self.mark_state.load(Ordering::Relaxed)
     }
@@ -57,6 +303,18 @@ impl GlobalContext {
         self.objs.store(0, Ordering::SeqCst);
         self.edges.store(0, Ordering::SeqCst);
         self.ne_edges.store(0, Ordering::SeqCst);
+        self.packets.store(0, Ordering::SeqCst);
+        self.steals.store(0, Ordering::SeqCst);
+        self.overflows.store(0, Ordering::SeqCst);
+        self.spilled.store(0, Ordering::SeqCst);
+        self.aged.store(0, Ordering::SeqCst);
+        self.sorted_slots.store(0, Ordering::SeqCst);
+        self.unsorted_page_switches.store(0, Ordering::SeqCst);
+        self.sorted_page_switches.store(0, Ordering::SeqCst);
+        self.filtered_marks.store(0, Ordering::SeqCst);
+        self.queued.store(0, Ordering::SeqCst);
+        self.peak_queued.store(0, Ordering::SeqCst);
+        *self.packet_latency.lock().unwrap() = PacketLatency::default();
         *self.epoch_monitor.0.lock().unwrap() = false;
         self.yield_monitor.2.store(0, Ordering::SeqCst);
     }
@@ -66,6 +324,17 @@ impl GlobalContext {
             marked_objects: self.objs.load(Ordering::SeqCst),
             slots: self.edges.load(Ordering::SeqCst),
             non_empty_slots: self.ne_edges.load(Ordering::SeqCst),
+            packets: self.packets.load(Ordering::SeqCst),
+            steals: self.steals.load(Ordering::SeqCst),
+            overflows: self.overflows.load(Ordering::SeqCst),
+            spilled_packets: self.spilled.load(Ordering::SeqCst),
+            aged_packets: self.aged.load(Ordering::SeqCst),
+            peak_queued_packets: self.peak_queued.load(Ordering::SeqCst) as u64,
+            sorted_slots: self.sorted_slots.load(Ordering::SeqCst),
+            unsorted_page_switches: self.unsorted_page_switches.load(Ordering::SeqCst),
+            sorted_page_switches: self.sorted_page_switches.load(Ordering::SeqCst),
+            filtered_marks: self.filtered_marks.load(Ordering::SeqCst),
+            packet_latency: self.packet_latency.lock().unwrap().clone(),
             ..Default::default()
         }
     }
@@ -73,49 +342,220 @@ impl GlobalContext {
 
 pub static GLOBAL: Lazy<Arc<GlobalContext>> = Lazy::new(|| Arc::new(GlobalContext::new()));
 
+/// The queue of a single worker, which other workers may steal from. It
+/// runs in the order of a queue discipline, and holds at most `limit`
+/// items if it has one.
+pub struct LocalQueue<T> {
+    queue: Worker<T>,
+    /// Takes the oldest items of `queue`, as the other workers would
+    own: Stealer<T>,
+    limit: Option<usize>,
+}
+
+impl<T> LocalQueue<T> {
+    /// Hierarchical queues run LIFO, bounded by `limit`.
+    pub fn new(discipline: QueueDisciplineChoice, limit: usize) -> Self {
+        let queue = match discipline {
+            QueueDisciplineChoice::Fifo => Worker::new_fifo(),
+            QueueDisciplineChoice::Lifo | QueueDisciplineChoice::Hierarchical => Worker::new_lifo(),
+        };
+        LocalQueue {
+            own: queue.stealer(),
+            queue,
+            limit: (discipline == QueueDisciplineChoice::Hierarchical).then_some(limit.max(1)),
+        }
+    }
+
+    /// Queues `item`, or hands it back if the queue is full.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        match self.limit {
+            Some(limit) if self.queue.len() >= limit => Err(item),
+            _ => {
+                self.queue.push(item);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    /// Takes the item that has been queued the longest.
+    pub fn take_oldest(&self) -> Option<T> {
+        self.own.steal().success()
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        self.queue.stealer()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
 thread_local! {
     static LOCAL: Cell<*mut WPWorker> = const { Cell::new(std::ptr::null_mut()) };
 }
 
 pub struct WPWorker {
-    _id: usize,
-    queue: Worker<Box<dyn Packet>>,
+    id: usize,
+    queue: LocalQueue<QueuedPacket>,
     pub global: Arc<GlobalContext>,
     pub group: Weak<WorkerGroup<WPWorker>>,
     pub objs: u64,
     pub slots: u64,
     pub ne_slots: u64,
+    pub packets: u64,
+    steals: u64,
+    time_packets: bool,
+    latency: PacketLatency,
+    /// Whether packets are queued with a timestamp
+    stamp_packets: bool,
+    /// How long a packet may wait at the bottom of the local queue before it
+    /// is promoted, in `timestamp` units, 0 for no aging
+    aging: u64,
+    /// The oldest packet of the local queue, taken to check its age and not
+    /// old enough to promote yet
+    held: Option<QueuedPacket>,
+    packet_budget: usize,
+    /// Spilled packets running inside each other on this worker
+    spill_depth: Cell<usize>,
+    slot_order: SlotOrderChoice,
+    referent_filter: Option<ReferentFilter>,
 }
 
 impl WPWorker {
-    pub fn spawn<P: Packet + 'static>(&self, packet: P) {
-        self.queue.push(Box::new(packet));
+    pub fn spawn<P: Packet + 'static>(&self, mut packet: P) {
+        let depth = self.spill_depth.get();
+        if self.packet_budget != 0
+            && depth < MAX_SPILL_DEPTH
+            && self.global.queued.load(Ordering::Relaxed) >= self.packet_budget
+        {
+            // Working through the packet now bounds the memory of the
+            // queues, at the cost of tracing depth-first on this worker
+            self.global.spilled.fetch_add(1, Ordering::Relaxed);
+            self.spill_depth.set(depth + 1);
+            packet.run();
+            self.spill_depth.set(depth);
+            return;
+        }
+        self.global.count_queued();
+        let packet = QueuedPacket {
+            packet: Box::new(packet),
+            queued: if self.stamp_packets { timestamp() } else { 0 },
+        };
+        if let Err(packet) = self.queue.push(packet) {
+            self.global.queue.push(packet);
+            self.global.overflows.fetch_add(1, Ordering::Relaxed);
+        }
         if GLOBAL.yield_monitor.2.load(Ordering::SeqCst) > 0 {
             self.global.yield_monitor.1.notify_one();
         }
     }
 
+    /// Puts the slots of a packet in the order of `--slot-order` before it
+    /// loads them, counting how many fewer pages the loads switch between.
+    pub fn order_slots(&self, slots: &mut [Slot]) {
+        if self.slot_order == SlotOrderChoice::Unsorted {
+            return;
+        }
+        let before = page_switches(slots);
+        order_slots(self.slot_order, slots);
+        let global = &self.global;
+        global
+            .sorted_slots
+            .fetch_add(slots.len() as u64, Ordering::Relaxed);
+        global
+            .unsorted_page_switches
+            .fetch_add(before, Ordering::Relaxed);
+        global
+            .sorted_page_switches
+            .fetch_add(page_switches(slots), Ordering::Relaxed);
+    }
+
+    /// Whether the mark attempt of `o` can be skipped, see
+    /// `--referent-filter`.
+    pub fn filter_referent(&mut self, o: ObjectReference) -> bool {
+        self.referent_filter.as_mut().is_some_and(|f| f.seen(o))
+    }
+
     pub fn current() -> &'static mut WPWorker {
         unsafe { &mut *LOCAL.get() }
     }
 
-    fn run_packet(&self, mut packet: Box<dyn Packet>) {
-        packet.run();
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    fn run_packet(&mut self, group: &WorkerGroup<WPWorker>, queued: QueuedPacket) {
+        let mut packet = queued.packet;
+        self.global.queued.fetch_sub(1, Ordering::Relaxed);
+        self.packets += 1;
+        WorkerActivity::count(&group.activity[self.id].packets);
+        if self.time_packets {
+            let kind = packet.kind();
+            let start = timestamp();
+            self.latency
+                .record_wait(start.saturating_sub(queued.queued));
+            packet.run();
+            self.latency.record(kind, timestamp() - start);
+        } else {
+            packet.run();
+        }
+    }
+
+    /// Promotes the packets at the bottom of the local queue that have
+    /// waited longer than `--packet-aging` to the global queue. Under a LIFO
+    /// discipline the bottom only runs once everything above it has, so the
+    /// chunks of a big array pushed early can otherwise wait out the whole
+    /// closure and run last, on one worker.
+    fn age_packets(&mut self) {
+        let now = timestamp();
+        loop {
+            let oldest = match self.held.take() {
+                Some(p) => p,
+                None => match self.queue.take_oldest() {
+                    Some(p) => p,
+                    None => return,
+                },
+            };
+            if now.saturating_sub(oldest.queued) < self.aging {
+                // Everything else in the queue is younger
+                self.held = Some(oldest);
+                return;
+            }
+            self.global.queue.push(oldest);
+            self.global.aged.fetch_add(1, Ordering::Relaxed);
+        }
     }
 }
 
 impl crate::util::workers::Worker for WPWorker {
-    type SharedWorker = Stealer<Box<dyn Packet>>;
+    type SharedWorker = Stealer<QueuedPacket>;
 
     fn new(id: usize, group: Weak<WorkerGroup<Self>>) -> Self {
+        let (discipline, local_limit) = *GLOBAL.queue_discipline.lock().unwrap();
         Self {
-            _id: id,
-            queue: Worker::new_lifo(),
+            id,
+            queue: LocalQueue::new(discipline, local_limit),
             group,
             global: GLOBAL.clone(),
             objs: 0,
             slots: 0,
             ne_slots: 0,
+            packets: 0,
+            steals: 0,
+            time_packets: false,
+            latency: PacketLatency::default(),
+            stamp_packets: false,
+            aging: 0,
+            held: None,
+            packet_budget: 0,
+            spill_depth: Cell::new(0),
+            slot_order: SlotOrderChoice::Unsorted,
+            referent_filter: None,
         }
     }
 
@@ -128,6 +568,21 @@ impl crate::util::workers::Worker for WPWorker {
         self.objs = 0;
         self.slots = 0;
         self.ne_slots = 0;
+        self.packets = 0;
+        self.steals = 0;
+        self.time_packets = self.global.time_packets.load(Ordering::SeqCst);
+        self.latency = PacketLatency::default();
+        let aging = self.global.packet_aging.load(Ordering::SeqCst);
+        self.aging = (aging as f64 * *TIMESTAMPS_PER_US) as u64;
+        self.stamp_packets = self.time_packets || aging != 0;
+        self.packet_budget = self.global.packet_budget.load(Ordering::SeqCst);
+        self.slot_order = *self.global.slot_order.lock().unwrap();
+        // A fresh filter every closure, since the mark sense flips and an
+        // entry from the last one would skip an object not marked yet
+        self.referent_filter = match self.global.referent_filter.load(Ordering::SeqCst) {
+            0 => None,
+            entries => Some(ReferentFilter::new(entries)),
+        };
         let group = self.group.upgrade().unwrap();
         // trace objects
         loop {
@@ -136,27 +591,37 @@ impl crate::util::workers::Worker for WPWorker {
                 // Drain local queue
                 while let Some(p) = self.queue.pop() {
                     executed_packets = true;
-                    self.run_packet(p);
+                    self.run_packet(&group, p);
+                    if self.aging != 0 {
+                        self.age_packets();
+                    }
+                }
+                if let Some(p) = self.held.take() {
+                    self.run_packet(&group, p);
+                    continue 'poll;
                 }
                 // Steal from global queue
                 match self.global.queue.steal() {
                     Steal::Success(p) => {
                         executed_packets = true;
-                        self.run_packet(p);
+                        self.run_packet(&group, p);
                     }
                     Steal::Retry => continue 'poll,
                     _ => {}
                 }
                 // Steal from other workers
+                let activity = &group.activity[self.id];
                 for stealer in &*group.workers {
                     match stealer.steal() {
                         Steal::Success(p) => {
                             executed_packets = true;
-                            self.run_packet(p);
+                            self.steals += 1;
+                            WorkerActivity::count(&activity.steals);
+                            self.run_packet(&group, p);
                             break;
                         }
                         Steal::Retry => continue 'poll,
-                        _ => {}
+                        Steal::Empty => WorkerActivity::count(&activity.failed_steals),
                     }
                 }
                 // If there was no packet to execute, break
@@ -186,5 +651,36 @@ impl crate::util::workers::Worker for WPWorker {
         global.objs.fetch_add(self.objs, Ordering::SeqCst);
         global.edges.fetch_add(self.slots, Ordering::SeqCst);
         global.ne_edges.fetch_add(self.ne_slots, Ordering::SeqCst);
+        global.packets.fetch_add(self.packets, Ordering::SeqCst);
+        global.steals.fetch_add(self.steals, Ordering::SeqCst);
+        if let Some(filter) = &self.referent_filter {
+            global
+                .filtered_marks
+                .fetch_add(filter.filtered, Ordering::SeqCst);
+        }
+        if self.time_packets {
+            global.packet_latency.lock().unwrap().add(&self.latency);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_slots() {
+        let slot = |addr: u64| Slot::from_raw(addr as *mut u64);
+        let unsorted = [0x2010, 0x1008, 0x2008, 0x1000, 0x3000].map(slot);
+        assert_eq!(page_switches(&unsorted), 4);
+        let mut slots = unsorted;
+        order_slots(SlotOrderChoice::Page, &mut slots);
+        assert_eq!(slots, [0x1008, 0x1000, 0x2010, 0x2008, 0x3000].map(slot));
+        assert_eq!(page_switches(&slots), 2);
+        order_slots(SlotOrderChoice::Sorted, &mut slots);
+        assert_eq!(slots, [0x1000, 0x1008, 0x2008, 0x2010, 0x3000].map(slot));
+        let mut slots = unsorted;
+        order_slots(SlotOrderChoice::Unsorted, &mut slots);
+        assert_eq!(slots, unsorted);
     }
 }